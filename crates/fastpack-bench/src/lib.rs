@@ -0,0 +1,529 @@
+//! Benchmark comparing FastPack, APEX, ANS vs gzip
+//!
+//! `run` measures every codec over a set of samples and returns a `Report`,
+//! which renders as a terminal table, JSON or CSV. JSON and CSV carry the
+//! host and settings alongside the numbers so runs from different machines
+//! and commits can be stored and compared.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use fastpack_core::apex::{ans_compress, ans_decompress, apex_compress, apex_decompress, ApexOptions};
+use fastpack_core::{compress, decompress, Level, Options};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+/// Codecs measured for every sample, gzip first as the baseline
+pub const CODECS: [Codec; 6] = [
+    Codec::Gzip,
+    Codec::FastPack,
+    Codec::FastPackBetter,
+    Codec::Apex,
+    Codec::ApexPredictive,
+    Codec::Ans,
+];
+
+/// Codec under test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// flate2 gzip at the default level (baseline)
+    Gzip,
+    /// FastPack LZ4-style
+    FastPack,
+    /// FastPack at `Level::Better`
+    #[serde(rename = "fastpack-better")]
+    FastPackBetter,
+    /// APEX with structural encoding
+    Apex,
+    /// APEX with structural and predictive encoding
+    #[serde(rename = "apex-pred")]
+    ApexPredictive,
+    /// APEX ANS entropy coder alone
+    Ans,
+}
+
+impl Codec {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::FastPack => "fastpack",
+            Codec::FastPackBetter => "fastpack-better",
+            Codec::Apex => "apex",
+            Codec::ApexPredictive => "apex-pred",
+            Codec::Ans => "ans",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Codec::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Codec::FastPack => compress(data, &Options::default()).unwrap(),
+            Codec::FastPackBetter => {
+                let opts = Options {
+                    level: Level::Better,
+                    ..Default::default()
+                };
+                compress(data, &opts).unwrap()
+            }
+            Codec::Apex => {
+                let opts = ApexOptions {
+                    structural: true,
+                    ..Default::default()
+                };
+                apex_compress(data, &opts).unwrap()
+            }
+            Codec::ApexPredictive => {
+                let opts = ApexOptions {
+                    structural: true,
+                    predictive: true,
+                    ..Default::default()
+                };
+                apex_compress(data, &opts).unwrap()
+            }
+            Codec::Ans => ans_compress(data),
+        }
+    }
+
+    /// Decode `compressed`, `None` if the codec rejects its own output
+    fn decompress(self, compressed: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Codec::Gzip => {
+                let mut decoder = GzDecoder::new(compressed);
+                let mut decompressed = Vec::new();
+                decoder.read_to_end(&mut decompressed).ok()?;
+                Some(decompressed)
+            }
+            Codec::FastPack | Codec::FastPackBetter => decompress(compressed).ok(),
+            Codec::Apex | Codec::ApexPredictive => apex_decompress(compressed).ok(),
+            Codec::Ans => ans_decompress(compressed),
+        }
+    }
+}
+
+/// Named benchmark input
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Sample {
+    pub fn new(name: impl Into<String>, data: Vec<u8>) -> Self {
+        Self { name: name.into(), data }
+    }
+}
+
+/// Built-in synthetic samples
+pub fn builtin_samples() -> Vec<Sample> {
+    vec![
+        Sample::new("Small JSON", br#"{"id":123,"name":"test","active":true}"#.to_vec()),
+        Sample::new("Medium JSON", generate_medium_json()),
+        Sample::new("Large JSON Array", generate_json_array(100)),
+        Sample::new("Repeated JSON", generate_repeated_json(50)),
+        Sample::new("API Response", generate_api_response()),
+        Sample::new("Log Records", generate_log_records(12)),
+        Sample::new("Binary-like", generate_binary_data(1000)),
+    ]
+}
+
+/// Every regular file in `dir`, sorted by name
+///
+/// Subdirectories and empty files are skipped.
+pub fn load_corpus(dir: &Path) -> io::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let data = fs::read(&path)?;
+        if data.is_empty() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        samples.push(Sample::new(name, data));
+    }
+    samples.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(samples)
+}
+
+/// Measurements for one codec on one sample
+#[derive(Debug, Clone, Serialize)]
+pub struct CodecResult {
+    pub codec: Codec,
+    pub compressed_size: usize,
+    /// Mean compression time per iteration
+    pub compress_ns: u64,
+    /// Mean decompression time per iteration
+    pub decompress_ns: u64,
+    /// Whether decoding reproduced the input byte for byte
+    pub roundtrip_ok: bool,
+}
+
+impl CodecResult {
+    /// Compressed size as a percentage of `original`
+    pub fn ratio(&self, original: usize) -> f64 {
+        if original == 0 {
+            100.0
+        } else {
+            self.compressed_size as f64 / original as f64 * 100.0
+        }
+    }
+}
+
+/// Measurements for one sample
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleResult {
+    pub name: String,
+    pub size: usize,
+    pub results: Vec<CodecResult>,
+}
+
+/// Machine the benchmark ran on
+#[derive(Debug, Clone, Serialize)]
+pub struct Host {
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub cpus: usize,
+}
+
+impl Host {
+    pub fn current() -> Self {
+        Self {
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+/// Full benchmark run
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// fastpack-core version benchmarked
+    pub version: &'static str,
+    /// Seconds since the Unix epoch when the run started
+    pub timestamp: u64,
+    pub host: Host,
+    pub iterations: u32,
+    pub samples: Vec<SampleResult>,
+}
+
+/// Output format of a `Report`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Human-readable table
+    #[default]
+    Table,
+    /// Single JSON document
+    Json,
+    /// One row per sample and codec
+    Csv,
+}
+
+/// Run every codec `iterations` times over each sample
+pub fn run(samples: &[Sample], iterations: u32) -> Report {
+    let iterations = iterations.max(1);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    let samples = samples
+        .iter()
+        .map(|sample| SampleResult {
+            name: sample.name.clone(),
+            size: sample.data.len(),
+            results: CODECS.iter().map(|&codec| bench_codec(codec, &sample.data, iterations)).collect(),
+        })
+        .collect();
+
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        timestamp,
+        host: Host::current(),
+        iterations,
+        samples,
+    }
+}
+
+fn bench_codec(codec: Codec, data: &[u8], iterations: u32) -> CodecResult {
+    let compressed = codec.compress(data);
+    let roundtrip_ok = codec.decompress(&compressed).is_some_and(|d| d == data);
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        codec.compress(data);
+    }
+    let compress_time = start.elapsed() / iterations;
+
+    let start = Instant::now();
+    if roundtrip_ok {
+        for _ in 0..iterations {
+            codec.decompress(&compressed);
+        }
+    }
+    let decompress_time = start.elapsed() / iterations;
+
+    CodecResult {
+        codec,
+        compressed_size: compressed.len(),
+        compress_ns: compress_time.as_nanos() as u64,
+        decompress_ns: decompress_time.as_nanos() as u64,
+        roundtrip_ok,
+    }
+}
+
+impl Report {
+    /// Write the report in `format`
+    pub fn write<W: Write>(&self, out: &mut W, format: Format) -> io::Result<()> {
+        match format {
+            Format::Table => write!(out, "{}", self),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)
+            }
+            Format::Csv => self.write_csv(out),
+        }
+    }
+
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "timestamp,version,os,arch,iterations,sample,size,codec,compressed_size,ratio,compress_ns,decompress_ns,roundtrip_ok"
+        )?;
+        for sample in &self.samples {
+            for r in &sample.results {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{},{},{:.2},{},{},{}",
+                    self.timestamp,
+                    self.version,
+                    self.host.os,
+                    self.host.arch,
+                    self.iterations,
+                    csv_field(&sample.name),
+                    sample.size,
+                    r.codec.as_str(),
+                    r.compressed_size,
+                    r.ratio(sample.size),
+                    r.compress_ns,
+                    r.decompress_ns,
+                    r.roundtrip_ok,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "╔═══════════════════════════════════════════════════════════════════════════════╗")?;
+        writeln!(f, "║             FastPack Compression Benchmark vs gzip                            ║")?;
+        writeln!(f, "╚═══════════════════════════════════════════════════════════════════════════════╝\n")?;
+        writeln!(f, "Legend: Size (% of original) | Compress time | Decompress time\n")?;
+
+        for sample in &self.samples {
+            writeln!(f, "┌─ {} ({} bytes) ─────────────────────────────────────────", sample.name, sample.size)?;
+            let gzip_ns = sample.results.iter().find(|r| r.codec == Codec::Gzip).map(|r| r.compress_ns);
+            for r in &sample.results {
+                let speed = match (r.codec, gzip_ns) {
+                    (Codec::Gzip | Codec::Ans, _) | (_, None) => "",
+                    (_, Some(gzip_ns)) => speed_indicator(r.compress_ns, gzip_ns),
+                };
+                writeln!(
+                    f,
+                    "│  {:<13} {:5} bytes ({:5.1}%) │ {:>10} │ {:>10} {}{}",
+                    format!("{}:", r.codec.as_str()),
+                    r.compressed_size,
+                    r.ratio(sample.size),
+                    format_duration(Duration::from_nanos(r.compress_ns)),
+                    format_duration(Duration::from_nanos(r.decompress_ns)),
+                    speed,
+                    if r.roundtrip_ok { "" } else { " MISMATCH" },
+                )?;
+            }
+            writeln!(f, "└───────────────────────────────────────────────────────────────────────────────\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let nanos = d.as_nanos();
+    if nanos < 1000 {
+        format!("{}ns", nanos)
+    } else if nanos < 1_000_000 {
+        format!("{:.1}us", nanos as f64 / 1000.0)
+    } else {
+        format!("{:.2}ms", nanos as f64 / 1_000_000.0)
+    }
+}
+
+fn speed_indicator(ours: u64, theirs: u64) -> &'static str {
+    let ratio = theirs as f64 / ours.max(1) as f64;
+    if ratio > 5.0 {
+        "5x+"
+    } else if ratio > 2.0 {
+        "2x+"
+    } else if ratio > 1.2 {
+        "fast"
+    } else if ratio < 0.5 {
+        "slow"
+    } else {
+        ""
+    }
+}
+
+fn generate_medium_json() -> Vec<u8> {
+    br#"{"user":{"id":12345,"name":"John Doe","email":"john@example.com","active":true,"roles":["admin","user"],"metadata":{"created":"2024-01-15","lastLogin":"2024-06-01"}}}"#.to_vec()
+}
+
+fn generate_json_array(count: usize) -> Vec<u8> {
+    let mut json = String::from("[");
+    for i in 0..count {
+        if i > 0 { json.push(','); }
+        json.push_str(&format!(r#"{{"id":{},"name":"user{}","score":{}}}"#, i, i, i * 10));
+    }
+    json.push(']');
+    json.into_bytes()
+}
+
+fn generate_repeated_json(count: usize) -> Vec<u8> {
+    let mut json = String::from("[");
+    for i in 0..count {
+        if i > 0 { json.push(','); }
+        json.push_str(r#"{"type":"event","action":"click","target":"button"}"#);
+    }
+    json.push(']');
+    json.into_bytes()
+}
+
+/// Records whose fields mostly repeat the previous record's, where
+/// predictive encoding wins
+fn generate_log_records(count: usize) -> Vec<u8> {
+    let mut json = String::from("[");
+    for i in 0..count {
+        if i > 0 { json.push(','); }
+        let level = if i % 5 == 0 { "error" } else { "info" };
+        json.push_str(&format!(r#"{{"seq":{},"level":"{}","service":"checkout","region":"eu-west"}}"#, i, level));
+    }
+    json.push(']');
+    json.into_bytes()
+}
+
+fn generate_api_response() -> Vec<u8> {
+    r#"{
+  "status": "success",
+  "data": {
+    "users": [
+      {"id": 1, "name": "Alice", "email": "alice@example.com", "role": "admin"},
+      {"id": 2, "name": "Bob", "email": "bob@example.com", "role": "user"},
+      {"id": 3, "name": "Charlie", "email": "charlie@example.com", "role": "user"},
+      {"id": 4, "name": "Diana", "email": "diana@example.com", "role": "moderator"},
+      {"id": 5, "name": "Eve", "email": "eve@example.com", "role": "user"}
+    ],
+    "pagination": {
+      "page": 1,
+      "perPage": 10,
+      "total": 5,
+      "totalPages": 1
+    }
+  },
+  "meta": {
+    "requestId": "abc123",
+    "timestamp": "2024-06-15T10:30:00Z",
+    "version": "2.0"
+  }
+}"#.as_bytes().to_vec()
+}
+
+fn generate_binary_data(size: usize) -> Vec<u8> {
+    (0..size).map(|i| ((i * 17 + 31) % 256) as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_report() -> Report {
+        let samples = vec![
+            Sample::new("api, v2", generate_api_response()),
+            Sample::new("binary", generate_binary_data(64)),
+        ];
+        run(&samples, 1)
+    }
+
+    #[test]
+    fn test_run_covers_every_codec() {
+        let report = small_report();
+        assert_eq!(report.samples.len(), 2);
+        for sample in &report.samples {
+            let codecs: Vec<Codec> = sample.results.iter().map(|r| r.codec).collect();
+            assert_eq!(codecs, CODECS);
+        }
+        let api = &report.samples[0];
+        assert!(api.results.iter().find(|r| r.codec == Codec::FastPack).unwrap().roundtrip_ok);
+    }
+
+    #[test]
+    fn test_json_output() {
+        let report = small_report();
+        let mut out = Vec::new();
+        report.write(&mut out, Format::Json).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["iterations"], 1);
+        assert_eq!(value["host"]["os"], std::env::consts::OS);
+        assert_eq!(value["samples"][0]["name"], "api, v2");
+        assert_eq!(value["samples"][0]["results"][1]["codec"], "fastpack");
+    }
+
+    #[test]
+    fn test_csv_output() {
+        let report = small_report();
+        let mut out = Vec::new();
+        report.write(&mut out, Format::Csv).unwrap();
+
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * CODECS.len());
+        let columns = lines[0].split(',').count();
+        assert!(lines[1].contains(",\"api, v2\","));
+        assert_eq!(lines[1 + CODECS.len()].split(',').count(), columns);
+    }
+
+    #[test]
+    fn test_load_corpus() {
+        let dir = std::env::temp_dir().join(format!("fastpack-bench-corpus-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("b.json"), b"{\"b\":2}").unwrap();
+        fs::write(dir.join("a.json"), b"{\"a\":1}").unwrap();
+        fs::write(dir.join("empty.json"), b"").unwrap();
+
+        let samples = load_corpus(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<&str> = samples.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["a.json", "b.json"]);
+    }
+}
@@ -0,0 +1,78 @@
+//! `fastpack-bench` - FastPack benchmark runner
+//!
+//! ```text
+//! fastpack-bench                                  # built-in samples, table output
+//! fastpack-bench --corpus data/ --format json     # every file in data/
+//! fastpack-bench --format csv -o results.csv
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, ValueEnum};
+use fastpack_bench::{builtin_samples, load_corpus, run, Format};
+
+#[derive(Parser)]
+#[command(name = "fastpack-bench", version, about = "Benchmark FastPack, APEX and ANS against gzip")]
+struct Cli {
+    /// Benchmark every file in this directory instead of the built-in samples
+    #[arg(long)]
+    corpus: Option<PathBuf>,
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+    /// Number of iterations per codec and sample
+    #[arg(short = 'n', long, default_value_t = 100)]
+    iterations: u32,
+    /// Output file (default: stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl From<OutputFormat> for Format {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Table => Format::Table,
+            OutputFormat::Json => Format::Json,
+            OutputFormat::Csv => Format::Csv,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match bench(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("fastpack-bench: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn bench(cli: Cli) -> io::Result<()> {
+    let samples = match &cli.corpus {
+        Some(dir) => load_corpus(dir)?,
+        None => builtin_samples(),
+    };
+    if samples.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "corpus has no non-empty files"));
+    }
+
+    let report = run(&samples, cli.iterations);
+
+    let mut out: Box<dyn Write> = match &cli.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    report.write(&mut out, cli.format.into())?;
+    out.flush()
+}
@@ -23,8 +23,8 @@ pub fn ans_compress(data: &[u8]) -> Vec<u8> {
 
     // Count unique symbols
     let mut symbols: Vec<u8> = Vec::new();
-    for i in 0..256 {
-        if freq[i] > 0 {
+    for (i, &f) in freq.iter().enumerate() {
+        if f > 0 {
             symbols.push(i as u8);
         }
     }
@@ -38,6 +38,12 @@ pub fn ans_compress(data: &[u8]) -> Vec<u8> {
         return output;
     }
 
+    // The symbol count is one byte; with every byte value in use the table
+    // alone outweighs any gain
+    if symbols.len() > u8::MAX as usize {
+        return store_raw(data);
+    }
+
     // Build Huffman-like codes (simple variable-length encoding)
     // Sort symbols by frequency (most frequent first)
     symbols.sort_by(|a, b| freq[*b as usize].cmp(&freq[*a as usize]));
@@ -84,18 +90,27 @@ pub fn ans_compress(data: &[u8]) -> Vec<u8> {
 
     // If nibble encoding is worse, just store raw
     if output.len() >= data.len() + 6 {
-        let mut output = Vec::new();
-        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
-        output.push(2); // Flag: raw storage
-        output.extend_from_slice(data);
-        return output;
+        return store_raw(data);
     }
 
     output
 }
 
+fn store_raw(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.push(2); // Flag: raw storage
+    output.extend_from_slice(data);
+    output
+}
+
 /// Decompress ANS data
 pub fn ans_decompress(input: &[u8]) -> Option<Vec<u8>> {
+    ans_decompress_with_limit(input, usize::MAX)
+}
+
+/// Decompress untrusted ANS data, `None` if it would exceed `limit` bytes
+pub fn ans_decompress_with_limit(input: &[u8], limit: usize) -> Option<Vec<u8>> {
     if input.len() < 4 {
         return None;
     }
@@ -104,6 +119,9 @@ pub fn ans_decompress(input: &[u8]) -> Option<Vec<u8>> {
     if orig_len == 0 {
         return Some(Vec::new());
     }
+    if orig_len > limit {
+        return None;
+    }
 
     if input.len() < 5 {
         return None;
@@ -145,7 +163,8 @@ pub fn ans_decompress(input: &[u8]) -> Option<Vec<u8>> {
 
     // Decode nibbles
     let compressed = &input[6 + sym_count..];
-    let mut output = Vec::with_capacity(orig_len);
+    // Each byte holds at most two symbols
+    let mut output = Vec::with_capacity(orig_len.min(compressed.len() * 2));
 
     let mut pos = 0;
     let mut nibble_pos = 0; // 0 = high nibble, 1 = low nibble
@@ -252,6 +271,18 @@ mod tests {
         assert_eq!(data.as_slice(), decompressed.as_slice());
     }
 
+    #[test]
+    fn test_ans_decompress_limit() {
+        // Six bytes claiming 4 GiB of one symbol
+        let bomb = [0xFF, 0xFF, 0xFF, 0xFF, 1, b'x'];
+        assert_eq!(ans_decompress_with_limit(&bomb, 1 << 20), None);
+
+        let data = b"aaaaaabbbbbbcccccc";
+        let compressed = ans_compress(data);
+        assert_eq!(ans_decompress_with_limit(&compressed, data.len()).unwrap(), data);
+        assert_eq!(ans_decompress_with_limit(&compressed, data.len() - 1), None);
+    }
+
     #[test]
     fn test_ans_roundtrip_repeated() {
         let data = b"aaaaaabbbbbbcccccc";
@@ -268,6 +299,16 @@ mod tests {
         assert_eq!(data, decompressed);
     }
 
+    #[test]
+    fn test_ans_roundtrip_every_byte_value() {
+        // Skewed enough to favour nibbles, with all 256 symbols present
+        let mut data = vec![b'a'; 10_000];
+        data.extend(0..=255u8);
+        let compressed = ans_compress(&data);
+        let decompressed = ans_decompress(&compressed).unwrap();
+        assert_eq!(data, decompressed);
+    }
+
     #[test]
     fn test_ans_empty() {
         let data = b"";
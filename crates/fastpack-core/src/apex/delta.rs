@@ -1,11 +1,19 @@
 //! Delta Stream Encoding
 //!
 //! Efficiently encode sequential/incremental data patterns.
+//!
+//! Within an `ApexSession`, every integer in a template's value slots
+//! passes through the template's `DeltaEncoder`, on both sides, so ids,
+//! counters and timestamps that advance between messages can be sent as
+//! `Delta` or `SameDelta` instead of literals (`ApexOptions::delta`).
 
 /// Delta encoder for sequences
+#[derive(Debug, Clone)]
 pub struct DeltaEncoder {
     /// Previous values for each slot
     prev_values: Vec<Option<i64>>,
+    /// Previous deltas for each slot
+    prev_deltas: Vec<Option<i64>>,
     /// Detected patterns
     patterns: Vec<DeltaPattern>,
 }
@@ -27,11 +35,19 @@ impl DeltaEncoder {
     pub fn new(slot_count: usize) -> Self {
         Self {
             prev_values: vec![None; slot_count],
+            prev_deltas: vec![None; slot_count],
             patterns: vec![DeltaPattern::None; slot_count],
         }
     }
 
+    /// Number of slots tracked
+    pub fn slot_count(&self) -> usize {
+        self.prev_values.len()
+    }
+
     /// Encode a number value, returning delta if beneficial
+    ///
+    /// `SameDelta` means the slot moved by the same amount as last time.
     pub fn encode_number(&mut self, slot: usize, value: i64) -> DeltaResult {
         if slot >= self.prev_values.len() {
             return DeltaResult::Literal(value);
@@ -43,39 +59,22 @@ impl DeltaEncoder {
                 DeltaResult::Literal(value)
             }
             Some(prev) => {
-                let delta = value - prev;
+                let delta = value.wrapping_sub(prev);
+                let same = self.prev_deltas[slot] == Some(delta);
 
                 // Update pattern detection
-                match self.patterns[slot] {
-                    DeltaPattern::None => {
-                        self.patterns[slot] = DeltaPattern::Linear(delta);
-                        DeltaResult::Delta(delta)
-                    }
-                    DeltaPattern::Linear(expected_delta) => {
-                        if delta == expected_delta {
-                            // Pattern continues, just signal "same delta"
-                            DeltaResult::SameDelta
-                        } else {
-                            // Pattern broken
-                            self.patterns[slot] = DeltaPattern::Varying;
-                            DeltaResult::Delta(delta)
-                        }
-                    }
-                    DeltaPattern::Constant(expected) => {
-                        if value == expected {
-                            DeltaResult::SameDelta
-                        } else {
-                            self.patterns[slot] = DeltaPattern::Varying;
-                            DeltaResult::Delta(delta)
-                        }
-                    }
-                    DeltaPattern::Varying => {
-                        if delta == 0 {
-                            DeltaResult::SameDelta
-                        } else {
-                            DeltaResult::Delta(delta)
-                        }
-                    }
+                self.patterns[slot] = match (self.patterns[slot], same) {
+                    (DeltaPattern::None, _) if delta == 0 => DeltaPattern::Constant(value),
+                    (DeltaPattern::None, _) => DeltaPattern::Linear(delta),
+                    (pattern, true) => pattern,
+                    (_, false) => DeltaPattern::Varying,
+                };
+                self.prev_deltas[slot] = Some(delta);
+
+                if same {
+                    DeltaResult::SameDelta
+                } else {
+                    DeltaResult::Delta(delta)
                 }
             }
         };
@@ -84,6 +83,20 @@ impl DeltaEncoder {
         result
     }
 
+    /// The value `result` stands for in `slot`, without advancing the slot
+    ///
+    /// `None` if the slot has no previous value (or delta) to apply it to.
+    pub fn resolve(&self, slot: usize, result: &DeltaResult) -> Option<i64> {
+        match result {
+            DeltaResult::Literal(v) => Some(*v),
+            DeltaResult::Delta(d) => Some(self.prev_values.get(slot).copied()??.wrapping_add(*d)),
+            DeltaResult::SameDelta => {
+                let prev = self.prev_values.get(slot).copied()??;
+                Some(prev.wrapping_add(self.prev_deltas[slot]?))
+            }
+        }
+    }
+
     /// Get pattern for slot
     pub fn pattern(&self, slot: usize) -> DeltaPattern {
         self.patterns.get(slot).copied().unwrap_or(DeltaPattern::None)
@@ -94,6 +107,9 @@ impl DeltaEncoder {
         for v in &mut self.prev_values {
             *v = None;
         }
+        for d in &mut self.prev_deltas {
+            *d = None;
+        }
         for p in &mut self.patterns {
             *p = DeltaPattern::None;
         }
@@ -130,40 +146,53 @@ impl DeltaResult {
             }
         }
     }
+
+    /// Decode from bytes
+    pub fn decode(input: &[u8], pos: &mut usize) -> Option<Self> {
+        let tag = *input.get(*pos)?;
+        *pos += 1;
+        match tag {
+            0 => decode_varint(input, pos).map(DeltaResult::Literal),
+            1 => decode_varint(input, pos).map(DeltaResult::Delta),
+            2 => Some(DeltaResult::SameDelta),
+            _ => None,
+        }
+    }
+}
+
+/// The integer a JSON number stands for, if it is written the way `i64`
+/// formats it, so deltas reproduce the exact bytes
+pub fn canonical_integer(number: &[u8]) -> Option<i64> {
+    let value: i64 = std::str::from_utf8(number).ok()?.parse().ok()?;
+    (value.to_string().as_bytes() == number).then_some(value)
 }
 
 /// Delta decoder
-#[allow(dead_code)]
+///
+/// Mirrors the encoder's state: every value of a slot, however it was
+/// sent, must go through `decode` or `observe` in order.
+#[derive(Debug, Clone)]
 pub struct DeltaDecoder {
-    prev_values: Vec<Option<i64>>,
-    prev_deltas: Vec<i64>,
+    state: DeltaEncoder,
 }
 
-#[allow(dead_code)]
 impl DeltaDecoder {
     pub fn new(slot_count: usize) -> Self {
         Self {
-            prev_values: vec![None; slot_count],
-            prev_deltas: vec![0; slot_count],
+            state: DeltaEncoder::new(slot_count),
         }
     }
 
     /// Decode a delta result back to value
-    pub fn decode(&mut self, slot: usize, result: &DeltaResult) -> i64 {
-        let value = match result {
-            DeltaResult::Literal(v) => *v,
-            DeltaResult::Delta(d) => {
-                self.prev_deltas[slot] = *d;
-                self.prev_values[slot].unwrap_or(0) + d
-            }
-            DeltaResult::SameDelta => {
-                let delta = self.prev_deltas[slot];
-                self.prev_values[slot].unwrap_or(0) + delta
-            }
-        };
+    pub fn decode(&mut self, slot: usize, result: &DeltaResult) -> Option<i64> {
+        let value = self.state.resolve(slot, result)?;
+        self.state.encode_number(slot, value);
+        Some(value)
+    }
 
-        self.prev_values[slot] = Some(value);
-        value
+    /// Record a value of `slot` that was sent some other way
+    pub fn observe(&mut self, slot: usize, value: i64) {
+        self.state.encode_number(slot, value);
     }
 }
 
@@ -190,7 +219,6 @@ fn encode_varint(value: i64) -> Vec<u8> {
 }
 
 /// Decode varint to signed integer
-#[allow(dead_code)]
 fn decode_varint(input: &[u8], pos: &mut usize) -> Option<i64> {
     let mut value: u64 = 0;
     let mut shift = 0;
@@ -268,8 +296,34 @@ mod tests {
 
         for (slot, value) in values {
             let result = encoder.encode_number(slot, value);
-            let decoded = decoder.decode(slot, &result);
-            assert_eq!(decoded, value);
+            let mut pos = 0;
+            let wire = DeltaResult::decode(&result.encode(), &mut pos).unwrap();
+            assert_eq!(decoder.decode(slot, &wire), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_same_delta_after_broken_pattern() {
+        let mut encoder = DeltaEncoder::new(1);
+        let mut decoder = DeltaDecoder::new(1);
+        // Steps of 10, a jump, steps of 10 again, then a constant run
+        let values = [100, 110, 120, 500, 510, 520, 520, 520, i64::MAX, i64::MIN];
+        let same = [false, false, true, false, false, true, false, true, false, false];
+        for (value, same) in values.into_iter().zip(same) {
+            let result = encoder.encode_number(0, value);
+            assert_eq!(result == DeltaResult::SameDelta, same, "{}", value);
+            assert_eq!(decoder.decode(0, &result), Some(value));
+        }
+        assert_eq!(encoder.pattern(0), DeltaPattern::Varying);
+        assert_eq!(DeltaDecoder::new(1).decode(0, &DeltaResult::SameDelta), None);
+    }
+
+    #[test]
+    fn test_canonical_integer() {
+        assert_eq!(canonical_integer(b"-42"), Some(-42));
+        assert_eq!(canonical_integer(b"9223372036854775807"), Some(i64::MAX));
+        for number in [&b"1.0"[..], b"01", b"-0", b"1e3", b"+1", b"9223372036854775808"] {
+            assert_eq!(canonical_integer(number), None);
         }
     }
 
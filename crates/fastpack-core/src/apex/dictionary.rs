@@ -152,7 +152,7 @@ impl Dictionary {
         for len in (2..=max_len).rev() {
             let pattern = &input[pos..pos + len];
             if let Some(id) = self.lookup(pattern) {
-                if best_match.map_or(true, |(_, l)| len > l) {
+                if best_match.is_none_or(|(_, l)| len > l) {
                     best_match = Some((id, len));
                     break; // Found longest match
                 }
@@ -3,51 +3,170 @@
 //! Main compression engine combining all APEX features.
 
 use super::{
-    dictionary::Dictionary,
-    template::{TemplateExtractor, Value},
+    dictionary::{Dictionary, DictionaryLevel},
+    template::{read_length, value_type, write_length, Template, TemplateExtractor, TemplateToken, Value},
+    predictor::{Predictor, SlotContext},
+    delta::{canonical_integer, DeltaDecoder, DeltaEncoder, DeltaResult},
     tokenizer::is_json,
-    ans::{ans_compress, ans_decompress},
+    whitespace::{Layout, WhitespaceProfile},
+    ans::{ans_compress, ans_decompress_with_limit},
     APEX_MAGIC, APEX_VERSION, ApexOptions,
 };
 use crate::{Result, Error};
+use std::collections::HashMap;
 use crate::compress::compress as lz4_compress;
-use crate::decompress::decompress as lz4_decompress;
+use crate::decompress::decompress_with_limit as lz4_decompress_with_limit;
 use crate::Options as Lz4Options;
 
 /// Flags for APEX frame
-#[allow(dead_code)]
 mod flags {
     pub const HAS_TEMPLATE: u8 = 0b0000_0001;
     pub const HAS_DICT_UPDATE: u8 = 0b0000_0010;
-    pub const DELTA_ENABLED: u8 = 0b0000_0100;
+    /// The JSON is pretty-printed to a `WhitespaceProfile` (version 5)
+    pub const HAS_WHITESPACE: u8 = 0b0000_0100;
     pub const IS_JSON: u8 = 0b0000_1000;
     pub const LZ4_FALLBACK: u8 = 0b0001_0000;
     pub const ANS_ENCODED: u8 = 0b0010_0000;
+    /// The template is known to the decoder and only its hash is sent
+    pub const HAS_TEMPLATE_REF: u8 = 0b0100_0000;
+    /// Values may be `PREDICTED` tags
+    pub const PREDICTIVE: u8 = 0b1000_0000;
+}
+
+/// Encoded templates shared with the peer, by template hash
+///
+/// Template bytes reference keys through the session dictionary, which
+/// only grows, so they stay valid for the session's lifetime.
+pub type KnownTemplates = HashMap<u64, Vec<u8>>;
+
+/// Per-template delta state of a session, by template hash
+///
+/// A frame carrying its template in full starts the template's deltas
+/// afresh, so a resent template also resynchronizes them.
+pub type DeltaSlots<D> = HashMap<u64, D>;
+
+/// Template tokens
+mod token {
+    pub const OBJECT_START: u8 = 1;
+    pub const OBJECT_END: u8 = 2;
+    pub const ARRAY_START: u8 = 3;
+    pub const ARRAY_END: u8 = 4;
+    pub const COLON: u8 = 5;
+    pub const COMMA: u8 = 6;
+    /// Key bytes, length-prefixed (keys the dictionary can't hold)
+    pub const KEY: u8 = 7;
+    pub const VALUE_SLOT: u8 = 8;
+    /// Dictionary ID of a key (u16)
+    pub const KEY_REF: u8 = 9;
+}
+
+/// Longest pattern a dictionary update can carry (its length is one byte)
+const MAX_PATTERN_LEN: usize = u8::MAX as usize;
+
+/// Reference a key through the session dictionary, or `None` to spell it out
+///
+/// Keys missing from `session` are added to `local` so they can be sent in
+/// the frame's dictionary update; their IDs continue the session's.
+fn key_ref(session: &Dictionary, local: &mut Dictionary, key: &[u8]) -> Option<u16> {
+    if let Some(id) = session.lookup(key) {
+        return Some(id);
+    }
+    let base = session.size();
+    if let Some(id) = local.lookup(key) {
+        return Some(base as u16 + id);
+    }
+    if key.len() > MAX_PATTERN_LEN || base + local.size() >= u16::MAX as usize {
+        return None;
+    }
+    Some(base as u16 + local.add(key.to_vec(), DictionaryLevel::Message))
+}
+
+/// Read a template token count or key length
+///
+/// Both are varints since APEX version 4 and single bytes before.
+fn read_template_length(template: &[u8], pos: &mut usize, version: u8) -> Option<usize> {
+    if version >= 4 {
+        return read_length(template, pos, version);
+    }
+    let len = *template.get(*pos)? as usize;
+    *pos += 1;
+    Some(len)
 }
 
 /// APEX Encoder
-pub struct ApexEncoder {
+///
+/// Object keys are sent as references into the session dictionary. Keys
+/// the dictionary lacks travel in a dictionary-update section of the frame,
+/// and the decoder appends them to its own copy in the same order, so both
+/// sides assign them the same IDs.
+pub struct ApexEncoder<'a> {
     opts: ApexOptions,
-    #[allow(dead_code)]
-    session_dict: Dictionary,
+    session_dict: &'a Dictionary,
     local_dict: Dictionary,
     template_extractor: TemplateExtractor,
+    learning: bool,
+    known_templates: Option<&'a KnownTemplates>,
+    new_template: Option<(u64, Vec<u8>)>,
+    template_ref: bool,
+    delta_slots: Option<&'a DeltaSlots<DeltaEncoder>>,
+    delta_state: Option<(u64, DeltaEncoder)>,
 }
 
-impl ApexEncoder {
-    pub fn new(opts: ApexOptions, _session_dict: &Dictionary) -> Self {
+impl<'a> ApexEncoder<'a> {
+    pub fn new(opts: ApexOptions, session_dict: &'a Dictionary) -> Self {
         Self {
             opts,
-            session_dict: Dictionary::empty(),
+            session_dict,
             local_dict: Dictionary::empty(),
             template_extractor: TemplateExtractor::new(),
+            learning: false,
+            known_templates: None,
+            new_template: None,
+            template_ref: false,
+            delta_slots: None,
+            delta_state: None,
         }
-        // Note: In a real implementation, we'd clone session_dict
-        // For simplicity, using empty dicts here
+    }
+
+    /// Track integers per template slot against `slots`
+    ///
+    /// With `ApexOptions::delta`, integers are then sent as deltas from the
+    /// slot's previous value where that is shorter. `take_delta_state`
+    /// returns the template's updated state for the caller to store.
+    pub fn deltas(mut self, slots: &'a DeltaSlots<DeltaEncoder>) -> Self {
+        self.delta_slots = Some(slots);
+        self
+    }
+
+    /// Send templates in `known` by hash alone
+    ///
+    /// Other templates are sent in full, and `take_new_template` returns
+    /// them for the caller to add to `known` once the frame is sent.
+    pub fn templates(mut self, known: &'a KnownTemplates) -> Self {
+        self.known_templates = Some(known);
+        self
+    }
+
+    /// Treat dictionary updates as an investment in later frames
+    ///
+    /// A frame teaching new keys then stays structural as long as it would
+    /// be smaller than its input without the update; otherwise the first
+    /// message of a session could never teach its keys. Sessions enable it.
+    /// New templates count as investments too, once `templates` is set.
+    pub fn learning(mut self, enabled: bool) -> Self {
+        self.learning = enabled;
+        self
     }
 
     /// Encode input data
+    ///
+    /// Afterwards `local_dictionary` holds the entries this frame added to
+    /// the session dictionary, in ID order.
     pub fn encode(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        self.local_dict = Dictionary::empty();
+        self.new_template = None;
+        self.template_ref = false;
+        self.delta_state = None;
         let mut output = Vec::with_capacity(input.len());
 
         // Write header
@@ -63,10 +182,20 @@ impl ApexEncoder {
             frame_flags |= flags::IS_JSON;
         }
 
-        if use_structural && input.len() > 50 {
+        // Structural frames rebuild the JSON from its tokens and whitespace profile
+        let profile = match use_structural && input.len() > 50 {
+            true => match Layout::detect(input) {
+                Layout::Compact => Some(None),
+                Layout::Profile(profile) => Some(Some(profile)),
+                Layout::Irregular => None,
+            },
+            false => None,
+        };
+
+        if let Some(profile) = profile {
             // Try structural compression for larger JSON
-            match self.encode_structural(input) {
-                Ok(structural_data) => {
+            match self.encode_structural(input, profile.as_ref()) {
+                Ok((structural_data, investment)) => {
                     // Apply ANS entropy coding for better compression
                     let ans_data = ans_compress(&structural_data);
 
@@ -77,8 +206,20 @@ impl ApexEncoder {
                         (structural_data, false)
                     };
 
-                    if final_data.len() < input.len() {
+                    if final_data.len() < input.len() + investment {
                         frame_flags |= flags::HAS_TEMPLATE;
+                        if self.local_dict.size() > 0 {
+                            frame_flags |= flags::HAS_DICT_UPDATE;
+                        }
+                        if self.template_ref {
+                            frame_flags |= flags::HAS_TEMPLATE_REF;
+                        }
+                        if self.opts.predictive {
+                            frame_flags |= flags::PREDICTIVE;
+                        }
+                        if profile.is_some() {
+                            frame_flags |= flags::HAS_WHITESPACE;
+                        }
                         if use_ans {
                             frame_flags |= flags::ANS_ENCODED;
                         }
@@ -94,7 +235,10 @@ impl ApexEncoder {
             }
         }
 
-        // Fallback to LZ4
+        // Fallback to LZ4, which teaches the decoder no keys or templates
+        self.local_dict = Dictionary::empty();
+        self.new_template = None;
+        self.delta_state = None;
         frame_flags |= flags::LZ4_FALLBACK;
         output.push(frame_flags);
 
@@ -106,48 +250,79 @@ impl ApexEncoder {
     }
 
     /// Structural encoding for JSON
-    fn encode_structural(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+    ///
+    /// Also returns the bytes spent on dictionary updates and templates
+    /// that later frames will reference, if those are shared.
+    fn encode_structural(&mut self, input: &[u8], profile: Option<&WhitespaceProfile>) -> Result<(Vec<u8>, usize)> {
         let (template, values) = self.template_extractor.extract(input);
 
         let mut output = Vec::new();
 
         // Encode template hash (for matching known templates)
         output.extend_from_slice(&template.hash.to_le_bytes());
+        if let Some(profile) = profile {
+            profile.encode(&mut output);
+        }
 
-        // Encode template pattern (simplified - in real impl, use dictionary)
-        let template_bytes = self.encode_template(&template);
-        output.extend_from_slice(&(template_bytes.len() as u16).to_le_bytes());
-        output.extend_from_slice(&template_bytes);
+        // Known templates go by hash alone
+        let known = self.known_templates.is_some_and(|known| known.contains_key(&template.hash));
+        let template_bytes = if known { Vec::new() } else { self.encode_template(&template) };
+        self.template_ref = known;
+
+        // Keys are dictionary references; new ones are sent first
+        let mut investment = 0;
+        if self.local_dict.size() > 0 {
+            let start = output.len();
+            let update = self.local_dict.encode(DictionaryLevel::Message);
+            write_length(update.len(), &mut output);
+            output.extend_from_slice(&update);
+            if self.learning {
+                investment += output.len() - start;
+            }
+        }
+        if !known {
+            let start = output.len();
+            write_length(template_bytes.len(), &mut output);
+            output.extend_from_slice(&template_bytes);
+            if self.known_templates.is_some() {
+                investment += output.len() - start;
+                self.new_template = Some((template.hash, template_bytes));
+            }
+        }
 
         // Encode values
-        let values_bytes = self.encode_values(&values);
-        output.extend_from_slice(&(values_bytes.len() as u16).to_le_bytes());
+        let values_bytes = self.encode_values(&template, &values);
+        write_length(values_bytes.len(), &mut output);
         output.extend_from_slice(&values_bytes);
 
-        Ok(output)
+        Ok((output, investment))
     }
 
-    fn encode_template(&self, template: &super::template::Template) -> Vec<u8> {
-        use super::template::TemplateToken;
-
+    fn encode_template(&mut self, template: &Template) -> Vec<u8> {
         let mut output = Vec::new();
-        output.push(template.pattern.len() as u8);
+        write_length(template.pattern.len(), &mut output);
 
         for token in &template.pattern {
             match token {
-                TemplateToken::ObjectStart => output.push(1),
-                TemplateToken::ObjectEnd => output.push(2),
-                TemplateToken::ArrayStart => output.push(3),
-                TemplateToken::ArrayEnd => output.push(4),
-                TemplateToken::Colon => output.push(5),
-                TemplateToken::Comma => output.push(6),
-                TemplateToken::Key(k) => {
-                    output.push(7);
-                    output.push(k.len() as u8);
-                    output.extend_from_slice(k);
-                }
+                TemplateToken::ObjectStart => output.push(token::OBJECT_START),
+                TemplateToken::ObjectEnd => output.push(token::OBJECT_END),
+                TemplateToken::ArrayStart => output.push(token::ARRAY_START),
+                TemplateToken::ArrayEnd => output.push(token::ARRAY_END),
+                TemplateToken::Colon => output.push(token::COLON),
+                TemplateToken::Comma => output.push(token::COMMA),
+                TemplateToken::Key(k) => match key_ref(self.session_dict, &mut self.local_dict, k) {
+                    Some(id) => {
+                        output.push(token::KEY_REF);
+                        output.extend_from_slice(&id.to_le_bytes());
+                    }
+                    None => {
+                        output.push(token::KEY);
+                        write_length(k.len(), &mut output);
+                        output.extend_from_slice(k);
+                    }
+                },
                 TemplateToken::ValueSlot(t) => {
-                    output.push(8);
+                    output.push(token::VALUE_SLOT);
                     output.push(*t);
                 }
             }
@@ -156,14 +331,55 @@ impl ApexEncoder {
         output
     }
 
-    fn encode_values(&self, values: &[Value]) -> Vec<u8> {
+    fn encode_values(&mut self, template: &Template, values: &[Value]) -> Vec<u8> {
         let mut output = Vec::new();
-        output.extend_from_slice(&(values.len() as u16).to_le_bytes());
+        write_length(values.len(), &mut output);
+
+        // Every integer advances its slot, however it is sent, as on the decoder
+        let mut deltas = self.delta_slots.map(|slots| match slots.get(&template.hash) {
+            Some(state) if self.template_ref => state.clone(),
+            _ => DeltaEncoder::new(template.slot_count),
+        });
+
+        // Walk the template for each slot's key, as the decoder will
+        let mut context = SlotContext::new();
+        let mut predictor = Predictor::new();
+        let mut values = values.iter().enumerate();
+        for token in &template.pattern {
+            match token {
+                TemplateToken::ObjectStart => context.open_object(),
+                TemplateToken::ArrayStart => context.open_array(),
+                TemplateToken::ObjectEnd | TemplateToken::ArrayEnd => context.close(),
+                TemplateToken::Key(k) => context.key(k),
+                TemplateToken::ValueSlot(_) => {
+                    let Some((slot, value)) = values.next() else { break };
+                    let delta = match (&mut deltas, value) {
+                        (Some(deltas), Value::Number(n)) => canonical_integer(n).map(|v| deltas.encode_number(slot, v)),
+                        _ => None,
+                    };
 
-        for value in values {
-            output.extend_from_slice(&value.encode());
+                    let key = context.current();
+                    if self.opts.predictive && predictor.predict(key) == Some(value) {
+                        output.push(value_type::PREDICTED);
+                        continue;
+                    }
+                    let literal = value.encode();
+                    match delta.filter(|_| self.opts.delta).map(|d| d.encode()) {
+                        Some(delta) if 1 + delta.len() < literal.len() => {
+                            output.push(value_type::DELTA);
+                            output.extend_from_slice(&delta);
+                        }
+                        _ => output.extend_from_slice(&literal),
+                    }
+                    if self.opts.predictive {
+                        predictor.update(key, value);
+                    }
+                }
+                TemplateToken::Colon | TemplateToken::Comma => {}
+            }
         }
 
+        self.delta_state = deltas.map(|state| (template.hash, state));
         output
     }
 
@@ -171,25 +387,80 @@ impl ApexEncoder {
     pub fn local_dictionary(&self) -> &Dictionary {
         &self.local_dict
     }
+
+    /// Take the delta state the last frame left its template in, if tracked
+    pub fn take_delta_state(&mut self) -> Option<(u64, DeltaEncoder)> {
+        self.delta_state.take()
+    }
+
+    /// Take the template the last frame sent in full, if templates are shared
+    pub fn take_new_template(&mut self) -> Option<(u64, Vec<u8>)> {
+        self.new_template.take()
+    }
+
+    /// Take the learned local dictionary, ending the borrow of the session's
+    pub fn into_local_dictionary(self) -> Dictionary {
+        self.local_dict
+    }
 }
 
 /// APEX Decoder
-pub struct ApexDecoder {
-    #[allow(dead_code)]
-    session_dict: Dictionary,
+pub struct ApexDecoder<'a> {
+    session_dict: &'a Dictionary,
     learned_dict: Dictionary,
+    known_templates: Option<&'a KnownTemplates>,
+    learned_template: Option<(u64, Vec<u8>)>,
+    delta_slots: Option<&'a DeltaSlots<DeltaDecoder>>,
+    delta_state: Option<(u64, DeltaDecoder)>,
+    /// Version of the frame being decoded
+    version: u8,
+    /// Most bytes a frame may decode to
+    limit: usize,
 }
 
-impl ApexDecoder {
-    pub fn new(_session_dict: &Dictionary) -> Self {
+impl<'a> ApexDecoder<'a> {
+    pub fn new(session_dict: &'a Dictionary) -> Self {
         Self {
-            session_dict: Dictionary::empty(),
+            session_dict,
             learned_dict: Dictionary::empty(),
+            known_templates: None,
+            learned_template: None,
+            delta_slots: None,
+            delta_state: None,
+            version: APEX_VERSION,
+            limit: usize::MAX,
         }
     }
 
+    /// Fail frames that would decode to more than `limit` bytes
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Resolve integers sent as deltas against `slots`
+    ///
+    /// `take_delta_state` returns the template's updated state for the
+    /// caller to store.
+    pub fn deltas(mut self, slots: &'a DeltaSlots<DeltaDecoder>) -> Self {
+        self.delta_slots = Some(slots);
+        self
+    }
+
+    /// Resolve templates referenced by hash from `known`
+    ///
+    /// Templates received in full are returned by `take_learned_template`
+    /// for the caller to add to `known`.
+    pub fn templates(mut self, known: &'a KnownTemplates) -> Self {
+        self.known_templates = Some(known);
+        self
+    }
+
     /// Decode APEX compressed data
     pub fn decode(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        self.learned_dict = Dictionary::empty();
+        self.learned_template = None;
+        self.delta_state = None;
         if input.len() < 6 {
             return Err(Error::CorruptedData);
         }
@@ -203,6 +474,7 @@ impl ApexDecoder {
         if version > APEX_VERSION {
             return Err(Error::UnsupportedVersion);
         }
+        self.version = version;
 
         let frame_flags = input[5];
         let mut pos = 6;
@@ -223,19 +495,19 @@ impl ApexDecoder {
             }
 
             let compressed = &input[pos..pos + compressed_len];
-            return lz4_decompress(compressed);
+            return lz4_decompress_with_limit(compressed, self.limit);
         }
 
         if frame_flags & flags::HAS_TEMPLATE != 0 {
             // Structural decompression
-            let ans_encoded = frame_flags & flags::ANS_ENCODED != 0;
-            return self.decode_structural(&input[pos..], ans_encoded);
+            return self.decode_structural(&input[pos..], frame_flags);
         }
 
         Err(Error::CorruptedData)
     }
 
-    fn decode_structural(&mut self, input: &[u8], ans_encoded: bool) -> Result<Vec<u8>> {
+    fn decode_structural(&mut self, input: &[u8], frame_flags: u8) -> Result<Vec<u8>> {
+        let ans_encoded = frame_flags & flags::ANS_ENCODED != 0;
         // First 4 bytes are data length (part of frame format)
         if input.len() < 4 {
             return Err(Error::CorruptedData);
@@ -245,7 +517,7 @@ impl ApexDecoder {
         // If ANS encoded, decode first to get structural data
         let decoded_input;
         let structural_data: &[u8] = if ans_encoded {
-            decoded_input = ans_decompress(data_bytes)
+            decoded_input = ans_decompress_with_limit(data_bytes, self.limit)
                 .ok_or(Error::CorruptedData)?;
             &decoded_input[..]
         } else {
@@ -258,59 +530,96 @@ impl ApexDecoder {
         if pos + 8 > structural_data.len() {
             return Err(Error::CorruptedData);
         }
-        let _template_hash = u64::from_le_bytes([
+        let template_hash = u64::from_le_bytes([
             structural_data[pos], structural_data[pos + 1], structural_data[pos + 2], structural_data[pos + 3],
             structural_data[pos + 4], structural_data[pos + 5], structural_data[pos + 6], structural_data[pos + 7]
         ]);
         pos += 8;
 
-        // Read template
-        if pos + 2 > structural_data.len() {
-            return Err(Error::CorruptedData);
-        }
-        let template_len = u16::from_le_bytes([structural_data[pos], structural_data[pos + 1]]) as usize;
-        pos += 2;
+        let profile = if frame_flags & flags::HAS_WHITESPACE != 0 {
+            Some(WhitespaceProfile::decode(structural_data, &mut pos, self.version).ok_or(Error::CorruptedData)?)
+        } else {
+            None
+        };
 
-        if pos + template_len > structural_data.len() {
-            return Err(Error::CorruptedData);
+        // Keys new to the session, continuing its IDs
+        if frame_flags & flags::HAS_DICT_UPDATE != 0 {
+            let update_len = read_length(structural_data, &mut pos, self.version).ok_or(Error::CorruptedData)?;
+            if pos + update_len > structural_data.len() {
+                return Err(Error::CorruptedData);
+            }
+            self.learned_dict = Dictionary::decode(&structural_data[pos..pos + update_len], DictionaryLevel::Message);
+            pos += update_len;
         }
-        let template_bytes = &structural_data[pos..pos + template_len];
-        pos += template_len;
 
-        // Read values
-        if pos + 2 > structural_data.len() {
-            return Err(Error::CorruptedData);
-        }
-        let values_len = u16::from_le_bytes([structural_data[pos], structural_data[pos + 1]]) as usize;
-        pos += 2;
+        // Read template, or look up the one referenced
+        let template_bytes: &[u8] = if frame_flags & flags::HAS_TEMPLATE_REF != 0 {
+            self.known_templates
+                .and_then(|known| known.get(&template_hash))
+                .ok_or(Error::UnknownTemplate(template_hash))?
+        } else {
+            let template_len = read_length(structural_data, &mut pos, self.version).ok_or(Error::CorruptedData)?;
+            if pos + template_len > structural_data.len() {
+                return Err(Error::CorruptedData);
+            }
+            let template_bytes = &structural_data[pos..pos + template_len];
+            pos += template_len;
+            template_bytes
+        };
 
+        // Read values
+        let values_len = read_length(structural_data, &mut pos, self.version).ok_or(Error::CorruptedData)?;
         if pos + values_len > structural_data.len() {
             return Err(Error::CorruptedData);
         }
         let values_bytes = &structural_data[pos..pos + values_len];
 
+        // Deltas continue only across frames referencing their template
+        let template_ref = frame_flags & flags::HAS_TEMPLATE_REF != 0;
+        let slot_count = read_length(values_bytes, &mut 0, self.version).unwrap_or(0);
+        let mut deltas = self.delta_slots.map(|slots| match slots.get(&template_hash) {
+            Some(state) if template_ref => state.clone(),
+            _ => DeltaDecoder::new(slot_count),
+        });
+
         // Reconstruct JSON
-        self.reconstruct_json(template_bytes, values_bytes)
+        let predictive = frame_flags & flags::PREDICTIVE != 0;
+        let mut output = self.reconstruct_json(template_bytes, values_bytes, predictive, deltas.as_mut())?;
+        if let Some(profile) = profile {
+            output = profile.apply(&output);
+        }
+        if output.len() > self.limit {
+            return Err(Error::OutputLimitExceeded(self.limit));
+        }
+        if self.known_templates.is_some() && !template_ref {
+            self.learned_template = Some((template_hash, template_bytes.to_vec()));
+        }
+        self.delta_state = deltas.map(|state| (template_hash, state));
+        Ok(output)
     }
 
-    fn reconstruct_json(&self, template: &[u8], values: &[u8]) -> Result<Vec<u8>> {
-        use super::template::Value;
-
+    fn reconstruct_json<'k>(
+        &'k self,
+        template: &'k [u8],
+        values: &[u8],
+        predictive: bool,
+        mut deltas: Option<&mut DeltaDecoder>,
+    ) -> Result<Vec<u8>> {
         let mut output = Vec::new();
+        let mut context = SlotContext::new();
+        let mut predictor = Predictor::new();
         let mut t_pos = 0;
         let mut v_pos = 0;
+        let mut slot = 0;
 
         if template.is_empty() {
             return Err(Error::CorruptedData);
         }
 
         // Skip value count in values
-        if values.len() >= 2 {
-            v_pos = 2;
-        }
+        read_length(values, &mut v_pos, self.version);
 
-        let token_count = template[t_pos] as usize;
-        t_pos += 1;
+        let token_count = read_template_length(template, &mut t_pos, self.version).ok_or(Error::CorruptedData)?;
 
         for _ in 0..token_count {
             if t_pos >= template.len() {
@@ -321,28 +630,52 @@ impl ApexDecoder {
             t_pos += 1;
 
             match token_type {
-                1 => output.push(b'{'),
-                2 => output.push(b'}'),
-                3 => output.push(b'['),
-                4 => output.push(b']'),
-                5 => output.push(b':'),
-                6 => output.push(b','),
-                7 => {
+                token::OBJECT_START => {
+                    context.open_object();
+                    output.push(b'{');
+                }
+                token::OBJECT_END => {
+                    context.close();
+                    output.push(b'}');
+                }
+                token::ARRAY_START => {
+                    context.open_array();
+                    output.push(b'[');
+                }
+                token::ARRAY_END => {
+                    context.close();
+                    output.push(b']');
+                }
+                token::COLON => output.push(b':'),
+                token::COMMA => output.push(b','),
+                token::KEY_REF => {
+                    if t_pos + 2 > template.len() {
+                        return Err(Error::CorruptedData);
+                    }
+                    let id = u16::from_le_bytes([template[t_pos], template[t_pos + 1]]);
+                    t_pos += 2;
+
+                    let key = self.lookup_key(id).ok_or(Error::CorruptedData)?;
+                    context.key(key);
+                    output.push(b'"');
+                    output.extend_from_slice(key);
+                    output.push(b'"');
+                }
+                token::KEY => {
                     // Key
-                    if t_pos >= template.len() {
+                    let Some(key_len) = read_template_length(template, &mut t_pos, self.version) else {
                         break;
-                    }
-                    let key_len = template[t_pos] as usize;
-                    t_pos += 1;
+                    };
 
                     output.push(b'"');
                     if t_pos + key_len <= template.len() {
+                        context.key(&template[t_pos..t_pos + key_len]);
                         output.extend_from_slice(&template[t_pos..t_pos + key_len]);
                     }
                     t_pos += key_len;
                     output.push(b'"');
                 }
-                8 => {
+                token::VALUE_SLOT => {
                     // Value slot
                     if t_pos >= template.len() {
                         break;
@@ -350,8 +683,36 @@ impl ApexDecoder {
                     let _value_type = template[t_pos];
                     t_pos += 1;
 
-                    // Decode value
-                    if let Some(value) = Value::decode(values, &mut v_pos) {
+                    // Decode value, or take the prediction
+                    let key = context.current();
+                    let mut delta_sent = false;
+                    let value = match values.get(v_pos) {
+                        Some(&value_type::PREDICTED) if predictive => {
+                            v_pos += 1;
+                            Some(predictor.predict(key).cloned().ok_or(Error::CorruptedData)?)
+                        }
+                        Some(&value_type::DELTA) => {
+                            v_pos += 1;
+                            let result = DeltaResult::decode(values, &mut v_pos).ok_or(Error::CorruptedData)?;
+                            let state = deltas.as_deref_mut().ok_or(Error::CorruptedData)?;
+                            let n = state.decode(slot, &result).ok_or(Error::CorruptedData)?;
+                            delta_sent = true;
+                            Some(Value::Number(n.to_string().into_bytes()))
+                        }
+                        _ => Value::decode(values, &mut v_pos, self.version),
+                    };
+                    if let Some(value) = &value {
+                        if predictive && predictor.predict(key) != Some(value) {
+                            predictor.update(key, value);
+                        }
+                        if let (Some(state), Value::Number(n), false) = (deltas.as_deref_mut(), value, delta_sent) {
+                            if let Some(n) = canonical_integer(n) {
+                                state.observe(slot, n);
+                            }
+                        }
+                    }
+                    slot += 1;
+                    if let Some(value) = value {
                         match value {
                             Value::String(s) => {
                                 output.push(b'"');
@@ -381,15 +742,40 @@ impl ApexDecoder {
         Ok(output)
     }
 
+    /// Resolve a key reference against the session and this frame's update
+    fn lookup_key(&self, id: u16) -> Option<&[u8]> {
+        let base = self.session_dict.size();
+        match (id as usize).checked_sub(base) {
+            None => self.session_dict.get(id),
+            Some(learned) => self.learned_dict.get(learned as u16),
+        }
+    }
+
     /// Get learned dictionary from decoding
     pub fn learned_dictionary(&self) -> &Dictionary {
         &self.learned_dict
     }
+
+    /// Take the delta state the last frame left its template in, if tracked
+    pub fn take_delta_state(&mut self) -> Option<(u64, DeltaDecoder)> {
+        self.delta_state.take()
+    }
+
+    /// Take the template the last frame carried in full, if templates are shared
+    pub fn take_learned_template(&mut self) -> Option<(u64, Vec<u8>)> {
+        self.learned_template.take()
+    }
+
+    /// Take the learned dictionary, ending the borrow of the session's
+    pub fn into_learned_dictionary(self) -> Dictionary {
+        self.learned_dict
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::apex::ApexSession;
 
     #[test]
     fn test_encode_decode_simple() {
@@ -424,6 +810,164 @@ mod tests {
         assert_eq!(input.as_slice(), decompressed.as_slice());
     }
 
+    #[test]
+    fn test_predictive() {
+        let mut input = String::from(r#"{"region":"eu-west","events":["#);
+        for i in 0..12 {
+            if i > 0 {
+                input.push(',');
+            }
+            let level = if i % 5 == 0 { "error" } else { "info" };
+            input.push_str(&format!(r#"{{"id":{},"level":"{}","service":"checkout","ok":true}}"#, i, level));
+        }
+        input.push_str("]}");
+
+        let dict = Dictionary::new();
+        let plain_opts = ApexOptions::builder().structural(true).build().unwrap();
+        let plain = ApexEncoder::new(plain_opts, &dict).encode(input.as_bytes()).unwrap();
+        let opts = ApexOptions::builder().structural(true).predictive(true).build().unwrap();
+        let predicted = ApexEncoder::new(opts, &dict).encode(input.as_bytes()).unwrap();
+        assert!(predicted[5] & flags::PREDICTIVE != 0);
+        assert!(predicted.len() < plain.len(), "{} vs {}", predicted.len(), plain.len());
+
+        let decompressed = ApexDecoder::new(&dict).decode(&predicted).unwrap();
+        assert!(decompressed == input.as_bytes());
+    }
+
+    #[test]
+    fn test_large_documents() {
+        // Pseudo-random text, so the sections stay large after ANS
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut text = |len: usize| -> String {
+            (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    (b'a' + (state % 26) as u8) as char
+                })
+                .collect()
+        };
+        let single = format!(r#"{{"id":1,"blob":"{}"}}"#, text(300_000));
+        let many = format!(
+            r#"{{"kind":"batch","chunks":[{}]}}"#,
+            (0..40).map(|_| format!(r#""{}""#, text(8_000))).collect::<Vec<_>>().join(",")
+        );
+
+        let dict = Dictionary::new();
+        let opts = ApexOptions::builder().structural(true).build().unwrap();
+        for input in [single, many] {
+            let compressed = ApexEncoder::new(opts.clone(), &dict).encode(input.as_bytes()).unwrap();
+            assert!(compressed[5] & flags::HAS_TEMPLATE != 0);
+            let decompressed = ApexDecoder::new(&dict).decode(&compressed).unwrap();
+            assert!(decompressed == input.as_bytes());
+        }
+    }
+
+    /// Xorshift, below `n`
+    fn next(rng: &mut u64, n: u64) -> u64 {
+        *rng ^= *rng << 13;
+        *rng ^= *rng >> 7;
+        *rng ^= *rng << 17;
+        *rng % n
+    }
+
+    /// Random compact JSON, without escapes
+    fn generate(rng: &mut u64, depth: u32, out: &mut String) {
+        let kind = if depth == 0 { next(rng, 4) } else { next(rng, 6) };
+        match kind {
+            0 => out.push_str(&format!("{}", next(rng, 1_000_000) as i64 - 500_000)),
+            1 => out.push_str(&format!(r#""s{}""#, next(rng, 1 << 20))),
+            2 => out.push_str(["true", "false", "null", "-1.5e3"][next(rng, 4) as usize]),
+            3 => out.push_str(&format!("{}.{}", next(rng, 100), next(rng, 100))),
+            4 => {
+                let len = [0, 3, 20, if depth > 2 { 20 } else { 300 }][next(rng, 4) as usize];
+                out.push('[');
+                for i in 0..len {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    generate(rng, depth - 1, out);
+                }
+                out.push(']');
+            }
+            _ => {
+                let len = next(rng, 8);
+                out.push('{');
+                for i in 0..len {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    let key_len = if next(rng, 10) == 0 { 300 } else { 1 + next(rng, 12) as usize };
+                    let key: String = (0..key_len).map(|j| (b'a' + ((i as usize + j) % 26) as u8) as char).collect();
+                    out.push_str(&format!(r#""{}":"#, key));
+                    generate(rng, depth - 1, out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    #[test]
+    fn test_generated_documents() {
+        let mut rng = 0x9e37_79b9_7f4a_7c15u64;
+        let opts = ApexOptions::builder().structural(true).predictive(true).delta(true).build().unwrap();
+        let mut session = ApexSession::new();
+        let mut peer = ApexSession::new();
+        let mut large = 0;
+        for _ in 0..200 {
+            let mut input = String::new();
+            generate(&mut rng, 4, &mut input);
+
+            let dict = Dictionary::new();
+            let compressed = ApexEncoder::new(opts.clone(), &dict).encode(input.as_bytes()).unwrap();
+            let decompressed = ApexDecoder::new(&dict).decode(&compressed).unwrap();
+            assert!(decompressed == input.as_bytes(), "{}", input);
+
+            let frame = session.compress(input.as_bytes(), &opts).unwrap();
+            assert!(peer.decompress(&frame).unwrap() == input.as_bytes(), "{}", input);
+            if compressed[5] & flags::HAS_TEMPLATE != 0 && input.len() > 2_000 {
+                large += 1;
+            }
+        }
+        // Structural frames with hundreds of template tokens were covered
+        assert!(large > 10, "{}", large);
+    }
+
+    #[test]
+    fn test_long_keys() {
+        let key = "k".repeat(1_000);
+        let input = format!(r#"{{"{}":1,"items":[{}]}}"#, key, vec!["true"; 500].join(","));
+        let dict = Dictionary::new();
+        let opts = ApexOptions::builder().structural(true).build().unwrap();
+        let compressed = ApexEncoder::new(opts, &dict).encode(input.as_bytes()).unwrap();
+        assert!(compressed[5] & flags::HAS_TEMPLATE != 0);
+        assert!(ApexDecoder::new(&dict).decode(&compressed).unwrap() == input.as_bytes());
+    }
+
+    #[test]
+    fn test_escapes_and_whitespace() {
+        let record = r#"{"text":"say \"hi\"\n","path":"C:\\dir\\","uni":"caf\u00e9 \ud83d\ude00","k\"ey":"\\"},{"text":"tab\there","path":"\/","uni":"\u0000","k\"ey":"x"}"#;
+        let records = format!("[{}]", [record; 4].join(","));
+        let pretty = "[\n  {\n    \"text\": \"say \\\"hi\\\"\",\n    \"list\": [],\n    \"more\": [\n      1,\n      \"\\u00e9\"\n    ]\n  },\n  {}\n]\n";
+        let spaced = format!("[{}]", [r#"{"id": 1, "tags": ["a", "b"], "nested": {"x": null, "y": [1.5, true]}}"#; 4].join(", "));
+
+        let dict = Dictionary::new();
+        let opts = ApexOptions::builder().structural(true).build().unwrap();
+        for (input, whitespace) in [(records.as_str(), false), (pretty, true), (spaced.as_str(), true)] {
+            let compressed = ApexEncoder::new(opts.clone(), &dict).encode(input.as_bytes()).unwrap();
+            assert!(compressed[5] & flags::HAS_TEMPLATE != 0, "{}", input);
+            assert_eq!(compressed[5] & flags::HAS_WHITESPACE != 0, whitespace);
+            assert_eq!(ApexDecoder::new(&dict).decode(&compressed).unwrap(), input.as_bytes());
+        }
+
+        // Whitespace no profile reproduces is kept by LZ4
+        let irregular = "{\"id\": 1,\n   \"name\":\"alice\" , \"tags\": [ \"a\",\"b\" ]}";
+        let compressed = ApexEncoder::new(opts, &dict).encode(irregular.as_bytes()).unwrap();
+        assert!(compressed[5] & flags::LZ4_FALLBACK != 0);
+        assert_eq!(ApexDecoder::new(&dict).decode(&compressed).unwrap(), irregular.as_bytes());
+    }
+
     #[test]
     fn test_non_json_fallback() {
         let input = b"This is not JSON, just plain text";
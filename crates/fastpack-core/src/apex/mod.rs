@@ -14,42 +14,117 @@ mod template;
 mod dictionary;
 mod encoder;
 mod delta;
+mod predictor;
+mod whitespace;
 pub mod ans;
 
 pub use tokenizer::{Token, Tokenizer, is_json};
 pub use template::{Template, TemplateExtractor};
 pub use dictionary::{Dictionary, DictionaryLevel};
-pub use encoder::{ApexEncoder, ApexDecoder};
-pub use delta::DeltaEncoder;
-pub use ans::{ans_compress, ans_decompress, FreqTable};
+pub use encoder::{ApexEncoder, ApexDecoder, DeltaSlots, KnownTemplates};
+pub use delta::{DeltaDecoder, DeltaEncoder};
+pub use ans::{ans_compress, ans_decompress, ans_decompress_with_limit, FreqTable};
 
-use crate::Result;
-#[allow(unused_imports)]
-use crate::Error;
+use crate::{Error, Result};
 
 /// APEX magic bytes
 pub const APEX_MAGIC: [u8; 4] = *b"APEX";
 
 /// APEX version
-pub const APEX_VERSION: u8 = 1;
+///
+/// Version 2 added dictionary key references, dictionary updates and
+/// template references. Version 3 writes section and value lengths as
+/// varints, lifting the 64KB limit on sections and strings, and version 4
+/// template token counts and key lengths, lifting the 255 limit on both.
+/// Version 5 added whitespace profiles. Earlier frames still decode.
+pub const APEX_VERSION: u8 = 5;
+
+/// Highest APEX compression level
+pub const APEX_MAX_LEVEL: u8 = 3;
 
 /// APEX compression options
 #[derive(Debug, Clone, Default)]
 pub struct ApexOptions {
     /// Enable structure detection
     pub structural: bool,
-    /// Enable predictive encoding
+    /// Enable predictive encoding: values repeating the last value under
+    /// the same key cost one byte
     pub predictive: bool,
-    /// Enable delta encoding
+    /// Enable delta encoding: integers continuing a slot's step across
+    /// session messages (ids, counters, timestamps) are sent as deltas
     pub delta: bool,
     /// Compression level (0-3)
     pub level: u8,
 }
 
+impl ApexOptions {
+    /// Start building options from the defaults
+    pub fn builder() -> ApexOptionsBuilder {
+        ApexOptionsBuilder::default()
+    }
+
+    /// Check that the options are in range and compatible
+    pub fn validate(&self) -> Result<()> {
+        if self.level > APEX_MAX_LEVEL {
+            return Err(Error::InvalidOptions("level"));
+        }
+        // Predictive and delta encoding both operate on the structural token stream
+        if (self.predictive || self.delta) && !self.structural {
+            return Err(Error::InvalidOptions("structural"));
+        }
+        Ok(())
+    }
+}
+
+/// Builder for `ApexOptions`
+#[derive(Debug, Clone, Default)]
+pub struct ApexOptionsBuilder {
+    opts: ApexOptions,
+}
+
+impl ApexOptionsBuilder {
+    /// Enable structure detection
+    pub fn structural(mut self, enabled: bool) -> Self {
+        self.opts.structural = enabled;
+        self
+    }
+
+    /// Enable predictive encoding (requires `structural`)
+    pub fn predictive(mut self, enabled: bool) -> Self {
+        self.opts.predictive = enabled;
+        self
+    }
+
+    /// Enable delta encoding (requires `structural`)
+    pub fn delta(mut self, enabled: bool) -> Self {
+        self.opts.delta = enabled;
+        self
+    }
+
+    /// Compression level (0-3)
+    pub fn level(mut self, level: u8) -> Self {
+        self.opts.level = level;
+        self
+    }
+
+    /// Validate and return the options
+    pub fn build(self) -> Result<ApexOptions> {
+        self.opts.validate()?;
+        Ok(self.opts)
+    }
+}
+
 /// APEX session for stateful compression
+///
+/// Object keys seen in earlier messages are sent as two-byte dictionary
+/// references, and structures seen before as their template hash. A
+/// session decodes only frames from one sending session, in order, since
+/// both keep their dictionaries in step.
 pub struct ApexSession {
     dictionary: Dictionary,
-    templates: Vec<Template>,
+    templates: KnownTemplates,
+    send_deltas: DeltaSlots<DeltaEncoder>,
+    receive_deltas: DeltaSlots<DeltaDecoder>,
     message_count: u64,
 }
 
@@ -57,18 +132,38 @@ impl ApexSession {
     pub fn new() -> Self {
         Self {
             dictionary: Dictionary::new(),
-            templates: Vec::new(),
+            templates: KnownTemplates::new(),
+            send_deltas: DeltaSlots::new(),
+            receive_deltas: DeltaSlots::new(),
             message_count: 0,
         }
     }
 
+    /// Send the template with `hash` in full again
+    ///
+    /// For a receiver that failed with `Error::UnknownTemplate(hash)`, having
+    /// missed the frame that carried it. Returns whether the template was
+    /// known.
+    pub fn forget_template(&mut self, hash: u64) -> bool {
+        self.send_deltas.remove(&hash);
+        self.templates.remove(&hash).is_some()
+    }
+
     /// Compress with session learning
     pub fn compress(&mut self, input: &[u8], opts: &ApexOptions) -> Result<Vec<u8>> {
-        let mut encoder = ApexEncoder::new(opts.clone(), &self.dictionary);
+        let mut encoder = ApexEncoder::new(opts.clone(), &self.dictionary)
+            .learning(true)
+            .templates(&self.templates)
+            .deltas(&self.send_deltas);
         let result = encoder.encode(input)?;
 
-        // Update session dictionary
-        self.dictionary.merge(&encoder.local_dictionary());
+        // Update session dictionary and templates with what the frame taught
+        let deltas = encoder.take_delta_state();
+        let template = encoder.take_new_template();
+        let learned = encoder.into_local_dictionary();
+        self.send_deltas.extend(deltas);
+        self.dictionary.merge(&learned);
+        self.templates.extend(template);
         self.message_count += 1;
 
         Ok(result)
@@ -76,11 +171,18 @@ impl ApexSession {
 
     /// Decompress with session state
     pub fn decompress(&mut self, input: &[u8]) -> Result<Vec<u8>> {
-        let mut decoder = ApexDecoder::new(&self.dictionary);
+        let mut decoder = ApexDecoder::new(&self.dictionary)
+            .templates(&self.templates)
+            .deltas(&self.receive_deltas);
         let result = decoder.decode(input)?;
 
-        // Update session dictionary from received data
-        self.dictionary.merge(&decoder.learned_dictionary());
+        // Update session dictionary and templates from received data
+        let deltas = decoder.take_delta_state();
+        let template = decoder.take_learned_template();
+        let learned = decoder.into_learned_dictionary();
+        self.receive_deltas.extend(deltas);
+        self.dictionary.merge(&learned);
+        self.templates.extend(template);
 
         Ok(result)
     }
@@ -123,6 +225,14 @@ pub fn apex_decompress(input: &[u8]) -> Result<Vec<u8>> {
     decoder.decode(input)
 }
 
+/// Standalone APEX decompression of untrusted input, failing once the
+/// output would exceed `limit` bytes
+pub fn apex_decompress_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let dict = Dictionary::new();
+    let mut decoder = ApexDecoder::new(&dict).limit(limit);
+    decoder.decode(input)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -138,6 +248,20 @@ mod tests {
         assert_eq!(data.as_slice(), decompressed.as_slice());
     }
 
+    #[test]
+    fn test_apex_decompress_limit() {
+        let data = format!("[{}]", vec![r#"{"id":1,"name":"test"}"#; 1000].join(","));
+        let compressed = apex_compress(data.as_bytes(), &ApexOptions::default()).unwrap();
+        assert_eq!(apex_decompress_with_limit(&compressed, data.len()).unwrap(), data.as_bytes());
+        assert_eq!(apex_decompress_with_limit(&compressed, 1000), Err(Error::OutputLimitExceeded(1000)));
+
+        // Structural data claiming 4 GiB of one ANS symbol
+        let mut bomb = b"APEX".to_vec();
+        bomb.extend_from_slice(&[APEX_VERSION, 0b0010_0001, 0, 0, 0, 0]);
+        bomb.extend_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF, 1, b'x']);
+        assert_eq!(apex_decompress_with_limit(&bomb, 1 << 20), Err(Error::CorruptedData));
+    }
+
     #[test]
     fn test_session_roundtrip() {
         let mut session = ApexSession::new();
@@ -156,9 +280,9 @@ mod tests {
         let c3 = session.compress(data3, &opts).unwrap();
 
         // All should compress
-        assert!(c1.len() > 0);
-        assert!(c2.len() > 0);
-        assert!(c3.len() > 0);
+        assert!(!c1.is_empty());
+        assert!(!c2.is_empty());
+        assert!(!c3.is_empty());
 
         // Verify roundtrip
         let mut decode_session = ApexSession::new();
@@ -173,5 +297,145 @@ mod tests {
         // Session stats
         let stats = session.stats();
         assert_eq!(stats.message_count, 3);
+        assert_eq!(stats.dictionary_size, decode_session.stats().dictionary_size);
+    }
+
+    #[test]
+    fn test_session_dictionary_learning() {
+        let opts = ApexOptions::builder().structural(true).build().unwrap();
+        let messages: Vec<Vec<u8>> = (0..4)
+            .map(|i| format!(r#"{{"request_id":{},"customer_name":"user{}","shipping_address":"street {}"}}"#, i, i, i).into_bytes())
+            .collect();
+
+        let mut sender = ApexSession::new();
+        let mut receiver = ApexSession::new();
+        let initial = sender.stats().dictionary_size;
+        let frames: Vec<Vec<u8>> = messages.iter().map(|m| sender.compress(m, &opts).unwrap()).collect();
+        for (frame, message) in frames.iter().zip(&messages) {
+            assert_eq!(&receiver.decompress(frame).unwrap(), message);
+        }
+
+        // The first frame teaches the three keys; later ones only reference them
+        assert_eq!(sender.stats().dictionary_size, initial + 3);
+        assert_eq!(receiver.stats().dictionary_size, initial + 3);
+        assert!(frames[1].len() + 30 < frames[0].len(), "{} vs {}", frames[1].len(), frames[0].len());
+
+        // Without the session's dictionary the references don't resolve
+        assert!(apex_decompress(&frames[1]).is_err());
+    }
+
+    #[test]
+    fn test_session_fallback_keeps_dictionaries_in_step() {
+        let opts = ApexOptions::builder().structural(true).build().unwrap();
+        let mut sender = ApexSession::new();
+        let mut receiver = ApexSession::new();
+        // Short JSON goes through LZ4 and must not teach its keys
+        let messages: [&[u8]; 3] = [
+            br#"{"alpha":1}"#,
+            br#"{"alpha":1,"bravo":"some longer value","charlie":[1,2,3],"delta":true}"#,
+            br#"{"alpha":2,"bravo":"another longer value","charlie":[4,5,6],"delta":false}"#,
+        ];
+        for message in messages {
+            let frame = sender.compress(message, &opts).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), message);
+        }
+        assert_eq!(sender.stats().dictionary_size, receiver.stats().dictionary_size);
+    }
+
+    #[test]
+    fn test_session_template_references() {
+        let opts = ApexOptions::builder().structural(true).build().unwrap();
+        let message = |i: u32| format!(r#"{{"id":{},"name":"user{}","tags":["admin","beta"],"active":true,"score":{}}}"#, i, i, i * 7).into_bytes();
+
+        let mut sender = ApexSession::new();
+        let mut receiver = ApexSession::new();
+        let full = sender.compress(&message(1), &opts).unwrap();
+        let by_ref = sender.compress(&message(2), &opts).unwrap();
+        assert!(by_ref.len() < full.len(), "{} vs {}", by_ref.len(), full.len());
+        assert_eq!(receiver.decompress(&full).unwrap(), message(1));
+        assert_eq!(receiver.decompress(&by_ref).unwrap(), message(2));
+        assert_eq!(sender.stats().template_count, 1);
+        assert_eq!(receiver.stats().template_count, 1);
+
+        // A receiver that missed the frame carrying a template asks for it again
+        let reordered = |i: u32| format!(r#"{{"name":"user{}","id":{},"score":{},"active":false,"tags":[]}}"#, i, i, i * 7).into_bytes();
+        let _dropped = sender.compress(&reordered(3), &opts).unwrap();
+        let by_ref = sender.compress(&reordered(4), &opts).unwrap();
+        let hash = match receiver.decompress(&by_ref) {
+            Err(Error::UnknownTemplate(hash)) => hash,
+            other => panic!("expected UnknownTemplate, got {:?}", other),
+        };
+        assert!(sender.forget_template(hash));
+        let resent = sender.compress(&reordered(5), &opts).unwrap();
+        assert!(resent.len() > by_ref.len());
+        assert_eq!(receiver.decompress(&resent).unwrap(), reordered(5));
+        assert_eq!(receiver.decompress(&sender.compress(&reordered(6), &opts).unwrap()).unwrap(), reordered(6));
+    }
+
+    #[test]
+    fn test_session_deltas() {
+        let message = |i: i64| {
+            let step = if i == 4 { 5 } else { 1 };
+            format!(
+                r#"{{"id":{},"seq":{},"ts":{},"ratio":1.0,"offset":{},"name":"job"}}"#,
+                1000 + i,
+                i * step,
+                1_700_000_000_000i64 + i * 250,
+                -30_000 - i * 3,
+            )
+            .into_bytes()
+        };
+        let roundtrip = |opts: &ApexOptions| {
+            let mut sender = ApexSession::new();
+            let mut receiver = ApexSession::new();
+            let mut total = 0;
+            for i in 0..8 {
+                let frame = sender.compress(&message(i), opts).unwrap();
+                assert_eq!(receiver.decompress(&frame).unwrap(), message(i), "message {}", i);
+                total += frame.len();
+            }
+            total
+        };
+
+        let plain = roundtrip(&ApexOptions::builder().structural(true).build().unwrap());
+        let delta = roundtrip(&ApexOptions::builder().structural(true).delta(true).build().unwrap());
+        let both = roundtrip(&ApexOptions::builder().structural(true).predictive(true).delta(true).build().unwrap());
+        assert!(delta < plain, "{} vs {}", delta, plain);
+        assert!(both <= delta, "{} vs {}", both, delta);
+
+        // A resent template restarts the deltas on both sides
+        let opts = ApexOptions::builder().structural(true).delta(true).build().unwrap();
+        let mut sender = ApexSession::new();
+        let mut receiver = ApexSession::new();
+        for i in 0..3 {
+            receiver.decompress(&sender.compress(&message(i), &opts).unwrap()).unwrap();
+        }
+        let hash = *sender.templates.keys().next().unwrap();
+        assert!(sender.forget_template(hash));
+        for i in 3..6 {
+            assert_eq!(receiver.decompress(&sender.compress(&message(i), &opts).unwrap()).unwrap(), message(i));
+        }
+    }
+
+    #[test]
+    fn test_options_builder() {
+        let opts = ApexOptions::builder()
+            .structural(true)
+            .predictive(true)
+            .level(APEX_MAX_LEVEL)
+            .build()
+            .unwrap();
+        assert!(opts.structural);
+        assert!(opts.predictive);
+        assert_eq!(opts.level, APEX_MAX_LEVEL);
+
+        assert_eq!(
+            ApexOptions::builder().level(APEX_MAX_LEVEL + 1).build().unwrap_err(),
+            Error::InvalidOptions("level")
+        );
+        assert_eq!(
+            ApexOptions::builder().delta(true).build().unwrap_err(),
+            Error::InvalidOptions("structural")
+        );
     }
 }
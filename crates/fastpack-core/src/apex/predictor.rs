@@ -0,0 +1,107 @@
+//! Key-Conditioned Value Prediction
+//!
+//! Predictive encoding guesses each value from the last value seen under
+//! the same key, a context the JSON grammar makes available to both sides:
+//! members of an object take their own key, array elements the key of the
+//! array. A correct guess is sent as a one-byte `PREDICTED` tag instead of
+//! the value, which pays off for arrays of records sharing statuses, types
+//! and flags. Predictions start afresh with every message.
+
+use super::template::Value;
+use std::collections::HashMap;
+
+/// Tracks the key each value slot sits under while walking a template
+#[derive(Debug, Default)]
+pub struct SlotContext<'k> {
+    /// Key of the values in each open container
+    stack: Vec<&'k [u8]>,
+}
+
+impl<'k> SlotContext<'k> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An object opens: its members take their own keys
+    pub fn open_object(&mut self) {
+        self.stack.push(b"");
+    }
+
+    /// An array opens: its elements share the current context
+    pub fn open_array(&mut self) {
+        let current = self.current();
+        self.stack.push(current);
+    }
+
+    /// A container closes
+    pub fn close(&mut self) {
+        self.stack.pop();
+    }
+
+    /// A key: the next value (or container) sits under it
+    pub fn key(&mut self, key: &'k [u8]) {
+        if let Some(top) = self.stack.last_mut() {
+            *top = key;
+        }
+    }
+
+    /// Context of the next value slot
+    pub fn current(&self) -> &'k [u8] {
+        self.stack.last().copied().unwrap_or(b"")
+    }
+}
+
+/// Last value seen under each key
+#[derive(Debug, Default)]
+pub struct Predictor<'k> {
+    last: HashMap<&'k [u8], Value>,
+}
+
+impl<'k> Predictor<'k> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value predicted under `context`
+    pub fn predict(&self, context: &[u8]) -> Option<&Value> {
+        self.last.get(context)
+    }
+
+    /// Record `value` as seen under `context`, after a wrong prediction
+    pub fn update(&mut self, context: &'k [u8], value: &Value) {
+        self.last.insert(context, value.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_array_elements_share_the_array_key() {
+        let mut ctx = SlotContext::new();
+        ctx.open_object();
+        ctx.key(b"tags");
+        ctx.open_array();
+        assert_eq!(ctx.current(), b"tags");
+        ctx.open_object();
+        ctx.key(b"id");
+        assert_eq!(ctx.current(), b"id");
+        ctx.close();
+        assert_eq!(ctx.current(), b"tags");
+        ctx.close();
+        ctx.key(b"name");
+        assert_eq!(ctx.current(), b"name");
+    }
+
+    #[test]
+    fn test_predicts_last_value_per_key() {
+        let mut predictor = Predictor::new();
+        assert!(predictor.predict(b"status").is_none());
+        predictor.update(b"status", &Value::String(b"ok".to_vec()));
+        predictor.update(b"id", &Value::Number(b"1".to_vec()));
+        assert_eq!(predictor.predict(b"status"), Some(&Value::String(b"ok".to_vec())));
+        predictor.update(b"status", &Value::Null);
+        assert_eq!(predictor.predict(b"status"), Some(&Value::Null));
+    }
+}
@@ -3,8 +3,32 @@
 //! Extracts the structural skeleton from JSON, separating keys from values.
 
 use super::tokenizer::{Token, Tokenizer};
+use crate::frame::{read_varint, write_varint};
 use std::collections::HashMap;
 
+/// Write a section or value length as a varint
+pub(crate) fn write_length(len: usize, out: &mut Vec<u8>) {
+    let mut buf = [0u8; 10];
+    let n = write_varint(len, &mut buf);
+    out.extend_from_slice(&buf[..n]);
+}
+
+/// Read a length written by `write_length`
+///
+/// APEX versions before 3 wrote lengths as a u16 (a u8 for numbers), which
+/// `version` selects.
+pub(crate) fn read_length(input: &[u8], pos: &mut usize, version: u8) -> Option<usize> {
+    if version >= 3 {
+        let (len, n) = read_varint(input.get(*pos..)?).ok()?;
+        *pos += n;
+        Some(len)
+    } else {
+        let bytes = input.get(*pos..*pos + 2)?;
+        *pos += 2;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    }
+}
+
 /// A template represents the structure of a JSON document
 #[derive(Debug, Clone)]
 pub struct Template {
@@ -38,6 +62,10 @@ pub mod value_type {
     pub const NULL: u8 = 3;
     pub const OBJECT: u8 = 4;
     pub const ARRAY: u8 = 5;
+    /// The value predicted for the slot (see `predictor`)
+    pub const PREDICTED: u8 = 6;
+    /// An integer as a `DeltaResult` against the slot's previous value
+    pub const DELTA: u8 = 7;
 }
 
 /// Extracts templates from JSON
@@ -189,7 +217,7 @@ impl Default for TemplateExtractor {
 }
 
 /// Extracted value
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
     String(Vec<u8>),
     Number(Vec<u8>),
@@ -203,13 +231,13 @@ impl Value {
         match self {
             Value::String(s) => {
                 let mut out = vec![value_type::STRING];
-                out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+                write_length(s.len(), &mut out);
                 out.extend_from_slice(s);
                 out
             }
             Value::Number(n) => {
                 let mut out = vec![value_type::NUMBER];
-                out.extend_from_slice(&(n.len() as u8).to_le_bytes());
+                write_length(n.len(), &mut out);
                 out.extend_from_slice(n);
                 out
             }
@@ -222,32 +250,31 @@ impl Value {
         }
     }
 
-    /// Decode value from bytes
-    pub fn decode(input: &[u8], pos: &mut usize) -> Option<Self> {
-        if *pos >= input.len() {
-            return None;
-        }
-
-        let typ = input[*pos];
+    /// Decode value from bytes of the given APEX version
+    pub fn decode(input: &[u8], pos: &mut usize, version: u8) -> Option<Self> {
+        let typ = *input.get(*pos)?;
         *pos += 1;
 
         match typ {
             value_type::STRING => {
-                let len = u16::from_le_bytes([input[*pos], input[*pos + 1]]) as usize;
-                *pos += 2;
-                let s = input[*pos..*pos + len].to_vec();
+                let len = read_length(input, pos, version)?;
+                let s = input.get(*pos..*pos + len)?.to_vec();
                 *pos += len;
                 Some(Value::String(s))
             }
             value_type::NUMBER => {
-                let len = input[*pos] as usize;
-                *pos += 1;
-                let n = input[*pos..*pos + len].to_vec();
+                let len = if version >= 3 {
+                    read_length(input, pos, version)?
+                } else {
+                    *pos += 1;
+                    *input.get(*pos - 1)? as usize
+                };
+                let n = input.get(*pos..*pos + len)?.to_vec();
                 *pos += len;
                 Some(Value::Number(n))
             }
             value_type::BOOL => {
-                let b = input[*pos] != 0;
+                let b = *input.get(*pos)? != 0;
                 *pos += 1;
                 Some(Value::Bool(b))
             }
@@ -260,6 +287,7 @@ impl Value {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::apex::APEX_VERSION;
 
     #[test]
     fn test_extract_simple() {
@@ -318,7 +346,7 @@ mod tests {
         for original in values {
             let encoded = original.encode();
             let mut pos = 0;
-            let decoded = Value::decode(&encoded, &mut pos).unwrap();
+            let decoded = Value::decode(&encoded, &mut pos, APEX_VERSION).unwrap();
 
             match (&original, &decoded) {
                 (Value::String(a), Value::String(b)) => assert_eq!(a, b),
@@ -328,5 +356,14 @@ mod tests {
                 _ => panic!("Type mismatch"),
             }
         }
+
+        // Lengths are varints, u16 before version 3; truncation is not a panic
+        let long = Value::String(vec![b'x'; 70_000]);
+        assert_eq!(Value::decode(&long.encode(), &mut 0, APEX_VERSION), Some(long));
+        let v2 = [value_type::STRING, 2, 0, b'h', b'i', value_type::NUMBER, 1, b'7'];
+        let mut pos = 0;
+        assert_eq!(Value::decode(&v2, &mut pos, 2), Some(Value::String(b"hi".to_vec())));
+        assert_eq!(Value::decode(&v2, &mut pos, 2), Some(Value::Number(b"7".to_vec())));
+        assert_eq!(Value::decode(&[value_type::STRING, 5, b'h'], &mut 0, APEX_VERSION), None);
     }
 }
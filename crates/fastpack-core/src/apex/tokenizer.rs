@@ -41,6 +41,9 @@ impl<'a> Tokenizer<'a> {
     }
 
     /// Get next token
+    ///
+    /// `None` at the end of the input, or at bytes that start no token: an
+    /// unterminated string, a misspelled literal or anything else.
     pub fn next_token(&mut self) -> Option<Token> {
         self.skip_whitespace();
 
@@ -74,10 +77,10 @@ impl<'a> Tokenizer<'a> {
                 self.pos += 1;
                 Token::Comma
             }
-            b'"' => self.read_string(),
-            b't' => self.read_true(),
-            b'f' => self.read_false(),
-            b'n' => self.read_null(),
+            b'"' => self.read_string()?,
+            b't' => self.read_literal(b"true", Token::True)?,
+            b'f' => self.read_literal(b"false", Token::False)?,
+            b'n' => self.read_literal(b"null", Token::Null)?,
             b'-' | b'0'..=b'9' => self.read_number(),
             _ => return None,
         };
@@ -94,12 +97,18 @@ impl<'a> Tokenizer<'a> {
         tokens
     }
 
+    /// Position after the last token read
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
     /// Get slice of input at position
     pub fn slice(&self, start: usize, len: usize) -> &'a [u8] {
         &self.input[start..start + len]
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skip whitespace up to the next token
+    pub fn skip_whitespace(&mut self) {
         while self.pos < self.input.len() {
             match self.input[self.pos] {
                 b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
@@ -108,25 +117,25 @@ impl<'a> Tokenizer<'a> {
         }
     }
 
-    fn read_string(&mut self) -> Token {
+    fn read_string(&mut self) -> Option<Token> {
         let start = self.pos + 1; // Skip opening quote
-        self.pos += 1;
+        let mut pos = start;
 
-        while self.pos < self.input.len() {
-            match self.input[self.pos] {
+        while pos < self.input.len() {
+            match self.input[pos] {
                 b'"' => {
-                    let len = self.pos - start;
-                    self.pos += 1; // Skip closing quote
-                    return Token::String(start, len);
+                    self.pos = pos + 1; // Skip closing quote
+                    return Some(Token::String(start, pos - start));
                 }
                 b'\\' => {
-                    self.pos += 2; // Skip escape sequence
+                    pos += 2; // Skip escape sequence
                 }
-                _ => self.pos += 1,
+                _ => pos += 1,
             }
         }
 
-        Token::String(start, self.pos - start)
+        // Unterminated
+        None
     }
 
     fn read_number(&mut self) -> Token {
@@ -164,19 +173,12 @@ impl<'a> Tokenizer<'a> {
         Token::Number(start, self.pos - start)
     }
 
-    fn read_true(&mut self) -> Token {
-        self.pos += 4; // "true"
-        Token::True
-    }
-
-    fn read_false(&mut self) -> Token {
-        self.pos += 5; // "false"
-        Token::False
-    }
-
-    fn read_null(&mut self) -> Token {
-        self.pos += 4; // "null"
-        Token::Null
+    fn read_literal(&mut self, literal: &[u8], token: Token) -> Option<Token> {
+        if !self.input[self.pos..].starts_with(literal) {
+            return None;
+        }
+        self.pos += literal.len();
+        Some(token)
     }
 }
 
@@ -260,4 +262,18 @@ mod tests {
             panic!("Expected string token");
         }
     }
+
+    #[test]
+    fn test_stops_at_malformed_tokens() {
+        for input in [&br#"{"a":tru}"#[..], br#"{"a":"open"#, br#"{"a":"\"#, b"[1] x"] {
+            let mut tokenizer = Tokenizer::new(input);
+            tokenizer.tokenize_all();
+            assert!(tokenizer.position() < input.len(), "{:?}", input);
+        }
+
+        let input = br#"{"a":"\"q\"\\","b":null}"#;
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(tokenizer.tokenize_all().len(), 9);
+        assert_eq!(tokenizer.position(), input.len());
+    }
 }
@@ -0,0 +1,237 @@
+//! Whitespace Profiles
+//!
+//! Structural mode rebuilds JSON from its tokens, dropping the whitespace
+//! between them. Compact JSON has none to lose. Pretty-printed JSON mostly
+//! follows a profile - a line break and indent per nesting level, or a
+//! separator after colons and commas - which the frame records and the
+//! decoder replays. Input whose whitespace fits no profile, or that doesn't
+//! tokenize to its end, can't be rebuilt and is not sent structurally.
+
+use super::template::{read_length, write_length};
+use super::tokenizer::{Token, Tokenizer};
+
+/// How a JSON input is laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// No whitespace between tokens
+    Compact,
+    /// Whitespace the profile reproduces
+    Profile(WhitespaceProfile),
+    /// Whitespace no profile reproduces, or input that isn't clean JSON
+    Irregular,
+}
+
+/// Token classes whitespace depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Open,
+    Close,
+    Colon,
+    Comma,
+    Value,
+}
+
+impl Kind {
+    fn of(token: Token) -> Self {
+        match token {
+            Token::ObjectStart | Token::ArrayStart => Kind::Open,
+            Token::ObjectEnd | Token::ArrayEnd => Kind::Close,
+            Token::Colon => Kind::Colon,
+            Token::Comma => Kind::Comma,
+            _ => Kind::Value,
+        }
+    }
+}
+
+/// Whitespace placement of pretty-printed JSON
+///
+/// With a `newline`, every member or element of a non-empty container
+/// starts a line indented by `indent` per level, and so does the closing
+/// bracket (`serde_json`, `JSON.stringify(v, null, 2)`). Without one,
+/// `colon` and `comma` follow those separators on a single line (Python's
+/// `json.dumps`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhitespaceProfile {
+    leading: Vec<u8>,
+    colon: Vec<u8>,
+    comma: Vec<u8>,
+    newline: Vec<u8>,
+    indent: Vec<u8>,
+    trailing: Vec<u8>,
+}
+
+impl WhitespaceProfile {
+    /// Append the whitespace before a `kind` token following `prev`, with
+    /// `depth` containers open
+    fn gap(&self, out: &mut Vec<u8>, prev: Option<Kind>, kind: Kind, depth: usize) {
+        let line = |out: &mut Vec<u8>, depth: usize| {
+            out.extend_from_slice(&self.newline);
+            for _ in 0..depth {
+                out.extend_from_slice(&self.indent);
+            }
+        };
+        match (prev, kind) {
+            (None, _) => out.extend_from_slice(&self.leading),
+            (Some(Kind::Open), Kind::Close) => {}
+            (Some(Kind::Open | Kind::Comma), _) if !self.newline.is_empty() => line(out, depth),
+            (_, Kind::Close) if !self.newline.is_empty() => line(out, depth.saturating_sub(1)),
+            (Some(Kind::Comma), _) => out.extend_from_slice(&self.comma),
+            (Some(Kind::Colon), _) => out.extend_from_slice(&self.colon),
+            _ => {}
+        }
+    }
+
+    /// Re-insert the whitespace into compact JSON
+    pub fn apply(&self, compact: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(compact.len() * 2);
+        let mut tokenizer = Tokenizer::new(compact);
+        let mut prev = None;
+        let mut depth = 0usize;
+        loop {
+            let start = tokenizer.position();
+            let Some(token) = tokenizer.next_token() else { break };
+            let kind = Kind::of(token);
+            self.gap(&mut out, prev, kind, depth);
+            out.extend_from_slice(&compact[start..tokenizer.position()]);
+            match kind {
+                Kind::Open => depth += 1,
+                Kind::Close => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            prev = Some(kind);
+        }
+        out.extend_from_slice(&self.trailing);
+        out
+    }
+
+    /// Write the profile
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        for part in [&self.leading, &self.colon, &self.comma, &self.newline, &self.indent, &self.trailing] {
+            write_length(part.len(), out);
+            out.extend_from_slice(part);
+        }
+    }
+
+    /// Read a profile written by `encode`
+    pub fn decode(input: &[u8], pos: &mut usize, version: u8) -> Option<Self> {
+        let mut part = || {
+            let len = read_length(input, pos, version)?;
+            let bytes = input.get(*pos..*pos + len)?.to_vec();
+            *pos += len;
+            Some(bytes)
+        };
+        Some(Self {
+            leading: part()?,
+            colon: part()?,
+            comma: part()?,
+            newline: part()?,
+            indent: part()?,
+            trailing: part()?,
+        })
+    }
+}
+
+impl Layout {
+    /// Find the layout of `input`
+    pub fn detect(input: &[u8]) -> Self {
+        // Each token with the whitespace before it and the depth it sits at
+        let mut tokenizer = Tokenizer::new(input);
+        let mut tokens = Vec::new();
+        let mut depth = 0usize;
+        let trailing = loop {
+            let start = tokenizer.position();
+            tokenizer.skip_whitespace();
+            let gap = &input[start..tokenizer.position()];
+            let Some(token) = tokenizer.next_token() else { break gap };
+            let kind = Kind::of(token);
+            tokens.push((kind, gap, depth));
+            match kind {
+                Kind::Open => depth += 1,
+                Kind::Close => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        };
+        if tokenizer.position() < input.len() {
+            return Layout::Irregular;
+        }
+        if trailing.is_empty() && tokens.iter().all(|(_, gap, _)| gap.is_empty()) {
+            return Layout::Compact;
+        }
+
+        // Take the profile from the first gap of each sort, then check the rest
+        let mut profile = WhitespaceProfile {
+            leading: tokens.first().map_or(&[][..], |(_, gap, _)| gap).to_vec(),
+            trailing: trailing.to_vec(),
+            ..Default::default()
+        };
+        let after = |prev: Kind| tokens.windows(2).find(|pair| pair[0].0 == prev && pair[1].0 != Kind::Close).map(|pair| pair[1]);
+        if let Some((_, gap, _)) = after(Kind::Colon) {
+            profile.colon = gap.to_vec();
+        }
+        match after(Kind::Open) {
+            Some((_, gap, depth)) if gap.contains(&b'\n') => {
+                let (newline, indent) = gap.split_at(gap.iter().position(|&b| b == b'\n').unwrap_or(0) + 1);
+                if depth == 0 || indent.len() % depth != 0 {
+                    return Layout::Irregular;
+                }
+                profile.newline = newline.to_vec();
+                profile.indent = indent[..indent.len() / depth].to_vec();
+            }
+            _ => {
+                if let Some((_, gap, _)) = after(Kind::Comma) {
+                    profile.comma = gap.to_vec();
+                }
+            }
+        }
+
+        let mut expected = Vec::new();
+        let mut prev = None;
+        for (kind, gap, depth) in tokens {
+            expected.clear();
+            profile.gap(&mut expected, prev, kind, depth);
+            if expected != gap {
+                return Layout::Irregular;
+            }
+            prev = Some(kind);
+        }
+        Layout::Profile(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apex::APEX_VERSION;
+
+    fn roundtrip(input: &[u8]) -> WhitespaceProfile {
+        let Layout::Profile(profile) = Layout::detect(input) else {
+            panic!("no profile for {:?}", String::from_utf8_lossy(input));
+        };
+        let mut encoded = Vec::new();
+        profile.encode(&mut encoded);
+        let decoded = WhitespaceProfile::decode(&encoded, &mut 0, APEX_VERSION).unwrap();
+        let compact: Vec<u8> = String::from_utf8_lossy(input).split(['\n', ' ', '\t', '\r']).collect::<String>().into_bytes();
+        assert_eq!(decoded.apply(&compact), input);
+        profile
+    }
+
+    #[test]
+    fn test_detect_layouts() {
+        assert_eq!(Layout::detect(br#"{"a":[1,{}],"b":"x y"}"#), Layout::Compact);
+
+        let pretty = roundtrip(b"{\n  \"a\": [\n    1,\n    {},\n    []\n  ],\n  \"b\": {\n    \"c\": null\n  }\n}\n");
+        assert_eq!(pretty.indent, b"  ");
+        roundtrip(b"[\r\n\t{\r\n\t\t\"id\": 1\r\n\t}\r\n]");
+        roundtrip(br#"{"a": 1, "b": [true, false], "c": {}}"#);
+        roundtrip(b"  [1, 2]  ");
+
+        for irregular in [
+            &b"{\n  \"a\": 1,\n    \"b\": 2\n}"[..],
+            b"{ \"a\": 1 }",
+            br#"{"a":1} x"#,
+            br#"{"a":tru}"#,
+        ] {
+            assert_eq!(Layout::detect(irregular), Layout::Irregular, "{:?}", String::from_utf8_lossy(irregular));
+        }
+    }
+}
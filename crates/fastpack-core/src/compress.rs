@@ -5,6 +5,16 @@
 //! 2. Encode matches as (offset, length) pairs
 //! 3. Store unmatched bytes as literals
 //!
+//! `Level::Fast` takes the one candidate the hash table remembers.
+//! `Level::Better` chains every position to the previous one with the same
+//! hash, compares up to `BETTER_CANDIDATES` of them for the longest match,
+//! and matches lazily: a longer match starting one byte later wins over the
+//! current one.
+//!
+//! With `Options::dictionary`, each block is compressed as if it followed
+//! the end of the dictionary, with the dictionary's positions in the hash
+//! table (or chains) before the block's own.
+//!
 //! Token format:
 //! ```text
 //! ┌────────────────┬────────────────┐
@@ -13,7 +23,8 @@
 //! └────────────────┴────────────────┘
 //! ```
 
-use crate::frame::{BlockHeader, Flags, FrameHeader, MAX_BLOCK_SIZE};
+use crate::frame::{dictionary_window, BlockHeader, Flags, FrameHeader, MAX_BLOCK_SIZE, MAX_OFFSET};
+use xxhash_rust::xxh32::{xxh32, Xxh32};
 use crate::{Level, Options, Result};
 
 /// Minimum match length (must be >= 4 for hash)
@@ -22,6 +33,12 @@ const MIN_MATCH: usize = 4;
 /// Hash table size (power of 2)
 const HASH_SIZE: usize = 1 << 14; // 16384
 
+/// Match candidates `Level::Better` compares per position
+const BETTER_CANDIDATES: usize = 32;
+
+/// Match length `Level::Better` settles for without searching further
+const NICE_MATCH: usize = 16;
+
 /// Hash function for 4 bytes
 #[inline]
 fn hash4(data: &[u8]) -> usize {
@@ -44,41 +61,88 @@ pub fn compress_to(input: &[u8], output: &mut Vec<u8>, opts: &Options) -> Result
 }
 
 /// Streaming compressor
-pub struct Compressor {
-    opts: Options,
+///
+/// `write` takes the input in chunks of any size and emits each block as
+/// it fills; `finish` ends the frame. Blocks are cut at the same offsets as
+/// for a single slice, so the output doesn't depend on the chunking and
+/// matches are found across chunk boundaries.
+pub struct Compressor<'a> {
+    opts: Options<'a>,
+    /// ID the frame header records for `opts.dictionary`
+    dictionary_id: Option<u32>,
     hash_table: Vec<u32>,
+    /// Dictionary window followed by the block being compressed
+    window: Vec<u8>,
+    /// Input of the current frame not yet making up a whole block
+    pending: Vec<u8>,
+    /// Whether the current frame's header is written
+    started: bool,
+    checksum: Xxh32,
 }
 
-impl Compressor {
-    pub fn new(opts: Options) -> Self {
+impl<'a> Compressor<'a> {
+    pub fn new(opts: Options<'a>) -> Self {
         Self {
+            dictionary_id: opts.dictionary.map(|dictionary| xxh32(dictionary, 0)),
             opts,
             hash_table: vec![0; HASH_SIZE],
+            window: Vec::new(),
+            pending: Vec::new(),
+            started: false,
+            checksum: Xxh32::new(0),
         }
     }
 
     /// Compress entire input as a single frame
     pub fn compress_frame(&mut self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
-        // Write frame header
-        let flags = if self.opts.checksum {
-            Flags::new().with_checksum()
-        } else {
-            Flags::new()
-        };
-        let header = FrameHeader::new(flags);
-        let start = output.len();
-        output.resize(start + FrameHeader::SIZE, 0);
-        header.write_to(&mut output[start..])?;
+        self.write(input, output)?;
+        self.finish(output)
+    }
 
-        // Compress in blocks
-        let mut pos = 0;
-        while pos < input.len() {
-            let block_end = (pos + MAX_BLOCK_SIZE).min(input.len());
-            let block = &input[pos..block_end];
-            self.compress_block(block, output)?;
-            pos = block_end;
+    /// Compress the next chunk of the frame, appending complete blocks to
+    /// `output`
+    pub fn write(&mut self, mut input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        if !self.started {
+            self.write_header(output)?;
+        }
+        if self.opts.checksum {
+            self.checksum.update(input);
+        }
+
+        // Top up a partial block first
+        if !self.pending.is_empty() {
+            let take = (MAX_BLOCK_SIZE - self.pending.len()).min(input.len());
+            self.pending.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.pending.len() == MAX_BLOCK_SIZE {
+                let block = std::mem::take(&mut self.pending);
+                self.compress_block(&block, output)?;
+                self.pending = block;
+                self.pending.clear();
+            }
         }
 
+        // Whole blocks straight from the chunk
+        while input.len() >= MAX_BLOCK_SIZE {
+            self.compress_block(&input[..MAX_BLOCK_SIZE], output)?;
+            input = &input[MAX_BLOCK_SIZE..];
+        }
+        self.pending.extend_from_slice(input);
+        Ok(())
+    }
+
+    /// Compress what remains and end the frame
+    ///
+    /// The compressor can then start the next frame.
+    pub fn finish(&mut self, output: &mut Vec<u8>) -> Result<()> {
+        if !self.started {
+            self.write_header(output)?;
+        }
+        let block = std::mem::take(&mut self.pending);
+        self.compress_block(&block, output)?;
+        self.pending = block;
+        self.pending.clear();
+
         // Write end marker
         let end_pos = output.len();
         output.resize(end_pos + 2, 0);
@@ -88,6 +152,28 @@ impl Compressor {
         }
         .write_to(&mut output[end_pos..]);
 
+        if self.opts.checksum {
+            output.extend_from_slice(&self.checksum.digest().to_le_bytes());
+        }
+        self.checksum = Xxh32::new(0);
+        self.started = false;
+        Ok(())
+    }
+
+    fn write_header(&mut self, output: &mut Vec<u8>) -> Result<()> {
+        let flags = if self.opts.checksum {
+            Flags::new().with_checksum()
+        } else {
+            Flags::new()
+        };
+        let mut header = FrameHeader::new(flags);
+        if let Some(id) = self.dictionary_id {
+            header = header.with_dictionary(id);
+        }
+        let start = output.len();
+        output.resize(start + header.size(), 0);
+        header.write_to(&mut output[start..])?;
+        self.started = true;
         Ok(())
     }
 
@@ -100,14 +186,28 @@ impl Compressor {
         // Reset hash table
         self.hash_table.fill(0);
 
+        // Matches may reach back into the dictionary in front of the block
+        let mut window = std::mem::take(&mut self.window);
+        window.clear();
+        let (data, start) = match self.opts.dictionary {
+            Some(dictionary) if self.opts.level != Level::None => {
+                window.extend_from_slice(dictionary_window(dictionary));
+                window.extend_from_slice(input);
+                (window.as_slice(), window.len() - input.len())
+            }
+            _ => (input, 0),
+        };
+
         // Compress based on level
         let compressed = match self.opts.level {
             Level::None => {
                 // No compression, just copy
                 input.to_vec()
             }
-            Level::Fast | Level::Better => self.compress_lz4(input),
+            Level::Fast => self.compress_lz4(data, start),
+            Level::Better => self.compress_lz4_chained(data, start),
         };
+        self.window = window;
 
         // If compression didn't help, store uncompressed
         let (data, original_size) = if compressed.len() >= input.len() {
@@ -132,11 +232,16 @@ impl Compressor {
         Ok(())
     }
 
-    /// LZ4-style compression
-    fn compress_lz4(&mut self, input: &[u8]) -> Vec<u8> {
-        let mut output = Vec::with_capacity(input.len());
-        let mut pos: usize = 0;
-        let mut literal_start: usize = 0;
+    /// LZ4-style compression of `input[start..]`
+    fn compress_lz4(&mut self, input: &[u8], start: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len() - start);
+        let mut pos = start;
+        let mut literal_start = start;
+
+        // Seed the hash table with the dictionary
+        for seed in 0..start.min((input.len() + 1).saturating_sub(MIN_MATCH)) {
+            self.hash_table[hash4(&input[seed..])] = seed as u32;
+        }
 
         while pos + MIN_MATCH <= input.len() {
             let hash = hash4(&input[pos..]);
@@ -177,6 +282,45 @@ impl Compressor {
         output
     }
 
+    /// LZ4-style compression of `input[start..]` over hash chains, with
+    /// lazy matching
+    fn compress_lz4_chained(&mut self, input: &[u8], start: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len() - start);
+        let mut hash_table = std::mem::take(&mut self.hash_table);
+        let mut chains = HashChains::new(&mut hash_table, input);
+        let mut pos = start;
+        let mut literal_start = start;
+
+        while pos + MIN_MATCH <= input.len() {
+            let Some((mut match_len, mut offset)) = chains.longest_match(pos) else {
+                pos += 1;
+                continue;
+            };
+
+            // Defer to a longer match starting at the next byte
+            while match_len < NICE_MATCH {
+                let Some((next_len, next_offset)) = chains.longest_match(pos + 1) else { break };
+                if next_len <= match_len {
+                    break;
+                }
+                pos += 1;
+                (match_len, offset) = (next_len, next_offset);
+            }
+
+            self.write_sequence(&mut output, &input[literal_start..pos], offset, match_len);
+            pos += match_len;
+            literal_start = pos;
+        }
+
+        // Write remaining literals
+        if literal_start < input.len() {
+            self.write_literals(&mut output, &input[literal_start..]);
+        }
+
+        self.hash_table = hash_table;
+        output
+    }
+
     /// Write a sequence (literals + match)
     fn write_sequence(&self, output: &mut Vec<u8>, literals: &[u8], offset: usize, match_len: usize) {
         let literal_len = literals.len();
@@ -241,6 +385,86 @@ impl Compressor {
     }
 }
 
+/// Length of the common prefix of `a` and `b`
+#[inline]
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let max = a.len().min(b.len());
+    let mut len = 0;
+    while len + 8 <= max {
+        let x = u64::from_le_bytes(a[len..len + 8].try_into().unwrap());
+        let y = u64::from_le_bytes(b[len..len + 8].try_into().unwrap());
+        if x != y {
+            return len + ((x ^ y).trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    len + a[len..max].iter().zip(&b[len..max]).take_while(|(x, y)| x == y).count()
+}
+
+/// Every earlier position of a block by hash, newest first
+///
+/// The hash table holds the newest position with each hash (plus one, so
+/// zero is empty) and `prev` links every position to the one before it
+/// with the same hash.
+struct HashChains<'a> {
+    head: &'a mut [u32],
+    prev: Vec<u32>,
+    input: &'a [u8],
+    /// Positions below this are chained
+    inserted: usize,
+}
+
+impl<'a> HashChains<'a> {
+    fn new(head: &'a mut [u32], input: &'a [u8]) -> Self {
+        Self {
+            head,
+            prev: vec![0; input.len()],
+            input,
+            inserted: 0,
+        }
+    }
+
+    /// Longest match for `pos` among the candidates before it, as
+    /// `(length, offset)`
+    fn longest_match(&mut self, pos: usize) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > self.input.len() {
+            return None;
+        }
+        while self.inserted < pos {
+            let hash = hash4(&self.input[self.inserted..]);
+            self.prev[self.inserted] = self.head[hash];
+            self.head[hash] = self.inserted as u32 + 1;
+            self.inserted += 1;
+        }
+
+        let rest = &self.input[pos..];
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[hash4(rest)] as usize;
+        for _ in 0..BETTER_CANDIDATES {
+            // Chains run back in position order, so the rest are farther still
+            if candidate == 0 || pos - (candidate - 1) > MAX_OFFSET {
+                break;
+            }
+            let start = candidate - 1;
+            candidate = self.prev[start] as usize;
+
+            // Only a candidate agreeing one byte past the best can beat it
+            let best_len = best.map_or(MIN_MATCH - 1, |(len, _)| len);
+            if best_len >= rest.len() || self.input[start + best_len] != rest[best_len] {
+                continue;
+            }
+            let len = common_prefix(&self.input[start..], rest);
+            if len > best_len {
+                best = Some((len, pos - start));
+                if len >= NICE_MATCH {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -257,13 +481,64 @@ mod tests {
     #[test]
     fn test_compress_empty() {
         let result = compress(b"", &Options::default()).unwrap();
-        assert!(result.len() > 0); // At least header + end marker
+        assert!(!result.is_empty()); // At least header + end marker
     }
 
     #[test]
     fn test_compress_small() {
         let result = compress(b"hello", &Options::default()).unwrap();
-        assert!(result.len() > 0);
+        assert!(!result.is_empty());
+    }
+
+    #[test]
+    fn test_better_level() {
+        let mut json = String::from("[");
+        for i in 0..400 {
+            let status = ["active", "pending", "suspended"][i % 3];
+            json.push_str(&format!(
+                r#"{{"id":{},"user":"user{:03}","status":"{}","score":{},"tags":["t{}","t{}"]}},"#,
+                i,
+                i * 7 % 100,
+                status,
+                i * 37 % 1000,
+                i % 5,
+                i % 11
+            ));
+        }
+        json.push(']');
+        let overlapping = b"ab".repeat(5000);
+
+        for data in [json.as_bytes(), &overlapping, b"short", b""] {
+            let fast = compress(data, &Options::default()).unwrap();
+            let better = compress(data, &Options { level: Level::Better, ..Default::default() }).unwrap();
+            assert_eq!(crate::decompress(&better).unwrap(), data);
+            assert!(better.len() <= fast.len(), "{} vs {}", better.len(), fast.len());
+        }
+
+        let fast = compress(json.as_bytes(), &Options::default()).unwrap();
+        let better = compress(json.as_bytes(), &Options { level: Level::Better, ..Default::default() }).unwrap();
+        assert!(better.len() * 10 < fast.len() * 9, "{} vs {}", better.len(), fast.len());
+    }
+
+    #[test]
+    fn test_streaming_write() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 97) as u8 ^ (i / 5000) as u8).collect();
+        for opts in [Options::default(), Options { level: Level::Better, checksum: true, ..Default::default() }] {
+            let whole = compress(&data, &opts).unwrap();
+            let mut compressor = Compressor::new(opts.clone());
+            for chunk in [1, 1000, 70_000] {
+                let mut streamed = Vec::new();
+                for piece in data.chunks(chunk) {
+                    compressor.write(piece, &mut streamed).unwrap();
+                }
+                compressor.finish(&mut streamed).unwrap();
+                assert!(streamed == whole, "chunks of {}", chunk);
+            }
+
+            let mut empty = Vec::new();
+            compressor.finish(&mut empty).unwrap();
+            assert_eq!(empty, compress(b"", &opts).unwrap());
+        }
     }
 
     #[test]
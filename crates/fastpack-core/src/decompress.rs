@@ -1,6 +1,7 @@
 //! LZ4-style decompression implementation
 
-use crate::frame::{BlockHeader, FrameHeader};
+use crate::frame::{dictionary_window, BlockHeader, FrameHeader, CHECKSUM_SIZE};
+use xxhash_rust::xxh32::{xxh32, Xxh32};
 use crate::{Error, Result};
 
 /// Decompress data
@@ -16,14 +17,174 @@ pub fn decompress_to(input: &[u8], output: &mut Vec<u8>) -> Result<()> {
     decompressor.decompress_frame(input, output)
 }
 
+/// Decompress untrusted data, failing once the output would exceed `limit` bytes
+pub fn decompress_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    Decompressor::new().limit(limit).decompress_frame(input, &mut output)?;
+    Ok(output)
+}
+
+/// Decompress data compressed with `Options::dictionary`
+pub fn decompress_with_dictionary(input: &[u8], dictionary: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    Decompressor::with_dictionary(dictionary).decompress_frame(input, &mut output)?;
+    Ok(output)
+}
+
 /// Streaming decompressor
-pub struct Decompressor {
-    // Reserved for streaming state
+///
+/// `decompress_frame` takes a whole frame. For one arriving in chunks,
+/// `write` buffers each chunk and `read` pulls out whatever the complete
+/// blocks so far decode to, holding at most one block of either.
+pub struct Decompressor<'a> {
+    /// Dictionary frames may have been compressed with
+    dictionary: Option<&'a [u8]>,
+    dictionary_id: Option<u32>,
+    stage: Stage,
+    header: Option<FrameHeader>,
+    /// Compressed input not yet decoded
+    pending: Vec<u8>,
+    /// Decoded block not yet read, from `decoded_pos`
+    decoded: Vec<u8>,
+    decoded_pos: usize,
+    checksum: Xxh32,
+    /// Most bytes `decompress_frame` may output
+    limit: usize,
 }
 
-impl Decompressor {
+/// Where a streamed frame is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Header,
+    Blocks,
+    Checksum,
+    Done,
+}
+
+impl<'a> Decompressor<'a> {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            dictionary: None,
+            dictionary_id: None,
+            stage: Stage::Header,
+            header: None,
+            pending: Vec::new(),
+            decoded: Vec::new(),
+            decoded_pos: 0,
+            checksum: Xxh32::new(0),
+            limit: usize::MAX,
+        }
+    }
+
+    /// Fail `decompress_frame` once the frame's content would exceed `limit` bytes
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Decompressor for frames compressed with `dictionary`, or none
+    pub fn with_dictionary(dictionary: &'a [u8]) -> Self {
+        Self {
+            dictionary: Some(dictionary),
+            dictionary_id: Some(xxh32(dictionary, 0)),
+            ..Self::new()
+        }
+    }
+
+    /// Buffer the next chunk of a streamed frame
+    ///
+    /// Input past the end of the frame is ignored.
+    pub fn write(&mut self, input: &[u8]) {
+        if self.stage != Stage::Done {
+            self.pending.extend_from_slice(input);
+        }
+    }
+
+    /// Read decompressed bytes into `buf`
+    ///
+    /// `Ok(0)` means nothing more can be decoded until more input is
+    /// written, or, once `is_finished`, that the frame is complete. A bad
+    /// checksum fails the read that reaches the end of the frame.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.decoded_pos < self.decoded.len() {
+                let n = buf.len().min(self.decoded.len() - self.decoded_pos);
+                buf[..n].copy_from_slice(&self.decoded[self.decoded_pos..self.decoded_pos + n]);
+                self.decoded_pos += n;
+                return Ok(n);
+            }
+            if buf.is_empty() || !self.advance()? {
+                return Ok(0);
+            }
+        }
+    }
+
+    /// Whether the streamed frame has been decoded and read to its end
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Done && self.decoded_pos == self.decoded.len()
+    }
+
+    /// Decode the next part of the buffered input, `false` if it is incomplete
+    fn advance(&mut self) -> Result<bool> {
+        match self.stage {
+            Stage::Header => {
+                let header = match FrameHeader::read_from(&self.pending) {
+                    Err(Error::BufferTooSmall) => return Ok(false),
+                    header => header?,
+                };
+                self.check_dictionary(&header)?;
+                self.pending.drain(..header.size());
+                self.header = Some(header);
+                self.stage = Stage::Blocks;
+            }
+            Stage::Blocks => {
+                let Some((block_header, header_size)) = BlockHeader::read_partial(&self.pending)? else {
+                    return Ok(false);
+                };
+                if block_header.is_end() {
+                    self.pending.drain(..header_size);
+                    let checksum = self.header.as_ref().is_some_and(|header| header.has_checksum());
+                    self.stage = if checksum { Stage::Checksum } else { Stage::Done };
+                    if !checksum {
+                        self.pending.clear();
+                    }
+                    return Ok(true);
+                }
+                // Block sizes are bounded by `BlockHeader::read_from`, so at
+                // most one block is waited for in `pending`
+                let end = header_size.checked_add(block_header.compressed_size).ok_or(Error::CorruptedData)?;
+                if self.pending.len() < end {
+                    return Ok(false);
+                }
+
+                let mut decoded = std::mem::take(&mut self.decoded);
+                decoded.clear();
+                self.decoded_pos = 0;
+                let block_data = &self.pending[header_size..end];
+                if block_header.compressed_size == block_header.original_size {
+                    decoded.extend_from_slice(block_data);
+                } else {
+                    let dictionary = self.block_dictionary(self.header.as_ref());
+                    self.decompress_block(block_data, block_header.original_size, dictionary, &mut decoded)?;
+                }
+                self.checksum.update(&decoded);
+                self.decoded = decoded;
+                self.pending.drain(..end);
+            }
+            Stage::Checksum => {
+                let Some(stored) = self.pending.get(..CHECKSUM_SIZE) else {
+                    return Ok(false);
+                };
+                let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+                if self.checksum.digest() != stored {
+                    return Err(Error::ChecksumMismatch);
+                }
+                self.pending.clear();
+                self.stage = Stage::Done;
+            }
+            Stage::Done => return Ok(false),
+        }
+        Ok(true)
     }
 
     /// Decompress entire frame
@@ -33,8 +194,14 @@ impl Decompressor {
         }
 
         // Read frame header
-        let _header = FrameHeader::read_from(input)?;
-        let mut pos = FrameHeader::SIZE;
+        let header = FrameHeader::read_from(input).map_err(|e| match e {
+            Error::BufferTooSmall => Error::CorruptedData,
+            e => e,
+        })?;
+        self.check_dictionary(&header)?;
+        let dictionary = self.block_dictionary(Some(&header));
+        let mut pos = header.size();
+        let content_start = output.len();
 
         // Read blocks
         loop {
@@ -47,16 +214,27 @@ impl Decompressor {
 
             // Check for end marker
             if block_header.is_end() {
+                if header.has_checksum() {
+                    let stored = input.get(pos..pos + CHECKSUM_SIZE).ok_or(Error::CorruptedData)?;
+                    let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+                    if xxh32(&output[content_start..], 0) != stored {
+                        return Err(Error::ChecksumMismatch);
+                    }
+                }
                 break;
             }
 
             // Validate block
-            if pos + block_header.compressed_size > input.len() {
-                return Err(Error::CorruptedData);
+            let end = pos
+                .checked_add(block_header.compressed_size)
+                .filter(|&end| end <= input.len())
+                .ok_or(Error::CorruptedData)?;
+            if output.len() - content_start + block_header.original_size > self.limit {
+                return Err(Error::OutputLimitExceeded(self.limit));
             }
 
-            let block_data = &input[pos..pos + block_header.compressed_size];
-            pos += block_header.compressed_size;
+            let block_data = &input[pos..end];
+            pos = end;
 
             // Decompress block
             if block_header.compressed_size == block_header.original_size {
@@ -64,16 +242,52 @@ impl Decompressor {
                 output.extend_from_slice(block_data);
             } else {
                 // Compressed block
-                self.decompress_block(block_data, block_header.original_size, output)?;
+                self.decompress_block(block_data, block_header.original_size, dictionary, output)?;
             }
         }
 
         Ok(())
     }
 
-    /// Decompress a single block
+    /// Fail on a frame needing a dictionary other than this one's
+    fn check_dictionary(&self, header: &FrameHeader) -> Result<()> {
+        match header.dictionary_id {
+            Some(id) if self.dictionary_id != Some(id) => Err(Error::DictionaryMismatch(id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// What the blocks of a frame with `header` may reference before them
+    fn block_dictionary(&self, header: Option<&FrameHeader>) -> &'a [u8] {
+        match (header.and_then(|header| header.dictionary_id), self.dictionary) {
+            (Some(_), Some(dictionary)) => dictionary_window(dictionary),
+            _ => &[],
+        }
+    }
+
+    /// Decompress a single block, whose matches may reach into `dictionary`
     fn decompress_block(
-        &mut self,
+        &self,
+        input: &[u8],
+        original_size: usize,
+        dictionary: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<()> {
+        if dictionary.is_empty() {
+            return self.decode_sequences(input, original_size, output);
+        }
+        // Both are bounded: the dictionary window by `MAX_OFFSET`, the block
+        // by `MAX_BLOCK_SIZE`
+        let mut window = Vec::with_capacity(dictionary.len() + original_size);
+        window.extend_from_slice(dictionary);
+        self.decode_sequences(input, original_size, &mut window)?;
+        output.extend_from_slice(&window[dictionary.len()..]);
+        Ok(())
+    }
+
+    /// Decode the sequences of a block onto the end of `output`
+    fn decode_sequences(
+        &self,
         input: &[u8],
         original_size: usize,
         output: &mut Vec<u8>,
@@ -149,10 +363,7 @@ impl Decompressor {
             match_len += 4; // MIN_MATCH
 
             // Copy match
-            let match_start = output.len() - offset;
-            if match_start > output.len() {
-                return Err(Error::CorruptedData);
-            }
+            let match_start = output.len().checked_sub(offset).ok_or(Error::CorruptedData)?;
 
             // Handle overlapping copy
             for i in 0..match_len {
@@ -170,7 +381,7 @@ impl Decompressor {
     }
 }
 
-impl Default for Decompressor {
+impl Default for Decompressor<'_> {
     fn default() -> Self {
         Self::new()
     }
@@ -179,7 +390,7 @@ impl Default for Decompressor {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{compress, Options};
+    use crate::{compress, Level, Options};
 
     #[test]
     fn test_decompress_roundtrip() {
@@ -197,6 +408,154 @@ mod tests {
         assert_eq!(data.as_slice(), decompressed.as_slice());
     }
 
+    #[test]
+    fn test_checksum() {
+        let data = b"Hello, World! Hello, World! Hello, World!";
+        let opts = Options { checksum: true, ..Default::default() };
+        let mut compressed = compress(data, &opts).unwrap();
+        assert_eq!(compressed.len(), compress(data, &Options::default()).unwrap().len() + CHECKSUM_SIZE);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        assert_eq!(decompress(&compressed), Err(Error::ChecksumMismatch));
+        assert_eq!(decompress(&compressed[..last]), Err(Error::CorruptedData));
+
+        // Version 1 set the flag without writing a checksum
+        let mut v1 = compress(data, &Options::default()).unwrap();
+        v1[4] = 1;
+        v1[5] |= crate::Flags::CHECKSUM;
+        assert_eq!(decompress(&v1).unwrap(), data);
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let error = |code: u32, field: &str| {
+            format!(
+                r#"{{"error":{{"code":{},"type":"invalid_request_error","message":"The field '{}' is required for this request.","param":"{}"}}}}"#,
+                code, field, field
+            )
+        };
+        let dictionary = [error(400, "model"), error(422, "messages")].concat();
+        let message = error(400, "max_tokens");
+
+        for level in [Level::Fast, Level::Better] {
+            let plain = compress(message.as_bytes(), &Options { level, ..Default::default() }).unwrap();
+            let opts = Options { level, dictionary: Some(dictionary.as_bytes()), ..Default::default() };
+            let compressed = compress(message.as_bytes(), &opts).unwrap();
+            assert!(compressed.len() * 3 < plain.len(), "{} vs {} bytes", compressed.len(), plain.len());
+            assert_eq!(decompress_with_dictionary(&compressed, dictionary.as_bytes()).unwrap(), message.as_bytes());
+
+            let mut streamed = Decompressor::with_dictionary(dictionary.as_bytes());
+            streamed.write(&compressed);
+            let mut buf = [0u8; 1024];
+            let n = streamed.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], message.as_bytes());
+
+            // Blocks past the first reach into the dictionary as well
+            let large = message.repeat(1000);
+            let compressed = compress(large.as_bytes(), &opts).unwrap();
+            assert_eq!(decompress_with_dictionary(&compressed, dictionary.as_bytes()).unwrap(), large.as_bytes());
+
+            let id = xxh32(dictionary.as_bytes(), 0);
+            assert_eq!(decompress(&compressed), Err(Error::DictionaryMismatch(id)));
+            assert_eq!(decompress_with_dictionary(&compressed, b"other"), Err(Error::DictionaryMismatch(id)));
+            let mut streamed = Decompressor::new();
+            streamed.write(&compressed);
+            assert_eq!(streamed.read(&mut buf), Err(Error::DictionaryMismatch(id)));
+        }
+
+        // A block claiming more than a block's worth is refused before the
+        // dictionary window is allocated for it
+        let opts = Options { dictionary: Some(dictionary.as_bytes()), ..Default::default() };
+        let compressed = compress(message.as_bytes(), &opts).unwrap();
+        let mut hostile = compressed[..FrameHeader::SIZE + FrameHeader::DICTIONARY_ID_SIZE].to_vec();
+        hostile.extend_from_slice(&[0x04, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 0x10, b'a', b'b', b'c']);
+        assert_eq!(decompress_with_dictionary(&hostile, dictionary.as_bytes()), Err(Error::InvalidBlock));
+
+        // A dictionary decompressor still takes frames compressed without it
+        let plain = compress(message.as_bytes(), &Options::default()).unwrap();
+        assert_eq!(decompress_with_dictionary(&plain, dictionary.as_bytes()).unwrap(), message.as_bytes());
+    }
+
+    #[test]
+    fn test_streaming_read() {
+        let data: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+        for checksum in [false, true] {
+            let compressed = compress(&data, &Options { checksum, ..Default::default() }).unwrap();
+            for chunk in [1, 7, 4096, compressed.len()] {
+                let mut decompressor = Decompressor::new();
+                let mut decompressed = Vec::new();
+                let mut buf = [0u8; 1000];
+                for piece in compressed.chunks(chunk) {
+                    decompressor.write(piece);
+                    loop {
+                        let n = decompressor.read(&mut buf).unwrap();
+                        if n == 0 {
+                            break;
+                        }
+                        decompressed.extend_from_slice(&buf[..n]);
+                    }
+                }
+                assert!(decompressor.is_finished());
+                assert!(decompressed == data);
+            }
+
+            let mut truncated = Decompressor::new();
+            truncated.write(&compressed[..compressed.len() - 1]);
+            while truncated.read(&mut [0; 1000]).unwrap() > 0 {}
+            assert!(!truncated.is_finished());
+        }
+
+        let mut corrupted = compress(&data, &Options { checksum: true, ..Default::default() }).unwrap();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        let mut decompressor = Decompressor::new();
+        decompressor.write(&corrupted);
+        let mut result = Ok(1);
+        while matches!(result, Ok(n) if n > 0) {
+            result = decompressor.read(&mut [0; 1000]);
+        }
+        assert_eq!(result, Err(Error::ChecksumMismatch));
+    }
+
+    #[test]
+    fn test_hostile_block_sizes() {
+        let frame = |compressed_size: u64, original_size: u64| {
+            let mut frame = b"FPCK\x03\x00".to_vec();
+            for mut value in [compressed_size, original_size] {
+                while value >= 0x80 {
+                    frame.push(value as u8 | 0x80);
+                    value >>= 7;
+                }
+                frame.push(value as u8);
+            }
+            frame.extend_from_slice(b"\x10abc");
+            frame
+        };
+
+        // A 4 TiB block must not be reserved, nor an offset overflow
+        assert_eq!(decompress(&frame(4, 4 << 40)), Err(Error::InvalidBlock));
+        assert_eq!(decompress(&frame(u64::MAX >> 1, 4)), Err(Error::InvalidBlock));
+        assert_eq!(decompress(&frame(4, 100)), Err(Error::CorruptedData));
+
+        // Streamed, the header fails before any of the block is waited for
+        for (compressed_size, original_size) in [(u64::MAX >> 1, 4), (4, 4 << 40), (1 << 20, 1 << 20)] {
+            let mut streamed = Decompressor::new();
+            let frame = frame(compressed_size, original_size);
+            streamed.write(&frame[..frame.len() - 4]);
+            assert_eq!(streamed.read(&mut [0; 16]), Err(Error::InvalidBlock));
+        }
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let data = vec![7u8; 200_000];
+        let compressed = compress(&data, &Options::default()).unwrap();
+        assert_eq!(decompress_with_limit(&compressed, data.len()).unwrap(), data);
+        assert_eq!(decompress_with_limit(&compressed, 100_000), Err(Error::OutputLimitExceeded(100_000)));
+    }
+
     #[test]
     fn test_decompress_invalid_magic() {
         let result = decompress(b"XXXX\x01\x00");
@@ -6,6 +6,10 @@
 //! │ 4 bytes  │ 1 byte  │ 1 byte│            │
 //! └──────────┴─────────┴───────┴────────────┘
 //!
+//! With the dictionary flag (version 3 on), the flags are followed by the
+//! dictionary ID: the xxHash32 (seed 0) of the dictionary, 4 bytes
+//! little-endian.
+//!
 //! Block format:
 //! ┌─────────────────┬─────────────────┬──────────────┐
 //! │ Compressed Size │ Original Size   │ Data         │
@@ -13,6 +17,10 @@
 //! └─────────────────┴─────────────────┴──────────────┘
 //!
 //! End marker: Compressed Size = 0
+//!
+//! With the checksum flag (version 2 on), the end marker is followed by
+//! the xxHash32 (seed 0) of the uncompressed content, 4 bytes
+//! little-endian. Version 1 frames set the flag without a checksum.
 //! ```
 
 use crate::{Error, Result};
@@ -21,11 +29,22 @@ use crate::{Error, Result};
 pub const MAGIC: [u8; 4] = *b"FPCK";
 
 /// Current format version
-pub const VERSION: u8 = 1;
+pub const VERSION: u8 = 3;
+
+/// Size of the content checksum after the end marker
+pub const CHECKSUM_SIZE: usize = 4;
 
 /// Maximum block size (64KB)
 pub const MAX_BLOCK_SIZE: usize = 64 * 1024;
 
+/// Largest offset a sequence can encode
+pub(crate) const MAX_OFFSET: usize = 65535;
+
+/// The end of `dictionary` that matches can reach
+pub(crate) fn dictionary_window(dictionary: &[u8]) -> &[u8] {
+    &dictionary[dictionary.len().saturating_sub(MAX_OFFSET)..]
+}
+
 /// Flags for frame header
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Flags(u8);
@@ -48,6 +67,15 @@ impl Flags {
         self.0 & Self::CHECKSUM != 0
     }
 
+    pub fn with_dictionary(mut self) -> Self {
+        self.0 |= Self::DICTIONARY;
+        self
+    }
+
+    pub fn has_dictionary(&self) -> bool {
+        self.0 & Self::DICTIONARY != 0
+    }
+
     pub fn as_byte(&self) -> u8 {
         self.0
     }
@@ -62,26 +90,55 @@ impl Flags {
 pub struct FrameHeader {
     pub version: u8,
     pub flags: Flags,
+    /// ID of the dictionary the frame was compressed with
+    pub dictionary_id: Option<u32>,
 }
 
 impl FrameHeader {
     pub const SIZE: usize = 6; // magic(4) + version(1) + flags(1)
 
+    /// Size of the dictionary ID following the flags
+    pub const DICTIONARY_ID_SIZE: usize = 4;
+
+    /// Size of this header, with the dictionary ID
+    pub fn size(&self) -> usize {
+        match self.dictionary_id {
+            Some(_) => Self::SIZE + Self::DICTIONARY_ID_SIZE,
+            None => Self::SIZE,
+        }
+    }
+
+    /// Whether a content checksum follows the end marker
+    pub fn has_checksum(&self) -> bool {
+        self.flags.has_checksum() && self.version >= 2
+    }
+
     pub fn new(flags: Flags) -> Self {
         Self {
             version: VERSION,
             flags,
+            dictionary_id: None,
         }
     }
 
+    /// Header of a frame compressed with the dictionary `id`
+    pub fn with_dictionary(mut self, id: u32) -> Self {
+        self.flags = self.flags.with_dictionary();
+        self.dictionary_id = Some(id);
+        self
+    }
+
     pub fn write_to(&self, buf: &mut [u8]) -> Result<usize> {
-        if buf.len() < Self::SIZE {
+        if buf.len() < self.size() {
             return Err(Error::BufferTooSmall);
         }
         buf[0..4].copy_from_slice(&MAGIC);
         buf[4] = self.version;
         buf[5] = self.flags.as_byte();
-        Ok(Self::SIZE)
+        if let Some(id) = self.dictionary_id {
+            buf[Self::SIZE..Self::SIZE + Self::DICTIONARY_ID_SIZE].copy_from_slice(&id.to_le_bytes());
+        }
+        Ok(self.size())
     }
 
     pub fn read_from(buf: &[u8]) -> Result<Self> {
@@ -96,10 +153,19 @@ impl FrameHeader {
             return Err(Error::UnsupportedVersion);
         }
         let flags = Flags::from_byte(buf[5]);
-        Ok(Self { version, flags })
+        let dictionary_id = if flags.has_dictionary() && version >= 3 {
+            let id = buf.get(Self::SIZE..Self::SIZE + Self::DICTIONARY_ID_SIZE).ok_or(Error::BufferTooSmall)?;
+            Some(u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+        } else {
+            None
+        };
+        Ok(Self { version, flags, dictionary_id })
     }
 }
 
+/// Longest varint `read_varint` accepts
+const MAX_VARINT_SIZE: usize = 10;
+
 /// Write a varint to buffer, return bytes written
 #[inline]
 pub fn write_varint(mut value: usize, buf: &mut [u8]) -> usize {
@@ -154,9 +220,15 @@ impl BlockHeader {
     }
 
     /// Read block header, return (header, bytes_read)
+    ///
+    /// Sizes above `MAX_BLOCK_SIZE` are rejected, so callers may trust them
+    /// for allocation and offsets.
     pub fn read_from(buf: &[u8]) -> Result<(Self, usize)> {
         let (compressed_size, n1) = read_varint(buf)?;
         let (original_size, n2) = read_varint(&buf[n1..])?;
+        if compressed_size > MAX_BLOCK_SIZE || original_size > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidBlock);
+        }
         Ok((
             Self {
                 compressed_size,
@@ -166,6 +238,17 @@ impl BlockHeader {
         ))
     }
 
+    /// Read block header from buffered stream input, `None` if `buf` ends
+    /// inside it
+    pub fn read_partial(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        // Each varint ends at its first byte without the continuation bit
+        let ends = buf.iter().take(2 * MAX_VARINT_SIZE).filter(|&&b| b & 0x80 == 0).count();
+        if ends < 2 && buf.len() < 2 * MAX_VARINT_SIZE {
+            return Ok(None);
+        }
+        Self::read_from(buf).map(Some)
+    }
+
     /// Check if this is end marker
     pub fn is_end(&self) -> bool {
         self.compressed_size == 0
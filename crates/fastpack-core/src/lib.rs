@@ -13,9 +13,9 @@ mod frame;
 pub mod apex;
 
 pub use compress::{compress, compress_to, Compressor};
-pub use decompress::{decompress, decompress_to, Decompressor};
+pub use decompress::{decompress, decompress_to, decompress_with_dictionary, decompress_with_limit, Decompressor};
 pub use frame::{FrameHeader, Flags, MAGIC, VERSION};
-pub use apex::{apex_compress, apex_decompress, ApexSession, ApexOptions};
+pub use apex::{apex_compress, apex_decompress, apex_decompress_with_limit, ApexSession, ApexOptions, ApexOptionsBuilder};
 
 /// Compression level
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -31,11 +31,18 @@ pub enum Level {
 
 /// Compression options
 #[derive(Debug, Clone, Default)]
-pub struct Options {
+pub struct Options<'a> {
     /// Compression level
     pub level: Level,
-    /// Enable checksum
+    /// Append an xxHash32 of the content, verified on decompression
     pub checksum: bool,
+    /// Content both sides know in advance, which matches may reference
+    ///
+    /// Blocks are compressed as if they followed the dictionary (its last
+    /// 64KB), so even small messages find matches in typical content. The
+    /// frame records the dictionary's ID; decompress it with the same
+    /// dictionary.
+    pub dictionary: Option<&'a [u8]>,
 }
 
 /// Error types
@@ -53,6 +60,17 @@ pub enum Error {
     InvalidBlock,
     /// Checksum mismatch
     ChecksumMismatch,
+    /// Invalid or incompatible options (names the offending option)
+    InvalidOptions(&'static str),
+    /// APEX frame referencing a template (by hash) the decoder doesn't know
+    ///
+    /// The sender should send it again; see `ApexSession::forget_template`.
+    UnknownTemplate(u64),
+    /// Frame compressed with a dictionary (by ID) the decompressor doesn't
+    /// have
+    DictionaryMismatch(u32),
+    /// Decompressed output would exceed the given limit (in bytes)
+    OutputLimitExceeded(usize),
 }
 
 impl std::fmt::Display for Error {
@@ -64,6 +82,10 @@ impl std::fmt::Display for Error {
             Error::BufferTooSmall => write!(f, "buffer too small"),
             Error::InvalidBlock => write!(f, "invalid block"),
             Error::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Error::InvalidOptions(option) => write!(f, "invalid options: {}", option),
+            Error::UnknownTemplate(hash) => write!(f, "unknown template {:016x}", hash),
+            Error::DictionaryMismatch(id) => write!(f, "frame needs dictionary {:08x}", id),
+            Error::OutputLimitExceeded(limit) => write!(f, "output exceeds {} bytes", limit),
         }
     }
 }
@@ -121,7 +143,7 @@ mod tests {
     #[test]
     fn test_level_none() {
         let data = b"test data";
-        let opts = Options { level: Level::None, checksum: false };
+        let opts = Options { level: Level::None, ..Default::default() };
         let compressed = compress(data, &opts).unwrap();
         let decompressed = decompress(&compressed).unwrap();
         assert_eq!(data.as_slice(), decompressed.as_slice());
@@ -0,0 +1,33 @@
+//! Auto-selecting compression
+//!
+//! `autoCompressSync` looks at the input and picks the codec for it, so
+//! callers don't have to guess with `recommend_algorithm` and call a
+//! different API for each answer. The envelope format and the choice are
+//! `flux_core::auto`'s.
+
+use flux_core::auto::{auto_codec as codec_of, auto_compress, auto_decompress};
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+
+/// Compress data with the codec that suits it, inside an envelope
+#[napi]
+pub fn auto_compress_sync(data: Buffer) -> Result<Buffer> {
+    Ok(auto_compress(&data).map_err(to_napi)?.into())
+}
+
+/// Decompress an envelope written by `autoCompressSync`
+#[napi]
+pub fn auto_decompress_sync(data: Buffer) -> Result<Buffer> {
+    Ok(auto_decompress(&data).map_err(to_napi)?.into())
+}
+
+/// Name of the codec an envelope was compressed with
+#[napi]
+pub fn auto_codec(data: Buffer) -> Result<String> {
+    Ok(codec_of(&data).map_err(to_napi)?.as_str().to_string())
+}
+
+fn to_napi(e: impl std::fmt::Display) -> Error {
+    Error::from_reason(e.to_string())
+}
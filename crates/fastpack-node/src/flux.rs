@@ -0,0 +1,400 @@
+//! FLUX streaming for Node.js
+//!
+//! `FluxStreamSession` runs `update()`, `receive()` and `reset()` on the
+//! libuv thread pool and returns promises, so computing a delta never
+//! blocks the event loop. Deltas only make sense in order, so each call
+//! takes a ticket when it is made and the pool runs them in ticket order.
+//!
+//! `FluxCompressor` and `FluxDecompressor` do the same for byte streams:
+//! newline-delimited JSON in, back-to-back FLUX frames out, and back. The
+//! package wraps them in `Transform` streams.
+
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+use flux_core::frame::{needs_more_bytes, RawFrame};
+use flux_core::{FluxConfig, FluxLevel, FluxSession, FluxStreamSession as CoreStream, StreamConfig};
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::{Env, Error, JsUnknown, Result, Task};
+use napi_derive::napi;
+
+/// Keyframe options of a `FluxStreamSession`
+#[napi(object)]
+pub struct FluxStreamOptions {
+    /// Send a full snapshot every this many updates (0 = never)
+    pub keyframe_interval: Option<u32>,
+    /// Send a full snapshot once this many milliseconds have passed since the last one (0 = never)
+    pub keyframe_period_ms: Option<f64>,
+}
+
+/// Streaming session statistics
+#[napi(object)]
+pub struct FluxStreamStats {
+    pub updates_sent: f64,
+    pub full_sends: f64,
+    pub delta_sends: f64,
+    pub bytes_full: f64,
+    pub bytes_delta: f64,
+    pub delta_efficiency: f64,
+}
+
+/// Session sending state updates as deltas against the previous state
+#[napi]
+pub struct FluxStreamSession {
+    shared: Arc<Ordered<CoreStream>>,
+}
+
+/// State used by calls in the order they were made
+struct Ordered<T> {
+    turn: Mutex<Turn<T>>,
+    changed: Condvar,
+    /// Ticket of the next call
+    issued: Mutex<u64>,
+}
+
+struct Turn<T> {
+    value: T,
+    /// Ticket allowed to use `value`
+    serving: u64,
+}
+
+impl<T> Ordered<T> {
+    fn new(value: T) -> Arc<Self> {
+        Arc::new(Self { turn: Mutex::new(Turn { value, serving: 0 }), changed: Condvar::new(), issued: Mutex::new(0) })
+    }
+
+    /// Ticket for a call made now
+    fn ticket(&self) -> u64 {
+        let mut issued = self.issued.lock().unwrap_or_else(PoisonError::into_inner);
+        *issued += 1;
+        *issued - 1
+    }
+
+    /// Wait for `ticket`'s turn, run `f` and pass the turn on
+    fn run<R>(&self, ticket: u64, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut turn = self.turn.lock().unwrap_or_else(PoisonError::into_inner);
+        while turn.serving != ticket {
+            turn = self.changed.wait(turn).unwrap_or_else(PoisonError::into_inner);
+        }
+        let result = f(&mut turn.value);
+        turn.serving += 1;
+        self.changed.notify_all();
+        result
+    }
+
+    /// Borrow the value without waiting for pending calls
+    fn peek<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.turn.lock().unwrap_or_else(PoisonError::into_inner).value)
+    }
+}
+
+#[napi]
+impl FluxStreamSession {
+    #[napi(constructor)]
+    pub fn new(options: Option<FluxStreamOptions>) -> Result<Self> {
+        let mut config = StreamConfig::default();
+        if let Some(options) = options {
+            let period_ms = options.keyframe_period_ms.unwrap_or(0.0);
+            if !(period_ms >= 0.0 && period_ms.is_finite()) {
+                return Err(Error::from_reason("keyframePeriodMs must be a non-negative number"));
+            }
+            config.keyframe_interval = options.keyframe_interval.unwrap_or(0);
+            config.keyframe_period = Duration::from_secs_f64(period_ms / 1000.0);
+        }
+        Ok(Self { shared: Ordered::new(CoreStream::with_config(config)) })
+    }
+
+    /// Encode a state object; the first update is the full state, later ones only changes
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn update(&self, state: serde_json::Value) -> AsyncTask<StreamTask> {
+        self.task(Op::Update(state))
+    }
+
+    /// Apply an update and resolve to the full state object
+    #[napi(ts_return_type = "Promise<unknown>")]
+    pub fn receive(&self, delta: Buffer) -> AsyncTask<StreamTask> {
+        self.task(Op::Receive(delta.to_vec()))
+    }
+
+    /// Reset streaming state once pending calls have run
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn reset(&self) -> AsyncTask<StreamTask> {
+        self.task(Op::Reset)
+    }
+
+    /// Streaming statistics, including calls still pending
+    #[napi]
+    pub fn stats(&self) -> FluxStreamStats {
+        self.shared.peek(|session| {
+            let stats = session.stats();
+            FluxStreamStats {
+                updates_sent: stats.updates_sent as f64,
+                full_sends: stats.full_sends as f64,
+                delta_sends: stats.delta_sends as f64,
+                bytes_full: stats.bytes_full as f64,
+                bytes_delta: stats.bytes_delta as f64,
+                delta_efficiency: session.delta_efficiency(),
+            }
+        })
+    }
+
+    fn task(&self, op: Op) -> AsyncTask<StreamTask> {
+        AsyncTask::new(StreamTask { shared: self.shared.clone(), ticket: self.shared.ticket(), op: Some(op) })
+    }
+}
+
+enum Op {
+    Update(serde_json::Value),
+    Receive(Vec<u8>),
+    Reset,
+}
+
+pub enum Outcome {
+    Delta(Vec<u8>),
+    State(serde_json::Value),
+    Done,
+}
+
+/// One `FluxStreamSession` call, run on the libuv thread pool
+pub struct StreamTask {
+    shared: Arc<Ordered<CoreStream>>,
+    ticket: u64,
+    op: Option<Op>,
+}
+
+impl Task for StreamTask {
+    type Output = Outcome;
+    type JsValue = JsUnknown;
+
+    fn compute(&mut self) -> Result<Outcome> {
+        let op = self.op.take().ok_or_else(|| Error::from_reason("Task already ran"))?;
+        self.shared.run(self.ticket, |session| match op {
+            Op::Update(state) => {
+                let json = serde_json::to_vec(&state).map_err(|e| Error::from_reason(e.to_string()))?;
+                session.update(&json).map(Outcome::Delta).map_err(flux_error)
+            }
+            Op::Receive(delta) => {
+                let json = session.receive(&delta).map_err(flux_error)?;
+                serde_json::from_slice(&json).map(Outcome::State).map_err(|e| Error::from_reason(e.to_string()))
+            }
+            Op::Reset => {
+                session.reset();
+                Ok(Outcome::Done)
+            }
+        })
+    }
+
+    fn resolve(&mut self, env: Env, output: Outcome) -> Result<JsUnknown> {
+        match output {
+            Outcome::Delta(bytes) => env.create_buffer_with_data(bytes).map(|buffer| buffer.into_raw().into_unknown()),
+            Outcome::State(state) => env.to_js_value(&state),
+            Outcome::Done => env.get_undefined().map(|undefined| undefined.into_unknown()),
+        }
+    }
+}
+
+// ============================================================================
+// Byte streams (newline-delimited JSON <-> FLUX frames)
+// ============================================================================
+
+/// Options of a `FluxCompressor`
+#[napi(object)]
+pub struct FluxCompressOptions {
+    /// 0 = fast, 1 = default, 2 = max
+    pub level: Option<u32>,
+    /// Append a checksum to every frame
+    pub checksum: Option<bool>,
+}
+
+/// Compresses newline-delimited JSON into back-to-back FLUX frames
+///
+/// Each line is one message; blank lines are skipped. All messages share
+/// one session, so a schema is sent once per stream.
+#[napi]
+pub struct FluxCompressor {
+    stage: Arc<Ordered<Box<dyn ByteStage>>>,
+}
+
+#[napi]
+impl FluxCompressor {
+    #[napi(constructor)]
+    pub fn new(options: Option<FluxCompressOptions>) -> Result<Self> {
+        let options = options.unwrap_or(FluxCompressOptions { level: None, checksum: None });
+        let level = match options.level.unwrap_or(1) {
+            0 => FluxLevel::Fast,
+            1 => FluxLevel::Default,
+            2 => FluxLevel::Max,
+            level => return Err(Error::from_reason(format!("Unknown level {}", level))),
+        };
+        let mut config = FluxConfig::with_level(level);
+        if let Some(checksum) = options.checksum {
+            config.checksum = checksum;
+        }
+        let compressor = Compressor { session: FluxSession::with_config(config), partial: Vec::new() };
+        Ok(Self { stage: Ordered::new(Box::new(compressor)) })
+    }
+
+    /// Compress the complete lines of `chunk`, resolving to their frames
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn push(&self, chunk: Buffer) -> AsyncTask<ByteTask> {
+        ByteTask::new(&self.stage, Some(chunk.to_vec()))
+    }
+
+    /// Compress the last line if it had no newline
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn finish(&self) -> AsyncTask<ByteTask> {
+        ByteTask::new(&self.stage, None)
+    }
+}
+
+/// Decompresses back-to-back FLUX frames into newline-delimited JSON
+#[napi]
+pub struct FluxDecompressor {
+    stage: Arc<Ordered<Box<dyn ByteStage>>>,
+}
+
+#[napi]
+impl FluxDecompressor {
+    #[napi(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let config = FluxConfig::default();
+        let decompressor = Decompressor { max_frame_size: config.max_decompressed_size, session: FluxSession::with_config(config), pending: Vec::new() };
+        Self { stage: Ordered::new(Box::new(decompressor)) }
+    }
+
+    /// Decompress the complete frames of `chunk`, resolving to one JSON line per frame
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn push(&self, chunk: Buffer) -> AsyncTask<ByteTask> {
+        ByteTask::new(&self.stage, Some(chunk.to_vec()))
+    }
+
+    /// Check that the input ended on a frame boundary
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn finish(&self) -> AsyncTask<ByteTask> {
+        ByteTask::new(&self.stage, None)
+    }
+}
+
+/// Bytes in, bytes out, with state carried between chunks
+trait ByteStage: Send {
+    fn push(&mut self, chunk: &[u8], out: &mut Vec<u8>) -> flux_core::Result<()>;
+    fn finish(&mut self, out: &mut Vec<u8>) -> flux_core::Result<()>;
+}
+
+struct Compressor {
+    session: FluxSession,
+    /// Start of a line whose end has not arrived yet
+    partial: Vec<u8>,
+}
+
+impl Compressor {
+    fn compress_line(&mut self, line: &[u8], out: &mut Vec<u8>) -> flux_core::Result<()> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        out.extend_from_slice(&self.session.compress(line)?);
+        Ok(())
+    }
+}
+
+impl ByteStage for Compressor {
+    fn push(&mut self, chunk: &[u8], out: &mut Vec<u8>) -> flux_core::Result<()> {
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            if self.partial.is_empty() {
+                self.compress_line(&rest[..end], out)?;
+            } else {
+                let mut line = std::mem::take(&mut self.partial);
+                line.extend_from_slice(&rest[..end]);
+                self.compress_line(&line, out)?;
+            }
+            rest = &rest[end + 1..];
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) -> flux_core::Result<()> {
+        let line = std::mem::take(&mut self.partial);
+        self.compress_line(&line, out)
+    }
+}
+
+struct Decompressor {
+    session: FluxSession,
+    /// Bytes of a frame that has not fully arrived
+    pending: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl ByteStage for Decompressor {
+    fn push(&mut self, chunk: &[u8], out: &mut Vec<u8>) -> flux_core::Result<()> {
+        self.pending.extend_from_slice(chunk);
+        let mut start = 0;
+        while start < self.pending.len() {
+            let buf = &self.pending[start..];
+            if let Some(more) = needs_more_bytes(buf) {
+                let len = buf.len() + more;
+                if len > self.max_frame_size {
+                    return Err(flux_core::Error::LengthLimitExceeded { len: len as u64, max: self.max_frame_size });
+                }
+                break;
+            }
+            let (_, len) = RawFrame::parse_prefix(buf)?;
+            out.extend_from_slice(&self.session.decompress(&buf[..len])?);
+            out.push(b'\n');
+            start += len;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<u8>) -> flux_core::Result<()> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(flux_core::Error::Truncated { what: "FLUX stream", needed: self.pending.len() + 1, available: self.pending.len() })
+        }
+    }
+}
+
+/// One `push()` or `finish()` call, run on the libuv thread pool
+pub struct ByteTask {
+    stage: Arc<Ordered<Box<dyn ByteStage>>>,
+    ticket: u64,
+    /// `None` for `finish()`
+    chunk: Option<Vec<u8>>,
+}
+
+impl ByteTask {
+    fn new(stage: &Arc<Ordered<Box<dyn ByteStage>>>, chunk: Option<Vec<u8>>) -> AsyncTask<Self> {
+        AsyncTask::new(Self { stage: stage.clone(), ticket: stage.ticket(), chunk })
+    }
+}
+
+impl Task for ByteTask {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> Result<Vec<u8>> {
+        let chunk = self.chunk.take();
+        self.stage.run(self.ticket, |stage| {
+            let mut out = Vec::new();
+            match chunk {
+                Some(chunk) => stage.push(&chunk, &mut out),
+                None => stage.finish(&mut out),
+            }
+            .map_err(flux_error)?;
+            Ok(out)
+        })
+    }
+
+    fn resolve(&mut self, _env: Env, output: Vec<u8>) -> Result<Buffer> {
+        Ok(output.into())
+    }
+}
+
+fn flux_error(e: flux_core::Error) -> Error {
+    Error::from_reason(e.to_string())
+}
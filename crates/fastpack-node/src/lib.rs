@@ -1,4 +1,12 @@
 //! Node.js native addon bindings for FastPack
+//!
+//! FLUX streaming sessions are exported from the `flux` module, and the
+//! codec-choosing `autoCompressSync` from the `auto` module.
+
+mod auto;
+mod flux;
+
+pub use auto::{auto_codec, auto_compress_sync, auto_decompress_sync};
 
 use napi_derive::napi;
 use fastpack_core::{compress as core_compress, decompress as core_decompress, Options, Level};
@@ -21,6 +29,7 @@ pub fn compress_sync_with_level(data: napi::bindgen_prelude::Buffer, level: u8)
             _ => Level::Better,
         },
         checksum: false,
+        dictionary: None,
     };
     let result = core_compress(&data, &opts)
         .map_err(|e| napi::Error::from_reason(e.to_string()))?;
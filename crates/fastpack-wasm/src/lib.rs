@@ -34,6 +34,7 @@ pub fn compress_with_level(data: &[u8], level: u8) -> Result<Vec<u8>, JsValue> {
             _ => Level::Better,
         },
         checksum: false,
+        dictionary: None,
     };
     core_compress(data, &opts)
         .map_err(|e| JsValue::from_str(&e.to_string()))
@@ -53,12 +54,11 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>, JsValue> {
 /// Compress data using APEX algorithm (JSON-optimized)
 #[wasm_bindgen]
 pub fn apex_compress(data: &[u8], structural: bool) -> Result<Vec<u8>, JsValue> {
-    let opts = ApexOptions {
-        structural,
-        predictive: false,
-        delta: false,
-        level: 1,
-    };
+    let opts = ApexOptions::builder()
+        .structural(structural)
+        .level(1)
+        .build()
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
     core_apex_compress(data, &opts)
         .map_err(|e| JsValue::from_str(&e.to_string()))
 }
@@ -76,7 +76,7 @@ pub fn apex_decompress(data: &[u8]) -> Result<Vec<u8>, JsValue> {
 
 thread_local! {
     static SESSIONS: RefCell<HashMap<u32, ApexSession>> = RefCell::new(HashMap::new());
-    static NEXT_SESSION_ID: RefCell<u32> = RefCell::new(1);
+    static NEXT_SESSION_ID: RefCell<u32> = const { RefCell::new(1) };
 }
 
 /// Create a new APEX session for stateful compression
@@ -101,12 +101,11 @@ pub fn apex_session_compress(session_id: u32, data: &[u8], structural: bool) ->
         let session = sessions.get_mut(&session_id)
             .ok_or_else(|| JsValue::from_str("Invalid session ID"))?;
 
-        let opts = ApexOptions {
-            structural,
-            predictive: false,
-            delta: false,
-            level: 1,
-        };
+        let opts = ApexOptions::builder()
+            .structural(structural)
+            .level(1)
+            .build()
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         session.compress(data, &opts)
             .map_err(|e| JsValue::from_str(&e.to_string()))
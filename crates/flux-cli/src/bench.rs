@@ -0,0 +1,128 @@
+//! Quick codec comparison on a single input
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use flux_core::transcode::{decode, encode};
+use flux_core::{ContentEncoding, Error, Result};
+
+use crate::percent;
+
+/// Codecs compared by `flux bench`
+const CODECS: [ContentEncoding; 3] = [
+    ContentEncoding::Flux,
+    ContentEncoding::Gzip,
+    ContentEncoding::Zstd,
+];
+
+/// Result for one codec
+#[derive(Debug)]
+pub struct BenchResult {
+    pub codec: ContentEncoding,
+    pub compressed_size: usize,
+    pub compress_time: Duration,
+    pub decompress_time: Duration,
+    /// Whether decoding reproduced the input
+    pub roundtrip_ok: bool,
+}
+
+/// Run every codec `iterations` times over `input`
+///
+/// Each codec's output is decoded once up front and checked against the
+/// input (semantically for FLUX, byte-exact for the others); a mismatch is
+/// reported rather than treated as an error so lossy cases stay visible.
+pub fn run(input: &[u8], iterations: u32) -> Result<Vec<BenchResult>> {
+    let expected: serde_json::Value = serde_json::from_slice(input)
+        .map_err(|e| Error::ParseError(e.to_string()))?;
+
+    CODECS
+        .iter()
+        .map(|&codec| {
+            let compressed = encode(input, codec)?;
+            let roundtrip = decode(&compressed, codec)?;
+            let roundtrip_ok = match codec {
+                ContentEncoding::Flux => serde_json::from_slice::<serde_json::Value>(&roundtrip)
+                    .is_ok_and(|v| v == expected),
+                _ => roundtrip == input,
+            };
+
+            let start = Instant::now();
+            for _ in 0..iterations {
+                encode(input, codec)?;
+            }
+            let compress_time = start.elapsed() / iterations;
+
+            let start = Instant::now();
+            for _ in 0..iterations {
+                decode(&compressed, codec)?;
+            }
+            let decompress_time = start.elapsed() / iterations;
+
+            Ok(BenchResult {
+                codec,
+                compressed_size: compressed.len(),
+                compress_time,
+                decompress_time,
+                roundtrip_ok,
+            })
+        })
+        .collect()
+}
+
+/// Table view of benchmark results
+pub struct Report<'a> {
+    pub input_size: usize,
+    pub results: &'a [BenchResult],
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Input: {} bytes", self.input_size)?;
+        writeln!(
+            f,
+            "{:<8} {:>10} {:>8} {:>14} {:>14}  roundtrip",
+            "codec", "bytes", "ratio", "compress", "decompress"
+        )?;
+        for r in self.results {
+            writeln!(
+                f,
+                "{:<8} {:>10} {:>7.1}% {:>9.1} MB/s {:>9.1} MB/s  {}",
+                r.codec.as_str(),
+                r.compressed_size,
+                percent(r.compressed_size, self.input_size),
+                throughput(self.input_size, r.compress_time),
+                throughput(self.input_size, r.decompress_time),
+                if r.roundtrip_ok { "ok" } else { "MISMATCH" },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Megabytes of `bytes` processed per second
+fn throughput(bytes: usize, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs == 0.0 {
+        f64::INFINITY
+    } else {
+        bytes as f64 / secs / 1_000_000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_all_codecs() {
+        let json = br#"{"id":1,"name":"alice","score":9.5,"active":true}"#;
+        let results = run(json, 2).unwrap();
+        assert_eq!(results.len(), CODECS.len());
+        assert!(results.iter().all(|r| r.roundtrip_ok));
+
+        let report = Report { input_size: json.len(), results: &results }.to_string();
+        for codec in CODECS {
+            assert!(report.contains(codec.as_str()));
+        }
+    }
+}
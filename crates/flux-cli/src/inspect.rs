@@ -0,0 +1,161 @@
+//! Frame inspection
+
+use std::fmt;
+
+use flux_core::{FrameFlags, FrameHeader, FrameMetadata, RawFrame, Result, Schema};
+
+/// Decoded view of a single FLUX frame
+#[derive(Debug)]
+pub struct FrameInfo {
+    pub header: FrameHeader,
+    pub total_size: usize,
+    pub metadata: FrameMetadata,
+    pub metadata_size: usize,
+    pub schema: Option<Schema>,
+    pub schema_size: usize,
+    pub dictionary_size: usize,
+    pub payload_size: usize,
+    /// Stored and computed CRC32C, when the frame carries a checksum
+    pub checksum: Option<(u32, u32)>,
+}
+
+impl FrameInfo {
+    /// Parse frame layout without decoding the payload
+    pub fn parse(input: &[u8]) -> Result<Self> {
+        let frame = RawFrame::parse(input)?;
+        let checksum = frame
+            .checksum
+            .map(|stored| (stored, crc32c::crc32c(frame.checksummed)));
+        let schema = frame.schema.map(Schema::deserialize).transpose()?;
+
+        Ok(Self {
+            header: frame.header,
+            total_size: input.len(),
+            metadata: frame.metadata,
+            metadata_size: frame.metadata_size,
+            schema,
+            schema_size: frame.schema_size,
+            dictionary_size: frame.dictionary_size,
+            payload_size: frame.payload.len(),
+            checksum,
+        })
+    }
+}
+
+impl fmt::Display for FrameInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.header;
+        writeln!(f, "Frame:     {} bytes", self.total_size)?;
+        let version = header.format_version();
+        let form = if header.is_raw() {
+            " (raw)"
+        } else if header.is_compact() {
+            " (compact header)"
+        } else {
+            ""
+        };
+        writeln!(f, "Version:   {}.{}{}", version >> 4, version & 0x0F, form)?;
+        writeln!(f, "Flags:     {:#010b} {:?}", header.flags.bits(), header.flags)?;
+        writeln!(f, "Schema ID: {}", header.schema_id)?;
+        writeln!(f, "Header:    {} bytes", header.magic_size() + header.size())?;
+        writeln!(f, "Metadata:  {} bytes", self.metadata_size)?;
+        writeln!(f, "Schema:    {} bytes", self.schema_size)?;
+        if header.flags.contains(FrameFlags::DICTIONARY_UPDATE) {
+            writeln!(f, "Dict:      {} bytes", self.dictionary_size)?;
+        }
+        writeln!(f, "Payload:   {} bytes (header says {})", self.payload_size, header.payload_len)?;
+
+        match self.checksum {
+            Some((stored, computed)) if stored == computed => {
+                writeln!(f, "Checksum:  {:#010x} (ok)", stored)?;
+            }
+            Some((stored, computed)) => {
+                writeln!(f, "Checksum:  {:#010x} (MISMATCH, computed {:#010x})", stored, computed)?;
+            }
+            None => writeln!(f, "Checksum:  none")?,
+        }
+
+        if !self.metadata.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Metadata:")?;
+            for (key, value) in self.metadata.iter() {
+                match std::str::from_utf8(value) {
+                    Ok(text) => writeln!(f, "  {}: {}", key, text)?,
+                    Err(_) => writeln!(f, "  {}: {:02x?}", key, value)?,
+                }
+            }
+        }
+
+        if let Some(schema) = &self.schema {
+            writeln!(f)?;
+            writeln!(f, "Schema {} v{} (hash {:#018x}):", schema.id, schema.version, schema.hash)?;
+            if schema.root != flux_core::RootKind::Object {
+                writeln!(f, "  root: {:?} array", schema.root)?;
+            }
+            for field in &schema.fields {
+                let nullable = if field.nullable { " (nullable)" } else { "" };
+                writeln!(f, "  {}: {:?}{}", field.name, field.field_type, nullable)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flux_core::{Error, FrameFlags};
+
+    #[test]
+    fn test_inspect_compressed_frame() {
+        let json = br#"{"id":1,"name":"alice","active":true}"#;
+        let frame = flux_core::compress(json).unwrap();
+
+        let info = FrameInfo::parse(&frame).unwrap();
+        assert_eq!(info.total_size, frame.len());
+        assert!(info.header.flags.contains(FrameFlags::SCHEMA_INCLUDED));
+        assert_eq!(info.schema.as_ref().unwrap().fields.len(), 3);
+
+        let (stored, computed) = info.checksum.unwrap();
+        assert_eq!(stored, computed);
+
+        let text = info.to_string();
+        assert!(text.contains("name"));
+        assert!(text.contains("(ok)"));
+    }
+
+    #[test]
+    fn test_inspect_metadata() {
+        let mut opts = flux_core::MessageOptions::default();
+        opts.metadata.insert("trace-id", "abc");
+        let frame = flux_core::FluxSession::new()
+            .compress_with(br#"{"id":1}"#, &opts)
+            .unwrap();
+
+        let info = FrameInfo::parse(&frame).unwrap();
+        assert_eq!(info.metadata.get_str("trace-id"), Some("abc"));
+        assert!(info.to_string().contains("trace-id: abc"));
+        assert!(info.schema.is_some());
+    }
+
+    #[test]
+    fn test_inspect_compact_header() {
+        let config = flux_core::FluxConfig::builder().compact_header(true).build().unwrap();
+        let frame = flux_core::FluxSession::with_config(config).compress(br#"{"id":1}"#).unwrap();
+
+        let text = FrameInfo::parse(&frame).unwrap().to_string();
+        assert!(text.contains("Version:   2.1 (compact header)"), "{}", text);
+        assert!(text.contains("Header:    4 bytes"), "{}", text);
+
+        let config = flux_core::FluxConfig::builder().raw_threshold(100).build().unwrap();
+        let frame = flux_core::FluxSession::with_config(config).compress(br#"{"id":1}"#).unwrap();
+        assert!(FrameInfo::parse(&frame).unwrap().to_string().contains("Version:   2.1 (raw)"));
+    }
+
+    #[test]
+    fn test_inspect_rejects_garbage() {
+        assert!(matches!(FrameInfo::parse(b"not a flux frame"), Err(Error::InvalidMagic)));
+        assert!(FrameInfo::parse(b"FLUX").is_err());
+    }
+}
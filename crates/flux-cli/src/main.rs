@@ -0,0 +1,220 @@
+//! `flux` - command line tool for FLUX compression
+//!
+//! ```text
+//! flux compress file.json          # writes file.json.flux
+//! flux decompress file.json.flux   # writes file.json
+//! flux inspect file.json.flux      # dumps header, flags, schema and sizes
+//! flux bench file.json             # compares FLUX against gzip and zstd
+//! ```
+
+mod bench;
+mod inspect;
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use flux_core::{FluxConfig, FluxSession, MessageOptions};
+
+use inspect::FrameInfo;
+
+/// File extension for FLUX frames
+const FLUX_EXTENSION: &str = "flux";
+
+#[derive(Parser)]
+#[command(name = "flux", version, about = "FLUX JSON compression tool")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress a JSON file into a FLUX frame
+    Compress {
+        /// Input JSON file (`-` for stdin)
+        input: PathBuf,
+        /// Output file (`-` for stdout, default: <input>.flux)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Omit the CRC32C checksum trailer
+        #[arg(long)]
+        no_checksum: bool,
+        /// Disable entropy coding
+        #[arg(long)]
+        no_entropy: bool,
+        /// Attach frame metadata as KEY=VALUE (repeatable)
+        #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = parse_meta)]
+        meta: Vec<(String, String)>,
+    },
+    /// Decompress a FLUX frame back to JSON
+    Decompress {
+        /// Input FLUX file (`-` for stdin)
+        input: PathBuf,
+        /// Output file (`-` for stdout, default: <input> without .flux)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Pretty-print the JSON output
+        #[arg(long)]
+        pretty: bool,
+    },
+    /// Dump frame header, flags, schema and section sizes
+    Inspect {
+        /// Input FLUX file (`-` for stdin)
+        input: PathBuf,
+    },
+    /// Benchmark FLUX against gzip and zstd on a JSON file
+    Bench {
+        /// Input JSON file (`-` for stdin)
+        input: PathBuf,
+        /// Number of iterations per codec
+        #[arg(short = 'n', long, default_value_t = 100)]
+        iterations: u32,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("flux: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Command::Compress { input, output, no_checksum, no_entropy, meta } => {
+            let data = read_input(&input)?;
+            let config = FluxConfig::builder()
+                .checksum(!no_checksum)
+                .entropy(!no_entropy)
+                .build()?;
+            let mut opts = MessageOptions::default();
+            for (key, value) in meta {
+                opts.metadata.insert(key, value);
+            }
+            let compressed = FluxSession::with_config(config).compress_with(&data, &opts)?;
+
+            let output = output.unwrap_or_else(|| compressed_path(&input));
+            write_output(&output, &compressed)?;
+            eprintln!(
+                "{} -> {} bytes ({:.1}%)",
+                data.len(),
+                compressed.len(),
+                percent(compressed.len(), data.len())
+            );
+        }
+        Command::Decompress { input, output, pretty } => {
+            let data = read_input(&input)?;
+            let mut json = flux_core::decompress(&data)?;
+            if pretty {
+                let value: serde_json::Value = serde_json::from_slice(&json)?;
+                json = serde_json::to_vec_pretty(&value)?;
+                json.push(b'\n');
+            }
+
+            let output = output.unwrap_or_else(|| decompressed_path(&input));
+            write_output(&output, &json)?;
+        }
+        Command::Inspect { input } => {
+            let data = read_input(&input)?;
+            print!("{}", FrameInfo::parse(&data)?);
+        }
+        Command::Bench { input, iterations } => {
+            let data = read_input(&input)?;
+            let results = bench::run(&data, iterations.max(1))?;
+            print!("{}", bench::Report { input_size: data.len(), results: &results });
+        }
+    }
+    Ok(())
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    if is_stdio(path) {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf)?;
+        Ok(buf)
+    } else {
+        fs::read(path)
+    }
+}
+
+fn write_output(path: &Path, data: &[u8]) -> io::Result<()> {
+    if is_stdio(path) {
+        io::stdout().write_all(data)
+    } else {
+        fs::write(path, data)
+    }
+}
+
+/// `file.json` -> `file.json.flux`; stdin goes to stdout
+fn compressed_path(input: &Path) -> PathBuf {
+    if is_stdio(input) {
+        return input.to_path_buf();
+    }
+    let mut name = input.as_os_str().to_owned();
+    name.push(".");
+    name.push(FLUX_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// `file.json.flux` -> `file.json`, anything else -> `<input>.json`; stdin goes to stdout
+fn decompressed_path(input: &Path) -> PathBuf {
+    if is_stdio(input) {
+        return input.to_path_buf();
+    }
+    if input.extension().is_some_and(|ext| ext == FLUX_EXTENSION) {
+        input.with_extension("")
+    } else {
+        input.with_extension("json")
+    }
+}
+
+fn parse_meta(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", arg))
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_paths() {
+        assert_eq!(compressed_path(Path::new("data.json")), PathBuf::from("data.json.flux"));
+        assert_eq!(decompressed_path(Path::new("data.json.flux")), PathBuf::from("data.json"));
+        assert_eq!(decompressed_path(Path::new("data.bin")), PathBuf::from("data.json"));
+        assert_eq!(compressed_path(Path::new("-")), PathBuf::from("-"));
+        assert_eq!(decompressed_path(Path::new("-")), PathBuf::from("-"));
+    }
+
+    #[test]
+    fn test_parse_meta() {
+        assert_eq!(parse_meta("trace=abc=1").unwrap(), ("trace".into(), "abc=1".into()));
+        assert!(parse_meta("novalue").is_err());
+    }
+
+    #[test]
+    fn test_cli_parses() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+}
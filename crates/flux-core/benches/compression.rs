@@ -1,7 +1,7 @@
 //! Compression benchmarks for FLUX v2
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput, BenchmarkId};
-use flux_core::{compress, decompress, FluxSession, FluxStreamSession};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use flux_core::{compress, decompress, FluxConfig, FluxSession, FluxStreamSession, Profile};
 
 fn sample_json_small() -> Vec<u8> {
     br#"{"id":1,"name":"Alice","email":"alice@example.com","age":30}"#.to_vec()
@@ -143,16 +143,55 @@ fn bench_session_caching(c: &mut Criterion) {
 
     let total_bytes: u64 = messages.iter().map(|m| m.len() as u64).sum();
 
-    c.benchmark_group("session_caching")
-        .throughput(Throughput::Bytes(total_bytes))
-        .bench_function("with_caching", |b| {
+    let mut group = c.benchmark_group("session_caching");
+    group.throughput(Throughput::Bytes(total_bytes));
+    group.bench_function("with_caching", |b| {
+        b.iter(|| {
+            let mut session = FluxSession::new();
+            for msg in &messages {
+                let _ = session.compress(black_box(msg));
+            }
+        })
+    });
+
+    // Steady state: one session and one output buffer for every message
+    let mut session = FluxSession::new();
+    let mut out = Vec::new();
+    group.bench_function("compress_into", |b| {
+        b.iter(|| {
+            for msg in &messages {
+                out.clear();
+                let _ = session.compress_into(black_box(msg), &mut out);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_realtime(c: &mut Criterion) {
+    let messages: Vec<Vec<u8>> = (0..10)
+        .map(|i| format!(r#"{{"user":"u{}","text":"message {}","ts":{}}}"#, i % 3, i, 1_700_000_000 + i).into_bytes())
+        .collect();
+    let total_bytes: u64 = messages.iter().map(|m| m.len() as u64).sum();
+
+    // Same steady state under each profile: the difference is what the
+    // small-frame path saves per message
+    let mut group = c.benchmark_group("realtime_small");
+    group.throughput(Throughput::Bytes(total_bytes));
+    for (name, profile) in [("balanced", Profile::Balanced), ("realtime", Profile::Realtime)] {
+        let config = FluxConfig::builder().profile(profile).build().unwrap();
+        let mut session = FluxSession::with_config(config);
+        let mut out = Vec::new();
+        group.bench_function(name, |b| {
             b.iter(|| {
-                let mut session = FluxSession::new();
                 for msg in &messages {
-                    let _ = session.compress(black_box(msg));
+                    out.clear();
+                    let _ = session.compress_into(black_box(msg), &mut out);
                 }
             })
         });
+    }
+    group.finish();
 }
 
 fn bench_decompress(c: &mut Criterion) {
@@ -303,6 +342,7 @@ criterion_group!(
     bench_compress_medium,
     bench_compress_large,
     bench_session_caching,
+    bench_realtime,
     bench_decompress,
     bench_streaming_delta,
     bench_compression_ratios,
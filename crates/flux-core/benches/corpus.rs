@@ -0,0 +1,316 @@
+//! Real-world corpus benchmark with ratio regression gates
+//!
+//! Compresses every file under `corpus/` (or `$FLUX_CORPUS_DIR`), one
+//! subdirectory per source: `.json` files as a single message, `.ndjson`
+//! files as a stream of messages through one session. Prints ratio and
+//! speed as CSV, with gzip and zstd sizes for reference.
+//!
+//! ```text
+//! cargo bench --bench corpus                              # CSV to stdout
+//! cargo bench --bench corpus -- --output corpus.csv
+//! cargo bench --bench corpus -- --check [--tolerance 5]   # fail on ratio regressions
+//! cargo bench --bench corpus -- --save-baseline           # update corpus/baselines.csv
+//! ```
+//!
+//! `--check` compares each file's ratio (input / FLUX size) with
+//! `baselines.csv` in the corpus directory and exits non-zero if any ratio
+//! dropped by more than `--tolerance` percent. Speed is reported but not
+//! gated, since it depends on the machine.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use flux_core::FluxSession;
+
+const BASELINES: &str = "baselines.csv";
+const DEFAULT_TOLERANCE: f64 = 5.0;
+const DEFAULT_ITERATIONS: u32 = 20;
+
+struct Options {
+    corpus: PathBuf,
+    output: Option<PathBuf>,
+    check: bool,
+    save_baseline: bool,
+    tolerance: f64,
+    iterations: u32,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let corpus = std::env::var_os("FLUX_CORPUS_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).ancestors().nth(2).expect("crate lives in the workspace");
+                workspace.join("corpus")
+            });
+        let mut opts = Self {
+            corpus,
+            output: None,
+            check: false,
+            save_baseline: false,
+            tolerance: DEFAULT_TOLERANCE,
+            iterations: DEFAULT_ITERATIONS,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                // Passed by `cargo bench`
+                "--bench" => {}
+                "--check" => opts.check = true,
+                "--save-baseline" => opts.save_baseline = true,
+                "--corpus" => opts.corpus = value(&arg)?.into(),
+                "--output" | "-o" => opts.output = Some(value(&arg)?.into()),
+                "--tolerance" => {
+                    opts.tolerance = value(&arg)?
+                        .parse()
+                        .ok()
+                        .filter(|t: &f64| *t >= 0.0)
+                        .ok_or("--tolerance must be a non-negative percentage")?;
+                }
+                "--iterations" | "-n" => {
+                    opts.iterations = value(&arg)?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or("--iterations must be a positive integer")?;
+                }
+                other => return Err(format!("unknown argument `{}`", other)),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// One corpus file, split into the messages sent through a session
+struct Sample {
+    /// Path relative to the corpus directory, e.g. `github/issues.json`
+    name: String,
+    messages: Vec<Vec<u8>>,
+}
+
+impl Sample {
+    fn mode(&self) -> &'static str {
+        if self.name.ends_with(".ndjson") {
+            "stream"
+        } else {
+            "message"
+        }
+    }
+
+    fn input_size(&self) -> usize {
+        self.messages.iter().map(Vec::len).sum()
+    }
+}
+
+/// `.json` and `.ndjson` files one directory below `dir`, sorted by name
+fn load_corpus(dir: &Path) -> io::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for group in fs::read_dir(dir)? {
+        let group = group?.path();
+        if !group.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&group)? {
+            let path = entry?.path();
+            let ext = path.extension().and_then(|e| e.to_str());
+            let data = match ext {
+                Some("json" | "ndjson") => fs::read(&path)?,
+                _ => continue,
+            };
+            let messages = if ext == Some("ndjson") {
+                data.split(|&b| b == b'\n')
+                    .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+                    .map(<[u8]>::to_vec)
+                    .collect()
+            } else {
+                vec![data]
+            };
+            let name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            samples.push(Sample { name, messages });
+        }
+    }
+    samples.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(samples)
+}
+
+/// Measurements for one sample
+struct Measurement {
+    flux_size: usize,
+    gzip_size: usize,
+    zstd_size: usize,
+    compress_time: Duration,
+    decompress_time: Duration,
+}
+
+impl Measurement {
+    fn ratio(&self, input_size: usize) -> f64 {
+        input_size as f64 / self.flux_size.max(1) as f64
+    }
+}
+
+fn measure(sample: &Sample, iterations: u32) -> Result<Measurement, String> {
+    let compress_all = || -> flux_core::Result<Vec<Vec<u8>>> {
+        let mut session = FluxSession::new();
+        sample.messages.iter().map(|msg| session.compress(msg)).collect()
+    };
+    let frames = compress_all().map_err(|e| format!("{}: {}", sample.name, e))?;
+
+    // Each message on its own, as a per-message HTTP or socket codec would send it
+    let gzip_size = sample.messages.iter().map(|msg| gzip(msg).len()).sum();
+    let zstd_size = sample.messages.iter().map(|msg| zstd::encode_all(&msg[..], 3).map_or(0, |z| z.len())).sum();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(compress_all().ok());
+    }
+    let compress_time = start.elapsed() / iterations;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let mut session = FluxSession::new();
+        for frame in &frames {
+            std::hint::black_box(session.decompress(frame).map_err(|e| format!("{}: {}", sample.name, e))?);
+        }
+    }
+    let decompress_time = start.elapsed() / iterations;
+
+    Ok(Measurement {
+        flux_size: frames.iter().map(Vec::len).sum(),
+        gzip_size,
+        zstd_size,
+        compress_time,
+        decompress_time,
+    })
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn mb_per_sec(bytes: usize, time: Duration) -> f64 {
+    bytes as f64 / 1e6 / time.as_secs_f64().max(1e-9)
+}
+
+fn write_csv(out: &mut impl Write, results: &[(Sample, Measurement)]) -> io::Result<()> {
+    writeln!(
+        out,
+        "file,mode,messages,input_size,flux_size,ratio,gzip_size,zstd_size,compress_mb_s,decompress_mb_s"
+    )?;
+    for (sample, m) in results {
+        let input_size = sample.input_size();
+        writeln!(
+            out,
+            "{},{},{},{},{},{:.3},{},{},{:.1},{:.1}",
+            sample.name,
+            sample.mode(),
+            sample.messages.len(),
+            input_size,
+            m.flux_size,
+            m.ratio(input_size),
+            m.gzip_size,
+            m.zstd_size,
+            mb_per_sec(input_size, m.compress_time),
+            mb_per_sec(input_size, m.decompress_time),
+        )?;
+    }
+    Ok(())
+}
+
+/// `file,ratio` lines, header skipped
+fn read_baselines(path: &Path) -> Result<BTreeMap<String, f64>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    text.lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (name, ratio) = line.rsplit_once(',').ok_or_else(|| format!("bad baseline line `{}`", line))?;
+            let ratio = ratio.trim().parse().map_err(|_| format!("bad baseline ratio in `{}`", line))?;
+            Ok((name.to_string(), ratio))
+        })
+        .collect()
+}
+
+fn write_baselines(path: &Path, results: &[(Sample, Measurement)]) -> io::Result<()> {
+    let mut out = String::from("file,ratio\n");
+    for (sample, m) in results {
+        out.push_str(&format!("{},{:.3}\n", sample.name, m.ratio(sample.input_size())));
+    }
+    fs::write(path, out)
+}
+
+/// Files whose ratio fell more than `tolerance` percent below the baseline
+fn regressions(results: &[(Sample, Measurement)], baselines: &BTreeMap<String, f64>, tolerance: f64) -> Vec<String> {
+    let mut failures = Vec::new();
+    for (sample, m) in results {
+        let ratio = m.ratio(sample.input_size());
+        match baselines.get(&sample.name) {
+            Some(&baseline) if ratio < baseline * (1.0 - tolerance / 100.0) => failures.push(format!(
+                "{}: ratio {:.3} is {:.1}% below baseline {:.3}",
+                sample.name,
+                ratio,
+                100.0 * (1.0 - ratio / baseline),
+                baseline
+            )),
+            Some(_) => {}
+            None => eprintln!("corpus: no baseline for {}", sample.name),
+        }
+    }
+    failures
+}
+
+fn run(opts: &Options) -> Result<bool, String> {
+    let samples = load_corpus(&opts.corpus).map_err(|e| format!("{}: {}", opts.corpus.display(), e))?;
+    if samples.is_empty() {
+        return Err(format!("no .json or .ndjson files under {}", opts.corpus.display()));
+    }
+    let results = samples
+        .into_iter()
+        .map(|sample| measure(&sample, opts.iterations).map(|m| (sample, m)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match &opts.output {
+        Some(path) => {
+            let mut file = fs::File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            write_csv(&mut file, &results)
+        }
+        None => write_csv(&mut io::stdout().lock(), &results),
+    }
+    .map_err(|e| e.to_string())?;
+
+    let baselines = opts.corpus.join(BASELINES);
+    if opts.save_baseline {
+        write_baselines(&baselines, &results).map_err(|e| format!("{}: {}", baselines.display(), e))?;
+        eprintln!("corpus: saved {} baselines to {}", results.len(), baselines.display());
+    }
+    if opts.check {
+        let failures = regressions(&results, &read_baselines(&baselines)?, opts.tolerance);
+        for failure in &failures {
+            eprintln!("corpus: REGRESSION {}", failure);
+        }
+        if !failures.is_empty() {
+            return Ok(false);
+        }
+        eprintln!("corpus: all ratios within {}% of baseline", opts.tolerance);
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    match Options::parse(std::env::args().skip(1)).and_then(|opts| run(&opts)) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("corpus: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Multi-threaded FLUX load test
+//!
+//! Compresses a corpus from N threads for a fixed duration and reports
+//! aggregate throughput, allocation counts and the heap high-water mark.
+//!
+//! ```text
+//! cargo run --release -p flux-core --example loadtest -- \
+//!     --threads 8 --duration 10 --sessions shared --corpus messages.ndjson
+//! ```
+//!
+//! Options:
+//!
+//! - `--threads N`     worker threads (default: available parallelism)
+//! - `--duration SECS` how long to run (default: 5)
+//! - `--sessions MODE` `per-thread` (default), `shared` (one session behind a mutex)
+//!   or `sync` (one `SyncFluxSession`)
+//! - `--corpus PATH`   NDJSON file, one JSON message per line (default: built-in sample)
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use flux_core::{FluxSession, SyncFluxSession};
+
+/// Allocator wrapper that counts allocations and tracks live bytes
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+fn record_alloc(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SessionMode {
+    PerThread,
+    Shared,
+    Sync,
+}
+
+struct Args {
+    threads: usize,
+    duration: Duration,
+    sessions: SessionMode,
+    corpus: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        threads: thread::available_parallelism().map_or(4, |n| n.get()),
+        duration: Duration::from_secs(5),
+        sessions: SessionMode::PerThread,
+        corpus: None,
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(flag) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("{} needs a value", flag));
+        match flag.as_str() {
+            "--threads" => {
+                args.threads = value()?.parse().map_err(|e| format!("--threads: {}", e))?;
+            }
+            "--duration" => {
+                let secs: f64 = value()?.parse().map_err(|e| format!("--duration: {}", e))?;
+                args.duration = Duration::from_secs_f64(secs);
+            }
+            "--sessions" => {
+                args.sessions = match value()?.as_str() {
+                    "per-thread" => SessionMode::PerThread,
+                    "shared" => SessionMode::Shared,
+                    "sync" => SessionMode::Sync,
+                    other => return Err(format!("unknown session mode: {}", other)),
+                };
+            }
+            "--corpus" => args.corpus = Some(value()?),
+            other => return Err(format!("unknown option: {}", other)),
+        }
+    }
+
+    args.threads = args.threads.max(1);
+    Ok(args)
+}
+
+/// Load NDJSON messages, or a built-in sample of user records
+fn load_corpus(path: Option<&str>) -> Result<Vec<Vec<u8>>, String> {
+    let corpus: Vec<Vec<u8>> = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path, e))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| line.as_bytes().to_vec())
+            .collect(),
+        None => (0..256)
+            .map(|i| {
+                serde_json::to_vec(&serde_json::json!({
+                    "id": i,
+                    "name": format!("User{}", i),
+                    "email": format!("user{}@example.com", i),
+                    "age": 20 + (i % 50),
+                    "active": i % 2 == 0,
+                    "created": "2024-01-15T10:30:00Z",
+                }))
+                .unwrap()
+            })
+            .collect(),
+    };
+
+    if corpus.is_empty() {
+        return Err("corpus is empty".into());
+    }
+    Ok(corpus)
+}
+
+#[derive(Default)]
+struct Totals {
+    messages: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    errors: AtomicU64,
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("loadtest: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let corpus = match load_corpus(args.corpus.as_deref()) {
+        Ok(corpus) => Arc::new(corpus),
+        Err(e) => {
+            eprintln!("loadtest: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    println!(
+        "threads: {}  duration: {:.1}s  sessions: {:?}  corpus: {} messages",
+        args.threads,
+        args.duration.as_secs_f64(),
+        args.sessions,
+        corpus.len()
+    );
+
+    let totals = Arc::new(Totals::default());
+    let stop = Arc::new(AtomicBool::new(false));
+    let shared = Arc::new(Mutex::new(FluxSession::new()));
+    let sync = Arc::new(SyncFluxSession::new());
+
+    let allocs_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let alloc_bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+
+    let workers: Vec<_> = (0..args.threads)
+        .map(|t| {
+            let corpus = corpus.clone();
+            let totals = totals.clone();
+            let stop = stop.clone();
+            let shared = shared.clone();
+            let sync = sync.clone();
+            let mode = args.sessions;
+
+            thread::spawn(move || {
+                let mut own = FluxSession::new();
+                let mut i = t;
+                while !stop.load(Ordering::Relaxed) {
+                    let msg = &corpus[i % corpus.len()];
+                    i += 1;
+
+                    let result = match mode {
+                        SessionMode::PerThread => own.compress(msg),
+                        SessionMode::Shared => shared.lock().unwrap().compress(msg),
+                        SessionMode::Sync => sync.compress(msg),
+                    };
+                    match result {
+                        Ok(out) => {
+                            totals.messages.fetch_add(1, Ordering::Relaxed);
+                            totals.bytes_in.fetch_add(msg.len() as u64, Ordering::Relaxed);
+                            totals.bytes_out.fetch_add(out.len() as u64, Ordering::Relaxed);
+                        }
+                        Err(_) => {
+                            totals.errors.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    thread::sleep(args.duration);
+    stop.store(true, Ordering::Relaxed);
+    for worker in workers {
+        worker.join().expect("worker panicked");
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let messages = totals.messages.load(Ordering::Relaxed);
+    let bytes_in = totals.bytes_in.load(Ordering::Relaxed);
+    let bytes_out = totals.bytes_out.load(Ordering::Relaxed);
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed) - allocs_before;
+    let alloc_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - alloc_bytes_before;
+    let per_msg = |n: u64| if messages == 0 { 0.0 } else { n as f64 / messages as f64 };
+
+    println!();
+    println!("messages:      {} ({} errors)", messages, totals.errors.load(Ordering::Relaxed));
+    println!("throughput:    {:.0} msg/s, {:.1} MB/s in", messages as f64 / elapsed, bytes_in as f64 / elapsed / 1e6);
+    println!("ratio:         {:.1}%", bytes_out as f64 / bytes_in.max(1) as f64 * 100.0);
+    println!("allocations:   {} ({:.1} per message)", allocs, per_msg(allocs));
+    println!("allocated:     {:.1} MB ({:.0} bytes per message)", alloc_bytes as f64 / 1e6, per_msg(alloc_bytes));
+    println!("heap peak:     {:.2} MB", PEAK_BYTES.load(Ordering::Relaxed) as f64 / 1e6);
+}
@@ -0,0 +1,107 @@
+//! Adaptive stage skipping
+//!
+//! Some traffic defeats a stage every time: entropy coding gains nothing on
+//! payloads that are mostly embedded gzip or JPEG bytes, yet each message
+//! still pays for the attempt. With `FluxConfig::adaptive`, a session keeps
+//! a moving average of the ratio each optional stage achieves. Once the
+//! average shows the stage saving too little, the session stops running
+//! it, except for one probe every `PROBE_INTERVAL` messages; a probe that
+//! saves enough puts the stage back in use.
+//!
+//! Skipping only changes what the sender tries, never the frame format, so
+//! the receiver needs no setting of its own.
+
+/// Messages a stage runs for before its average is trusted
+pub const MIN_SAMPLES: u32 = 8;
+
+/// Output/input ratio a stage must beat, on average, to keep running
+pub const SKIP_RATIO: f64 = 0.97;
+
+/// While a stage is skipped, every this many messages run it as a probe
+pub const PROBE_INTERVAL: u32 = 64;
+
+/// Weight of the newest ratio in the moving average
+const ALPHA: f64 = 0.25;
+
+/// Recent ratios of one stage
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StageTracker {
+    /// Moving average of output/input size
+    ratio: f64,
+    samples: u32,
+    /// Messages skipped since the stage last ran
+    idle: u32,
+}
+
+impl StageTracker {
+    fn skipping(&self) -> bool {
+        self.samples >= MIN_SAMPLES && self.ratio >= SKIP_RATIO
+    }
+
+    /// Whether to run the stage for the next message
+    pub(crate) fn should_run(&mut self) -> bool {
+        if self.skipping() && self.idle + 1 < PROBE_INTERVAL {
+            self.idle += 1;
+            return false;
+        }
+        true
+    }
+
+    /// Record a run of the stage that turned `input` bytes into `output`
+    pub(crate) fn record(&mut self, input: usize, output: usize) {
+        let ratio = if input == 0 { 1.0 } else { output as f64 / input as f64 };
+        if self.skipping() {
+            // A probe: back in use if it paid off
+            if ratio < SKIP_RATIO {
+                *self = Self::default();
+            }
+            self.idle = 0;
+            return;
+        }
+        self.ratio = if self.samples == 0 { ratio } else { ALPHA * ratio + (1.0 - ALPHA) * self.ratio };
+        self.samples += 1;
+        self.idle = 0;
+    }
+}
+
+/// Trackers of the stages a session may skip
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AdaptiveState {
+    pub(crate) lz: StageTracker,
+    pub(crate) entropy: StageTracker,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skip_and_probe() {
+        let mut tracker = StageTracker::default();
+        for _ in 0..MIN_SAMPLES {
+            assert!(tracker.should_run());
+            tracker.record(100, 101);
+        }
+
+        // Skipped until the probe
+        let runs: Vec<bool> = (0..PROBE_INTERVAL).map(|_| tracker.should_run()).collect();
+        assert_eq!(runs.iter().filter(|&&run| run).count(), 1);
+        assert!(runs[PROBE_INTERVAL as usize - 1]);
+
+        // A failed probe keeps it skipped; a good one brings it back
+        tracker.record(100, 100);
+        assert!(!tracker.should_run());
+        (0..PROBE_INTERVAL).for_each(|_| {
+            tracker.should_run();
+        });
+        tracker.record(100, 40);
+        assert!(tracker.should_run());
+
+        // Occasional misses among good runs don't trigger skipping
+        let mut tracker = StageTracker::default();
+        for i in 0..100 {
+            assert!(tracker.should_run());
+            tracker.record(100, if i % 4 == 0 { 105 } else { 60 });
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Auto-selecting compression
+//!
+//! `auto_compress` looks at the input and picks the codec for it, so
+//! callers don't have to guess and call a different API for each answer.
+//! The choice is recorded in an envelope that `auto_decompress` routes on:
+//!
+//! ```text
+//! ┌─────────┬───────┬─────────────────┐
+//! │ "FXA"   │ Codec │ Payload         │
+//! │ 3 bytes │ 1 byte│ codec's output  │
+//! └─────────┴───────┴─────────────────┘
+//! ```
+//!
+//! - Small or non-JSON input goes to fastpack LZ.
+//! - Other JSON goes to FLUX.
+//! - FLUX rebuilds JSON rather than keeping its bytes, so when the rebuild
+//!   differs (pretty-printed input, numbers like `1.50`) the input goes to
+//!   APEX, which keeps every byte.
+//!
+//! Requires the `fastpack` feature.
+
+use fastpack_core::{apex_compress, apex_decompress_with_limit, compress as lz_compress, decompress_with_limit as lz_decompress_with_limit, ApexOptions, Options};
+
+use crate::config::DEFAULT_MAX_DECOMPRESSED_SIZE;
+use crate::{Error, FluxConfig, FluxSession, Result};
+
+/// Envelope magic bytes
+const MAGIC: [u8; 3] = *b"FXA";
+
+/// JSON smaller than this goes to LZ: the FLUX schema and APEX header cost
+/// more than they save
+pub const MIN_STRUCTURED_SIZE: usize = 256;
+
+/// Codec of an envelope's payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Codec {
+    /// fastpack LZ frame
+    Lz = 0,
+    /// fastpack APEX frame
+    Apex = 1,
+    /// FLUX frame
+    Flux = 2,
+}
+
+impl Codec {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Codec::Lz),
+            1 => Some(Codec::Apex),
+            2 => Some(Codec::Flux),
+            _ => None,
+        }
+    }
+
+    /// Name of the codec
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Lz => "lz4",
+            Codec::Apex => "apex",
+            Codec::Flux => "flux",
+        }
+    }
+}
+
+/// Compress `data` with the codec that suits it, inside an envelope
+pub fn auto_compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    if data.len() < MIN_STRUCTURED_SIZE || !starts_like_json(data) {
+        out.push(Codec::Lz as u8);
+        out.extend_from_slice(&lz_compress(data, &Options::default()).map_err(encode_error)?);
+        return Ok(out);
+    }
+
+    let config = FluxConfig { preserve_key_order: true, lossless_numbers: true, ..FluxConfig::default() };
+    if let Ok(frame) = FluxSession::with_config(config).compress(data) {
+        if crate::decompress(&frame).is_ok_and(|rebuilt| rebuilt == data) {
+            out.push(Codec::Flux as u8);
+            out.extend_from_slice(&frame);
+            return Ok(out);
+        }
+    }
+
+    out.push(Codec::Apex as u8);
+    out.extend_from_slice(&apex_compress(data, &ApexOptions::default()).map_err(encode_error)?);
+    Ok(out)
+}
+
+/// Decompress an envelope written by `auto_compress`
+///
+/// Output is bounded by `DEFAULT_MAX_DECOMPRESSED_SIZE` whatever the codec.
+pub fn auto_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let (codec, payload) = read_envelope(data)?;
+    let limit = DEFAULT_MAX_DECOMPRESSED_SIZE;
+    match codec {
+        Codec::Lz => lz_decompress_with_limit(payload, limit).map_err(decode_error),
+        Codec::Apex => apex_decompress_with_limit(payload, limit).map_err(decode_error),
+        Codec::Flux => crate::decompress(payload),
+    }
+}
+
+/// Codec an envelope was compressed with
+pub fn auto_codec(data: &[u8]) -> Result<Codec> {
+    Ok(read_envelope(data)?.0)
+}
+
+fn read_envelope(data: &[u8]) -> Result<(Codec, &[u8])> {
+    match data {
+        [m0, m1, m2, codec, payload @ ..] if [*m0, *m1, *m2] == MAGIC => {
+            let codec = Codec::from_byte(*codec)
+                .ok_or_else(|| Error::InvalidFrame(format!("Unknown auto codec {}", codec)))?;
+            Ok((codec, payload))
+        }
+        _ => Err(Error::InvalidMagic),
+    }
+}
+
+/// Whether `data` starts like a JSON object or array
+fn starts_like_json(data: &[u8]) -> bool {
+    matches!(data.iter().find(|b| !b.is_ascii_whitespace()), Some(b'[' | b'{'))
+}
+
+fn encode_error(e: fastpack_core::Error) -> Error {
+    Error::EncodeError(format!("FastPack: {}", e))
+}
+
+fn decode_error(e: fastpack_core::Error) -> Error {
+    match e {
+        fastpack_core::Error::OutputLimitExceeded(limit) => Error::OutputLimitExceeded { limit },
+        e => Error::DecodeError(format!("FastPack: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(n: usize) -> Vec<u8> {
+        let rows: Vec<String> = (0..n).map(|i| format!(r#"{{"id":{},"name":"user{}","active":true}}"#, i, i)).collect();
+        format!("[{}]", rows.join(",")).into_bytes()
+    }
+
+    #[test]
+    fn test_auto_roundtrip() {
+        let pretty = serde_json::to_vec_pretty(&serde_json::from_slice::<serde_json::Value>(&records(20)).unwrap()).unwrap();
+        let cases: [(Vec<u8>, Codec); 5] = [
+            (b"hello".to_vec(), Codec::Lz),
+            (br#"{"id":1}"#.to_vec(), Codec::Lz),
+            (vec![b'x'; 1000], Codec::Lz),
+            (records(20), Codec::Flux),
+            (pretty, Codec::Apex),
+        ];
+        for (data, codec) in cases {
+            let envelope = auto_compress(&data).unwrap();
+            assert_eq!(&envelope[..3], b"FXA");
+            assert_eq!(auto_codec(&envelope).unwrap(), codec);
+            assert_eq!(auto_decompress(&envelope).unwrap(), data, "{}", codec.as_str());
+        }
+    }
+
+    #[test]
+    fn test_auto_envelope_errors() {
+        assert!(matches!(auto_decompress(b"FLUX"), Err(Error::InvalidMagic)));
+        assert!(matches!(auto_decompress(b"FXA"), Err(Error::InvalidMagic)));
+        assert!(matches!(auto_codec(b"FXA\x09"), Err(Error::InvalidFrame(_))));
+        assert!(matches!(auto_decompress(b"FXA\x00FPCK"), Err(Error::DecodeError(_))));
+    }
+}
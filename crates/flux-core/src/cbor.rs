@@ -0,0 +1,108 @@
+//! CBOR (RFC 8949) input and output
+//!
+//! CBOR is parsed into the same value model as JSON, so schemas are inferred
+//! and cached exactly as for JSON traffic and a frame compressed from CBOR
+//! decompresses to JSON just as well (and vice versa).
+//!
+//! The value model is JSON's: map keys must be text strings and byte strings
+//! are rejected with `Error::ParseError`. Semantic tags are dropped, keeping
+//! the tagged value.
+//!
+//! Requires the `cbor` feature.
+
+use crate::observer::{MessageProbe, Operation};
+use crate::{Error, FluxSession, MessageOptions, Result};
+
+/// Compress CBOR data
+///
+/// One-shot version of `FluxSession::compress_cbor`.
+pub fn compress_cbor(input: &[u8]) -> Result<Vec<u8>> {
+    FluxSession::new().compress_cbor(input)
+}
+
+/// Decompress FLUX data to CBOR
+///
+/// One-shot version of `FluxSession::decompress_cbor`.
+pub fn decompress_cbor(input: &[u8]) -> Result<Vec<u8>> {
+    FluxSession::new().decompress_cbor(input)
+}
+
+impl FluxSession {
+    /// Compress a CBOR-encoded message
+    pub fn compress_cbor(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut probe = MessageProbe::new(Operation::Compress, input.len(), self.probe_clock());
+        self.stats.messages_processed += 1;
+        self.stats.bytes_in += input.len() as u64;
+
+        let value: serde_json::Value = ciborium::from_reader(input)
+            .map_err(|e| Error::ParseError(e.to_string()))?;
+        probe.lap(|t| &mut t.parse);
+
+        let mut output = Vec::with_capacity(input.len() / 2 + 32);
+        self.compress_parsed(&value, input.len(), None, &MessageOptions::default(), &mut output, &mut probe)?;
+        self.notify(probe);
+        Ok(output)
+    }
+
+    /// Decompress a FLUX frame to CBOR
+    pub fn decompress_cbor(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut probe = MessageProbe::new(Operation::Decompress, input.len(), self.probe_clock());
+        let value = self.decompress_value(input, &mut probe, None)?;
+        let mut output = Vec::new();
+        ciborium::into_writer(&value, &mut output).map_err(|e| Error::SerializeError(e.to_string()))?;
+        if output.len() > self.config.max_decompressed_size {
+            return Err(Error::OutputLimitExceeded { limit: self.config.max_decompressed_size });
+        }
+        probe.lap(|t| &mut t.encode);
+        probe.stats.output_bytes = output.len();
+        self.notify(probe);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn to_cbor(value: &serde_json::Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        ciborium::into_writer(value, &mut out).unwrap();
+        out
+    }
+
+    fn from_cbor(bytes: &[u8]) -> serde_json::Value {
+        ciborium::from_reader(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_cbor_roundtrip() {
+        let value = json!({"device": "th-01", "temp": 21.5, "seq": 42, "ok": true, "tags": ["a", "b"], "note": null});
+        let encoded = to_cbor(&value);
+
+        let mut encoder = FluxSession::new();
+        let mut decoder = FluxSession::new();
+        let first = encoder.compress_cbor(&encoded).unwrap();
+        let second = encoder.compress_cbor(&encoded).unwrap();
+        assert!(second.len() < first.len(), "schema is cached across CBOR messages");
+
+        assert_eq!(from_cbor(&decoder.decompress_cbor(&first).unwrap()), value);
+        assert_eq!(from_cbor(&decoder.decompress_cbor(&second).unwrap()), value);
+
+        // Frames are format-agnostic
+        let json: serde_json::Value = serde_json::from_slice(&crate::decompress(&first).unwrap()).unwrap();
+        assert_eq!(json, value);
+        let from_json = crate::compress(&serde_json::to_vec(&value).unwrap()).unwrap();
+        assert_eq!(from_cbor(&decompress_cbor(&from_json).unwrap()), value);
+    }
+
+    #[test]
+    fn test_cbor_rejects_non_json_values() {
+        // {1: 2}: integer map key
+        assert!(matches!(compress_cbor(&[0xA1, 0x01, 0x02]), Err(Error::ParseError(_))));
+        // h'0102': byte string
+        assert!(matches!(compress_cbor(&[0x42, 0x01, 0x02]), Err(Error::ParseError(_))));
+        // Truncated array
+        assert!(matches!(compress_cbor(&[0x82, 0x01]), Err(Error::ParseError(_))));
+    }
+}
@@ -0,0 +1,126 @@
+//! Pluggable time source
+//!
+//! Everything time-based in a session (stage timings, time-based keyframes)
+//! reads a `Clock` instead of `std::time::Instant`, so it can be replaced:
+//!
+//! - `SystemClock`: monotonic std clock, the default on native targets
+//! - `NoClock`: no time source; time-based features switch off. The default
+//!   on `wasm32-unknown-unknown`, where `Instant::now` panics. Bindings
+//!   install a clock backed by `performance.now()` instead.
+//! - `ManualClock`: advanced by hand, for deterministic tests
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Monotonic time source
+pub trait Clock: Send + Sync {
+    /// Time since an arbitrary fixed origin, or `None` if no clock is available
+    fn now(&self) -> Option<Duration>;
+}
+
+/// `std::time::Instant`-backed clock
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    origin: std::time::Instant,
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl SystemClock {
+    pub fn new() -> Self {
+        Self { origin: std::time::Instant::now() }
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for SystemClock {
+    fn now(&self) -> Option<Duration> {
+        Some(self.origin.elapsed())
+    }
+}
+
+/// Absent clock: time never advances and time-based features stay off
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoClock;
+
+impl Clock for NoClock {
+    fn now(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Clock that only moves when told to
+///
+/// ```rust,ignore
+/// let clock = Arc::new(ManualClock::new());
+/// session.set_clock(clock.clone());
+/// clock.advance(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    nanos: AtomicU64,
+}
+
+impl ManualClock {
+    /// Start at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `by`
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Jump to `to` (which may move the clock backwards)
+    pub fn set(&self, to: Duration) {
+        self.nanos.store(to.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Option<Duration> {
+        Some(Duration::from_nanos(self.nanos.load(Ordering::Relaxed)))
+    }
+}
+
+/// The clock new sessions start with
+pub fn default_clock() -> Arc<dyn Clock> {
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    {
+        Arc::new(SystemClock::new())
+    }
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    {
+        Arc::new(NoClock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clocks() {
+        let clock = ManualClock::new();
+        assert_eq!(clock.now(), Some(Duration::ZERO));
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now(), Some(Duration::from_millis(1500)));
+        clock.set(Duration::from_secs(1));
+        assert_eq!(clock.now(), Some(Duration::from_secs(1)));
+
+        assert_eq!(NoClock.now(), None);
+
+        let system = SystemClock::new();
+        let t0 = system.now().unwrap();
+        assert!(system.now().unwrap() >= t0);
+    }
+}
@@ -0,0 +1,141 @@
+//! tokio-util codec for FLUX frames
+//!
+//! `FluxCodec` plugs a FLUX session into `tokio_util::codec::Framed`: the
+//! encoder compresses JSON messages into frames, the decoder buffers
+//! incoming bytes until a whole frame has arrived and decompresses it.
+//!
+//! ```rust,ignore
+//! let mut framed = Framed::new(tcp_stream, FluxCodec::new());
+//! framed.send(br#"{"id": 1}"#.as_slice()).await?;
+//! while let Some(json) = framed.next().await {
+//!     handle(json?);
+//! }
+//! ```
+//!
+//! Each direction has its own session, so schemas are sent once per
+//! connection and direction. Requires the `async` feature.
+
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::frame::{needs_more_bytes, RawFrame};
+use crate::{Error, FluxConfig, FluxSession, Result};
+
+/// FLUX framing for `tokio_util::codec::Framed`
+pub struct FluxCodec {
+    sender: FluxSession,
+    receiver: FluxSession,
+    /// Largest frame the decoder buffers
+    max_frame_size: usize,
+}
+
+impl FluxCodec {
+    /// Create a codec with the default configuration
+    pub fn new() -> Self {
+        Self::with_config(FluxConfig::default())
+    }
+
+    /// Create a codec whose sessions use `config`
+    ///
+    /// Incoming frames are limited to `config.max_decompressed_size` bytes.
+    pub fn with_config(config: FluxConfig) -> Self {
+        Self {
+            max_frame_size: config.max_decompressed_size,
+            sender: FluxSession::with_config(config.clone()),
+            receiver: FluxSession::with_config(config),
+        }
+    }
+
+    /// Session compressing outgoing messages
+    pub fn sender(&self) -> &FluxSession {
+        &self.sender
+    }
+
+    /// Session decompressing incoming frames
+    pub fn receiver(&self) -> &FluxSession {
+        &self.receiver
+    }
+}
+
+impl Default for FluxCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for FluxCodec {
+    type Error = Error;
+
+    fn encode(&mut self, json: T, dst: &mut BytesMut) -> Result<()> {
+        self.sender.compress_to(json.as_ref(), &mut dst.writer())
+    }
+}
+
+impl Decoder for FluxCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        if let Some(more) = needs_more_bytes(src) {
+            let len = src.len() + more;
+            if len > self.max_frame_size {
+                return Err(Error::LengthLimitExceeded { len: len as u64, max: self.max_frame_size });
+            }
+            src.reserve(more);
+            return Ok(None);
+        }
+
+        let (_, len) = RawFrame::parse_prefix(src)?;
+        let frame = src.split_to(len);
+        self.receiver.decompress(&frame).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_roundtrip() {
+        let mut client = FluxCodec::new();
+        let mut server = FluxCodec::new();
+        let messages: [&[u8]; 3] = [br#"{"id":1,"name":"a"}"#, br#"{"id":2,"name":"b"}"#, br#"[1,2,3]"#];
+
+        let mut wire = BytesMut::new();
+        for json in messages {
+            client.encode(json, &mut wire).unwrap();
+        }
+
+        // Bytes arrive one at a time; frames come out whole
+        let mut inbox = BytesMut::new();
+        let mut received = Vec::new();
+        for byte in wire {
+            inbox.put_u8(byte);
+            if let Some(json) = server.decode(&mut inbox).unwrap() {
+                received.push(json);
+            }
+        }
+        assert_eq!(received, messages.map(<[u8]>::to_vec));
+        assert!(inbox.is_empty());
+        assert_eq!(client.sender().stats().messages_processed, 3);
+    }
+
+    #[test]
+    fn test_codec_errors() {
+        let mut codec = FluxCodec::new();
+        let mut src = BytesMut::from(&b"JSON{}"[..]);
+        assert!(matches!(codec.decode(&mut src), Err(Error::InvalidMagic)));
+
+        let config = FluxConfig { max_decompressed_size: 16, ..FluxConfig::default() };
+        let mut frame = BytesMut::new();
+        FluxCodec::new().encode(br#"{"name":"a long enough value"}"#, &mut frame).unwrap();
+        frame.truncate(frame.len() - 1);
+        assert!(matches!(
+            FluxCodec::with_config(config).decode(&mut frame),
+            Err(Error::LengthLimitExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+//! Apache Arrow interop for columnar blocks
+//!
+//! Moves FLUX columnar data into Arrow record batches (and IPC streams) for
+//! DataFusion, Polars and friends, and back. Integers, floats, booleans and
+//! strings map to the matching Arrow types and nested objects to struct
+//! arrays; everything else (arrays, unions) travels as JSON text. Each field
+//! carries its exact FLUX type in the `flux:type` metadata entry, so a
+//! batch exported here converts back to the same schema.
+//!
+//! Requires the `arrow` feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type, UInt64Type,
+    UInt8Type,
+};
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array, Int8Array,
+    RecordBatch, RecordBatchOptions, StringArray, StructArray, UInt64Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Fields, Schema as ArrowSchema};
+use serde_json::Value;
+
+use super::{present_type, ColumnarBlock};
+use crate::cursor::Cursor;
+use crate::schema::{read_type, write_type, FieldDef, Schema, MAX_TYPE_DEPTH};
+use crate::types::{FieldType, FloatType, IntegerType};
+use crate::{Error, Result};
+
+/// Field metadata key holding the hex-encoded FLUX type descriptor
+pub const FLUX_TYPE_KEY: &str = "flux:type";
+
+impl ColumnarBlock {
+    /// Convert to an Arrow record batch with one column per top-level field
+    pub fn to_arrow_record_batch(&self) -> Result<RecordBatch> {
+        let rows = self.rows()?;
+        let mut fields = Vec::new();
+        let mut arrays = Vec::new();
+        for column in self.columns.iter().filter(|c| super::split_column_name(&c.name).len() == 1) {
+            let name = super::split_column_name(&column.name).remove(0);
+            let values: Vec<Option<&Value>> = rows.iter().map(|row| row.get(&name)).collect();
+            let array = build_array(&values, &column.field_type)?;
+            fields.push(arrow_field(name, &column.field_type, &array, array.null_count() > 0));
+            arrays.push(array);
+        }
+
+        let options = RecordBatchOptions::new().with_row_count(Some(self.row_count));
+        RecordBatch::try_new_with_options(Arc::new(ArrowSchema::new(fields)), arrays, &options).map_err(encode_error)
+    }
+
+    /// Arrow schema fitting the record batches of every block of `schema`
+    ///
+    /// Batches from `to_arrow_record_batch` mark a field nullable only when
+    /// the block holds a null; here every field is nullable.
+    pub fn arrow_schema(schema: &Schema) -> Result<ArrowSchema> {
+        let fields = schema
+            .fields
+            .iter()
+            .map(|field| {
+                let array = build_array(&[], &field.field_type)?;
+                Ok(arrow_field(field.name.clone(), &field.field_type, &array, true))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ArrowSchema::new(fields))
+    }
+
+    /// Convert an Arrow record batch, returning the block and its schema
+    ///
+    /// Fields without `flux:type` metadata get the FLUX type closest to
+    /// their Arrow type; unsigned integers are widened to the next signed
+    /// type. Arrow types with no counterpart (lists, dictionaries, ...)
+    /// fail with `Error::UnsupportedType`.
+    pub fn from_arrow(batch: &RecordBatch) -> Result<(Self, Schema)> {
+        let (rows, schema) = arrow_rows(batch)?;
+        Ok((Self::from_array(&rows, &schema)?, schema))
+    }
+
+    /// Write the block as an Arrow IPC stream holding one record batch
+    pub fn to_arrow_ipc(&self) -> Result<Vec<u8>> {
+        let batch = self.to_arrow_record_batch()?;
+        let mut buf = Vec::new();
+        let mut writer = arrow_ipc::writer::StreamWriter::try_new(&mut buf, &batch.schema()).map_err(encode_error)?;
+        writer.write(&batch).map_err(encode_error)?;
+        writer.finish().map_err(encode_error)?;
+        drop(writer);
+        Ok(buf)
+    }
+
+    /// Read an Arrow IPC stream, concatenating its record batches
+    pub fn from_arrow_ipc(bytes: &[u8]) -> Result<(Self, Schema)> {
+        let reader = arrow_ipc::reader::StreamReader::try_new(std::io::Cursor::new(bytes), None).map_err(decode_error)?;
+        let mut rows = Vec::new();
+        let mut schema = None;
+        for batch in reader {
+            let (batch_rows, batch_schema) = arrow_rows(&batch.map_err(decode_error)?)?;
+            rows.extend(batch_rows);
+            schema.get_or_insert(batch_schema);
+        }
+        let schema = schema.ok_or_else(|| Error::DecodeError("Arrow stream holds no record batch".into()))?;
+        Ok((Self::from_array(&rows, &schema)?, schema))
+    }
+}
+
+fn encode_error(e: ArrowError) -> Error {
+    Error::EncodeError(format!("Arrow: {}", e))
+}
+
+fn decode_error(e: ArrowError) -> Error {
+    Error::DecodeError(format!("Arrow: {}", e))
+}
+
+/// Arrow field for `array`, tagged with its FLUX type
+fn arrow_field(name: String, field_type: &FieldType, array: &ArrayRef, nullable: bool) -> Field {
+    let mut descriptor = Vec::new();
+    write_type(field_type, &mut descriptor);
+    let metadata = HashMap::from([(FLUX_TYPE_KEY.to_string(), hex::encode(descriptor))]);
+    Field::new(name, array.data_type().clone(), nullable).with_metadata(metadata)
+}
+
+/// Arrow array holding `values` (missing or null = null) of `field_type`
+fn build_array(values: &[Option<&Value>], field_type: &FieldType) -> Result<ArrayRef> {
+    let values: Vec<Option<&Value>> = values.iter().map(|v| v.filter(|v| !v.is_null())).collect();
+    let array: ArrayRef = match present_type(field_type) {
+        FieldType::Boolean => Arc::new(BooleanArray::from(convert(&values, Value::as_bool)?)),
+        FieldType::Integer(IntegerType::Int8) => Arc::new(Int8Array::from(convert(&values, int)?)),
+        FieldType::Integer(IntegerType::Int16) => Arc::new(Int16Array::from(convert(&values, int)?)),
+        FieldType::Integer(IntegerType::Int32) => Arc::new(Int32Array::from(convert(&values, int)?)),
+        FieldType::Integer(IntegerType::Int64 | IntegerType::Varint) => {
+            Arc::new(Int64Array::from(convert(&values, Value::as_i64)?))
+        }
+        FieldType::Integer(IntegerType::UInt64) => Arc::new(UInt64Array::from(convert(&values, Value::as_u64)?)),
+        FieldType::Float(FloatType::Float32) => {
+            Arc::new(Float32Array::from(convert(&values, |v| v.as_f64().map(|f| f as f32))?))
+        }
+        FieldType::Float(FloatType::Float64) => Arc::new(Float64Array::from(convert(&values, Value::as_f64)?)),
+        FieldType::String | FieldType::Timestamp | FieldType::Uuid | FieldType::Binary | FieldType::Decimal { .. } => {
+            Arc::new(StringArray::from(convert(&values, |v| v.as_str().map(str::to_string))?))
+        }
+        FieldType::Object(members) => {
+            let mut fields = Vec::with_capacity(members.len());
+            let mut children = Vec::with_capacity(members.len());
+            for (name, member_type) in members {
+                let member_values: Vec<Option<&Value>> = values.iter().map(|v| v.and_then(|v| v.get(name))).collect();
+                let child = build_array(&member_values, member_type)?;
+                fields.push(Field::new(name, child.data_type().clone(), true));
+                children.push(child);
+            }
+            let validity = values.iter().map(Option::is_some).collect::<Vec<_>>();
+            Arc::new(
+                StructArray::try_new(Fields::from(fields), children, Some(validity.into())).map_err(encode_error)?,
+            )
+        }
+        // Arrays, unions and anything else travel as JSON text
+        _ => Arc::new(StringArray::from(convert(&values, |v| serde_json::to_string(v).ok())?)),
+    };
+    Ok(array)
+}
+
+/// Map present values with `f`, failing on values it rejects
+fn convert<T>(values: &[Option<&Value>], f: impl Fn(&Value) -> Option<T>) -> Result<Vec<Option<T>>> {
+    values
+        .iter()
+        .map(|v| match v {
+            Some(v) => f(v)
+                .map(Some)
+                .ok_or_else(|| Error::EncodeError(format!("Value {} does not fit its Arrow column", v))),
+            None => Ok(None),
+        })
+        .collect()
+}
+
+fn int<T: TryFrom<i64>>(value: &Value) -> Option<T> {
+    value.as_i64().and_then(|i| T::try_from(i).ok())
+}
+
+/// Rows of a record batch as JSON objects, with the matching FLUX schema
+fn arrow_rows(batch: &RecordBatch) -> Result<(Vec<Value>, Schema)> {
+    let arrow_schema = batch.schema();
+    let mut fields = Vec::with_capacity(arrow_schema.fields().len());
+    for field in arrow_schema.fields() {
+        let field_type = match field.metadata().get(FLUX_TYPE_KEY) {
+            Some(descriptor) => {
+                let bytes = hex::decode(descriptor)
+                    .map_err(|e| Error::DecodeError(format!("Bad {} on '{}': {}", FLUX_TYPE_KEY, field.name(), e)))?;
+                read_type(&mut Cursor::new(&bytes), 0, MAX_TYPE_DEPTH)?
+            }
+            None => flux_type(field.data_type())?,
+        };
+        fields.push(FieldDef {
+            name: field.name().clone(),
+            field_type,
+            nullable: field.is_nullable(),
+        });
+    }
+
+    let mut rows = vec![serde_json::Map::new(); batch.num_rows()];
+    for ((column, field), def) in batch.columns().iter().zip(arrow_schema.fields()).zip(&fields) {
+        for (i, row) in rows.iter_mut().enumerate() {
+            let value = arrow_value(column, i, &def.field_type)?;
+            if !value.is_null() {
+                row.insert(field.name().clone(), value);
+            }
+        }
+    }
+    Ok((rows.into_iter().map(Value::Object).collect(), Schema::new(fields)))
+}
+
+/// FLUX type for an Arrow type
+fn flux_type(data_type: &DataType) -> Result<FieldType> {
+    Ok(match data_type {
+        DataType::Null => FieldType::Null,
+        DataType::Boolean => FieldType::Boolean,
+        DataType::Int8 => FieldType::Integer(IntegerType::Int8),
+        DataType::Int16 | DataType::UInt8 => FieldType::Integer(IntegerType::Int16),
+        DataType::Int32 | DataType::UInt16 => FieldType::Integer(IntegerType::Int32),
+        DataType::Int64 | DataType::UInt32 => FieldType::Integer(IntegerType::Int64),
+        DataType::UInt64 => FieldType::Integer(IntegerType::UInt64),
+        DataType::Float32 => FieldType::Float(FloatType::Float32),
+        DataType::Float64 => FieldType::Float(FloatType::Float64),
+        DataType::Utf8 | DataType::LargeUtf8 => FieldType::String,
+        DataType::Struct(fields) => FieldType::Object(
+            fields
+                .iter()
+                .map(|f| {
+                    let member_type = flux_type(f.data_type())?;
+                    let member_type = if f.is_nullable() && !member_type.is_nullable() && member_type != FieldType::Null {
+                        FieldType::Union(vec![member_type, FieldType::Null])
+                    } else {
+                        member_type
+                    };
+                    Ok((f.name().clone(), member_type))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        other => return Err(Error::UnsupportedType(format!("Arrow type {}", other))),
+    })
+}
+
+/// JSON value of row `i` of `array`
+fn arrow_value(array: &dyn Array, i: usize, field_type: &FieldType) -> Result<Value> {
+    if array.is_null(i) {
+        return Ok(Value::Null);
+    }
+    let number = |f: f64| serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number);
+    Ok(match array.data_type() {
+        DataType::Null => Value::Null,
+        DataType::Boolean => Value::Bool(array.as_boolean().value(i)),
+        DataType::Int8 => array.as_primitive::<Int8Type>().value(i).into(),
+        DataType::Int16 => array.as_primitive::<Int16Type>().value(i).into(),
+        DataType::Int32 => array.as_primitive::<Int32Type>().value(i).into(),
+        DataType::Int64 => array.as_primitive::<Int64Type>().value(i).into(),
+        DataType::UInt8 => array.as_primitive::<UInt8Type>().value(i).into(),
+        DataType::UInt16 => array.as_primitive::<UInt16Type>().value(i).into(),
+        DataType::UInt32 => array.as_primitive::<UInt32Type>().value(i).into(),
+        DataType::UInt64 => array.as_primitive::<UInt64Type>().value(i).into(),
+        DataType::Float32 => number(array.as_primitive::<Float32Type>().value(i) as f64),
+        DataType::Float64 => number(array.as_primitive::<Float64Type>().value(i)),
+        DataType::Utf8 | DataType::LargeUtf8 => {
+            let text = match array.data_type() {
+                DataType::Utf8 => array.as_string::<i32>().value(i),
+                _ => array.as_string::<i64>().value(i),
+            };
+            match present_type(field_type) {
+                FieldType::String
+                | FieldType::Timestamp
+                | FieldType::Uuid
+                | FieldType::Binary
+                | FieldType::Decimal { .. } => Value::String(text.to_string()),
+                _ => serde_json::from_str(text).map_err(|e| Error::DecodeError(format!("Arrow JSON column: {}", e)))?,
+            }
+        }
+        DataType::Struct(fields) => {
+            let array = array.as_struct();
+            let members = match present_type(field_type) {
+                FieldType::Object(members) => members.as_slice(),
+                _ => &[],
+            };
+            let mut obj = serde_json::Map::new();
+            for (field, child) in fields.iter().zip(array.columns()) {
+                let member_type = members
+                    .iter()
+                    .find(|(name, _)| name == field.name())
+                    .map_or(FieldType::Null, |(_, t)| t.clone());
+                let value = arrow_value(child, i, &member_type)?;
+                if !value.is_null() {
+                    obj.insert(field.name().clone(), value);
+                }
+            }
+            Value::Object(obj)
+        }
+        other => return Err(Error::UnsupportedType(format!("Arrow type {}", other))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SchemaInferrer;
+
+    fn sample() -> (Vec<Value>, Schema) {
+        let values: Vec<Value> = (0..40)
+            .map(|i| {
+                let mut row = serde_json::json!({
+                    "id": 1000 + i,
+                    "temp": 20.5 + i as f64 / 4.0,
+                    "name": format!("sensor-{}", i % 4),
+                    "ok": i % 5 != 0,
+                    "tags": ["a", "b"],
+                    "site": {"city": "Oslo", "floor": i % 3}
+                });
+                if i % 7 == 0 {
+                    row.as_object_mut().unwrap().remove("site");
+                }
+                row
+            })
+            .collect();
+        let mut inferrer = SchemaInferrer::new();
+        inferrer.add_value(&Value::Array(values.clone())).unwrap();
+        (values, inferrer.infer().unwrap())
+    }
+
+    #[test]
+    fn test_arrow_roundtrip() {
+        let (values, schema) = sample();
+        let block = ColumnarBlock::from_array(&values, &schema).unwrap();
+        let batch = block.to_arrow_record_batch().unwrap();
+        assert_eq!(batch.num_rows(), 40);
+        assert_eq!(batch.num_columns(), 6);
+        assert_eq!(batch.column_by_name("id").unwrap().data_type(), &DataType::Int16);
+        assert_eq!(batch.column_by_name("tags").unwrap().data_type(), &DataType::Utf8);
+        let site = batch.column_by_name("site").unwrap();
+        assert!(matches!(site.data_type(), DataType::Struct(_)));
+        assert_eq!(site.null_count(), 6);
+        let arrow_schema = ColumnarBlock::arrow_schema(&schema).unwrap();
+        for (field, batch_field) in arrow_schema.fields().iter().zip(batch.schema().fields()) {
+            assert_eq!(field.data_type(), batch_field.data_type());
+            assert!(field.is_nullable());
+        }
+
+        let (decoded, decoded_schema) = ColumnarBlock::from_arrow(&batch).unwrap();
+        assert_eq!(decoded_schema.fields, schema.fields);
+        assert_eq!(decoded.to_array(&decoded_schema).unwrap(), values);
+
+        let ipc = block.to_arrow_ipc().unwrap();
+        let (decoded, decoded_schema) = ColumnarBlock::from_arrow_ipc(&ipc).unwrap();
+        assert_eq!(decoded.to_array(&decoded_schema).unwrap(), values);
+    }
+
+    #[test]
+    fn test_from_plain_arrow() {
+        let fields = vec![
+            Field::new("n", DataType::UInt32, false),
+            Field::new("label", DataType::Utf8, true),
+        ];
+        let batch = RecordBatch::try_new(
+            Arc::new(ArrowSchema::new(fields)),
+            vec![
+                Arc::new(arrow_array::UInt32Array::from(vec![1, 2, 3])),
+                Arc::new(StringArray::from(vec![Some("x"), None, Some("z")])),
+            ],
+        )
+        .unwrap();
+        let (block, schema) = ColumnarBlock::from_arrow(&batch).unwrap();
+        assert_eq!(schema.fields[1].field_type, FieldType::Integer(IntegerType::Int64));
+        assert_eq!(
+            block.to_array(&schema).unwrap(),
+            [serde_json::json!({"label": "x", "n": 1}), serde_json::json!({"n": 2}), serde_json::json!({"label": "z", "n": 3})]
+        );
+
+        let list = Field::new("l", DataType::List(Arc::new(Field::new("item", DataType::Int32, true))), true);
+        let batch = RecordBatch::new_empty(Arc::new(ArrowSchema::new(vec![list])));
+        assert!(matches!(ColumnarBlock::from_arrow(&batch), Err(Error::UnsupportedType(_))));
+    }
+}
@@ -4,13 +4,29 @@
 //! Key benefits:
 //! - Similar values grouped together (better for compression)
 //! - Type-specific encodings applied per column
-//! - Null bitmaps for sparse data
+//! - Null bitmaps for sparse data, run-length coded or replaced by a list
+//!   of null rows when that is smaller
 //! - Run-length encoding for repeated values
+//! - XOR (Gorilla) compression for float columns
+//! - Nested objects flattened into one column per member
+//!
+//! A member `city` of an object `address` inside `user` becomes the column
+//! `user.address.city`; dots and backslashes inside member names are
+//! escaped with a backslash. Each object gets a column of its own too,
+//! holding no data, whose null bitmap records where the object is present.
 
 use crate::{Error, Result};
+use crate::cursor::Cursor;
 use crate::schema::Schema;
 use crate::types::FieldType;
-use crate::encoding::{encode_varint, decode_varint, zigzag_encode, zigzag_decode};
+use crate::encoding::{encode_varint, zigzag_encode, zigzag_decode};
+use crate::encoding::float;
+
+#[cfg(feature = "arrow")]
+mod arrow;
+
+/// Most rows `ColumnarBlock::deserialize` accepts
+pub const MAX_ROWS: usize = 1 << 20;
 
 /// Columnar block representation
 pub struct ColumnarBlock {
@@ -20,13 +36,53 @@ pub struct ColumnarBlock {
 
 /// Single column of data
 pub struct Column {
+    /// Dotted path of the member, e.g. `user.address.city`
     pub name: String,
     pub field_type: FieldType,
     pub encoding: ColumnEncoding,
+    /// Rows holding a value (`None` when every row does)
     pub null_bitmap: Option<bitvec::vec::BitVec>,
+    /// How `null_bitmap` is serialized
+    pub null_encoding: NullEncoding,
+    /// Values of the rows present in `null_bitmap`, in row order
     pub data: Vec<u8>,
 }
 
+/// Serialized form of a column's null bitmap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NullEncoding {
+    /// One bit per row
+    #[default]
+    Bitmap,
+    /// Alternating run lengths of present and null rows
+    Runs,
+    /// Numbers of the null rows; no bitmap is stored
+    Positions,
+}
+
+/// Options for `ColumnarBlock::from_array_with`
+#[derive(Debug, Clone)]
+pub struct ColumnarConfig {
+    /// Columns with at least this fraction of rows present list their null
+    /// rows (`NullEncoding::Positions`) instead of storing a bitmap
+    ///
+    /// Below it, the smaller of a plain and a run-length coded bitmap is
+    /// used.
+    pub sparse_null_density: f64,
+    /// Compress float columns whose values are all exact as `f32` as 32-bit
+    /// words (`ColumnEncoding::Xor32`)
+    pub float32_downcast: bool,
+}
+
+impl Default for ColumnarConfig {
+    fn default() -> Self {
+        Self {
+            sparse_null_density: 0.9,
+            float32_downcast: true,
+        }
+    }
+}
+
 /// Column encoding type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ColumnEncoding {
@@ -42,6 +98,10 @@ pub enum ColumnEncoding {
     RunLength,
     /// Bit-packed integers (N bits per value)
     BitPacked(u8),
+    /// XOR-compressed 64-bit floats
+    Xor,
+    /// XOR-compressed floats that are all exact as 32-bit floats
+    Xor32,
 }
 
 impl ColumnarBlock {
@@ -55,46 +115,62 @@ impl ColumnarBlock {
 
     /// Convert array of objects to columnar format
     pub fn from_array(values: &[serde_json::Value], schema: &Schema) -> Result<Self> {
+        Self::from_array_with(values, schema, &ColumnarConfig::default())
+    }
+
+    /// Convert array of objects to columnar format with custom options
+    pub fn from_array_with(values: &[serde_json::Value], schema: &Schema, config: &ColumnarConfig) -> Result<Self> {
         if values.is_empty() {
             return Ok(Self::new());
         }
 
         let row_count = values.len();
-        let mut columns = Vec::with_capacity(schema.fields.len());
-
+        let mut leaves = Vec::with_capacity(schema.fields.len());
         for field in &schema.fields {
+            flatten(&mut vec![field.name.as_str()], &field.field_type, &mut leaves);
+        }
+        let mut columns = Vec::with_capacity(leaves.len());
+
+        for (path, field_type) in leaves {
+            let is_struct = matches!(present_type(field_type), FieldType::Object(_));
             let mut column_values = Vec::with_capacity(row_count);
             let mut null_bits = bitvec::vec::BitVec::with_capacity(row_count);
 
             for value in values {
-                if let serde_json::Value::Object(obj) = value {
-                    match obj.get(&field.name) {
-                        Some(v) if !v.is_null() => {
+                match path.iter().try_fold(value, |value, key| value.get(key)) {
+                    Some(v) if is_struct && !v.is_object() && !v.is_null() => {
+                        return Err(Error::EncodeError(format!("Member '{}' is not an object", path.join("."))));
+                    }
+                    Some(v) if !v.is_null() => {
+                        if !is_struct {
                             column_values.push(v.clone());
-                            null_bits.push(true);
-                        }
-                        _ => {
-                            column_values.push(serde_json::Value::Null);
-                            null_bits.push(false);
                         }
+                        null_bits.push(true);
                     }
+                    _ => null_bits.push(false),
                 }
             }
 
-            // Select optimal encoding and encode column
-            let (data, encoding) = encode_column_optimized(&column_values, &field.field_type)?;
+            // Select optimal encoding and encode column; objects only have a bitmap
+            let (data, encoding) = if is_struct {
+                (Vec::new(), ColumnEncoding::Raw)
+            } else {
+                encode_column_optimized(&column_values, field_type, config)?
+            };
 
-            let null_bitmap = if null_bits.iter().any(|b| !*b) {
-                Some(null_bits)
+            let (null_bitmap, null_encoding) = if null_bits.all() {
+                (None, NullEncoding::default())
             } else {
-                None
+                let encoding = choose_null_encoding(&null_bits, config);
+                (Some(null_bits), encoding)
             };
 
             columns.push(Column {
-                name: field.name.clone(),
-                field_type: field.field_type.clone(),
+                name: column_name(&path),
+                field_type: field_type.clone(),
                 encoding,
                 null_bitmap,
+                null_encoding,
                 data,
             });
         }
@@ -103,19 +179,38 @@ impl ColumnarBlock {
     }
 
     /// Convert back to array of objects
-    pub fn to_array(&self, schema: &Schema) -> Result<Vec<serde_json::Value>> {
-        // First decode all columns
-        let decoded_columns: Vec<Vec<serde_json::Value>> = self.columns
-            .iter()
-            .map(|col| decode_column(&col.data, col.encoding, &col.field_type, self.row_count))
-            .collect::<Result<Vec<_>>>()?;
+    pub fn to_array(&self, _schema: &Schema) -> Result<Vec<serde_json::Value>> {
+        self.rows()
+    }
+
+    /// Reassemble the rows; column types come from the columns themselves
+    fn rows(&self) -> Result<Vec<serde_json::Value>> {
+        // First decode all columns (present values only)
+        let mut decoded_columns = Vec::with_capacity(self.columns.len());
+        let mut paths = Vec::with_capacity(self.columns.len());
+        for column in &self.columns {
+            let bitmap_len = column.null_bitmap.as_ref().map_or(self.row_count, |b| b.len());
+            let present = column.null_bitmap.as_ref().map_or(self.row_count, |b| b.count_ones());
+            let values = match present_type(&column.field_type) {
+                FieldType::Object(_) => vec![serde_json::Value::Object(serde_json::Map::new()); present],
+                _ => decode_column(&column.data, column.encoding, &column.field_type, present)?,
+            };
+            if values.len() < present || bitmap_len < self.row_count {
+                return Err(Error::DecodeError(format!(
+                    "Column '{}' shorter than {} rows",
+                    column.name, self.row_count
+                )));
+            }
+            decoded_columns.push(values.into_iter());
+            paths.push(split_column_name(&column.name));
+        }
 
         let mut rows = Vec::with_capacity(self.row_count);
 
         for i in 0..self.row_count {
             let mut obj = serde_json::Map::new();
 
-            for (col_idx, column) in self.columns.iter().enumerate() {
+            for ((values, column), path) in decoded_columns.iter_mut().zip(&self.columns).zip(&paths) {
                 // Check null bitmap
                 if let Some(ref bitmap) = column.null_bitmap {
                     if !bitmap[i] {
@@ -123,9 +218,9 @@ impl ColumnarBlock {
                     }
                 }
 
-                let field = &schema.fields[col_idx];
-                let value = decoded_columns[col_idx][i].clone();
-                obj.insert(field.name.clone(), value);
+                if let Some(value) = values.next() {
+                    insert_at(&mut obj, path, value)?;
+                }
             }
 
             rows.push(serde_json::Value::Object(obj));
@@ -158,27 +253,14 @@ impl ColumnarBlock {
                 ColumnEncoding::Dictionary => 0x03,
                 ColumnEncoding::RunLength => 0x04,
                 ColumnEncoding::BitPacked(bits) => 0x10 | (bits & 0x0F),
+                ColumnEncoding::Xor => 0x05,
+                ColumnEncoding::Xor32 => 0x06,
             });
 
-            // Null bitmap presence
-            if let Some(ref bitmap) = col.null_bitmap {
-                buf.push(0x01);
-                // Encode bitmap as bytes
-                let bitmap_bytes: Vec<u8> = bitmap.chunks(8)
-                    .map(|chunk| {
-                        let mut byte = 0u8;
-                        for (i, bit) in chunk.iter().enumerate() {
-                            if *bit {
-                                byte |= 1 << i;
-                            }
-                        }
-                        byte
-                    })
-                    .collect();
-                encode_varint(bitmap_bytes.len() as u64, &mut buf);
-                buf.extend_from_slice(&bitmap_bytes);
-            } else {
-                buf.push(0x00);
+            // Null bitmap
+            match col.null_bitmap {
+                Some(ref bitmap) => write_nulls(bitmap, col.null_encoding, &mut buf),
+                None => buf.push(0x00),
             }
 
             // Data length + data
@@ -189,6 +271,63 @@ impl ColumnarBlock {
         buf
     }
 
+    /// Deserialize a block written by `serialize`
+    ///
+    /// Column types are not stored in the block; they are taken from the
+    /// `schema` field of the same name.
+    pub fn deserialize(buf: &[u8], schema: &Schema) -> Result<Self> {
+        let mut cursor = Cursor::new(buf);
+
+        let row_count = cursor.read_varint("columnar row count")?;
+        if row_count > MAX_ROWS as u64 {
+            return Err(Error::InvalidFrame(format!(
+                "Columnar block has {} rows, limit is {}",
+                row_count, MAX_ROWS
+            )));
+        }
+        let row_count = row_count as usize;
+
+        let column_count = cursor.read_len("columnar column count")?;
+        let mut columns = Vec::with_capacity(column_count);
+
+        for _ in 0..column_count {
+            let name = cursor.read_str("column name")?.to_string();
+            let field_type = resolve_column(schema, &name)
+                .cloned()
+                .ok_or_else(|| Error::DecodeError(format!("Column '{}' not in schema", name)))?;
+
+            let encoding = match cursor.read_u8("column encoding")? {
+                0x00 => ColumnEncoding::Raw,
+                0x01 => ColumnEncoding::Varint,
+                0x02 => ColumnEncoding::Delta,
+                0x03 => ColumnEncoding::Dictionary,
+                0x04 => ColumnEncoding::RunLength,
+                0x05 => ColumnEncoding::Xor,
+                0x06 => ColumnEncoding::Xor32,
+                tag @ 0x10..=0x1F => ColumnEncoding::BitPacked(tag & 0x0F),
+                tag => return Err(Error::DecodeError(format!("Unknown column encoding: {:#04x}", tag))),
+            };
+
+            let (null_bitmap, null_encoding) = match read_nulls(&mut cursor, row_count)? {
+                Some((bitmap, encoding)) => (Some(bitmap), encoding),
+                None => (None, NullEncoding::default()),
+            };
+
+            let data = cursor.read_len_bytes("column data")?.to_vec();
+
+            columns.push(Column {
+                name,
+                field_type,
+                encoding,
+                null_bitmap,
+                null_encoding,
+                data,
+            });
+        }
+
+        Ok(Self { row_count, columns })
+    }
+
     /// Get total encoded size
     pub fn encoded_size(&self) -> usize {
         self.columns.iter().map(|c| c.data.len()).sum()
@@ -201,11 +340,239 @@ impl Default for ColumnarBlock {
     }
 }
 
+/// Append `(path, type)` for a field and, if it is an object, all its members
+fn flatten<'a>(path: &mut Vec<&'a str>, field_type: &'a FieldType, out: &mut Vec<(Vec<&'a str>, &'a FieldType)>) {
+    out.push((path.clone(), field_type));
+    if let FieldType::Object(members) = present_type(field_type) {
+        for (name, member_type) in members {
+            path.push(name);
+            flatten(path, member_type, out);
+            path.pop();
+        }
+    }
+}
+
+/// Type of the values a column stores: nulls live in the bitmap, so a
+/// nullable member is stored as its non-null type
+fn present_type(field_type: &FieldType) -> &FieldType {
+    match field_type {
+        FieldType::Union(types) => {
+            let mut present = types.iter().filter(|t| **t != FieldType::Null);
+            match (present.next(), present.next()) {
+                (Some(only), None) => only,
+                _ => field_type,
+            }
+        }
+        _ => field_type,
+    }
+}
+
+/// Column name for a member path: keys joined by dots, with `.` and `\` escaped
+fn column_name(path: &[&str]) -> String {
+    let mut name = String::new();
+    for (i, key) in path.iter().enumerate() {
+        if i > 0 {
+            name.push('.');
+        }
+        for c in key.chars() {
+            if c == '.' || c == '\\' {
+                name.push('\\');
+            }
+            name.push(c);
+        }
+    }
+    name
+}
+
+/// Inverse of `column_name`
+fn split_column_name(name: &str) -> Vec<String> {
+    let mut path = vec![String::new()];
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => path.last_mut().unwrap().extend(chars.next()),
+            '.' => path.push(String::new()),
+            c => path.last_mut().unwrap().push(c),
+        }
+    }
+    path
+}
+
+/// Type of the schema member a column name refers to
+fn resolve_column<'a>(schema: &'a Schema, name: &str) -> Option<&'a FieldType> {
+    let path = split_column_name(name);
+    let (first, rest) = path.split_first()?;
+    let mut field_type = &schema.fields.iter().find(|f| &f.name == first)?.field_type;
+    for key in rest {
+        field_type = match field_type {
+            FieldType::Object(members) => &members.iter().find(|(name, _)| name == key)?.1,
+            _ => return None,
+        };
+    }
+    Some(field_type)
+}
+
+/// Insert `value` at `path` in `obj`, creating missing parent objects
+fn insert_at(obj: &mut serde_json::Map<String, serde_json::Value>, path: &[String], value: serde_json::Value) -> Result<()> {
+    let (last, parents) = path.split_last().expect("column paths are never empty");
+    let mut target = obj;
+    for key in parents {
+        let parent = target
+            .entry(key.clone())
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        target = parent
+            .as_object_mut()
+            .ok_or_else(|| Error::DecodeError(format!("Column parent '{}' is not an object", key)))?;
+    }
+    target.insert(last.clone(), value);
+    Ok(())
+}
+
+/// Null bitmap flag bytes
+const NULLS_NONE: u8 = 0x00;
+const NULLS_BITMAP: u8 = 0x01;
+const NULLS_RUNS: u8 = 0x02;
+const NULLS_POSITIONS: u8 = 0x03;
+
+/// Pick the smallest serialized form for a bitmap with some nulls
+fn choose_null_encoding(bitmap: &bitvec::vec::BitVec, config: &ColumnarConfig) -> NullEncoding {
+    let present = bitmap.count_ones();
+    if present as f64 >= config.sparse_null_density * bitmap.len() as f64 {
+        return NullEncoding::Positions;
+    }
+    let runs = null_runs(bitmap);
+    let runs_size = varint_size(runs.len() as u64) + runs.iter().map(|&r| varint_size(r as u64)).sum::<usize>();
+    let bitmap_size = varint_size(bitmap.len().div_ceil(8) as u64) + bitmap.len().div_ceil(8);
+    if runs_size < bitmap_size {
+        NullEncoding::Runs
+    } else {
+        NullEncoding::Bitmap
+    }
+}
+
+/// Alternating lengths of present and null runs, starting with present
+fn null_runs(bitmap: &bitvec::vec::BitVec) -> Vec<usize> {
+    let mut runs = Vec::new();
+    let mut current = true;
+    let mut len = 0;
+    for bit in bitmap.iter().by_vals() {
+        if bit != current {
+            runs.push(len);
+            current = bit;
+            len = 0;
+        }
+        len += 1;
+    }
+    runs.push(len);
+    runs
+}
+
+/// Write the flag byte and body of a null bitmap
+fn write_nulls(bitmap: &bitvec::vec::BitVec, encoding: NullEncoding, buf: &mut Vec<u8>) {
+    match encoding {
+        NullEncoding::Bitmap => {
+            buf.push(NULLS_BITMAP);
+            let bitmap_bytes: Vec<u8> = bitmap
+                .chunks(8)
+                .map(|chunk| {
+                    let mut byte = 0u8;
+                    for (i, bit) in chunk.iter().enumerate() {
+                        if *bit {
+                            byte |= 1 << i;
+                        }
+                    }
+                    byte
+                })
+                .collect();
+            encode_varint(bitmap_bytes.len() as u64, buf);
+            buf.extend_from_slice(&bitmap_bytes);
+        }
+        NullEncoding::Runs => {
+            buf.push(NULLS_RUNS);
+            let runs = null_runs(bitmap);
+            encode_varint(runs.len() as u64, buf);
+            for run in runs {
+                encode_varint(run as u64, buf);
+            }
+        }
+        NullEncoding::Positions => {
+            // Gaps between successive null rows
+            buf.push(NULLS_POSITIONS);
+            encode_varint(bitmap.count_zeros() as u64, buf);
+            let mut prev = 0;
+            for row in bitmap.iter_zeros() {
+                encode_varint((row - prev) as u64, buf);
+                prev = row;
+            }
+        }
+    }
+}
+
+/// Read a null bitmap written by `write_nulls` (`None` when every row is present)
+fn read_nulls(cursor: &mut Cursor<'_>, row_count: usize) -> Result<Option<(bitvec::vec::BitVec, NullEncoding)>> {
+    let encoding = match cursor.read_u8("null bitmap flag")? {
+        NULLS_NONE => return Ok(None),
+        NULLS_BITMAP => NullEncoding::Bitmap,
+        NULLS_RUNS => NullEncoding::Runs,
+        NULLS_POSITIONS => NullEncoding::Positions,
+        tag => return Err(Error::DecodeError(format!("Unknown null bitmap encoding: {:#04x}", tag))),
+    };
+    let bitmap = match encoding {
+        NullEncoding::Bitmap => {
+            let bytes = cursor.read_len_bytes("null bitmap")?;
+            if bytes.len() < row_count.div_ceil(8) {
+                return Err(Error::Truncated {
+                    what: "null bitmap",
+                    needed: row_count.div_ceil(8),
+                    available: bytes.len(),
+                });
+            }
+            (0..row_count).map(|i| (bytes[i / 8] >> (i % 8)) & 1 == 1).collect()
+        }
+        NullEncoding::Runs => {
+            let run_count = cursor.read_len("null run count")?;
+            let mut bitmap = bitvec::vec::BitVec::with_capacity(row_count);
+            for i in 0..run_count {
+                let run = cursor.read_varint("null run length")?;
+                if run > (row_count - bitmap.len()) as u64 {
+                    return Err(Error::DecodeError("Null runs exceed the row count".into()));
+                }
+                bitmap.resize(bitmap.len() + run as usize, i % 2 == 0);
+            }
+            if bitmap.len() != row_count {
+                return Err(Error::DecodeError("Null runs don't cover every row".into()));
+            }
+            bitmap
+        }
+        NullEncoding::Positions => {
+            let null_count = cursor.read_len("null row count")?;
+            let mut bitmap = bitvec::bitvec![1; row_count];
+            let mut row = 0u64;
+            for i in 0..null_count {
+                let gap = cursor.read_varint("null row")?;
+                if i > 0 && gap == 0 {
+                    return Err(Error::DecodeError("Null rows out of order".into()));
+                }
+                row = row.saturating_add(gap);
+                if row >= row_count as u64 {
+                    return Err(Error::DecodeError(format!("Null row {} past {} rows", row, row_count)));
+                }
+                bitmap.set(row as usize, false);
+            }
+            bitmap
+        }
+    };
+    Ok(Some((bitmap, encoding)))
+}
+
 /// Select optimal encoding and encode column
 fn encode_column_optimized(
     values: &[serde_json::Value],
     field_type: &FieldType,
+    config: &ColumnarConfig,
 ) -> Result<(Vec<u8>, ColumnEncoding)> {
+    let field_type = present_type(field_type);
+
     // For integer columns, analyze and pick best encoding
     if let FieldType::Integer(_) = field_type {
         let integers: Vec<i64> = values
@@ -218,6 +585,25 @@ fn encode_column_optimized(
         }
     }
 
+    // For floats, XOR-compress unless it comes out larger than raw
+    if let FieldType::Float(_) = field_type {
+        let floats: Vec<f64> = values.iter().filter_map(|v| v.as_f64()).collect();
+        if !floats.is_empty() && floats.len() == values.len() {
+            let mut buf = Vec::new();
+            encode_varint(floats.len() as u64, &mut buf);
+            let encoding = if config.float32_downcast && floats.iter().all(|&f| float::fits_f32(f)) {
+                float::gorilla_encode_f32(&floats, &mut buf);
+                ColumnEncoding::Xor32
+            } else {
+                float::gorilla_encode(&floats, &mut buf);
+                ColumnEncoding::Xor
+            };
+            if buf.len() < varint_size(floats.len() as u64) + floats.len() * 8 {
+                return Ok((buf, encoding));
+            }
+        }
+    }
+
     // For strings, check if dictionary encoding helps
     if matches!(field_type, FieldType::String) {
         let strings: Vec<&str> = values
@@ -353,9 +739,6 @@ fn encode_column_raw(
 
     for value in values {
         match (value, field_type) {
-            (serde_json::Value::Null, _) => {
-                // Already handled by null bitmap
-            }
             (serde_json::Value::Bool(b), FieldType::Boolean) => {
                 buf.push(if *b { 1 } else { 0 });
             }
@@ -405,155 +788,104 @@ fn decode_column(
         return Ok(vec![serde_json::Value::Null; expected_count]);
     }
 
-    let mut pos = 0;
+    let mut cursor = Cursor::new(data);
+    let field_type = present_type(field_type);
 
     match encoding {
         ColumnEncoding::Varint => {
-            let (count, len) = decode_varint(data)?;
-            pos += len;
-
-            let mut values = Vec::with_capacity(count as usize);
+            let count = cursor.read_len("varint column count")?;
+            let mut values = Vec::with_capacity(count);
             for _ in 0..count {
-                let (encoded, len) = decode_varint(&data[pos..])?;
-                pos += len;
-                let i = zigzag_decode(encoded);
+                let i = zigzag_decode(cursor.read_varint("varint column value")?);
                 values.push(serde_json::Value::Number(i.into()));
             }
             Ok(values)
         }
 
         ColumnEncoding::Delta => {
-            let (count, len) = decode_varint(data)?;
-            pos += len;
-
-            if count == 0 {
-                return Ok(Vec::new());
-            }
-
-            let mut values = Vec::with_capacity(count as usize);
-
-            // First value
-            let (encoded, len) = decode_varint(&data[pos..])?;
-            pos += len;
-            let mut prev = zigzag_decode(encoded);
-            values.push(serde_json::Value::Number(prev.into()));
-
-            // Deltas
-            for _ in 1..count {
-                let (encoded, len) = decode_varint(&data[pos..])?;
-                pos += len;
-                let delta = zigzag_decode(encoded);
-                prev += delta;
+            let count = cursor.read_len("delta column count")?;
+            let mut values = Vec::with_capacity(count);
+            let mut prev = 0i64;
+            for _ in 0..count {
+                // The first entry is the base value, the rest are deltas
+                prev = prev.wrapping_add(zigzag_decode(cursor.read_varint("delta column value")?));
                 values.push(serde_json::Value::Number(prev.into()));
             }
             Ok(values)
         }
 
         ColumnEncoding::BitPacked(bits) => {
-            let (count, len) = decode_varint(data)?;
-            pos += len;
-
-            let (min_encoded, len) = decode_varint(&data[pos..])?;
-            pos += len;
-            let min = zigzag_decode(min_encoded);
-
-            let _bits_stored = data[pos];
-            pos += 1;
+            let count = cursor.read_varint("bit-packed column count")?;
+            let min = zigzag_decode(cursor.read_varint("bit-packed column minimum")?);
+            let _bits_stored = cursor.read_u8("bit-packed column width")?;
+
+            let packed = cursor.rest();
+            if count.saturating_mul(bits as u64) > packed.len() as u64 * 8 {
+                return Err(Error::Truncated {
+                    what: "bit-packed column",
+                    needed: (count.saturating_mul(bits as u64)).div_ceil(8) as usize,
+                    available: packed.len(),
+                });
+            }
 
             let mut values = Vec::with_capacity(count as usize);
-            let mut bit_pos = 0u32;
-
+            let mut bit_pos = 0usize;
             for _ in 0..count {
                 let mut offset = 0u64;
                 for bit in 0..bits {
-                    let byte_idx = (bit_pos / 8) as usize;
-                    let bit_idx = bit_pos % 8;
-                    if byte_idx < data.len() - pos
-                        && (data[pos + byte_idx] >> bit_idx) & 1 == 1 {
-                            offset |= 1 << bit;
-                        }
+                    if (packed[bit_pos / 8] >> (bit_pos % 8)) & 1 == 1 {
+                        offset |= 1 << bit;
+                    }
                     bit_pos += 1;
                 }
-                values.push(serde_json::Value::Number((min + offset as i64).into()));
+                values.push(serde_json::Value::Number(min.wrapping_add(offset as i64).into()));
             }
             Ok(values)
         }
 
         ColumnEncoding::Dictionary => {
-            // Read dictionary
-            let (dict_len, len) = decode_varint(data)?;
-            pos += len;
-
-            let mut dict = Vec::with_capacity(dict_len as usize);
+            let dict_len = cursor.read_len("dictionary size")?;
+            let mut dict = Vec::with_capacity(dict_len);
             for _ in 0..dict_len {
-                let (str_len, len) = decode_varint(&data[pos..])?;
-                pos += len;
-
-                let s = std::str::from_utf8(&data[pos..pos + str_len as usize])
-                    .map_err(|e| Error::DecodeError(e.to_string()))?;
-                dict.push(s.to_string());
-                pos += str_len as usize;
+                dict.push(cursor.read_str("dictionary entry")?);
             }
 
-            // Read indices
-            let (count, len) = decode_varint(&data[pos..])?;
-            pos += len;
-
-            let mut values = Vec::with_capacity(count as usize);
+            let count = cursor.read_len("dictionary column count")?;
+            let mut values = Vec::with_capacity(count);
             for _ in 0..count {
-                let (idx, len) = decode_varint(&data[pos..])?;
-                pos += len;
-                values.push(serde_json::Value::String(dict[idx as usize].clone()));
+                let idx = cursor.read_varint("dictionary index")?;
+                let s = usize::try_from(idx)
+                    .ok()
+                    .and_then(|idx| dict.get(idx))
+                    .ok_or_else(|| Error::DecodeError(format!("Dictionary index {} out of range", idx)))?;
+                values.push(serde_json::Value::String(s.to_string()));
             }
             Ok(values)
         }
 
         ColumnEncoding::Raw => {
-            let (count, len) = decode_varint(data)?;
-            pos += len;
-
-            let mut values = Vec::with_capacity(count as usize);
+            let count = cursor.read_len("raw column count")?;
+            let mut values = Vec::with_capacity(count);
 
             for _ in 0..count {
                 let value = match field_type {
-                    FieldType::Boolean => {
-                        let b = data[pos] != 0;
-                        pos += 1;
-                        serde_json::Value::Bool(b)
-                    }
+                    FieldType::Boolean => serde_json::Value::Bool(cursor.read_u8("boolean")? != 0),
                     FieldType::Integer(_) => {
-                        let (encoded, len) = decode_varint(&data[pos..])?;
-                        pos += len;
-                        serde_json::Value::Number(zigzag_decode(encoded).into())
+                        serde_json::Value::Number(zigzag_decode(cursor.read_varint("integer")?).into())
                     }
                     FieldType::Float(_) => {
-                        let f = f64::from_le_bytes([
-                            data[pos], data[pos+1], data[pos+2], data[pos+3],
-                            data[pos+4], data[pos+5], data[pos+6], data[pos+7],
-                        ]);
-                        pos += 8;
+                        let f = cursor.read_f64_le("float")?;
                         serde_json::Number::from_f64(f)
                             .map(serde_json::Value::Number)
                             .unwrap_or(serde_json::Value::Null)
                     }
                     FieldType::String | FieldType::Timestamp | FieldType::Uuid => {
-                        let (str_len, len) = decode_varint(&data[pos..])?;
-                        pos += len;
-
-                        let s = std::str::from_utf8(&data[pos..pos + str_len as usize])
-                            .map_err(|e| Error::DecodeError(e.to_string()))?;
-                        pos += str_len as usize;
-                        serde_json::Value::String(s.to_string())
+                        serde_json::Value::String(cursor.read_str("string")?.to_string())
                     }
                     _ => {
                         // Fallback: JSON deserialize
-                        let (json_len, len) = decode_varint(&data[pos..])?;
-                        pos += len;
-
-                        let v: serde_json::Value = serde_json::from_slice(&data[pos..pos + json_len as usize])
-                            .map_err(|e| Error::DecodeError(e.to_string()))?;
-                        pos += json_len as usize;
-                        v
+                        let json = cursor.read_len_bytes("JSON value")?;
+                        serde_json::from_slice(json).map_err(|e| Error::DecodeError(e.to_string()))?
                     }
                 };
                 values.push(value);
@@ -561,6 +893,23 @@ fn decode_column(
             Ok(values)
         }
 
+        ColumnEncoding::Xor | ColumnEncoding::Xor32 => {
+            let count = cursor.read_varint("XOR column count")?;
+            let count = usize::try_from(count)
+                .ok()
+                .filter(|&count| count <= MAX_ROWS)
+                .ok_or_else(|| Error::DecodeError(format!("XOR column claims {} values", count)))?;
+            let floats = if encoding == ColumnEncoding::Xor32 {
+                float::gorilla_decode_f32(cursor.rest(), count)?
+            } else {
+                float::gorilla_decode(cursor.rest(), count)?
+            };
+            Ok(floats
+                .into_iter()
+                .map(|f| serde_json::Number::from_f64(f).map_or(serde_json::Value::Null, serde_json::Value::Number))
+                .collect())
+        }
+
         ColumnEncoding::RunLength => {
             // Not implemented yet
             Ok(vec![serde_json::Value::Null; expected_count])
@@ -616,6 +965,205 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_columnar_serialize_roundtrip() {
+        let values: Vec<serde_json::Value> = (0..50)
+            .map(|i| {
+                let status = if i % 2 == 0 { "on" } else { "off" };
+                serde_json::json!({"id": 100 + i, "status": status, "score": i as f64 / 2.0})
+            })
+            .collect();
+
+        let mut inferrer = SchemaInferrer::new();
+        for v in &values {
+            inferrer.add_value(v).unwrap();
+        }
+        let schema = inferrer.infer().unwrap();
+
+        let bytes = ColumnarBlock::from_array(&values, &schema).unwrap().serialize();
+        let block = ColumnarBlock::deserialize(&bytes, &schema).unwrap();
+        assert_eq!(block.to_array(&schema).unwrap(), values);
+
+        for cut in 0..bytes.len() {
+            let truncated = ColumnarBlock::deserialize(&bytes[..cut], &schema);
+            assert!(truncated.is_err() || truncated.unwrap().to_array(&schema).is_err(), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn test_columnar_null_encodings() {
+        let values: Vec<serde_json::Value> = (0..200)
+            .map(|i| {
+                let mut row = serde_json::json!({"id": i});
+                if i % 100 != 7 {
+                    row["rare"] = serde_json::json!(i * 2);
+                }
+                if i < 100 {
+                    row["block"] = serde_json::json!(format!("v{}", i));
+                }
+                if i % 2 == 0 {
+                    row["even"] = serde_json::json!(i % 3 == 0);
+                }
+                row
+            })
+            .collect();
+        let mut inferrer = SchemaInferrer::new();
+        for v in &values {
+            inferrer.add_value(v).unwrap();
+        }
+        let schema = inferrer.infer().unwrap();
+
+        let block = ColumnarBlock::from_array(&values, &schema).unwrap();
+        let encoding = |name: &str| {
+            let column = block.columns.iter().find(|c| c.name == name).unwrap();
+            column.null_bitmap.as_ref().map(|_| column.null_encoding)
+        };
+        assert_eq!(encoding("id"), None);
+        assert_eq!(encoding("rare"), Some(NullEncoding::Positions));
+        assert_eq!(encoding("block"), Some(NullEncoding::Runs));
+        assert_eq!(encoding("even"), Some(NullEncoding::Bitmap));
+
+        // serialize and deserialize are inverses
+        let bytes = block.serialize();
+        let decoded = ColumnarBlock::deserialize(&bytes, &schema).unwrap();
+        assert_eq!(decoded.serialize(), bytes);
+        assert_eq!(decoded.to_array(&schema).unwrap(), values);
+
+        // Listing null rows is opt-out
+        let config = ColumnarConfig { sparse_null_density: 1.0, ..ColumnarConfig::default() };
+        let dense = ColumnarBlock::from_array_with(&values, &schema, &config).unwrap();
+        let rare = dense.columns.iter().find(|c| c.name == "rare").unwrap();
+        assert_ne!(rare.null_encoding, NullEncoding::Positions);
+        assert!(dense.serialize().len() > bytes.len());
+        assert_eq!(ColumnarBlock::deserialize(&dense.serialize(), &schema).unwrap().to_array(&schema).unwrap(), values);
+    }
+
+    #[test]
+    fn test_columnar_float_encoding() {
+        let values: Vec<serde_json::Value> = (0..200)
+            .map(|i| {
+                let temp = 21.0 + (i as f64 * 0.05).sin();
+                serde_json::json!({"temp": temp, "level": (i / 20) as f64 * 0.5})
+            })
+            .collect();
+        let mut inferrer = SchemaInferrer::new();
+        for v in &values {
+            inferrer.add_value(v).unwrap();
+        }
+        let schema = inferrer.infer().unwrap();
+
+        let block = ColumnarBlock::from_array(&values, &schema).unwrap();
+        let column = |block: &ColumnarBlock, name: &str| {
+            let column = block.columns.iter().find(|c| c.name == name).unwrap();
+            (column.encoding, column.data.len())
+        };
+        let (temp, temp_size) = column(&block, "temp");
+        assert_eq!(temp, ColumnEncoding::Xor);
+        assert!(temp_size < 200 * 8);
+        // Halves are exact as f32
+        let (level, level_size) = column(&block, "level");
+        assert_eq!(level, ColumnEncoding::Xor32);
+
+        let bytes = block.serialize();
+        let decoded = ColumnarBlock::deserialize(&bytes, &schema).unwrap();
+        assert_eq!(decoded.to_array(&schema).unwrap(), values);
+
+        let config = ColumnarConfig { float32_downcast: false, ..ColumnarConfig::default() };
+        let wide = ColumnarBlock::from_array_with(&values, &schema, &config).unwrap();
+        let (level, wide_size) = column(&wide, "level");
+        assert_eq!(level, ColumnEncoding::Xor);
+        assert!(wide_size > level_size);
+        assert_eq!(wide.to_array(&schema).unwrap(), values);
+    }
+
+    #[test]
+    fn test_columnar_nested_objects() {
+        let values: Vec<serde_json::Value> = (0..30)
+            .map(|i| match i % 3 {
+                0 => serde_json::json!({"id": i, "user": {"name": format!("u{}", i), "address": {"city": "Oslo", "zip": i}}}),
+                1 => serde_json::json!({"id": i, "user": {"name": format!("u{}", i), "address": {}}}),
+                _ => serde_json::json!({"id": i, "v1.2": {"a\\b": i}}),
+            })
+            .collect();
+        let mut inferrer = SchemaInferrer::new();
+        for v in &values {
+            inferrer.add_value(v).unwrap();
+        }
+        let schema = inferrer.infer().unwrap();
+
+        let block = ColumnarBlock::from_array(&values, &schema).unwrap();
+        let names: Vec<&str> = block.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["id", "user", "user.address", "user.address.city", "user.address.zip", "user.name", "v1\\.2", "v1\\.2.a\\\\b"]
+        );
+        let city = block.columns.iter().find(|c| c.name == "user.address.city").unwrap();
+        assert_eq!(city.encoding, ColumnEncoding::Dictionary);
+        assert_eq!(split_column_name("v1\\.2.a\\\\b"), ["v1.2", "a\\b"]);
+
+        let bytes = block.serialize();
+        let decoded = ColumnarBlock::deserialize(&bytes, &schema).unwrap();
+        assert_eq!(decoded.to_array(&schema).unwrap(), values);
+
+        // A scalar where the schema has an object
+        let bad = [serde_json::json!({"id": 1, "user": "nobody"})];
+        assert!(matches!(ColumnarBlock::from_array(&bad, &schema), Err(Error::EncodeError(_))));
+    }
+
+    #[test]
+    fn test_columnar_corrupt_nulls() {
+        let read = |body: &[u8]| read_nulls(&mut Cursor::new(body), 4);
+
+        // Runs longer than the block, or short of it
+        assert!(read(&[NULLS_RUNS, 2, 3, 2]).is_err());
+        assert!(read(&[NULLS_RUNS, 2, 1, 2]).is_err());
+        // Null rows past the end or repeated
+        assert!(read(&[NULLS_POSITIONS, 1, 4]).is_err());
+        assert!(read(&[NULLS_POSITIONS, 2, 1, 0]).is_err());
+        assert!(read(&[0x09]).is_err());
+
+        let (bitmap, encoding) = read(&[NULLS_POSITIONS, 2, 0, 3]).unwrap().unwrap();
+        assert_eq!(encoding, NullEncoding::Positions);
+        assert_eq!(bitmap.iter().by_vals().collect::<Vec<_>>(), [false, true, true, false]);
+        let (bitmap, _) = read(&[NULLS_RUNS, 3, 0, 1, 3]).unwrap().unwrap();
+        assert_eq!(bitmap.iter().by_vals().collect::<Vec<_>>(), [false, true, true, true]);
+    }
+
+    #[test]
+    fn test_columnar_corrupt_columns() {
+        let schema = Schema::new(vec![crate::schema::FieldDef {
+            name: "n".into(),
+            field_type: FieldType::Integer(crate::types::IntegerType::Int64),
+            nullable: false,
+        }]);
+
+        // Column claims more rows than it holds
+        let mut bytes = Vec::new();
+        encode_varint(3, &mut bytes);
+        encode_varint(1, &mut bytes);
+        encode_varint(1, &mut bytes);
+        bytes.push(b'n');
+        bytes.extend_from_slice(&[0x01, 0x00, 2, 1, 2]);
+        let block = ColumnarBlock::deserialize(&bytes, &schema).unwrap();
+        assert!(block.to_array(&schema).is_err());
+
+        // Dictionary index past the end of the dictionary
+        let column = [1, 1, b'a', 1, 5];
+        assert!(decode_column(&column, ColumnEncoding::Dictionary, &FieldType::String, 1).is_err());
+
+        // Bit-packed count larger than the packed data
+        let column = [0xFF, 0xFF, 0x03, 0, 4, 0xAB];
+        assert!(matches!(
+            decode_column(&column, ColumnEncoding::BitPacked(4), &FieldType::Integer(crate::types::IntegerType::Int64), 1),
+            Err(Error::Truncated { .. })
+        ));
+
+        let mut bytes = Vec::new();
+        encode_varint(MAX_ROWS as u64 + 1, &mut bytes);
+        encode_varint(0, &mut bytes);
+        assert!(ColumnarBlock::deserialize(&bytes, &schema).is_err());
+    }
+
     #[test]
     fn test_columnar_delta_encoding() {
         // Sequential IDs should use delta encoding
@@ -0,0 +1,681 @@
+//! Session configuration and builder
+//!
+//! `FluxConfig` fields stay public for backwards compatibility, but new code
+//! should go through `FluxConfig::builder()`, which rejects combinations that
+//! would otherwise be silently accepted.
+
+use crate::delta::DeltaLimits;
+use crate::frame::FrameMetadata;
+use crate::lz::{LzParams, MAX_HISTORY};
+use crate::schema::EvictionPolicy;
+use crate::{Error, Result};
+use std::time::Duration;
+
+/// Largest dictionary a session may grow to
+pub const MAX_DICT_SIZE: usize = 1 << 24;
+
+/// Default cap on the size of a decompressed message
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 256 << 20;
+
+/// Inputs below this size take the `Profile::Realtime` fast path
+pub const SMALL_FRAME_MAX: usize = 1024;
+
+/// Workload profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Run every enabled stage and keep whichever output is smallest
+    #[default]
+    Balanced,
+    /// Minimize per-message latency
+    ///
+    /// Inputs under `SMALL_FRAME_MAX` bytes skip LZ, columnar and entropy
+    /// coding and are framed in place in a reused thread-local buffer.
+    /// Parsing the JSON and finding its schema still allocate; the
+    /// `realtime_small` benchmark compares the two profiles.
+    Realtime,
+}
+
+/// Overall speed/ratio trade-off
+///
+/// A level sets exactly three options; see `FluxConfig::with_level`:
+///
+/// | Level     | `lz_params`                                    | `entropy` | `detect_string_types` |
+/// |-----------|------------------------------------------------|-----------|-----------------------|
+/// | `Fast`    | `LzParams::fast()`: 2^12 table, 1 probe        | off       | off                   |
+/// | `Default` | `LzParams::default()`: 2^14 table, 1 probe     | on        | on                    |
+/// | `Max`     | `LzParams::max()`: 2^16 table, 64-deep chains  | on        | on                    |
+///
+/// Levels leave every other option, `columnar` included, as it was. Schema
+/// inference always reads each message in full, so there is no sampling to
+/// tune. Individual options can still be changed afterwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FluxLevel {
+    /// Lowest latency: single-probe LZ, no entropy coding, strings stored as
+    /// strings
+    Fast,
+    /// The `FluxConfig::default()` settings
+    #[default]
+    Default,
+    /// Best ratio: deep LZ chains
+    Max,
+}
+
+impl FluxLevel {
+    /// LZ stage tuning for this level
+    pub fn lz_params(self) -> LzParams {
+        match self {
+            FluxLevel::Fast => LzParams::fast(),
+            FluxLevel::Default => LzParams::default(),
+            FluxLevel::Max => LzParams::max(),
+        }
+    }
+}
+
+/// FLUX configuration
+#[derive(Debug, Clone)]
+pub struct FluxConfig {
+    /// Set `FrameFlags::COLUMNAR` on frames
+    ///
+    /// Only marks the frame; the session pipeline encodes rows as they come
+    /// and does not transpose them.
+    pub columnar: bool,
+    /// Enable FSE entropy coding
+    pub entropy: bool,
+    /// Enable delta encoding
+    pub delta: bool,
+    /// Enable checksum
+    pub checksum: bool,
+    /// Maximum dictionary size in bytes
+    ///
+    /// Bounds the session value dictionary on both sides; a peer whose
+    /// updates would grow it further is rejected.
+    pub max_dict_size: usize,
+    /// LZ stage tuning (`LzParams::fast()`, default, `LzParams::max()`)
+    pub lz_params: LzParams,
+    /// Workload profile
+    pub profile: Profile,
+    /// Largest output `decompress` will produce, in bytes
+    ///
+    /// Frames claiming or expanding to more fail with
+    /// `Error::OutputLimitExceeded` before the memory is allocated.
+    pub max_decompressed_size: usize,
+    /// Accumulate per-stage wall time in `SessionStats`
+    ///
+    /// Off by default: it reads the session `Clock` several times per message.
+    pub stage_timing: bool,
+    /// Record the original key order of objects whose keys are not sorted,
+    /// so `decompress` reproduces it instead of canonical (sorted) order
+    ///
+    /// Off by default: the order record costs a few bytes per such object.
+    pub preserve_key_order: bool,
+    /// Never widen numbers into a type that changes them
+    ///
+    /// By default integers sharing a field with floats come back as floats
+    /// (`1` as `1.0`), and integers above `i64::MAX` sharing a field with
+    /// negative ones lose precision. Lossless mode encodes such fields as
+    /// unions instead, at a byte per value.
+    pub lossless_numbers: bool,
+    /// Store a hash of each message in its frame, checked after decoding
+    ///
+    /// Catches messages the pipeline got wrong, which the frame checksum
+    /// cannot (see `content_hash`). Needs `lossless_numbers`, so decoded
+    /// messages equal their input. Off by default, as it grows every frame.
+    pub content_hash: bool,
+    /// Send short recurring string values once per session
+    ///
+    /// Strings seen a second time are added to a dictionary shared with the
+    /// receiver (up to `max_dict_size` bytes) and referenced by index from
+    /// then on. The receiver must decompress every frame, in order, with
+    /// one session. Off by default.
+    pub value_dictionary: bool,
+    /// Most schemas a session caches (0 = unbounded)
+    ///
+    /// Beyond the limit, `schema_eviction` picks a schema to drop. Peers
+    /// evict in step when both use the same limit and policy; otherwise a
+    /// decompressor recovers a schema it dropped with a schema request.
+    pub max_cached_schemas: usize,
+    /// Which schema a full cache evicts
+    pub schema_eviction: EvictionPolicy,
+    /// Send a changed schema as its difference from the previous one
+    ///
+    /// When a message's shape differs from the last message's by a few
+    /// fields, the frame carries only those changes (see `SchemaDelta`)
+    /// rather than the full schema. The receiver needs the previous schema
+    /// cached. Off by default.
+    pub schema_deltas: bool,
+    /// Run the LZ stage
+    ///
+    /// Even when on, the stage is skipped for large payloads whose bytes
+    /// look random (embedded compressed or encrypted data), where it would
+    /// find no matches. On by default.
+    pub lz: bool,
+    /// Bytes of earlier payloads the LZ stage may reference (0 = off)
+    ///
+    /// Each message is LZ-compressed against the tail of the previous ones,
+    /// so a response that repeats most of the last one costs only its
+    /// differences. The receiver must use a window at least this large and
+    /// decompress every frame, in order, with one session. At most
+    /// `lz::MAX_HISTORY` bytes; off by default.
+    pub lz_history: usize,
+    /// Bytes a session may hold, per `FluxSession::memory_usage` (0 = unbounded)
+    ///
+    /// When a message leaves the session above the budget, it frees its
+    /// scratch buffers and then evicts schemas by `schema_eviction`. A peer
+    /// whose frame references an evicted schema recovers it with a schema
+    /// request. The value dictionary and LZ history are bounded by their own
+    /// limits and never evicted, so the budget must leave room for them.
+    /// `SyncFluxSession` rejects a budget, as its schemas are shared.
+    pub memory_budget: usize,
+    /// Infer timestamp, UUID and base64 types for string values
+    ///
+    /// Detected values are stored in binary form, which is smaller but
+    /// means every string is inspected during inference.
+    pub detect_string_types: bool,
+    /// Deepest nesting of objects and arrays compressed or decompressed;
+    /// deeper messages, and frames whose schema nests deeper, fail with
+    /// `Error::DepthLimitExceeded` instead of exhausting the stack
+    ///
+    /// Defaults to `DEFAULT_MAX_DEPTH`; at most `MAX_DEPTH`. Encoding and
+    /// decoding recurse once per level: in release builds 1,000 levels fit
+    /// the 1 MB stack wasm32 gets by default, while debug builds need more
+    /// than ten times that. The peer needs a limit at least as deep.
+    pub max_depth: usize,
+    /// Stop running the LZ and entropy stages while they fail to shrink
+    /// this session's traffic, probing now and then (see `adaptive`)
+    ///
+    /// Saves their cost on payloads that can't be compressed further, such
+    /// as embedded gzip or image bytes. Off by default.
+    pub adaptive: bool,
+    /// Messages shorter than this many bytes are sent as JSON text in a raw
+    /// frame (`frame::RAW_PAYLOAD`) when that is smaller (0 = off)
+    ///
+    /// Schema, encoding and compression can cost more than they save on
+    /// tiny messages. Below the threshold the message is also sized as an
+    /// encoded frame, and the smaller one is sent: a raw frame adds 3 bytes
+    /// (7 with `checksum`), so it wins whenever the schema would have to be
+    /// sent along. Around 100 suits most traffic. Messages with metadata, a
+    /// given schema or encryption always take the pipeline. The receiver
+    /// needs a version that reads raw frames. Off by default.
+    ///
+    /// The text is rewritten without whitespace and with object keys sorted
+    /// (kept as given with `preserve_key_order`), so raw frames are as
+    /// deterministic as encoded ones.
+    pub raw_threshold: usize,
+    /// Write data frames in the compact form (`frame::COMPACT_HEADER`)
+    ///
+    /// The magic is dropped and the schema ID and payload length are stored
+    /// as varints instead of fixed 4-byte integers. A cached-schema message
+    /// with a schema ID and payload under 128 then carries 4 bytes of
+    /// framing instead of 16, or 8 instead of 20 with `checksum`. The
+    /// receiver needs a version that reads compact frames; `decompress_any`
+    /// and `ContentEncoding::detect` tell them by their version byte. Off
+    /// by default.
+    pub compact_header: bool,
+}
+
+/// Deepest nesting `FluxConfig::max_depth` allows
+pub const MAX_DEPTH: usize = 1024;
+
+/// Default `FluxConfig::max_depth`, the nesting serde_json parses by default
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+impl Default for FluxConfig {
+    fn default() -> Self {
+        Self {
+            columnar: true,
+            entropy: true,
+            delta: true,
+            checksum: true,
+            max_dict_size: 65536,
+            lz_params: LzParams::default(),
+            profile: Profile::default(),
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            stage_timing: false,
+            preserve_key_order: false,
+            lossless_numbers: false,
+            content_hash: false,
+            value_dictionary: false,
+            max_cached_schemas: 0,
+            schema_eviction: EvictionPolicy::default(),
+            schema_deltas: false,
+            lz: true,
+            lz_history: 0,
+            memory_budget: 0,
+            detect_string_types: true,
+            max_depth: DEFAULT_MAX_DEPTH,
+            adaptive: false,
+            raw_threshold: 0,
+            compact_header: false,
+        }
+    }
+}
+
+impl FluxConfig {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> FluxConfigBuilder {
+        FluxConfigBuilder::default()
+    }
+
+    /// Default configuration tuned for `level`
+    pub fn with_level(level: FluxLevel) -> Self {
+        let mut config = Self::default();
+        config.apply_level(level);
+        config
+    }
+
+    /// Set every option a level controls
+    fn apply_level(&mut self, level: FluxLevel) {
+        let fast = level == FluxLevel::Fast;
+        self.lz_params = level.lz_params();
+        self.entropy = !fast;
+        self.detect_string_types = !fast;
+    }
+
+    /// Check that every option is within range
+    ///
+    /// This is the lenient check applied by `FluxConfigBuilder::build`;
+    /// `FluxConfigBuilder::strict` adds checks for risky combinations.
+    pub fn validate(&self) -> Result<()> {
+        if self.max_dict_size == 0 {
+            return Err(invalid("max_dict_size", "must be greater than zero"));
+        }
+        if self.max_dict_size > MAX_DICT_SIZE {
+            return Err(invalid(
+                "max_dict_size",
+                format!("must be at most {} bytes, got {}", MAX_DICT_SIZE, self.max_dict_size),
+            ));
+        }
+        if self.max_decompressed_size == 0 {
+            return Err(invalid("max_decompressed_size", "must be greater than zero"));
+        }
+        self.lz_params.validate()?;
+        if self.content_hash && !self.lossless_numbers {
+            return Err(invalid("content_hash", "needs lossless_numbers, or decoded numbers may differ from the input"));
+        }
+        if self.lz_history > MAX_HISTORY {
+            return Err(invalid(
+                "lz_history",
+                format!("must be at most {} bytes, got {}", MAX_HISTORY, self.lz_history),
+            ));
+        }
+        if self.lz_history > 0 && !self.lz {
+            return Err(invalid("lz_history", "needs the LZ stage (`lz`)"));
+        }
+        // Both directions keep a dictionary and a history
+        let fixed = 2 * self.lz_history + if self.value_dictionary { 2 * self.max_dict_size } else { 0 };
+        if self.max_depth == 0 || self.max_depth > MAX_DEPTH {
+            return Err(invalid("max_depth", format!("must be between 1 and {}, got {}", MAX_DEPTH, self.max_depth)));
+        }
+        if self.memory_budget > 0 && self.memory_budget <= fixed {
+            return Err(invalid(
+                "memory_budget",
+                format!("must exceed the {} bytes of value dictionary and LZ history, got {}", fixed, self.memory_budget),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Strict-mode checks on top of `validate`
+    fn validate_strict(&self) -> Result<()> {
+        self.validate()?;
+        if self.delta && !self.checksum {
+            return Err(invalid(
+                "checksum",
+                "delta encoding without a checksum cannot detect a diverged baseline",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Builder for `FluxConfig`
+///
+/// ```rust,ignore
+/// let config = FluxConfig::builder()
+///     .entropy(false)
+///     .max_dict_size(1 << 20)
+///     .strict(true)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FluxConfigBuilder {
+    config: FluxConfig,
+    strict: bool,
+}
+
+impl FluxConfigBuilder {
+    /// Speed/ratio level
+    ///
+    /// Overwrites the options the level controls, so call it before
+    /// adjusting any of them individually.
+    pub fn level(mut self, level: FluxLevel) -> Self {
+        self.config.apply_level(level);
+        self
+    }
+
+    /// Set `FrameFlags::COLUMNAR` on frames
+    pub fn columnar(mut self, enabled: bool) -> Self {
+        self.config.columnar = enabled;
+        self
+    }
+
+    /// Enable FSE entropy coding
+    pub fn entropy(mut self, enabled: bool) -> Self {
+        self.config.entropy = enabled;
+        self
+    }
+
+    /// Enable delta encoding
+    pub fn delta(mut self, enabled: bool) -> Self {
+        self.config.delta = enabled;
+        self
+    }
+
+    /// Enable the CRC32C frame checksum
+    pub fn checksum(mut self, enabled: bool) -> Self {
+        self.config.checksum = enabled;
+        self
+    }
+
+    /// Maximum dictionary size in bytes
+    pub fn max_dict_size(mut self, size: usize) -> Self {
+        self.config.max_dict_size = size;
+        self
+    }
+
+    /// LZ stage tuning
+    pub fn lz_params(mut self, params: LzParams) -> Self {
+        self.config.lz_params = params;
+        self
+    }
+
+    /// Workload profile
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.config.profile = profile;
+        self
+    }
+
+    /// Largest output `decompress` will produce, in bytes
+    pub fn max_decompressed_size(mut self, size: usize) -> Self {
+        self.config.max_decompressed_size = size;
+        self
+    }
+
+    /// Accumulate per-stage wall time in `SessionStats`
+    pub fn stage_timing(mut self, enabled: bool) -> Self {
+        self.config.stage_timing = enabled;
+        self
+    }
+
+    /// Reproduce the original key order on decompress
+    pub fn preserve_key_order(mut self, enabled: bool) -> Self {
+        self.config.preserve_key_order = enabled;
+        self
+    }
+
+    /// Decode every number exactly as it was written
+    pub fn lossless_numbers(mut self, enabled: bool) -> Self {
+        self.config.lossless_numbers = enabled;
+        self
+    }
+
+    /// Store a hash of each message in its frame, checked after decoding
+    pub fn content_hash(mut self, enabled: bool) -> Self {
+        self.config.content_hash = enabled;
+        self
+    }
+
+    /// Send short recurring string values once per session
+    pub fn value_dictionary(mut self, enabled: bool) -> Self {
+        self.config.value_dictionary = enabled;
+        self
+    }
+
+    /// Most schemas a session caches (0 = unbounded)
+    pub fn max_cached_schemas(mut self, max: usize) -> Self {
+        self.config.max_cached_schemas = max;
+        self
+    }
+
+    /// Which schema a full cache evicts
+    pub fn schema_eviction(mut self, policy: EvictionPolicy) -> Self {
+        self.config.schema_eviction = policy;
+        self
+    }
+
+    /// Send changed schemas as deltas from the previous one
+    pub fn schema_deltas(mut self, enabled: bool) -> Self {
+        self.config.schema_deltas = enabled;
+        self
+    }
+
+    /// Run the LZ stage
+    pub fn lz(mut self, enabled: bool) -> Self {
+        self.config.lz = enabled;
+        self
+    }
+
+    /// Bytes of earlier payloads the LZ stage may reference (0 = off)
+    pub fn lz_history(mut self, bytes: usize) -> Self {
+        self.config.lz_history = bytes;
+        self
+    }
+
+    /// Bytes a session may hold (0 = unbounded)
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.config.memory_budget = bytes;
+        self
+    }
+
+    /// Infer timestamp, UUID and base64 types for string values
+    pub fn detect_string_types(mut self, enabled: bool) -> Self {
+        self.config.detect_string_types = enabled;
+        self
+    }
+
+    /// Deepest nesting of objects and arrays compressed or decompressed
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.config.max_depth = depth;
+        self
+    }
+
+    /// Skip stages that fail to shrink recent messages
+    pub fn adaptive(mut self, enabled: bool) -> Self {
+        self.config.adaptive = enabled;
+        self
+    }
+
+    /// Send messages shorter than `bytes` as raw frames (0 = off)
+    pub fn raw_threshold(mut self, bytes: usize) -> Self {
+        self.config.raw_threshold = bytes;
+        self
+    }
+
+    /// Write data frames with a compact header
+    pub fn compact_header(mut self, enabled: bool) -> Self {
+        self.config.compact_header = enabled;
+        self
+    }
+
+    /// Reject risky combinations such as delta without checksum
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Validate and return the configuration
+    pub fn build(self) -> Result<FluxConfig> {
+        if self.strict {
+            self.config.validate_strict()?;
+        } else {
+            self.config.validate()?;
+        }
+        Ok(self.config)
+    }
+}
+
+/// Configuration for `FluxStreamSession`
+#[derive(Debug, Clone, Default)]
+pub struct StreamConfig {
+    /// Emit a full snapshot every `keyframe_interval` updates (0 = never)
+    ///
+    /// A receiver that lost a message recovers at the next keyframe.
+    pub keyframe_interval: u32,
+    /// Send a full snapshot instead of any delta larger than this (0 = no limit)
+    pub max_delta_bytes: usize,
+    /// Wire format of updates
+    pub patch_format: PatchFormat,
+    /// Also emit a full snapshot once this much time has passed since the
+    /// last one (zero = never); measured with the session `Clock`
+    pub keyframe_period: Duration,
+    /// Compute deltas against the last state the receiver acknowledged
+    /// (`StreamSender::on_ack`) instead of the last one sent
+    ///
+    /// For transports that lose or reorder messages: updates carry a
+    /// sequence number and the one they build on, and a lost update costs
+    /// nothing but the retransmit. `PatchFormat::Delta` only. Sequence
+    /// numbers and unacknowledged states aren't part of `export_state`.
+    pub acked: bool,
+    /// Send edits to strings of at least this many bytes as the changed
+    /// middle part alone (0 = resend whole strings)
+    ///
+    /// `PatchFormat::Delta` only; receivers from before string patches
+    /// existed reject them.
+    pub string_diff: usize,
+    /// Bounds on comparing states, against deeply nested or huge documents
+    pub delta_limits: DeltaLimits,
+}
+
+/// Wire format of `FluxStreamSession` updates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PatchFormat {
+    /// Internal `DeltaOp` encoding
+    #[default]
+    Delta,
+    /// RFC 7386 JSON Merge Patch documents, each compressed as a FLUX frame
+    ///
+    /// Clients that only understand merge-patch semantics can decompress a
+    /// frame and apply it. States must be JSON objects, and since `null`
+    /// means "delete" in a merge patch, states with `null` object members
+    /// are refused (`null` inside arrays is fine).
+    MergePatch,
+}
+
+/// Per-message overrides for `FluxSession::compress_with`
+///
+/// Overrides apply to a single call and never touch the session config, so
+/// middleware can decide per request (e.g. on `Content-Length` or client
+/// hints) without cloning sessions.
+#[derive(Debug, Clone, Default)]
+pub struct MessageOptions {
+    /// Skip entropy coding for this message
+    pub skip_entropy: bool,
+    /// Include the full schema even if it is already cached
+    pub force_schema: bool,
+    /// Send this message without a schema delta (`FluxConfig::schema_deltas`)
+    /// or LZ matches into earlier messages (`FluxConfig::lz_history`)
+    ///
+    /// A peer that missed earlier frames can then decode it, given its
+    /// schema (resent with `force_schema`). The message still joins the
+    /// history, so later messages may refer to it.
+    pub disable_delta: bool,
+    /// Key-value metadata written to the frame (omitted when empty)
+    pub metadata: FrameMetadata,
+}
+
+fn invalid(field: &'static str, reason: impl Into<String>) -> Error {
+    Error::InvalidConfig {
+        field,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_defaults() {
+        let config = FluxConfig::builder().build().unwrap();
+        let default = FluxConfig::default();
+        assert_eq!(config.columnar, default.columnar);
+        assert_eq!(config.entropy, default.entropy);
+        assert_eq!(config.delta, default.delta);
+        assert_eq!(config.checksum, default.checksum);
+        assert_eq!(config.max_dict_size, default.max_dict_size);
+    }
+
+    #[test]
+    fn test_builder_sets_fields() {
+        let config = FluxConfig::builder()
+            .columnar(false)
+            .entropy(false)
+            .checksum(false)
+            .max_dict_size(1024)
+            .build()
+            .unwrap();
+        assert!(!config.columnar);
+        assert!(!config.entropy);
+        assert!(!config.checksum);
+        assert_eq!(config.max_dict_size, 1024);
+    }
+
+    #[test]
+    fn test_dict_size_range() {
+        let err = FluxConfig::builder().max_dict_size(0).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "max_dict_size", .. }));
+
+        let err = FluxConfig::builder().max_dict_size(MAX_DICT_SIZE + 1).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "max_dict_size", .. }));
+    }
+
+    #[test]
+    fn test_lz_params() {
+        let config = FluxConfig::builder().lz_params(LzParams::max()).build().unwrap();
+        assert_eq!(config.lz_params, LzParams::max());
+
+        let bad = LzParams { hash_bits: 30, ..LzParams::default() };
+        let err = FluxConfig::builder().lz_params(bad).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "lz.hash_bits", .. }));
+
+        let err = FluxConfig::builder().lz_history(MAX_HISTORY + 1).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "lz_history", .. }));
+
+        let err = FluxConfig::builder().lz(false).lz_history(4096).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "lz_history", .. }));
+    }
+
+    #[test]
+    fn test_levels() {
+        let default = FluxConfig::with_level(FluxLevel::Default);
+        assert_eq!(default.lz_params, FluxConfig::default().lz_params);
+        assert!(default.entropy && default.detect_string_types);
+
+        let fast = FluxConfig::with_level(FluxLevel::Fast);
+        assert_eq!(fast.lz_params, LzParams::fast());
+        assert!(!fast.entropy && !fast.detect_string_types);
+        assert_eq!(fast.columnar, FluxConfig::default().columnar);
+        assert_eq!(FluxConfig::with_level(FluxLevel::Max).lz_params, LzParams::max());
+
+        // Later builder calls override the level
+        let config = FluxConfig::builder().level(FluxLevel::Fast).entropy(true).build().unwrap();
+        assert!(config.entropy);
+        assert_eq!(config.lz_params, LzParams::fast());
+    }
+
+    #[test]
+    fn test_strict_delta_requires_checksum() {
+        // Lenient mode accepts it
+        assert!(FluxConfig::builder().delta(true).checksum(false).build().is_ok());
+
+        let err = FluxConfig::builder()
+            .delta(true)
+            .checksum(false)
+            .strict(true)
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "checksum", .. }));
+
+        assert!(FluxConfig::builder().delta(false).checksum(false).strict(true).build().is_ok());
+    }
+}
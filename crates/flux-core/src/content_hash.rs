@@ -0,0 +1,103 @@
+//! End-to-end content hash
+//!
+//! The frame checksum covers the compressed bytes, so it catches transport
+//! corruption but not a message the encoder or decoder got wrong. With
+//! `FluxConfig::content_hash` the compressor also stores the xxHash64 of
+//! the message itself in the frame metadata, under `CONTENT_HASH_KEY`, and
+//! `decompress` checks the decoded message against it.
+//!
+//! The hash covers the canonical JSON text of the message: compact, with
+//! object keys sorted. Whitespace and key order therefore don't matter, and
+//! the decoder hashes exactly what it reproduces. Since the decoded message
+//! must equal the input, the option needs `FluxConfig::lossless_numbers`.
+
+use std::io::{self, Write};
+
+use xxhash_rust::xxh64::Xxh64;
+
+use crate::{Error, Result};
+
+/// Frame metadata key carrying the content hash (8 bytes, little-endian)
+pub const CONTENT_HASH_KEY: &str = "flux-content-hash";
+
+/// xxHash64 (seed 0) of the canonical JSON text of `value`
+pub fn content_hash(value: &serde_json::Value) -> u64 {
+    let mut hasher = HashWriter(Xxh64::new(0));
+    write_canonical(&mut hasher, value).expect("hashing does not fail");
+    hasher.0.digest()
+}
+
+/// Check a decoded `value` against the hash stored in its frame
+pub(crate) fn verify(value: &serde_json::Value, stored: &[u8]) -> Result<()> {
+    let expected = <[u8; 8]>::try_from(stored)
+        .map(u64::from_le_bytes)
+        .map_err(|_| Error::InvalidFrame(format!("{}-byte content hash", stored.len())))?;
+    let actual = content_hash(value);
+    if actual != expected {
+        return Err(Error::ContentHashMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Feeds written bytes to the hash, so the canonical text is never buffered
+struct HashWriter(Xxh64);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn write_canonical(out: &mut HashWriter, value: &serde_json::Value) -> io::Result<()> {
+    match value {
+        serde_json::Value::Array(items) => {
+            out.write_all(b"[")?;
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                write_canonical(out, item)?;
+            }
+            out.write_all(b"]")
+        }
+        serde_json::Value::Object(obj) => {
+            let mut members: Vec<_> = obj.iter().collect();
+            members.sort_unstable_by_key(|(key, _)| *key);
+            out.write_all(b"{")?;
+            for (i, (key, member)) in members.into_iter().enumerate() {
+                if i > 0 {
+                    out.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *out, key)?;
+                out.write_all(b":")?;
+                write_canonical(out, member)?;
+            }
+            out.write_all(b"}")
+        }
+        scalar => Ok(serde_json::to_writer(&mut *out, scalar)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_content_hash_is_canonical() {
+        let a: serde_json::Value = serde_json::from_str(r#"{ "b": [1, 2.5, "x"], "a": {"d": null, "c": true} }"#).unwrap();
+        let b = json!({"a": {"c": true, "d": null}, "b": [1, 2.5, "x"]});
+        assert_eq!(content_hash(&a), content_hash(&b));
+        assert_eq!(content_hash(&a), xxhash_rust::xxh64::xxh64(br#"{"a":{"c":true,"d":null},"b":[1,2.5,"x"]}"#, 0));
+        assert_ne!(content_hash(&json!({"a": 1})), content_hash(&json!({"a": 1.0})));
+
+        assert!(verify(&a, &content_hash(&b).to_le_bytes()).is_ok());
+        assert!(matches!(verify(&json!([]), &content_hash(&b).to_le_bytes()), Err(Error::ContentHashMismatch { .. })));
+        assert!(matches!(verify(&a, &[0; 4]), Err(Error::InvalidFrame(_))));
+    }
+}
@@ -0,0 +1,218 @@
+//! Encrypted frames
+//!
+//! A session with a key provider seals every frame it compresses with
+//! XChaCha20-Poly1305, so stored or relayed payloads stay confidential
+//! without a second tool:
+//!
+//! ```rust,ignore
+//! let keys = Arc::new(Keyring::new(1, key));
+//! let mut sender = FluxSession::new().with_encryption(keys.clone());
+//! let mut receiver = FluxSession::new().with_encryption(keys);
+//! let frame = sender.compress(json)?;
+//! assert_eq!(receiver.decompress(&frame)?, json);
+//! ```
+//!
+//! Frames carry the ID of their key, so keys can be rotated: encrypt with
+//! a new key while older ones stay in the keyring for decryption. Nonces
+//! are random, which the 24-byte XChaCha20 nonce makes safe for any number
+//! of frames per key.
+//!
+//! The header stays readable (flags, schema ID and payload length), as do
+//! the key ID and sizes; metadata, schemas, dictionaries and payloads are
+//! sealed. See `frame::Encrypted` for the layout.
+//!
+//! Requires the `crypto` feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+use crate::frame::{Capabilities, FrameFlags, RawFrame, CHECKSUM_SIZE, COMPACT_HEADER, TAG_SIZE};
+use crate::{Error, FluxSession, Result, FLUX_MAGIC};
+
+/// A 256-bit XChaCha20-Poly1305 key
+pub type Key = [u8; 32];
+
+/// Source of encryption keys
+pub trait KeyProvider: Send + Sync {
+    /// ID of the key new frames are sealed with
+    fn current_key_id(&self) -> u32;
+
+    /// The key with `id`, if this provider has it
+    fn key(&self, id: u32) -> Option<Key>;
+}
+
+/// Keys held in memory, by ID
+#[derive(Clone)]
+pub struct Keyring {
+    current: u32,
+    keys: HashMap<u32, Key>,
+}
+
+impl Keyring {
+    /// A keyring sealing frames with `key`, known as `id`
+    pub fn new(id: u32, key: Key) -> Self {
+        Self { current: id, keys: HashMap::from([(id, key)]) }
+    }
+
+    /// Also open frames sealed with `key` (a retired key, for instance)
+    pub fn with_key(mut self, id: u32, key: Key) -> Self {
+        self.keys.entry(id).or_insert(key);
+        self
+    }
+}
+
+impl KeyProvider for Keyring {
+    fn current_key_id(&self) -> u32 {
+        self.current
+    }
+
+    fn key(&self, id: u32) -> Option<Key> {
+        self.keys.get(&id).copied()
+    }
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ids: Vec<_> = self.keys.keys().collect();
+        ids.sort();
+        f.debug_struct("Keyring").field("current", &self.current).field("ids", &ids).finish()
+    }
+}
+
+impl FluxSession {
+    /// Seal compressed frames with keys from `keys`, and open sealed frames
+    ///
+    /// Without a key provider, sealed frames fail to decompress with
+    /// `Error::KeyNotFound`.
+    pub fn with_encryption(mut self, keys: Arc<dyn KeyProvider>) -> Self {
+        self.encryption = Some(keys);
+        self
+    }
+}
+
+/// Seal the plain `frame`, with a checksum trailer if `checksum`
+pub(crate) fn seal(frame: &[u8], keys: &dyn KeyProvider, checksum: bool) -> Result<Vec<u8>> {
+    let raw = RawFrame::parse(frame)?;
+    let start = raw.header.magic_size() + raw.header.size();
+    let end = frame.len() - if raw.checksum.is_some() { CHECKSUM_SIZE } else { 0 };
+    let plaintext = &frame[start..end];
+
+    let key_id = keys.current_key_id();
+    let key = keys.key(key_id).ok_or(Error::KeyNotFound(key_id))?;
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    // The capability field needs the full header
+    let mut header = raw.header;
+    header.version &= !COMPACT_HEADER;
+    header.capabilities.insert(Capabilities::ENCRYPTED);
+    header.flags.set(FrameFlags::CHECKSUM_PRESENT, checksum);
+
+    let ciphertext_len = u32::try_from(plaintext.len() + TAG_SIZE)
+        .map_err(|_| Error::EncodeError("frame too large to encrypt".into()))?;
+    let mut sealed = Vec::with_capacity(frame.len() + 64);
+    sealed.extend_from_slice(&FLUX_MAGIC);
+    sealed.extend_from_slice(&header.to_bytes());
+    sealed.extend_from_slice(&key_id.to_le_bytes());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext_len.to_le_bytes());
+
+    let ciphertext = XChaCha20Poly1305::new(&key.into())
+        .encrypt(&nonce, Payload { msg: plaintext, aad: &sealed[FLUX_MAGIC.len()..] })
+        .map_err(|_| Error::EncodeError("encryption failed".into()))?;
+    sealed.extend_from_slice(&ciphertext);
+
+    if checksum {
+        let crc = crc32c::crc32c(&sealed[FLUX_MAGIC.len()..]);
+        sealed.extend_from_slice(&crc.to_le_bytes());
+    }
+    Ok(sealed)
+}
+
+/// Open a sealed frame, returning the plain frame it seals
+pub(crate) fn open(frame: &RawFrame<'_>, keys: Option<&dyn KeyProvider>) -> Result<Vec<u8>> {
+    let Some(encrypted) = frame.encrypted else {
+        return Ok(frame.bytes.to_vec());
+    };
+    let key = keys
+        .and_then(|keys| keys.key(encrypted.key_id))
+        .ok_or(Error::KeyNotFound(encrypted.key_id))?;
+    let plaintext = XChaCha20Poly1305::new(&key.into())
+        .decrypt(
+            XNonce::from_slice(encrypted.nonce),
+            Payload { msg: encrypted.ciphertext, aad: encrypted.associated_data },
+        )
+        .map_err(|_| Error::DecryptionFailed)?;
+    Ok(frame.decrypted_frame(&plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::read_metadata;
+    use crate::MessageOptions;
+
+    const JSON: &[u8] = br#"{"id":1,"secret":"hunter2","tags":["a","b"]}"#;
+
+    fn keys() -> Arc<Keyring> {
+        Arc::new(Keyring::new(7, [0x42; 32]))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut sender = FluxSession::new().with_encryption(keys());
+        let mut receiver = FluxSession::new().with_encryption(keys());
+        for _ in 0..3 {
+            let frame = sender.compress(JSON).unwrap();
+            assert!(!frame.windows(7).any(|w| w == b"hunter2"));
+            let raw = RawFrame::parse(&frame).unwrap();
+            assert_eq!(raw.encrypted.unwrap().key_id, 7);
+            raw.verify_checksum().unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), JSON);
+        }
+
+        // Metadata is sealed too
+        let mut opts = MessageOptions::default();
+        opts.metadata.insert("trace", "t-1");
+        let frame = sender.compress_with(JSON, &opts).unwrap();
+        assert!(read_metadata(&frame).unwrap().is_empty());
+        assert_eq!(receiver.decompress(&frame).unwrap(), JSON);
+
+        // Sealed frames keep the full header, for its capability field
+        let config = crate::FluxConfig::builder().compact_header(true).build().unwrap();
+        let frame = FluxSession::with_config(config).with_encryption(keys()).compress(JSON).unwrap();
+        assert!(!RawFrame::parse(&frame).unwrap().header.is_compact());
+        assert_eq!(receiver.decompress(&frame).unwrap(), JSON);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = FluxSession::new().with_encryption(keys()).compress(JSON).unwrap();
+        let rotated = Arc::new(Keyring::new(8, [0x43; 32]).with_key(7, [0x42; 32]));
+        let mut receiver = FluxSession::new().with_encryption(rotated.clone());
+        assert_eq!(receiver.decompress(&old).unwrap(), JSON);
+        let new = FluxSession::new().with_encryption(rotated).compress(JSON).unwrap();
+        assert_eq!(RawFrame::parse(&new).unwrap().encrypted.unwrap().key_id, 8);
+
+        let err = FluxSession::new().with_encryption(keys()).decompress(&new).unwrap_err();
+        assert!(matches!(err.root(), Error::KeyNotFound(8)));
+        assert!(matches!(FluxSession::new().decompress(&old).unwrap_err().root(), Error::KeyNotFound(7)));
+    }
+
+    #[test]
+    fn test_tampering() {
+        let config = crate::FluxConfig::builder().checksum(false).build().unwrap();
+        let frame = FluxSession::with_config(config).with_encryption(keys()).compress(JSON).unwrap();
+        // Every byte after the magic is authenticated: header, key ID, nonce and ciphertext
+        for i in FLUX_MAGIC.len()..frame.len() {
+            let mut tampered = frame.clone();
+            tampered[i] ^= 0x01;
+            assert!(FluxSession::new().with_encryption(keys()).decompress(&tampered).is_err(), "byte {}", i);
+        }
+        let wrong = Arc::new(Keyring::new(7, [0x00; 32]));
+        let err = FluxSession::new().with_encryption(wrong).decompress(&frame).unwrap_err();
+        assert!(matches!(err.root(), Error::DecryptionFailed));
+    }
+}
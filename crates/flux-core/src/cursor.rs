@@ -0,0 +1,250 @@
+//! Bounds-checked reader for decoding untrusted input
+//!
+//! Every read names the field being decoded, so a truncated frame fails with
+//! `Error::Truncated { what, .. }` instead of panicking on a slice index.
+
+use crate::{Error, Result};
+
+/// Read position over a byte slice
+#[derive(Debug, Clone)]
+pub struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Start reading at the beginning of `buf`
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Resume reading `buf` at `pos`
+    fn at(buf: &'a [u8], pos: usize) -> Self {
+        Self { buf, pos: pos.min(buf.len()) }
+    }
+
+    /// Bytes consumed so far
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Check if everything has been read
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Unread bytes, without consuming them
+    pub fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Read `len` bytes
+    pub fn read_bytes(&mut self, len: usize, what: &'static str) -> Result<&'a [u8]> {
+        if len > self.remaining() {
+            return Err(Error::Truncated {
+                what,
+                needed: len,
+                available: self.remaining(),
+            });
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Read a fixed-size array
+    pub fn read_array<const N: usize>(&mut self, what: &'static str) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N, what)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self, what: &'static str) -> Result<u8> {
+        Ok(self.read_array::<1>(what)?[0])
+    }
+
+    pub fn read_u16_le(&mut self, what: &'static str) -> Result<u16> {
+        self.read_array(what).map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32_le(&mut self, what: &'static str) -> Result<u32> {
+        self.read_array(what).map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64_le(&mut self, what: &'static str) -> Result<u64> {
+        self.read_array(what).map(u64::from_le_bytes)
+    }
+
+    pub fn read_f64_le(&mut self, what: &'static str) -> Result<f64> {
+        self.read_array(what).map(f64::from_le_bytes)
+    }
+
+    /// Read a LEB128 varint
+    pub fn read_varint(&mut self, what: &'static str) -> Result<u64> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8(what)?;
+            // The tenth byte holds only the top bit of a u64
+            if shift == 63 && byte & 0x7F > 1 {
+                return Err(Error::DecodeError(format!("{} varint overflows u64", what)));
+            }
+            result |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(Error::DecodeError(format!("{} varint too long", what)));
+            }
+        }
+    }
+
+    /// Read a varint length whose bytes must follow in the buffer
+    pub fn read_len(&mut self, what: &'static str) -> Result<usize> {
+        self.read_len_max(what, usize::MAX)
+    }
+
+    /// Read a varint length of at most `max` whose bytes must follow in the buffer
+    ///
+    /// Nothing is consumed on error.
+    pub fn read_len_max(&mut self, what: &'static str, max: usize) -> Result<usize> {
+        let start = self.pos;
+        let result = self.read_varint(what).and_then(|len| {
+            if len > max as u64 {
+                Err(Error::LengthLimitExceeded { len, max })
+            } else if len > self.remaining() as u64 {
+                Err(Error::Truncated {
+                    what,
+                    needed: usize::try_from(len).unwrap_or(usize::MAX),
+                    available: self.remaining(),
+                })
+            } else {
+                Ok(len as usize)
+            }
+        });
+        if result.is_err() {
+            self.pos = start;
+        }
+        result
+    }
+
+    /// Read a varint-length-prefixed byte string
+    pub fn read_len_bytes(&mut self, what: &'static str) -> Result<&'a [u8]> {
+        let len = self.read_len(what)?;
+        self.read_bytes(len, what)
+    }
+
+    /// Read a varint-length-prefixed UTF-8 string
+    pub fn read_str(&mut self, what: &'static str) -> Result<&'a str> {
+        let bytes = self.read_len_bytes(what)?;
+        std::str::from_utf8(bytes).map_err(|_| Error::DecodeError(format!("{} is not UTF-8", what)))
+    }
+}
+
+/// Read a varint length at `*pos`, capped at `max` and at the bytes left in `data`
+///
+/// Use for element counts where every element takes at least one byte;
+/// `pos` only advances on success.
+pub fn read_len(data: &[u8], pos: &mut usize, max: usize) -> Result<usize> {
+    let mut cursor = Cursor::at(data, *pos);
+    let len = cursor.read_len_max("length", max)?;
+    *pos = cursor.position();
+    Ok(len)
+}
+
+/// Read a varint-length-prefixed byte string of at most `max` bytes at `*pos`
+///
+/// The one place decoders turn an untrusted length into a slice: the length
+/// is checked against `max` (`Error::LengthLimitExceeded`) and against the
+/// bytes left in `data` (`Error::Truncated`) before anything is sliced or
+/// allocated. `pos` only advances on success.
+pub fn read_len_prefixed<'a>(data: &'a [u8], pos: &mut usize, max: usize) -> Result<&'a [u8]> {
+    let mut cursor = Cursor::at(data, *pos);
+    let len = cursor.read_len_max("length-prefixed field", max)?;
+    let bytes = cursor.read_bytes(len, "length-prefixed field")?;
+    *pos = cursor.position();
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_reads() {
+        let mut buf = vec![7, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12];
+        buf.extend_from_slice(&[0xAC, 0x02, 3, b'a', b'b', b'c']);
+        let mut cursor = Cursor::new(&buf);
+
+        assert_eq!(cursor.read_u8("tag").unwrap(), 7);
+        assert_eq!(cursor.read_u16_le("short").unwrap(), 0x1234);
+        assert_eq!(cursor.read_u32_le("word").unwrap(), 0x1234_5678);
+        assert_eq!(cursor.read_varint("varint").unwrap(), 300);
+        assert_eq!(cursor.read_str("name").unwrap(), "abc");
+        assert!(cursor.is_empty());
+        assert_eq!(cursor.position(), buf.len());
+    }
+
+    #[test]
+    fn test_cursor_truncation_errors() {
+        let mut cursor = Cursor::new(&[1, 2, 3]);
+        assert!(matches!(
+            cursor.read_u32_le("header"),
+            Err(Error::Truncated { what: "header", needed: 4, available: 3 })
+        ));
+        // A failed read consumes nothing
+        assert_eq!(cursor.position(), 0);
+
+        let mut cursor = Cursor::new(&[0x80, 0x80]);
+        assert!(matches!(cursor.read_varint("count"), Err(Error::Truncated { what: "count", .. })));
+
+        let mut cursor = Cursor::new(&[10, b'x']);
+        assert!(matches!(
+            cursor.read_len_bytes("key"),
+            Err(Error::Truncated { what: "key", needed: 10, available: 1 })
+        ));
+
+        let mut cursor = Cursor::new(&[0xFF; 11]);
+        assert!(matches!(cursor.read_varint("len"), Err(Error::DecodeError(_))));
+
+        let mut max = [0xFF; 10];
+        max[9] = 0x01;
+        assert_eq!(Cursor::new(&max).read_varint("len").unwrap(), u64::MAX);
+        max[9] = 0x02;
+        assert!(matches!(Cursor::new(&max).read_varint("len"), Err(Error::DecodeError(_))));
+    }
+
+    #[test]
+    fn test_read_len_prefixed() {
+        let data = [0xFF, 3, b'a', b'b', b'c', 5, b'x'];
+
+        let mut pos = 1;
+        assert_eq!(read_len_prefixed(&data, &mut pos, 3).unwrap(), b"abc");
+        assert_eq!(pos, 5);
+
+        // Over the caller's cap, then past the end of the data; neither advances
+        let mut pos = 1;
+        assert!(matches!(
+            read_len_prefixed(&data, &mut pos, 2),
+            Err(Error::LengthLimitExceeded { len: 3, max: 2 })
+        ));
+        let mut pos = 5;
+        assert!(matches!(
+            read_len_prefixed(&data, &mut pos, usize::MAX),
+            Err(Error::Truncated { needed: 5, available: 1, .. })
+        ));
+        assert_eq!(pos, 5);
+
+        // Huge lengths never reach an allocation
+        let mut huge = Vec::new();
+        crate::encoding::encode_varint(u64::MAX, &mut huge);
+        assert!(read_len(&huge, &mut 0, usize::MAX).is_err());
+        assert!(read_len_prefixed(&data, &mut 99, 1).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! JSON Merge Patch (RFC 7386)
+//!
+//! A merge patch mirrors the shape of the target document: members present
+//! in the patch replace (or recursively patch) the target's members, and
+//! `null` members delete them. Merge patches cannot set a value to `null`
+//! and replace arrays as a whole.
+
+use serde_json::{Map, Value};
+
+/// Build a merge patch that turns `old` into `new`
+pub fn create_merge_patch(old: &Value, new: &Value) -> Value {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut patch = Map::new();
+            for key in old.keys() {
+                if !new.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            for (key, value) in new {
+                match old.get(key) {
+                    Some(prev) if prev == value => {}
+                    Some(prev) if prev.is_object() && value.is_object() => {
+                        patch.insert(key.clone(), create_merge_patch(prev, value));
+                    }
+                    _ => {
+                        patch.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            Value::Object(patch)
+        }
+        _ => new.clone(),
+    }
+}
+
+/// Build a patch carrying all of `new`, deleting members of `old` it lacks
+///
+/// Unlike `create_merge_patch` the result does not depend on the receiver
+/// holding `old`, so it also recovers a receiver that missed patches.
+pub fn snapshot_merge_patch(old: Option<&Value>, new: &Value) -> Value {
+    match (old, new) {
+        (Some(Value::Object(old)), Value::Object(new)) => {
+            let mut patch = Map::new();
+            for key in old.keys() {
+                if !new.contains_key(key) {
+                    patch.insert(key.clone(), Value::Null);
+                }
+            }
+            for (key, value) in new {
+                patch.insert(key.clone(), snapshot_merge_patch(old.get(key), value));
+            }
+            Value::Object(patch)
+        }
+        _ => new.clone(),
+    }
+}
+
+/// Whether `value` has a `null` object member, which a merge patch can't
+/// carry (arrays are replaced whole, so nulls inside them are fine)
+pub(crate) fn has_null_member(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map.values().any(|v| v.is_null() || has_null_member(v)),
+        _ => false,
+    }
+}
+
+/// Apply a merge patch to `target` in place
+pub fn apply_merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rfc7386_examples() {
+        // Appendix A of RFC 7386
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "b"}), json!({"b": "c"}), json!({"a": "b", "b": "c"})),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (json!({"a": "b", "b": "c"}), json!({"a": null}), json!({"b": "c"})),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (json!({"a": {"b": "c"}}), json!({"a": {"b": "d", "c": null}}), json!({"a": {"b": "d"}})),
+            (json!({"a": [{"b": "c"}]}), json!({"a": [1]}), json!({"a": [1]})),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (json!({"e": null}), json!({"a": 1}), json!({"e": null, "a": 1})),
+            (json!([1, 2]), json!({"a": "b", "c": null}), json!({"a": "b"})),
+            (json!({}), json!({"a": {"bb": {"ccc": null}}}), json!({"a": {"bb": {}}})),
+        ];
+        for (target, patch, expected) in cases {
+            let mut result = target.clone();
+            apply_merge_patch(&mut result, &patch);
+            assert_eq!(result, expected, "{} + {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn test_create_merge_patch() {
+        let old = json!({"count": 1, "user": {"name": "alice", "role": "admin"}, "tags": ["a"]});
+        let new = json!({"count": 2, "user": {"name": "alice"}, "tags": ["a", "b"], "live": true});
+
+        let patch = create_merge_patch(&old, &new);
+        assert_eq!(
+            patch,
+            json!({"count": 2, "user": {"role": null}, "tags": ["a", "b"], "live": true})
+        );
+
+        let mut applied = old.clone();
+        apply_merge_patch(&mut applied, &patch);
+        assert_eq!(applied, new);
+
+        assert_eq!(create_merge_patch(&new, &new), json!({}));
+    }
+
+    #[test]
+    fn test_snapshot_merge_patch() {
+        let old = json!({"a": 1, "b": {"c": 2, "d": 3}});
+        let new = json!({"b": {"c": 2}, "e": 4});
+
+        let patch = snapshot_merge_patch(Some(&old), &new);
+        assert_eq!(patch, json!({"a": null, "b": {"c": 2, "d": null}, "e": 4}));
+
+        // Recovers a receiver that is behind
+        let mut stale = json!({"a": 0, "b": {"d": 0}});
+        apply_merge_patch(&mut stale, &patch);
+        assert_eq!(stale, new);
+
+        assert_eq!(snapshot_merge_patch(None, &new), new);
+    }
+
+    #[test]
+    fn test_has_null_member() {
+        assert!(has_null_member(&json!({"a": null})));
+        assert!(has_null_member(&json!({"a": {"b": null}})));
+        assert!(!has_null_member(&json!({"a": [null, {"b": null}]})));
+        assert!(!has_null_member(&json!({"a": 1})));
+    }
+}
@@ -2,6 +2,11 @@
 //!
 //! Efficiently transmit only changes between similar JSON states.
 
+pub mod merge_patch;
+
+pub use merge_patch::{apply_merge_patch, create_merge_patch, snapshot_merge_patch};
+
+use crate::cursor::{read_len, read_len_prefixed};
 use crate::{Error, Result};
 use serde::{Serialize, Deserialize};
 
@@ -20,6 +25,9 @@ pub enum DeltaOp {
     ArrayOps(Vec<ArrayOp>),
     /// Object field operations
     ObjectOps(Vec<ObjectOp>),
+    /// String edit: keep the first `prefix` and last `suffix` bytes of the
+    /// previous string, with `insert` between them
+    StringPatch { prefix: usize, suffix: usize, insert: String },
 }
 
 /// Array-specific delta operations
@@ -54,6 +62,9 @@ pub struct DeltaEncoder {
     prev_state: Option<serde_json::Value>,
     /// Schema hash for validation
     schema_hash: u64,
+    /// Shortest string sent as a `StringPatch` (0 = never)
+    string_diff: usize,
+    limits: DeltaLimits,
 }
 
 impl DeltaEncoder {
@@ -62,6 +73,8 @@ impl DeltaEncoder {
         Self {
             prev_state: None,
             schema_hash: 0,
+            string_diff: 0,
+            limits: DeltaLimits::default(),
         }
     }
 
@@ -71,11 +84,27 @@ impl DeltaEncoder {
         self
     }
 
+    /// Send edits to strings of at least `min_len` bytes as `StringPatch`
+    /// ops (0 = always send the whole string)
+    pub fn with_string_diff(mut self, min_len: usize) -> Self {
+        self.string_diff = min_len;
+        self
+    }
+
+    /// Bound the comparison of states (defaults to `DeltaLimits::default()`)
+    pub fn with_limits(mut self, limits: DeltaLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     /// Compute delta between previous and current state
+    ///
+    /// Fails with `Error::DeltaLimitExceeded` past a strict limit, keeping
+    /// the previous state.
     pub fn encode(&mut self, current: &serde_json::Value) -> Result<DeltaOp> {
         let delta = match &self.prev_state {
             None => DeltaOp::Add(current.clone()),
-            Some(prev) => compute_delta(prev, current),
+            Some(prev) => Diff::new(self.string_diff, self.limits).delta(prev, current, 0)?,
         };
 
         self.prev_state = Some(current.clone());
@@ -86,6 +115,37 @@ impl DeltaEncoder {
     pub fn reset(&mut self) {
         self.prev_state = None;
     }
+
+    /// Last sent state
+    pub(crate) fn state(&self) -> Option<&serde_json::Value> {
+        self.prev_state.as_ref()
+    }
+
+    /// Record `current` as the last sent state without computing a delta
+    pub(crate) fn replace_state(&mut self, current: serde_json::Value) -> Option<serde_json::Value> {
+        self.prev_state.replace(current)
+    }
+}
+
+/// Approximate bytes held by a JSON value, itself included
+///
+/// Strings count their capacity and object members a share for the map's
+/// bookkeeping.
+pub(crate) fn value_memory_usage(value: &serde_json::Value) -> usize {
+    const MEMBER_OVERHEAD: usize = size_of::<String>() + 16;
+    size_of::<serde_json::Value>()
+        + match value {
+            serde_json::Value::String(s) => s.capacity(),
+            serde_json::Value::Array(items) => {
+                let spare = (items.capacity() - items.len()) * size_of::<serde_json::Value>();
+                spare + items.iter().map(value_memory_usage).sum::<usize>()
+            }
+            serde_json::Value::Object(map) => map
+                .iter()
+                .map(|(key, value)| key.capacity() + MEMBER_OVERHEAD + value_memory_usage(value))
+                .sum(),
+            _ => 0,
+        }
 }
 
 impl Default for DeltaEncoder {
@@ -112,12 +172,13 @@ impl DeltaDecoder {
     pub fn decode(&mut self, delta: &DeltaOp) -> Result<serde_json::Value> {
         let new_state = match (&self.current_state, delta) {
             (_, DeltaOp::Add(v)) => v.clone(),
-            (None, _) => return Err(Error::DecodeError("No base state for delta".into())),
+            (None, op) => return Err(state_mismatch(op.base_kind(), "no state")),
             (Some(prev), DeltaOp::Unchanged) => prev.clone(),
             (Some(_), DeltaOp::Remove) => serde_json::Value::Null,
             (Some(_), DeltaOp::Modify(v)) => v.clone(),
             (Some(prev), DeltaOp::ArrayOps(ops)) => apply_array_ops(prev, ops)?,
             (Some(prev), DeltaOp::ObjectOps(ops)) => apply_object_ops(prev, ops)?,
+            (Some(prev), op @ DeltaOp::StringPatch { .. }) => apply_delta(prev, op)?,
         };
 
         self.current_state = Some(new_state.clone());
@@ -128,6 +189,16 @@ impl DeltaDecoder {
     pub fn reset(&mut self) {
         self.current_state = None;
     }
+
+    /// Current state
+    pub(crate) fn state(&self) -> Option<&serde_json::Value> {
+        self.current_state.as_ref()
+    }
+
+    /// Current state, for decoders that apply changes themselves
+    pub(crate) fn state_mut(&mut self) -> &mut Option<serde_json::Value> {
+        &mut self.current_state
+    }
 }
 
 impl Default for DeltaDecoder {
@@ -136,113 +207,227 @@ impl Default for DeltaDecoder {
     }
 }
 
-/// Compute delta between two JSON values
-fn compute_delta(prev: &serde_json::Value, current: &serde_json::Value) -> DeltaOp {
-    use serde_json::Value;
+/// Limits on the work `DeltaEncoder::encode` does comparing two states
+///
+/// Past a limit, the rest of the state is sent as whole values (`Modify`)
+/// instead of being compared further, or with `strict` the update fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaLimits {
+    /// Deepest object nesting compared member by member
+    pub max_depth: usize,
+    /// Most members and elements compared per update
+    pub max_nodes: usize,
+    /// Fail with `Error::DeltaLimitExceeded` instead of sending whole values
+    pub strict: bool,
+}
+
+impl Default for DeltaLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 128,
+            max_nodes: 1 << 20,
+            strict: false,
+        }
+    }
+}
+
+/// One comparison of two states
+struct Diff {
+    /// Shortest string patched (0 = never)
+    string_diff: usize,
+    limits: DeltaLimits,
+    /// Members and elements compared so far
+    nodes: usize,
+}
 
-    if prev == current {
-        return DeltaOp::Unchanged;
+impl Diff {
+    fn new(string_diff: usize, limits: DeltaLimits) -> Self {
+        Self { string_diff, limits, nodes: 0 }
     }
 
-    match (prev, current) {
-        (Value::Object(prev_obj), Value::Object(curr_obj)) => {
-            let mut ops = Vec::new();
-            let mut prev_keys: std::collections::HashSet<_> = prev_obj.keys().collect();
+    /// Whether comparing containers at `depth` is past a limit
+    fn exceeded(&self, depth: usize) -> Result<bool> {
+        let (what, max) = if depth > self.limits.max_depth {
+            ("depth", self.limits.max_depth)
+        } else if self.nodes > self.limits.max_nodes {
+            ("size", self.limits.max_nodes)
+        } else {
+            return Ok(false);
+        };
+        if self.limits.strict {
+            return Err(Error::DeltaLimitExceeded { what, max });
+        }
+        Ok(true)
+    }
 
-            // Check current fields
-            for (key, curr_val) in curr_obj {
-                match prev_obj.get(key) {
-                    None => {
-                        ops.push(ObjectOp::Add(key.clone(), curr_val.clone()));
-                    }
-                    Some(prev_val) => {
-                        prev_keys.remove(key);
-                        let field_delta = compute_delta(prev_val, curr_val);
-                        match field_delta {
-                            DeltaOp::Unchanged => ops.push(ObjectOp::Keep(key.clone())),
-                            _ => ops.push(ObjectOp::Modify(key.clone(), Box::new(field_delta))),
+    /// Compute delta between two JSON values nested `depth` objects deep
+    fn delta(&mut self, prev: &serde_json::Value, current: &serde_json::Value, depth: usize) -> Result<DeltaOp> {
+        use serde_json::Value;
+
+        if prev == current {
+            return Ok(DeltaOp::Unchanged);
+        }
+
+        Ok(match (prev, current) {
+            (Value::Object(_), Value::Object(_)) | (Value::Array(_), Value::Array(_)) if self.exceeded(depth)? => {
+                DeltaOp::Modify(current.clone())
+            }
+
+            (Value::Object(prev_obj), Value::Object(curr_obj)) => {
+                let mut ops = Vec::new();
+
+                // Check current fields
+                for (key, curr_val) in curr_obj {
+                    match prev_obj.get(key) {
+                        None => {
+                            ops.push(ObjectOp::Add(key.clone(), curr_val.clone()));
+                        }
+                        Some(prev_val) => {
+                            self.nodes += 1;
+                            let field_delta = self.delta(prev_val, curr_val, depth + 1)?;
+                            match field_delta {
+                                DeltaOp::Unchanged => ops.push(ObjectOp::Keep(key.clone())),
+                                _ => ops.push(ObjectOp::Modify(key.clone(), Box::new(field_delta))),
+                            }
                         }
                     }
                 }
-            }
-
-            // Check removed fields
-            for key in prev_keys {
-                ops.push(ObjectOp::Remove(key.clone()));
-            }
 
-            DeltaOp::ObjectOps(ops)
-        }
+                // Check removed fields, in their previous order so deltas are deterministic
+                for key in prev_obj.keys().filter(|key| !curr_obj.contains_key(*key)) {
+                    ops.push(ObjectOp::Remove(key.clone()));
+                }
 
-        (Value::Array(prev_arr), Value::Array(curr_arr)) => {
-            // Simple array delta - could use LCS for better compression
-            let mut ops = Vec::new();
-            let mut i = 0;
-            let mut j = 0;
+                DeltaOp::ObjectOps(ops)
+            }
 
-            while i < prev_arr.len() && j < curr_arr.len() {
-                if prev_arr[i] == curr_arr[j] {
-                    // Count consecutive keeps
-                    let mut keep_count = 1;
-                    i += 1;
-                    j += 1;
-                    while i < prev_arr.len() && j < curr_arr.len() && prev_arr[i] == curr_arr[j] {
-                        keep_count += 1;
+            (Value::Array(prev_arr), Value::Array(curr_arr)) => {
+                // Simple array delta - could use LCS for better compression
+                let mut ops = Vec::new();
+                let mut i = 0;
+                let mut j = 0;
+
+                while i < prev_arr.len() && j < curr_arr.len() {
+                    self.nodes += 1;
+                    if prev_arr[i] == curr_arr[j] {
+                        // Count consecutive keeps
+                        let mut keep_count = 1;
+                        i += 1;
+                        j += 1;
+                        while i < prev_arr.len() && j < curr_arr.len() && prev_arr[i] == curr_arr[j] {
+                            self.nodes += 1;
+                            keep_count += 1;
+                            i += 1;
+                            j += 1;
+                        }
+                        ops.push(ArrayOp::Keep(keep_count));
+                    } else {
+                        // Replace element
+                        ops.push(ArrayOp::Replace(curr_arr[j].clone()));
                         i += 1;
                         j += 1;
                     }
-                    ops.push(ArrayOp::Keep(keep_count));
-                } else {
-                    // Replace element
-                    ops.push(ArrayOp::Replace(curr_arr[j].clone()));
-                    i += 1;
-                    j += 1;
                 }
-            }
 
-            // Handle remaining elements
-            if i < prev_arr.len() {
-                ops.push(ArrayOp::Delete(prev_arr.len() - i));
+                // Handle remaining elements
+                if i < prev_arr.len() {
+                    ops.push(ArrayOp::Delete(prev_arr.len() - i));
+                }
+                if j < curr_arr.len() {
+                    ops.push(ArrayOp::Insert(curr_arr[j..].to_vec()));
+                }
+
+                DeltaOp::ArrayOps(ops)
             }
-            if j < curr_arr.len() {
-                ops.push(ArrayOp::Insert(curr_arr[j..].to_vec()));
+
+            (Value::String(prev_str), Value::String(curr_str)) if self.string_diff > 0 && curr_str.len() >= self.string_diff => {
+                diff_strings(prev_str, curr_str)
             }
 
-            DeltaOp::ArrayOps(ops)
+            _ => DeltaOp::Modify(current.clone()),
+        })
+    }
+}
+
+/// Patch turning `prev` into `current` by trimming their common prefix and
+/// suffix, or `Modify` when they share too little for a patch to pay off
+fn diff_strings(prev: &str, current: &str) -> DeltaOp {
+    // Cut at character boundaries; the bytes on the kept side are the same
+    // in both strings, so a boundary in one is a boundary in the other
+    let common = prev.bytes().zip(current.bytes()).take_while(|(a, b)| a == b).count();
+    let prefix = (0..=common).rev().find(|&n| current.is_char_boundary(n)).unwrap_or(0);
+    let max_suffix = prev.len().min(current.len()) - prefix;
+    let common = prev.bytes().rev().zip(current.bytes().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    let suffix = (0..=common).rev().find(|&n| current.is_char_boundary(current.len() - n)).unwrap_or(0);
+
+    // The varints of a patch cost a few bytes; keeping less saves nothing
+    if prefix + suffix < 8 {
+        return DeltaOp::Modify(serde_json::Value::String(current.to_string()));
+    }
+    DeltaOp::StringPatch { prefix, suffix, insert: current[prefix..current.len() - suffix].to_string() }
+}
+
+impl DeltaOp {
+    /// Kind of state the op applies to
+    fn base_kind(&self) -> &'static str {
+        match self {
+            DeltaOp::ArrayOps(_) => "array",
+            DeltaOp::ObjectOps(_) => "object",
+            DeltaOp::StringPatch { .. } => "string",
+            _ => "a previous state",
         }
+    }
+}
+
+/// Error for ops that don't fit the previous state
+fn state_mismatch(expected: impl Into<String>, got: impl Into<String>) -> Error {
+    Error::DeltaStateMismatch { expected: expected.into(), got: got.into() }
+}
 
-        _ => DeltaOp::Modify(current.clone()),
+fn kind_of(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
     }
 }
 
 /// Apply array operations to reconstruct value
 fn apply_array_ops(prev: &serde_json::Value, ops: &[ArrayOp]) -> Result<serde_json::Value> {
-    let prev_arr = prev.as_array().ok_or_else(|| {
-        Error::DecodeError("Expected array for ArrayOps".into())
-    })?;
+    let prev_arr = prev.as_array().ok_or_else(|| state_mismatch("array", kind_of(prev)))?;
 
     let mut result = Vec::new();
     let mut i = 0;
 
+    // Ops consuming elements past the end were computed against a longer array
+    let mut consume = |n: usize| {
+        let end = i + n;
+        if end > prev_arr.len() {
+            return Err(state_mismatch(format!("array of {}+ elements", end), format!("{} elements", prev_arr.len())));
+        }
+        let range = i..end;
+        i = end;
+        Ok(range)
+    };
+
     for op in ops {
         match op {
             ArrayOp::Keep(n) => {
-                for _ in 0..*n {
-                    if i < prev_arr.len() {
-                        result.push(prev_arr[i].clone());
-                        i += 1;
-                    }
-                }
+                let range = consume(*n)?;
+                result.extend_from_slice(&prev_arr[range]);
             }
             ArrayOp::Insert(values) => {
                 result.extend(values.iter().cloned());
             }
             ArrayOp::Delete(n) => {
-                i += n;
+                consume(*n)?;
             }
             ArrayOp::Replace(v) => {
+                consume(1)?;
                 result.push(v.clone());
-                i += 1;
             }
         }
     }
@@ -252,9 +437,7 @@ fn apply_array_ops(prev: &serde_json::Value, ops: &[ArrayOp]) -> Result<serde_js
 
 /// Apply object operations to reconstruct value
 fn apply_object_ops(prev: &serde_json::Value, ops: &[ObjectOp]) -> Result<serde_json::Value> {
-    let prev_obj = prev.as_object().ok_or_else(|| {
-        Error::DecodeError("Expected object for ObjectOps".into())
-    })?;
+    let prev_obj = prev.as_object().ok_or_else(|| state_mismatch("object", kind_of(prev)))?;
 
     let mut result = serde_json::Map::new();
 
@@ -272,10 +455,9 @@ fn apply_object_ops(prev: &serde_json::Value, ops: &[ObjectOp]) -> Result<serde_
                 // Don't include in result
             }
             ObjectOp::Modify(key, delta) => {
-                if let Some(prev_val) = prev_obj.get(key) {
-                    let new_val = apply_delta(prev_val, delta)?;
-                    result.insert(key.clone(), new_val);
-                }
+                let prev_val = prev_obj.get(key).ok_or_else(|| state_mismatch(format!("member `{}`", key), "none"))?;
+                let new_val = apply_delta(prev_val, delta)?;
+                result.insert(key.clone(), new_val);
             }
         }
     }
@@ -292,9 +474,28 @@ fn apply_delta(prev: &serde_json::Value, delta: &DeltaOp) -> Result<serde_json::
         DeltaOp::Modify(v) => Ok(v.clone()),
         DeltaOp::ArrayOps(ops) => apply_array_ops(prev, ops),
         DeltaOp::ObjectOps(ops) => apply_object_ops(prev, ops),
+        DeltaOp::StringPatch { prefix, suffix, insert } => apply_string_patch(prev, *prefix, *suffix, insert),
     }
 }
 
+/// Apply a `StringPatch` to reconstruct a string
+fn apply_string_patch(prev: &serde_json::Value, prefix: usize, suffix: usize, insert: &str) -> Result<serde_json::Value> {
+    let prev_str = prev.as_str().ok_or_else(|| state_mismatch("string", kind_of(prev)))?;
+    let kept = prefix.checked_add(suffix).filter(|&kept| kept <= prev_str.len());
+    let (Some(head), Some(tail)) = (
+        kept.and_then(|_| prev_str.get(..prefix)),
+        kept.and_then(|_| prev_str.get(prev_str.len() - suffix..)),
+    ) else {
+        return Err(state_mismatch(format!("string of {}+ bytes", prefix.saturating_add(suffix)), format!("{} bytes", prev_str.len())));
+    };
+
+    let mut result = String::with_capacity(prefix + insert.len() + suffix);
+    result.push_str(head);
+    result.push_str(insert);
+    result.push_str(tail);
+    Ok(serde_json::Value::String(result))
+}
+
 // Binary delta format tags
 const TAG_UNCHANGED: u8 = 0;
 const TAG_ADD: u8 = 1;
@@ -302,6 +503,7 @@ const TAG_REMOVE: u8 = 2;
 const TAG_MODIFY: u8 = 3;
 const TAG_ARRAY_OPS: u8 = 4;
 const TAG_OBJECT_OPS: u8 = 5;
+const TAG_STRING_PATCH: u8 = 6;
 
 // Array op tags
 const ARRAY_KEEP: u8 = 0;
@@ -323,9 +525,25 @@ pub fn serialize_delta(delta: &DeltaOp) -> Result<Vec<u8>> {
 }
 
 /// Deserialize delta from binary format
+///
+/// Fails with `Error::DepthLimitExceeded` for deltas nested deeper than
+/// `MAX_DELTA_DEPTH`.
 pub fn deserialize_delta(data: &[u8]) -> Result<DeltaOp> {
     let mut pos = 0;
-    decode_delta(data, &mut pos)
+    decode_delta(data, &mut pos, 0)
+}
+
+/// Deepest nesting of objects and arrays in a deserialized delta, the most
+/// `serde_json` parses, so that every state `update` accepts decodes
+pub const MAX_DELTA_DEPTH: usize = 128;
+
+/// Reject containers `depth` containers deep past `MAX_DELTA_DEPTH`, before
+/// recursing into them
+fn check_depth(depth: usize) -> Result<()> {
+    if depth >= MAX_DELTA_DEPTH {
+        return Err(Error::DepthLimitExceeded { max: MAX_DELTA_DEPTH });
+    }
+    Ok(())
 }
 
 fn encode_delta(delta: &DeltaOp, buf: &mut Vec<u8>) -> Result<()> {
@@ -358,11 +576,17 @@ fn encode_delta(delta: &DeltaOp, buf: &mut Vec<u8>) -> Result<()> {
                 encode_object_op(op, buf)?;
             }
         }
+        DeltaOp::StringPatch { prefix, suffix, insert } => {
+            buf.push(TAG_STRING_PATCH);
+            encode_varint(*prefix as u64, buf);
+            encode_varint(*suffix as u64, buf);
+            encode_string(insert, buf);
+        }
     }
     Ok(())
 }
 
-fn decode_delta(data: &[u8], pos: &mut usize) -> Result<DeltaOp> {
+fn decode_delta(data: &[u8], pos: &mut usize, depth: usize) -> Result<DeltaOp> {
     if *pos >= data.len() {
         return Err(Error::DecodeError("Unexpected end of delta data".into()));
     }
@@ -373,30 +597,38 @@ fn decode_delta(data: &[u8], pos: &mut usize) -> Result<DeltaOp> {
     match tag {
         TAG_UNCHANGED => Ok(DeltaOp::Unchanged),
         TAG_ADD => {
-            let value = decode_json_value(data, pos)?;
+            let value = decode_json_value(data, pos, depth)?;
             Ok(DeltaOp::Add(value))
         }
         TAG_REMOVE => Ok(DeltaOp::Remove),
         TAG_MODIFY => {
-            let value = decode_json_value(data, pos)?;
+            let value = decode_json_value(data, pos, depth)?;
             Ok(DeltaOp::Modify(value))
         }
         TAG_ARRAY_OPS => {
-            let count = decode_varint(data, pos)? as usize;
+            check_depth(depth)?;
+            let count = read_len(data, pos, usize::MAX)?;
             let mut ops = Vec::with_capacity(count);
             for _ in 0..count {
-                ops.push(decode_array_op(data, pos)?);
+                ops.push(decode_array_op(data, pos, depth + 1)?);
             }
             Ok(DeltaOp::ArrayOps(ops))
         }
         TAG_OBJECT_OPS => {
-            let count = decode_varint(data, pos)? as usize;
+            check_depth(depth)?;
+            let count = read_len(data, pos, usize::MAX)?;
             let mut ops = Vec::with_capacity(count);
             for _ in 0..count {
-                ops.push(decode_object_op(data, pos)?);
+                ops.push(decode_object_op(data, pos, depth + 1)?);
             }
             Ok(DeltaOp::ObjectOps(ops))
         }
+        TAG_STRING_PATCH => {
+            let prefix = decode_varint(data, pos)? as usize;
+            let suffix = decode_varint(data, pos)? as usize;
+            let insert = decode_string(data, pos)?;
+            Ok(DeltaOp::StringPatch { prefix, suffix, insert })
+        }
         _ => Err(Error::DecodeError(format!("Unknown delta tag: {}", tag))),
     }
 }
@@ -426,7 +658,7 @@ fn encode_array_op(op: &ArrayOp, buf: &mut Vec<u8>) -> Result<()> {
     Ok(())
 }
 
-fn decode_array_op(data: &[u8], pos: &mut usize) -> Result<ArrayOp> {
+fn decode_array_op(data: &[u8], pos: &mut usize, depth: usize) -> Result<ArrayOp> {
     if *pos >= data.len() {
         return Err(Error::DecodeError("Unexpected end of array op".into()));
     }
@@ -440,10 +672,10 @@ fn decode_array_op(data: &[u8], pos: &mut usize) -> Result<ArrayOp> {
             Ok(ArrayOp::Keep(n))
         }
         ARRAY_INSERT => {
-            let count = decode_varint(data, pos)? as usize;
+            let count = read_len(data, pos, usize::MAX)?;
             let mut values = Vec::with_capacity(count);
             for _ in 0..count {
-                values.push(decode_json_value(data, pos)?);
+                values.push(decode_json_value(data, pos, depth)?);
             }
             Ok(ArrayOp::Insert(values))
         }
@@ -452,7 +684,7 @@ fn decode_array_op(data: &[u8], pos: &mut usize) -> Result<ArrayOp> {
             Ok(ArrayOp::Delete(n))
         }
         ARRAY_REPLACE => {
-            let value = decode_json_value(data, pos)?;
+            let value = decode_json_value(data, pos, depth)?;
             Ok(ArrayOp::Replace(value))
         }
         _ => Err(Error::DecodeError(format!("Unknown array op tag: {}", tag))),
@@ -483,7 +715,7 @@ fn encode_object_op(op: &ObjectOp, buf: &mut Vec<u8>) -> Result<()> {
     Ok(())
 }
 
-fn decode_object_op(data: &[u8], pos: &mut usize) -> Result<ObjectOp> {
+fn decode_object_op(data: &[u8], pos: &mut usize, depth: usize) -> Result<ObjectOp> {
     if *pos >= data.len() {
         return Err(Error::DecodeError("Unexpected end of object op".into()));
     }
@@ -498,7 +730,7 @@ fn decode_object_op(data: &[u8], pos: &mut usize) -> Result<ObjectOp> {
         }
         OBJ_ADD => {
             let key = decode_string(data, pos)?;
-            let value = decode_json_value(data, pos)?;
+            let value = decode_json_value(data, pos, depth)?;
             Ok(ObjectOp::Add(key, value))
         }
         OBJ_REMOVE => {
@@ -507,7 +739,7 @@ fn decode_object_op(data: &[u8], pos: &mut usize) -> Result<ObjectOp> {
         }
         OBJ_MODIFY => {
             let key = decode_string(data, pos)?;
-            let delta = decode_delta(data, pos)?;
+            let delta = decode_delta(data, pos, depth)?;
             Ok(ObjectOp::Modify(key, Box::new(delta)))
         }
         _ => Err(Error::DecodeError(format!("Unknown object op tag: {}", tag))),
@@ -523,6 +755,8 @@ const JSON_NUMBER_FLOAT: u8 = 4;
 const JSON_STRING: u8 = 5;
 const JSON_ARRAY: u8 = 6;
 const JSON_OBJECT: u8 = 7;
+/// Integers above `i64::MAX`
+const JSON_NUMBER_UINT: u8 = 8;
 
 fn encode_json_value(value: &serde_json::Value, buf: &mut Vec<u8>) -> Result<()> {
     use serde_json::Value;
@@ -534,6 +768,9 @@ fn encode_json_value(value: &serde_json::Value, buf: &mut Vec<u8>) -> Result<()>
             if let Some(i) = n.as_i64() {
                 buf.push(JSON_NUMBER_INT);
                 encode_signed_varint(i, buf);
+            } else if let Some(u) = n.as_u64() {
+                buf.push(JSON_NUMBER_UINT);
+                encode_varint(u, buf);
             } else if let Some(f) = n.as_f64() {
                 buf.push(JSON_NUMBER_FLOAT);
                 buf.extend_from_slice(&f.to_le_bytes());
@@ -564,7 +801,7 @@ fn encode_json_value(value: &serde_json::Value, buf: &mut Vec<u8>) -> Result<()>
     Ok(())
 }
 
-fn decode_json_value(data: &[u8], pos: &mut usize) -> Result<serde_json::Value> {
+fn decode_json_value(data: &[u8], pos: &mut usize, depth: usize) -> Result<serde_json::Value> {
     use serde_json::Value;
 
     if *pos >= data.len() {
@@ -582,6 +819,7 @@ fn decode_json_value(data: &[u8], pos: &mut usize) -> Result<serde_json::Value>
             let i = decode_signed_varint(data, pos)?;
             Ok(Value::Number(i.into()))
         }
+        JSON_NUMBER_UINT => Ok(Value::Number(decode_varint(data, pos)?.into())),
         JSON_NUMBER_FLOAT => {
             if *pos + 8 > data.len() {
                 return Err(Error::DecodeError("Truncated float".into()));
@@ -600,19 +838,21 @@ fn decode_json_value(data: &[u8], pos: &mut usize) -> Result<serde_json::Value>
             Ok(Value::String(s))
         }
         JSON_ARRAY => {
-            let count = decode_varint(data, pos)? as usize;
+            check_depth(depth)?;
+            let count = read_len(data, pos, usize::MAX)?;
             let mut arr = Vec::with_capacity(count);
             for _ in 0..count {
-                arr.push(decode_json_value(data, pos)?);
+                arr.push(decode_json_value(data, pos, depth + 1)?);
             }
             Ok(Value::Array(arr))
         }
         JSON_OBJECT => {
-            let count = decode_varint(data, pos)? as usize;
+            check_depth(depth)?;
+            let count = read_len(data, pos, usize::MAX)?;
             let mut obj = serde_json::Map::with_capacity(count);
             for _ in 0..count {
                 let k = decode_string(data, pos)?;
-                let v = decode_json_value(data, pos)?;
+                let v = decode_json_value(data, pos, depth + 1)?;
                 obj.insert(k, v);
             }
             Ok(Value::Object(obj))
@@ -627,14 +867,8 @@ fn encode_string(s: &str, buf: &mut Vec<u8>) {
 }
 
 fn decode_string(data: &[u8], pos: &mut usize) -> Result<String> {
-    let len = decode_varint(data, pos)? as usize;
-    if *pos + len > data.len() {
-        return Err(Error::DecodeError("Truncated string".into()));
-    }
-    let s = String::from_utf8(data[*pos..*pos + len].to_vec())
-        .map_err(|_| Error::DecodeError("Invalid UTF-8".into()))?;
-    *pos += len;
-    Ok(s)
+    let bytes = read_len_prefixed(data, pos, usize::MAX)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::DecodeError("Invalid UTF-8".into()))
 }
 
 fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
@@ -655,6 +889,9 @@ fn decode_varint(data: &[u8], pos: &mut usize) -> Result<u64> {
         }
         let byte = data[*pos];
         *pos += 1;
+        if shift == 63 && byte & 0x7F > 1 {
+            return Err(Error::DecodeError("Varint overflows u64".into()));
+        }
         result |= ((byte & 0x7F) as u64) << shift;
         if byte & 0x80 == 0 {
             break;
@@ -684,12 +921,16 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    fn compute_delta(prev: &serde_json::Value, current: &serde_json::Value, string_diff: usize) -> DeltaOp {
+        Diff::new(string_diff, DeltaLimits::default()).delta(prev, current, 0).unwrap()
+    }
+
     #[test]
     fn test_unchanged() {
         let v1 = json!({"a": 1, "b": 2});
         let v2 = json!({"a": 1, "b": 2});
 
-        let delta = compute_delta(&v1, &v2);
+        let delta = compute_delta(&v1, &v2, 0);
         assert_eq!(delta, DeltaOp::Unchanged);
     }
 
@@ -698,7 +939,7 @@ mod tests {
         let v1 = json!({"a": 1, "b": 2});
         let v2 = json!({"a": 1, "b": 3});
 
-        let delta = compute_delta(&v1, &v2);
+        let delta = compute_delta(&v1, &v2, 0);
 
         match delta {
             DeltaOp::ObjectOps(ops) => {
@@ -714,7 +955,7 @@ mod tests {
         let v1 = json!({"a": 1, "b": 2});
         let v2 = json!({"a": 1, "c": 3});
 
-        let delta = compute_delta(&v1, &v2);
+        let delta = compute_delta(&v1, &v2, 0);
 
         match delta {
             DeltaOp::ObjectOps(ops) => {
@@ -725,6 +966,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_removals_are_deterministic() {
+        let prev: serde_json::Value = (0..32).map(|i| (format!("k{}", i), json!(i))).collect::<serde_json::Map<_, _>>().into();
+        let delta = compute_delta(&prev, &json!({"k5": 5}), 0);
+        let DeltaOp::ObjectOps(ops) = &delta else { panic!("Expected ObjectOps") };
+        let removed: Vec<&str> = ops
+            .iter()
+            .filter_map(|op| match op {
+                ObjectOp::Remove(k) => Some(k.as_str()),
+                _ => None,
+            })
+            .collect();
+        let expected: Vec<String> = (0..32).filter(|&i| i != 5).map(|i| format!("k{}", i)).collect();
+        assert_eq!(removed, expected);
+    }
+
     #[test]
     fn test_encoder_decoder_roundtrip() {
         let mut encoder = DeltaEncoder::new();
@@ -749,7 +1006,7 @@ mod tests {
         let v1 = json!([1, 2, 3, 4, 5]);
         let v2 = json!([1, 2, 99, 4, 5, 6]);
 
-        let delta = compute_delta(&v1, &v2);
+        let delta = compute_delta(&v1, &v2, 0);
 
         match delta {
             DeltaOp::ArrayOps(_) => {}
@@ -757,12 +1014,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_state_mismatch() {
+        let mismatch = |state: Option<serde_json::Value>, op: DeltaOp| {
+            let mut decoder = DeltaDecoder::new();
+            *decoder.state_mut() = state.clone();
+            match decoder.decode(&op) {
+                Err(Error::DeltaStateMismatch { expected, got }) => {
+                    assert_eq!(decoder.state(), state.as_ref());
+                    (expected, got)
+                }
+                other => panic!("expected a state mismatch, got {:?}", other),
+            }
+        };
+        let pair = |expected: &str, got: &str| (expected.to_string(), got.to_string());
+
+        assert_eq!(mismatch(None, DeltaOp::Unchanged), pair("a previous state", "no state"));
+        assert_eq!(mismatch(Some(json!({"a": 1})), DeltaOp::ArrayOps(vec![ArrayOp::Keep(1)])), pair("array", "object"));
+        assert_eq!(mismatch(Some(json!([1, 2])), DeltaOp::ArrayOps(vec![ArrayOp::Keep(1), ArrayOp::Delete(2)])), pair("array of 3+ elements", "2 elements"));
+        let modify = DeltaOp::ObjectOps(vec![ObjectOp::Modify("b".into(), Box::new(DeltaOp::Modify(json!(2))))]);
+        assert_eq!(mismatch(Some(json!({"a": 1})), modify), pair("member `b`", "none"));
+    }
+
+    #[test]
+    fn test_string_patch() {
+        let text = |edit: &str| format!("A long description of the item, {} with more after it", edit);
+        let mut encoder = DeltaEncoder::new().with_string_diff(32);
+        let mut decoder = DeltaDecoder::new();
+        decoder.decode(&encoder.encode(&json!({"description": text("née"), "id": 1})).unwrap()).unwrap();
+
+        for edit in ["nèe", "né", "ñée", "née, now edited"] {
+            let state = json!({"description": text(edit), "id": 1});
+            let delta = encoder.encode(&state).unwrap();
+            let DeltaOp::ObjectOps(ops) = &delta else { panic!("expected object ops, got {:?}", delta) };
+            assert!(matches!(&ops[0], ObjectOp::Modify(_, op) if matches!(**op, DeltaOp::StringPatch { .. })), "{:?}", ops);
+            let delta = deserialize_delta(&serialize_delta(&delta).unwrap()).unwrap();
+            assert_eq!(decoder.decode(&delta).unwrap(), state);
+        }
+
+        // Short strings and strings with little in common are resent whole
+        assert_eq!(compute_delta(&json!("short text"), &json!("short test"), 32), DeltaOp::Modify(json!("short test")));
+        let unrelated = "y".repeat(40);
+        assert_eq!(compute_delta(&json!("x".repeat(40)), &json!(unrelated), 32), DeltaOp::Modify(json!(unrelated)));
+
+        let patch = DeltaOp::StringPatch { prefix: 4, suffix: 4, insert: "-".into() };
+        let err = apply_delta(&json!("abcdefg"), &patch).unwrap_err();
+        assert!(matches!(err, Error::DeltaStateMismatch { expected, got } if expected == "string of 8+ bytes" && got == "7 bytes"));
+        assert!(apply_delta(&json!("aé"), &DeltaOp::StringPatch { prefix: 2, suffix: 0, insert: String::new() }).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let nested = |depth: usize, leaf: i32| (0..depth).fold(json!(leaf), |value, _| json!({"next": value, "tag": "x"}));
+        let roundtrip = |limits: DeltaLimits, prev: &serde_json::Value, current: &serde_json::Value| {
+            let mut encoder = DeltaEncoder::new().with_limits(limits);
+            let mut decoder = DeltaDecoder::new();
+            decoder.decode(&encoder.encode(prev).unwrap()).unwrap();
+            let delta = encoder.encode(current)?;
+            assert_eq!(&decoder.decode(&delta).unwrap(), current);
+            Ok::<_, Error>(serialize_delta(&delta).unwrap().len())
+        };
+
+        // Past the depth limit the changed subtree is sent whole
+        let (prev, current) = (nested(10, 1), nested(10, 2));
+        let deep = roundtrip(DeltaLimits::default(), &prev, &current).unwrap();
+        let shallow = roundtrip(DeltaLimits { max_depth: 3, ..Default::default() }, &prev, &current).unwrap();
+        assert!(deep < shallow, "{} vs {}", deep, shallow);
+
+        // Past the size limit, so are the remaining members
+        let members = |leaf: i32| json!({"a": {"k": leaf, "v": "same"}, "b": {"k": leaf, "v": "same"}, "c": {"k": leaf, "v": "same"}});
+        let full = roundtrip(DeltaLimits::default(), &members(1), &members(2)).unwrap();
+        let capped = roundtrip(DeltaLimits { max_nodes: 2, ..Default::default() }, &members(1), &members(2)).unwrap();
+        assert!(full < capped, "{} vs {}", full, capped);
+
+        let strict = DeltaLimits { max_depth: 3, strict: true, ..Default::default() };
+        let err = roundtrip(strict, &prev, &current).unwrap_err();
+        assert!(matches!(err, Error::DeltaLimitExceeded { what: "depth", max: 3 }));
+        let mut encoder = DeltaEncoder::new().with_limits(strict);
+        encoder.encode(&prev).unwrap();
+        assert!(encoder.encode(&current).is_err());
+        assert_eq!(encoder.state(), Some(&prev));
+        let strict = DeltaLimits { max_nodes: 2, strict: true, ..Default::default() };
+        assert!(matches!(roundtrip(strict, &members(1), &members(2)), Err(Error::DeltaLimitExceeded { what: "size", .. })));
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let nested = |depth: usize| (0..depth).fold(json!(1), |value, _| json!([value]));
+        let delta = DeltaOp::Add(nested(MAX_DELTA_DEPTH));
+        assert_eq!(deserialize_delta(&serialize_delta(&delta).unwrap()).unwrap(), delta);
+
+        // Crafted deltas nest far deeper than any encoder produces
+        let mut deep = vec![TAG_ADD];
+        deep.extend([JSON_ARRAY, 1].repeat(100_000));
+        assert!(matches!(deserialize_delta(&deep), Err(Error::DepthLimitExceeded { max: MAX_DELTA_DEPTH })));
+        let mut deep = Vec::new();
+        for _ in 0..100_000 {
+            deep.extend([TAG_OBJECT_OPS, 1, OBJ_MODIFY, 1, b'a']);
+        }
+        assert!(matches!(deserialize_delta(&deep), Err(Error::DepthLimitExceeded { .. })));
+    }
+
     #[test]
     fn test_serialize_deserialize_roundtrip() {
         let v1 = json!({"count": 0, "items": [1, 2, 3]});
         let v2 = json!({"count": 5, "items": [1, 2, 3, 4], "new": true});
 
-        let delta = compute_delta(&v1, &v2);
+        let delta = compute_delta(&v1, &v2, 0);
 
         let serialized = serialize_delta(&delta).unwrap();
         let deserialized = deserialize_delta(&serialized).unwrap();
@@ -798,7 +1156,7 @@ mod tests {
         });
 
         let full_json = serde_json::to_vec(&v2).unwrap();
-        let delta = compute_delta(&v1, &v2);
+        let delta = compute_delta(&v1, &v2, 0);
         let delta_bytes = serialize_delta(&delta).unwrap();
 
         // Delta should be much smaller than full JSON
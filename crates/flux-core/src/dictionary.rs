@@ -0,0 +1,348 @@
+//! Trained content dictionaries and zstd interop
+//!
+//! A `FluxDictionary` is a block of content that both sides know in advance:
+//! common keys and string values from sample messages, most valuable last.
+//!
+//! Dictionaries convert to and from zstd dictionaries, so an existing zstd
+//! training pipeline can feed FLUX and vice versa:
+//!
+//! - `from_zstd_dict` accepts both zstd formats. A formatted dictionary
+//!   (magic `0xEC30A437`) keeps its dictionary ID; its entropy tables are
+//!   skipped and only the content is kept.
+//! - `to_zstd_dict` exports a raw content dictionary, which zstd loads in
+//!   its default (auto) mode. Raw dictionaries carry no ID, so the ID has to
+//!   travel out of band.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let dict = FluxDictionary::train(&samples, 16 * 1024);
+//! std::fs::write("api.zdict", dict.to_zstd_dict())?;
+//!
+//! let imported = FluxDictionary::from_zstd_dict(&std::fs::read("trained.zdict")?)?;
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{Error, Result};
+
+/// Magic number of formatted zstd dictionaries
+pub const ZSTD_DICT_MAGIC: u32 = 0xEC30_A437;
+
+/// Dictionary IDs below this are reserved by zstd for registered dictionaries
+const MIN_TRAINED_ID: u32 = 1 << 15;
+
+/// Highest symbol value of each zstd FSE table (offsets, match lengths, literal lengths)
+const FSE_MAX_SYMBOLS: [usize; 3] = [31, 52, 35];
+
+/// Shared content dictionary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FluxDictionary {
+    id: u32,
+    content: Vec<u8>,
+}
+
+impl FluxDictionary {
+    /// Create a dictionary from raw content
+    pub fn new(id: u32, content: Vec<u8>) -> Self {
+        Self { id, content }
+    }
+
+    /// Train a dictionary of at most `max_size` bytes from sample messages
+    ///
+    /// Collects JSON string literals (keys and values) that occur in more
+    /// than one sample and keeps those that save the most bytes, placing the
+    /// most valuable ones at the end where references are shortest.
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Self {
+        let mut frequency: HashMap<&[u8], u32> = HashMap::new();
+        for sample in samples {
+            let tokens: HashSet<&[u8]> = string_literals(sample.as_ref()).collect();
+            for token in tokens {
+                *frequency.entry(token).or_insert(0) += 1;
+            }
+        }
+
+        let min_frequency = if samples.len() > 1 { 2 } else { 1 };
+        let mut ranked: Vec<(&[u8], u64)> = frequency
+            .into_iter()
+            .filter(|&(_, freq)| freq >= min_frequency)
+            .map(|(token, freq)| (token, freq as u64 * token.len() as u64))
+            .collect();
+        // Best first, ties broken by content for deterministic output
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        let mut selected = Vec::new();
+        let mut size = 0;
+        for (token, _) in ranked {
+            if size + token.len() <= max_size {
+                size += token.len();
+                selected.push(token);
+            }
+        }
+
+        let mut content = Vec::with_capacity(size);
+        for token in selected.iter().rev() {
+            content.extend_from_slice(token);
+        }
+
+        let id = crc32c::crc32c(&content).max(MIN_TRAINED_ID);
+        Self { id, content }
+    }
+
+    /// Dictionary ID
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Dictionary content
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Content size in bytes
+    pub fn len(&self) -> usize {
+        self.content.len()
+    }
+
+    /// Whether the dictionary has no content
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    /// Export as a zstd raw content dictionary
+    pub fn to_zstd_dict(&self) -> Vec<u8> {
+        self.content.clone()
+    }
+
+    /// Import a zstd dictionary, formatted or raw content
+    ///
+    /// Raw content dictionaries have no ID and are imported with ID 0.
+    pub fn from_zstd_dict(dict: &[u8]) -> Result<Self> {
+        if dict.len() < 8 || read_u32(dict, 0) != ZSTD_DICT_MAGIC {
+            return Ok(Self::new(0, dict.to_vec()));
+        }
+
+        let id = read_u32(dict, 4);
+        let mut pos = 8;
+
+        pos += huffman_table_size(&dict[pos..])?;
+        for max_symbol in FSE_MAX_SYMBOLS {
+            pos += fse_table_size(dict.get(pos..).unwrap_or_default(), max_symbol)?;
+        }
+        // Three repeat offsets
+        pos += 12;
+
+        let content = dict
+            .get(pos..)
+            .ok_or_else(|| Error::DecodeError("zstd dictionary truncated".into()))?;
+        Ok(Self::new(id, content.to_vec()))
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// JSON string literals in `data`, quotes included; keys keep their `:`
+fn string_literals(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let start = pos + data.get(pos..)?.iter().position(|&b| b == b'"')?;
+        let mut end = start + 1;
+        while end < data.len() && data[end] != b'"' {
+            end += if data[end] == b'\\' { 2 } else { 1 };
+        }
+        end = (end + 1).min(data.len());
+        if data.get(end) == Some(&b':') {
+            end += 1;
+        }
+        pos = end;
+        Some(&data[start..end])
+    })
+}
+
+/// Size of a Huffman tree description (RFC 8878 section 4.2.1)
+fn huffman_table_size(data: &[u8]) -> Result<usize> {
+    let header = *data
+        .first()
+        .ok_or_else(|| Error::DecodeError("zstd dictionary missing Huffman table".into()))? as usize;
+    let size = if header >= 128 {
+        // Direct representation: 4-bit weights
+        1 + (header - 127).div_ceil(2)
+    } else {
+        // FSE-compressed weights
+        1 + header
+    };
+    if size > data.len() {
+        return Err(Error::DecodeError("zstd dictionary Huffman table truncated".into()));
+    }
+    Ok(size)
+}
+
+/// Size of an FSE table description (RFC 8878 section 4.1.1)
+fn fse_table_size(data: &[u8], max_symbol: usize) -> Result<usize> {
+    let truncated = || Error::DecodeError("zstd dictionary FSE table truncated".into());
+
+    let mut bits = BitReader { data, pos: 0 };
+    let accuracy_log = bits.read(4)? + 5;
+    if accuracy_log > 9 {
+        return Err(Error::DecodeError(format!("zstd dictionary accuracy log {} too large", accuracy_log)));
+    }
+
+    let mut remaining = (1i32 << accuracy_log) + 1;
+    let mut threshold = 1i32 << accuracy_log;
+    let mut nb_bits = accuracy_log + 1;
+    let mut symbol = 0;
+    let mut previous_zero = false;
+
+    while remaining > 1 && symbol <= max_symbol {
+        if previous_zero {
+            // Runs of zero-probability symbols, 2 bits per repeat field
+            loop {
+                let repeat = bits.read(2)? as usize;
+                symbol += repeat;
+                if repeat != 3 {
+                    break;
+                }
+            }
+            if symbol > max_symbol {
+                break;
+            }
+        }
+
+        let max = 2 * threshold - 1 - remaining;
+        let low = bits.peek(nb_bits - 1) as i32;
+        let mut value;
+        if low < max {
+            value = low;
+            bits.pos += nb_bits as usize - 1;
+        } else {
+            value = bits.peek(nb_bits) as i32;
+            if value >= threshold {
+                value -= max;
+            }
+            bits.pos += nb_bits as usize;
+        }
+
+        let count = value - 1;
+        remaining -= count.abs();
+        symbol += 1;
+        previous_zero = count == 0;
+
+        while remaining < threshold && nb_bits > 1 {
+            nb_bits -= 1;
+            threshold >>= 1;
+        }
+    }
+
+    if remaining != 1 {
+        return Err(Error::DecodeError("zstd dictionary FSE table invalid".into()));
+    }
+    let size = bits.pos.div_ceil(8);
+    if size > data.len() {
+        return Err(truncated());
+    }
+    Ok(size)
+}
+
+/// Little-endian bit reader, reading zeros past the end
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn peek(&self, n: u32) -> u32 {
+        let mut value = 0;
+        for i in 0..n as usize {
+            let bit = self.pos + i;
+            let byte = self.data.get(bit / 8).copied().unwrap_or(0);
+            value |= (((byte >> (bit % 8)) & 1) as u32) << i;
+        }
+        value
+    }
+
+    fn read(&mut self, n: u32) -> Result<u32> {
+        if self.pos + n as usize > self.data.len() * 8 {
+            return Err(Error::DecodeError("zstd dictionary FSE table truncated".into()));
+        }
+        let value = self.peek(n);
+        self.pos += n as usize;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Vec<u8>> {
+        (0..200)
+            .map(|i| {
+                format!(
+                    r#"{{"id":{},"status":"{}","country":"{}","email":"user{}@example.com","plan":"enterprise"}}"#,
+                    i,
+                    ["ACTIVE", "SUSPENDED", "PENDING"][i % 3],
+                    ["US", "DE", "JP", "BR"][i % 4],
+                    i
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_train() {
+        let dict = FluxDictionary::train(&samples(), 256);
+        assert!(!dict.is_empty());
+        assert!(dict.len() <= 256);
+        assert!(dict.id() >= MIN_TRAINED_ID);
+
+        let content = String::from_utf8_lossy(dict.content());
+        assert!(content.contains(r#""status":"#));
+        assert!(content.contains(r#""enterprise""#));
+        // Unique per-message values are left out
+        assert!(!content.contains("user7@"));
+
+        assert_eq!(dict, FluxDictionary::train(&samples(), 256));
+    }
+
+    #[test]
+    fn test_zstd_raw_export_roundtrip() {
+        let dict = FluxDictionary::train(&samples(), 1024);
+        let zdict = dict.to_zstd_dict();
+
+        let message = &samples()[7];
+        let mut compressor = zstd::bulk::Compressor::with_dictionary(3, &zdict).unwrap();
+        let compressed = compressor.compress(message).unwrap();
+        let mut decompressor = zstd::bulk::Decompressor::with_dictionary(&zdict).unwrap();
+        assert_eq!(&decompressor.decompress(&compressed, message.len()).unwrap(), message);
+
+        let plain = zstd::bulk::compress(message, 3).unwrap();
+        assert!(compressed.len() < plain.len());
+
+        let imported = FluxDictionary::from_zstd_dict(&zdict).unwrap();
+        assert_eq!(imported.content(), dict.content());
+        assert_eq!(imported.id(), 0);
+    }
+
+    #[test]
+    fn test_zstd_formatted_import() {
+        let zdict = zstd::dict::from_samples(&samples(), 4096).unwrap();
+        let dict = FluxDictionary::from_zstd_dict(&zdict).unwrap();
+
+        let expected_id = zstd::zstd_safe::get_dict_id_from_dict(&zdict).unwrap().get();
+        assert_eq!(dict.id(), expected_id);
+        assert!(!dict.is_empty());
+        assert!(zdict.ends_with(dict.content()));
+        assert!(String::from_utf8_lossy(dict.content()).contains("status"));
+    }
+
+    #[test]
+    fn test_zstd_import_truncated() {
+        let zdict = zstd::dict::from_samples(&samples(), 4096).unwrap();
+        for len in [9, 12, 40] {
+            assert!(FluxDictionary::from_zstd_dict(&zdict[..len]).is_err());
+        }
+    }
+}
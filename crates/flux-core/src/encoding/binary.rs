@@ -0,0 +1,81 @@
+//! Binary encoding of base64 strings
+//!
+//! A `FieldType::Binary` value is a variant byte followed by length-prefixed
+//! bytes. Base64 strings are stored decoded, with the variant recording the
+//! alphabet and padding so decoding re-emits the exact original text;
+//! strings that turn out not to be base64 are stored as text.
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::engine::GeneralPurpose;
+use base64::Engine;
+
+use super::varint::encode_varint;
+use crate::{Error, Result};
+
+/// Shortest string treated as base64 during inference
+///
+/// Shorter strings are as likely to be ordinary words or ids.
+pub const MIN_BASE64_LEN: usize = 16;
+
+/// Variant byte for values stored as plain text
+pub const TEXT: u8 = 0x00;
+
+/// Base64 flavours, indexed by variant byte - 1
+///
+/// Decoding is strict (canonical padding and trailing bits), so a string
+/// that decodes re-encodes to itself.
+const ENGINES: [GeneralPurpose; 4] = [STANDARD, URL_SAFE, STANDARD_NO_PAD, URL_SAFE_NO_PAD];
+
+/// Decode `s` as base64, returning the variant byte and raw bytes
+pub fn detect(s: &str) -> Option<(u8, Vec<u8>)> {
+    ENGINES
+        .iter()
+        .enumerate()
+        .find_map(|(i, engine)| engine.decode(s).ok().map(|bytes| (i as u8 + 1, bytes)))
+}
+
+/// Encode a string as a binary value
+pub fn encode(s: &str, buf: &mut Vec<u8>) {
+    let (variant, bytes) = detect(s).unwrap_or_else(|| (TEXT, s.as_bytes().to_vec()));
+    buf.push(variant);
+    encode_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(&bytes);
+}
+
+/// Turn stored bytes back into the original string
+pub fn decode(variant: u8, bytes: &[u8]) -> Result<String> {
+    match variant {
+        TEXT => std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .map_err(|e| Error::DecodeError(e.to_string())),
+        _ => ENGINES
+            .get(variant as usize - 1)
+            .map(|engine| engine.encode(bytes))
+            .ok_or_else(|| Error::DecodeError(format!("Unknown binary variant {}", variant))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_variants_roundtrip() {
+        let cases = [
+            ("aGVsbG8gd29ybGQ=", 1),
+            ("-_8AAQ==", 2),
+            ("aGVsbG8gd29ybGQ", 3),
+            ("-_8AAQ", 4),
+            ("not base64!", TEXT),
+            // Non-canonical trailing bits would not re-encode to the same text
+            ("aGVsbG8gd29ybGR=", TEXT),
+        ];
+        for (s, expected) in cases {
+            let mut buf = Vec::new();
+            encode(s, &mut buf);
+            assert_eq!(buf[0], expected, "{}", s);
+            assert_eq!(decode(buf[0], &buf[2..]).unwrap(), s);
+        }
+        assert!(decode(9, b"").is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Float encoding strategies
+//!
+//! Gorilla XOR compression (Pelkonen et al., 2015): each value is XORed
+//! with the previous one, and only the bits that changed are stored.
+//! Consecutive readings from a sensor or metric share sign, exponent and
+//! high mantissa bits, so most values cost a few bits instead of eight
+//! bytes. Values that survive a round trip through `f32` can be
+//! compressed as 32-bit words, which shortens the XOR further.
+
+use crate::{Error, Result};
+
+/// Whether `value` is exactly representable as an `f32`
+pub fn fits_f32(value: f64) -> bool {
+    (value as f32 as f64).to_bits() == value.to_bits()
+}
+
+/// Append the Gorilla encoding of `values` to `buf`
+///
+/// The count is not stored; pass it to `gorilla_decode`.
+pub fn gorilla_encode(values: &[f64], buf: &mut Vec<u8>) {
+    encode_words(values.iter().map(|v| v.to_bits()), 64, buf);
+}
+
+/// Append the Gorilla encoding of `values` as 32-bit floats
+///
+/// Only exact if every value `fits_f32`.
+pub fn gorilla_encode_f32(values: &[f64], buf: &mut Vec<u8>) {
+    encode_words(values.iter().map(|&v| (v as f32).to_bits() as u64), 32, buf);
+}
+
+/// Decode `count` values written by `gorilla_encode`
+pub fn gorilla_decode(data: &[u8], count: usize) -> Result<Vec<f64>> {
+    decode_words(data, count, 64).map(|words| words.into_iter().map(f64::from_bits).collect())
+}
+
+/// Decode `count` values written by `gorilla_encode_f32`
+pub fn gorilla_decode_f32(data: &[u8], count: usize) -> Result<Vec<f64>> {
+    decode_words(data, count, 32).map(|words| words.into_iter().map(|w| f32::from_bits(w as u32) as f64).collect())
+}
+
+/// Bits used for the leading-zero count and the length of the changed bits
+fn field_bits(width: u32) -> u32 {
+    if width == 64 { 6 } else { 5 }
+}
+
+/// Control bits per value:
+/// - `0`: same as the previous value
+/// - `10`: changed bits fit the previous window; store them
+/// - `11`: new window (leading zeros, length - 1), then the changed bits
+fn encode_words(words: impl Iterator<Item = u64>, width: u32, buf: &mut Vec<u8>) {
+    let fields = field_bits(width);
+    let mut out = BitWriter::new(buf);
+    let mut prev: Option<u64> = None;
+    // (leading zeros, meaningful bits) of the current window
+    let mut window: Option<(u32, u32)> = None;
+
+    for word in words {
+        let Some(last) = prev.replace(word) else {
+            out.write(word, width);
+            continue;
+        };
+        let xor = last ^ word;
+        if xor == 0 {
+            out.write(0, 1);
+            continue;
+        }
+        let leading = xor.leading_zeros() - (64 - width);
+        let trailing = xor.trailing_zeros();
+        match window {
+            Some((lead, len)) if leading >= lead && trailing >= width - lead - len => {
+                out.write(0b10, 2);
+                out.write(xor >> (width - lead - len), len);
+            }
+            _ => {
+                let len = width - leading - trailing;
+                out.write(0b11, 2);
+                out.write(leading as u64, fields);
+                out.write((len - 1) as u64, fields);
+                out.write(xor >> trailing, len);
+                window = Some((leading, len));
+            }
+        }
+    }
+    out.finish();
+}
+
+fn decode_words(data: &[u8], count: usize, width: u32) -> Result<Vec<u64>> {
+    // Every value after the first costs at least one bit
+    let min_bits = ((count as u64).saturating_sub(1)).saturating_add(width as u64);
+    if count > 0 && min_bits > data.len() as u64 * 8 {
+        return Err(Error::Truncated {
+            what: "XOR float column",
+            needed: min_bits.div_ceil(8) as usize,
+            available: data.len(),
+        });
+    }
+
+    let fields = field_bits(width);
+    let mut bits = BitReader { data, pos: 0 };
+    let mut words = Vec::with_capacity(count);
+    let mut window: Option<(u32, u32)> = None;
+    for i in 0..count {
+        if i == 0 {
+            words.push(bits.read(width)?);
+            continue;
+        }
+        let prev = words[i - 1];
+        if bits.read(1)? == 0 {
+            words.push(prev);
+            continue;
+        }
+        if bits.read(1)? == 1 {
+            let lead = bits.read(fields)? as u32;
+            let len = bits.read(fields)? as u32 + 1;
+            if lead + len > width {
+                return Err(Error::DecodeError("XOR float window exceeds the value width".into()));
+            }
+            window = Some((lead, len));
+        }
+        let (lead, len) = window.ok_or_else(|| Error::DecodeError("XOR float value reuses a missing window".into()))?;
+        let xor = bits.read(len)? << (width - lead - len);
+        words.push(prev ^ xor);
+    }
+    Ok(words)
+}
+
+/// MSB-first bit writer appending to a byte buffer
+struct BitWriter<'a> {
+    buf: &'a mut Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buf: &'a mut Vec<u8>) -> Self {
+        Self { buf, acc: 0, len: 0 }
+    }
+
+    /// Write the low `n` bits of `value`, `n` at most 64
+    fn write(&mut self, value: u64, n: u32) {
+        if n > 32 {
+            self.write(value >> 32, n - 32);
+            self.write(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        self.acc = (self.acc << n) | (value & ((1 << n) - 1));
+        self.len += n;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.buf.push((self.acc >> self.len) as u8);
+        }
+    }
+
+    /// Flush the last partial byte, padded with zeros
+    fn finish(self) {
+        if self.len > 0 {
+            self.buf.push((self.acc << (8 - self.len)) as u8);
+        }
+    }
+}
+
+/// MSB-first bit reader
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    /// Read `n` bits, `n` at most 64
+    fn read(&mut self, n: u32) -> Result<u64> {
+        let end = self.pos + n as usize;
+        if end > self.data.len() * 8 {
+            return Err(Error::Truncated {
+                what: "XOR float column",
+                needed: end.div_ceil(8),
+                available: self.data.len(),
+            });
+        }
+        let mut value = 0u64;
+        for pos in self.pos..end {
+            value = (value << 1) | ((self.data[pos / 8] >> (7 - pos % 8)) & 1) as u64;
+        }
+        self.pos = end;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gorilla_roundtrip() {
+        let series: Vec<f64> = (0..500).map(|i| 20.0 + (i as f64 * 0.1).sin() * 3.0).collect();
+        let edge = [0.0, -0.0, f64::MAX, f64::MIN_POSITIVE, -1.5, -1.5, 1e-300, 12.25];
+        for values in [&series[..], &edge[..], &[][..], &[42.0][..]] {
+            let mut buf = Vec::new();
+            gorilla_encode(values, &mut buf);
+            let decoded = gorilla_decode(&buf, values.len()).unwrap();
+            assert_eq!(
+                decoded.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                values.iter().map(|v| v.to_bits()).collect::<Vec<_>>()
+            );
+        }
+
+        // A slowly changing series costs well under 8 bytes per value
+        let mut buf = Vec::new();
+        gorilla_encode(&[21.5; 100], &mut buf);
+        assert!(buf.len() < 30);
+    }
+
+    #[test]
+    fn test_gorilla_f32() {
+        let values = [0.5, 21.25, 21.75, -3.0, 1e10, 21.75];
+        assert!(values.iter().all(|&v| fits_f32(v)));
+        assert!(!fits_f32(0.1));
+
+        let (mut narrow, mut wide) = (Vec::new(), Vec::new());
+        gorilla_encode_f32(&values, &mut narrow);
+        gorilla_encode(&values, &mut wide);
+        assert!(narrow.len() < wide.len());
+        assert_eq!(gorilla_decode_f32(&narrow, values.len()).unwrap(), values);
+    }
+
+    #[test]
+    fn test_gorilla_truncated() {
+        let mut buf = Vec::new();
+        gorilla_encode(&[1.0, 2.0, 3.5, 3.5, 100.125], &mut buf);
+        for cut in 0..buf.len() {
+            assert!(gorilla_decode(&buf[..cut], 5).is_err(), "cut at {}", cut);
+        }
+        assert!(gorilla_decode(&buf, usize::MAX).is_err());
+    }
+}
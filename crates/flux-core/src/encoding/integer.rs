@@ -1,6 +1,7 @@
 //! Integer encoding strategies
 
-use super::varint::{encode_varint, decode_varint, encode_signed_varint};
+use super::varint::{encode_varint, encode_signed_varint};
+use crate::cursor::read_len;
 use crate::Result;
 
 /// Integer encoding strategy
@@ -86,14 +87,13 @@ pub fn encode_delta(values: &[i64], buf: &mut Vec<u8>) {
 pub fn decode_delta(buf: &[u8]) -> Result<Vec<i64>> {
     let mut pos = 0;
 
-    let (count, len) = decode_varint(buf)?;
-    pos += len;
+    let count = read_len(buf, &mut pos, usize::MAX)?;
 
     if count == 0 {
         return Ok(Vec::new());
     }
 
-    let mut values = Vec::with_capacity(count as usize);
+    let mut values = Vec::with_capacity(count);
 
     // Read first value
     let (first, len) = super::varint::decode_signed_varint(&buf[pos..])?;
@@ -105,7 +105,7 @@ pub fn decode_delta(buf: &[u8]) -> Result<Vec<i64>> {
     for _ in 1..count {
         let (delta, len) = super::varint::decode_signed_varint(&buf[pos..])?;
         pos += len;
-        prev += delta;
+        prev = prev.wrapping_add(delta);
         values.push(prev);
     }
 
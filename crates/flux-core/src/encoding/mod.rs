@@ -2,21 +2,31 @@
 
 pub mod varint;
 pub mod integer;
+pub mod float;
 pub mod string;
+pub mod binary;
+pub mod timestamp;
+pub mod value_dict;
 
 pub use varint::{encode_varint, decode_varint, zigzag_encode, zigzag_decode};
 
-use crate::{Error, Result};
+use crate::{Error, Result, Stage};
 use crate::types::{FieldType, IntegerType, FloatType};
-use crate::schema::Schema;
+use crate::schema::{FieldDef, RootKind, Schema};
+use crate::cursor::{read_len_prefixed, Cursor};
+use value_dict::ValueDictionary;
+
+/// Byte limit of value dictionaries when none is configured
+pub const DEFAULT_DICTIONARY_BYTES: usize = 65536;
 
 /// Main encoder that orchestrates type-specific encoders
-#[allow(dead_code)]
 pub struct Encoder {
-    /// String dictionary for key compression
-    key_dict: StringDictionary,
-    /// String dictionary for value compression
-    value_dict: StringDictionary,
+    /// Dictionary of strings this side sends (`None`: strings are written inline)
+    outgoing: Option<ValueDictionary>,
+    /// Dictionary built from the peer's updates
+    incoming: ValueDictionary,
+    /// Strings in the payload being decoded are dictionary-coded
+    incoming_coded: bool,
 }
 
 /// String dictionary for compression
@@ -48,6 +58,18 @@ impl StringDictionary {
         self.entries.get(id as usize).map(|s| s.as_str())
     }
 
+    /// Look up the id of an entry without adding it
+    pub fn id(&self, s: &str) -> Option<u32> {
+        self.index.get(s).copied()
+    }
+
+    /// Keep only the first `len` entries
+    pub fn truncate(&mut self, len: usize) {
+        for entry in self.entries.drain(len.min(self.entries.len())..) {
+            self.index.remove(&entry);
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.entries.len()
     }
@@ -65,69 +87,233 @@ impl Default for StringDictionary {
 
 impl Encoder {
     pub fn new() -> Self {
+        Self::with_value_dictionary(false, DEFAULT_DICTIONARY_BYTES)
+    }
+
+    /// Create an encoder that dictionary-codes outgoing strings if `enabled`
+    ///
+    /// `max_bytes` bounds the strings held by both the outgoing dictionary
+    /// and the one built from the peer's updates.
+    pub fn with_value_dictionary(enabled: bool, max_bytes: usize) -> Self {
         Self {
-            key_dict: StringDictionary::new(),
-            value_dict: StringDictionary::new(),
+            outgoing: enabled.then(|| ValueDictionary::new(max_bytes)),
+            incoming: ValueDictionary::new(max_bytes),
+            incoming_coded: false,
         }
     }
 
     /// Encode a JSON value according to schema
     pub fn encode(&mut self, value: &serde_json::Value, schema: &Schema) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
-        self.encode_with_schema(value, schema, &mut buf)?;
+        self.encode_into(value, schema, &mut buf)?;
         Ok(buf)
     }
 
+    /// Encode a JSON value according to schema, appending to `buf`
+    ///
+    /// Dictionary entries left uncommitted by an earlier call are dropped.
+    pub fn encode_into(&mut self, value: &serde_json::Value, schema: &Schema, buf: &mut Vec<u8>) -> Result<()> {
+        if let Some(dict) = &mut self.outgoing {
+            dict.rollback();
+        }
+        self.encode_with_schema(value, schema, buf)
+    }
+
+    /// Approximate bytes held by the value dictionaries
+    pub fn memory_usage(&self) -> usize {
+        self.outgoing.as_ref().map_or(0, ValueDictionary::memory_usage) + self.incoming.memory_usage()
+    }
+
+    /// Dictionary section for the last encoded payload
+    ///
+    /// `None` unless outgoing strings are dictionary-coded; the frame must
+    /// then carry the section (even with no new entries) and, once written,
+    /// the update is made permanent with `commit_value_dictionary`.
+    pub fn value_dictionary_update(&self) -> Option<Vec<u8>> {
+        let mut section = Vec::new();
+        self.write_value_dictionary_update(&mut section).then_some(section)
+    }
+
+    /// Append the dictionary section for the last encoded payload to `buf`
+    ///
+    /// Returns whether the frame carries a section, as
+    /// `value_dictionary_update` does.
+    pub fn write_value_dictionary_update(&self, buf: &mut Vec<u8>) -> bool {
+        match &self.outgoing {
+            Some(dict) => {
+                dict.write_update(buf);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Mark the entries of the last update as sent
+    pub fn commit_value_dictionary(&mut self) {
+        if let Some(dict) = &mut self.outgoing {
+            dict.commit();
+        }
+    }
+
+    /// Append the committed entries of both dictionaries, for a session snapshot
+    pub(crate) fn write_dictionaries(&self, buf: &mut Vec<u8>) {
+        let mut outgoing = Vec::new();
+        if let Some(dict) = &self.outgoing {
+            dict.write_snapshot(&mut outgoing);
+        }
+        let mut incoming = Vec::new();
+        self.incoming.write_snapshot(&mut incoming);
+        for section in [outgoing, incoming] {
+            encode_varint(section.len() as u64, buf);
+            buf.extend_from_slice(&section);
+        }
+    }
+
+    /// Restore dictionaries written by `write_dictionaries` into a fresh encoder
+    ///
+    /// Outgoing entries are dropped if this encoder writes strings inline.
+    pub(crate) fn read_dictionaries(&mut self, cursor: &mut Cursor<'_>) -> Result<()> {
+        let outgoing = cursor.read_len_bytes("outgoing dictionary")?;
+        let incoming = cursor.read_len_bytes("incoming dictionary")?;
+        if let Some(dict) = &mut self.outgoing {
+            dict.apply_update(outgoing)?;
+        }
+        self.incoming.apply_update(incoming)
+    }
+
+    /// Prepare to decode a payload, applying its frame's dictionary section
+    ///
+    /// Pass `None` for frames without one, whose strings are written inline.
+    pub fn load_value_dictionary(&mut self, section: Option<&[u8]>) -> Result<()> {
+        self.incoming_coded = false;
+        if let Some(section) = section {
+            self.incoming.apply_update(section)?;
+            self.incoming_coded = true;
+        }
+        Ok(())
+    }
+
+    /// Entries in the outgoing and incoming dictionaries
+    pub fn value_dictionary_len(&self) -> (usize, usize) {
+        (self.outgoing.as_ref().map_or(0, ValueDictionary::len), self.incoming.len())
+    }
+
     /// Decode data according to schema
     pub fn decode(&self, data: &[u8], schema: &Schema) -> Result<serde_json::Value> {
+        self.decode_with_limit(data, schema, usize::MAX)
+    }
+
+    /// Decode data according to schema, bounding the size of the result
+    ///
+    /// Array elements and string bytes are charged against `limit`, so an
+    /// array header claiming billions of zero-width elements fails with
+    /// `Error::OutputLimitExceeded` instead of exhausting memory.
+    pub fn decode_with_limit(&self, data: &[u8], schema: &Schema, limit: usize) -> Result<serde_json::Value> {
+        let mut pos = 0;
+        let mut budget = OutputBudget { limit, remaining: limit };
+        self.decode_with_schema(data, &mut pos, schema, &mut budget, None)
+    }
+
+    /// Decode only the top-level `fields` of the record, bounding the size of the result
+    ///
+    /// Other fields are skipped without being materialized, and decoding
+    /// stops once the last requested field has been read. Requested fields
+    /// missing from the record are left out of the result.
+    pub fn decode_projected(
+        &self,
+        data: &[u8],
+        schema: &Schema,
+        fields: &[&str],
+        limit: usize,
+    ) -> Result<serde_json::Value> {
         let mut pos = 0;
-        self.decode_with_schema(data, &mut pos, schema)
+        let mut budget = OutputBudget { limit, remaining: limit };
+        self.decode_with_schema(data, &mut pos, schema, &mut budget, Some(fields))
     }
 
     /// Encode value using schema for type information
+    ///
+    /// Array roots are a count followed by the elements: whole records for
+    /// `RootKind::Records`, bare values (with a presence byte if nullable)
+    /// for `RootKind::Values`.
     fn encode_with_schema(
         &mut self,
         value: &serde_json::Value,
         schema: &Schema,
         buf: &mut Vec<u8>,
     ) -> Result<()> {
-        match value {
-            serde_json::Value::Object(obj) => {
-                // Encode fields in schema order (eliminates key storage!)
-                for field in &schema.fields {
-                    if let Some(field_value) = obj.get(&field.name) {
-                        // Field present
-                        if field.nullable {
-                            buf.push(0x01); // Present flag
-                        }
-                        self.encode_typed_value(field_value, &field.field_type, buf)?;
-                    } else {
-                        // Field absent (must be nullable)
-                        if field.nullable {
-                            buf.push(0x00); // Absent flag
-                        } else {
-                            return Err(Error::EncodeError(format!(
-                                "Required field '{}' missing", field.name
-                            )));
-                        }
-                    }
+        match (schema.root, value) {
+            (RootKind::Object, serde_json::Value::Object(obj)) => {
+                self.encode_record(obj, &schema.fields, buf)?;
+            }
+            (RootKind::Records, serde_json::Value::Array(arr)) => {
+                encode_varint(arr.len() as u64, buf);
+                for item in arr {
+                    let obj = item
+                        .as_object()
+                        .ok_or_else(|| Error::EncodeError("Record array element is not an object".into()))?;
+                    self.encode_record(obj, &schema.fields, buf)?;
                 }
             }
-            serde_json::Value::Array(arr) => {
-                // For array at root level
+            (RootKind::Values, serde_json::Value::Array(arr)) => {
+                let field = root_value_field(schema)?;
                 encode_varint(arr.len() as u64, buf);
                 for item in arr {
-                    self.encode_with_schema(item, schema, buf)?;
+                    let item = Some(item).filter(|v| !v.is_null());
+                    self.encode_field(item, field, buf)?;
                 }
             }
-            _ => {
-                // Single value at root (unusual for JSON APIs)
-                self.encode_typed_value(value, &FieldType::infer(value), buf)?;
+            (root, _) => {
+                return Err(Error::EncodeError(format!("Value does not match {:?} schema root", root)));
             }
         }
         Ok(())
     }
 
+    /// Encode the members of an object in schema order (eliminates key storage!)
+    fn encode_record(
+        &mut self,
+        obj: &serde_json::Map<String, serde_json::Value>,
+        fields: &[FieldDef],
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        let mut matched = 0;
+        for field in fields {
+            let value = obj.get(&field.name);
+            matched += value.is_some() as usize;
+            self.encode_field(value, field, buf)?;
+        }
+        // Members without a field would be silently dropped
+        if matched < obj.len() {
+            return Err(unknown_member(obj, fields.iter().map(|field| &field.name)));
+        }
+        Ok(())
+    }
+
+    /// Encode one field, preceded by a presence byte if it is nullable
+    fn encode_field(
+        &mut self,
+        value: Option<&serde_json::Value>,
+        field: &FieldDef,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        if let Some(field_value) = value {
+            // Field present
+            if field.nullable {
+                buf.push(0x01); // Present flag
+            }
+            self.encode_typed_value(field_value, &field.field_type, buf)
+        } else if field.nullable {
+            // Field absent
+            buf.push(0x00); // Absent flag
+            Ok(())
+        } else {
+            Err(Error::EncodeError(format!(
+                "Required field '{}' missing", field.name
+            )))
+        }
+    }
+
     /// Encode a value using its type information
     fn encode_typed_value(
         &mut self,
@@ -136,6 +322,18 @@ impl Encoder {
         buf: &mut Vec<u8>,
     ) -> Result<()> {
         match (value, field_type) {
+            // Tag byte naming the variant, then the value as that variant
+            (_, FieldType::Union(types)) => {
+                let idx = union_variant(value, types).ok_or_else(|| {
+                    Error::EncodeError(format!("Value {} matches no variant of {:?}", value, types))
+                })?;
+                buf.push(idx as u8);
+                self.encode_typed_value(value, &types[idx], buf)?;
+            }
+
+            // Null-typed values carry no data
+            (serde_json::Value::Null, FieldType::Null) => {}
+
             (serde_json::Value::Null, _) => {
                 // Null is encoded as absence for nullable fields
                 // If we get here, encode as 0
@@ -147,7 +345,11 @@ impl Encoder {
             }
 
             (serde_json::Value::Number(n), FieldType::Integer(int_type)) => {
-                let i = n.as_i64().unwrap_or(0);
+                // Truncating would corrupt the value without a trace
+                if !int_fits(n, int_type) {
+                    return Err(Error::EncodeError(format!("Number {} does not fit {:?}", n, int_type)));
+                }
+                let i = n.as_i64().unwrap_or_default();
                 match int_type {
                     IntegerType::Int8 => buf.push(i as u8),
                     IntegerType::Int16 => buf.extend_from_slice(&(i as i16).to_le_bytes()),
@@ -157,6 +359,7 @@ impl Encoder {
                         let encoded = zigzag_encode(i);
                         encode_varint(encoded, buf);
                     }
+                    IntegerType::UInt64 => encode_varint(n.as_u64().unwrap_or_default(), buf),
                 }
             }
 
@@ -168,37 +371,29 @@ impl Encoder {
                 }
             }
 
-            (serde_json::Value::String(s), FieldType::String) => {
-                encode_varint(s.len() as u64, buf);
-                buf.extend_from_slice(s.as_bytes());
-            }
-
-            (serde_json::Value::String(s), FieldType::Timestamp) => {
-                // Parse ISO 8601 timestamp to epoch milliseconds (8 bytes)
-                if let Some(millis) = parse_iso8601_to_millis(s) {
-                    buf.push(0x01); // Binary timestamp flag
-                    buf.extend_from_slice(&millis.to_le_bytes());
-                } else {
-                    // Fallback to string storage
-                    buf.push(0x00); // String flag
+            (serde_json::Value::String(s), FieldType::String) => match &mut self.outgoing {
+                Some(dict) => dict.encode(s, buf),
+                None => {
                     encode_varint(s.len() as u64, buf);
                     buf.extend_from_slice(s.as_bytes());
                 }
+            },
+
+            (serde_json::Value::String(s), FieldType::Timestamp) => {
+                timestamp::encode(s, buf);
             }
 
             (serde_json::Value::String(s), FieldType::Uuid) => {
-                // Store as 16 bytes if valid UUID, otherwise as string
-                if s.len() == 36 {
-                    // Parse UUID to bytes
-                    let hex: String = s.chars().filter(|c| *c != '-').collect();
-                    if hex.len() == 32 {
-                        if let Ok(bytes) = hex::decode(&hex) {
-                            buf.extend_from_slice(&bytes);
-                            return Ok(());
-                        }
-                    }
-                }
-                // Fallback to string encoding
+                // The decoder always reads 16 bytes; other text needs a
+                // `Uuid | String` union (see `Schema::with_uuid_text`)
+                let bytes = parse_uuid(s).ok_or_else(|| Error::EncodeError(format!("'{}' is not a lowercase UUID", s)))?;
+                buf.extend_from_slice(&bytes);
+            }
+
+            (serde_json::Value::String(s), FieldType::Binary) => binary::encode(s, buf),
+
+            // Decimals are stored as text for now
+            (serde_json::Value::String(s), FieldType::Decimal { .. }) => {
                 encode_varint(s.len() as u64, buf);
                 buf.extend_from_slice(s.as_bytes());
             }
@@ -212,92 +407,149 @@ impl Encoder {
 
             (serde_json::Value::Object(obj), FieldType::Object(fields)) => {
                 // Encode in field order
+                let mut matched = 0;
                 for (name, ftype) in fields {
-                    if let Some(v) = obj.get(name) {
-                        self.encode_typed_value(v, ftype, buf)?;
-                    } else {
-                        // Missing field - encode null
-                        buf.push(0x00);
+                    let v = obj.get(name);
+                    matched += v.is_some() as usize;
+                    match (v, ftype) {
+                        // Members missing from some objects are unions, so absence gets its own tag
+                        (None, FieldType::Union(_)) => buf.push(ABSENT_MEMBER),
+                        (v, ftype) => self.encode_typed_value(v.unwrap_or(&serde_json::Value::Null), ftype, buf)?,
                     }
                 }
+                if matched < obj.len() {
+                    return Err(unknown_member(obj, fields.iter().map(|(name, _)| name)));
+                }
             }
 
-            // Fallback: use generic encoding
+            // Inferred schemas always match; explicit ones may not
             _ => {
-                self.encode_generic(value, buf)?;
+                return Err(Error::EncodeError(format!("Value {} does not match {:?}", value, field_type)));
             }
         }
         Ok(())
     }
 
-    /// Generic encoding when type doesn't match schema
-    fn encode_generic(&mut self, value: &serde_json::Value, buf: &mut Vec<u8>) -> Result<()> {
-        match value {
-            serde_json::Value::Null => buf.push(0x00),
-            serde_json::Value::Bool(b) => buf.push(if *b { 0x01 } else { 0x00 }),
-            serde_json::Value::Number(n) => {
-                if let Some(i) = n.as_i64() {
-                    encode_varint(zigzag_encode(i), buf);
-                } else if let Some(f) = n.as_f64() {
-                    buf.extend_from_slice(&f.to_le_bytes());
-                }
-            }
-            serde_json::Value::String(s) => {
-                encode_varint(s.len() as u64, buf);
-                buf.extend_from_slice(s.as_bytes());
-            }
-            serde_json::Value::Array(arr) => {
-                encode_varint(arr.len() as u64, buf);
-                for item in arr {
-                    self.encode_generic(item, buf)?;
+    /// Decode value using schema
+    fn decode_with_schema(
+        &self,
+        data: &[u8],
+        pos: &mut usize,
+        schema: &Schema,
+        budget: &mut OutputBudget,
+        projection: Option<&[&str]>,
+    ) -> Result<serde_json::Value> {
+        match schema.root {
+            RootKind::Object => self.decode_record(data, pos, &schema.fields, budget, projection, true),
+            RootKind::Records => {
+                let count = read_root_count(data, pos, budget)?;
+                let mut arr = Vec::with_capacity(count.min(data.len() - *pos));
+                for _ in 0..count {
+                    // Projection applies to every record; skip the rest of each
+                    arr.push(self.decode_record(data, pos, &schema.fields, budget, projection, false)?);
                 }
+                Ok(serde_json::Value::Array(arr))
             }
-            serde_json::Value::Object(obj) => {
-                encode_varint(obj.len() as u64, buf);
-                for (key, val) in obj {
-                    encode_varint(key.len() as u64, buf);
-                    buf.extend_from_slice(key.as_bytes());
-                    self.encode_generic(val, buf)?;
+            RootKind::Values => {
+                let field = root_value_field(schema)?;
+                let count = read_root_count(data, pos, budget)?;
+                let mut arr = Vec::with_capacity(count.min(data.len() - *pos));
+                for _ in 0..count {
+                    let value = self
+                        .decode_field(data, pos, field, budget, true)?
+                        .unwrap_or(serde_json::Value::Null);
+                    arr.push(value);
                 }
+                Ok(serde_json::Value::Array(arr))
             }
         }
-        Ok(())
     }
 
-    /// Decode value using schema
-    fn decode_with_schema(
+    /// Decode the members of an object
+    ///
+    /// With `stop_early`, decoding ends as soon as the last projected field
+    /// has been read instead of skipping to the end of the record.
+    fn decode_record(
         &self,
         data: &[u8],
         pos: &mut usize,
-        schema: &Schema,
+        fields: &[FieldDef],
+        budget: &mut OutputBudget,
+        projection: Option<&[&str]>,
+        stop_early: bool,
     ) -> Result<serde_json::Value> {
         let mut obj = serde_json::Map::new();
+        let wanted = |name: &str| projection.is_none_or(|fields| fields.contains(&name));
+        let mut pending = fields.iter().filter(|field| wanted(&field.name)).count();
 
-        for field in &schema.fields {
-            if field.nullable {
-                if *pos >= data.len() {
-                    return Err(Error::DecodeError("Unexpected end of data".into()));
-                }
-                let present = data[*pos];
-                *pos += 1;
-                if present == 0x00 {
-                    continue; // Field absent
-                }
+        for field in fields {
+            if pending == 0 && stop_early {
+                break;
+            }
+            let keep = wanted(&field.name);
+            if keep {
+                pending -= 1;
             }
 
-            let value = self.decode_typed_value(data, pos, &field.field_type)?;
-            obj.insert(field.name.clone(), value);
+            if let Some(value) = self.decode_field(data, pos, field, budget, keep)? {
+                obj.insert(field.name.clone(), value);
+            }
         }
 
         Ok(serde_json::Value::Object(obj))
     }
 
+    /// Decode one field, or skip over it unless `keep`
+    ///
+    /// Returns `None` for absent nullable fields and skipped ones. Errors
+    /// record the offset the field starts at.
+    fn decode_field(
+        &self,
+        data: &[u8],
+        pos: &mut usize,
+        field: &FieldDef,
+        budget: &mut OutputBudget,
+        keep: bool,
+    ) -> Result<Option<serde_json::Value>> {
+        let start = *pos;
+        self.decode_field_at(data, pos, field, budget, keep)
+            .map_err(|e| e.in_stage(Stage::Encoding, Some(start)))
+    }
+
+    fn decode_field_at(
+        &self,
+        data: &[u8],
+        pos: &mut usize,
+        field: &FieldDef,
+        budget: &mut OutputBudget,
+        keep: bool,
+    ) -> Result<Option<serde_json::Value>> {
+        if field.nullable {
+            if *pos >= data.len() {
+                return Err(Error::DecodeError("Unexpected end of data".into()));
+            }
+            let present = data[*pos];
+            *pos += 1;
+            if present == 0x00 {
+                return Ok(None); // Field absent
+            }
+        }
+
+        if keep {
+            self.decode_typed_value(data, pos, &field.field_type, budget).map(Some)
+        } else {
+            skip_typed_value(data, pos, &field.field_type, self.incoming_coded)?;
+            Ok(None)
+        }
+    }
+
     /// Decode a typed value
     fn decode_typed_value(
         &self,
         data: &[u8],
         pos: &mut usize,
         field_type: &FieldType,
+        budget: &mut OutputBudget,
     ) -> Result<serde_json::Value> {
         match field_type {
             FieldType::Null => Ok(serde_json::Value::Null),
@@ -355,6 +607,11 @@ impl Encoder {
                         *pos += len;
                         zigzag_decode(encoded)
                     }
+                    IntegerType::UInt64 => {
+                        let (u, len) = decode_varint(&data[*pos..])?;
+                        *pos += len;
+                        return Ok(serde_json::Value::Number(u.into()));
+                    }
                 };
                 Ok(serde_json::Value::Number(i.into()))
             }
@@ -388,51 +645,56 @@ impl Encoder {
                     .ok_or_else(|| Error::DecodeError("Invalid float".into()))
             }
 
-            FieldType::String => {
-                let (len, bytes_read) = decode_varint(&data[*pos..])?;
-                *pos += bytes_read;
-
-                if *pos + len as usize > data.len() {
-                    return Err(Error::DecodeError("String length exceeds data".into()));
-                }
+            FieldType::String if self.incoming_coded => {
+                let mut cursor = Cursor::new(&data[*pos..]);
+                let tag = cursor.read_varint("string")?;
+                let bytes = if tag & 1 == 1 {
+                    let entry = u32::try_from(tag >> 1)
+                        .ok()
+                        .and_then(|id| self.incoming.get(id))
+                        .ok_or_else(|| Error::DecodeError(format!("Dictionary entry {} not found", tag >> 1)))?;
+                    entry.as_bytes()
+                } else {
+                    let len = usize::try_from(tag >> 1).unwrap_or(usize::MAX);
+                    budget.charge(len)?;
+                    cursor.read_bytes(len, "string")?
+                };
+                *pos += cursor.position();
+                let s = std::str::from_utf8(bytes)
+                    .map_err(|e| Error::DecodeError(e.to_string()))?;
+                Ok(serde_json::Value::String(s.to_string()))
+            }
 
-                let s = std::str::from_utf8(&data[*pos..*pos + len as usize])
+            FieldType::String => {
+                let bytes = budget.read_field(data, pos, 1)?;
+                let s = std::str::from_utf8(bytes)
                     .map_err(|e| Error::DecodeError(e.to_string()))?;
-                *pos += len as usize;
                 Ok(serde_json::Value::String(s.to_string()))
             }
 
             FieldType::Timestamp => {
-                if *pos >= data.len() {
-                    return Err(Error::DecodeError("Timestamp truncated".into()));
-                }
-                let flag = data[*pos];
+                let flag = *data.get(*pos).ok_or(Error::DecodeError("Timestamp truncated".into()))?;
                 *pos += 1;
-
-                if flag == 0x01 {
-                    // Binary timestamp (epoch millis)
-                    if *pos + 8 > data.len() {
-                        return Err(Error::DecodeError("Timestamp truncated".into()));
+                match flag {
+                    timestamp::EXACT => {
+                        let mut cursor = Cursor::new(&data[*pos..]);
+                        let ts = timestamp::Timestamp::read(&mut cursor)?;
+                        *pos += cursor.position();
+                        Ok(serde_json::Value::String(ts.to_string()))
                     }
-                    let millis = i64::from_le_bytes([
-                        data[*pos], data[*pos + 1], data[*pos + 2], data[*pos + 3],
-                        data[*pos + 4], data[*pos + 5], data[*pos + 6], data[*pos + 7]
-                    ]);
-                    *pos += 8;
-                    Ok(serde_json::Value::String(millis_to_iso8601(millis)))
-                } else {
-                    // String fallback
-                    let (len, bytes_read) = decode_varint(&data[*pos..])?;
-                    *pos += bytes_read;
-
-                    if *pos + len as usize > data.len() {
-                        return Err(Error::DecodeError("Timestamp string truncated".into()));
+                    timestamp::LEGACY_MILLIS => {
+                        let mut cursor = Cursor::new(&data[*pos..]);
+                        let millis = cursor.read_u64_le("timestamp")? as i64;
+                        *pos += cursor.position();
+                        Ok(serde_json::Value::String(timestamp::millis_to_iso8601(millis)))
+                    }
+                    _ => {
+                        // String fallback
+                        let bytes = budget.read_field(data, pos, 1)?;
+                        let s = std::str::from_utf8(bytes)
+                            .map_err(|e| Error::DecodeError(e.to_string()))?;
+                        Ok(serde_json::Value::String(s.to_string()))
                     }
-
-                    let s = std::str::from_utf8(&data[*pos..*pos + len as usize])
-                        .map_err(|e| Error::DecodeError(e.to_string()))?;
-                    *pos += len as usize;
-                    Ok(serde_json::Value::String(s.to_string()))
                 }
             }
 
@@ -460,9 +722,11 @@ impl Encoder {
                 let (len, bytes_read) = decode_varint(&data[*pos..])?;
                 *pos += bytes_read;
 
-                let mut arr = Vec::with_capacity(len as usize);
+                budget.charge(len as usize)?;
+                // The length is untrusted: preallocate no more than the bytes left
+                let mut arr = Vec::with_capacity((len as usize).min(data.len() - *pos));
                 for _ in 0..len {
-                    arr.push(self.decode_typed_value(data, pos, elem_type)?);
+                    arr.push(self.decode_typed_value(data, pos, elem_type, budget)?);
                 }
                 Ok(serde_json::Value::Array(arr))
             }
@@ -470,26 +734,24 @@ impl Encoder {
             FieldType::Object(fields) => {
                 let mut obj = serde_json::Map::new();
                 for (name, ftype) in fields {
-                    let v = self.decode_typed_value(data, pos, ftype)?;
+                    if is_absent_member(data, pos, ftype) {
+                        continue;
+                    }
+                    let v = self.decode_typed_value(data, pos, ftype, budget)?;
                     obj.insert(name.clone(), v);
                 }
                 Ok(serde_json::Value::Object(obj))
             }
 
             FieldType::Binary => {
-                let (len, bytes_read) = decode_varint(&data[*pos..])?;
-                *pos += bytes_read;
-
-                if *pos + len as usize > data.len() {
-                    return Err(Error::DecodeError("Binary length exceeds data".into()));
+                let variant = *data.get(*pos).ok_or(Error::DecodeError("Unexpected end of data".into()))?;
+                *pos += 1;
+                let bytes = budget.read_field(data, pos, 1)?;
+                if variant != binary::TEXT {
+                    // Base64 grows 3 bytes into 4
+                    budget.charge(bytes.len().div_ceil(3))?;
                 }
-
-                let bytes = &data[*pos..*pos + len as usize];
-                *pos += len as usize;
-
-                // Return as hex string
-                let hex = hex::encode(bytes);
-                Ok(serde_json::Value::String(hex))
+                Ok(serde_json::Value::String(binary::decode(variant, bytes)?))
             }
 
             FieldType::Union(types) => {
@@ -504,143 +766,308 @@ impl Encoder {
                     return Err(Error::DecodeError("Invalid union type index".into()));
                 }
 
-                self.decode_typed_value(data, pos, &types[type_idx])
+                self.decode_typed_value(data, pos, &types[type_idx], budget)
             }
 
             FieldType::Decimal { .. } => {
                 // Decimal stored as string for now
-                let (len, bytes_read) = decode_varint(&data[*pos..])?;
-                *pos += bytes_read;
-
-                if *pos + len as usize > data.len() {
-                    return Err(Error::DecodeError("Decimal length exceeds data".into()));
-                }
-
-                let s = std::str::from_utf8(&data[*pos..*pos + len as usize])
+                let bytes = budget.read_field(data, pos, 1)?;
+                let s = std::str::from_utf8(bytes)
                     .map_err(|e| Error::DecodeError(e.to_string()))?;
-                *pos += len as usize;
                 Ok(serde_json::Value::String(s.to_string()))
             }
         }
     }
 }
 
-impl Default for Encoder {
-    fn default() -> Self {
-        Self::new()
+/// Whether `n` is an integer that `int_type` holds exactly
+/// Error naming the first member of `obj` that is not in `names`
+fn unknown_member<'a>(
+    obj: &serde_json::Map<String, serde_json::Value>,
+    names: impl Iterator<Item = &'a String> + Clone,
+) -> Error {
+    let key = obj.keys().find(|key| !names.clone().any(|name| name == *key));
+    Error::EncodeError(format!("Member '{}' is not in the schema", key.map_or("", String::as_str)))
+}
+
+fn int_fits(n: &serde_json::Number, int_type: &IntegerType) -> bool {
+    match (int_type, n.as_i64()) {
+        (IntegerType::UInt64, _) => n.is_u64(),
+        (IntegerType::Int8, Some(i)) => i8::try_from(i).is_ok(),
+        (IntegerType::Int16, Some(i)) => i16::try_from(i).is_ok(),
+        (IntegerType::Int32, Some(i)) => i32::try_from(i).is_ok(),
+        (IntegerType::Int64 | IntegerType::Varint, Some(_)) => true,
+        (_, None) => false,
     }
 }
 
-/// Parse ISO 8601 timestamp to epoch milliseconds
-/// Supports: 2024-01-15T10:30:00Z, 2024-01-15T10:30:00.123Z, 2024-01-15
-fn parse_iso8601_to_millis(s: &str) -> Option<i64> {
-    // Full datetime with optional milliseconds: 2024-01-15T10:30:00Z or 2024-01-15T10:30:00.123Z
-    if s.len() >= 20 && s.contains('T') && s.ends_with('Z') {
-        let parts: Vec<&str> = s.trim_end_matches('Z').split('T').collect();
-        if parts.len() == 2 {
-            let date_parts: Vec<i32> = parts[0]
-                .split('-')
-                .filter_map(|p| p.parse().ok())
-                .collect();
-
-            // Handle time with optional milliseconds
-            let time_str = parts[1];
-            let (time_parts, millis) = if time_str.contains('.') {
-                let tp: Vec<&str> = time_str.split('.').collect();
-                let ms: i64 = tp.get(1).and_then(|m| m.parse().ok()).unwrap_or(0);
-                (tp[0], ms)
-            } else {
-                (time_str, 0i64)
-            };
-
-            let time_nums: Vec<i32> = time_parts
-                .split(':')
-                .filter_map(|p| p.parse().ok())
-                .collect();
-
-            if date_parts.len() == 3 && time_nums.len() == 3 {
-                let year = date_parts[0];
-                let month = date_parts[1];
-                let day = date_parts[2];
-                let hour = time_nums[0];
-                let minute = time_nums[1];
-                let second = time_nums[2];
-
-                // Calculate days since epoch (1970-01-01)
-                let days = days_since_epoch(year, month, day);
-                let seconds = days as i64 * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64;
-                return Some(seconds * 1000 + millis);
-            }
-        }
-    }
-
-    // Date only: 2024-01-15
-    if s.len() == 10 && s.chars().filter(|c| *c == '-').count() == 2 {
-        let parts: Vec<i32> = s.split('-').filter_map(|p| p.parse().ok()).collect();
-        if parts.len() == 3 {
-            let days = days_since_epoch(parts[0], parts[1], parts[2]);
-            return Some(days as i64 * 86400 * 1000);
-        }
-    }
-
-    None
+/// Union tag of a nested object member that is absent (rather than `null`)
+const ABSENT_MEMBER: u8 = 0xFF;
+
+/// Consume the `ABSENT_MEMBER` tag of a nested object member, if it is one
+fn is_absent_member(data: &[u8], pos: &mut usize, ftype: &FieldType) -> bool {
+    let absent = matches!(ftype, FieldType::Union(_)) && data.get(*pos) == Some(&ABSENT_MEMBER);
+    *pos += absent as usize;
+    absent
+}
+
+/// The 16 bytes of a canonical lowercase UUID (`8-4-4-4-12` hex digits)
+///
+/// `FieldType::Uuid` values decode in this form, so only strings already in
+/// it are stored as bytes.
+pub(crate) fn parse_uuid(s: &str) -> Option<[u8; 16]> {
+    let b = s.as_bytes();
+    if b.len() != 36 || [8, 13, 18, 23].iter().any(|&i| b[i] != b'-') {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    let mut digits = b.iter().filter(|&&c| c != b'-');
+    for byte in &mut bytes {
+        let mut next = || match digits.next()? {
+            c @ b'0'..=b'9' => Some(c - b'0'),
+            c @ b'a'..=b'f' => Some(c - b'a' + 10),
+            _ => None,
+        };
+        *byte = (next()? << 4) | next()?;
+    }
+    Some(bytes)
+}
+
+/// Whether `value` holds text other than a canonical UUID where `schema`
+/// has a `Uuid`, which only a `Uuid | String` union can carry
+pub(crate) fn has_uuid_text(value: &serde_json::Value, schema: &Schema) -> bool {
+    use serde_json::Value;
+
+    let record = |obj: &serde_json::Map<String, Value>| {
+        schema.fields.iter().any(|field| obj.get(&field.name).is_some_and(|v| holds_uuid_text(v, &field.field_type)))
+    };
+    match (schema.root, value) {
+        (RootKind::Object, Value::Object(obj)) => record(obj),
+        (RootKind::Records, Value::Array(items)) => items.iter().filter_map(Value::as_object).any(record),
+        (RootKind::Values, Value::Array(items)) => {
+            root_value_field(schema).is_ok_and(|field| items.iter().any(|v| holds_uuid_text(v, &field.field_type)))
+        }
+        _ => false,
+    }
 }
 
-/// Convert epoch milliseconds to ISO 8601 string
-fn millis_to_iso8601(millis: i64) -> String {
-    let total_seconds = millis / 1000;
-    let ms = (millis % 1000) as u32;
+fn holds_uuid_text(value: &serde_json::Value, field_type: &FieldType) -> bool {
+    use serde_json::Value;
+
+    match (value, field_type) {
+        (Value::String(s), FieldType::Uuid) => parse_uuid(s).is_none(),
+        (Value::Array(items), FieldType::Array(elem)) => items.iter().any(|v| holds_uuid_text(v, elem)),
+        (Value::Object(obj), FieldType::Object(members)) => {
+            members.iter().any(|(name, t)| obj.get(name).is_some_and(|v| holds_uuid_text(v, t)))
+        }
+        (value, FieldType::Union(types)) => match union_variant(value, types) {
+            Some(variant) => holds_uuid_text(value, &types[variant]),
+            None => value.is_string() && types.contains(&FieldType::Uuid),
+        },
+        _ => false,
+    }
+}
 
-    let days = (total_seconds / 86400) as i32;
-    let remaining = (total_seconds % 86400) as i32;
+/// Index of the union variant `value` is encoded as
+///
+/// Integers prefer an integer variant wide enough to hold them, so they do
+/// not come back as floats; otherwise the first variant that fits wins.
+fn union_variant(value: &serde_json::Value, types: &[FieldType]) -> Option<usize> {
+    use serde_json::Value;
+
+    if let Value::Number(n) = value {
+        let exact = types
+            .iter()
+            .position(|t| matches!(t, FieldType::Integer(int_type) if int_fits(n, int_type)));
+        if exact.is_some() {
+            return exact;
+        }
+    }
 
-    let hour = remaining / 3600;
-    let minute = (remaining % 3600) / 60;
-    let second = remaining % 60;
+    if let Value::String(s) = value {
+        if parse_uuid(s).is_some() {
+            if let Some(uuid) = types.iter().position(|t| matches!(t, FieldType::Uuid)) {
+                return Some(uuid);
+            }
+        }
+    }
 
-    let (year, month, day) = days_to_ymd(days);
+    types.iter().position(|t| {
+        matches!(
+            (value, t),
+            (Value::Null, FieldType::Null)
+                | (Value::Bool(_), FieldType::Boolean)
+                | (Value::Number(_), FieldType::Float(_))
+                | (
+                    Value::String(_),
+                    FieldType::String | FieldType::Timestamp | FieldType::Binary | FieldType::Decimal { .. }
+                )
+                | (Value::Array(_), FieldType::Array(_))
+                | (Value::Object(_), FieldType::Object(_))
+        )
+    })
+}
 
-    if ms > 0 {
-        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
-            year, month, day, hour, minute, second, ms)
-    } else {
-        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
-            year, month, day, hour, minute, second)
+/// The element field of a `RootKind::Values` schema
+fn root_value_field(schema: &Schema) -> Result<&FieldDef> {
+    match schema.fields.as_slice() {
+        [field] => Ok(field),
+        _ => Err(Error::InvalidFrame("Value array schema needs exactly one field".into())),
     }
 }
 
-/// Calculate days since Unix epoch (1970-01-01)
-/// Uses Howard Hinnant's algorithm from chrono
-fn days_since_epoch(year: i32, month: i32, day: i32) -> i32 {
-    let y = if month <= 2 { year - 1 } else { year };
-    let m = if month <= 2 { month + 12 } else { month };
-    let era = if y >= 0 { y / 400 } else { (y - 399) / 400 };
-    let yoe = y - era * 400;
-    let doy = (153 * (m - 3) + 2) / 5 + day - 1;
-    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
-    era * 146097 + doe - 719468
+/// Read the element count of an array root, charging it against `budget`
+fn read_root_count(data: &[u8], pos: &mut usize, budget: &mut OutputBudget) -> Result<usize> {
+    let (count, len) = decode_varint(&data[*pos..])?;
+    *pos += len;
+    let count = usize::try_from(count).map_err(|_| Error::DecodeError("Array length overflows".into()))?;
+    budget.charge(count)?;
+    Ok(count)
+}
+
+/// Advance `pos` past `n` bytes
+fn skip_bytes(data: &[u8], pos: &mut usize, n: usize, what: &'static str) -> Result<()> {
+    let available = data.len() - *pos;
+    if n > available {
+        return Err(Error::Truncated { what, needed: n, available });
+    }
+    *pos += n;
+    Ok(())
 }
 
-/// Convert days since epoch to year, month, day
-/// Uses Howard Hinnant's algorithm from chrono
-fn days_to_ymd(days: i32) -> (i32, i32, i32) {
-    let z = days + 719468;
-    let era = if z >= 0 { z / 146097 } else { (z - 146096) / 146097 };
-    let doe = z - era * 146097;
-    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
-    let y = yoe + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 };
-    let year = y + if m <= 2 { 1 } else { 0 };
-    (year, m, d)
+/// Encoded size of `field_type` if every value of it takes the same space
+fn fixed_width(field_type: &FieldType) -> Option<usize> {
+    match field_type {
+        FieldType::Null => Some(0),
+        FieldType::Boolean => Some(1),
+        FieldType::Integer(IntegerType::Int8) => Some(1),
+        FieldType::Integer(IntegerType::Int16) => Some(2),
+        FieldType::Integer(IntegerType::Int32) => Some(4),
+        FieldType::Integer(IntegerType::Int64) => Some(8),
+        FieldType::Float(FloatType::Float32) => Some(4),
+        FieldType::Float(FloatType::Float64) => Some(8),
+        FieldType::Uuid => Some(16),
+        FieldType::Object(fields) => fields
+            .iter()
+            .try_fold(0usize, |sum, (_, ftype)| sum.checked_add(fixed_width(ftype)?)),
+        _ => None,
+    }
+}
+
+/// Advance `pos` past one encoded value of `field_type` without decoding it
+fn skip_typed_value(data: &[u8], pos: &mut usize, field_type: &FieldType, coded: bool) -> Result<()> {
+    if let Some(width) = fixed_width(field_type) {
+        return skip_bytes(data, pos, width, "skipped value");
+    }
+    match field_type {
+        FieldType::Integer(_) => {
+            let (_, len) = decode_varint(&data[*pos..])?;
+            *pos += len;
+        }
+        FieldType::String if coded => {
+            let (tag, len) = decode_varint(&data[*pos..])?;
+            *pos += len;
+            if tag & 1 == 0 {
+                let len = usize::try_from(tag >> 1).unwrap_or(usize::MAX);
+                skip_bytes(data, pos, len, "string")?;
+            }
+        }
+        FieldType::String | FieldType::Decimal { .. } => {
+            read_len_prefixed(data, pos, usize::MAX)?;
+        }
+        FieldType::Binary => {
+            skip_bytes(data, pos, 1, "binary variant")?;
+            read_len_prefixed(data, pos, usize::MAX)?;
+        }
+        FieldType::Timestamp => {
+            let flag = *data.get(*pos).ok_or(Error::DecodeError("Timestamp truncated".into()))?;
+            *pos += 1;
+            match flag {
+                timestamp::EXACT => {
+                    let mut cursor = Cursor::new(&data[*pos..]);
+                    timestamp::Timestamp::skip(&mut cursor)?;
+                    *pos += cursor.position();
+                }
+                timestamp::LEGACY_MILLIS => skip_bytes(data, pos, 8, "timestamp")?,
+                _ => {
+                    read_len_prefixed(data, pos, usize::MAX)?;
+                }
+            }
+        }
+        FieldType::Array(elem_type) => {
+            let (count, len) = decode_varint(&data[*pos..])?;
+            *pos += len;
+            match fixed_width(elem_type) {
+                // Skip fixed-width runs in one step, so a huge count of
+                // zero-width elements costs nothing
+                Some(width) => {
+                    let total = usize::try_from(count)
+                        .ok()
+                        .and_then(|count| count.checked_mul(width))
+                        .ok_or(Error::DecodeError("Array length overflows".into()))?;
+                    skip_bytes(data, pos, total, "array")?;
+                }
+                None => {
+                    for _ in 0..count {
+                        skip_typed_value(data, pos, elem_type, coded)?;
+                    }
+                }
+            }
+        }
+        FieldType::Object(fields) => {
+            for (_, ftype) in fields {
+                if !is_absent_member(data, pos, ftype) {
+                    skip_typed_value(data, pos, ftype, coded)?;
+                }
+            }
+        }
+        FieldType::Union(types) => {
+            let idx = *data.get(*pos).ok_or(Error::DecodeError("Unexpected end of data".into()))? as usize;
+            *pos += 1;
+            let ftype = types.get(idx).ok_or(Error::DecodeError("Invalid union type index".into()))?;
+            skip_typed_value(data, pos, ftype, coded)?;
+        }
+        _ => unreachable!("fixed-width types are skipped above"),
+    }
+    Ok(())
+}
+
+/// Remaining output allowance while decoding
+struct OutputBudget {
+    limit: usize,
+    remaining: usize,
+}
+
+impl OutputBudget {
+    fn charge(&mut self, bytes: usize) -> Result<()> {
+        self.remaining = self
+            .remaining
+            .checked_sub(bytes)
+            .ok_or(Error::OutputLimitExceeded { limit: self.limit })?;
+        Ok(())
+    }
+
+    /// Read a length-prefixed field that decodes to `scale` output bytes per input byte
+    fn read_field<'a>(&mut self, data: &'a [u8], pos: &mut usize, scale: usize) -> Result<&'a [u8]> {
+        let bytes = read_len_prefixed(data, pos, self.remaining / scale).map_err(|e| match e {
+            Error::LengthLimitExceeded { .. } => Error::OutputLimitExceeded { limit: self.limit },
+            e => e,
+        })?;
+        self.remaining -= bytes.len() * scale;
+        Ok(bytes)
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::schema::SchemaInferrer;
+    use crate::schema::{FieldDef, SchemaInferrer};
 
     #[test]
     fn test_varint_roundtrip() {
@@ -734,6 +1161,33 @@ mod tests {
         assert_eq!(json, decoded);
     }
 
+    #[test]
+    fn test_encoder_roundtrip_union() {
+        let json = serde_json::json!({
+            "mixed": [1, "a", null, 2.5, 100000, true],
+            "items": [{"id": 1, "note": null}, {"id": 2, "note": "hi"}]
+        });
+
+        let mut inferrer = SchemaInferrer::new();
+        inferrer.add_value(&json).unwrap();
+        let schema = inferrer.infer().unwrap();
+        let mixed = schema.fields.iter().find(|f| f.name == "mixed").unwrap();
+        assert!(matches!(&mixed.field_type, FieldType::Array(elem) if matches!(**elem, FieldType::Union(_))));
+
+        let mut encoder = Encoder::new();
+        let encoded = encoder.encode(&json, &schema).unwrap();
+        assert_eq!(encoder.decode(&encoded, &schema).unwrap(), json);
+
+        // Projection skips over tagged values too
+        let projected = encoder.decode_projected(&encoded, &schema, &["items"], usize::MAX).unwrap();
+        assert_eq!(projected, serde_json::json!({"items": json["items"]}));
+
+        // Values no variant can hold are rejected rather than mis-encoded
+        let strict = FieldType::Union(vec![FieldType::String, FieldType::Null]);
+        let mut buf = Vec::new();
+        assert!(encoder.encode_typed_value(&serde_json::json!(1), &strict, &mut buf).is_err());
+    }
+
     #[test]
     fn test_encoder_size_savings() {
         // Create JSON with repeated keys
@@ -761,31 +1215,107 @@ mod tests {
     }
 
     #[test]
-    fn test_timestamp_parsing() {
-        // Full datetime
-        let millis = parse_iso8601_to_millis("2024-01-15T10:30:00Z").unwrap();
-        assert!(millis > 0);
-        let back = millis_to_iso8601(millis);
-        assert_eq!(back, "2024-01-15T10:30:00Z");
+    fn test_decode_limit_zero_width_array() {
+        // A tiny payload claiming a huge array of nulls
+        let schema = Schema::new(vec![FieldDef {
+            name: "a".into(),
+            field_type: FieldType::Array(Box::new(FieldType::Null)),
+            nullable: false,
+        }]);
+        let mut payload = Vec::new();
+        encode_varint(u32::MAX as u64, &mut payload);
+
+        let encoder = Encoder::new();
+        let err = encoder.decode_with_limit(&payload, &schema, 1 << 20).unwrap_err();
+        assert!(matches!(err.root(), Error::OutputLimitExceeded { limit } if *limit == 1 << 20));
+        assert_eq!((err.stage(), err.offset()), (Some(Stage::Encoding), Some(0)));
+    }
 
-        // With milliseconds
-        let millis = parse_iso8601_to_millis("2024-01-15T10:30:00.123Z").unwrap();
-        let back = millis_to_iso8601(millis);
-        assert_eq!(back, "2024-01-15T10:30:00.123Z");
+    #[test]
+    fn test_decode_array_len_exceeds_data() {
+        // A few bytes claiming ~241M elements must not be preallocated
+        let schema = Schema::new(vec![FieldDef {
+            name: "a".into(),
+            field_type: FieldType::Array(Box::new(FieldType::Integer(IntegerType::Varint))),
+            nullable: false,
+        }]);
+        let mut payload = Vec::new();
+        encode_varint(241_000_000, &mut payload);
+        payload.extend_from_slice(&[0x02; 8]);
+
+        let err = Encoder::new().decode_with_limit(&payload, &schema, usize::MAX).unwrap_err();
+        assert_eq!(err.stage(), Some(Stage::Encoding));
+    }
 
-        // Date only
-        let millis = parse_iso8601_to_millis("2024-01-15").unwrap();
-        assert!(millis > 0);
+    #[test]
+    fn test_decode_projected_skips_fields() {
+        let field = |name: &str, field_type| FieldDef { name: name.into(), field_type, nullable: false };
+        // Names sort in the order the fields are laid out below
+        let schema = Schema::new(vec![
+            field("a_nulls", FieldType::Array(Box::new(FieldType::Null))),
+            field("b_blob", FieldType::Binary),
+            field("id", FieldType::Integer(IntegerType::Varint)),
+            field("tail", FieldType::String),
+        ]);
+        let mut payload = Vec::new();
+        encode_varint(u32::MAX as u64, &mut payload); // zero-width elements: skipped in one step
+        payload.extend_from_slice(&[binary::TEXT, 2, 0xAB, 0xCD]);
+        encode_varint(zigzag_encode(-5), &mut payload);
+        // `tail` is never reached, so its truncation goes unnoticed
+
+        let encoder = Encoder::new();
+        let value = encoder.decode_projected(&payload, &schema, &["id"], 1 << 20).unwrap();
+        assert_eq!(value, serde_json::json!({"id": -5}));
+
+        // Truncation inside a skipped field is still an error
+        assert!(encoder.decode_projected(&payload[..3], &schema, &["id"], 1 << 20).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_parsing() {
+        let schema = Schema::new(vec![FieldDef {
+            name: "at".into(),
+            field_type: FieldType::Timestamp,
+            nullable: false,
+        }]);
+        let mut encoder = Encoder::new();
+        for at in [
+            "2024-01-15T10:30:00Z",
+            "2024-01-15T10:30:00.123Z",
+            "2024-01-15T10:30:00.100Z",
+            "2024-01-15T10:30:00.123456789+02:00",
+            "2024-01-15",
+            "1965-06-01 08:00:00",
+            "2024-01-15T10:30:00-00:00",
+            "not a timestamp",
+        ] {
+            let json = serde_json::json!({ "at": at });
+            let encoded = encoder.encode(&json, &schema).unwrap();
+            assert_eq!(encoder.decode(&encoded, &schema).unwrap(), json);
+            let projected = encoder.decode_projected(&encoded, &schema, &[], usize::MAX).unwrap();
+            assert_eq!(projected, serde_json::json!({}));
+        }
+
+        // Frames from before exact timestamps stored epoch millis
+        let mut legacy = vec![timestamp::LEGACY_MILLIS];
+        legacy.extend_from_slice(&1_705_314_600_123i64.to_le_bytes());
+        assert_eq!(
+            encoder.decode(&legacy, &schema).unwrap(),
+            serde_json::json!({"at": "2024-01-15T10:30:00.123Z"})
+        );
     }
 
     #[test]
     fn test_timestamp_size_savings() {
         // Timestamp string: "2024-01-15T10:30:00Z" = 20 bytes
-        // Binary encoding: 1 flag + 8 bytes = 9 bytes
-        // Savings: 11 bytes per timestamp
-        let timestamp = "2024-01-15T10:30:00Z";
-        assert!(parse_iso8601_to_millis(timestamp).is_some());
+        // Binary encoding: 1 flag + 1 layout + 5 byte varint = 7 bytes
+        let mut buf = Vec::new();
+        timestamp::encode("2024-01-15T10:30:00Z", &mut buf);
+        assert_eq!(buf.len(), 7);
 
-        // With 10 timestamps, save 110 bytes
+        // Offsets and nanoseconds stay well under their text length
+        buf.clear();
+        timestamp::encode("2024-01-15T10:30:00.123456789+02:00", &mut buf);
+        assert!(buf.len() < 16);
     }
 }
@@ -1,6 +1,7 @@
 //! String encoding strategies
 
 use super::varint::{encode_varint, decode_varint};
+use crate::cursor::{read_len, read_len_prefixed};
 use crate::{Error, Result};
 use std::collections::HashMap;
 
@@ -25,9 +26,9 @@ pub fn build_dictionary(strings: &[&str], max_entries: usize) -> Vec<String> {
         *freq.entry(*s).or_insert(0) += 1;
     }
 
-    // Sort by frequency
+    // Sort by frequency, ties broken by content for deterministic output
     let mut entries: Vec<_> = freq.into_iter().collect();
-    entries.sort_by(|a, b| b.1.cmp(&a.1));
+    entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
 
     // Take top entries that appear more than once
     entries
@@ -81,49 +82,46 @@ pub fn decode_dictionary(buf: &[u8]) -> Result<Vec<String>> {
     let mut pos = 0;
 
     // Read dictionary
-    let (dict_len, len) = decode_varint(buf)?;
-    pos += len;
+    let dict_len = read_len(buf, &mut pos, usize::MAX)?;
 
-    let mut dict = Vec::with_capacity(dict_len as usize);
+    let mut dict = Vec::with_capacity(dict_len);
     for _ in 0..dict_len {
-        let (str_len, len) = decode_varint(&buf[pos..])?;
-        pos += len;
-
-        let s = std::str::from_utf8(&buf[pos..pos + str_len as usize])
-            .map_err(|e| Error::DecodeError(e.to_string()))?;
-        dict.push(s.to_string());
-        pos += str_len as usize;
+        dict.push(decode_str(buf, &mut pos)?);
     }
 
     // Read strings
-    let (count, len) = decode_varint(&buf[pos..])?;
-    pos += len;
+    let count = read_len(buf, &mut pos, usize::MAX)?;
 
-    let mut strings = Vec::with_capacity(count as usize);
+    let mut strings = Vec::with_capacity(count);
     for _ in 0..count {
-        let marker = buf[pos];
+        let marker = *buf
+            .get(pos)
+            .ok_or(Error::Truncated { what: "string marker", needed: 1, available: 0 })?;
         pos += 1;
 
         if marker == 0x01 {
             // Dictionary reference
             let (id, len) = decode_varint(&buf[pos..])?;
             pos += len;
-            strings.push(dict[id as usize].clone());
+            let s = dict
+                .get(id as usize)
+                .ok_or_else(|| Error::DecodeError(format!("Dictionary index {} out of range", id)))?;
+            strings.push(s.clone());
         } else {
             // Literal
-            let (str_len, len) = decode_varint(&buf[pos..])?;
-            pos += len;
-
-            let s = std::str::from_utf8(&buf[pos..pos + str_len as usize])
-                .map_err(|e| Error::DecodeError(e.to_string()))?;
-            strings.push(s.to_string());
-            pos += str_len as usize;
+            strings.push(decode_str(buf, &mut pos)?);
         }
     }
 
     Ok(strings)
 }
 
+fn decode_str(buf: &[u8], pos: &mut usize) -> Result<String> {
+    let bytes = read_len_prefixed(buf, pos, usize::MAX)?;
+    let s = std::str::from_utf8(bytes).map_err(|e| Error::DecodeError(e.to_string()))?;
+    Ok(s.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -138,6 +136,9 @@ mod tests {
         assert!(dict.contains(&"apple".to_string()));
         // "cherry" appears only once, shouldn't be in dict
         assert!(!dict.contains(&"cherry".to_string()));
+
+        let tied = vec!["d", "c", "b", "a", "d", "c", "b", "a"];
+        assert_eq!(build_dictionary(&tied, 3), ["a", "b", "c"]);
     }
 
     #[test]
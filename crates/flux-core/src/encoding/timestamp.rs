@@ -0,0 +1,432 @@
+//! Binary encoding of ISO 8601 timestamps
+//!
+//! A `FieldType::Timestamp` value starts with a flag byte. Timestamps the
+//! parser accepts are stored as an instant plus the few details needed to
+//! print them back exactly as written (fraction digits, separator and UTC
+//! offset):
+//!
+//! ```text
+//! 0x02 | layout (u8) | seconds (zigzag varint) | fraction (varint)? | offset minutes (zigzag varint)?
+//! ```
+//!
+//! The layout byte holds the fraction digit count (bits 0-3), a date-only
+//! bit (4), a space-separator bit (5) and the offset kind (bits 6-7). The
+//! fraction is present only with digits, the offset only when numeric.
+//!
+//! A string is only parsed if formatting the result reproduces it byte for
+//! byte; anything else (lowercase designators, `-00:00`, leap seconds, ...)
+//! is stored as text under `TEXT`. Frames written before this layout used
+//! `LEGACY_MILLIS`, which is still decoded.
+
+use std::fmt;
+
+use super::varint::{encode_signed_varint, encode_varint, zigzag_decode};
+use crate::cursor::Cursor;
+use crate::{Error, Result};
+
+/// Flag for values stored as plain text
+pub const TEXT: u8 = 0x00;
+
+/// Flag for epoch milliseconds (i64 LE), always printed with a `Z` suffix
+pub const LEGACY_MILLIS: u8 = 0x01;
+
+/// Flag for the exact layout described in the module docs
+pub const EXACT: u8 = 0x02;
+
+const DATE_ONLY: u8 = 0x10;
+const SPACE_SEPARATOR: u8 = 0x20;
+const OFFSET_SHIFT: u8 = 6;
+
+/// Largest numeric offset, `±23:59`
+const MAX_OFFSET_MINUTES: i64 = 23 * 60 + 59;
+
+/// Seconds from the epoch to 0000-01-01 and to 10000-01-01, the range
+/// four-digit years can express
+const MIN_SECONDS: i64 = -62_167_219_200;
+const MAX_SECONDS: i64 = 253_402_300_800;
+
+/// How a timestamp expresses its offset from UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    /// `Z`
+    Utc,
+    /// `+hh:mm` or `-hh:mm`
+    Minutes(i16),
+    /// No designator (local time), also used for date-only values
+    Local,
+}
+
+impl Offset {
+    fn kind(self) -> u8 {
+        match self {
+            Offset::Utc => 0,
+            Offset::Minutes(_) => 1,
+            Offset::Local => 2,
+        }
+    }
+
+    fn minutes(self) -> i64 {
+        match self {
+            Offset::Minutes(m) => m as i64,
+            _ => 0,
+        }
+    }
+}
+
+/// A parsed ISO 8601 date or date-time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch, UTC (local times count as UTC)
+    pub seconds: i64,
+    /// Fraction of a second as written, e.g. 50 with 3 digits for `.050`
+    pub fraction: u32,
+    /// Number of fraction digits, 0-9
+    pub fraction_digits: u8,
+    /// Written as a date without a time
+    pub date_only: bool,
+    /// Date and time separated by a space rather than `T`
+    pub space_separator: bool,
+    /// UTC offset designator
+    pub offset: Offset,
+}
+
+impl Timestamp {
+    /// Parse `YYYY-MM-DD` or `YYYY-MM-DD(T| )hh:mm:ss(.f{1,9})?(Z|±hh:mm)?`
+    ///
+    /// Returns `None` unless formatting the result gives back `s` exactly.
+    pub fn parse(s: &str) -> Option<Self> {
+        let b = s.as_bytes();
+        if b.len() < 10 || b[4] != b'-' || b[7] != b'-' {
+            return None;
+        }
+        let (year, month, day) = (digits(b, 0, 4)?, digits(b, 5, 2)?, digits(b, 8, 2)?);
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        let days = days_since_epoch(year as i32, month as i32, day as i32) as i64;
+
+        let mut ts = Timestamp {
+            seconds: days * 86400,
+            fraction: 0,
+            fraction_digits: 0,
+            date_only: b.len() == 10,
+            space_separator: false,
+            offset: Offset::Local,
+        };
+        if ts.date_only {
+            return Some(ts);
+        }
+
+        ts.space_separator = match b[10] {
+            b'T' => false,
+            b' ' => true,
+            _ => return None,
+        };
+        if b.len() < 19 || b[13] != b':' || b[16] != b':' {
+            return None;
+        }
+        let (hour, minute, second) = (digits(b, 11, 2)?, digits(b, 14, 2)?, digits(b, 17, 2)?);
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+
+        let mut i = 19;
+        if b.get(i) == Some(&b'.') {
+            let count = b[i + 1..].iter().take_while(|c| c.is_ascii_digit()).count();
+            if !(1..=9).contains(&count) {
+                return None;
+            }
+            ts.fraction = digits(b, i + 1, count)? as u32;
+            ts.fraction_digits = count as u8;
+            i += 1 + count;
+        }
+
+        ts.offset = match &b[i..] {
+            [] => Offset::Local,
+            [b'Z'] => Offset::Utc,
+            [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+                let (h, m) = (digits(b, i + 1, 2)?, digits(b, i + 4, 2)?);
+                if h > 23 || m > 59 {
+                    return None;
+                }
+                let minutes = (h * 60 + m) as i16;
+                // `-00:00` means "offset unknown" and would print as `+00:00`
+                if *sign == b'-' && minutes == 0 {
+                    return None;
+                }
+                Offset::Minutes(if *sign == b'-' { -minutes } else { minutes })
+            }
+            _ => return None,
+        };
+
+        ts.seconds += hour * 3600 + minute * 60 + second - ts.offset.minutes() * 60;
+        Some(ts)
+    }
+
+    /// Append the binary form (flag included) to `buf`
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let mut layout = self.fraction_digits | (self.offset.kind() << OFFSET_SHIFT);
+        if self.date_only {
+            layout |= DATE_ONLY;
+        }
+        if self.space_separator {
+            layout |= SPACE_SEPARATOR;
+        }
+        buf.push(EXACT);
+        buf.push(layout);
+        encode_signed_varint(self.seconds, buf);
+        if self.fraction_digits > 0 {
+            encode_varint(self.fraction as u64, buf);
+        }
+        if let Offset::Minutes(m) = self.offset {
+            encode_signed_varint(m as i64, buf);
+        }
+    }
+
+    /// Read the binary form that follows an `EXACT` flag
+    ///
+    /// Rejects anything `parse` could not have produced, so every decoded
+    /// value formats to a string that parses back to itself.
+    pub fn read(cursor: &mut Cursor<'_>) -> Result<Self> {
+        let invalid = || Error::DecodeError("Invalid timestamp".into());
+
+        let layout = cursor.read_u8("timestamp layout")?;
+        let fraction_digits = layout & 0x0F;
+        let date_only = layout & DATE_ONLY != 0;
+        let space_separator = layout & SPACE_SEPARATOR != 0;
+        let seconds = zigzag_decode(cursor.read_varint("timestamp seconds")?);
+        let fraction = if fraction_digits > 0 {
+            cursor.read_varint("timestamp fraction")?
+        } else {
+            0
+        };
+        let offset = match layout >> OFFSET_SHIFT {
+            0 => Offset::Utc,
+            1 => {
+                let minutes = zigzag_decode(cursor.read_varint("timestamp offset")?);
+                if minutes.abs() > MAX_OFFSET_MINUTES {
+                    return Err(invalid());
+                }
+                Offset::Minutes(minutes as i16)
+            }
+            2 => Offset::Local,
+            _ => return Err(invalid()),
+        };
+
+        let wall = seconds.checked_add(offset.minutes() * 60).ok_or_else(invalid)?;
+        let date_only_ok = !date_only
+            || (fraction_digits == 0 && !space_separator && offset == Offset::Local && wall % 86400 == 0);
+        if fraction_digits > 9
+            || fraction >= 10u64.pow(fraction_digits as u32)
+            || !(MIN_SECONDS..MAX_SECONDS).contains(&wall)
+            || !date_only_ok
+        {
+            return Err(invalid());
+        }
+
+        Ok(Timestamp {
+            seconds,
+            fraction: fraction as u32,
+            fraction_digits,
+            date_only,
+            space_separator,
+            offset,
+        })
+    }
+
+    /// Skip the binary form that follows an `EXACT` flag
+    pub fn skip(cursor: &mut Cursor<'_>) -> Result<()> {
+        let layout = cursor.read_u8("timestamp layout")?;
+        cursor.read_varint("timestamp seconds")?;
+        if layout & 0x0F > 0 {
+            cursor.read_varint("timestamp fraction")?;
+        }
+        if layout >> OFFSET_SHIFT == 1 {
+            cursor.read_varint("timestamp offset")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wall = self.seconds + self.offset.minutes() * 60;
+        let (year, month, day) = days_to_ymd(wall.div_euclid(86400) as i32);
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)?;
+        if self.date_only {
+            return Ok(());
+        }
+
+        let secs = wall.rem_euclid(86400);
+        let separator = if self.space_separator { ' ' } else { 'T' };
+        write!(f, "{}{:02}:{:02}:{:02}", separator, secs / 3600, secs % 3600 / 60, secs % 60)?;
+        if self.fraction_digits > 0 {
+            write!(f, ".{:0width$}", self.fraction, width = self.fraction_digits as usize)?;
+        }
+        match self.offset {
+            Offset::Utc => f.write_str("Z"),
+            Offset::Minutes(m) => {
+                let sign = if m < 0 { '-' } else { '+' };
+                write!(f, "{}{:02}:{:02}", sign, m.abs() / 60, m.abs() % 60)
+            }
+            Offset::Local => Ok(()),
+        }
+    }
+}
+
+/// Encode a timestamp string, falling back to text when it can't be stored exactly
+pub fn encode(s: &str, buf: &mut Vec<u8>) {
+    match Timestamp::parse(s) {
+        Some(ts) => ts.encode(buf),
+        None => {
+            buf.push(TEXT);
+            encode_varint(s.len() as u64, buf);
+            buf.extend_from_slice(s.as_bytes());
+        }
+    }
+}
+
+/// Convert legacy epoch milliseconds to an ISO 8601 string
+pub fn millis_to_iso8601(millis: i64) -> String {
+    let ms = millis.rem_euclid(1000);
+    let ts = Timestamp {
+        seconds: millis.div_euclid(1000),
+        fraction: ms as u32,
+        fraction_digits: if ms > 0 { 3 } else { 0 },
+        date_only: false,
+        space_separator: false,
+        offset: Offset::Utc,
+    };
+    ts.to_string()
+}
+
+/// Parse `len` ASCII digits starting at `start`
+fn digits(b: &[u8], start: usize, len: usize) -> Option<i64> {
+    b.get(start..start + len)?
+        .iter()
+        .try_fold(0i64, |acc, c| c.is_ascii_digit().then(|| acc * 10 + (c - b'0') as i64))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Calculate days since Unix epoch (1970-01-01)
+/// Uses Howard Hinnant's algorithm from chrono
+fn days_since_epoch(year: i32, month: i32, day: i32) -> i32 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let m = if month <= 2 { month + 12 } else { month };
+    let era = if y >= 0 { y / 400 } else { (y - 399) / 400 };
+    let yoe = y - era * 400;
+    let doy = (153 * (m - 3) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Convert days since epoch to year, month, day
+/// Uses Howard Hinnant's algorithm from chrono
+fn days_to_ymd(days: i32) -> (i32, i32, i32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z / 146097 } else { (z - 146096) / 146097 };
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = y + if m <= 2 { 1 } else { 0 };
+    (year, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_exact_roundtrip() {
+        let cases = [
+            "2024-01-15",
+            "2024-01-15T10:30:00Z",
+            "2024-01-15T10:30:00.000Z",
+            "2024-01-15T10:30:00.1Z",
+            "2024-01-15T10:30:00.123456789Z",
+            "2024-01-15T10:30:00+02:00",
+            "2024-01-15T10:30:00+00:00",
+            "2024-01-15T00:30:00.5-05:30",
+            "2024-01-15 10:30:00",
+            "1969-12-31T23:59:59.999Z",
+            "1900-02-28T12:00:00Z",
+            "2000-02-29",
+        ];
+        for s in cases {
+            let ts = Timestamp::parse(s).unwrap_or_else(|| panic!("{}", s));
+            assert_eq!(ts.to_string(), s);
+
+            let mut buf = Vec::new();
+            encode(s, &mut buf);
+            assert_eq!(buf[0], EXACT, "{}", s);
+            let mut cursor = Cursor::new(&buf[1..]);
+            assert_eq!(Timestamp::read(&mut cursor).unwrap(), ts);
+            assert!(cursor.is_empty());
+            let mut cursor = Cursor::new(&buf[1..]);
+            Timestamp::skip(&mut cursor).unwrap();
+            assert!(cursor.is_empty());
+        }
+
+        // Same instant, different offsets
+        let a = Timestamp::parse("2024-01-15T12:00:00+02:00").unwrap();
+        let b = Timestamp::parse("2024-01-15T10:00:00Z").unwrap();
+        assert_eq!(a.seconds, b.seconds);
+        assert_eq!(Timestamp::parse("1969-12-31T23:59:59Z").unwrap().seconds, -1);
+    }
+
+    #[test]
+    fn test_timestamp_text_fallback() {
+        let cases = [
+            "2024-02-30",
+            "2024-13-01T00:00:00Z",
+            "2024-01-15t10:30:00z",
+            "2024-01-15T24:00:00Z",
+            "2024-01-15T23:59:60Z",
+            "2024-01-15T10:30:00-00:00",
+            "2024-01-15T10:30:00.1234567890Z",
+            "2024-01-15T10:30Z",
+            "2024-01-15T10:30:00+0200",
+            "2023-02-29",
+            "+2024-01-15",
+        ];
+        for s in cases {
+            assert!(Timestamp::parse(s).is_none(), "{}", s);
+            let mut buf = Vec::new();
+            encode(s, &mut buf);
+            assert_eq!(buf[0], TEXT);
+        }
+    }
+
+    #[test]
+    fn test_timestamp_rejects_invalid_layout() {
+        // Fraction out of range for its digit count
+        assert!(Timestamp::read(&mut Cursor::new(&[0x01, 0x00, 10])).is_err());
+        // Unknown offset kind
+        assert!(Timestamp::read(&mut Cursor::new(&[0xC0, 0x00])).is_err());
+        // Date-only with a time of day
+        assert!(Timestamp::read(&mut Cursor::new(&[0x90, 0x02])).is_err());
+        // Past year 9999
+        let mut buf = vec![0x80];
+        encode_signed_varint(MAX_SECONDS, &mut buf);
+        assert!(Timestamp::read(&mut Cursor::new(&buf)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_millis() {
+        assert_eq!(millis_to_iso8601(1_705_314_600_000), "2024-01-15T10:30:00Z");
+        assert_eq!(millis_to_iso8601(1_705_314_600_123), "2024-01-15T10:30:00.123Z");
+        assert_eq!(millis_to_iso8601(-1), "1969-12-31T23:59:59.999Z");
+    }
+}
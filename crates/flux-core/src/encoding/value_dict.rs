@@ -0,0 +1,277 @@
+//! Session value dictionary
+//!
+//! With `FluxConfig::value_dictionary`, short strings that recur (status
+//! values, country codes, ...) are sent once per session and referenced by
+//! index afterwards. A string enters the dictionary on its second sighting,
+//! and the entries a frame adds travel in its dictionary section, which
+//! follows the schema section:
+//!
+//! ```text
+//! len (varint) | first_index (varint) | count (varint) | { len (varint) | bytes }*
+//! ```
+//!
+//! A frame that adds nothing carries an empty section. In frames flagged
+//! `FrameFlags::DICTIONARY_UPDATE`, every `FieldType::String` value is a
+//! tagged varint: `len << 1` followed by the bytes, or `index << 1 | 1`
+//! naming an entry.
+//!
+//! Entries are only ever appended, and the dictionary stops growing at its
+//! byte limit. The sender commits an update once its frame is written, so a
+//! failed compression never leaves the two sides out of step.
+
+use std::collections::HashMap;
+
+use super::varint::encode_varint;
+use super::StringDictionary;
+use crate::cursor::Cursor;
+use crate::{Error, Result};
+
+/// Longest string considered for the dictionary
+pub const MAX_VALUE_LEN: usize = 32;
+
+/// Sightings (across messages or within one) before a string is added
+const MIN_SIGHTINGS: u32 = 2;
+
+/// Strings tracked while waiting for a second sighting
+///
+/// The candidates are forgotten when the table fills, so a stream of unique
+/// ids can't grow it without bound.
+const MAX_CANDIDATES: usize = 4096;
+
+/// Bookkeeping per held string (the `String` and its map slot), for
+/// memory estimates
+const STRING_OVERHEAD: usize = std::mem::size_of::<String>() + 8;
+
+/// One side of a session value dictionary
+pub struct ValueDictionary {
+    entries: StringDictionary,
+    /// Entries the peer already knows about
+    committed: usize,
+    bytes: usize,
+    max_bytes: usize,
+    candidates: HashMap<String, u32>,
+}
+
+impl ValueDictionary {
+    /// Create an empty dictionary holding at most `max_bytes` of strings
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: StringDictionary::new(),
+            committed: 0,
+            bytes: 0,
+            max_bytes,
+            candidates: HashMap::new(),
+        }
+    }
+
+    /// Number of entries, committed or not
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the dictionary has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total bytes of the strings held
+    pub fn byte_size(&self) -> usize {
+        self.bytes
+    }
+
+    /// Approximate bytes held, recurrence candidates included
+    ///
+    /// Entries are kept both in order and in an index, each with a copy of
+    /// the string.
+    pub fn memory_usage(&self) -> usize {
+        let candidates: usize = self.candidates.keys().map(|s| s.len() + STRING_OVERHEAD).sum();
+        2 * (self.bytes + self.entries.len() * STRING_OVERHEAD) + candidates
+    }
+
+    /// Look up an entry by index
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.entries.get(id)
+    }
+
+    /// Write `s` as a tagged string, adding it to the dictionary if it recurs
+    pub fn encode(&mut self, s: &str, buf: &mut Vec<u8>) {
+        match self.lookup(s) {
+            Some(id) => encode_varint((id as u64) << 1 | 1, buf),
+            None => {
+                encode_varint((s.len() as u64) << 1, buf);
+                buf.extend_from_slice(s.as_bytes());
+            }
+        }
+    }
+
+    fn lookup(&mut self, s: &str) -> Option<u32> {
+        if let Some(id) = self.entries.id(s) {
+            return Some(id);
+        }
+        if s.is_empty() || s.len() > MAX_VALUE_LEN || self.bytes + s.len() > self.max_bytes {
+            return None;
+        }
+
+        let sightings = match self.candidates.get_mut(s) {
+            Some(sightings) => sightings,
+            None => {
+                if self.candidates.len() >= MAX_CANDIDATES {
+                    self.candidates.clear();
+                }
+                self.candidates.entry(s.to_string()).or_insert(0)
+            }
+        };
+        *sightings += 1;
+        if *sightings < MIN_SIGHTINGS {
+            return None;
+        }
+
+        self.candidates.remove(s);
+        self.bytes += s.len();
+        Some(self.entries.get_or_add(s))
+    }
+
+    /// Write the entries added since the last commit as a dictionary section
+    ///
+    /// The length prefix is not included. Writes nothing when there are no
+    /// new entries.
+    pub fn write_update(&self, buf: &mut Vec<u8>) {
+        self.write_section(self.committed..self.entries.len(), buf);
+    }
+
+    /// Write every committed entry as a dictionary section
+    ///
+    /// Applying it to an empty dictionary restores what the peer knows.
+    pub fn write_snapshot(&self, buf: &mut Vec<u8>) {
+        self.write_section(0..self.committed, buf);
+    }
+
+    fn write_section(&self, ids: std::ops::Range<usize>, buf: &mut Vec<u8>) {
+        if ids.is_empty() {
+            return;
+        }
+        encode_varint(ids.start as u64, buf);
+        encode_varint(ids.len() as u64, buf);
+        for id in ids {
+            let entry = self.entries.get(id as u32).unwrap_or_default();
+            encode_varint(entry.len() as u64, buf);
+            buf.extend_from_slice(entry.as_bytes());
+        }
+    }
+
+    /// Mark every entry as known to the peer
+    pub fn commit(&mut self) {
+        self.committed = self.entries.len();
+    }
+
+    /// Drop the entries added since the last commit
+    pub fn rollback(&mut self) {
+        for id in self.committed..self.entries.len() {
+            self.bytes -= self.entries.get(id as u32).map_or(0, str::len);
+        }
+        self.entries.truncate(self.committed);
+    }
+
+    /// Append the entries of a received dictionary section
+    ///
+    /// The section must continue exactly where the dictionary ends; an
+    /// update that skips or repeats entries means a frame was lost or
+    /// replayed, and fails with `Error::InvalidFrame`.
+    pub fn apply_update(&mut self, section: &[u8]) -> Result<()> {
+        if section.is_empty() {
+            return Ok(());
+        }
+        let mut cursor = Cursor::new(section);
+        let first = cursor.read_varint("dictionary index")?;
+        if first != self.entries.len() as u64 {
+            return Err(Error::InvalidFrame(format!(
+                "Dictionary update starts at entry {}, expected {}",
+                first,
+                self.entries.len()
+            )));
+        }
+
+        let count = cursor.read_len("dictionary update count")?;
+        let mut added = Vec::with_capacity(count);
+        let mut bytes = self.bytes;
+        for _ in 0..count {
+            let entry = cursor.read_str("dictionary entry")?;
+            bytes += entry.len();
+            if bytes > self.max_bytes {
+                return Err(Error::LengthLimitExceeded { len: bytes as u64, max: self.max_bytes });
+            }
+            if self.entries.id(entry).is_some() || added.contains(&entry) {
+                return Err(Error::InvalidFrame(format!("Duplicate dictionary entry {:?}", entry)));
+            }
+            added.push(entry);
+        }
+        if !cursor.is_empty() {
+            return Err(Error::InvalidFrame("Trailing bytes after dictionary update".into()));
+        }
+
+        for entry in added {
+            self.entries.get_or_add(entry);
+        }
+        self.bytes = bytes;
+        self.commit();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_dictionary_sync() {
+        let mut sender = ValueDictionary::new(1024);
+        let mut receiver = ValueDictionary::new(1024);
+
+        // First sighting is written inline, the second adds an entry
+        let mut buf = Vec::new();
+        sender.encode("ACTIVE", &mut buf);
+        assert_eq!(buf[0], 6 << 1);
+        buf.clear();
+        sender.encode("ACTIVE", &mut buf);
+        assert_eq!(buf, [1]);
+
+        let mut update = Vec::new();
+        sender.write_update(&mut update);
+        sender.commit();
+        receiver.apply_update(&update).unwrap();
+        assert_eq!(receiver.get(0), Some("ACTIVE"));
+
+        // Replaying the update is detected
+        assert!(matches!(receiver.apply_update(&update), Err(Error::InvalidFrame(_))));
+
+        // An uncommitted entry is dropped, and the next update starts after the commit
+        sender.encode("PENDING", &mut buf);
+        sender.encode("PENDING", &mut buf);
+        assert_eq!(sender.len(), 2);
+        sender.rollback();
+        assert_eq!(sender.len(), 1);
+        assert_eq!(sender.byte_size(), 6);
+        update.clear();
+        sender.write_update(&mut update);
+        assert!(update.is_empty());
+        receiver.apply_update(&update).unwrap();
+    }
+
+    #[test]
+    fn test_value_dictionary_limits() {
+        let mut dict = ValueDictionary::new(8);
+        let long = "x".repeat(MAX_VALUE_LEN + 1);
+        for s in [long.as_str(), long.as_str(), "abcdef", "abcdef", "ghijkl", "ghijkl"] {
+            dict.encode(s, &mut Vec::new());
+        }
+        // Too long, then over the byte limit
+        assert_eq!(dict.len(), 1);
+
+        // Received updates are held to the same limit
+        let mut receiver = ValueDictionary::new(4);
+        let mut update = Vec::new();
+        dict.write_update(&mut update);
+        assert!(matches!(receiver.apply_update(&update), Err(Error::LengthLimitExceeded { .. })));
+        assert!(receiver.is_empty());
+    }
+}
@@ -24,6 +24,9 @@ pub fn decode_varint(buf: &[u8]) -> Result<(u64, usize)> {
         }
 
         let byte = buf[pos];
+        if shift == 63 && byte & 0x7F > 1 {
+            return Err(Error::DecodeError("Varint overflows u64".into()));
+        }
         result |= ((byte & 0x7F) as u64) << shift;
         pos += 1;
 
@@ -89,6 +92,11 @@ mod tests {
             assert_eq!(decoded, value, "Failed for value {}", value);
             assert_eq!(len, buf.len());
         }
+
+        // A tenth byte above 1 would need more than 64 bits
+        let mut buf = vec![0xFF; 9];
+        buf.push(0x02);
+        assert!(decode_varint(&buf).is_err());
     }
 
     #[test]
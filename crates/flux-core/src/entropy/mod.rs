@@ -27,8 +27,19 @@ pub struct EntropyStats {
 /// - Symbols 0-14: single nibble (4 bits)
 /// - Symbol 15+: escape nibble + full byte index
 pub fn fse_compress(input: &[u8]) -> Result<Vec<u8>> {
+    let mut output = Vec::new();
+    fse_compress_into(input, &mut Vec::new(), &mut output)?;
+    Ok(output)
+}
+
+/// `fse_compress`, replacing the contents of `output`
+///
+/// `nibbles` is working space; passing the same buffer each time (as
+/// `output`) avoids allocating once both have grown.
+pub fn fse_compress_into(input: &[u8], nibbles: &mut Vec<u8>, output: &mut Vec<u8>) -> Result<()> {
+    output.clear();
     if input.is_empty() {
-        return Ok(Vec::new());
+        return Ok(());
     }
 
     // Build frequency table
@@ -38,22 +49,35 @@ pub fn fse_compress(input: &[u8]) -> Result<Vec<u8>> {
     }
 
     // Collect symbols with non-zero frequency
-    let mut symbols: Vec<u8> = (0..=255u8)
-        .filter(|&i| freq[i as usize] > 0)
-        .collect();
+    let mut table = [0u8; 256];
+    let mut count = 0;
+    for byte in 0..=255u8 {
+        if freq[byte as usize] > 0 {
+            table[count] = byte;
+            count += 1;
+        }
+    }
+    let symbols = &mut table[..count];
 
     // Special case: all same byte (extreme compression)
     if symbols.len() == 1 {
-        let mut output = Vec::with_capacity(7);
         output.push(ENTROPY_MAGIC);
         output.extend_from_slice(&(input.len() as u32).to_le_bytes());
         output.push(FLAG_SINGLE_SYMBOL);
         output.push(symbols[0]);
-        return Ok(output);
+        return Ok(());
+    }
+
+    // The symbol count is one byte; with every byte value in use the table
+    // alone outweighs any gain
+    if symbols.len() > u8::MAX as usize {
+        write_raw(input, output);
+        return Ok(());
     }
 
-    // Sort symbols by frequency (most frequent first for better nibble encoding)
-    symbols.sort_by(|a, b| freq[*b as usize].cmp(&freq[*a as usize]));
+    // Sort symbols by frequency (most frequent first for better nibble
+    // encoding), ties in byte order
+    symbols.sort_unstable_by(|a, b| freq[*b as usize].cmp(&freq[*a as usize]).then(a.cmp(b)));
 
     // Create symbol to index mapping
     let mut sym_to_idx = [0u8; 256];
@@ -62,7 +86,8 @@ pub fn fse_compress(input: &[u8]) -> Result<Vec<u8>> {
     }
 
     // Encode data using nibble packing
-    let mut nibbles = Vec::with_capacity(input.len() * 2);
+    nibbles.clear();
+    nibbles.reserve(input.len() * 2);
     for &byte in input {
         let idx = sym_to_idx[byte as usize];
         if idx < 15 {
@@ -76,39 +101,45 @@ pub fn fse_compress(input: &[u8]) -> Result<Vec<u8>> {
     }
 
     // Build output
-    let mut output = Vec::with_capacity(6 + symbols.len() + nibbles.len().div_ceil(2));
+    output.reserve(6 + symbols.len() + nibbles.len().div_ceil(2));
     output.push(ENTROPY_MAGIC);
     output.extend_from_slice(&(input.len() as u32).to_le_bytes());
     output.push(FLAG_NIBBLE_ENCODED);
 
     // Write symbol table
     output.push(symbols.len() as u8);
-    output.extend_from_slice(&symbols);
+    output.extend_from_slice(symbols);
 
     // Pack nibbles into bytes
-    let mut i = 0;
-    while i < nibbles.len() {
-        let high = nibbles[i];
-        let low = if i + 1 < nibbles.len() { nibbles[i + 1] } else { 0 };
-        output.push((high << 4) | low);
-        i += 2;
+    for pair in nibbles.chunks(2) {
+        let low = pair.get(1).copied().unwrap_or(0);
+        output.push((pair[0] << 4) | low);
     }
 
     // If nibble encoding is worse than raw, store raw instead
     if output.len() >= input.len() + 7 {
-        let mut output = Vec::with_capacity(6 + input.len());
-        output.push(ENTROPY_MAGIC);
-        output.extend_from_slice(&(input.len() as u32).to_le_bytes());
-        output.push(FLAG_RAW_STORAGE);
-        output.extend_from_slice(input);
-        return Ok(output);
+        write_raw(input, output);
     }
 
-    Ok(output)
+    Ok(())
+}
+
+/// Replace the contents of `output` with `input` stored raw
+fn write_raw(input: &[u8], output: &mut Vec<u8>) {
+    output.clear();
+    output.push(ENTROPY_MAGIC);
+    output.extend_from_slice(&(input.len() as u32).to_le_bytes());
+    output.push(FLAG_RAW_STORAGE);
+    output.extend_from_slice(input);
 }
 
 /// Decompress entropy-coded data
 pub fn fse_decompress(input: &[u8]) -> Result<Vec<u8>> {
+    fse_decompress_with_limit(input, usize::MAX)
+}
+
+/// Decompress entropy-coded data, refusing output longer than `limit` bytes
+pub fn fse_decompress_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>> {
     if input.is_empty() {
         return Ok(Vec::new());
     }
@@ -126,6 +157,9 @@ pub fn fse_decompress(input: &[u8]) -> Result<Vec<u8>> {
     if orig_len == 0 {
         return Ok(Vec::new());
     }
+    if orig_len > limit {
+        return Err(Error::OutputLimitExceeded { limit });
+    }
 
     let flag = input[5];
 
@@ -363,5 +397,12 @@ mod tests {
         let compressed = fse_compress(&data).unwrap();
         let decompressed = fse_decompress(&compressed).unwrap();
         assert_eq!(data, decompressed);
+
+        // Skewed enough to favour nibbles, with all 256 symbols present
+        let mut data = vec![b'a'; 10_000];
+        data.extend(0..=255u8);
+        let compressed = fse_compress(&data).unwrap();
+        let decompressed = fse_decompress(&compressed).unwrap();
+        assert_eq!(data, decompressed);
     }
 }
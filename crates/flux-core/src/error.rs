@@ -1,7 +1,48 @@
 //! FLUX error types
+//!
+//! Errors raised while decoding a frame are wrapped in `Error::Context`,
+//! recording the pipeline stage that failed and, where known, an offset:
+//! for `Stage::Encoding` the start of the failing field in the decoded
+//! payload, for other stages the start in the frame of the section they
+//! were reading (the schema section, the payload, ...). Offsets locate a
+//! field or section, not the failing byte within it. `Error::root` gives
+//! the underlying error for matching; `std::error::Error::source` walks the
+//! same chain.
+
+use std::fmt;
 
 use thiserror::Error;
 
+/// Pipeline stage an error was raised in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Frame header, sections and checksum
+    Frame,
+    /// Schema definitions and schema deltas
+    Schema,
+    /// LZ decompression
+    Lz,
+    /// Entropy decoding
+    Entropy,
+    /// Schema-driven value decoding
+    Encoding,
+    /// Delta decoding and application
+    Delta,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Frame => "frame",
+            Stage::Schema => "schema",
+            Stage::Lz => "lz",
+            Stage::Entropy => "entropy",
+            Stage::Encoding => "encoding",
+            Stage::Delta => "delta",
+        })
+    }
+}
+
 /// FLUX error type
 #[derive(Error, Debug)]
 pub enum Error {
@@ -11,9 +52,19 @@ pub enum Error {
     #[error("Unsupported version: {0}")]
     UnsupportedVersion(u8),
 
+    #[error("Unsupported capabilities: {0:#06x}")]
+    UnsupportedCapabilities(u16),
+
     #[error("Invalid frame: {0}")]
     InvalidFrame(String),
 
+    #[error("Truncated {what}: needed {needed} bytes, {available} available")]
+    Truncated {
+        what: &'static str,
+        needed: usize,
+        available: usize,
+    },
+
     #[error("Schema not found: {0}")]
     SchemaNotFound(u32),
 
@@ -32,6 +83,15 @@ pub enum Error {
     #[error("Checksum mismatch")]
     ChecksumMismatch,
 
+    #[error("Content hash mismatch: expected {expected:016x}, got {actual:016x}")]
+    ContentHashMismatch { expected: u64, actual: u64 },
+
+    #[error("Encryption key not found: {0}")]
+    KeyNotFound(u32),
+
+    #[error("Decryption failed: wrong key or tampered frame")]
+    DecryptionFailed,
+
     #[error("Buffer overflow")]
     BufferOverflow,
 
@@ -41,12 +101,113 @@ pub enum Error {
     #[error("State desync: expected hash {expected:016x}, got {actual:016x}")]
     StateDesync { expected: u64, actual: u64 },
 
+    /// A delta op doesn't fit the receiver's state; ask the sender for a
+    /// full update (`FluxStreamSession::force_full_next`)
+    #[error("Delta state mismatch: expected {expected}, got {got}")]
+    DeltaStateMismatch { expected: String, got: String },
+
     #[error("Unsupported type: {0}")]
     UnsupportedType(String),
 
+    #[error("Unsupported codec: {0}")]
+    UnsupportedCodec(String),
+
+    #[error("Invalid config `{field}`: {reason}")]
+    InvalidConfig { field: &'static str, reason: String },
+
+    #[error("Decompressed output exceeds limit of {limit} bytes")]
+    OutputLimitExceeded { limit: usize },
+
+    #[error("Length {len} exceeds limit of {max}")]
+    LengthLimitExceeded { len: u64, max: usize },
+
+    /// Objects and arrays nested deeper than the limit
+    #[error("Nesting exceeds depth limit of {max}")]
+    DepthLimitExceeded { max: usize },
+
+    /// Comparing two states went past a strict `DeltaLimits` limit
+    #[error("Delta exceeds {what} limit of {max}")]
+    DeltaLimitExceeded { what: &'static str, max: usize },
+
+    #[error("Unexpected {what}: expected {expected}, found {found}")]
+    Unexpected {
+        what: &'static str,
+        expected: String,
+        found: String,
+    },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+
+    /// `source` annotated with the stage that raised it
+    #[error("{stage} stage failed{}: {source}", .offset.map(|o| format!(" at byte {}", o)).unwrap_or_default())]
+    Context {
+        stage: Stage,
+        /// Start of the failing field or section (see the module docs)
+        offset: Option<usize>,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Record that the error was raised in `stage`, at `offset` if known
+    ///
+    /// An error that already has a stage keeps it, since the innermost
+    /// context is the most precise.
+    pub fn in_stage(self, stage: Stage, offset: Option<usize>) -> Self {
+        match self {
+            Error::Context { .. } => self,
+            source => Error::Context { stage, offset, source: Box::new(source) },
+        }
+    }
+
+    /// Stage the error was raised in, if recorded
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Error::Context { stage, .. } => Some(*stage),
+            _ => None,
+        }
+    }
+
+    /// Start of the failing field or section, if recorded (see the module docs)
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Context { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without stage context
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            _ => self,
+        }
+    }
 }
 
 /// FLUX result type
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_context() {
+        let err = Error::DecodeError("bad tag".into()).in_stage(Stage::Encoding, Some(17));
+        assert_eq!(err.to_string(), "encoding stage failed at byte 17: Decode error: bad tag");
+        assert_eq!((err.stage(), err.offset()), (Some(Stage::Encoding), Some(17)));
+
+        // The innermost stage wins
+        let err = err.in_stage(Stage::Lz, None);
+        assert_eq!(err.stage(), Some(Stage::Encoding));
+        assert!(matches!(err.root(), Error::DecodeError(_)));
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "Decode error: bad tag");
+
+        let err = Error::ChecksumMismatch.in_stage(Stage::Frame, None);
+        assert_eq!(err.to_string(), "frame stage failed: Checksum mismatch");
+        assert!(matches!(Error::InvalidMagic.root(), Error::InvalidMagic));
+    }
+}
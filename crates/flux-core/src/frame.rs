@@ -1,6 +1,11 @@
 //! FLUX frame format
 
-use crate::{Error, Result, FLUX_MAGIC, FLUX_VERSION};
+use std::io::{self, Read};
+
+use crate::config::DEFAULT_MAX_DECOMPRESSED_SIZE;
+use crate::cursor::Cursor;
+use crate::encoding::encode_varint;
+use crate::{Error, Result, FLUX_MAGIC, FLUX_VERSION, SUPPORTED_VERSIONS};
 use bitflags::bitflags;
 
 bitflags! {
@@ -17,61 +22,255 @@ bitflags! {
         const DELTA_MESSAGE = 0b0000_1000;
         /// CRC32 checksum included
         const CHECKSUM_PRESENT = 0b0001_0000;
-        /// Contains dictionary entries
+        /// Value dictionary section follows the schema; strings are dictionary-coded
         const DICTIONARY_UPDATE = 0b0010_0000;
         /// Part of streaming session
         const STREAMING = 0b0100_0000;
+        /// Key-value metadata section follows the header
+        const METADATA = 0b1000_0000;
+    }
+}
+
+bitflags! {
+    /// Optional features a frame relies on (format 2.1 and later)
+    ///
+    /// Unlike `FrameFlags`, every bit is mandatory: a decoder that finds a
+    /// bit it doesn't know rejects the frame rather than misreading it, so
+    /// features can be added in a minor version. 2.0 frames have no
+    /// capability field and read as empty.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Capabilities: u16 {
+        /// Sections after the header are sealed with XChaCha20-Poly1305,
+        /// behind an encryption section (see `Encrypted`)
+        const ENCRYPTED = 0x0001;
+    }
+}
+
+/// Version 2.0, whose header has no capability field
+pub const VERSION_2_0: u8 = 0x20;
+
+/// Size of the fixed frame header after the magic bytes, at `FLUX_VERSION`
+///
+/// The CRC32C checksum (when present) is appended as a trailer after the
+/// payload rather than stored in the header.
+pub const HEADER_SIZE: usize = 12;
+
+/// Size of the fixed frame header of a 2.0 frame
+pub const HEADER_SIZE_2_0: usize = 10;
+
+/// Bit of the version byte marking a compact frame
+///
+/// ```text
+/// FLUX_VERSION | 0x80 | flags | schema_id (varint) | payload_len (varint) | sections | [checksum]
+/// ```
+///
+/// A compact frame has no magic: it starts with its version byte, which
+/// can't be mistaken for the `F` of `FLUX_MAGIC`. The schema ID and payload
+/// length are varints, one byte each below 128, so a cached-schema message
+/// carries 4 bytes of framing, 8 with the checksum trailer (see
+/// `FluxConfig::compact_header`). There is no capability field: sealed
+/// frames keep the full header. Without the magic, `ContentEncoding::detect`
+/// recognizes compact frames by their version byte.
+pub const COMPACT_HEADER: u8 = 0x80;
+
+/// Bit of a compact version byte marking a raw payload, the message's JSON text
+///
+/// ```text
+/// FLUX_VERSION | 0x80 | 0x40 | flags | payload_len (varint) | JSON | [checksum]
+/// ```
+///
+/// Tiny messages often gain nothing from the pipeline, so they are sent as
+/// they are when that is smaller (see `FluxConfig::raw_threshold`): 3 bytes
+/// of framing below 128 bytes of JSON, 7 with the checksum. Only
+/// `CHECKSUM_PRESENT` may be set, and there is no schema ID (it reads as 0).
+/// `ContentEncoding::detect` also checks the flags byte of raw frames.
+pub const RAW_PAYLOAD: u8 = 0x40;
+
+/// Size of the CRC32C trailer
+pub const CHECKSUM_SIZE: usize = 4;
+
+/// Size of the XChaCha20-Poly1305 nonce of an encrypted frame
+pub const NONCE_SIZE: usize = 24;
+
+/// Size of the authentication tag ending the ciphertext of an encrypted frame
+pub const TAG_SIZE: usize = 16;
+
+/// Size of the fixed header of a frame of `version`
+pub fn header_size(version: u8) -> usize {
+    if version == VERSION_2_0 {
+        HEADER_SIZE_2_0
+    } else {
+        HEADER_SIZE
     }
 }
 
 /// FLUX frame header
+///
+/// ```text
+/// 2.1: version | flags | capabilities (u16) | schema_id (u32) | payload_len (u32)
+/// 2.0: version | flags |                      schema_id (u32) | payload_len (u32)
+/// ```
+///
+/// Integers are little-endian.
 #[derive(Debug, Clone)]
 pub struct FrameHeader {
     pub version: u8,
     pub flags: FrameFlags,
+    pub capabilities: Capabilities,
     pub schema_id: u32,
     pub payload_len: u32,
     pub checksum: Option<u32>,
 }
 
 impl FrameHeader {
+    /// Header of a frame at the current `FLUX_VERSION`
+    pub fn new(flags: FrameFlags, schema_id: u32, payload_len: u32) -> Self {
+        Self {
+            version: FLUX_VERSION,
+            flags,
+            capabilities: Capabilities::empty(),
+            schema_id,
+            payload_len,
+            checksum: None,
+        }
+    }
+
     /// Parse header from bytes (after magic)
+    ///
+    /// `checksum` is left unset; it lives in the frame trailer.
     pub fn parse(buf: &[u8]) -> Result<Self> {
-        if buf.len() < 14 {
-            return Err(Error::InvalidFrame("Header too short".into()));
-        }
+        Self::read(&mut Cursor::new(buf))
+    }
 
-        let version = buf[0];
-        if version != FLUX_VERSION {
+    /// Read the header at the cursor
+    ///
+    /// Versions outside `SUPPORTED_VERSIONS` fail with `UnsupportedVersion`
+    /// and unknown capability bits with `UnsupportedCapabilities`, before
+    /// anything after them is read.
+    pub fn read(cursor: &mut Cursor<'_>) -> Result<Self> {
+        let version = cursor.read_u8("frame header")?;
+        if version & COMPACT_HEADER != 0 {
+            return Self::read_compact(cursor, version);
+        }
+        if !SUPPORTED_VERSIONS.contains(&version) {
             return Err(Error::UnsupportedVersion(version));
         }
 
-        let flags = FrameFlags::from_bits_truncate(buf[1]);
-
-        let schema_id = u32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
-        let payload_len = u32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]);
+        let flags = FrameFlags::from_bits_truncate(cursor.read_u8("frame header")?);
 
-        let checksum = if flags.contains(FrameFlags::CHECKSUM_PRESENT) {
-            Some(u32::from_le_bytes([buf[10], buf[11], buf[12], buf[13]]))
+        let capabilities = if version == VERSION_2_0 {
+            Capabilities::empty()
         } else {
-            None
+            let bits = cursor.read_u16_le("frame header")?;
+            Capabilities::from_bits(bits).ok_or(Error::UnsupportedCapabilities(bits))?
         };
 
+        let schema_id = cursor.read_u32_le("frame header")?;
+        let payload_len = cursor.read_u32_le("frame header")?;
+
+        Ok(Self {
+            version,
+            flags,
+            capabilities,
+            schema_id,
+            payload_len,
+            checksum: None,
+        })
+    }
+
+    /// Rest of a compact header, after its version byte
+    fn read_compact(cursor: &mut Cursor<'_>, version: u8) -> Result<Self> {
+        if version & !(COMPACT_HEADER | RAW_PAYLOAD) != FLUX_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let flags = FrameFlags::from_bits_truncate(cursor.read_u8("frame header")?);
+        let raw = version & RAW_PAYLOAD != 0;
+        if raw && !FrameFlags::CHECKSUM_PRESENT.contains(flags) {
+            return Err(Error::InvalidFrame(format!("Raw frame with flags {:#04x}", flags.bits())));
+        }
+        let schema_id = if raw {
+            0
+        } else {
+            u32::try_from(cursor.read_varint("schema id")?)
+                .map_err(|_| Error::InvalidFrame("Schema id out of range".into()))?
+        };
+        let payload_len = u32::try_from(cursor.read_varint("payload length")?)
+            .map_err(|_| Error::InvalidFrame("Payload length out of range".into()))?;
         Ok(Self {
             version,
             flags,
+            capabilities: Capabilities::empty(),
             schema_id,
             payload_len,
-            checksum,
+            checksum: None,
         })
     }
 
+    /// Whether this is a compact header (`COMPACT_HEADER`)
+    pub fn is_compact(&self) -> bool {
+        self.version & COMPACT_HEADER != 0
+    }
+
+    /// Whether the payload is the message's JSON text (`RAW_PAYLOAD`)
+    pub fn is_raw(&self) -> bool {
+        self.is_compact() && self.version & RAW_PAYLOAD != 0
+    }
+
+    /// Format version, without the compact and raw bits
+    pub fn format_version(&self) -> u8 {
+        if self.is_compact() {
+            self.version & !(COMPACT_HEADER | RAW_PAYLOAD)
+        } else {
+            self.version
+        }
+    }
+
+    /// Bytes of magic before this header: none for a compact header
+    pub fn magic_size(&self) -> usize {
+        if self.is_compact() {
+            0
+        } else {
+            FLUX_MAGIC.len()
+        }
+    }
+
+    /// Size of this header in bytes
+    pub fn size(&self) -> usize {
+        if self.is_compact() {
+            self.to_bytes().len()
+        } else {
+            header_size(self.version)
+        }
+    }
+
+    /// Header bytes (without magic or checksum)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        self.write_into(&mut bytes);
+        bytes
+    }
+
+    /// Append the header bytes (without magic or checksum) to `bytes`
+    pub fn write_into(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.version);
+        bytes.push(self.flags.bits());
+        if self.is_compact() {
+            if !self.is_raw() {
+                encode_varint(self.schema_id as u64, bytes);
+            }
+            encode_varint(self.payload_len as u64, bytes);
+            return;
+        }
+        if self.version != VERSION_2_0 {
+            bytes.extend_from_slice(&self.capabilities.bits().to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.schema_id.to_le_bytes());
+        bytes.extend_from_slice(&self.payload_len.to_le_bytes());
+    }
+
     /// Serialize header to bytes
     pub fn serialize(&self, buf: &mut Vec<u8>) {
-        buf.push(self.version);
-        buf.push(self.flags.bits());
-        buf.extend_from_slice(&self.schema_id.to_le_bytes());
-        buf.extend_from_slice(&self.payload_len.to_le_bytes());
+        buf.extend_from_slice(&self.to_bytes());
 
         if let Some(checksum) = self.checksum {
             buf.extend_from_slice(&checksum.to_le_bytes());
@@ -79,6 +278,339 @@ impl FrameHeader {
     }
 }
 
+/// Highest format version both peers support
+///
+/// `offered` lists the versions a peer reads (its `SUPPORTED_VERSIONS`),
+/// exchanged out of band, e.g. in a handshake or frame metadata. `None`
+/// means the peers share no version and cannot talk FLUX.
+pub fn negotiate_version(offered: &[u8]) -> Option<u8> {
+    SUPPORTED_VERSIONS.iter().rev().find(|v| offered.contains(v)).copied()
+}
+
+/// Key-value metadata carried in a frame (trace id, producer id, timestamp...)
+///
+/// The section sits right after the header, so it can be read with
+/// `read_metadata` without touching the schema or payload.
+///
+/// ```text
+/// count (varint) | { key_len (varint) | key | value_len (varint) | value }*
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameMetadata {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+impl FrameMetadata {
+    /// Create empty metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` to `value`, replacing any previous value
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> &mut Self {
+        let key = key.into();
+        let value = value.into();
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key, value)),
+        }
+        self
+    }
+
+    /// Raw value for `key`
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// Value for `key` as UTF-8
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(|v| std::str::from_utf8(v).ok())
+    }
+
+    /// Entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Serialize the section
+    pub fn serialize(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.entries.len() as u64, buf);
+        for (key, value) in &self.entries {
+            encode_varint(key.len() as u64, buf);
+            buf.extend_from_slice(key.as_bytes());
+            encode_varint(value.len() as u64, buf);
+            buf.extend_from_slice(value);
+        }
+    }
+
+    /// Parse the section, returning it and the number of bytes consumed
+    pub fn parse(buf: &[u8]) -> Result<(Self, usize)> {
+        let mut cursor = Cursor::new(buf);
+        let metadata = Self::read(&mut cursor)?;
+        Ok((metadata, cursor.position()))
+    }
+
+    /// Read the section at the cursor
+    pub fn read(cursor: &mut Cursor<'_>) -> Result<Self> {
+        let count = cursor.read_varint("metadata count")?;
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let key = cursor.read_str("metadata key")?.to_string();
+            let value = cursor.read_len_bytes("metadata value")?.to_vec();
+            entries.push((key, value));
+        }
+
+        Ok(Self { entries })
+    }
+}
+
+/// Sections of a complete frame, located but not decoded
+///
+/// Parsing checks every length against the input, so a truncated or
+/// corrupted frame fails here with a typed error before any decoding.
+#[derive(Debug, Clone)]
+pub struct RawFrame<'a> {
+    pub header: FrameHeader,
+    /// Metadata section (empty when the frame has none)
+    pub metadata: FrameMetadata,
+    /// Encoded size of the metadata section
+    pub metadata_size: usize,
+    /// Serialized schema, without its length prefix
+    pub schema: Option<&'a [u8]>,
+    /// Encoded size of the schema section, length prefix included
+    pub schema_size: usize,
+    /// Value dictionary update, without its length prefix
+    pub dictionary: Option<&'a [u8]>,
+    /// Encoded size of the dictionary section, length prefix included
+    pub dictionary_size: usize,
+    pub payload: &'a [u8],
+    /// Stored CRC32C trailer
+    pub checksum: Option<u32>,
+    /// Bytes covered by the checksum (everything after the magic)
+    pub checksummed: &'a [u8],
+    /// The whole frame, magic to checksum
+    pub bytes: &'a [u8],
+    /// Encryption section, if the frame is encrypted
+    ///
+    /// The sections of an encrypted frame are sealed in its ciphertext, so
+    /// `metadata`, `schema`, `dictionary` and `payload` are left empty.
+    pub encrypted: Option<Encrypted<'a>>,
+}
+
+/// Encryption section of a frame with `Capabilities::ENCRYPTED`
+///
+/// ```text
+/// header | key_id (u32) | nonce (24B) | ciphertext_len (u32) | ciphertext | checksum
+/// ```
+///
+/// The header keeps the flags and `payload_len` of the plain frame, with
+/// `CHECKSUM_PRESENT` describing the outer checksum. The ciphertext seals
+/// everything the plain frame has between header and checksum (metadata,
+/// schema, dictionary and payload), authenticating the header and
+/// encryption section with it.
+#[derive(Debug, Clone, Copy)]
+pub struct Encrypted<'a> {
+    /// Key the frame was sealed with, resolved by the receiver's key provider
+    pub key_id: u32,
+    pub nonce: &'a [u8],
+    /// Sealed sections followed by the authentication tag
+    pub ciphertext: &'a [u8],
+    /// Header and encryption section, authenticated with the ciphertext
+    pub associated_data: &'a [u8],
+}
+
+impl<'a> RawFrame<'a> {
+    /// Split a frame into its sections
+    ///
+    /// `frame` must hold exactly one frame.
+    pub fn parse(frame: &'a [u8]) -> Result<Self> {
+        let (raw, len) = Self::parse_prefix(frame)?;
+        if len != frame.len() {
+            return Err(Error::InvalidFrame(format!("{} trailing bytes after frame", frame.len() - len)));
+        }
+        Ok(raw)
+    }
+
+    /// Split the frame at the start of `buf` into its sections
+    ///
+    /// The end of the frame follows from its section lengths and
+    /// `payload_len`, so `buf` may continue with more frames. Returns the
+    /// frame and its length.
+    pub fn parse_prefix(buf: &'a [u8]) -> Result<(Self, usize)> {
+        let mut cursor = Cursor::new(buf);
+        // Compact frames start with their version byte instead of the magic
+        let compact = buf.first().is_some_and(|&byte| byte & COMPACT_HEADER != 0);
+        if !compact && cursor.read_array::<4>("magic")? != FLUX_MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let header = FrameHeader::read(&mut cursor)?;
+        if header.is_compact() != compact {
+            return Err(Error::InvalidFrame("Compact header after the magic".into()));
+        }
+        if header.capabilities.contains(Capabilities::ENCRYPTED) {
+            return Self::parse_encrypted(buf, cursor, header);
+        }
+        let sections_start = cursor.position();
+
+        let metadata = if header.flags.contains(FrameFlags::METADATA) {
+            FrameMetadata::read(&mut cursor)?
+        } else {
+            FrameMetadata::new()
+        };
+        let metadata_size = cursor.position() - sections_start;
+
+        let schema = if header.flags.contains(FrameFlags::SCHEMA_INCLUDED) {
+            Some(cursor.read_len_bytes("schema")?)
+        } else {
+            None
+        };
+        let schema_size = cursor.position() - sections_start - metadata_size;
+
+        let dictionary = if header.flags.contains(FrameFlags::DICTIONARY_UPDATE) {
+            Some(cursor.read_len_bytes("dictionary update")?)
+        } else {
+            None
+        };
+        let dictionary_size = cursor.position() - sections_start - metadata_size - schema_size;
+
+        let payload = cursor.read_bytes(header.payload_len as usize, "payload")?;
+        let (checksum, checksummed, len) = Self::read_checksum(buf, &mut cursor, &header)?;
+
+        Ok((
+            Self {
+                header,
+                metadata,
+                metadata_size,
+                schema,
+                schema_size,
+                dictionary,
+                dictionary_size,
+                payload,
+                checksum,
+                checksummed,
+                bytes: &buf[..len],
+                encrypted: None,
+            },
+            len,
+        ))
+    }
+
+    /// Rest of `parse_prefix` for an encrypted frame, the cursor after its header
+    fn parse_encrypted(buf: &'a [u8], mut cursor: Cursor<'a>, header: FrameHeader) -> Result<(Self, usize)> {
+        let key_id = cursor.read_u32_le("encryption section")?;
+        let nonce = cursor.read_bytes(NONCE_SIZE, "encryption section")?;
+        let ciphertext_len = cursor.read_u32_le("encryption section")? as usize;
+        let associated_data = &buf[FLUX_MAGIC.len()..cursor.position()];
+        let ciphertext = cursor.read_bytes(ciphertext_len, "ciphertext")?;
+        if ciphertext.len() < TAG_SIZE {
+            return Err(Error::InvalidFrame(format!("{}-byte ciphertext is shorter than its tag", ciphertext.len())));
+        }
+        let (checksum, checksummed, len) = Self::read_checksum(buf, &mut cursor, &header)?;
+
+        Ok((
+            Self {
+                header,
+                metadata: FrameMetadata::new(),
+                metadata_size: 0,
+                schema: None,
+                schema_size: 0,
+                dictionary: None,
+                dictionary_size: 0,
+                payload: &[],
+                checksum,
+                checksummed,
+                bytes: &buf[..len],
+                encrypted: Some(Encrypted { key_id, nonce, ciphertext, associated_data }),
+            },
+            len,
+        ))
+    }
+
+    /// Read the checksum trailer at the cursor, returning it with the bytes
+    /// it covers (everything after the magic) and the frame length
+    fn read_checksum(buf: &'a [u8], cursor: &mut Cursor<'a>, header: &FrameHeader) -> Result<(Option<u32>, &'a [u8], usize)> {
+        let checksummed = &buf[header.magic_size()..cursor.position()];
+
+        // The checksum trailer is not part of the payload
+        let checksum = if header.flags.contains(FrameFlags::CHECKSUM_PRESENT) {
+            Some(cursor.read_u32_le("checksum")?)
+        } else {
+            None
+        };
+        Ok((checksum, checksummed, cursor.position()))
+    }
+
+    /// The plain frame an encrypted frame seals, given its decrypted sections
+    ///
+    /// The result has no checksum: the authentication tag already vouched
+    /// for the plaintext.
+    pub fn decrypted_frame(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut header = self.header.clone();
+        header.capabilities.remove(Capabilities::ENCRYPTED);
+        header.flags.remove(FrameFlags::CHECKSUM_PRESENT);
+        let mut frame = FLUX_MAGIC.to_vec();
+        frame.extend_from_slice(&header.to_bytes());
+        frame.extend_from_slice(plaintext);
+        frame
+    }
+
+    /// Check the stored checksum, if the frame has one
+    pub fn verify_checksum(&self) -> Result<()> {
+        match self.checksum {
+            Some(checksum) if crc32c::crc32c(self.checksummed) != checksum => Err(Error::ChecksumMismatch),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Metadata key of a control frame asking the peer for schemas by ID
+pub const SCHEMA_REQUEST_KEY: &str = "flux-schema-request";
+
+/// Metadata key of a control frame carrying requested schemas
+pub const SCHEMA_REPLY_KEY: &str = "flux-schema-reply";
+
+/// Build a control frame: metadata only, with schema ID 0 and no payload
+///
+/// Control frames are exchanged beside data frames (e.g. schema requests)
+/// and are never passed to `decompress`.
+pub fn control_frame(metadata: &FrameMetadata, checksum: bool) -> Vec<u8> {
+    let mut flags = FrameFlags::METADATA;
+    if checksum {
+        flags |= FrameFlags::CHECKSUM_PRESENT;
+    }
+    let header = FrameHeader::new(flags, 0, 0);
+
+    let mut frame = FLUX_MAGIC.to_vec();
+    frame.extend_from_slice(&header.to_bytes());
+    metadata.serialize(&mut frame);
+    if checksum {
+        let crc = crc32c::crc32c(&frame[FLUX_MAGIC.len()..]);
+        frame.extend_from_slice(&crc.to_le_bytes());
+    }
+    frame
+}
+
+/// Read the metadata section of a complete frame without decoding it
+///
+/// Returns empty metadata for frames without a metadata section.
+pub fn read_metadata(frame: &[u8]) -> Result<FrameMetadata> {
+    Ok(RawFrame::parse(frame)?.metadata)
+}
+
 /// Frame writer
 #[allow(dead_code)]
 pub struct FrameWriter {
@@ -120,63 +652,124 @@ impl Default for FrameWriter {
     }
 }
 
-/// Frame reader
-pub struct FrameReader {
+/// Bytes still missing before `buf` starts with a complete frame
+///
+/// Returns `None` once `buf` holds a whole frame, and also when it can't
+/// start one at all (bad magic, unsupported version, ...), leaving the
+/// error to `RawFrame::parse_prefix`. Otherwise the count is a lower bound:
+/// after that many more bytes the frame is either complete or reveals the
+/// next section length. This is the check a tokio `Decoder` makes before
+/// splitting a frame off its buffer.
+pub fn needs_more_bytes(buf: &[u8]) -> Option<usize> {
+    match RawFrame::parse_prefix(buf) {
+        Err(Error::Truncated { needed, available, .. }) => Some(needed.saturating_sub(available).max(1)),
+        _ => None,
+    }
+}
+
+/// Reads frames stored back to back, e.g. a file of appended messages
+///
+/// Iterating yields each frame in turn, with its section lengths checked
+/// and its checksum (if any) verified. After an error the start of the
+/// next frame is unknown, so iteration stops there.
+///
+/// ```rust,ignore
+/// for frame in FrameReader::new(&log) {
+///     let frame = frame?;
+///     println!("schema {} ({} bytes)", frame.header.schema_id, frame.bytes.len());
+/// }
+/// ```
+pub struct FrameReader<'a> {
+    buf: &'a [u8],
     pos: usize,
+    failed: bool,
 }
 
-impl FrameReader {
-    pub fn new() -> Self {
-        Self { pos: 0 }
+impl<'a> FrameReader<'a> {
+    /// Read frames from `buf`
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0, failed: false }
     }
 
     /// Read and validate magic
-    pub fn read_magic(&mut self, buf: &[u8]) -> Result<()> {
-        if buf.len() < 4 {
-            return Err(Error::InvalidFrame("Too short for magic".into()));
-        }
-
-        if buf[0..4] != FLUX_MAGIC {
+    pub fn read_magic(&mut self) -> Result<()> {
+        let mut cursor = self.cursor();
+        if cursor.read_array::<4>("magic")? != FLUX_MAGIC {
             return Err(Error::InvalidMagic);
         }
 
-        self.pos = 4;
+        self.pos += cursor.position();
         Ok(())
     }
 
     /// Read header
-    pub fn read_header(&mut self, buf: &[u8]) -> Result<FrameHeader> {
-        let header = FrameHeader::parse(&buf[self.pos..])?;
-        self.pos += 14;
+    pub fn read_header(&mut self) -> Result<FrameHeader> {
+        let mut cursor = self.cursor();
+        let header = FrameHeader::read(&mut cursor)?;
+        self.pos += cursor.position();
         Ok(header)
     }
 
     /// Read varint
-    pub fn read_varint(&mut self, buf: &[u8]) -> Result<u64> {
-        let mut result: u64 = 0;
-        let mut shift = 0;
-
-        loop {
-            if self.pos >= buf.len() {
-                return Err(Error::InvalidFrame("Varint truncated".into()));
-            }
+    pub fn read_varint(&mut self) -> Result<u64> {
+        let mut cursor = self.cursor();
+        let value = cursor.read_varint("varint")?;
+        self.pos += cursor.position();
+        Ok(value)
+    }
 
-            let byte = buf[self.pos];
-            self.pos += 1;
+    /// Read the next complete frame, or `None` at the end of the buffer
+    pub fn next_frame(&mut self) -> Result<Option<RawFrame<'a>>> {
+        let rest = self.buf.get(self.pos..).unwrap_or_default();
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let (frame, len) = RawFrame::parse_prefix(rest)?;
+        frame.verify_checksum()?;
+        self.pos += len;
+        Ok(Some(frame))
+    }
 
-            result |= ((byte & 0x7F) as u64) << shift;
+    /// Read exactly one frame from a stream such as a TCP socket
+    ///
+    /// Bytes are read as the frame's sections reveal its length, never past
+    /// its end, so the next call starts at the next frame. Returns `None`
+    /// if the stream ends before a frame starts; ending inside one is an
+    /// `io::ErrorKind::UnexpectedEof` error. Frames claiming more than
+    /// `DEFAULT_MAX_DECOMPRESSED_SIZE` bytes fail with
+    /// `Error::LengthLimitExceeded` before they are read.
+    pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+        Self::read_frame_with_limit(reader, DEFAULT_MAX_DECOMPRESSED_SIZE)
+    }
 
-            if byte & 0x80 == 0 {
-                break;
+    /// `read_frame` for frames of at most `limit` bytes
+    pub fn read_frame_with_limit<R: Read>(reader: &mut R, limit: usize) -> Result<Option<Vec<u8>>> {
+        let mut frame = vec![0u8];
+        loop {
+            match reader.read(&mut frame) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
             }
+        }
 
-            shift += 7;
-            if shift > 63 {
-                return Err(Error::InvalidFrame("Varint too long".into()));
+        while let Some(more) = needs_more_bytes(&frame) {
+            let start = frame.len();
+            let len = start + more;
+            if len > limit {
+                return Err(Error::LengthLimitExceeded { len: len as u64, max: limit });
             }
+            frame.resize(len, 0);
+            reader.read_exact(&mut frame[start..])?;
         }
+        RawFrame::parse(&frame)?.verify_checksum()?;
+        Ok(Some(frame))
+    }
 
-        Ok(result)
+    /// Cursor over the unread part of the buffer
+    fn cursor(&self) -> Cursor<'a> {
+        Cursor::new(self.buf.get(self.pos..).unwrap_or_default())
     }
 
     /// Current position
@@ -185,9 +778,16 @@ impl FrameReader {
     }
 }
 
-impl Default for FrameReader {
-    fn default() -> Self {
-        Self::new()
+impl<'a> Iterator for FrameReader<'a> {
+    type Item = Result<RawFrame<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_frame().transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
     }
 }
 
@@ -199,6 +799,7 @@ mod tests {
     fn test_header_roundtrip() {
         let header = FrameHeader {
             version: FLUX_VERSION,
+            capabilities: Capabilities::empty(),
             flags: FrameFlags::SCHEMA_INCLUDED | FrameFlags::COLUMNAR,
             schema_id: 42,
             payload_len: 1024,
@@ -216,10 +817,88 @@ mod tests {
         assert_eq!(parsed.payload_len, header.payload_len);
     }
 
+    #[test]
+    fn test_header_versions() {
+        let header = FrameHeader::new(FrameFlags::CHECKSUM_PRESENT, 7, 3);
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE);
+
+        // 2.0 headers have no capability field
+        let old = FrameHeader { version: VERSION_2_0, ..header.clone() };
+        let old_bytes = old.to_bytes();
+        assert_eq!(old_bytes.len(), HEADER_SIZE_2_0);
+        let parsed = FrameHeader::parse(&old_bytes).unwrap();
+        assert_eq!((parsed.version, parsed.schema_id, parsed.payload_len), (VERSION_2_0, 7, 3));
+        assert_eq!(parsed.size(), HEADER_SIZE_2_0);
+
+        // Later versions and unknown capabilities are rejected, not misread
+        let mut future = bytes.clone();
+        future[0] = FLUX_VERSION + 1;
+        assert!(matches!(FrameHeader::parse(&future), Err(Error::UnsupportedVersion(v)) if v == FLUX_VERSION + 1));
+        let mut unknown = bytes;
+        unknown[3] = 0x80;
+        assert!(matches!(FrameHeader::parse(&unknown), Err(Error::UnsupportedCapabilities(0x8000))));
+    }
+
+    #[test]
+    fn test_compact_header() {
+        let full = FrameHeader::new(FrameFlags::CHECKSUM_PRESENT | FrameFlags::FSE_COMPRESSED, 3, 90);
+        let header = FrameHeader { version: FLUX_VERSION | COMPACT_HEADER, ..full };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(header.size(), 4);
+        let parsed = FrameHeader::parse(&bytes).unwrap();
+        assert!(parsed.is_compact());
+        assert_eq!((parsed.flags, parsed.schema_id, parsed.payload_len), (header.flags, 3, 90));
+
+        // Large values take longer varints
+        let large = FrameHeader { schema_id: 70_000, payload_len: 1 << 20, ..header };
+        let parsed = FrameHeader::parse(&large.to_bytes()).unwrap();
+        assert_eq!((parsed.schema_id, parsed.payload_len, parsed.size()), (70_000, 1 << 20, 8));
+
+        // Only the current version has a compact form
+        let old = [VERSION_2_0 | COMPACT_HEADER, 0, 0, 0];
+        assert!(matches!(FrameHeader::parse(&old), Err(Error::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_raw_header() {
+        let version = FLUX_VERSION | COMPACT_HEADER | RAW_PAYLOAD;
+        let header = FrameHeader { version, ..FrameHeader::new(FrameFlags::CHECKSUM_PRESENT, 0, 300) };
+        let bytes = header.to_bytes();
+        assert_eq!(bytes, [version, FrameFlags::CHECKSUM_PRESENT.bits(), 0xAC, 0x02]);
+        assert_eq!(header.size(), 4);
+        let parsed = FrameHeader::parse(&bytes).unwrap();
+        assert!(parsed.is_raw());
+        assert_eq!((parsed.format_version(), parsed.flags, parsed.payload_len), (FLUX_VERSION, header.flags, 300));
+
+        // Raw frames have no sections
+        let bad = [version, FrameFlags::METADATA.bits(), 0];
+        assert!(matches!(FrameHeader::parse(&bad), Err(Error::InvalidFrame(_))));
+    }
+
+    #[test]
+    fn test_compact_frame_has_no_magic() {
+        let header = FrameHeader { version: FLUX_VERSION | COMPACT_HEADER, ..FrameHeader::new(FrameFlags::CHECKSUM_PRESENT, 1, 2) };
+        let mut frame = header.to_bytes();
+        frame.extend_from_slice(&[7, 8]);
+        let checksum = crc32c::crc32c(&frame);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(frame.len(), 4 + 2 + CHECKSUM_SIZE);
+
+        let raw = RawFrame::parse(&frame).unwrap();
+        assert_eq!((raw.header.magic_size(), raw.payload), (0, [7, 8].as_slice()));
+        raw.verify_checksum().unwrap();
+
+        // The magic and a compact header don't mix
+        let mut magic = FLUX_MAGIC.to_vec();
+        magic.extend_from_slice(&frame);
+        assert!(matches!(RawFrame::parse(&magic), Err(Error::InvalidFrame(_))));
+    }
+
     #[test]
     fn test_varint_roundtrip() {
         let writer = FrameWriter::new();
-        let mut reader = FrameReader::new();
 
         let values = [0u64, 1, 127, 128, 255, 256, 16383, 16384, u64::MAX];
 
@@ -227,10 +906,121 @@ mod tests {
             let mut buf = Vec::new();
             writer.write_varint(value, &mut buf);
 
-            reader.pos = 0;
-            let decoded = reader.read_varint(&buf).unwrap();
+            let decoded = FrameReader::new(&buf).read_varint().unwrap();
 
             assert_eq!(decoded, value, "Failed for value {}", value);
         }
     }
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let mut metadata = FrameMetadata::new();
+        metadata.insert("trace-id", "abc123").insert("ts", 1700000000u64.to_le_bytes().to_vec());
+        metadata.insert("trace-id", "def456");
+
+        let mut buf = Vec::new();
+        metadata.serialize(&mut buf);
+        let (parsed, used) = FrameMetadata::parse(&buf).unwrap();
+
+        assert_eq!(used, buf.len());
+        assert_eq!(parsed, metadata);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed.get_str("trace-id"), Some("def456"));
+        assert_eq!(parsed.get("ts"), Some(1700000000u64.to_le_bytes().as_slice()));
+        assert_eq!(parsed.get("missing"), None);
+    }
+
+    #[test]
+    fn test_header_truncated() {
+        let header = FrameHeader {
+            version: FLUX_VERSION,
+            capabilities: Capabilities::empty(),
+            flags: FrameFlags::empty(),
+            schema_id: 1,
+            payload_len: 0,
+            checksum: None,
+        };
+        let bytes = header.to_bytes();
+        for cut in 0..HEADER_SIZE {
+            assert!(matches!(
+                FrameHeader::parse(&bytes[..cut]),
+                Err(Error::Truncated { what: "frame header", .. })
+            ));
+        }
+
+        let mut reader = FrameReader::new(&bytes);
+        reader.pos = 100;
+        assert!(reader.read_header().is_err());
+    }
+
+    #[test]
+    fn test_raw_frame_sections() {
+        let mut metadata = FrameMetadata::new();
+        metadata.insert("k", "v");
+        let header = FrameHeader {
+            version: FLUX_VERSION,
+            capabilities: Capabilities::empty(),
+            flags: FrameFlags::METADATA | FrameFlags::SCHEMA_INCLUDED | FrameFlags::CHECKSUM_PRESENT,
+            schema_id: 3,
+            payload_len: 2,
+            checksum: None,
+        };
+        let mut frame = FLUX_MAGIC.to_vec();
+        frame.extend_from_slice(&header.to_bytes());
+        metadata.serialize(&mut frame);
+        frame.extend_from_slice(&[3, 0xAA, 0xBB, 0xCC]);
+        frame.extend_from_slice(&[1, 2]);
+        let checksum = crc32c::crc32c(&frame[4..]);
+        frame.extend_from_slice(&checksum.to_le_bytes());
+
+        let raw = RawFrame::parse(&frame).unwrap();
+        assert_eq!(raw.metadata, metadata);
+        assert_eq!(raw.schema, Some([0xAA, 0xBB, 0xCC].as_slice()));
+        assert_eq!(raw.schema_size, 4);
+        assert_eq!(raw.payload, [1, 2]);
+        assert_eq!(raw.checksum, Some(checksum));
+        assert_eq!(crc32c::crc32c(raw.checksummed), checksum);
+
+        for cut in 0..frame.len() - 2 {
+            assert!(RawFrame::parse(&frame[..cut]).is_err(), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn test_frame_reader() {
+        let mut metadata = FrameMetadata::new();
+        metadata.insert("k", "v");
+        let mut stream = control_frame(&metadata, true);
+        let first_len = stream.len();
+        stream.extend_from_slice(&control_frame(&FrameMetadata::new(), false));
+
+        let frames: Vec<RawFrame> = FrameReader::new(&stream).collect::<Result<_>>().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].metadata, metadata);
+        assert_eq!(frames[0].bytes, &stream[..first_len]);
+        assert!(frames[1].checksum.is_none());
+        assert!(FrameReader::new(&[]).next().is_none());
+
+        // A single frame must not be followed by anything
+        assert!(matches!(RawFrame::parse(&stream), Err(Error::InvalidFrame(_))));
+
+        // Corruption fails the frame and ends the iteration
+        stream[first_len - 5] ^= 0xFF;
+        let mut reader = FrameReader::new(&stream);
+        assert!(matches!(reader.next(), Some(Err(Error::ChecksumMismatch))));
+        assert!(reader.next().is_none());
+        assert!(matches!(FrameReader::new(&stream[first_len..stream.len() - 1]).next(), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_metadata_truncated() {
+        let mut metadata = FrameMetadata::new();
+        metadata.insert("producer", "svc-a");
+        let mut buf = Vec::new();
+        metadata.serialize(&mut buf);
+
+        for cut in 0..buf.len() {
+            assert!(FrameMetadata::parse(&buf[..cut]).is_err());
+        }
+    }
 }
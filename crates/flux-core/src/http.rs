@@ -0,0 +1,298 @@
+//! HTTP content negotiation for FLUX
+//!
+//! Clients advertise FLUX in `Accept-Encoding` with an optional `schema`
+//! parameter saying whether they keep a session across responses:
+//!
+//! ```text
+//! Accept-Encoding: flux;schema=cached, gzip;q=0.8
+//! ```
+//!
+//! - `schema=inline` (the default): every response must carry its schema,
+//!   as a one-shot `compress` does.
+//! - `schema=cached`: the client keeps a session per connection, so frames
+//!   may reference schemas sent earlier.
+//!
+//! `Negotiator` picks the response encoding from that header, falling back
+//! to identity (or another supported coding) when FLUX is not named or the
+//! body is too small to gain anything. `response_headers` lists the
+//! headers that go with a FLUX body.
+
+use crate::frame::RawFrame;
+use crate::transcode::ContentEncoding;
+use crate::Result;
+
+/// `Content-Encoding` token for FLUX
+pub const FLUX_TOKEN: &str = "flux";
+
+/// Response header carrying the schema id of a FLUX body
+pub const SCHEMA_ID_HEADER: &str = "flux-schema-id";
+
+/// Response header naming the server session that produced a FLUX body
+pub const SESSION_ID_HEADER: &str = "flux-session-id";
+
+/// Bodies below this many bytes are sent as identity by default
+pub const DEFAULT_MIN_SIZE: usize = 64;
+
+/// How a client handles schemas across responses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaMode {
+    /// Every frame carries its schema
+    #[default]
+    Inline,
+    /// Frames may reference schemas cached by the client's session
+    Cached,
+}
+
+impl SchemaMode {
+    /// Value of the `schema` parameter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchemaMode::Inline => "inline",
+            SchemaMode::Cached => "cached",
+        }
+    }
+}
+
+/// One coding of an `Accept-Encoding` header
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcceptedCoding {
+    /// Lowercased coding token (`*` for the wildcard)
+    pub coding: String,
+    /// Quality value, 1.0 when absent
+    pub quality: f32,
+    /// Parameters other than `q`, names lowercased
+    pub params: Vec<(String, String)>,
+}
+
+/// Parse an `Accept-Encoding` header into its codings, in header order
+///
+/// Malformed quality values count as 1.0, as most servers treat them.
+pub fn parse_accept_encoding(value: &str) -> Vec<AcceptedCoding> {
+    value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next()?.trim().to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let mut quality = 1.0;
+            let mut params = Vec::new();
+            for param in parts {
+                let Some((name, value)) = param.split_once('=') else { continue };
+                let name = name.trim().to_ascii_lowercase();
+                let value = value.trim().trim_matches('"');
+                if name == "q" {
+                    quality = value.parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q)).unwrap_or(1.0);
+                } else {
+                    params.push((name, value.to_string()));
+                }
+            }
+            Some(AcceptedCoding { coding, quality, params })
+        })
+        .collect()
+}
+
+/// What a client accepts of FLUX
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FluxAccept {
+    pub quality: f32,
+    pub schema: SchemaMode,
+}
+
+impl FluxAccept {
+    /// FLUX preferences listed in an `Accept-Encoding` header
+    ///
+    /// `None` unless `flux` is listed with a non-zero quality. Unknown
+    /// `schema` values fall back to `SchemaMode::Inline`.
+    pub fn parse(accept_encoding: &str) -> Option<Self> {
+        parse_accept_encoding(accept_encoding)
+            .into_iter()
+            .find(|c| c.coding == FLUX_TOKEN)
+            .filter(|c| c.quality > 0.0)
+            .map(|c| Self::from_coding(&c))
+    }
+
+    fn from_coding(coding: &AcceptedCoding) -> Self {
+        let cached = coding.params.iter().any(|(name, value)| name == "schema" && value.eq_ignore_ascii_case("cached"));
+        Self { quality: coding.quality, schema: if cached { SchemaMode::Cached } else { SchemaMode::Inline } }
+    }
+
+    /// `Accept-Encoding` item advertising these preferences, e.g. `flux;schema=cached`
+    pub fn to_header_value(&self) -> String {
+        let mut value = FLUX_TOKEN.to_string();
+        if self.schema != SchemaMode::Inline {
+            value.push_str(";schema=");
+            value.push_str(self.schema.as_str());
+        }
+        if self.quality < 1.0 {
+            value.push_str(&format!(";q={}", (self.quality * 1000.0).round() / 1000.0));
+        }
+        value
+    }
+}
+
+impl Default for FluxAccept {
+    fn default() -> Self {
+        Self { quality: 1.0, schema: SchemaMode::Inline }
+    }
+}
+
+/// Outcome of content negotiation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Negotiated {
+    /// Send FLUX, with schemas inline or cached
+    Flux(SchemaMode),
+    /// Send the body with another coding (often identity)
+    Fallback(ContentEncoding),
+}
+
+/// Chooses the response encoding from a request's `Accept-Encoding`
+///
+/// ```rust,ignore
+/// let negotiator = Negotiator::new().fallback(ContentEncoding::Gzip);
+/// match negotiator.negotiate(req_accept_encoding, body.len()) {
+///     Negotiated::Flux(SchemaMode::Cached) => session.compress(&body)?,
+///     Negotiated::Flux(SchemaMode::Inline) => flux_core::compress(&body)?,
+///     Negotiated::Fallback(encoding) => transcode_to(&body, encoding)?,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Negotiator {
+    /// Codings besides FLUX this server can produce, in preference order
+    fallbacks: Vec<ContentEncoding>,
+    min_size: usize,
+}
+
+impl Negotiator {
+    /// Negotiate between FLUX and identity
+    pub fn new() -> Self {
+        Self { fallbacks: Vec::new(), min_size: DEFAULT_MIN_SIZE }
+    }
+
+    /// Also offer `encoding` to clients that don't accept FLUX
+    ///
+    /// Earlier fallbacks win ties in quality.
+    pub fn fallback(mut self, encoding: ContentEncoding) -> Self {
+        if !matches!(encoding, ContentEncoding::Identity | ContentEncoding::Flux) && !self.fallbacks.contains(&encoding) {
+            self.fallbacks.push(encoding);
+        }
+        self
+    }
+
+    /// Send bodies smaller than `bytes` as identity (0 = always negotiate)
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Pick the encoding for a body of `body_len` bytes
+    ///
+    /// The highest-quality coding wins, FLUX first on ties. FLUX is only
+    /// sent to clients that name it: a `*` item covers the fallbacks the
+    /// header does not name, but never FLUX, which a client has to know how
+    /// to decode. Identity is the last resort even if the client refused it,
+    /// since every client can read it.
+    pub fn negotiate(&self, accept_encoding: Option<&str>, body_len: usize) -> Negotiated {
+        let identity = Negotiated::Fallback(ContentEncoding::Identity);
+        let Some(header) = accept_encoding else { return identity };
+        if body_len < self.min_size {
+            return identity;
+        }
+
+        let codings = parse_accept_encoding(header);
+        let wildcard = codings.iter().find(|c| c.coding == "*");
+        let quality_of = |token: &str| {
+            codings
+                .iter()
+                .find(|c| c.coding == token)
+                .or(wildcard)
+                .map_or(0.0, |c| c.quality)
+        };
+
+        let mut best = None;
+        let mut best_quality = 0.0;
+        let flux = codings.iter().find(|c| c.coding == FLUX_TOKEN);
+        if let Some(flux) = flux.filter(|c| c.quality > 0.0) {
+            best = Some(Negotiated::Flux(FluxAccept::from_coding(flux).schema));
+            best_quality = flux.quality;
+        }
+        for &encoding in &self.fallbacks {
+            let quality = quality_of(encoding.as_str());
+            if quality > best_quality {
+                best = Some(Negotiated::Fallback(encoding));
+                best_quality = quality;
+            }
+        }
+        best.unwrap_or(identity)
+    }
+}
+
+impl Default for Negotiator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Headers for a response whose body is the FLUX `frame`
+///
+/// Returns `Content-Encoding`, `Vary`, the frame's schema id and, if
+/// given, the session id, as lowercase name/value pairs.
+pub fn response_headers(frame: &[u8], session_id: Option<&str>) -> Result<Vec<(&'static str, String)>> {
+    let (frame, _) = RawFrame::parse_prefix(frame)?;
+    let mut headers = vec![
+        ("content-encoding", FLUX_TOKEN.to_string()),
+        ("vary", "accept-encoding".to_string()),
+        (SCHEMA_ID_HEADER, frame.header.schema_id.to_string()),
+    ];
+    if let Some(session_id) = session_id {
+        headers.push((SESSION_ID_HEADER, session_id.to_string()));
+    }
+    Ok(headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flux_accept() {
+        let accept = FluxAccept::parse("gzip;q=0.8, FLUX; schema=cached; q=0.9").unwrap();
+        assert_eq!(accept, FluxAccept { quality: 0.9, schema: SchemaMode::Cached });
+        assert_eq!(accept.to_header_value(), "flux;schema=cached;q=0.9");
+        assert_eq!(FluxAccept::parse("flux").unwrap().schema, SchemaMode::Inline);
+        assert_eq!(FluxAccept::default().to_header_value(), "flux");
+        assert!(FluxAccept::parse("flux;q=0").is_none());
+        assert!(FluxAccept::parse("gzip, br").is_none());
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let negotiator = Negotiator::new().fallback(ContentEncoding::Gzip);
+        let pick = |header: &str| negotiator.negotiate(Some(header), 1000);
+        assert_eq!(pick("flux;schema=cached"), Negotiated::Flux(SchemaMode::Cached));
+        assert_eq!(pick("gzip, flux"), Negotiated::Flux(SchemaMode::Inline));
+        assert_eq!(pick("gzip, flux;q=0.5"), Negotiated::Fallback(ContentEncoding::Gzip));
+        assert_eq!(pick("br"), Negotiated::Fallback(ContentEncoding::Identity));
+        assert_eq!(pick("flux;q=0.3, *"), Negotiated::Fallback(ContentEncoding::Gzip));
+        assert_eq!(pick("flux;q=0, *"), Negotiated::Fallback(ContentEncoding::Gzip));
+        assert_eq!(negotiator.negotiate(Some("flux"), 10), Negotiated::Fallback(ContentEncoding::Identity));
+
+        // A wildcard never selects FLUX
+        assert_eq!(pick("*"), Negotiated::Fallback(ContentEncoding::Gzip));
+        assert_eq!(Negotiator::new().negotiate(Some("*"), 1000), Negotiated::Fallback(ContentEncoding::Identity));
+        assert_eq!(negotiator.negotiate(None, 1000), Negotiated::Fallback(ContentEncoding::Identity));
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let mut session = crate::FluxSession::new();
+        session.compress(br#"{"a":1}"#).unwrap();
+        let frame = session.compress(br#"{"id":1,"name":"x"}"#).unwrap();
+        let headers = response_headers(&frame, Some("c1")).unwrap();
+        assert_eq!(headers[0], ("content-encoding", "flux".to_string()));
+        assert_eq!(headers[2], (SCHEMA_ID_HEADER, "2".to_string()));
+        assert_eq!(headers[3], (SESSION_ID_HEADER, "c1".to_string()));
+        assert!(response_headers(b"not flux", None).is_err());
+    }
+}
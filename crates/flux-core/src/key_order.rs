@@ -0,0 +1,181 @@
+//! Original key order of objects
+//!
+//! Schemas hold members in canonical (name-sorted) order, so a decompressed
+//! object normally comes back with sorted keys. With
+//! `FluxConfig::preserve_key_order` the compressor records every object
+//! whose keys were not already sorted, and decompression puts them back in
+//! their original order.
+//!
+//! The record travels in the frame metadata under `KEY_ORDER_KEY`, so
+//! decoders that predate it simply emit canonical order:
+//!
+//! ```text
+//! count (varint) | { object_gap (varint) | key_count (varint) | rank (varint)* }*
+//! ```
+//!
+//! Objects are numbered in pre-order, visiting members in sorted key order.
+//! `object_gap` is the distance from the previously recorded object, and the
+//! ranks are the positions of the original keys in sorted order.
+
+use crate::cursor::Cursor;
+use crate::encoding::encode_varint;
+use crate::{Error, Result};
+
+/// Frame metadata key carrying the key-order record
+pub const KEY_ORDER_KEY: &str = "flux-key-order";
+
+/// Record the key order of every unsorted object in `value`
+///
+/// Returns `None` when all objects are already in canonical order.
+pub(crate) fn record(value: &serde_json::Value) -> Option<Vec<u8>> {
+    let mut walk = RecordWalk::default();
+    walk.visit(value);
+    if walk.count == 0 {
+        return None;
+    }
+    let mut buf = Vec::with_capacity(walk.entries.len() + 2);
+    encode_varint(walk.count, &mut buf);
+    buf.extend_from_slice(&walk.entries);
+    Some(buf)
+}
+
+/// Put the objects of a canonically ordered `value` back in recorded order
+///
+/// Objects whose key count no longer matches the record (e.g. because
+/// decoding filled in `null` for members the original lacked) keep
+/// canonical order.
+pub(crate) fn restore(value: &mut serde_json::Value, record: &[u8]) -> Result<()> {
+    let mut cursor = Cursor::new(record);
+    let count = cursor.read_len("key order count")?;
+    let mut entries = Vec::with_capacity(count);
+    let mut index = 0u64;
+    for i in 0..count {
+        let gap = cursor.read_varint("key order object")?;
+        if i > 0 && gap == 0 {
+            return Err(Error::InvalidFrame("Key order objects out of sequence".into()));
+        }
+        index = index
+            .checked_add(gap)
+            .ok_or(Error::InvalidFrame("Key order object index overflows".into()))?;
+        let key_count = cursor.read_len("key order key count")?;
+        let mut ranks = Vec::with_capacity(key_count);
+        let mut seen = vec![false; key_count];
+        for _ in 0..key_count {
+            let rank = cursor.read_varint("key order rank")? as usize;
+            if rank >= key_count || std::mem::replace(&mut seen[rank], true) {
+                return Err(Error::InvalidFrame("Key order is not a permutation".into()));
+            }
+            ranks.push(rank);
+        }
+        entries.push((index, ranks));
+    }
+
+    let mut walk = RestoreWalk { entries: entries.into_iter().peekable(), next: 0 };
+    walk.visit(value);
+    Ok(())
+}
+
+#[derive(Default)]
+struct RecordWalk {
+    next: u64,
+    last: u64,
+    count: u64,
+    entries: Vec<u8>,
+}
+
+impl RecordWalk {
+    fn visit(&mut self, value: &serde_json::Value) {
+        match value {
+            serde_json::Value::Object(obj) => {
+                let index = self.next;
+                self.next += 1;
+
+                let mut sorted: Vec<&String> = obj.keys().collect();
+                sorted.sort();
+                if obj.keys().zip(&sorted).any(|(key, canonical)| key != *canonical) {
+                    encode_varint(index - self.last, &mut self.entries);
+                    encode_varint(obj.len() as u64, &mut self.entries);
+                    for key in obj.keys() {
+                        let rank = sorted.binary_search(&key).unwrap_or_default();
+                        encode_varint(rank as u64, &mut self.entries);
+                    }
+                    self.last = index;
+                    self.count += 1;
+                }
+
+                for key in sorted {
+                    self.visit(&obj[key]);
+                }
+            }
+            serde_json::Value::Array(arr) => arr.iter().for_each(|item| self.visit(item)),
+            _ => {}
+        }
+    }
+}
+
+struct RestoreWalk<I: Iterator<Item = (u64, Vec<usize>)>> {
+    entries: std::iter::Peekable<I>,
+    next: u64,
+}
+
+impl<I: Iterator<Item = (u64, Vec<usize>)>> RestoreWalk<I> {
+    fn visit(&mut self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(obj) => {
+                let index = self.next;
+                self.next += 1;
+                let entry = self.entries.next_if(|(i, _)| *i == index);
+
+                let mut sorted: Vec<String> = obj.keys().cloned().collect();
+                sorted.sort();
+                for key in &sorted {
+                    if let Some(member) = obj.get_mut(key) {
+                        self.visit(member);
+                    }
+                }
+
+                if let Some((_, ranks)) = entry {
+                    if ranks.len() == sorted.len() {
+                        let mut members = std::mem::take(obj);
+                        for rank in ranks {
+                            let key = &sorted[rank];
+                            if let Some(member) = members.remove(key) {
+                                obj.insert(key.clone(), member);
+                            }
+                        }
+                    }
+                }
+            }
+            serde_json::Value::Array(arr) => arr.iter_mut().for_each(|item| self.visit(item)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_order_roundtrip() {
+        let original: serde_json::Value = serde_json::from_str(
+            r#"{"z": 1, "a": {"y": [{"k": 1, "b": 2}], "c": 3}, "m": [{"a": 1, "b": 2}, {"q": 1, "p": 2}]}"#,
+        )
+        .unwrap();
+        let record = record(&original).unwrap();
+
+        // Canonical order, as the decoder produces it
+        let mut value: serde_json::Value = serde_json::from_str(
+            r#"{"a": {"c": 3, "y": [{"b": 2, "k": 1}]}, "m": [{"a": 1, "b": 2}, {"p": 2, "q": 1}], "z": 1}"#,
+        )
+        .unwrap();
+        restore(&mut value, &record).unwrap();
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            serde_json::to_string(&original).unwrap()
+        );
+
+        assert!(super::record(&serde_json::json!({"a": [{"b": 1, "c": 2}]})).is_none());
+        assert!(matches!(restore(&mut value, &[1, 0, 2, 0, 0]), Err(Error::InvalidFrame(_))));
+    }
+}
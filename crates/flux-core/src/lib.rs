@@ -25,9 +25,21 @@
 //! let c1 = session.compress(br#"{"id": 1, "name": "alice"}"#)?;
 //! let c2 = session.compress(br#"{"id": 2, "name": "bob"}"#)?;  // Uses cached schema
 //! ```
+//!
+//! # Deterministic Output
+//!
+//! Output depends only on the configuration and the messages a session
+//! has compressed so far: schema fields are sorted, and every table is
+//! ordered or breaks ties by content, so the same input always yields the
+//! same bytes, whatever the order of its object keys (unless
+//! `preserve_key_order` records it). Content-addressed storage and
+//! snapshot tests can rely on this. Encrypted frames are the exception, as
+//! each takes a random nonce.
 
 pub mod error;
+pub mod config;
 pub mod types;
+pub mod cursor;
 pub mod frame;
 pub mod schema;
 pub mod encoding;
@@ -35,24 +47,95 @@ pub mod columnar;
 pub mod lz;
 pub mod entropy;
 pub mod delta;
+pub mod dictionary;
+pub mod transcode;
+pub mod http;
+pub mod socket;
+pub mod observer;
+pub mod clock;
+pub mod key_order;
+pub mod content_hash;
+pub mod scratch;
+pub mod snapshot;
+pub mod session_manager;
+pub mod sync_session;
+pub mod adaptive;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+#[cfg(feature = "async")]
+pub mod codec;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "fastpack")]
+pub mod auto;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod v2;
+#[doc(hidden)]
+pub mod fuzzing;
 
 // Re-exports
-pub use error::{Error, Result};
+pub use error::{Error, Result, Stage};
+pub use config::{FluxConfig, FluxConfigBuilder, FluxLevel, MessageOptions, PatchFormat, Profile, StreamConfig};
 pub use types::{Value, FieldType};
-pub use frame::{FrameHeader, FrameFlags};
-pub use schema::{Schema, FieldDef, SchemaCache};
-pub use delta::{DeltaOp, DeltaEncoder, DeltaDecoder, ArrayOp, ObjectOp};
-pub use delta::{serialize_delta, deserialize_delta};
-
-use schema::SchemaInferrer;
+pub use lz::{LzParams, LzStats};
+pub use frame::{Capabilities, FrameHeader, FrameFlags, FrameMetadata, FrameReader, RawFrame, needs_more_bytes, negotiate_version, read_metadata};
+pub use schema::{Schema, SchemaBuilder, FieldDef, RootKind, SchemaCache, EvictionPolicy};
+pub use schema::{Compatibility, SchemaChange, SchemaDelta};
+pub use delta::{DeltaOp, DeltaEncoder, DeltaDecoder, DeltaLimits, ArrayOp, ObjectOp};
+pub use delta::{serialize_delta, deserialize_delta, MAX_DELTA_DEPTH};
+pub use delta::{apply_merge_patch, create_merge_patch};
+pub use dictionary::FluxDictionary;
+pub use transcode::{decompress_any, decompress_any_with_limit, transcode, transcode_to, ContentEncoding};
+pub use socket::{FluxSocketCodec, SocketEvent};
+pub use observer::{MessageStats, Operation, StageTimings, StatsObserver};
+pub use clock::{Clock, ManualClock, NoClock};
+pub use scratch::ScratchBuffers;
+pub use snapshot::{snapshot_kind, SnapshotKind};
+pub use content_hash::content_hash;
+pub use session_manager::{SessionManager, SessionManagerStats};
+pub use sync_session::SyncFluxSession;
+#[cfg(feature = "msgpack")]
+pub use msgpack::{compress_msgpack, decompress_msgpack};
+#[cfg(feature = "cbor")]
+pub use cbor::{compress_cbor, decompress_cbor};
+#[cfg(feature = "async")]
+pub use codec::FluxCodec;
+#[cfg(feature = "crypto")]
+pub use crypto::{Key, KeyProvider, Keyring};
+#[cfg(feature = "fastpack")]
+pub use auto::{auto_compress, auto_decompress};
+#[cfg(feature = "parquet")]
+pub use self::parquet::{read_parquet, FluxParquetWriter};
+
+use schema::{InferenceConfig, SchemaInferrer};
 use encoding::Encoder;
-use frame::FrameWriter;
+use lz::LzHistory;
+use config::SMALL_FRAME_MAX;
+use observer::MessageProbe;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
 
 /// FLUX magic bytes
 pub const FLUX_MAGIC: [u8; 4] = *b"FLUX";
 
-/// FLUX version (2.0)
-pub const FLUX_VERSION: u8 = 0x20;
+/// FLUX version (2.1), written in the header of every frame
+///
+/// The high nibble is the major version and the low nibble the minor one.
+/// Frames in `testdata/golden/` pin the format of each version.
+pub const FLUX_VERSION: u8 = 0x21;
+
+/// Frame format versions this decoder reads, oldest first
+///
+/// Minor versions only add to the format, so every earlier minor version
+/// of the current major one stays readable. Frames from later versions are
+/// rejected rather than guessed at.
+pub const SUPPORTED_VERSIONS: &[u8] = &[frame::VERSION_2_0, FLUX_VERSION];
 
 /// Compress JSON data
 ///
@@ -69,6 +152,129 @@ pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
     session.decompress(input)
 }
 
+/// Decompress untrusted FLUX data, failing once the output exceeds `limit` bytes
+///
+/// One-shot version of `FluxConfig::max_decompressed_size`.
+pub fn decompress_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let config = FluxConfig {
+        max_decompressed_size: limit,
+        ..FluxConfig::default()
+    };
+    FluxSession::with_config(config).decompress(input)
+}
+
+/// Decompress only the top-level `fields` of a FLUX record
+///
+/// One-shot version of `FluxSession::decompress_projected`.
+pub fn decompress_projected(input: &[u8], fields: &[&str]) -> Result<Vec<u8>> {
+    FluxSession::new().decompress_projected(input, fields)
+}
+
+/// Compress JSON data into a writer
+///
+/// One-shot version of `FluxSession::compress_to`.
+pub fn compress_to<W: Write>(input: &[u8], out: &mut W) -> Result<()> {
+    FluxSession::new().compress_to(input, out)
+}
+
+/// Decompress FLUX data into a writer as JSON
+///
+/// One-shot version of `FluxSession::decompress_to`.
+pub fn decompress_to<W: Write>(input: &[u8], out: &mut W) -> Result<()> {
+    FluxSession::new().decompress_to(input, out)
+}
+
+/// Decompress every frame of a buffer holding several back to back
+///
+/// One-shot version of `FluxSession::decompress_all`.
+pub fn decompress_all(input: &[u8]) -> Result<Vec<Vec<u8>>> {
+    FluxSession::new().decompress_all(input)
+}
+
+thread_local! {
+    /// Frame buffer reused by the `Profile::Realtime` small-frame path
+    static SMALL_FRAME: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(2 * SMALL_FRAME_MAX));
+}
+
+/// Varint encoding of `value` without allocating
+fn varint_bytes(mut value: u64) -> ([u8; 10], usize) {
+    let mut bytes = [0u8; 10];
+    let mut len = 0;
+    while value >= 0x80 {
+        bytes[len] = (value as u8 & 0x7F) | 0x80;
+        value >>= 7;
+        len += 1;
+    }
+    bytes[len] = value as u8;
+    (bytes, len + 1)
+}
+
+/// Prefix the bytes of `buf` from `start` on with their varint length
+fn prefix_len_in_place(buf: &mut Vec<u8>, start: usize) {
+    let (prefix, prefix_len) = varint_bytes((buf.len() - start) as u64);
+    buf.extend_from_slice(&prefix[..prefix_len]);
+    buf[start..].rotate_right(prefix_len);
+}
+
+/// Writer that refuses to pass more than `remaining` bytes through
+struct LimitedWriter<'a, W> {
+    inner: &'a mut W,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl<W: Write> Write for LimitedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.len() > self.remaining {
+            self.exceeded = true;
+            return Err(std::io::Error::other("output limit exceeded"));
+        }
+        let written = self.inner.write(buf)?;
+        self.remaining -= written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes frame bytes after the magic, tracking the CRC32C trailer
+struct FrameSink<'a, W: Write> {
+    out: &'a mut W,
+    crc: Option<u32>,
+    written: usize,
+}
+
+impl<'a, W: Write> FrameSink<'a, W> {
+    fn new(out: &'a mut W, checksum: bool) -> Self {
+        Self {
+            out,
+            crc: checksum.then_some(0),
+            written: 0,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if let Some(crc) = &mut self.crc {
+            *crc = crc32c::crc32c_append(*crc, bytes);
+        }
+        self.out.write_all(bytes)?;
+        self.written += bytes.len();
+        Ok(())
+    }
+
+    /// Write the checksum trailer, if any, and return the bytes written
+    fn finish(self) -> Result<usize> {
+        let mut written = self.written;
+        if let Some(crc) = self.crc {
+            self.out.write_all(&crc.to_le_bytes())?;
+            written += 4;
+        }
+        Ok(written)
+    }
+}
+
 /// FLUX compression session
 ///
 /// Maintains state across multiple compression operations,
@@ -78,33 +284,22 @@ pub struct FluxSession {
     encoder: Encoder,
     config: FluxConfig,
     stats: SessionStats,
-}
-
-/// FLUX configuration
-#[derive(Debug, Clone)]
-pub struct FluxConfig {
-    /// Enable columnar transformation
-    pub columnar: bool,
-    /// Enable FSE entropy coding
-    pub entropy: bool,
-    /// Enable delta encoding
-    pub delta: bool,
-    /// Enable checksum
-    pub checksum: bool,
-    /// Maximum dictionary size
-    pub max_dict_size: usize,
-}
-
-impl Default for FluxConfig {
-    fn default() -> Self {
-        Self {
-            columnar: true,
-            entropy: true,
-            delta: true,
-            checksum: true,
-            max_dict_size: 65536,
-        }
-    }
+    observer: Option<Arc<dyn StatsObserver>>,
+    clock: Arc<dyn Clock>,
+    /// Schema of the last message compressed, the base of schema deltas
+    last_schema: Option<u32>,
+    scratch: ScratchBuffers,
+    /// Payloads compressed so far, for `FluxConfig::lz_history`
+    sent_history: LzHistory,
+    /// Payloads decompressed so far
+    received_history: LzHistory,
+    /// Keys sealing and opening encrypted frames
+    #[cfg(feature = "crypto")]
+    encryption: Option<Arc<dyn crypto::KeyProvider>>,
+    /// Schema registry shared with the other shards of a `SyncFluxSession`
+    shared_schemas: Option<Arc<sync_session::SharedSchemas>>,
+    /// Recent stage ratios, for `FluxConfig::adaptive`
+    adaptive: adaptive::AdaptiveState,
 }
 
 /// Session statistics
@@ -114,8 +309,162 @@ pub struct SessionStats {
     pub bytes_in: u64,
     pub bytes_out: u64,
     pub schemas_cached: usize,
+    /// Schemas dropped to stay within `FluxConfig::max_cached_schemas` or
+    /// `FluxConfig::memory_budget`
+    pub schema_evictions: u64,
     pub cache_hits: u64,
     pub cache_misses: u64,
+    /// Bytes produced by the schema encoder (input to LZ)
+    pub encoded_bytes: u64,
+    /// Bytes after the LZ stage (input to entropy coding)
+    pub lz_bytes: u64,
+    /// Bytes after the entropy stage (frame payload)
+    pub entropy_bytes: u64,
+    /// Bytes spent on inline schemas and schema deltas
+    pub schema_bytes: u64,
+    /// Schemas sent as a delta from the previous one
+    pub schema_deltas: u64,
+    /// Bytes spent on value dictionary sections
+    pub dictionary_bytes: u64,
+    /// Matches found by the LZ stage
+    pub lz_matches: u64,
+    /// LZ matches referencing earlier messages (see `FluxConfig::lz_history`)
+    pub lz_history_matches: u64,
+    /// LZ match candidates examined
+    pub lz_chain_steps: u64,
+    /// Messages framed by the `Profile::Realtime` small-frame path
+    pub small_frames: u64,
+    /// Messages that skipped the LZ stage (`FluxConfig::lz` off, a payload
+    /// that looked incompressible, or `FluxConfig::adaptive`)
+    pub lz_skipped: u64,
+    /// Messages whose entropy stage `FluxConfig::adaptive` skipped
+    pub entropy_skipped: u64,
+    /// Messages sent as their JSON text (`FluxConfig::raw_threshold`)
+    pub raw_frames: u64,
+    /// Time spent compressing, per stage (needs `FluxConfig::stage_timing`)
+    pub compress_time: StageTimings,
+    /// Time spent decompressing, per stage (needs `FluxConfig::stage_timing`)
+    pub decompress_time: StageTimings,
+}
+
+impl SessionStats {
+    /// Bytes saved by schema encoding (JSON text to binary)
+    pub fn encode_gain(&self) -> i64 {
+        self.bytes_in as i64 - self.encoded_bytes as i64
+    }
+
+    /// Bytes saved by the LZ stage
+    pub fn lz_gain(&self) -> i64 {
+        self.encoded_bytes as i64 - self.lz_bytes as i64
+    }
+
+    /// Bytes saved by the entropy stage
+    pub fn entropy_gain(&self) -> i64 {
+        self.lz_bytes as i64 - self.entropy_bytes as i64
+    }
+
+    /// Effective ratio of the encode stage (output / input)
+    pub fn encode_ratio(&self) -> f64 {
+        stage_ratio(self.encoded_bytes, self.bytes_in)
+    }
+
+    /// Effective ratio of the LZ stage (output / input)
+    pub fn lz_ratio(&self) -> f64 {
+        stage_ratio(self.lz_bytes, self.encoded_bytes)
+    }
+
+    /// Effective ratio of the entropy stage (output / input)
+    pub fn entropy_ratio(&self) -> f64 {
+        stage_ratio(self.entropy_bytes, self.lz_bytes)
+    }
+}
+
+/// Bytes held by a session, by what holds them
+///
+/// Estimates: buffers count their capacity, and cached schemas and
+/// dictionary strings include a share for bookkeeping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Schema cache
+    pub schemas: usize,
+    /// Value dictionaries, both directions
+    pub dictionaries: usize,
+    /// LZ history windows, both directions
+    pub history: usize,
+    /// Scratch buffers
+    pub scratch: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.schemas + self.dictionaries + self.history + self.scratch
+    }
+}
+
+/// Bytes held by one side of a stream, by what holds them
+///
+/// Estimates, like `MemoryUsage`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamMemoryUsage {
+    /// Delta baselines, one state per channel (two for a sender with
+    /// `StreamConfig::acked`: the last sent and the last acknowledged)
+    pub baselines: usize,
+    /// States kept for acknowledgements (`StreamConfig::acked`), at most
+    /// `MAX_UNACKED_STATES` per channel
+    pub retained: usize,
+    /// FLUX sessions carrying merge patches (`PatchFormat::MergePatch`)
+    pub sessions: usize,
+}
+
+impl StreamMemoryUsage {
+    pub fn total(&self) -> usize {
+        self.baselines + self.retained + self.sessions
+    }
+}
+
+/// Sum the usage of both sides of a stream
+impl std::ops::AddAssign for StreamMemoryUsage {
+    fn add_assign(&mut self, other: StreamMemoryUsage) {
+        self.baselines += other.baselines;
+        self.retained += other.retained;
+        self.sessions += other.sessions;
+    }
+}
+
+/// Sum the statistics of several sessions
+impl std::ops::AddAssign<&SessionStats> for SessionStats {
+    fn add_assign(&mut self, other: &SessionStats) {
+        self.messages_processed += other.messages_processed;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.schemas_cached += other.schemas_cached;
+        self.schema_evictions += other.schema_evictions;
+        self.cache_hits += other.cache_hits;
+        self.cache_misses += other.cache_misses;
+        self.encoded_bytes += other.encoded_bytes;
+        self.lz_bytes += other.lz_bytes;
+        self.entropy_bytes += other.entropy_bytes;
+        self.schema_bytes += other.schema_bytes;
+        self.schema_deltas += other.schema_deltas;
+        self.dictionary_bytes += other.dictionary_bytes;
+        self.lz_matches += other.lz_matches;
+        self.lz_history_matches += other.lz_history_matches;
+        self.lz_chain_steps += other.lz_chain_steps;
+        self.small_frames += other.small_frames;
+        self.lz_skipped += other.lz_skipped;
+        self.entropy_skipped += other.entropy_skipped;
+        self.raw_frames += other.raw_frames;
+        self.compress_time += other.compress_time;
+        self.decompress_time += other.decompress_time;
+    }
+}
+
+fn stage_ratio(out: u64, input: u64) -> f64 {
+    if input == 0 {
+        1.0
+    } else {
+        out as f64 / input as f64
+    }
 }
 
 impl FluxSession {
@@ -127,69 +476,376 @@ impl FluxSession {
     /// Create a new FLUX session with custom configuration
     pub fn with_config(config: FluxConfig) -> Self {
         Self {
-            schema_cache: SchemaCache::new(),
-            encoder: Encoder::new(),
+            sent_history: LzHistory::new(config.lz_history),
+            received_history: LzHistory::new(config.lz_history),
+            schema_cache: SchemaCache::with_capacity(config.max_cached_schemas, config.schema_eviction),
+            encoder: Encoder::with_value_dictionary(config.value_dictionary, config.max_dict_size),
             config,
             stats: SessionStats::default(),
+            observer: None,
+            clock: clock::default_clock(),
+            last_schema: None,
+            scratch: ScratchBuffers::new(),
+            #[cfg(feature = "crypto")]
+            encryption: None,
+            shared_schemas: None,
+            adaptive: adaptive::AdaptiveState::default(),
         }
     }
 
+    /// Replace the time source used for stage timings
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Install (or with `None`, remove) a per-message statistics observer
+    ///
+    /// The observer is shared, so one exporter can watch many sessions.
+    pub fn set_observer(&mut self, observer: Option<Arc<dyn StatsObserver>>) {
+        self.observer = observer;
+    }
+
     /// Compress JSON data
     pub fn compress(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        self.compress_with(input, &MessageOptions::default())
+    }
+
+    /// Compress JSON data with per-message overrides
+    ///
+    /// The session config is left untouched; `opts` only affects this call.
+    pub fn compress_with(&mut self, input: &[u8], opts: &MessageOptions) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(input.len() / 2 + 32);
+        self.compress_with_to(input, opts, &mut output)?;
+        Ok(output)
+    }
+
+    /// Compress JSON data, appending the frame to `out`
+    ///
+    /// The pipeline stages work in the session's `ScratchBuffers`, so with
+    /// `out` reused (cleared by the caller) between messages, steady-state
+    /// compression doesn't allocate intermediate buffers.
+    pub fn compress_into(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        self.compress_with_to(input, &MessageOptions::default(), out)
+    }
+
+    /// Compress JSON data straight into a writer
+    ///
+    /// The frame is written piecewise (header, sections, payload, checksum),
+    /// so no buffer holding the whole frame is allocated.
+    pub fn compress_to<W: Write>(&mut self, input: &[u8], out: &mut W) -> Result<()> {
+        self.compress_with_to(input, &MessageOptions::default(), out)
+    }
+
+    /// `compress_with` into a writer
+    pub fn compress_with_to<W: Write>(&mut self, input: &[u8], opts: &MessageOptions, out: &mut W) -> Result<()> {
+        let mut probe = MessageProbe::new(Operation::Compress, input.len(), self.probe_clock());
+        self.compress_frame(input, None, opts, out, &mut probe)?;
+        self.notify(probe);
+        Ok(())
+    }
+
+    /// Compress JSON data against a known schema, skipping inference
+    ///
+    /// For messages of a fixed shape, build the schema once (with
+    /// `Schema::builder()`) instead of inferring it from every message. It
+    /// is cached and sent like an inferred schema. Messages that don't fit
+    /// it (missing required fields, extra members, wrong types) fail with
+    /// `Error::EncodeError`. A `Uuid` field holding text other than a
+    /// canonical lowercase UUID is no misfit: the message goes with the
+    /// schema's `Uuid` types widened to `Uuid | String` unions.
+    pub fn compress_with_schema(&mut self, input: &[u8], schema: &Schema) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(input.len() / 2 + 32);
+        let mut probe = MessageProbe::new(Operation::Compress, input.len(), self.probe_clock());
+        self.compress_frame(input, Some(schema), &MessageOptions::default(), &mut output, &mut probe)?;
+        self.notify(probe);
+        Ok(output)
+    }
+
+    fn compress_frame<W: Write>(
+        &mut self,
+        input: &[u8],
+        schema: Option<&Schema>,
+        opts: &MessageOptions,
+        out: &mut W,
+        probe: &mut MessageProbe,
+    ) -> Result<()> {
         self.stats.messages_processed += 1;
         self.stats.bytes_in += input.len() as u64;
 
         // Parse JSON
-        let value: serde_json::Value = serde_json::from_slice(input)
-            .map_err(|e| Error::ParseError(e.to_string()))?;
+        let value = parse_json(input, self.config.max_depth)?;
+        probe.lap(|t| &mut t.parse);
+
+        // Inference only types canonical UUIDs as `Uuid`; a given schema
+        // takes other UUID text as strings
+        let widened;
+        let schema = match schema {
+            Some(schema) if encoding::has_uuid_text(&value, schema) => {
+                widened = schema.with_uuid_text();
+                Some(&widened)
+            }
+            schema => schema,
+        };
+
+        if input.len() < self.config.raw_threshold && schema.is_none() && opts.metadata.is_empty() && !self.encrypting() {
+            if exceeds_depth(&value, self.config.max_depth) {
+                return Err(Error::DepthLimitExceeded { max: self.config.max_depth });
+            }
+            let inferred = self.infer_schema(&value)?;
+            let text = raw_json(&value, self.config.preserve_key_order)?;
+            let header = raw_frame_header(&self.config, text.len());
+            if header.size() + text.len() <= self.encoded_frame_size(&value, &inferred)? {
+                return self.write_raw_frame(&header, &text, out, probe);
+            }
+            // Sizing checked the depth and left the encoding to reuse
+            return self.compress_plain(&value, input.len(), Some(&inferred), opts, true, out, probe);
+        }
+        self.compress_parsed(&value, input.len(), schema, opts, out, probe)
+    }
+
+    fn infer_schema(&self, value: &serde_json::Value) -> Result<Schema> {
+        let mut inferrer = SchemaInferrer::with_config(InferenceConfig {
+            lossless_numbers: self.config.lossless_numbers,
+            detect_timestamps: self.config.detect_string_types,
+            detect_uuids: self.config.detect_string_types,
+            detect_base64: self.config.detect_string_types,
+            ..InferenceConfig::default()
+        });
+        inferrer.add_value(value)?;
+        inferrer.infer()
+    }
+
+    /// Size of the frame `value` would be sent in, for the raw frame check
+    ///
+    /// The payload is counted before LZ and entropy coding, which seldom
+    /// shrink a message this small, and without metadata. The checksum
+    /// trailer is left out, as both frames have it or neither does. The
+    /// encoding is left in `scratch.encoded` for `compress_plain`.
+    fn encoded_frame_size(&mut self, value: &serde_json::Value, schema: &Schema) -> Result<usize> {
+        let cached = self.schema_cache.get_by_hash(schema.hash).map(|cached| cached.id);
+        let cached = cached.or_else(|| self.load_shared_by_hash(schema.hash));
+        let scratch = &mut self.scratch;
+        scratch.encoded.clear();
+        self.encoder.encode_into(value, schema, &mut scratch.encoded)?;
+        let mut size = scratch.encoded.len();
+        if let Some(update) = self.encoder.value_dictionary_update() {
+            size += varint_bytes(update.len() as u64).1 + update.len();
+        }
+        // A new schema is sent along, under the next free ID
+        let schema_id = match cached {
+            Some(id) => id,
+            None => {
+                scratch.section.clear();
+                schema.serialize_into(&mut scratch.section);
+                size += varint_bytes(scratch.section.len() as u64).1 + scratch.section.len();
+                self.schema_cache.next_id()
+            }
+        };
+        let header = data_frame_header(&self.config, FrameFlags::empty(), schema_id, size);
+        Ok(header.magic_size() + header.size() + size)
+    }
+
+    /// `FluxConfig::raw_threshold` path: send the message's `raw_json` text
+    fn write_raw_frame<W: Write>(&mut self, header: &FrameHeader, text: &[u8], out: &mut W, probe: &mut MessageProbe) -> Result<()> {
+        let mut sink = FrameSink::new(out, self.config.checksum);
+        sink.write(&header.to_bytes())?;
+        sink.write(text)?;
+        let written = sink.finish()?;
+
+        self.stats.raw_frames += 1;
+        self.stats.bytes_out += written as u64;
+        probe.stats.output_bytes = written;
+        Ok(())
+    }
+
+    #[cfg(feature = "crypto")]
+    fn encrypting(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    #[cfg(not(feature = "crypto"))]
+    fn encrypting(&self) -> bool {
+        false
+    }
+
+    /// Frame an already parsed message of `input_len` source bytes
+    ///
+    /// Without an explicit `schema`, one is inferred from `value`.
+    fn compress_parsed<W: Write>(
+        &mut self,
+        value: &serde_json::Value,
+        input_len: usize,
+        schema: Option<&Schema>,
+        opts: &MessageOptions,
+        out: &mut W,
+        probe: &mut MessageProbe,
+    ) -> Result<()> {
+        // Inference and encoding recurse once per level
+        if exceeds_depth(value, self.config.max_depth) {
+            return Err(Error::DepthLimitExceeded { max: self.config.max_depth });
+        }
+
+        #[cfg(feature = "crypto")]
+        if let Some(keys) = self.encryption.clone() {
+            // Build the plain frame, then seal it
+            let mut plain = Vec::new();
+            self.compress_plain(value, input_len, schema, opts, false, &mut plain, probe)?;
+            let sealed = crypto::seal(&plain, keys.as_ref(), self.config.checksum)?;
+            out.write_all(&sealed)?;
+            self.stats.bytes_out += (sealed.len() - plain.len()) as u64;
+            probe.stats.output_bytes = sealed.len();
+            return Ok(());
+        }
+        self.compress_plain(value, input_len, schema, opts, false, out, probe)
+    }
+
+    /// `compress_parsed` without encryption
+    ///
+    /// With `encoded`, `scratch.encoded` already holds `value` encoded
+    /// against `schema`.
+    #[allow(clippy::too_many_arguments)]
+    fn compress_plain<W: Write>(
+        &mut self,
+        value: &serde_json::Value,
+        input_len: usize,
+        schema: Option<&Schema>,
+        opts: &MessageOptions,
+        encoded: bool,
+        out: &mut W,
+        probe: &mut MessageProbe,
+    ) -> Result<()> {
+        // Key order and schema deltas ride along as metadata
+        let mut extended: Option<MessageOptions> = None;
+        if let Some(record) = self.config.preserve_key_order.then(|| key_order::record(value)).flatten() {
+            extended.get_or_insert_with(|| opts.clone()).metadata.insert(key_order::KEY_ORDER_KEY, record);
+        }
+        if self.config.content_hash {
+            let hash = content_hash::content_hash(value).to_le_bytes();
+            extended.get_or_insert_with(|| opts.clone()).metadata.insert(content_hash::CONTENT_HASH_KEY, hash.to_vec());
+        }
 
         // Infer schema
-        let mut inferrer = SchemaInferrer::new();
-        inferrer.add_value(&value)?;
-        let schema = inferrer.infer()?;
+        let inferred;
+        let schema = match schema {
+            Some(schema) => schema,
+            None => {
+                inferred = self.infer_schema(value)?;
+                &inferred
+            }
+        };
 
         // Check schema cache
-        let (schema_id, schema_included) = match self.schema_cache.get_by_hash(schema.hash) {
-            Some(cached) => {
+        let hits_before = self.stats.cache_hits;
+        let cached = self.schema_cache.get_by_hash(schema.hash).map(|cached| cached.id);
+        let (schema_id, schema_included) = match cached.or_else(|| self.load_shared_by_hash(schema.hash)) {
+            Some(id) => {
                 self.stats.cache_hits += 1;
-                (cached.id, false)
+                self.schema_cache.touch(id);
+                (id, opts.force_schema)
             }
             None => {
                 self.stats.cache_misses += 1;
-                let id = self.schema_cache.register(schema.clone());
-                self.stats.schemas_cached = self.schema_cache.len();
-                (id, true)
+                let mut new_schema = schema.clone();
+                let delta = (self.config.schema_deltas && !opts.force_schema && !opts.disable_delta)
+                    .then(|| self.schema_delta(schema))
+                    .flatten();
+                let schema_included = match delta {
+                    Some((version, delta)) => {
+                        new_schema.version = version;
+                        self.stats.schema_bytes += delta.len() as u64;
+                        self.stats.schema_deltas += 1;
+                        extended.get_or_insert_with(|| opts.clone()).metadata.insert(schema::SCHEMA_DELTA_KEY, delta);
+                        false
+                    }
+                    None => true,
+                };
+                let id = match &self.shared_schemas {
+                    Some(shared) => {
+                        let id = shared.register(&new_schema);
+                        self.schema_cache.insert(id, new_schema);
+                        id
+                    }
+                    None => self.schema_cache.register(new_schema),
+                };
+                self.note_schema_cache();
+                (id, schema_included)
             }
         };
+        self.last_schema = Some(schema_id);
+        let opts = extended.as_ref().unwrap_or(opts);
+        probe.stats.schema_cache_hit = self.stats.cache_hits > hits_before;
+        probe.lap(|t| &mut t.schema);
 
-        // Encode data
-        let encoded = self.encoder.encode(&value, &schema)?;
+        if self.config.profile == Profile::Realtime && input_len < SMALL_FRAME_MAX {
+            return self.write_small_frame(value, schema, schema_id, schema_included, opts, encoded, out, probe);
+        }
 
-        // Apply LZ compression first (handles repeated sequences)
-        let lz_result = lz::lz_compress(&encoded)?;
-        let after_lz = if lz_result.len() < encoded.len() {
-            lz_result
+        // Encode data
+        let scratch = &mut self.scratch;
+        if !encoded {
+            scratch.encoded.clear();
+            self.encoder.encode_into(value, schema, &mut scratch.encoded)?;
+        }
+        let dictionary = self.encoder.value_dictionary_update();
+        self.stats.encoded_bytes += scratch.encoded.len() as u64;
+        probe.lap(|t| &mut t.encode);
+
+        // Apply LZ compression first (handles repeated sequences), unless
+        // the payload looks random and there's no history it could match
+        let history = if opts.disable_delta { &[][..] } else { self.sent_history.as_slice() };
+        let run_lz = self.config.lz
+            && (!history.is_empty() || !lz::likely_incompressible(&scratch.encoded))
+            && (!self.config.adaptive || self.adaptive.lz.should_run());
+        if run_lz {
+            let lz_stats = lz::lz_compress_with_history(
+                &scratch.encoded,
+                history,
+                &self.config.lz_params,
+                &mut scratch.lz_tables,
+                &mut scratch.lz,
+            )?;
+            self.stats.lz_matches += lz_stats.matches as u64;
+            self.stats.lz_history_matches += lz_stats.history_matches as u64;
+            self.stats.lz_chain_steps += lz_stats.chain_steps;
+            self.adaptive.lz.record(scratch.encoded.len(), scratch.lz.len());
+        } else {
+            self.stats.lz_skipped += 1;
+            scratch.lz.clear();
+            if lz::is_block(&scratch.encoded) {
+                lz::write_raw_block(&scratch.encoded, &mut scratch.lz);
+            }
+        }
+        // An encoding that starts like an LZ block can't be sent bare
+        let after_lz = if (run_lz && scratch.lz.len() < scratch.encoded.len()) || lz::is_block(&scratch.encoded) {
+            &scratch.lz
         } else {
-            encoded
+            &scratch.encoded
         };
+        self.stats.lz_bytes += after_lz.len() as u64;
+        probe.lap(|t| &mut t.lz);
 
         // Then apply entropy compression (handles frequency distribution)
-        let (payload, entropy_applied) = if self.config.entropy {
-            let compressed = entropy::fse_compress(&after_lz)?;
+        let run_entropy = self.config.entropy && !opts.skip_entropy;
+        let adaptive_skip = run_entropy && self.config.adaptive && !self.adaptive.entropy.should_run();
+        if adaptive_skip {
+            self.stats.entropy_skipped += 1;
+        }
+        let (payload, entropy_applied) = if run_entropy && !adaptive_skip {
+            entropy::fse_compress_into(after_lz, &mut scratch.nibbles, &mut scratch.entropy)?;
+            self.adaptive.entropy.record(after_lz.len(), scratch.entropy.len());
             // Only use entropy if it actually helps
-            if compressed.len() < after_lz.len() {
-                (compressed, true)
+            if scratch.entropy.len() < after_lz.len() {
+                (&scratch.entropy, true)
             } else {
                 (after_lz, false)
             }
         } else {
             (after_lz, false)
         };
+        self.stats.entropy_bytes += payload.len() as u64;
+        probe.lap(|t| &mut t.entropy);
 
         // Build frame
-        let mut output = Vec::with_capacity(payload.len() + 32);
-        let mut writer = FrameWriter::new();
-
         let mut flags = FrameFlags::empty();
         if schema_included {
             flags |= FrameFlags::SCHEMA_INCLUDED;
@@ -203,92 +859,397 @@ impl FluxSession {
         if self.config.checksum {
             flags |= FrameFlags::CHECKSUM_PRESENT;
         }
+        if !opts.metadata.is_empty() {
+            flags |= FrameFlags::METADATA;
+        }
+        if dictionary.is_some() {
+            flags |= FrameFlags::DICTIONARY_UPDATE;
+        }
 
-        let header = FrameHeader {
-            version: FLUX_VERSION,
-            flags,
-            schema_id,
-            payload_len: payload.len() as u32,
-            checksum: None, // Computed by writer
-        };
+        // Checksum computed by writer
+        let header = data_frame_header(&self.config, flags, schema_id, payload.len());
 
-        writer.write_header(&header, &mut output);
+        let magic = &FLUX_MAGIC[..header.magic_size()];
+        out.write_all(magic)?;
+        let mut sink = FrameSink::new(out, self.config.checksum);
+        sink.write(&header.to_bytes())?;
 
-        if schema_included {
-            let schema_bytes = schema.serialize();
-            writer.write_varint(schema_bytes.len() as u64, &mut output);
-            output.extend_from_slice(&schema_bytes);
+        let section = &mut scratch.section;
+        if !opts.metadata.is_empty() {
+            section.clear();
+            opts.metadata.serialize(section);
+            sink.write(section)?;
         }
 
-        output.extend_from_slice(&payload);
+        if schema_included {
+            section.clear();
+            schema.serialize_into(section);
+            let (prefix, prefix_len) = varint_bytes(section.len() as u64);
+            sink.write(&prefix[..prefix_len])?;
+            sink.write(section)?;
+            self.stats.schema_bytes += (prefix_len + section.len()) as u64;
+        }
 
-        if self.config.checksum {
-            let checksum = crc32c::crc32c(&output[FLUX_MAGIC.len()..]);
-            output.extend_from_slice(&checksum.to_le_bytes());
+        if let Some(update) = dictionary {
+            let (prefix, prefix_len) = varint_bytes(update.len() as u64);
+            sink.write(&prefix[..prefix_len])?;
+            sink.write(&update)?;
+            self.stats.dictionary_bytes += (prefix_len + update.len()) as u64;
         }
 
-        self.stats.bytes_out += output.len() as u64;
-        Ok(output)
+        sink.write(payload)?;
+        let written = magic.len() + sink.finish()?;
+        self.encoder.commit_value_dictionary();
+        self.sent_history.push(&scratch.encoded);
+        scratch.trim();
+
+        self.stats.bytes_out += written as u64;
+        probe.stats.output_bytes = written;
+        Ok(())
     }
 
-    /// Decompress FLUX data
-    pub fn decompress(&mut self, input: &[u8]) -> Result<Vec<u8>> {
-        // Validate magic
-        if input.len() < 18 {
-            return Err(Error::InvalidFrame("Frame too short".into()));
-        }
+    /// `Profile::Realtime` path: build a small frame in a reused buffer
+    ///
+    /// The payload is stored as a raw LZ block without searching for matches
+    /// or entropy coding, so decoders need no special handling. Every section
+    /// is written straight into the thread-local buffer, and length prefixes
+    /// and the header are moved into place there, so framing allocates
+    /// nothing once the buffer has grown; parsing the input and finding its
+    /// schema still do. With `encoded`, the payload is copied from
+    /// `scratch.encoded`, as in `compress_plain`.
+    #[allow(clippy::too_many_arguments)]
+    fn write_small_frame<W: Write>(
+        &mut self,
+        value: &serde_json::Value,
+        schema: &Schema,
+        schema_id: u32,
+        schema_included: bool,
+        opts: &MessageOptions,
+        encoded: bool,
+        out: &mut W,
+        probe: &mut MessageProbe,
+    ) -> Result<()> {
+        SMALL_FRAME.with(|buf| {
+            let mut buf = buf.borrow_mut();
+            buf.clear();
+            // Room for the largest magic and header, filled in from the back
+            let body_start = FLUX_MAGIC.len() + frame::HEADER_SIZE;
+            buf.resize(body_start, 0);
+
+            let mut flags = FrameFlags::empty();
+            if !opts.metadata.is_empty() {
+                flags |= FrameFlags::METADATA;
+                opts.metadata.serialize(&mut buf);
+            }
 
-        if input[0..4] != FLUX_MAGIC {
-            return Err(Error::InvalidMagic);
-        }
+            if schema_included {
+                flags |= FrameFlags::SCHEMA_INCLUDED;
+                let start = buf.len();
+                schema.serialize_into(&mut buf);
+                prefix_len_in_place(&mut buf, start);
+                self.stats.schema_bytes += (buf.len() - start) as u64;
+            }
 
-        // Parse header
-        let header = FrameHeader::parse(&input[4..])?;
+            let mut payload_start = buf.len();
+            buf.extend_from_slice(&[0; lz::HEADER_SIZE]);
+            if encoded {
+                buf.extend_from_slice(&self.scratch.encoded);
+            } else {
+                self.encoder.encode_into(value, schema, &mut buf)?;
+            }
+            probe.lap(|t| &mut t.encode);
+            let encoded_len = buf.len() - payload_start - lz::HEADER_SIZE;
+            buf[payload_start..payload_start + lz::HEADER_SIZE].copy_from_slice(&lz::raw_header(encoded_len));
+
+            // The dictionary section goes before the payload
+            let update_start = buf.len();
+            if self.encoder.write_value_dictionary_update(&mut buf) {
+                flags |= FrameFlags::DICTIONARY_UPDATE;
+                prefix_len_in_place(&mut buf, update_start);
+                let section_len = buf.len() - update_start;
+                buf[payload_start..].rotate_right(section_len);
+                payload_start += section_len;
+                self.stats.dictionary_bytes += section_len as u64;
+            }
+            let payload_len = buf.len() - payload_start;
 
-        // Verify checksum if present
-        if header.flags.contains(FrameFlags::CHECKSUM_PRESENT) {
-            // TODO: Verify checksum
-        }
+            if self.config.checksum {
+                flags |= FrameFlags::CHECKSUM_PRESENT;
+            }
+            let header = data_frame_header(&self.config, flags, schema_id, payload_len);
+            let magic_size = header.magic_size();
+            let body_end = buf.len();
+            buf.extend_from_slice(&FLUX_MAGIC[..magic_size]);
+            header.write_into(&mut buf);
+            let frame_start = body_start - (buf.len() - body_end);
+            buf.copy_within(body_end.., frame_start);
+            buf.truncate(body_end);
+
+            if self.config.checksum {
+                let checksum = crc32c::crc32c(&buf[frame_start + magic_size..]);
+                buf.extend_from_slice(&checksum.to_le_bytes());
+            }
 
-        let mut pos = 18; // After header
+            out.write_all(&buf[frame_start..])?;
+            self.encoder.commit_value_dictionary();
+            let encoded_start = payload_start + lz::HEADER_SIZE;
+            self.sent_history.push(&buf[encoded_start..encoded_start + encoded_len]);
+
+            let frame_len = buf.len() - frame_start;
+            self.stats.encoded_bytes += encoded_len as u64;
+            self.stats.lz_bytes += payload_len as u64;
+            self.stats.entropy_bytes += payload_len as u64;
+            self.stats.bytes_out += frame_len as u64;
+            self.stats.small_frames += 1;
+            probe.stats.output_bytes = frame_len;
+
+            // Don't pin a buffer grown by unusually large metadata
+            if buf.capacity() > 4 * SMALL_FRAME_MAX {
+                *buf = Vec::new();
+            }
+            Ok(())
+        })
+    }
 
-        // Load schema
-        let schema = if header.flags.contains(FrameFlags::SCHEMA_INCLUDED) {
-            let (schema_len, len_bytes) = encoding::decode_varint(&input[pos..])?;
-            pos += len_bytes;
-            let schema = Schema::deserialize(&input[pos..pos + schema_len as usize])?;
-            pos += schema_len as usize;
-            self.schema_cache.register(schema.clone());
-            schema
-        } else {
-            self.schema_cache.get(header.schema_id)
-                .ok_or(Error::SchemaNotFound(header.schema_id))?
-                .clone()
+    /// Decompress FLUX data
+    pub fn decompress(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut probe = MessageProbe::new(Operation::Decompress, input.len(), self.probe_clock());
+        let value = self.decompress_value(input, &mut probe, None)?;
+        let json = to_json_bytes(&value)?;
+        if json.len() > self.config.max_decompressed_size {
+            return Err(Error::OutputLimitExceeded { limit: self.config.max_decompressed_size });
+        }
+        probe.lap(|t| &mut t.encode);
+        probe.stats.output_bytes = json.len();
+        self.notify(probe);
+        Ok(json)
+    }
+
+    /// Decode a frame into a JSON value
+    ///
+    /// With `projection`, only those top-level fields are decoded.
+    fn decompress_value(
+        &mut self,
+        input: &[u8],
+        probe: &mut MessageProbe,
+        projection: Option<&[&str]>,
+    ) -> Result<serde_json::Value> {
+        let frame = RawFrame::parse(input)
+            .and_then(|frame| frame.verify_checksum().map(|_| frame))
+            .map_err(|e| e.in_stage(Stage::Frame, None))?;
+
+        // Encrypted frames are opened into the plain frame they seal
+        let decrypted;
+        let frame = match frame.encrypted {
+            Some(_) => {
+                decrypted = self.open(&frame).map_err(|e| e.in_stage(Stage::Frame, None))?;
+                RawFrame::parse(&decrypted).map_err(|e| e.in_stage(Stage::Frame, None))?
+            }
+            None => frame,
+        };
+        let header = &frame.header;
+        if header.is_raw() {
+            return read_raw_json(frame.payload, projection, self.config.max_decompressed_size, self.config.max_depth);
+        }
+        // Offset of a frame section, for error reports (none within a decrypted frame)
+        let at = |section: &[u8]| {
+            (section.as_ptr() as usize).checked_sub(input.as_ptr() as usize).filter(|&offset| offset < input.len())
         };
+        probe.lap(|t| &mut t.parse);
+
+        // Load schema (a missing one stays a bare `SchemaNotFound`, so callers can recover)
+        let schema = match frame.schema {
+            Some(bytes) => {
+                // Keep the sender's ID, so later frames referencing it resolve
+                let schema = Schema::deserialize_max_depth(bytes, self.config.max_depth)
+                    .map_err(|e| e.in_stage(Stage::Schema, at(bytes)))?;
+                self.cache_schema(header.schema_id, schema.clone());
+                self.note_schema_cache();
+                schema
+            }
+            None => match frame.metadata.get(schema::SCHEMA_DELTA_KEY) {
+                Some(bytes) => {
+                    let delta = SchemaDelta::deserialize(bytes).map_err(|e| e.in_stage(Stage::Schema, None))?;
+                    self.load_shared_schema(delta.base);
+                    let base = self.schema_cache.get(delta.base).ok_or(Error::SchemaNotFound(delta.base))?;
+                    let schema = base.apply_delta(&delta).map_err(|e| e.in_stage(Stage::Schema, None))?;
+                    self.schema_cache.touch(delta.base);
+                    self.cache_schema(header.schema_id, schema.clone());
+                    self.note_schema_cache();
+                    schema
+                }
+                None => {
+                    self.load_shared_schema(header.schema_id);
+                    let schema = self.schema_cache.get(header.schema_id)
+                        .ok_or(Error::SchemaNotFound(header.schema_id))?
+                        .clone();
+                    self.schema_cache.touch(header.schema_id);
+                    schema
+                }
+            },
+        };
+        // Decoding recurses once per level
+        if schema.nesting_depth() > self.config.max_depth {
+            let err = Error::DepthLimitExceeded { max: self.config.max_depth };
+            return Err(err.in_stage(Stage::Schema, frame.schema.and_then(at)));
+        }
+        probe.stats.schema_cache_hit = frame.schema.is_none() && frame.metadata.get(schema::SCHEMA_DELTA_KEY).is_none();
+        self.encoder
+            .load_value_dictionary(frame.dictionary)
+            .map_err(|e| e.in_stage(Stage::Encoding, frame.dictionary.and_then(at)))?;
+        probe.lap(|t| &mut t.schema);
 
         // Get payload and decompress entropy if needed
-        let payload = &input[pos..];
-        let after_entropy = if header.flags.contains(FrameFlags::FSE_COMPRESSED) {
-            entropy::fse_decompress(payload)?
+        let payload = frame.payload;
+        let limit = self.config.max_decompressed_size;
+        let entropy_coded = header.flags.contains(FrameFlags::FSE_COMPRESSED);
+        let after_entropy = if entropy_coded {
+            entropy::fse_decompress_with_limit(payload, limit).map_err(|e| e.in_stage(Stage::Entropy, at(payload)))?
         } else {
             payload.to_vec()
         };
+        probe.lap(|t| &mut t.entropy);
 
         // Decompress LZ if it was applied (check for LZ magic)
-        let decoded_payload = if !after_entropy.is_empty() && after_entropy[0] == 0x4C {
-            lz::lz_decompress(&after_entropy)?
+        let lz_coded = lz::is_block(&after_entropy);
+        let decoded_payload = if lz_coded {
+            lz::lz_decompress_with_history(&after_entropy, self.received_history.as_slice(), limit)
+                .map_err(|e| e.in_stage(Stage::Lz, if entropy_coded { None } else { at(payload) }))?
         } else {
             after_entropy
         };
+        probe.lap(|t| &mut t.lz);
+
+        // Decode data; offsets are within the decoded payload
+        let value = match projection {
+            Some(fields) => self.encoder.decode_projected(&decoded_payload, &schema, fields, limit),
+            None => self.encoder.decode_with_limit(&decoded_payload, &schema, limit).and_then(|mut value| {
+                if let Some(hash) = frame.metadata.get(content_hash::CONTENT_HASH_KEY) {
+                    content_hash::verify(&value, hash)?;
+                }
+                if let Some(record) = frame.metadata.get(key_order::KEY_ORDER_KEY) {
+                    key_order::restore(&mut value, record)?;
+                }
+                Ok(value)
+            }),
+        }
+        .map_err(|e| e.in_stage(Stage::Encoding, None))?;
+        self.received_history.push(&decoded_payload);
+        probe.lap(|t| &mut t.encode);
+        Ok(value)
+    }
 
-        // Decode data
-        let value = self.encoder.decode(&decoded_payload, &schema)?;
+    /// Decrypt an encrypted frame into the plain frame it seals
+    #[cfg(feature = "crypto")]
+    fn open(&self, frame: &RawFrame<'_>) -> Result<Vec<u8>> {
+        crypto::open(frame, self.encryption.as_deref())
+    }
 
-        // Serialize back to JSON
-        let output = serde_json::to_vec(&value)
-            .map_err(|e| Error::SerializeError(e.to_string()))?;
+    #[cfg(not(feature = "crypto"))]
+    fn open(&self, _frame: &RawFrame<'_>) -> Result<Vec<u8>> {
+        Err(Error::UnsupportedCapabilities(Capabilities::ENCRYPTED.bits()))
+    }
 
-        Ok(output)
+    /// Decompress concatenated frames, e.g. a file of appended messages
+    ///
+    /// Frames are decoded in order by this session, so schemas sent by
+    /// earlier frames resolve in later ones. Fails on the first frame that
+    /// is truncated, fails its checksum or doesn't decode.
+    pub fn decompress_all(&mut self, input: &[u8]) -> Result<Vec<Vec<u8>>> {
+        FrameReader::new(input).map(|frame| self.decompress(frame?.bytes)).collect()
+    }
+
+    /// Decompress only the top-level `fields` of a record
+    ///
+    /// Unrequested fields are skipped rather than decoded, so pulling a
+    /// couple of fields out of a wide record costs a fraction of a full
+    /// `decompress`. Requested fields the record lacks are omitted. A
+    /// frame holding an array of records has every record projected.
+    pub fn decompress_projected(&mut self, input: &[u8], fields: &[&str]) -> Result<Vec<u8>> {
+        let mut probe = MessageProbe::new(Operation::Decompress, input.len(), self.probe_clock());
+        let value = self.decompress_value(input, &mut probe, Some(fields))?;
+        let json = to_json_bytes(&value)?;
+        if json.len() > self.config.max_decompressed_size {
+            return Err(Error::OutputLimitExceeded { limit: self.config.max_decompressed_size });
+        }
+        probe.lap(|t| &mut t.encode);
+        probe.stats.output_bytes = json.len();
+        self.notify(probe);
+        Ok(json)
+    }
+
+    /// Decompress FLUX data straight into a writer as JSON
+    pub fn decompress_to<W: Write>(&mut self, input: &[u8], out: &mut W) -> Result<()> {
+        let mut probe = MessageProbe::new(Operation::Decompress, input.len(), self.probe_clock());
+        let value = self.decompress_value(input, &mut probe, None)?;
+        let limit = self.config.max_decompressed_size;
+        let mut out = LimitedWriter { inner: out, remaining: limit, exceeded: false };
+        serde_json::to_writer(&mut out, &value).map_err(|e| {
+            if out.exceeded {
+                Error::OutputLimitExceeded { limit }
+            } else if e.is_io() {
+                Error::Io(e.into())
+            } else {
+                Error::SerializeError(e.to_string())
+            }
+        })?;
+        probe.lap(|t| &mut t.encode);
+        probe.stats.output_bytes = limit - out.remaining;
+        self.notify(probe);
+        Ok(())
+    }
+
+    /// Whether messages need the clock
+    fn timed(&self) -> bool {
+        self.config.stage_timing || self.observer.is_some()
+    }
+
+    /// Clock for a new `MessageProbe`, if timing is wanted
+    fn probe_clock(&self) -> Option<Arc<dyn Clock>> {
+        self.timed().then(|| self.clock.clone())
+    }
+
+    /// Record a finished message in the stats and report it to the observer
+    fn notify(&mut self, probe: MessageProbe) {
+        self.enforce_memory_budget();
+        if !self.timed() {
+            return;
+        }
+        let stats = probe.finish();
+        if self.config.stage_timing {
+            match stats.operation {
+                Operation::Compress => self.stats.compress_time += stats.timings,
+                Operation::Decompress => self.stats.decompress_time += stats.timings,
+            }
+        }
+        if let Some(observer) = &self.observer {
+            observer.observe(&stats);
+        }
+    }
+
+    /// Free scratch buffers, then evict schemas, until within
+    /// `FluxConfig::memory_budget`
+    ///
+    /// The schema of the last message compressed is kept.
+    fn enforce_memory_budget(&mut self) {
+        let budget = self.config.memory_budget;
+        if budget == 0 || self.memory_usage().total() <= budget {
+            return;
+        }
+        self.scratch.release();
+        let fixed = self.memory_usage().total() - self.schema_cache.memory_usage();
+        while fixed + self.schema_cache.memory_usage() > budget && self.schema_cache.evict(self.last_schema) {}
+        self.note_schema_cache();
+    }
+
+    /// Bytes held by the session
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            schemas: self.schema_cache.memory_usage(),
+            dictionaries: self.encoder.memory_usage(),
+            history: self.sent_history.capacity() + self.received_history.capacity(),
+            scratch: self.scratch.capacity(),
+        }
     }
 
     /// Get session statistics
@@ -305,172 +1266,2193 @@ impl FluxSession {
         }
     }
 
-    /// Reset session state
-    pub fn reset(&mut self) {
-        self.schema_cache = SchemaCache::new();
-        self.encoder = Encoder::new();
-        self.stats = SessionStats::default();
+    /// Working buffers of the compression pipeline
+    pub fn scratch(&self) -> &ScratchBuffers {
+        &self.scratch
     }
-}
 
-impl Default for FluxSession {
-    fn default() -> Self {
-        Self::new()
+    /// Free the working buffers, e.g. after a burst of large messages
+    pub fn release_scratch(&mut self) {
+        self.scratch.release();
     }
-}
 
-/// FLUX streaming session with delta compression
-///
-/// Optimized for real-time state updates where only changes
-/// between states need to be transmitted.
-///
-/// # Example
-///
-/// ```rust,ignore
-/// use flux_core::FluxStreamSession;
-///
-/// let mut session = FluxStreamSession::new();
-///
-/// // First state - full transmission
-/// let msg1 = session.update(br#"{"count": 0, "users": ["alice"]}"#)?;
-///
-/// // Second state - only delta transmitted
-/// let msg2 = session.update(br#"{"count": 1, "users": ["alice", "bob"]}"#)?;
-/// // msg2 is much smaller, containing only the changes
-/// ```
-pub struct FluxStreamSession {
-    delta_encoder: DeltaEncoder,
-    delta_decoder: DeltaDecoder,
-    stats: StreamStats,
-}
+    /// Schemas cached by this session
+    pub fn schema_cache(&self) -> &SchemaCache {
+        &self.schema_cache
+    }
 
-/// Streaming session statistics
-#[derive(Debug, Clone, Default)]
-pub struct StreamStats {
-    pub updates_sent: u64,
-    pub full_sends: u64,
-    pub delta_sends: u64,
-    pub bytes_full: u64,
-    pub bytes_delta: u64,
-}
+    /// Drop a cached schema, returning it if it was present
+    ///
+    /// The next message of that shape carries its schema again.
+    pub fn remove_schema(&mut self, id: u32) -> Option<Schema> {
+        let schema = self.schema_cache.remove(id);
+        self.note_schema_cache();
+        schema
+    }
 
-impl FluxStreamSession {
+    /// Keep only the cached schemas for which `keep` returns true
+    pub fn retain_schemas<F: FnMut(&Schema) -> bool>(&mut self, keep: F) {
+        self.schema_cache.retain(keep);
+        self.note_schema_cache();
+    }
+
+    /// Cache `schema` under `id`, publishing it to the other shards of a
+    /// `SyncFluxSession`
+    fn cache_schema(&mut self, id: u32, schema: Schema) {
+        if let Some(shared) = &self.shared_schemas {
+            shared.insert(id, &schema);
+        }
+        self.schema_cache.insert(id, schema);
+    }
+
+    /// Copy schema `id` from the shared registry if another shard cached it
+    fn load_shared_schema(&mut self, id: u32) {
+        if self.schema_cache.get(id).is_none() {
+            if let Some(schema) = self.shared_schemas.as_ref().and_then(|shared| shared.get(id)) {
+                self.schema_cache.insert(id, schema);
+                self.note_schema_cache();
+            }
+        }
+    }
+
+    /// Copy the schema with `hash` from the shared registry, returning its ID
+    fn load_shared_by_hash(&mut self, hash: u64) -> Option<u32> {
+        let (id, schema) = self.shared_schemas.as_ref()?.get_by_hash(hash)?;
+        self.schema_cache.insert(id, schema);
+        self.note_schema_cache();
+        Some(id)
+    }
+
+    fn note_schema_cache(&mut self) {
+        self.stats.schemas_cached = self.schema_cache.len();
+        self.stats.schema_evictions = self.schema_cache.evictions();
+    }
+
+    /// Serialized delta from the last message's schema to `schema`, with
+    /// the version it gives, when that is smaller than the full schema
+    fn schema_delta(&self, schema: &Schema) -> Option<(u16, Vec<u8>)> {
+        let base = self.schema_cache.get(self.last_schema?)?;
+        let delta = base.diff(schema)?;
+        let mut bytes = Vec::new();
+        delta.serialize_into(&mut bytes);
+        // The metadata key costs about as much as the schema's fixed fields
+        let full = schema.serialize().len();
+        (bytes.len() + schema::SCHEMA_DELTA_KEY.len() < full).then(|| (base.version.wrapping_add(1), bytes))
+    }
+
+    /// Build a request for schemas this session no longer has
+    ///
+    /// Send it to the compressing peer after `decompress` fails with
+    /// `Error::SchemaNotFound` (e.g. because the schema was evicted), and
+    /// pass the answer to `load_schemas` before retrying the frame.
+    pub fn schema_request(&self, ids: &[u32]) -> Vec<u8> {
+        let mut value = Vec::new();
+        encoding::encode_varint(ids.len() as u64, &mut value);
+        for &id in ids {
+            encoding::encode_varint(id as u64, &mut value);
+        }
+        let mut metadata = FrameMetadata::new();
+        metadata.insert(frame::SCHEMA_REQUEST_KEY, value);
+        frame::control_frame(&metadata, self.config.checksum)
+    }
+
+    /// Answer a peer's `schema_request` with the schemas it asked for
+    ///
+    /// Fails with `Error::SchemaNotFound` if one of them is no longer cached
+    /// here either; the peer then needs the message again with
+    /// `MessageOptions::force_schema`.
+    pub fn answer_schema_request(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let metadata = frame::read_metadata(request)?;
+        let ids = metadata
+            .get(frame::SCHEMA_REQUEST_KEY)
+            .ok_or_else(|| Error::InvalidFrame("Not a schema request".into()))?;
+        let mut cursor = cursor::Cursor::new(ids);
+        let count = cursor.read_len("schema request count")?;
+        let ids = (0..count)
+            .map(|_| read_schema_id(&mut cursor))
+            .collect::<Result<Vec<u32>>>()?;
+        self.schema_reply(&ids)
+    }
+
+    /// Cache the schemas carried by an answer to `schema_request`
+    ///
+    /// Returns the number of schemas loaded.
+    pub fn load_schemas(&mut self, reply: &[u8]) -> Result<usize> {
+        let schemas = read_schema_reply(reply, self.config.max_depth)?;
+        let count = schemas.len();
+        for (id, schema) in schemas {
+            self.cache_schema(id, schema);
+        }
+        self.note_schema_cache();
+        Ok(count)
+    }
+
+    /// Export cached schema `id` as a resync message for `import_schema`
+    ///
+    /// When a peer's `decompress` fails with `Error::SchemaNotFound(id)`
+    /// (it missed the frame that carried the schema), the application can
+    /// fetch this message out of band and hand it to the peer, which then
+    /// decodes the frame it rejected.
+    pub fn export_schema(&self, id: u32) -> Result<Vec<u8>> {
+        self.schema_reply(&[id])
+    }
+
+    /// Cache the schema carried by an `export_schema` message, returning its ID
+    pub fn import_schema(&mut self, message: &[u8]) -> Result<u32> {
+        let mut schemas = read_schema_reply(message, self.config.max_depth)?;
+        if schemas.len() != 1 {
+            return Err(Error::Unexpected {
+                what: "schema count",
+                expected: "1".into(),
+                found: schemas.len().to_string(),
+            });
+        }
+        let (id, schema) = schemas.remove(0);
+        self.cache_schema(id, schema);
+        self.note_schema_cache();
+        Ok(id)
+    }
+
+    /// Control frame carrying the cached schemas `ids`
+    fn schema_reply(&self, ids: &[u32]) -> Result<Vec<u8>> {
+        let mut value = Vec::new();
+        encoding::encode_varint(ids.len() as u64, &mut value);
+        for &id in ids {
+            let bytes = match self.schema_cache.get(id) {
+                Some(schema) => schema.serialize(),
+                None => self.shared_schemas.as_ref().and_then(|shared| shared.get(id)).ok_or(Error::SchemaNotFound(id))?.serialize(),
+            };
+            encoding::encode_varint(id as u64, &mut value);
+            encoding::encode_varint(bytes.len() as u64, &mut value);
+            value.extend_from_slice(&bytes);
+        }
+
+        let mut metadata = FrameMetadata::new();
+        metadata.insert(frame::SCHEMA_REPLY_KEY, value);
+        Ok(frame::control_frame(&metadata, self.config.checksum))
+    }
+
+    /// Reset session state
+    pub fn reset(&mut self) {
+        self.schema_cache = SchemaCache::with_capacity(self.config.max_cached_schemas, self.config.schema_eviction);
+        self.encoder = Encoder::with_value_dictionary(self.config.value_dictionary, self.config.max_dict_size);
+        self.stats = SessionStats::default();
+        self.last_schema = None;
+        self.sent_history.clear();
+        self.received_history.clear();
+        self.adaptive = adaptive::AdaptiveState::default();
+    }
+}
+
+/// Header of a data frame, compact with `FluxConfig::compact_header`
+fn data_frame_header(config: &FluxConfig, flags: FrameFlags, schema_id: u32, payload_len: usize) -> FrameHeader {
+    let header = FrameHeader::new(flags, schema_id, payload_len as u32);
+    if config.compact_header {
+        FrameHeader { version: FLUX_VERSION | frame::COMPACT_HEADER, ..header }
+    } else {
+        header
+    }
+}
+
+/// Header of a raw frame of `len` bytes of JSON
+fn raw_frame_header(config: &FluxConfig, len: usize) -> FrameHeader {
+    let mut flags = FrameFlags::empty();
+    if config.checksum {
+        flags |= FrameFlags::CHECKSUM_PRESENT;
+    }
+    let version = FLUX_VERSION | frame::COMPACT_HEADER | frame::RAW_PAYLOAD;
+    FrameHeader { version, ..FrameHeader::new(flags, 0, len as u32) }
+}
+
+fn read_schema_id(cursor: &mut cursor::Cursor<'_>) -> Result<u32> {
+    u32::try_from(cursor.read_varint("schema id")?)
+        .map_err(|_| Error::InvalidFrame("Schema id out of range".into()))
+}
+
+/// Schemas of a `FluxSession::schema_reply` control frame
+fn read_schema_reply(reply: &[u8], max_depth: usize) -> Result<Vec<(u32, Schema)>> {
+    let metadata = frame::read_metadata(reply)?;
+    let schemas = metadata
+        .get(frame::SCHEMA_REPLY_KEY)
+        .ok_or_else(|| Error::InvalidFrame("Not a schema reply".into()))?;
+    let mut cursor = cursor::Cursor::new(schemas);
+    let count = cursor.read_len("schema reply count")?;
+    (0..count)
+        .map(|_| {
+            let id = read_schema_id(&mut cursor)?;
+            Ok((id, Schema::deserialize_max_depth(cursor.read_len_bytes("schema")?, max_depth)?))
+        })
+        .collect()
+}
+
+impl Default for FluxSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// FLUX streaming session with delta compression
+///
+/// Optimized for real-time state updates where only changes
+/// between states need to be transmitted.
+///
+/// A session holds an independent sender (`update`) and receiver
+/// (`receive`), one per direction of a full-duplex connection. `split`
+/// separates them, and `sender_mut` and `receiver_mut` reach one without
+/// disturbing the other.
+///
+/// Several documents can share a stream as channels (`update_channel`),
+/// each with its own delta baseline; `update` sends on `DEFAULT_CHANNEL`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// use flux_core::FluxStreamSession;
+///
+/// let mut session = FluxStreamSession::new();
+///
+/// // First state - full transmission
+/// let msg1 = session.update(br#"{"count": 0, "users": ["alice"]}"#)?;
+///
+/// // Second state - only delta transmitted
+/// let msg2 = session.update(br#"{"count": 1, "users": ["alice", "bob"]}"#)?;
+/// // msg2 is much smaller, containing only the changes
+/// ```
+pub struct FluxStreamSession {
+    tx: StreamSender,
+    rx: StreamReceiver,
+}
+
+/// Channel of `FluxStreamSession::update`, framed without a channel prefix
+pub const DEFAULT_CHANNEL: u32 = 0;
+
+/// First byte of an update on a channel other than `DEFAULT_CHANNEL`,
+/// followed by the channel (varint) and the update
+///
+/// Delta updates start with a delta tag (below 8) and merge patches with
+/// the FLUX magic, so neither is mistaken for it.
+const CHANNEL_TAG: u8 = 0xC7;
+
+/// Unacknowledged states a sender keeps per channel (`StreamConfig::acked`);
+/// acknowledging an older one no longer moves the baseline
+pub const MAX_UNACKED_STATES: usize = 64;
+
+/// Sending half of a stream: turns states into deltas
+pub struct StreamSender {
+    config: StreamConfig,
+    clock: Arc<dyn Clock>,
+    channels: HashMap<u32, ChannelSender>,
+    stats: StreamStats,
+}
+
+/// Sending state of one channel
+pub(crate) struct ChannelSender {
+    pub(crate) delta_encoder: DeltaEncoder,
+    /// Updates sent since the last full snapshot
+    pub(crate) since_keyframe: u32,
+    /// When the last full snapshot was sent, per the sender's clock
+    pub(crate) last_keyframe: Option<Duration>,
+    /// Send the next update as a full snapshot
+    full_requested: bool,
+    /// JSON Pointer to the part of each state the receiver subscribed to
+    subscription: Option<String>,
+    /// Sequence number of the last update sent (`StreamConfig::acked`)
+    seq: u64,
+    /// Last acknowledged state, the baseline of deltas (`StreamConfig::acked`)
+    acked: Option<(u64, serde_json::Value)>,
+    /// States sent and not yet acknowledged, oldest first (`StreamConfig::acked`)
+    unacked: VecDeque<(u64, serde_json::Value)>,
+    /// FLUX session carrying merge patches (`PatchFormat::MergePatch`)
+    pub(crate) patch_session: FluxSession,
+}
+
+/// Receiving half of a stream: applies deltas to the last state
+pub struct StreamReceiver {
+    patch_format: PatchFormat,
+    acked: bool,
+    last_seq: Option<u64>,
+    clock: Arc<dyn Clock>,
+    channels: HashMap<u32, ChannelReceiver>,
+    stats: StreamReceiverStats,
+}
+
+/// Receiving state of one channel
+pub(crate) struct ChannelReceiver {
+    pub(crate) delta_decoder: DeltaDecoder,
+    /// States received, oldest first, that later updates may build on
+    /// (`StreamConfig::acked`)
+    received: VecDeque<(u64, serde_json::Value)>,
+    /// FLUX session carrying merge patches (`PatchFormat::MergePatch`)
+    pub(crate) patch_session: FluxSession,
+}
+
+/// Streaming session statistics (sending side, all channels)
+#[derive(Debug, Clone, Default)]
+pub struct StreamStats {
+    pub updates_sent: u64,
+    pub full_sends: u64,
+    pub delta_sends: u64,
+    pub bytes_full: u64,
+    pub bytes_delta: u64,
+}
+
+/// Statistics of a `StreamReceiver`, all channels
+#[derive(Debug, Clone, Default)]
+pub struct StreamReceiverStats {
+    /// Updates applied
+    pub updates_received: u64,
+    /// Bytes of the updates applied
+    pub bytes_received: u64,
+    /// Updates that failed to apply, leaving the state unchanged
+    pub rejected: u64,
+}
+
+impl FluxStreamSession {
     /// Create new streaming session
     pub fn new() -> Self {
+        Self::with_config(StreamConfig::default())
+    }
+
+    /// Create new streaming session with keyframe and delta size limits
+    pub fn with_config(config: StreamConfig) -> Self {
+        Self {
+            rx: StreamReceiver::with_config(config.clone()),
+            tx: StreamSender::with_config(config),
+        }
+    }
+
+    /// Join a sender and a receiver into one session
+    pub fn from_parts(tx: StreamSender, rx: StreamReceiver) -> Self {
+        Self { tx, rx }
+    }
+
+    /// Separate the sending and receiving halves
+    pub fn split(self) -> (StreamSender, StreamReceiver) {
+        (self.tx, self.rx)
+    }
+
+    /// Sending half
+    pub fn sender(&self) -> &StreamSender {
+        &self.tx
+    }
+
+    pub fn sender_mut(&mut self) -> &mut StreamSender {
+        &mut self.tx
+    }
+
+    /// Receiving half
+    pub fn receiver(&self) -> &StreamReceiver {
+        &self.rx
+    }
+
+    pub fn receiver_mut(&mut self) -> &mut StreamReceiver {
+        &mut self.rx
+    }
+
+    /// Replace the time source used for `StreamConfig::keyframe_period`
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.rx.set_clock(clock.clone());
+        self.tx.set_clock(clock);
+    }
+
+    /// Send state update, returning compressed delta
+    pub fn update(&mut self, json: &[u8]) -> Result<Vec<u8>> {
+        self.tx.update(json)
+    }
+
+    /// Send a state update for the document on `channel`
+    pub fn update_channel(&mut self, channel: u32, json: &[u8]) -> Result<Vec<u8>> {
+        self.tx.update_channel(channel, json)
+    }
+
+    /// Send the next update on every channel as a full snapshot
+    pub fn force_full_next(&mut self) {
+        self.tx.force_full_next();
+    }
+
+    /// Send only the part of each state at `pointer` (JSON Pointer, `""` for all of it)
+    pub fn subscribe(&mut self, pointer: &str) -> Result<()> {
+        self.tx.subscribe(pointer)
+    }
+
+    /// Record that the peer applied update `seq` (`StreamConfig::acked`)
+    pub fn on_ack(&mut self, seq: u64) -> bool {
+        self.tx.on_ack(seq)
+    }
+
+    /// Send the latest state again if it wasn't acknowledged
+    pub fn retransmit(&mut self) -> Result<Option<Vec<u8>>> {
+        self.tx.retransmit()
+    }
+
+    /// Receive delta and reconstruct state
+    pub fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.rx.receive(data)
+    }
+
+    /// Receive an update, returning its channel and the channel's new state
+    pub fn receive_channel(&mut self, data: &[u8]) -> Result<(u32, Vec<u8>)> {
+        self.rx.receive_channel(data)
+    }
+
+    /// Get streaming statistics of the sending side
+    pub fn stats(&self) -> &StreamStats {
+        self.tx.stats()
+    }
+
+    /// Statistics of the receiving side
+    pub fn receiver_stats(&self) -> &StreamReceiverStats {
+        self.rx.stats()
+    }
+
+    /// Bytes held by both directions
+    pub fn memory_usage(&self) -> StreamMemoryUsage {
+        let mut usage = self.tx.memory_usage();
+        usage += self.rx.memory_usage();
+        usage
+    }
+
+    /// Calculate delta efficiency (bytes saved / bytes if all were full)
+    pub fn delta_efficiency(&self) -> f64 {
+        self.tx.delta_efficiency()
+    }
+
+    /// Reset session state, both directions
+    pub fn reset(&mut self) {
+        self.tx.reset();
+        self.rx.reset();
+    }
+}
+
+impl StreamSender {
+    pub fn new() -> Self {
+        Self::with_config(StreamConfig::default())
+    }
+
+    pub fn with_config(config: StreamConfig) -> Self {
         Self {
-            delta_encoder: DeltaEncoder::new(),
-            delta_decoder: DeltaDecoder::new(),
+            config,
+            clock: clock::default_clock(),
+            channels: HashMap::new(),
             stats: StreamStats::default(),
         }
     }
 
-    /// Send state update, returning compressed delta
-    pub fn update(&mut self, json: &[u8]) -> Result<Vec<u8>> {
-        let value: serde_json::Value = serde_json::from_slice(json)
-            .map_err(|e| Error::ParseError(e.to_string()))?;
+    /// Replace the time source used for `StreamConfig::keyframe_period`
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        for channel in self.channels.values_mut() {
+            channel.patch_session.set_clock(clock.clone());
+        }
+        self.clock = clock;
+    }
+
+    /// Send state update, returning compressed delta
+    pub fn update(&mut self, json: &[u8]) -> Result<Vec<u8>> {
+        self.update_channel(DEFAULT_CHANNEL, json)
+    }
+
+    /// Send a state update for the document on `channel`
+    ///
+    /// Each channel has its own baseline and keyframe schedule, so a
+    /// change to one document costs a delta against that document alone.
+    pub fn update_channel(&mut self, channel: u32, json: &[u8]) -> Result<Vec<u8>> {
+        let value: serde_json::Value = serde_json::from_slice(json)
+            .map_err(|e| Error::ParseError(e.to_string()))?;
+        let value = match &self.channel(channel).subscription {
+            Some(pointer) => value.pointer(pointer).cloned().unwrap_or(serde_json::Value::Null),
+            None => value,
+        };
+        self.send(channel, value)
+    }
+
+    fn send(&mut self, channel: u32, value: serde_json::Value) -> Result<Vec<u8>> {
+        let acked = self.config.acked;
+        if acked && self.config.patch_format == PatchFormat::MergePatch {
+            return Err(Error::InvalidConfig {
+                field: "acked",
+                reason: "merge patches ride a FLUX session, which assumes delivery".into(),
+            });
+        }
+
+        let period = self.config.keyframe_period;
+        let now = if period.is_zero() { None } else { self.clock.now() };
+        let (clock, config) = (&self.clock, &self.config);
+        let state = self.channels.entry(channel).or_insert_with(|| ChannelSender::new(clock.clone(), config));
+        let overdue = match (now, state.last_keyframe) {
+            (Some(now), Some(last)) => now.saturating_sub(last) >= period,
+            _ => false,
+        };
+        let interval = self.config.keyframe_interval;
+        let keyframe = state.full_requested || (interval > 0 && state.since_keyframe >= interval) || overdue;
+        if keyframe && self.config.patch_format == PatchFormat::Delta {
+            state.delta_encoder.reset();
+        } else if acked {
+            match &state.acked {
+                Some((_, base)) => {
+                    state.delta_encoder.replace_state(base.clone());
+                }
+                None => state.delta_encoder.reset(),
+            }
+        }
+
+        let max = self.config.max_delta_bytes;
+        // Delta states are kept for acknowledgment
+        let (payload, full, value) = match self.config.patch_format {
+            PatchFormat::Delta => {
+                let (payload, full) = state.encode_delta(&value, max)?;
+                (payload, full, Some(value))
+            }
+            PatchFormat::MergePatch => {
+                let (payload, full) = state.encode_merge_patch(value, keyframe, max)?;
+                (payload, full, None)
+            }
+        };
+
+        state.full_requested = false;
+        self.stats.updates_sent += 1;
+        if full {
+            state.since_keyframe = 1;
+            state.last_keyframe = now;
+            self.stats.full_sends += 1;
+            self.stats.bytes_full += payload.len() as u64;
+        } else {
+            state.since_keyframe += 1;
+            self.stats.delta_sends += 1;
+            self.stats.bytes_delta += payload.len() as u64;
+        }
+
+        let mut framed = Vec::with_capacity(payload.len() + 16);
+        if acked {
+            let base = match &state.acked {
+                Some((seq, _)) if !full => *seq,
+                _ => 0,
+            };
+            state.seq += 1;
+            encoding::encode_varint(state.seq, &mut framed);
+            encoding::encode_varint(base, &mut framed);
+            state.unacked.extend(value.map(|value| (state.seq, value)));
+            if state.unacked.len() > MAX_UNACKED_STATES {
+                state.unacked.pop_front();
+            }
+        }
+        if channel != DEFAULT_CHANNEL {
+            framed.push(CHANNEL_TAG);
+            encoding::encode_varint(channel as u64, &mut framed);
+        }
+        if framed.is_empty() {
+            return Ok(payload);
+        }
+        framed.extend_from_slice(&payload);
+        Ok(framed)
+    }
+
+    /// Record that the receiver applied update `seq` on `DEFAULT_CHANNEL`
+    pub fn on_ack(&mut self, seq: u64) -> bool {
+        self.on_ack_channel(DEFAULT_CHANNEL, seq)
+    }
+
+    /// Record that the receiver applied update `seq` on `channel`
+    /// (`StreamConfig::acked`), making its state the baseline of later
+    /// deltas
+    ///
+    /// Acknowledging an update also retires the ones sent before it.
+    /// Returns false for updates no longer awaiting acknowledgment.
+    pub fn on_ack_channel(&mut self, channel: u32, seq: u64) -> bool {
+        let Some(state) = self.channels.get_mut(&channel) else { return false };
+        let Some(i) = state.unacked.iter().position(|(sent, _)| *sent == seq) else { return false };
+        state.acked = state.unacked.drain(..=i).next_back();
+        true
+    }
+
+    /// Send the latest state on `DEFAULT_CHANNEL` again, if it wasn't acknowledged
+    pub fn retransmit(&mut self) -> Result<Option<Vec<u8>>> {
+        self.retransmit_channel(DEFAULT_CHANNEL)
+    }
+
+    /// Send the latest state on `channel` again, if it wasn't acknowledged
+    ///
+    /// The update gets a new sequence number and is computed against the
+    /// last acknowledged state, so it applies whichever earlier updates the
+    /// receiver lost.
+    pub fn retransmit_channel(&mut self, channel: u32) -> Result<Option<Vec<u8>>> {
+        let latest = self.channels.get(&channel).and_then(|state| state.unacked.back());
+        match latest.map(|(_, value)| value.clone()) {
+            Some(value) => self.send(channel, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Sending state of `channel`, created on first use
+    pub(crate) fn channel(&mut self, channel: u32) -> &mut ChannelSender {
+        let (clock, config) = (&self.clock, &self.config);
+        self.channels.entry(channel).or_insert_with(|| ChannelSender::new(clock.clone(), config))
+    }
+
+    /// Send the next update on every channel as a full snapshot
+    ///
+    /// For a receiver whose `receive` failed with
+    /// `Error::DeltaStateMismatch`: its state is unchanged, and applies
+    /// nothing until the snapshot arrives.
+    pub fn force_full_next(&mut self) {
+        for channel in self.channels.values_mut() {
+            channel.full_requested = true;
+        }
+    }
+
+    /// Send the next update on `channel` as a full snapshot
+    pub fn force_full_next_channel(&mut self, channel: u32) {
+        if let Some(channel) = self.channels.get_mut(&channel) {
+            channel.full_requested = true;
+        }
+    }
+
+    /// Send only the part of each state at `pointer` on `DEFAULT_CHANNEL`
+    pub fn subscribe(&mut self, pointer: &str) -> Result<()> {
+        self.subscribe_channel(DEFAULT_CHANNEL, pointer)
+    }
+
+    /// Send only the part of each state at `pointer` on `channel`
+    ///
+    /// For receivers that need one subtree of a large document: deltas are
+    /// computed against that subtree alone, and the receiver's state is the
+    /// value at `pointer` (`null` while the document has none). `pointer` is
+    /// an RFC 6901 JSON Pointer such as `/users`; `""` sends whole states
+    /// again. The next update is a full snapshot.
+    ///
+    /// Subscriptions aren't part of `export_state`; subscribe again after
+    /// `import_state`.
+    pub fn subscribe_channel(&mut self, channel: u32, pointer: &str) -> Result<()> {
+        check_pointer(pointer)?;
+        let state = self.channel(channel);
+        state.subscription = (!pointer.is_empty()).then(|| pointer.to_string());
+        state.full_requested = true;
+        Ok(())
+    }
+
+    /// Forget `channel`'s baseline; its next update is a full send
+    pub fn remove_channel(&mut self, channel: u32) {
+        self.channels.remove(&channel);
+    }
+
+    /// Get streaming statistics
+    pub fn stats(&self) -> &StreamStats {
+        &self.stats
+    }
+
+    /// Bytes held by the channels' baselines, unacknowledged states and
+    /// patch sessions
+    pub fn memory_usage(&self) -> StreamMemoryUsage {
+        let mut usage = StreamMemoryUsage::default();
+        for state in self.channels.values() {
+            let acked = state.acked.as_ref().map(|(_, value)| value);
+            usage.baselines += state.delta_encoder.state().into_iter().chain(acked).map(delta::value_memory_usage).sum::<usize>();
+            usage.retained += state.unacked.iter().map(|(_, value)| delta::value_memory_usage(value)).sum::<usize>();
+            usage.sessions += state.patch_session.memory_usage().total();
+        }
+        usage
+    }
+
+    /// Calculate delta efficiency (bytes saved / bytes if all were full)
+    pub fn delta_efficiency(&self) -> f64 {
+        let total = self.stats.bytes_full + self.stats.bytes_delta;
+        if total == 0 || self.stats.full_sends == 0 {
+            return 0.0;
+        }
+
+        // Estimate: if all were full sends, bytes would be approximately
+        // (bytes_full / full_sends) * total_sends
+        let avg_full = self.stats.bytes_full as f64 / self.stats.full_sends as f64;
+        let estimated_full = avg_full * self.stats.updates_sent as f64;
+
+        if estimated_full == 0.0 {
+            return 0.0;
+        }
+
+        1.0 - (total as f64 / estimated_full)
+    }
+
+    /// Forget every channel's sent state and the statistics; the next
+    /// update is a full send
+    pub fn reset(&mut self) {
+        self.channels.clear();
+        self.stats = StreamStats::default();
+    }
+}
+
+impl ChannelSender {
+    pub(crate) fn new(clock: Arc<dyn Clock>, config: &StreamConfig) -> Self {
+        let mut patch_session = FluxSession::new();
+        patch_session.set_clock(clock);
+        Self {
+            delta_encoder: DeltaEncoder::new().with_string_diff(config.string_diff).with_limits(config.delta_limits),
+            since_keyframe: 0,
+            last_keyframe: None,
+            full_requested: false,
+            subscription: None,
+            seq: 0,
+            acked: None,
+            unacked: VecDeque::new(),
+            patch_session,
+        }
+    }
+
+    /// Encode `value` as a `DeltaOp`, returning the bytes and whether it is a full snapshot
+    fn encode_delta(&mut self, value: &serde_json::Value, max: usize) -> Result<(Vec<u8>, bool)> {
+        let mut delta = self.delta_encoder.encode(value)?;
+        let mut serialized = serialize_delta(&delta)?;
+
+        // Oversized delta: fall back to a full snapshot
+        if max > 0 && serialized.len() > max && !matches!(delta, DeltaOp::Add(_)) {
+            self.delta_encoder.reset();
+            delta = self.delta_encoder.encode(value)?;
+            serialized = serialize_delta(&delta)?;
+        }
+
+        Ok((serialized, matches!(delta, DeltaOp::Add(_))))
+    }
+
+    /// Encode `value` as a FLUX-compressed merge patch
+    ///
+    /// Snapshots carry the whole state (plus deletions of members the last
+    /// state had) and their schema, so they apply on any receiver state.
+    fn encode_merge_patch(&mut self, value: serde_json::Value, keyframe: bool, max: usize) -> Result<(Vec<u8>, bool)> {
+        if !value.is_object() {
+            return Err(Error::UnsupportedType("Merge patch states must be JSON objects".into()));
+        }
+        // The receiver would read them as deletions
+        if delta::merge_patch::has_null_member(&value) {
+            return Err(Error::UnsupportedType("Merge patch states can't have null members".into()));
+        }
+
+        let prev = self.delta_encoder.replace_state(value.clone());
+        let snapshot = |session: &mut FluxSession, prev: Option<&serde_json::Value>| {
+            let patch = delta::snapshot_merge_patch(prev, &value);
+            let opts = MessageOptions { force_schema: true, ..Default::default() };
+            session.compress_with(&to_json_bytes(&patch)?, &opts)
+        };
+
+        let prev = match prev {
+            Some(prev) if !keyframe => prev,
+            prev => return Ok((snapshot(&mut self.patch_session, prev.as_ref())?, true)),
+        };
+
+        let patch = delta::create_merge_patch(&prev, &value);
+        let serialized = self.patch_session.compress(&to_json_bytes(&patch)?)?;
+
+        if max > 0 && serialized.len() > max {
+            return Ok((snapshot(&mut self.patch_session, Some(&prev))?, true));
+        }
+        Ok((serialized, false))
+    }
+}
+
+impl StreamReceiver {
+    pub fn new() -> Self {
+        Self::with_config(StreamConfig::default())
+    }
+
+    /// Create a receiver for a sender using `config` (only the patch format
+    /// and `acked` matter)
+    pub fn with_config(config: StreamConfig) -> Self {
+        Self {
+            patch_format: config.patch_format,
+            acked: config.acked,
+            last_seq: None,
+            clock: clock::default_clock(),
+            channels: HashMap::new(),
+            stats: StreamReceiverStats::default(),
+        }
+    }
+
+    /// Replace the time source of the merge patch sessions
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        for channel in self.channels.values_mut() {
+            channel.patch_session.set_clock(clock.clone());
+        }
+        self.clock = clock;
+    }
+
+    /// Receive delta and reconstruct state
+    pub fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.receive_channel(data).map(|(_, state)| state)
+    }
+
+    /// Receive an update, returning its channel and the channel's new state
+    pub fn receive_channel(&mut self, data: &[u8]) -> Result<(u32, Vec<u8>)> {
+        let result = if self.acked {
+            read_seq(data).and_then(|(seq, base, rest)| {
+                let (channel, payload) = read_channel(rest)?;
+                let json = self.channel(channel).apply_acked(seq, base, payload)?;
+                self.last_seq = Some(seq);
+                Ok((channel, json))
+            })
+        } else {
+            read_channel(data).and_then(|(channel, payload)| {
+                let patch_format = self.patch_format;
+                Ok((channel, self.channel(channel).apply(patch_format, payload)?))
+            })
+        };
+        match &result {
+            Ok(_) => {
+                self.stats.updates_received += 1;
+                self.stats.bytes_received += data.len() as u64;
+            }
+            Err(_) => self.stats.rejected += 1,
+        }
+        result
+    }
+
+    /// Receiving state of `channel`, created on first use
+    pub(crate) fn channel(&mut self, channel: u32) -> &mut ChannelReceiver {
+        let clock = &self.clock;
+        self.channels.entry(channel).or_insert_with(|| ChannelReceiver::new(clock.clone()))
+    }
+
+    /// Sequence number of the last update received (`StreamConfig::acked`),
+    /// for the sender's `on_ack`
+    pub fn last_seq(&self) -> Option<u64> {
+        self.last_seq
+    }
+
+    /// Forget `channel`'s state
+    pub fn remove_channel(&mut self, channel: u32) {
+        self.channels.remove(&channel);
+    }
+
+    /// Statistics of the updates received
+    pub fn stats(&self) -> &StreamReceiverStats {
+        &self.stats
+    }
+
+    /// Bytes held by the channels' states, states kept for later updates to
+    /// build on, and patch sessions
+    pub fn memory_usage(&self) -> StreamMemoryUsage {
+        let mut usage = StreamMemoryUsage::default();
+        for state in self.channels.values() {
+            usage.baselines += state.delta_decoder.state().map_or(0, delta::value_memory_usage);
+            usage.retained += state.received.iter().map(|(_, value)| delta::value_memory_usage(value)).sum::<usize>();
+            usage.sessions += state.patch_session.memory_usage().total();
+        }
+        usage
+    }
+
+    /// Forget every channel's state and the statistics
+    pub fn reset(&mut self) {
+        self.channels.clear();
+        self.stats = StreamReceiverStats::default();
+    }
+}
+
+impl ChannelReceiver {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        let mut patch_session = FluxSession::new();
+        patch_session.set_clock(clock);
+        Self { delta_decoder: DeltaDecoder::new(), received: VecDeque::new(), patch_session }
+    }
+
+    /// Apply update `seq`, a delta against update `base` (0 for none)
+    fn apply_acked(&mut self, seq: u64, base: u64, data: &[u8]) -> Result<Vec<u8>> {
+        let base_state = match base {
+            0 => None,
+            base => match self.received.iter().find(|(received, _)| *received == base) {
+                Some((_, state)) => Some(state.clone()),
+                None => {
+                    return Err(Error::DeltaStateMismatch { expected: format!("state {}", base), got: "none".into() }
+                        .in_stage(Stage::Delta, None))
+                }
+            },
+        };
+        *self.delta_decoder.state_mut() = base_state;
+        let delta = deserialize_delta(data).map_err(|e| e.in_stage(Stage::Delta, None))?;
+        let value = self.delta_decoder.decode(&delta).map_err(|e| e.in_stage(Stage::Delta, None))?;
+
+        // The sender's baseline only moves forward
+        let json = to_json_bytes(&value)?;
+        self.received.retain(|(received, _)| *received >= base && *received != seq);
+        self.received.push_back((seq, value));
+        if self.received.len() > MAX_UNACKED_STATES + 1 {
+            self.received.pop_front();
+        }
+        Ok(json)
+    }
+
+    fn apply(&mut self, patch_format: PatchFormat, data: &[u8]) -> Result<Vec<u8>> {
+        if patch_format == PatchFormat::MergePatch {
+            let patch: serde_json::Value = serde_json::from_slice(&self.patch_session.decompress(data)?)
+                .map_err(|e| Error::DecodeError(e.to_string()).in_stage(Stage::Delta, None))?;
+            let state = self.delta_decoder.state_mut().get_or_insert_with(|| serde_json::json!({}));
+            delta::apply_merge_patch(state, &patch);
+            return to_json_bytes(state);
+        }
+
+        let delta = deserialize_delta(data).map_err(|e| e.in_stage(Stage::Delta, None))?;
+        let value = self.delta_decoder.decode(&delta).map_err(|e| e.in_stage(Stage::Delta, None))?;
+        to_json_bytes(&value)
+    }
+}
+
+/// Check that `pointer` is an RFC 6901 JSON Pointer
+fn check_pointer(pointer: &str) -> Result<()> {
+    let escapes_valid = pointer.split('~').skip(1).all(|rest| rest.starts_with(['0', '1']));
+    if (pointer.is_empty() || pointer.starts_with('/')) && escapes_valid {
+        Ok(())
+    } else {
+        Err(Error::ParseError(format!("Invalid JSON Pointer: {:?}", pointer)))
+    }
+}
+
+/// Sequence number and baseline of an acknowledged-mode update, and the rest of it
+fn read_seq(data: &[u8]) -> Result<(u64, u64, &[u8])> {
+    let mut cursor = cursor::Cursor::new(data);
+    let seq = cursor.read_varint("update sequence number")?;
+    let base = cursor.read_varint("update baseline")?;
+    if seq == 0 || base >= seq {
+        return Err(Error::InvalidFrame(format!("Update {} can't build on update {}", seq, base)));
+    }
+    Ok((seq, base, cursor.rest()))
+}
+
+/// Channel of a stream update and the update without its channel prefix
+fn read_channel(data: &[u8]) -> Result<(u32, &[u8])> {
+    if data.first() != Some(&CHANNEL_TAG) {
+        return Ok((DEFAULT_CHANNEL, data));
+    }
+    let mut cursor = cursor::Cursor::new(&data[1..]);
+    let channel = u32::try_from(cursor.read_varint("stream channel")?)
+        .map_err(|_| Error::InvalidFrame("Stream channel out of range".into()))?;
+    Ok((channel, cursor.rest()))
+}
+
+/// Parse JSON nested at most `max_depth` deep
+///
+/// serde_json's own limit of 128 levels is lifted, as the depth is checked
+/// with a scan of the text first.
+fn parse_json(input: &[u8], max_depth: usize) -> Result<serde_json::Value> {
+    if json_exceeds_depth(input, max_depth) {
+        return Err(Error::DepthLimitExceeded { max: max_depth });
+    }
+    let mut deserializer = serde_json::Deserializer::from_slice(input);
+    deserializer.disable_recursion_limit();
+    let value = serde::Deserialize::deserialize(&mut deserializer).map_err(|e| Error::ParseError(e.to_string()))?;
+    deserializer.end().map_err(|e| Error::ParseError(e.to_string()))?;
+    Ok(value)
+}
+
+/// Whether JSON text opens more than `max` brackets at once, outside strings
+fn json_exceeds_depth(input: &[u8], max: usize) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in input {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max {
+                    return true;
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether `value` nests objects and arrays more than `max` deep, checked
+/// without recursion
+fn exceeds_depth(value: &serde_json::Value, max: usize) -> bool {
+    // Each value with the number of containers around it
+    let mut stack = vec![(value, 0)];
+    while let Some((value, depth)) = stack.pop() {
+        match value {
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) if depth >= max => return true,
+            serde_json::Value::Array(items) => stack.extend(items.iter().map(|item| (item, depth + 1))),
+            serde_json::Value::Object(members) => stack.extend(members.values().map(|member| (member, depth + 1))),
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Message of a raw frame (`frame::RAW_PAYLOAD`), keeping only `projection`'s fields
+fn read_raw_json(payload: &[u8], projection: Option<&[&str]>, limit: usize, max_depth: usize) -> Result<serde_json::Value> {
+    if payload.len() > limit {
+        return Err(Error::OutputLimitExceeded { limit });
+    }
+    let mut value = parse_json(payload, max_depth).map_err(|e| e.in_stage(Stage::Encoding, None))?;
+    if let Some(fields) = projection {
+        let project = |value: &mut serde_json::Value| {
+            if let serde_json::Value::Object(map) = value {
+                map.retain(|key, _| fields.contains(&key.as_str()));
+            }
+        };
+        match &mut value {
+            serde_json::Value::Array(records) => records.iter_mut().for_each(project),
+            record => project(record),
+        }
+    }
+    Ok(value)
+}
+
+/// JSON text of a raw frame: compact, with object keys sorted like schema
+/// fields unless `preserve_key_order` keeps them as given
+///
+/// Raw frames thus depend on the message, not on how its text was written.
+fn raw_json(value: &serde_json::Value, preserve_key_order: bool) -> Result<Vec<u8>> {
+    if preserve_key_order {
+        return to_json_bytes(value);
+    }
+    let mut sorted = value.clone();
+    sorted.sort_all_objects();
+    to_json_bytes(&sorted)
+}
+
+fn to_json_bytes(value: &serde_json::Value) -> Result<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| Error::SerializeError(e.to_string()))
+}
+
+impl Default for FluxStreamSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for StreamSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for StreamReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_decompress_simple() {
+        let json = br#"{"id": 123, "name": "test"}"#;
+        let compressed = compress(json).unwrap();
+
+        // Verify magic bytes
+        assert_eq!(&compressed[0..4], b"FLUX");
+        assert_eq!(decompress(&compressed).unwrap(), br#"{"id":123,"name":"test"}"#);
+
+        // Absent members of nested objects stay apart from null members
+        let json = br#"{"items":[{},{"a":null},{"a":1}],"mixed":[{"b":true},2,"x"],"big":18446744073709551615}"#;
+        let output = decompress(&compress(json).unwrap()).unwrap();
+        let parse = |b: &[u8]| serde_json::from_slice::<serde_json::Value>(b).unwrap();
+        assert_eq!(parse(&output), parse(json));
+    }
+
+    #[test]
+    fn test_session_schema_caching() {
+        let mut session = FluxSession::new();
+
+        // First message - schema included
+        let c1 = session.compress(br#"{"id": 1, "name": "alice"}"#).unwrap();
+
+        // Second message - schema cached
+        let c2 = session.compress(br#"{"id": 2, "name": "bob"}"#).unwrap();
+
+        // Second should be smaller (no schema) - but may not always be true
+        // depending on field order serialization
+        // For now just verify both compress successfully
+        assert!(!c1.is_empty());
+        assert!(!c2.is_empty());
+
+        // Stats should show cache hit
+        assert_eq!(session.stats().cache_hits, 1);
+        assert_eq!(session.stats().cache_misses, 1);
+    }
+
+    #[test]
+    fn test_session_prune_schemas() {
+        let mut session = FluxSession::new();
+        session.compress(br#"{"id": 1}"#).unwrap();
+        session.compress(br#"{"name": "alice"}"#).unwrap();
+        session.compress(br#"{"active": true}"#).unwrap();
+
+        let ids: Vec<u32> = session.schema_cache().iter().map(|s| s.id).collect();
+        assert_eq!(ids, [1, 2, 3]);
+
+        assert!(session.remove_schema(1).is_some());
+        session.retain_schemas(|schema| schema.fields[0].name != "name");
+        assert_eq!(session.stats().schemas_cached, 1);
+
+        // A pruned shape is sent with its schema again
+        let frame = session.compress(br#"{"id": 2}"#).unwrap();
+        let header = FrameHeader::parse(&frame[4..]).unwrap();
+        assert!(header.flags.contains(FrameFlags::SCHEMA_INCLUDED));
+        assert_eq!(header.schema_id, 4);
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let json = serde_json::to_vec(&serde_json::json!({"text": "a".repeat(4000)})).unwrap();
+        let compressed = compress(&json).unwrap();
+
+        assert_eq!(decompress_with_limit(&compressed, json.len()).unwrap(), json);
+        let err = decompress_with_limit(&compressed, 1000).unwrap_err();
+        assert!(matches!(err.root(), Error::OutputLimitExceeded { limit: 1000 }));
+
+        let config = FluxConfig::builder().max_decompressed_size(json.len() - 1).build().unwrap();
+        let mut session = FluxSession::with_config(config);
+        assert!(matches!(session.decompress(&compressed).unwrap_err().root(), Error::OutputLimitExceeded { .. }));
+        let mut out = Vec::new();
+        assert!(matches!(session.decompress_to(&compressed, &mut out).unwrap_err().root(), Error::OutputLimitExceeded { .. }));
+
+        assert!(FluxConfig::builder().max_decompressed_size(0).build().is_err());
+    }
+
+    #[test]
+    fn test_compress_to_decompress_to() {
+        let json = br#"{"id": 7, "name": "alice", "tags": ["a", "b"]}"#;
+
+        let mut framed = Vec::new();
+        compress_to(json, &mut framed).unwrap();
+        assert_eq!(framed, compress(json).unwrap());
+
+        let mut out = std::io::Cursor::new(Vec::new());
+        decompress_to(&framed, &mut out).unwrap();
+        assert_eq!(out.into_inner(), decompress(&framed).unwrap());
+
+        // Metadata and checksum sections go through the writer too
+        let mut session = FluxSession::new();
+        let mut opts = MessageOptions::default();
+        opts.metadata.insert("trace", "abc");
+        let mut framed = Vec::new();
+        session.compress_with_to(json, &opts, &mut framed).unwrap();
+        assert_eq!(session.stats().bytes_out, framed.len() as u64);
+        assert_eq!(read_metadata(&framed).unwrap().get_str("trace"), Some("abc"));
+        assert!(decompress(&framed).is_ok());
+
+        struct Full;
+        impl std::io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::WriteZero.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        assert!(matches!(compress_to(json, &mut Full), Err(Error::Io(_))));
+        assert!(matches!(decompress_to(&framed, &mut Full), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_lz_history() {
+        let messages: Vec<String> = (0..5)
+            .map(|i| {
+                format!(
+                    r#"{{"id":{},"status":"active","title":"Quarterly report for the northern region","owner":"operations-team"}}"#,
+                    i
+                )
+            })
+            .collect();
+        let config = FluxConfig::builder().lz_history(4096).build().unwrap();
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::with_config(config);
+        let mut plain = FluxSession::new();
+
+        let mut frames = Vec::new();
+        for json in &messages {
+            let frame = sender.compress(json.as_bytes()).unwrap();
+            let decoded: serde_json::Value = serde_json::from_slice(&receiver.decompress(&frame).unwrap()).unwrap();
+            assert_eq!(decoded, serde_json::from_str::<serde_json::Value>(json).unwrap());
+            frames.push(frame);
+        }
+        // Later messages reference the earlier ones
+        let last = plain.compress(messages[4].as_bytes()).unwrap();
+        assert!(frames[4].len() < last.len());
+        assert!(sender.stats().lz_history_matches > 0);
+
+        // A session without the history can't decode them
+        let mut fresh = FluxSession::new();
+        let err = frames.iter().map(|frame| fresh.decompress(frame)).find_map(Result::err).unwrap();
+        assert!(matches!(err.root(), Error::DecodeError(_)));
+        assert_eq!(err.stage(), Some(Stage::Lz));
+
+        // Reset starts both windows over
+        sender.reset();
+        receiver.reset();
+        let frame = sender.compress(messages[0].as_bytes()).unwrap();
+        assert!(receiver.decompress(&frame).is_ok());
+    }
+
+    #[test]
+    fn test_lz_skipped() {
+        use base64::Engine;
+
+        // Random bytes (stored raw from base64) give LZ nothing to match
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let bytes: Vec<u8> = (0..8192)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let blob = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let random = serde_json::to_vec(&serde_json::json!({"blob": blob, "id": 1})).unwrap();
+        let repetitive = serde_json::to_vec(&serde_json::json!({"rows": vec!["the same row"; 500]})).unwrap();
+
+        let mut session = FluxSession::new();
+        let frame = session.compress(&random).unwrap();
+        assert_eq!(decompress(&frame).unwrap(), random);
+        assert_eq!(session.stats().lz_skipped, 1);
+        session.compress(&repetitive).unwrap();
+        assert_eq!(session.stats().lz_skipped, 1);
+        assert!(session.stats().lz_matches > 0);
+
+        // With the stage off, nothing goes through it
+        let mut session = FluxSession::with_config(FluxConfig::builder().lz(false).build().unwrap());
+        for json in [&random, &repetitive] {
+            let frame = session.compress(json).unwrap();
+            assert_eq!(&decompress(&frame).unwrap(), json);
+        }
+        assert_eq!(session.stats().lz_skipped, 2);
+        assert_eq!(session.stats().lz_matches, 0);
+    }
+
+    #[test]
+    fn test_adaptive_skips_stages() {
+        use base64::Engine;
+
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = || {
+            let bytes: Vec<u8> = (0..2048)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let blob = base64::engine::general_purpose::STANDARD.encode(&bytes);
+            serde_json::to_vec(&serde_json::json!({"blob": blob, "id": 1})).unwrap()
+        };
+        let config = FluxConfig::builder().adaptive(true).build().unwrap();
+        let mut session = FluxSession::with_config(config);
+        let mut receiver = FluxSession::new();
+
+        // Stages stop once they keep failing on random bytes
+        for _ in 0..adaptive::MIN_SAMPLES + 10 {
+            let json = random();
+            assert_eq!(receiver.decompress(&session.compress(&json).unwrap()).unwrap(), json);
+        }
+        let stats = session.stats();
+        assert_eq!((stats.lz_skipped, stats.entropy_skipped), (10, 10));
+
+        // A probe finds compressible traffic and brings them back
+        let text = serde_json::to_vec(&serde_json::json!({"rows": vec!["the same row"; 500]})).unwrap();
+        for _ in 0..adaptive::PROBE_INTERVAL + 10 {
+            assert_eq!(receiver.decompress(&session.compress(&text).unwrap()).unwrap(), text);
+        }
+        let stats = session.stats().clone();
+        assert!(stats.entropy_skipped < 10 + adaptive::PROBE_INTERVAL as u64);
+        session.compress(&text).unwrap();
+        assert_eq!(session.stats().entropy_skipped, stats.entropy_skipped);
+        assert_eq!(session.stats().lz_skipped, stats.lz_skipped);
+    }
+
+    #[test]
+    fn test_levels_roundtrip() {
+        let json = r#"{"at":"2024-01-15T10:30:00Z","id":"550e8400-e29b-41d4-a716-446655440000","tags":["a","b","a","b"]}"#;
+        let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+        for level in [FluxLevel::Fast, FluxLevel::Default, FluxLevel::Max] {
+            let mut session = FluxSession::with_config(FluxConfig::with_level(level));
+            let frame = session.compress(json.as_bytes()).unwrap();
+            let decoded: serde_json::Value = serde_json::from_slice(&decompress(&frame).unwrap()).unwrap();
+            assert_eq!(decoded, expected);
+
+            // Fast stores timestamps and UUIDs as plain strings
+            let mut inferrer = SchemaInferrer::new();
+            inferrer.add_value(&expected).unwrap();
+            let detected = inferrer.infer().unwrap();
+            assert_eq!(session.schema_cache().get_by_hash(detected.hash).is_none(), level == FluxLevel::Fast);
+        }
+    }
+
+    #[test]
+    fn test_levels_differ() {
+        let notes = ["alpha beta gamma", "alpha beta delta", "alpha gamma beta", "beta alpha gamma"];
+        let rows: Vec<String> = (0..200)
+            .map(|i| format!(r#"{{"id":{},"name":"user-{}","note":"{}"}}"#, i, i % 37, notes[i * 7 % 4]))
+            .collect();
+        let json = format!("[{}]", rows.join(","));
+        let sizes: Vec<usize> = [FluxLevel::Fast, FluxLevel::Default, FluxLevel::Max]
+            .into_iter()
+            .map(|level| {
+                let frame = FluxSession::with_config(FluxConfig::with_level(level)).compress(json.as_bytes()).unwrap();
+                assert_eq!(decompress(&frame).unwrap(), json.as_bytes());
+                frame.len()
+            })
+            .collect();
+        assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2], "{:?}", sizes);
+    }
 
-        let delta = self.delta_encoder.encode(&value)?;
-        let serialized = serialize_delta(&delta)?;
+    #[test]
+    fn test_raw_frames() {
+        let tiny = br#"{"ok":true,"id":7}"#;
+        let large = serde_json::to_vec(&serde_json::json!({"items": vec!["row"; 50]})).unwrap();
+        let config = FluxConfig::builder().raw_threshold(100).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+
+        let frame = sender.compress(tiny).unwrap();
+        assert_eq!(frame.len(), tiny.len() + 7);
+        assert!(RawFrame::parse(&frame).unwrap().header.is_raw());
+        // Like encoded frames, they don't depend on key order or whitespace
+        let config = FluxConfig::builder().raw_threshold(100).build().unwrap();
+        let reordered = FluxSession::with_config(config).compress(br#"{ "id": 7, "ok": true }"#).unwrap();
+        assert_eq!(reordered, frame);
+        let mut log = frame.clone();
+        log.extend(sender.compress(&large).unwrap());
+        assert_eq!(sender.stats().raw_frames, 1);
+
+        // Raw frames sit among others and decode like them
+        let mut receiver = FluxSession::new();
+        let messages = receiver.decompress_all(&log).unwrap();
+        assert_eq!(messages[0], br#"{"id":7,"ok":true}"#);
+        assert_eq!(messages[1], large);
+        assert_eq!(receiver.decompress_projected(&frame, &["id"]).unwrap(), br#"{"id":7}"#);
+
+        let mut corrupt = frame.clone();
+        corrupt[8] ^= 1;
+        assert!(receiver.decompress(&corrupt).is_err());
+
+        // Without a checksum the overhead is 3 bytes
+        let config = FluxConfig::builder().raw_threshold(100).checksum(false).build().unwrap();
+        let frame = FluxSession::with_config(config).compress(tiny).unwrap();
+        assert_eq!(frame.len(), tiny.len() + 3);
+        assert_eq!(decompress(&frame).unwrap(), br#"{"id":7,"ok":true}"#);
+
+        // Recorded key order is kept
+        let config = FluxConfig::builder().raw_threshold(100).preserve_key_order(true).build().unwrap();
+        let frame = FluxSession::with_config(config).compress(tiny).unwrap();
+        assert!(RawFrame::parse(&frame).unwrap().header.is_raw());
+        assert_eq!(decompress(&frame).unwrap(), tiny);
+    }
 
-        self.stats.updates_sent += 1;
-        match &delta {
-            DeltaOp::Add(_) => {
-                self.stats.full_sends += 1;
-                self.stats.bytes_full += serialized.len() as u64;
-            }
-            _ => {
-                self.stats.delta_sends += 1;
-                self.stats.bytes_delta += serialized.len() as u64;
+    #[test]
+    fn test_raw_frames_only_when_smaller() {
+        let config = FluxConfig::builder().raw_threshold(100).compact_header(true).checksum(false).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        let mut receiver = FluxSession::new();
+        let long = format!(r#"{{"id":1,"name":"{}"}}"#, "x".repeat(120));
+        let short = br#"{"id":2,"name":"ab"}"#;
+
+        // Sending the schema along costs more than the JSON text...
+        let frame = sender.compress(short).unwrap();
+        assert_eq!(frame.len(), short.len() + 3);
+        assert_eq!(receiver.decompress(&frame).unwrap(), short);
+
+        // ...but once it's cached, the encoded frame is smaller
+        receiver.decompress(&sender.compress(long.as_bytes()).unwrap()).unwrap();
+        let frame = sender.compress(short).unwrap();
+        let raw = RawFrame::parse(&frame).unwrap();
+        assert!(!raw.header.is_raw());
+        assert!(frame.len() < short.len() + 3, "{} bytes", frame.len());
+        assert_eq!(receiver.decompress(&frame).unwrap(), short);
+        assert_eq!(sender.stats().raw_frames, 1);
+    }
+
+    #[test]
+    fn test_raw_threshold_encodes_once() {
+        // Encoded frames below the threshold match those of a session
+        // without one: sizing a message doesn't sight its values twice
+        for profile in [Profile::Balanced, Profile::Realtime] {
+            let base = FluxConfig::builder().profile(profile).value_dictionary(true).checksum(false);
+            let mut sender = FluxSession::with_config(base.clone().raw_threshold(100).build().unwrap());
+            let mut plain = FluxSession::with_config(base.build().unwrap());
+            let mut receiver = FluxSession::with_config(plain.config.clone());
+            // The first message is long enough to cache the schema
+            let long = "eu-north, the region serving the Nordic and Baltic countries from Stockholm";
+            for (i, region) in [long, "eu-north", "eu-north", "eu-west", "eu-north", "eu-west"].into_iter().enumerate() {
+                let json = format!(r#"{{"id":{},"region":"{}","status":"active"}}"#, i, region);
+                let frame = sender.compress(json.as_bytes()).unwrap();
+                assert_eq!(frame, plain.compress(json.as_bytes()).unwrap(), "{:?} message {}", profile, i);
+                assert_eq!(receiver.decompress(&frame).unwrap(), json.as_bytes());
             }
+            assert_eq!(sender.stats().raw_frames, 0);
         }
+    }
 
-        Ok(serialized)
+    #[test]
+    fn test_compact_header() {
+        let messages: Vec<String> = (0..3).map(|i| format!(r#"{{"id":{},"name":"user{}"}}"#, i, i)).collect();
+        let mut full = FluxSession::new();
+        let mut compact = FluxSession::with_config(FluxConfig::builder().compact_header(true).build().unwrap());
+        let mut receiver = FluxSession::new();
+
+        for json in &messages {
+            let frame = compact.compress(json.as_bytes()).unwrap();
+            let raw = RawFrame::parse(&frame).unwrap();
+            assert!(raw.header.is_compact());
+            assert_eq!(frame.len() + 12, full.compress(json.as_bytes()).unwrap().len());
+            assert_eq!(receiver.decompress(&frame).unwrap(), json.as_bytes());
+        }
+        // A cached-schema message carries 4 bytes of framing plus the checksum
+        let frame = compact.compress(messages[0].as_bytes()).unwrap();
+        let raw = RawFrame::parse(&frame).unwrap();
+        assert_eq!((raw.header.magic_size(), raw.header.size()), (0, 4));
+        assert_eq!(frame.len(), 4 + raw.payload.len() + frame::CHECKSUM_SIZE);
+
+        // The small-frame path writes them too
+        let config = FluxConfig::builder().profile(Profile::Realtime).compact_header(true).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        for json in &messages {
+            let frame = sender.compress(json.as_bytes()).unwrap();
+            assert!(RawFrame::parse(&frame).unwrap().header.is_compact());
+            assert_eq!(receiver.decompress(&frame).unwrap(), json.as_bytes());
+        }
+        assert_eq!(sender.stats().small_frames, 3);
+
+        // Without the checksum, `{"a":1}` takes 4 bytes of framing and a 1-byte payload
+        let config = FluxConfig::builder().compact_header(true).checksum(false).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        receiver.decompress(&sender.compress(br#"{"a":1}"#).unwrap()).unwrap();
+        let frame = sender.compress(br#"{"a":1}"#).unwrap();
+        assert_eq!(frame.len(), 5);
+        assert_eq!(receiver.decompress(&frame).unwrap(), br#"{"a":1}"#);
     }
 
-    /// Receive delta and reconstruct state
-    pub fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>> {
-        let delta = deserialize_delta(data)?;
-        let value = self.delta_decoder.decode(&delta)?;
+    #[test]
+    fn test_realtime_small_frames() {
+        let config = FluxConfig::builder().profile(Profile::Realtime).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        let mut receiver = FluxSession::new();
+
+        for i in 0..3 {
+            let json = format!(r#"{{"user": "u{}", "text": "hello hello hello hello", "ts": {}}}"#, i, i);
+            let mut opts = MessageOptions::default();
+            opts.metadata.insert("seq", i.to_string());
+            let frame = sender.compress_with(json.as_bytes(), &opts).unwrap();
+
+            let header = FrameHeader::parse(&frame[4..]).unwrap();
+            assert!(!header.flags.contains(FrameFlags::FSE_COMPRESSED));
+            assert_eq!(read_metadata(&frame).unwrap().get_str("seq"), Some(i.to_string().as_str()));
+
+            let decoded: serde_json::Value = serde_json::from_slice(&receiver.decompress(&frame).unwrap()).unwrap();
+            assert_eq!(decoded, serde_json::from_str::<serde_json::Value>(&json).unwrap());
+        }
+        assert_eq!(sender.stats().small_frames, 3);
+        assert_eq!(sender.stats().cache_hits, 2);
+
+        // Large inputs take the regular pipeline
+        let large = serde_json::to_vec(&serde_json::json!({"blob": "x".repeat(SMALL_FRAME_MAX)})).unwrap();
+        let frame = sender.compress(&large).unwrap();
+        assert_eq!(sender.stats().small_frames, 3);
+        assert_eq!(receiver.decompress(&frame).unwrap(), large);
+    }
 
-        serde_json::to_vec(&value)
-            .map_err(|e| Error::SerializeError(e.to_string()))
+    #[test]
+    fn test_stage_stats() {
+        let mut session = FluxSession::new();
+        let input = br#"{"id": 1, "name": "alice", "tags": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}"#;
+        let c1 = session.compress(input).unwrap();
+        let c2 = session.compress(input).unwrap();
+
+        let stats = session.stats();
+        assert_eq!(stats.encode_gain(), stats.bytes_in as i64 - stats.encoded_bytes as i64);
+        assert!(stats.lz_gain() >= 0);
+        assert!(stats.entropy_gain() >= 0);
+        assert!(stats.lz_bytes <= stats.encoded_bytes);
+        assert!(stats.entropy_bytes <= stats.lz_bytes);
+
+        // Only the first frame carries the schema
+        assert!(stats.schema_bytes > 0);
+        assert_eq!(c1.len() - c2.len(), stats.schema_bytes as usize);
+
+        assert!(stats.lz_ratio() <= 1.0);
+        assert_eq!(SessionStats::default().entropy_ratio(), 1.0);
     }
 
-    /// Get streaming statistics
-    pub fn stats(&self) -> &StreamStats {
-        &self.stats
+    #[test]
+    fn test_stage_timing() {
+        let input = br#"{"id": 1, "name": "alice", "tags": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}"#;
+
+        // Off by default
+        let mut session = FluxSession::new();
+        session.compress(input).unwrap();
+        assert_eq!(session.stats().compress_time, StageTimings::default());
+
+        let config = FluxConfig::builder().stage_timing(true).build().unwrap();
+        let mut session = FluxSession::with_config(config);
+        let frame = session.compress(input).unwrap();
+        session.decompress(&frame).unwrap();
+
+        let stats = session.stats();
+        let compress = stats.compress_time;
+        assert!(compress.total > Duration::ZERO);
+        assert!(compress.total >= compress.parse + compress.schema + compress.encode + compress.lz + compress.entropy);
+        assert!(stats.decompress_time.total > Duration::ZERO);
+        assert!(["encode", "lz", "entropy"].contains(&compress.bottleneck().0));
     }
 
-    /// Calculate delta efficiency (bytes saved / bytes if all were full)
-    pub fn delta_efficiency(&self) -> f64 {
-        let total = self.stats.bytes_full + self.stats.bytes_delta;
-        if total == 0 || self.stats.full_sends == 0 {
-            return 0.0;
+    #[test]
+    fn test_stage_timing_manual_clock() {
+        let config = FluxConfig::builder().stage_timing(true).build().unwrap();
+        let mut session = FluxSession::with_config(config);
+        let clock = Arc::new(ManualClock::new());
+        session.set_clock(clock.clone());
+        session.compress(br#"{"id": 1}"#).unwrap();
+        assert_eq!(session.stats().compress_time, StageTimings::default());
+
+        session.set_clock(Arc::new(NoClock));
+        session.compress(br#"{"id": 2}"#).unwrap();
+        assert_eq!(session.stats().compress_time, StageTimings::default());
+    }
+
+    #[test]
+    fn test_compress_with_overrides() {
+        let mut session = FluxSession::new();
+        let json = br#"{"id": 1, "name": "alice", "tags": "aaaaaaaaaaaaaaaaaaaaaaaa"}"#;
+        session.compress(json).unwrap();
+
+        // Cached schema is resent on request
+        let forced = session
+            .compress_with(json, &MessageOptions { force_schema: true, ..Default::default() })
+            .unwrap();
+        let header = FrameHeader::parse(&forced[4..]).unwrap();
+        assert!(header.flags.contains(FrameFlags::SCHEMA_INCLUDED));
+
+        let plain = session
+            .compress_with(json, &MessageOptions { skip_entropy: true, ..Default::default() })
+            .unwrap();
+        let header = FrameHeader::parse(&plain[4..]).unwrap();
+        assert!(!header.flags.contains(FrameFlags::SCHEMA_INCLUDED));
+        assert!(!header.flags.contains(FrameFlags::FSE_COMPRESSED));
+
+        // Overrides do not leak into the session config
+        assert!(session.config.entropy);
+
+        // A fresh peer can start from the forced-schema frame
+        let mut decoder = FluxSession::new();
+        decoder.decompress(&forced).unwrap();
+        decoder.decompress(&plain).unwrap();
+    }
+
+    #[test]
+    fn test_frame_metadata() {
+        let mut opts = MessageOptions::default();
+        opts.metadata.insert("trace-id", "4bf92f35").insert("producer", "orders");
+
+        let json = br#"{"id": 1, "name": "alice"}"#;
+        let frame = FluxSession::new().compress_with(json, &opts).unwrap();
+
+        let metadata = read_metadata(&frame).unwrap();
+        assert_eq!(metadata.get_str("trace-id"), Some("4bf92f35"));
+        assert_eq!(metadata.get_str("producer"), Some("orders"));
+
+        let decoded: serde_json::Value = serde_json::from_slice(&decompress(&frame).unwrap()).unwrap();
+        assert_eq!(decoded, serde_json::from_slice::<serde_json::Value>(json).unwrap());
+
+        // Frames without metadata report none
+        assert!(read_metadata(&compress(json).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stats_observer() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<MessageStats>>);
+
+        impl StatsObserver for Recorder {
+            fn observe(&self, stats: &MessageStats) {
+                self.0.lock().unwrap().push(stats.clone());
+            }
         }
 
-        // Estimate: if all were full sends, bytes would be approximately
-        // (bytes_full / full_sends) * total_sends
-        let avg_full = self.stats.bytes_full as f64 / self.stats.full_sends as f64;
-        let estimated_full = avg_full * self.stats.updates_sent as f64;
+        let recorder = Arc::new(Recorder::default());
+        let mut encoder = FluxSession::new();
+        let mut decoder = FluxSession::new();
+        encoder.set_observer(Some(recorder.clone()));
+        decoder.set_observer(Some(recorder.clone()));
+
+        let json = br#"{"id": 1, "name": "alice"}"#;
+        let first = encoder.compress(json).unwrap();
+        let second = encoder.compress(json).unwrap();
+        decoder.decompress(&first).unwrap();
+        let mut out = Vec::new();
+        decoder.decompress_to(&second, &mut out).unwrap();
+        assert!(encoder.compress(b"not json").is_err());
+
+        let seen = recorder.0.lock().unwrap();
+        assert_eq!(seen.len(), 4, "failed calls are not reported");
+        let ops: Vec<_> = seen.iter().map(|s| (s.operation, s.schema_cache_hit)).collect();
+        assert_eq!(ops, [
+            (Operation::Compress, false),
+            (Operation::Compress, true),
+            (Operation::Decompress, false),
+            (Operation::Decompress, true),
+        ]);
+        assert_eq!((seen[0].input_bytes, seen[0].output_bytes), (json.len(), first.len()));
+        assert_eq!((seen[3].input_bytes, seen[3].output_bytes), (second.len(), out.len()));
+        assert!(seen.iter().all(|s| s.timings.total >= s.timings.encode));
+        drop(seen);
+
+        encoder.set_observer(None);
+        encoder.compress(json).unwrap();
+        assert_eq!(recorder.0.lock().unwrap().len(), 4);
+    }
 
-        if estimated_full == 0.0 {
-            return 0.0;
+    #[test]
+    fn test_decompress_projected() {
+        let json = br#"{"id": 7, "payload": {"a": [1, 2, 3], "b": "long text"}, "tags": ["x", "y"], "name": "alice", "at": "2024-01-15T10:30:00Z"}"#;
+        let mut sender = FluxSession::new();
+        let mut receiver = FluxSession::new();
+        let first = sender.compress(json).unwrap();
+        let second = sender.compress(json).unwrap();
+
+        for frame in [&first, &second] {
+            let projected = receiver.decompress_projected(frame, &["name", "id", "missing"]).unwrap();
+            let value: serde_json::Value = serde_json::from_slice(&projected).unwrap();
+            assert_eq!(value, serde_json::json!({"id": 7, "name": "alice"}));
         }
 
-        1.0 - (total as f64 / estimated_full)
+        let value: serde_json::Value = serde_json::from_slice(&decompress_projected(&first, &["at"]).unwrap()).unwrap();
+        assert_eq!(value, serde_json::json!({"at": "2024-01-15T10:30:00Z"}));
+        assert_eq!(decompress_projected(&first, &[]).unwrap(), b"{}");
     }
 
-    /// Reset session state
-    pub fn reset(&mut self) {
-        self.delta_encoder.reset();
-        self.delta_decoder.reset();
-        self.stats = StreamStats::default();
+    #[test]
+    fn test_root_array_roundtrip() {
+        let cases = [
+            serde_json::json!([1, 2, 300, -7]),
+            serde_json::json!(["a", null, "c"]),
+            serde_json::json!([0.5, 2.5]),
+            serde_json::json!([1, "a", true, null, [2]]),
+            serde_json::json!([[1, 2], [3]]),
+            serde_json::json!([]),
+            serde_json::json!([{"id": 1, "name": "alice"}, {"id": 2, "tags": ["x"]}, {"name": "carol"}]),
+            serde_json::json!([{}, {"id": 1}]),
+        ];
+        let mut sender = FluxSession::new();
+        let mut receiver = FluxSession::new();
+        for value in &cases {
+            let json = serde_json::to_vec(value).unwrap();
+            for _ in 0..2 {
+                let frame = sender.compress(&json).unwrap();
+                let decoded: serde_json::Value = serde_json::from_slice(&receiver.decompress(&frame).unwrap()).unwrap();
+                assert_eq!(&decoded, value);
+            }
+        }
+
+        // Array roots get their own schemas, distinct from an object of the same shape
+        let record = sender.compress(br#"{"id": 1, "name": "alice"}"#).unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&receiver.decompress(&record).unwrap()).unwrap();
+        assert_eq!(decoded, serde_json::json!({"id": 1, "name": "alice"}));
+
+        let frame = compress(br#"[{"id": 1, "name": "alice"}, {"id": 2, "name": "bob"}]"#).unwrap();
+        let projected: serde_json::Value = serde_json::from_slice(&decompress_projected(&frame, &["name"]).unwrap()).unwrap();
+        assert_eq!(projected, serde_json::json!([{"name": "alice"}, {"name": "bob"}]));
+
+        assert_eq!(decompress(&compress(br#"[{"id": 1}, 2]"#).unwrap()).unwrap(), br#"[{"id":1},2]"#);
     }
-}
 
-impl Default for FluxStreamSession {
-    fn default() -> Self {
-        Self::new()
+    #[test]
+    fn test_key_order_shares_schema() {
+        let mut session = FluxSession::new();
+        let mut receiver = FluxSession::new();
+        let first = session.compress(br#"{"id": 1, "name": "alice", "items": [{"b": 1, "a": 2}, {"c": 3, "a": 1}]}"#).unwrap();
+        let frame = session.compress(br#"{"items": [{"a": 2, "c": 1}, {"a": 1, "b": 3}], "name": "bob", "id": 2}"#).unwrap();
+        assert_eq!(session.stats().cache_hits, 1);
+        assert_eq!(session.schema_cache().len(), 1);
+
+        receiver.decompress(&first).unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&receiver.decompress(&frame).unwrap()).unwrap();
+        assert_eq!(decoded["name"], "bob");
+        assert_eq!(decoded["items"][1]["b"], 3);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_preserve_key_order() {
+        let json = br#"{"name":"alice","id":1,"meta":{"z":true,"a":[{"y":1,"x":2}]}}"#;
+        let canonical = br#"{"id":1,"meta":{"a":[{"x":2,"y":1}],"z":true},"name":"alice"}"#;
+
+        assert_eq!(decompress(&compress(json).unwrap()).unwrap(), canonical);
+
+        let config = FluxConfig::builder().preserve_key_order(true).build().unwrap();
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::new();
+        for _ in 0..2 {
+            let frame = sender.compress(json).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), json);
+        }
+
+        // Sorted input needs no record
+        let plain = compress(canonical).unwrap();
+        assert_eq!(FluxSession::with_config(config).compress(canonical).unwrap(), plain);
+    }
 
     #[test]
-    fn test_compress_decompress_simple() {
-        // For now, just test compression works and produces output
-        // Full roundtrip requires complete decoder implementation
-        let json = br#"{"id": 123, "name": "test"}"#;
-        let compressed = compress(json).unwrap();
+    fn test_content_hash() {
+        let json = br#"{"name":"alice","id":18446744073709551615,"score":1.0,"meta":{"z":true,"a":[]}}"#;
+        assert!(FluxConfig::builder().content_hash(true).build().is_err());
+
+        let config = FluxConfig::builder().lossless_numbers(true).content_hash(true).preserve_key_order(true).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        let mut receiver = FluxSession::new();
+        for _ in 0..2 {
+            let frame = sender.compress(json).unwrap();
+            let metadata = frame::read_metadata(&frame).unwrap();
+            let stored = metadata.get(content_hash::CONTENT_HASH_KEY).unwrap();
+            assert_eq!(stored, content_hash(&serde_json::from_slice(json).unwrap()).to_le_bytes());
+            assert_eq!(receiver.decompress(&frame).unwrap(), json);
+        }
 
-        // Verify magic bytes
-        assert_eq!(&compressed[0..4], b"FLUX");
+        // A hash that doesn't match the decoded message fails the decode
+        let mut opts = MessageOptions::default();
+        opts.metadata.insert(content_hash::CONTENT_HASH_KEY, 0u64.to_le_bytes().to_vec());
+        let frame = FluxSession::new().compress_with(json, &opts).unwrap();
+        let err = FluxSession::new().decompress(&frame).unwrap_err();
+        assert!(matches!(err.root(), Error::ContentHashMismatch { expected: 0, .. }));
+    }
 
-        // Verify we got some output
-        assert!(!compressed.is_empty());
+    #[test]
+    fn test_value_dictionary() {
+        let messages: Vec<Vec<u8>> = (10..30)
+            .map(|i| {
+                let status = ["ACTIVE", "SUSPENDED", "PENDING_REVIEW"][i % 3];
+                format!(r#"{{"country":"NZ","id":{},"status":"{}"}}"#, i, status).into_bytes()
+            })
+            .collect();
+
+        let base = FluxConfig::builder().entropy(false).build().unwrap();
+        let config = FluxConfig { value_dictionary: true, ..base.clone() };
+        let mut plain = FluxSession::with_config(base);
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::new();
+        let mut frames = Vec::new();
+        for json in &messages {
+            let frame = sender.compress(json).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), *json);
+            frames.push(frame);
+        }
+        // Values are spelled out at most twice, then referenced
+        assert!(sender.stats().dictionary_bytes > 0);
+        let plain_size: usize = messages.iter().map(|json| plain.compress(json).unwrap().len()).sum();
+        assert!(frames.iter().map(Vec::len).sum::<usize>() < plain_size);
+        assert!(frames[19].len() < plain.compress(&messages[19]).unwrap().len());
+
+        // A receiver that missed the update can't decode later references
+        let mut late = FluxSession::new();
+        late.decompress(&frames[0]).unwrap();
+        assert!(matches!(late.decompress(&frames[5]).unwrap_err().root(), Error::InvalidFrame(_)));
+
+        // The small-frame path carries updates too
+        let realtime = FluxConfig { profile: Profile::Realtime, ..config };
+        let mut sender = FluxSession::with_config(realtime);
+        let mut receiver = FluxSession::new();
+        for json in &messages {
+            let frame = sender.compress(json).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), *json);
+        }
+        assert_eq!(sender.stats().small_frames, messages.len() as u64);
     }
 
     #[test]
-    fn test_session_schema_caching() {
+    fn test_schema_eviction() {
+        let messages: Vec<Vec<u8>> = ["a", "b", "c", "a", "c", "b"]
+            .iter()
+            .map(|key| format!(r#"{{"{}":1}}"#, key).into_bytes())
+            .collect();
+
+        // Peers with the same limit evict in step
+        let config = FluxConfig::builder().max_cached_schemas(2).build().unwrap();
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::with_config(config.clone());
+        for json in &messages {
+            let frame = sender.compress(json).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), *json);
+        }
+        assert_eq!(sender.stats().schemas_cached, 2);
+        assert!(sender.stats().schema_evictions > 0);
+        assert_eq!(receiver.stats().schema_evictions, sender.stats().schema_evictions);
+
+        // A receiver with a smaller cache asks for what it dropped
+        let mut sender = FluxSession::new();
+        let mut receiver = FluxSession::with_config(FluxConfig { max_cached_schemas: 1, ..config });
+        for json in &messages {
+            let frame = sender.compress(json).unwrap();
+            let json_out = match receiver.decompress(&frame) {
+                Err(Error::SchemaNotFound(id)) => {
+                    let request = receiver.schema_request(&[id]);
+                    let reply = sender.answer_schema_request(&request).unwrap();
+                    assert_eq!(receiver.load_schemas(&reply).unwrap(), 1);
+                    receiver.decompress(&frame).unwrap()
+                }
+                result => result.unwrap(),
+            };
+            assert_eq!(json_out, *json);
+        }
+        assert!(sender.answer_schema_request(&receiver.schema_request(&[99])).is_err());
+        assert!(receiver.load_schemas(&sender.compress(b"{}").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let messages: Vec<Vec<u8>> = (0..40)
+            .map(|i| format!(r#"{{"field{}":{},"name":"x"}}"#, i % 20, i).into_bytes())
+            .collect();
+        let mut unbounded = FluxSession::new();
+        for json in &messages {
+            unbounded.compress(json).unwrap();
+        }
+        let usage = unbounded.memory_usage();
+        assert!(usage.schemas > 0 && usage.scratch > 0);
+        assert_eq!(usage.dictionaries + usage.history, 0);
+        assert_eq!(usage.total(), usage.schemas + usage.scratch);
+
+        // Over budget, scratch goes first and then schemas
+        let budget = usage.schemas / 2;
+        let config = FluxConfig::builder().memory_budget(budget).build().unwrap();
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::with_config(config);
+        for json in &messages {
+            let frame = sender.compress(json).unwrap();
+            assert!(sender.memory_usage().total() <= budget);
+            assert_eq!(receiver.decompress(&frame).unwrap(), *json);
+            assert!(receiver.memory_usage().total() <= budget);
+        }
+        assert!(sender.stats().schema_evictions > 0);
+        assert_eq!(sender.memory_usage().scratch, 0);
+
+        // The budget must leave room for the dictionaries and histories
+        let err = FluxConfig::builder().lz_history(4096).memory_budget(8192).build().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig { field: "memory_budget", .. }));
+        assert!(FluxConfig::builder().lz_history(4096).memory_budget(65536).build().is_ok());
+    }
+
+    #[test]
+    fn test_schema_deltas() {
+        let messages: [&[u8]; 3] = [
+            br#"{"active":true,"country":"SE","id":1,"name":"alice","score":2.5}"#,
+            br#"{"active":false,"country":"NO","email":"b@x.io","id":2,"name":"bob","score":1.5}"#,
+            br#"{"active":true,"country":"DK","email":"c@x.io","id":3,"name":"carol"}"#,
+        ];
+        let config = FluxConfig::builder().schema_deltas(true).build().unwrap();
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::with_config(config);
+        let mut plain = FluxSession::new();
+        for json in messages {
+            let frame = sender.compress(json).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), json);
+            let full = plain.compress(json).unwrap();
+            assert!(frame.len() <= full.len());
+        }
+        // The first schema goes in full, the two evolutions as deltas
+        assert_eq!(sender.stats().schema_deltas, 2);
+        assert!(sender.stats().schema_bytes < plain.stats().schema_bytes);
+        let latest = receiver.schema_cache().get(sender.last_schema.unwrap()).unwrap();
+        assert_eq!(latest.version, 3);
+
+        // A receiver without the base schema can't apply the delta
+        let mut sender = FluxSession::with_config(FluxConfig::builder().schema_deltas(true).build().unwrap());
+        sender.compress(messages[0]).unwrap();
+        let frame = sender.compress(messages[1]).unwrap();
+        assert!(matches!(FluxSession::new().decompress(&frame), Err(Error::SchemaNotFound(_))));
+    }
+
+    #[test]
+    fn test_disable_delta() {
+        let messages: [&[u8]; 2] = [
+            br#"{"id":1,"name":"alice","title":"Quarterly report for the northern region"}"#,
+            br#"{"email":"b@x.io","id":2,"name":"bob","title":"Quarterly report for the northern region"}"#,
+        ];
+        let config = FluxConfig::builder().schema_deltas(true).lz_history(4096).build().unwrap();
+        let mut sender = FluxSession::with_config(config.clone());
+        let mut receiver = FluxSession::with_config(config);
+        assert_eq!(receiver.decompress(&sender.compress(messages[0]).unwrap()).unwrap(), messages[0]);
+
+        let opts = MessageOptions { disable_delta: true, ..Default::default() };
+        let frame = sender.compress_with(messages[1], &opts).unwrap();
+        assert_eq!(sender.stats().schema_deltas, 0);
+        assert_eq!(sender.stats().lz_history_matches, 0);
+        // Decodable without the first message, and by the peer that has it
+        let fresh = FluxSession::with_config(sender.config.clone()).decompress(&frame).unwrap();
+        assert_eq!(fresh, messages[1]);
+        assert_eq!(receiver.decompress(&frame).unwrap(), messages[1]);
+
+        // Without the override the same message refers back
+        let mut sender = FluxSession::with_config(sender.config.clone());
+        sender.compress(messages[0]).unwrap();
+        let frame = sender.compress(messages[1]).unwrap();
+        assert_eq!(sender.stats().schema_deltas, 1);
+        assert!(sender.stats().lz_history_matches > 0);
+        assert!(FluxSession::new().decompress(&frame).is_err());
+    }
+
+    #[test]
+    fn test_compress_into() {
+        let messages: Vec<Vec<u8>> = (10..30)
+            .map(|i| format!(r#"{{"id":{},"items":[{},{},{}],"name":"user{}"}}"#, i, i, i * 2, i * 3, i).into_bytes())
+            .collect();
         let mut session = FluxSession::new();
+        let mut reference = FluxSession::new();
+        let mut out = Vec::new();
+        let mut capacity = 0;
+        for (i, json) in messages.iter().enumerate() {
+            out.clear();
+            session.compress_into(json, &mut out).unwrap();
+            assert_eq!(out, reference.compress(json).unwrap());
+            // Buffers stop growing once they fit the messages
+            if i == 2 {
+                capacity = session.scratch().capacity();
+            }
+        }
+        assert!(capacity > 0);
+        assert_eq!(session.scratch().capacity(), capacity);
+
+        // One oversized message doesn't pin its buffers
+        let big = format!(r#"{{"blob":"{}"}}"#, "xy".repeat(scratch::MAX_RETAINED_SCRATCH));
+        session.compress_into(big.as_bytes(), &mut Vec::new()).unwrap();
+        assert!(session.scratch().capacity() < 2 * scratch::MAX_RETAINED_SCRATCH);
+        session.release_scratch();
+        assert_eq!(session.scratch().capacity(), 0);
+    }
 
-        // First message - schema included
-        let c1 = session.compress(br#"{"id": 1, "name": "alice"}"#).unwrap();
+    #[test]
+    fn test_decompress_all() {
+        let messages: [&[u8]; 3] = [br#"{"id":1}"#, br#"{"id":2}"#, br#"{"name":"x"}"#];
+        let config = FluxConfig::builder().checksum(true).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        let mut log = Vec::new();
+        for json in messages {
+            sender.compress_to(json, &mut log).unwrap();
+        }
 
-        // Second message - schema cached
-        let c2 = session.compress(br#"{"id": 2, "name": "bob"}"#).unwrap();
+        // Later frames rely on schemas sent by earlier ones
+        let decoded = decompress_all(&log).unwrap();
+        assert_eq!(decoded, messages.map(<[u8]>::to_vec));
+        assert!(decompress_all(&[]).unwrap().is_empty());
 
-        // Second should be smaller (no schema) - but may not always be true
-        // depending on field order serialization
-        // For now just verify both compress successfully
-        assert!(!c1.is_empty());
-        assert!(!c2.is_empty());
+        assert!(decompress_all(&log[..log.len() - 1]).is_err());
+        let last = log.len() - 5;
+        log[last] ^= 0x01;
+        assert!(matches!(decompress_all(&log), Err(Error::ChecksumMismatch)));
+    }
 
-        // Stats should show cache hit
-        assert_eq!(session.stats().cache_hits, 1);
-        assert_eq!(session.stats().cache_misses, 1);
+    #[test]
+    fn test_error_stages() {
+        let config = FluxConfig::builder().checksum(true).build().unwrap();
+        let mut frame = FluxSession::with_config(config).compress(br#"{"id":1,"name":"alice"}"#).unwrap();
+        let last = frame.len() - 5;
+        frame[last] ^= 0x01;
+        let err = decompress(&frame).unwrap_err();
+        assert_eq!(err.stage(), Some(Stage::Frame));
+        assert!(matches!(err.root(), Error::ChecksumMismatch));
+
+        // Schema errors point at the start of the schema section
+        let config = FluxConfig::builder().checksum(false).build().unwrap();
+        let frame = FluxSession::with_config(config).compress(br#"{"id":1,"name":"alice"}"#).unwrap();
+        let raw = RawFrame::parse(&frame).unwrap();
+        let schema = raw.schema.unwrap();
+        let offset = schema.as_ptr() as usize - frame.as_ptr() as usize;
+        let mut bad = frame.clone();
+        bad[offset + schema::SCHEMA_HEADER_SIZE] = 0xFF; // field count
+        let err = decompress(&bad).unwrap_err();
+        assert_eq!((err.stage(), err.offset()), (Some(Stage::Schema), Some(offset)));
+
+        // A delta that doesn't fit the receiver's state
+        let mut stream = FluxStreamSession::new();
+        stream.receive(&serialize_delta(&DeltaOp::Add(serde_json::json!([1, 2]))).unwrap()).unwrap();
+        let ops = DeltaOp::ObjectOps(vec![ObjectOp::Remove("a".into())]);
+        let err = stream.receive(&serialize_delta(&ops).unwrap()).unwrap_err();
+        assert_eq!(err.stage(), Some(Stage::Delta));
+        assert!(matches!(err.root(), Error::DeltaStateMismatch { expected, got } if expected == "object" && got == "array"));
+        assert_eq!(err.to_string(), "delta stage failed: Delta state mismatch: expected object, got array");
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut sender = FluxSession::new();
+        let mut stream = Vec::new();
+        for json in [br#"{"id":1,"name":"a"}"#, br#"{"id":2,"name":"b"}"#] {
+            sender.compress_to(json, &mut stream).unwrap();
+        }
+        let first_len = RawFrame::parse_prefix(&stream).unwrap().1;
+
+        // Every prefix of a frame asks for more, without overshooting
+        for cut in 0..first_len {
+            let more = needs_more_bytes(&stream[..cut]).unwrap();
+            assert!(more > 0 && cut + more <= first_len, "cut at {}", cut);
+        }
+        assert_eq!(needs_more_bytes(&stream[..first_len]), None);
+        assert_eq!(needs_more_bytes(b"JSON{}"), None);
+
+        let mut reader = stream.as_slice();
+        let mut receiver = FluxSession::new();
+        let frame = FrameReader::read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(frame, stream[..first_len]);
+        assert_eq!(receiver.decompress(&frame).unwrap(), br#"{"id":1,"name":"a"}"#);
+        let frame = FrameReader::read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(receiver.decompress(&frame).unwrap(), br#"{"id":2,"name":"b"}"#);
+        assert!(FrameReader::read_frame(&mut reader).unwrap().is_none());
+
+        let mut truncated = &stream[..first_len - 1];
+        assert!(matches!(FrameReader::read_frame(&mut truncated), Err(Error::Io(_))));
+        assert!(matches!(
+            FrameReader::read_frame_with_limit(&mut stream.as_slice(), 16),
+            Err(Error::LengthLimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_export_import_schema() {
+        let mut sender = FluxSession::new();
+        let first = sender.compress(br#"{"id":1,"name":"a"}"#).unwrap();
+        let second = sender.compress(br#"{"id":2,"name":"b"}"#).unwrap();
+
+        // The receiver missed the frame carrying the schema
+        let mut receiver = FluxSession::new();
+        let id = match receiver.decompress(&second) {
+            Err(Error::SchemaNotFound(id)) => id,
+            other => panic!("expected SchemaNotFound, got {:?}", other.map(String::from_utf8)),
+        };
+        let message = sender.export_schema(id).unwrap();
+        assert_eq!(receiver.import_schema(&message).unwrap(), id);
+        assert_eq!(receiver.decompress(&second).unwrap(), br#"{"id":2,"name":"b"}"#);
+        assert_eq!(receiver.decompress(&first).unwrap(), br#"{"id":1,"name":"a"}"#);
+
+        assert!(matches!(sender.export_schema(42), Err(Error::SchemaNotFound(42))));
+        assert!(receiver.import_schema(&first).is_err());
+    }
+
+    #[test]
+    fn test_compress_with_schema() {
+        use types::IntegerType;
+
+        let schema = Schema::builder()
+            .field("id", FieldType::Integer(IntegerType::Varint))
+            .field("name", FieldType::String)
+            .nullable_field("tags", FieldType::Array(Box::new(FieldType::String)))
+            .nullable_field("price", FieldType::Decimal { precision: 10, scale: 2 })
+            .build()
+            .unwrap();
+        let mut sender = FluxSession::new();
+        let mut receiver = FluxSession::new();
+        let messages: [&[u8]; 3] = [
+            br#"{"id":1,"name":"a","tags":["x"]}"#,
+            br#"{"id":300000,"name":"b"}"#,
+            br#"{"id":-5,"name":"c","price":"12.50","tags":[]}"#,
+        ];
+        for json in messages {
+            let frame = sender.compress_with_schema(json, &schema).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), json);
+        }
+        // Sent once, then referenced
+        assert_eq!(sender.stats().cache_misses, 1);
+        assert_eq!(sender.stats().cache_hits, 2);
+
+        for bad in [
+            &br#"{"name":"a"}"#[..],
+            br#"{"id":1,"name":"a","extra":true}"#,
+            br#"{"id":"1","name":"a"}"#,
+            br#"{"id":1,"name":"a","tags":[{"k":1}]}"#,
+        ] {
+            let err = sender.compress_with_schema(bad, &schema).unwrap_err();
+            assert!(matches!(err, Error::EncodeError(_)), "{}", String::from_utf8_lossy(bad));
+        }
+    }
+
+    #[test]
+    fn test_schema_uuid_text() {
+        let schema = Schema::builder()
+            .field("id", FieldType::Uuid)
+            .nullable_field("refs", FieldType::Array(Box::new(FieldType::Uuid)))
+            .build()
+            .unwrap();
+        let mut sender = FluxSession::new();
+        let mut receiver = FluxSession::new();
+        let canonical = br#"{"id":"550e8400-e29b-41d4-a716-446655440000"}"#;
+        let messages: [&[u8]; 3] = [
+            canonical,
+            br#"{"id":"550E8400-E29B-41D4-A716-446655440000"}"#,
+            br#"{"id":"550e8400-e29b-41d4-a716-446655440000","refs":["{550e8400-e29b-41d4-a716-446655440000}"]}"#,
+        ];
+        for json in messages {
+            let frame = sender.compress_with_schema(json, &schema).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), json);
+        }
+
+        // Only messages that need it take the widened schema
+        let frame = sender.compress_with_schema(canonical, &schema).unwrap();
+        let sent = receiver.schema_cache().get(RawFrame::parse(&frame).unwrap().header.schema_id).unwrap();
+        assert_eq!(sent.hash, schema.hash);
+        assert_eq!(sender.stats().cache_misses, 2);
+    }
+
+    #[test]
+    fn test_lossless_numbers() {
+        let corpus: &[&[u8]] = &[
+            br#"{"n":[9223372036854775807,-9223372036854775808,0]}"#,
+            br#"{"n":[18446744073709551615,9223372036854775808,1]}"#,
+            br#"{"n":[1e300,-0.0,5e-324,0.1,1.7976931348623157e308]}"#,
+            br#"{"n":[1,2.5,-3,18446744073709551615,-1]}"#,
+            br#"{"a":18446744073709551615,"b":-0.0,"c":1.0}"#,
+            br#"[1,2.5,18446744073709551615,-7]"#,
+        ];
+        let config = FluxConfig::builder().lossless_numbers(true).build().unwrap();
+        let mut sender = FluxSession::with_config(config);
+        let mut receiver = FluxSession::new();
+        for json in corpus {
+            // serde_json's own rendering (`1e+300`), which keeps `-0.0` and exponents exact
+            let expected = serde_json::to_vec(&serde_json::from_slice::<serde_json::Value>(json).unwrap()).unwrap();
+            let frame = sender.compress(json).unwrap();
+            assert_eq!(receiver.decompress(&frame).unwrap(), expected, "{}", String::from_utf8_lossy(json));
+        }
+
+        // Unsigned values never degrade to floats, even by default
+        let json = br#"{"id":18446744073709551615}"#;
+        assert_eq!(decompress(&compress(json).unwrap()).unwrap(), json);
+
+        // By default mixed integers and floats widen to floats
+        let widened: serde_json::Value = serde_json::from_slice(&decompress(&compress(br#"{"n":[1,2.5]}"#).unwrap()).unwrap()).unwrap();
+        assert_eq!(widened, serde_json::json!({"n": [1.0, 2.5]}));
+    }
+
+    #[test]
+    fn test_base64_fields_roundtrip() {
+        use base64::Engine;
+
+        let bytes: Vec<u8> = (0..600u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let blob = base64::engine::general_purpose::STANDARD.encode(&bytes);
+        let json = serde_json::to_vec(&serde_json::json!({"blob": blob, "id": 1, "note": "plain text"})).unwrap();
+
+        let mut session = FluxSession::new();
+        let frame = session.compress(&json).unwrap();
+        assert!(decompress(&frame).unwrap() == json);
+        // Stored as raw bytes: a quarter smaller than the text
+        assert!(session.stats().encoded_bytes < bytes.len() as u64 + 32);
+    }
+
+    #[test]
+    fn test_truncated_frames_error() {
+        let mut opts = MessageOptions::default();
+        opts.metadata.insert("trace-id", "4bf92f35");
+        let json = br#"{"id": 1, "name": "alice", "tags": ["a", "b"]}"#;
+        let frame = FluxSession::new().compress_with(json, &opts).unwrap();
+
+        // Every prefix is rejected; none panics
+        for cut in 0..frame.len() {
+            assert!(FluxSession::new().decompress(&frame[..cut]).is_err(), "cut at {}", cut);
+        }
     }
 
     #[test]
@@ -502,6 +3484,366 @@ mod tests {
         assert_eq!(sender.stats().delta_sends, 3);
     }
 
+    #[test]
+    fn test_stream_keyframe_interval() {
+        let config = StreamConfig { keyframe_interval: 3, ..Default::default() };
+        let mut sender = FluxStreamSession::with_config(config);
+        let mut receiver = FluxStreamSession::new();
+
+        for i in 0..7 {
+            let state = format!(r#"{{"count": {}}}"#, i);
+            let msg = sender.update(state.as_bytes()).unwrap();
+
+            // Message 2 is lost; receiver is back in sync at the keyframe (message 3)
+            if i == 2 {
+                continue;
+            }
+            let received = receiver.receive(&msg).unwrap();
+            let decoded: serde_json::Value = serde_json::from_slice(&received).unwrap();
+            assert_eq!(decoded["count"], i);
+        }
+
+        // Keyframes at updates 0, 3 and 6
+        assert_eq!(sender.stats().full_sends, 3);
+        assert_eq!(sender.stats().delta_sends, 4);
+    }
+
+    #[test]
+    fn test_stream_force_full_next() {
+        for patch_format in [PatchFormat::Delta, PatchFormat::MergePatch] {
+            let config = StreamConfig { patch_format, ..Default::default() };
+            let mut sender = FluxStreamSession::with_config(config.clone());
+            let mut receiver = FluxStreamSession::with_config(config);
+            receiver.receive(&sender.update(br#"{"count":0,"tags":["a"]}"#).unwrap()).unwrap();
+            sender.update(br#"{"count":1,"tags":["a","b"]}"#).unwrap(); // lost
+
+            // The receiver rejects a delta against the state it missed, then resyncs
+            let delta = sender.update(br#"{"count":2,"tags":["b"]}"#).unwrap();
+            if patch_format == PatchFormat::Delta {
+                let err = receiver.receive(&delta).unwrap_err();
+                assert!(matches!(err.root(), Error::DeltaStateMismatch { .. }));
+            }
+            sender.force_full_next();
+            let full = sender.update(br#"{"count":3,"tags":["b"]}"#).unwrap();
+            assert_eq!(receiver.receive(&full).unwrap(), br#"{"count":3,"tags":["b"]}"#);
+            assert_eq!(sender.stats().full_sends, 2);
+
+            // Only the next update
+            sender.update(br#"{"count":4,"tags":["b"]}"#).unwrap();
+            assert_eq!(sender.stats().full_sends, 2);
+        }
+    }
+
+    #[test]
+    fn test_stream_full_duplex() {
+        let (mut a, mut b) = (FluxStreamSession::new(), FluxStreamSession::new());
+        for i in 0..5 {
+            let to_b = a.update(format!(r#"{{"from":"a","seq":{}}}"#, i).as_bytes()).unwrap();
+            let to_a = b.update(format!(r#"{{"from":"b","seq":{},"x":[1]}}"#, i).as_bytes()).unwrap();
+            assert_eq!(b.receive(&to_b).unwrap(), format!(r#"{{"from":"a","seq":{}}}"#, i).into_bytes());
+            assert_eq!(a.receive(&to_a).unwrap(), format!(r#"{{"from":"b","seq":{},"x":[1]}}"#, i).into_bytes());
+        }
+        assert_eq!((a.stats().updates_sent, a.receiver_stats().updates_received), (5, 5));
+        assert_eq!(a.stats().full_sends, 1);
+
+        // Resetting one direction leaves the other in sync
+        b.receiver_mut().reset();
+        a.sender_mut().force_full_next();
+        let to_b = a.update(br#"{"from":"a","seq":5}"#).unwrap();
+        assert_eq!(b.receive(&to_b).unwrap(), br#"{"from":"a","seq":5}"#);
+        let to_a = b.update(br#"{"from":"b","seq":5,"x":[1]}"#).unwrap();
+        assert_eq!(b.stats().full_sends, 1);
+        a.receive(&to_a).unwrap();
+        assert_eq!(b.receiver_stats().updates_received, 1);
+
+        // The halves work apart, e.g. on separate tasks
+        let (mut tx, _) = a.split();
+        let (_, mut rx) = b.split();
+        let update = tx.update(br#"{"from":"a","seq":6}"#).unwrap();
+        assert_eq!(rx.receive(&update).unwrap(), br#"{"from":"a","seq":6}"#);
+        assert!(rx.receive(b"\xff").is_err());
+        assert_eq!(rx.stats().rejected, 1);
+    }
+
+    #[test]
+    fn test_stream_channels() {
+        for patch_format in [PatchFormat::Delta, PatchFormat::MergePatch] {
+            let config = StreamConfig { patch_format, ..Default::default() };
+            let (mut sender, mut receiver) = (FluxStreamSession::with_config(config.clone()), FluxStreamSession::with_config(config));
+            let cursor = |i: u32| format!(r#"{{"x":{},"y":2}}"#, i);
+            let doc = |i: u32| format!(r#"{{"rev":{},"text":"hello world, hello channels"}}"#, i);
+            for i in 0..4 {
+                let update = sender.update_channel(7, cursor(i).as_bytes()).unwrap();
+                assert_eq!(receiver.receive_channel(&update).unwrap(), (7, cursor(i).into_bytes()));
+                let update = sender.update(doc(i).as_bytes()).unwrap();
+                assert_eq!(receiver.receive_channel(&update).unwrap(), (DEFAULT_CHANNEL, doc(i).into_bytes()));
+            }
+            // Interleaving doesn't break either baseline
+            assert_eq!(sender.stats().full_sends, 2);
+
+            // A resync on one channel leaves the other on deltas
+            receiver.receiver_mut().remove_channel(7);
+            sender.sender_mut().force_full_next_channel(7);
+            let update = sender.update_channel(7, cursor(4).as_bytes()).unwrap();
+            assert_eq!(receiver.receive_channel(&update).unwrap().1, cursor(4).as_bytes());
+            sender.update(doc(4).as_bytes()).unwrap();
+            assert_eq!(sender.stats().full_sends, 3);
+        }
+
+        // Truncated channel prefix
+        assert!(FluxStreamSession::new().receive_channel(b"\xc7\xff").is_err());
+    }
+
+    #[test]
+    fn test_stream_subscribe() {
+        let (mut sender, mut receiver) = (FluxStreamSession::new(), FluxStreamSession::new());
+        let state = |i: u32| format!(r#"{{"config":{{"a/b":{},"theme":"dark"}},"users":["alice"]}}"#, i);
+        receiver.receive(&sender.update(state(0).as_bytes()).unwrap()).unwrap();
+
+        sender.subscribe("/config/a~1b").unwrap();
+        let update = sender.update(state(1).as_bytes()).unwrap();
+        assert_eq!(receiver.receive(&update).unwrap(), b"1");
+        receiver.receive(&sender.update(state(2).as_bytes()).unwrap()).unwrap();
+        assert_eq!(sender.stats().full_sends, 2);
+
+        // Missing subtrees read as null; "" restores whole states
+        sender.subscribe("/missing").unwrap();
+        assert_eq!(receiver.receive(&sender.update(state(3).as_bytes()).unwrap()).unwrap(), b"null");
+        sender.subscribe("").unwrap();
+        assert_eq!(receiver.receive(&sender.update(state(4).as_bytes()).unwrap()).unwrap(), state(4).as_bytes());
+
+        for invalid in ["users", "/a~2", "/a~"] {
+            assert!(matches!(sender.subscribe(invalid), Err(Error::ParseError(_))), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_stream_acked_baseline() {
+        let config = StreamConfig { acked: true, ..Default::default() };
+        let (mut sender, mut receiver) = (FluxStreamSession::with_config(config.clone()), FluxStreamSession::with_config(config));
+        let state = |i: u32| format!(r#"{{"count":{},"name":"lossy"}}"#, i);
+
+        let first = sender.update(state(0).as_bytes()).unwrap();
+        assert_eq!(receiver.receive(&first).unwrap(), state(0).as_bytes());
+        assert!(sender.on_ack(receiver.receiver().last_seq().unwrap()));
+
+        // Updates 2 and 3 are lost; each is a delta against update 1
+        sender.update(state(1).as_bytes()).unwrap();
+        sender.update(state(2).as_bytes()).unwrap();
+        let retransmit = sender.retransmit().unwrap().unwrap();
+        assert_eq!(receiver.receive(&retransmit).unwrap(), state(2).as_bytes());
+        assert_eq!(receiver.receiver().last_seq(), Some(4));
+        assert_eq!(sender.stats().full_sends, 1);
+
+        // Acks arriving late or out of order
+        let update = sender.update(state(3).as_bytes()).unwrap();
+        assert!(sender.on_ack(4));
+        assert!(!sender.on_ack(2));
+        assert_eq!(receiver.receive(&update).unwrap(), state(3).as_bytes());
+        let update = sender.update(state(4).as_bytes()).unwrap();
+        assert_eq!(receiver.receive(&update).unwrap(), state(4).as_bytes());
+        assert!(sender.on_ack(6));
+        assert_eq!(sender.retransmit().unwrap(), None);
+
+        // A receiver that lost its baseline says so
+        let mut fresh = FluxStreamSession::with_config(StreamConfig { acked: true, ..Default::default() });
+        let update = sender.update(state(5).as_bytes()).unwrap();
+        let err = fresh.receive(&update).unwrap_err();
+        assert!(matches!(err.root(), Error::DeltaStateMismatch { expected, .. } if expected == "state 6"));
+
+        let config = StreamConfig { acked: true, patch_format: PatchFormat::MergePatch, ..Default::default() };
+        assert!(matches!(FluxStreamSession::with_config(config).update(b"{}"), Err(Error::InvalidConfig { field: "acked", .. })));
+    }
+
+    #[test]
+    fn test_stream_memory_usage() {
+        let config = StreamConfig { acked: true, ..Default::default() };
+        let (mut sender, mut receiver) = (FluxStreamSession::with_config(config.clone()), FluxStreamSession::with_config(config));
+        assert_eq!(sender.memory_usage().total(), 0);
+        let state = |i: usize| format!(r#"{{"count":{},"log":"{}"}}"#, i, "x".repeat(1000));
+
+        // Unacknowledged states pile up to MAX_UNACKED_STATES
+        for i in 0..MAX_UNACKED_STATES + 10 {
+            receiver.receive(&sender.update(state(i).as_bytes()).unwrap()).unwrap();
+        }
+        let usage = sender.sender().memory_usage();
+        assert!(usage.baselines >= 1000 && usage.baselines < 2 * 1500, "{:?}", usage);
+        assert!(usage.retained >= MAX_UNACKED_STATES * 1000 && usage.retained < MAX_UNACKED_STATES * 1500, "{:?}", usage);
+        let received = receiver.receiver().memory_usage();
+        assert!(received.retained >= MAX_UNACKED_STATES * 1000, "{:?}", received);
+
+        assert!(sender.on_ack(receiver.receiver().last_seq().unwrap()));
+        let acked = sender.sender().memory_usage();
+        assert_eq!(acked.retained, 0);
+        assert!(acked.total() < usage.total());
+        assert_eq!(sender.memory_usage().total(), acked.total() + sender.receiver().memory_usage().total());
+    }
+
+    #[test]
+    fn test_stream_string_diff() {
+        let state = |word: &str| format!(r#"{{"description":"{} {}"}}"#, "lorem ipsum ".repeat(20), word);
+        let sizes: Vec<usize> = [0, 64]
+            .into_iter()
+            .map(|string_diff| {
+                let config = StreamConfig { string_diff, ..Default::default() };
+                let (mut sender, mut receiver) = (FluxStreamSession::with_config(config.clone()), FluxStreamSession::with_config(config));
+                receiver.receive(&sender.update(state("dolor").as_bytes()).unwrap()).unwrap();
+                let update = sender.update(state("dolar").as_bytes()).unwrap();
+                assert_eq!(receiver.receive(&update).unwrap(), state("dolar").as_bytes());
+                update.len()
+            })
+            .collect();
+        assert!(sizes[1] * 10 < sizes[0], "{:?}", sizes);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let nested = |depth: usize| format!("{}1{}", "[".repeat(depth), "]".repeat(depth));
+        let mut session = FluxSession::new();
+        let frame = session.compress(nested(config::DEFAULT_MAX_DEPTH).as_bytes()).unwrap();
+        assert_eq!(FluxSession::new().decompress(&frame).unwrap(), nested(config::DEFAULT_MAX_DEPTH).as_bytes());
+        let err = session.compress(nested(config::DEFAULT_MAX_DEPTH + 1).as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::DepthLimitExceeded { max: config::DEFAULT_MAX_DEPTH }));
+
+        let config = FluxConfig::builder().max_depth(2).build().unwrap();
+        let mut session = FluxSession::with_config(config.clone());
+        session.compress(br#"{"a":[1],"b":{}}"#).unwrap();
+        assert!(matches!(session.compress(br#"{"a":{"b":[1]}}"#), Err(Error::DepthLimitExceeded { max: 2 })));
+
+        // Receivers refuse schemas nested deeper than their own limit
+        let frame = FluxSession::new().compress(br#"{"a":{"b":[1]}}"#).unwrap();
+        let err = FluxSession::with_config(config).decompress(&frame).unwrap_err();
+        assert_eq!(err.stage(), Some(Stage::Schema));
+        assert!(matches!(err.root(), Error::DepthLimitExceeded { max: 2 }));
+
+        // Deep documents opt in on both sides; debug builds need a deep stack
+        let deep = nested(1000);
+        let config = FluxConfig::builder().max_depth(1000).build().unwrap();
+        let (frame, decoded) = std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || {
+                let frame = FluxSession::with_config(config.clone()).compress(deep.as_bytes()).unwrap();
+                (frame.clone(), FluxSession::with_config(config).decompress(&frame).unwrap())
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(decoded, nested(1000).as_bytes());
+        let err = FluxSession::new().decompress(&frame).unwrap_err();
+        assert!(matches!(err.root(), Error::DepthLimitExceeded { max: config::DEFAULT_MAX_DEPTH }));
+
+        for depth in [0, config::MAX_DEPTH + 1] {
+            assert!(FluxConfig::builder().max_depth(depth).build().is_err());
+        }
+    }
+
+    #[test]
+    fn test_stream_keyframe_period() {
+        let config = StreamConfig { keyframe_period: Duration::from_secs(10), ..Default::default() };
+        let clock = Arc::new(ManualClock::new());
+        let mut sender = FluxStreamSession::with_config(config.clone());
+        sender.set_clock(clock.clone());
+
+        let mut full = Vec::new();
+        for i in 0..6 {
+            let before = sender.stats().full_sends;
+            sender.update(format!(r#"{{"count": {}}}"#, i).as_bytes()).unwrap();
+            full.push(sender.stats().full_sends > before);
+            clock.advance(Duration::from_secs(4));
+        }
+        // Snapshots at t=0 and at t=12, the first update 10s after it
+        assert_eq!(full, [true, false, false, true, false, false]);
+
+        // Without a clock the period never elapses
+        let mut sender = FluxStreamSession::with_config(config);
+        sender.set_clock(Arc::new(NoClock));
+        for i in 0..3 {
+            sender.update(format!(r#"{{"count": {}}}"#, i).as_bytes()).unwrap();
+        }
+        assert_eq!(sender.stats().full_sends, 1);
+    }
+
+    #[test]
+    fn test_stream_max_delta_bytes() {
+        let config = StreamConfig { max_delta_bytes: 16, ..Default::default() };
+        let mut sender = FluxStreamSession::with_config(config);
+
+        sender.update(br#"{"a": 1, "b": "x"}"#).unwrap();
+        // Small change fits in the limit
+        let small = sender.update(br#"{"a": 2, "b": "x"}"#).unwrap();
+        assert!(small.len() <= 16, "delta is {} bytes", small.len());
+        // Large change exceeds it and is sent as a snapshot
+        sender.update(br#"{"a": 2, "b": "a much longer string value"}"#).unwrap();
+
+        assert_eq!(sender.stats().full_sends, 2);
+        assert_eq!(sender.stats().delta_sends, 1);
+    }
+
+    #[test]
+    fn test_stream_merge_patch() {
+        let config = StreamConfig { patch_format: PatchFormat::MergePatch, ..Default::default() };
+        let mut sender = FluxStreamSession::with_config(config.clone());
+        let mut receiver = FluxStreamSession::with_config(config);
+
+        let states = [
+            serde_json::json!({"count": 0, "room": {"name": "lobby", "users": ["alice"]}}),
+            serde_json::json!({"count": 1, "room": {"name": "lobby", "users": ["alice", "bob"]}}),
+            serde_json::json!({"count": 1, "room": {"name": "lobby", "users": ["alice", "bob"]}}),
+            serde_json::json!({"room": {"name": "hall", "users": []}, "topic": "news"}),
+        ];
+        for state in &states {
+            let frame = sender.update(&serde_json::to_vec(state).unwrap()).unwrap();
+            assert_eq!(&frame[0..4], b"FLUX");
+            let json = receiver.receive(&frame).unwrap();
+            assert_eq!(&serde_json::from_slice::<serde_json::Value>(&json).unwrap(), state);
+        }
+
+        // A plain FLUX decoder yields an RFC 7386 patch (new shape, so the schema is inline)
+        let frame = sender.update(br#"{"room": {"name": "hall", "users": []}, "topic": "sports"}"#).unwrap();
+        let patch: serde_json::Value = serde_json::from_slice(&decompress(&frame).unwrap()).unwrap();
+        let mut state = states[3].clone();
+        assert_eq!(patch, serde_json::json!({"topic": "sports"}));
+        apply_merge_patch(&mut state, &patch);
+        assert_eq!(state["topic"], "sports");
+
+        assert_eq!(sender.stats().full_sends, 1);
+        assert_eq!(sender.stats().delta_sends, 4);
+
+        // Null members would arrive as deletions, so they are refused
+        for state in [r#"{"topic": null}"#, r#"{"room": {"name": null}}"#] {
+            let err = sender.update(state.as_bytes()).unwrap_err();
+            assert!(matches!(err, Error::UnsupportedType(_)), "{}", err);
+        }
+        let frame = sender.update(br#"{"room": {"name": "hall", "users": []}, "list": [null]}"#).unwrap();
+        let json = receiver.receive(&frame).unwrap();
+        let state: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(state, serde_json::json!({"room": {"name": "hall", "users": []}, "list": [null]}));
+    }
+
+    #[test]
+    fn test_stream_merge_patch_keyframe_recovers() {
+        let config = StreamConfig {
+            patch_format: PatchFormat::MergePatch,
+            keyframe_interval: 2,
+            ..Default::default()
+        };
+        let mut sender = FluxStreamSession::with_config(config.clone());
+        let mut receiver = FluxStreamSession::with_config(config);
+
+        receiver.receive(&sender.update(br#"{"a": 1, "b": 2}"#).unwrap()).unwrap();
+        let _lost = sender.update(br#"{"a": 1, "b": 3}"#).unwrap();
+        let keyframe = sender.update(br#"{"a": 1, "c": 4}"#).unwrap();
+
+        let json = receiver.receive(&keyframe).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({"a": 1, "c": 4})
+        );
+        assert_eq!(sender.stats().full_sends, 2);
+
+        assert!(sender.update(b"[1, 2]").is_err());
+    }
+
     #[test]
     fn test_stream_session_efficiency_large_state() {
         let mut sender = FluxStreamSession::new();
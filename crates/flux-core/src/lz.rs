@@ -17,68 +17,274 @@ const MAX_MATCH: usize = 255 + MIN_MATCH;
 /// Maximum offset (64KB window)
 const MAX_OFFSET: usize = 65535;
 
-/// Hash table size
-const HASH_SIZE: usize = 1 << 14;
+/// Most bytes of earlier messages a block can reference
+pub const MAX_HISTORY: usize = MAX_OFFSET;
+
+/// Block flags
+const FLAG_RAW: u8 = 0;
+const FLAG_COMPRESSED: u8 = 1;
+/// Compressed, with matches reaching back into the history of earlier messages
+const FLAG_HISTORY: u8 = 2;
+
+/// Smallest hash table, in bits
+pub const MIN_HASH_BITS: u8 = 8;
+
+/// Largest hash table, in bits
+pub const MAX_HASH_BITS: u8 = 20;
+
+/// Most candidates examined per position
+pub const MAX_CHAIN: u32 = 4096;
+
+/// LZ tuning parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LzParams {
+    /// Hash table size in bits; inputs smaller than the table use fewer
+    pub hash_bits: u8,
+    /// Candidates examined per position (1 = single probe, no chains)
+    pub max_chain: u32,
+    /// Maximum match distance in bytes (at most 65535)
+    pub window: usize,
+}
+
+impl Default for LzParams {
+    fn default() -> Self {
+        Self {
+            hash_bits: 14,
+            max_chain: 1,
+            window: MAX_OFFSET,
+        }
+    }
+}
+
+impl LzParams {
+    /// Lowest latency: small table, single probe
+    pub fn fast() -> Self {
+        Self {
+            hash_bits: 12,
+            max_chain: 1,
+            window: MAX_OFFSET,
+        }
+    }
+
+    /// Best ratio: large table, deep chains
+    pub fn max() -> Self {
+        Self {
+            hash_bits: 16,
+            max_chain: 64,
+            window: MAX_OFFSET,
+        }
+    }
+
+    /// Check that every parameter is within range
+    pub fn validate(&self) -> Result<()> {
+        if !(MIN_HASH_BITS..=MAX_HASH_BITS).contains(&self.hash_bits) {
+            return Err(Error::InvalidConfig {
+                field: "lz.hash_bits",
+                reason: format!("must be {}..={}, got {}", MIN_HASH_BITS, MAX_HASH_BITS, self.hash_bits),
+            });
+        }
+        if !(1..=MAX_CHAIN).contains(&self.max_chain) {
+            return Err(Error::InvalidConfig {
+                field: "lz.max_chain",
+                reason: format!("must be 1..={}, got {}", MAX_CHAIN, self.max_chain),
+            });
+        }
+        if !(MIN_MATCH..=MAX_OFFSET).contains(&self.window) {
+            return Err(Error::InvalidConfig {
+                field: "lz.window",
+                reason: format!("must be {}..={}, got {}", MIN_MATCH, MAX_OFFSET, self.window),
+            });
+        }
+        Ok(())
+    }
+
+    /// Hash table bits for an input of `len` bytes
+    ///
+    /// A table with more slots than input positions only costs time to
+    /// allocate and clear, so small messages get a smaller one.
+    pub fn hash_bits_for(&self, len: usize) -> u8 {
+        let needed = usize::BITS - len.saturating_sub(1).leading_zeros();
+        (needed as u8).clamp(MIN_HASH_BITS, self.hash_bits.max(MIN_HASH_BITS))
+    }
+}
+
+/// Statistics from one `lz_compress_with` call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LzStats {
+    /// Hash table size used, in bits
+    pub hash_bits: u8,
+    /// Matches emitted
+    pub matches: u32,
+    /// Input bytes covered by matches
+    pub matched_bytes: usize,
+    /// Candidates examined
+    pub chain_steps: u64,
+    /// Matches that reference the history rather than the message itself
+    pub history_matches: u32,
+}
 
 /// Hash function for 4 bytes
 #[inline]
-fn hash4(data: &[u8]) -> usize {
+fn hash4(data: &[u8], bits: u8) -> usize {
     let v = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
-    ((v.wrapping_mul(2654435761)) >> 18) as usize & (HASH_SIZE - 1)
+    (v.wrapping_mul(2654435761) >> (32 - bits as u32)) as usize
 }
 
-/// Compress data using LZ77
+/// Compress data using LZ77 with default parameters
 pub fn lz_compress(input: &[u8]) -> Result<Vec<u8>> {
+    lz_compress_with(input, &LzParams::default()).map(|(output, _)| output)
+}
+
+/// Compress data using LZ77
+pub fn lz_compress_with(input: &[u8], params: &LzParams) -> Result<(Vec<u8>, LzStats)> {
+    let mut output = Vec::new();
+    let stats = lz_compress_into(input, params, &mut LzTables::default(), &mut output)?;
+    Ok((output, stats))
+}
+
+/// Match-finder tables, kept between calls to avoid reallocating them
+#[derive(Debug, Default)]
+pub struct LzTables {
+    hash_table: Vec<u32>,
+    chain: Vec<u32>,
+    /// History followed by the input, when compressing against a history
+    window: Vec<u8>,
+}
+
+impl LzTables {
+    /// Bytes held by the tables
+    pub fn capacity(&self) -> usize {
+        (self.hash_table.capacity() + self.chain.capacity()) * 4 + self.window.capacity()
+    }
+}
+
+/// Compress data using LZ77, replacing the contents of `output`
+pub fn lz_compress_into(input: &[u8], params: &LzParams, tables: &mut LzTables, output: &mut Vec<u8>) -> Result<LzStats> {
+    lz_compress_with_history(input, &[], params, tables, output)
+}
+
+/// Compress data using LZ77, letting matches reference `history`
+///
+/// `history` holds the bytes that preceded `input`, normally the tail of
+/// earlier messages; only its last `MAX_HISTORY` bytes are used. Blocks
+/// whose matches reach into it must be decompressed with
+/// `lz_decompress_with_history` and the same history.
+pub fn lz_compress_with_history(
+    input: &[u8],
+    history: &[u8],
+    params: &LzParams,
+    tables: &mut LzTables,
+    output: &mut Vec<u8>,
+) -> Result<LzStats> {
+    output.clear();
     if input.is_empty() {
-        return Ok(Vec::new());
+        return Ok(LzStats::default());
     }
 
     // Too small to benefit from LZ
     if input.len() < MIN_MATCH * 2 {
-        let mut output = Vec::with_capacity(input.len() + 6);
-        output.push(LZ_MAGIC);
-        output.extend_from_slice(&(input.len() as u32).to_le_bytes());
-        output.push(0); // Flag: raw
-        output.extend_from_slice(input);
-        return Ok(output);
+        write_raw_block(input, output);
+        return Ok(LzStats::default());
     }
 
-    let mut hash_table = vec![0u32; HASH_SIZE];
-    let mut output = Vec::with_capacity(input.len());
+    let history = &history[history.len().saturating_sub(MAX_HISTORY)..];
+    if history.is_empty() {
+        return compress_block(input, 0, params, &mut tables.hash_table, &mut tables.chain, output);
+    }
+    let window = &mut tables.window;
+    window.clear();
+    window.extend_from_slice(history);
+    window.extend_from_slice(input);
+    compress_block(window, history.len(), params, &mut tables.hash_table, &mut tables.chain, output)
+}
+
+/// Compress `data[start..]`, with `data[..start]` available to matches
+fn compress_block(
+    data: &[u8],
+    start: usize,
+    params: &LzParams,
+    hash_table: &mut Vec<u32>,
+    chain: &mut Vec<u32>,
+    output: &mut Vec<u8>,
+) -> Result<LzStats> {
+    let mut stats = LzStats::default();
+    let input = &data[start..];
+    let window = params.window.min(MAX_OFFSET);
+    stats.hash_bits = params.hash_bits_for(data.len());
+    // Positions are stored +1 so that 0 means empty
+    hash_table.clear();
+    hash_table.resize(1 << stats.hash_bits, 0);
+    chain.clear();
+    if params.max_chain > 1 {
+        chain.resize(data.len(), 0);
+    }
+    output.reserve(input.len());
 
     // Header
     output.push(LZ_MAGIC);
     output.extend_from_slice(&(input.len() as u32).to_le_bytes());
-    output.push(1); // Flag: compressed
-
-    let mut pos: usize = 0;
-    let mut literal_start: usize = 0;
-
-    while pos + MIN_MATCH <= input.len() {
-        let hash = hash4(&input[pos..]);
-        let match_pos = hash_table[hash] as usize;
-        hash_table[hash] = pos as u32;
-
-        // Check for match
-        if match_pos > 0
-            && pos > match_pos
-            && pos - match_pos <= MAX_OFFSET
-            && input[match_pos..match_pos + MIN_MATCH] == input[pos..pos + MIN_MATCH]
-        {
-            // Found match, extend it
-            let offset = pos - match_pos;
-            let mut match_len = MIN_MATCH;
-            while pos + match_len < input.len()
+    output.push(FLAG_COMPRESSED);
+
+    // Seed the tables with the history
+    for pos in 0..start.min(data.len() - MIN_MATCH + 1) {
+        let hash = hash4(&data[pos..], stats.hash_bits);
+        if !chain.is_empty() {
+            chain[pos] = hash_table[hash];
+        }
+        hash_table[hash] = pos as u32 + 1;
+    }
+
+    let mut pos = start;
+    let mut literal_start = start;
+
+    while pos + MIN_MATCH <= data.len() {
+        let hash = hash4(&data[pos..], stats.hash_bits);
+        let mut candidate = hash_table[hash] as usize;
+        hash_table[hash] = pos as u32 + 1;
+        if !chain.is_empty() {
+            chain[pos] = candidate as u32;
+        }
+
+        // Longest match among the chained candidates
+        let mut best = (0, 0);
+        for _ in 0..params.max_chain {
+            if candidate == 0 {
+                break;
+            }
+            let match_pos = candidate - 1;
+            if pos - match_pos > window {
+                break;
+            }
+            stats.chain_steps += 1;
+
+            let mut match_len = 0;
+            while pos + match_len < data.len()
                 && match_pos + match_len < pos
                 && match_len < MAX_MATCH
-                && input[match_pos + match_len] == input[pos + match_len]
+                && data[match_pos + match_len] == data[pos + match_len]
             {
                 match_len += 1;
             }
+            if match_len > best.1 {
+                best = (pos - match_pos, match_len);
+            }
 
+            candidate = match chain.get(match_pos) {
+                Some(&prev) => prev as usize,
+                None => 0,
+            };
+        }
+
+        let (offset, match_len) = best;
+        if match_len >= MIN_MATCH {
             // Write literals if any
-            let literals = &input[literal_start..pos];
-            write_sequence(&mut output, literals, offset, match_len);
+            let literals = &data[literal_start..pos];
+            write_sequence(output, literals, offset, match_len);
+            stats.matches += 1;
+            stats.matched_bytes += match_len;
+            if pos - offset < start {
+                stats.history_matches += 1;
+            }
 
             pos += match_len;
             literal_start = pos;
@@ -88,25 +294,128 @@ pub fn lz_compress(input: &[u8]) -> Result<Vec<u8>> {
     }
 
     // Write remaining literals
-    if literal_start < input.len() {
-        write_literals(&mut output, &input[literal_start..]);
+    if literal_start < data.len() {
+        write_literals(output, &data[literal_start..]);
     }
 
     // If compression didn't help, return raw
     if output.len() >= input.len() + 6 {
-        let mut output = Vec::with_capacity(input.len() + 6);
-        output.push(LZ_MAGIC);
-        output.extend_from_slice(&(input.len() as u32).to_le_bytes());
-        output.push(0); // Flag: raw
-        output.extend_from_slice(input);
-        return Ok(output);
+        output.clear();
+        write_raw_block(input, output);
+    } else if stats.history_matches > 0 {
+        output[HEADER_SIZE - 1] = FLAG_HISTORY;
     }
 
-    Ok(output)
+    Ok(stats)
+}
+
+/// Tail of the payloads a session has sent or received, for LZ history
+#[derive(Debug, Default)]
+pub(crate) struct LzHistory {
+    buf: Vec<u8>,
+    max: usize,
+}
+
+impl LzHistory {
+    /// Keep the last `max` bytes (0 = keep nothing)
+    pub(crate) fn new(max: usize) -> Self {
+        Self { buf: Vec::new(), max: max.min(MAX_HISTORY) }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Append a payload, dropping bytes that fall out of the window
+    pub(crate) fn push(&mut self, payload: &[u8]) {
+        if self.max == 0 {
+            return;
+        }
+        let payload = &payload[payload.len().saturating_sub(self.max)..];
+        let excess = (self.buf.len() + payload.len()).saturating_sub(self.max);
+        self.buf.drain(..excess);
+        self.buf.extend_from_slice(payload);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buf = Vec::new();
+    }
+
+    /// Bytes reserved for the window
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+}
+
+/// Size of the LZ block header
+pub(crate) const HEADER_SIZE: usize = 6;
+
+/// Header of an uncompressed LZ block of `len` bytes
+pub(crate) fn raw_header(len: usize) -> [u8; HEADER_SIZE] {
+    let len = (len as u32).to_le_bytes();
+    [LZ_MAGIC, len[0], len[1], len[2], len[3], 0] // Flag: raw
+}
+
+/// Whether `data` reads as an LZ block
+pub(crate) fn is_block(data: &[u8]) -> bool {
+    data.first() == Some(&LZ_MAGIC)
+}
+
+/// Inputs shorter than this always go through LZ: estimating their entropy
+/// costs about as much as the pass
+const ESTIMATE_MIN: usize = 4096;
+
+/// Bytes sampled for the entropy estimate
+const ESTIMATE_SAMPLE: usize = 16 * 1024;
+
+/// Order-0 entropy (bits per byte) above which matches are unlikely
+const INCOMPRESSIBLE_BITS: f64 = 7.5;
+
+/// Whether `input` looks too random for the LZ stage to find matches,
+/// judging by the byte entropy of its first bytes
+pub(crate) fn likely_incompressible(input: &[u8]) -> bool {
+    if input.len() < ESTIMATE_MIN {
+        return false;
+    }
+    let sample = &input[..input.len().min(ESTIMATE_SAMPLE)];
+    let mut counts = [0u32; 256];
+    for &byte in sample {
+        counts[byte as usize] += 1;
+    }
+    let n = sample.len() as f64;
+    let bits: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / n;
+            -p * p.log2()
+        })
+        .sum();
+    bits > INCOMPRESSIBLE_BITS
+}
+
+/// Append an uncompressed LZ block
+pub(crate) fn write_raw_block(input: &[u8], output: &mut Vec<u8>) {
+    output.reserve(input.len() + HEADER_SIZE);
+    output.extend_from_slice(&raw_header(input.len()));
+    output.extend_from_slice(input);
 }
 
 /// Decompress LZ77 data
 pub fn lz_decompress(input: &[u8]) -> Result<Vec<u8>> {
+    lz_decompress_with_limit(input, usize::MAX)
+}
+
+/// Decompress LZ77 data, refusing blocks that expand past `limit` bytes
+pub fn lz_decompress_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+    lz_decompress_with_history(input, &[], limit)
+}
+
+/// Decompress LZ77 data whose matches may reference `history`
+///
+/// `history` must hold the bytes the compressor was given as history, or at
+/// least their last `MAX_HISTORY` bytes.
+pub fn lz_decompress_with_history(input: &[u8], history: &[u8], limit: usize) -> Result<Vec<u8>> {
     if input.is_empty() {
         return Ok(Vec::new());
     }
@@ -116,9 +425,19 @@ pub fn lz_decompress(input: &[u8]) -> Result<Vec<u8>> {
     }
 
     let orig_len = u32::from_le_bytes([input[1], input[2], input[3], input[4]]) as usize;
+    if orig_len > limit {
+        return Err(Error::OutputLimitExceeded { limit });
+    }
     let flag = input[5];
+    let history = match flag {
+        FLAG_HISTORY if history.is_empty() => {
+            return Err(Error::DecodeError("LZ block references earlier messages, but no history is kept".into()));
+        }
+        FLAG_HISTORY => &history[history.len().saturating_sub(MAX_HISTORY)..],
+        _ => &[],
+    };
 
-    if flag == 0 {
+    if flag == FLAG_RAW {
         // Raw data
         if input.len() < 6 + orig_len {
             return Err(Error::DecodeError("Truncated LZ raw data".into()));
@@ -170,7 +489,7 @@ pub fn lz_decompress(input: &[u8]) -> Result<Vec<u8>> {
         let offset = input[pos] as usize | ((input[pos + 1] as usize) << 8);
         pos += 2;
 
-        if offset == 0 || offset > output.len() {
+        if offset == 0 || offset > history.len() + output.len() {
             return Err(Error::DecodeError("Invalid offset".into()));
         }
 
@@ -187,13 +506,17 @@ pub fn lz_decompress(input: &[u8]) -> Result<Vec<u8>> {
             }
         }
 
-        // Copy match (handle overlapping)
-        let match_start = output.len() - offset;
+        // Copy match (handle overlapping); positions below history.len() are in the history
+        let match_start = history.len() + output.len() - offset;
         for i in 0..match_len {
             if output.len() >= orig_len {
                 break;
             }
-            output.push(output[match_start + i]);
+            let byte = match (match_start + i).checked_sub(history.len()) {
+                Some(at) => output[at],
+                None => history[match_start + i],
+            };
+            output.push(byte);
         }
     }
 
@@ -335,4 +658,86 @@ mod tests {
         // JSON with repeated patterns should compress
         assert!(compressed.len() <= data.len() + 6); // At least not much worse
     }
+
+    #[test]
+    fn test_decompress_limit() {
+        let data = vec![b'a'; 4096];
+        let compressed = lz_compress(&data).unwrap();
+        assert_eq!(lz_decompress_with_limit(&compressed, 4096).unwrap(), data);
+        assert!(matches!(
+            lz_decompress_with_limit(&compressed, 4095),
+            Err(Error::OutputLimitExceeded { limit: 4095 })
+        ));
+    }
+
+    #[test]
+    fn test_history() {
+        let first = br#"{"id":1,"name":"alice","email":"alice@example.com","roles":["admin","dev"]}"#;
+        let second = br#"{"id":2,"name":"alice","email":"alice@example.com","roles":["admin","ops"]}"#;
+        let mut history = LzHistory::new(1024);
+        history.push(first);
+
+        let params = LzParams::default();
+        let mut tables = LzTables::default();
+        let mut with_history = Vec::new();
+        let stats = lz_compress_with_history(second, history.as_slice(), &params, &mut tables, &mut with_history).unwrap();
+        assert!(stats.history_matches > 0);
+        assert!(with_history.len() < lz_compress(second).unwrap().len());
+        assert_eq!(lz_decompress_with_history(&with_history, history.as_slice(), usize::MAX).unwrap(), second);
+        assert!(matches!(lz_decompress(&with_history), Err(Error::DecodeError(_))));
+
+        // The window keeps only the newest bytes
+        let mut small = LzHistory::new(8);
+        small.push(b"abcdef");
+        small.push(b"ghij");
+        assert_eq!(small.as_slice(), b"cdefghij");
+        small.push(b"0123456789");
+        assert_eq!(small.as_slice(), b"23456789");
+        LzHistory::new(0).push(b"ignored");
+    }
+
+    #[test]
+    fn test_params_roundtrip() {
+        let data = br#"{"id":1,"name":"test","tags":["a","b"]},{"id":2,"name":"test","tags":["a","c"]}"#.repeat(20);
+        for params in [LzParams::fast(), LzParams::default(), LzParams::max()] {
+            params.validate().unwrap();
+            let (compressed, stats) = lz_compress_with(&data, &params).unwrap();
+            assert_eq!(lz_decompress(&compressed).unwrap(), data);
+            assert!(stats.matches > 0);
+        }
+
+        let (fast, _) = lz_compress_with(&data, &LzParams::fast()).unwrap();
+        let (max, stats) = lz_compress_with(&data, &LzParams::max()).unwrap();
+        assert!(max.len() <= fast.len());
+        assert!(stats.chain_steps >= stats.matches as u64);
+    }
+
+    #[test]
+    fn test_window_limits_offsets() {
+        let block: Vec<u8> = (0..=255u8).collect();
+        let data = [block.as_slice(), &[0xAA; 64], block.as_slice()].concat();
+
+        let wide = LzParams::default();
+        let narrow = LzParams { window: 128, ..wide };
+        let (_, wide_stats) = lz_compress_with(&data, &wide).unwrap();
+        let (compressed, narrow_stats) = lz_compress_with(&data, &narrow).unwrap();
+        assert!(narrow_stats.matched_bytes < wide_stats.matched_bytes);
+        assert_eq!(lz_decompress(&compressed).unwrap(), data);
+    }
+
+    #[test]
+    fn test_hash_bits_adapt_to_input() {
+        let params = LzParams::default();
+        assert_eq!(params.hash_bits_for(0), MIN_HASH_BITS);
+        assert_eq!(params.hash_bits_for(200), MIN_HASH_BITS);
+        assert_eq!(params.hash_bits_for(1000), 10);
+        assert_eq!(params.hash_bits_for(1 << 20), 14);
+    }
+
+    #[test]
+    fn test_params_validate() {
+        assert!(LzParams { hash_bits: 4, ..Default::default() }.validate().is_err());
+        assert!(LzParams { max_chain: 0, ..Default::default() }.validate().is_err());
+        assert!(LzParams { window: 1 << 20, ..Default::default() }.validate().is_err());
+    }
 }
@@ -0,0 +1,98 @@
+//! MessagePack input and output
+//!
+//! MessagePack is parsed into the same value model as JSON, so schemas are
+//! inferred and cached exactly as for JSON traffic and a frame compressed
+//! from MessagePack decompresses to JSON just as well (and vice versa).
+//!
+//! The value model is JSON's: map keys must be strings, and `bin` and
+//! extension types are rejected with `Error::ParseError`.
+//!
+//! Requires the `msgpack` feature.
+
+use crate::observer::{MessageProbe, Operation};
+use crate::{Error, FluxSession, MessageOptions, Result};
+
+/// Compress MessagePack data
+///
+/// One-shot version of `FluxSession::compress_msgpack`.
+pub fn compress_msgpack(input: &[u8]) -> Result<Vec<u8>> {
+    FluxSession::new().compress_msgpack(input)
+}
+
+/// Decompress FLUX data to MessagePack
+///
+/// One-shot version of `FluxSession::decompress_msgpack`.
+pub fn decompress_msgpack(input: &[u8]) -> Result<Vec<u8>> {
+    FluxSession::new().decompress_msgpack(input)
+}
+
+impl FluxSession {
+    /// Compress a MessagePack-encoded message
+    pub fn compress_msgpack(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut probe = MessageProbe::new(Operation::Compress, input.len(), self.probe_clock());
+        self.stats.messages_processed += 1;
+        self.stats.bytes_in += input.len() as u64;
+
+        let value: serde_json::Value = rmp_serde::from_slice(input)
+            .map_err(|e| Error::ParseError(e.to_string()))?;
+        probe.lap(|t| &mut t.parse);
+
+        let mut output = Vec::with_capacity(input.len() / 2 + 32);
+        self.compress_parsed(&value, input.len(), None, &MessageOptions::default(), &mut output, &mut probe)?;
+        self.notify(probe);
+        Ok(output)
+    }
+
+    /// Decompress a FLUX frame to MessagePack
+    pub fn decompress_msgpack(&mut self, input: &[u8]) -> Result<Vec<u8>> {
+        let mut probe = MessageProbe::new(Operation::Decompress, input.len(), self.probe_clock());
+        let value = self.decompress_value(input, &mut probe, None)?;
+        let output = rmp_serde::to_vec(&value).map_err(|e| Error::SerializeError(e.to_string()))?;
+        if output.len() > self.config.max_decompressed_size {
+            return Err(Error::OutputLimitExceeded { limit: self.config.max_decompressed_size });
+        }
+        probe.lap(|t| &mut t.encode);
+        probe.stats.output_bytes = output.len();
+        self.notify(probe);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_msgpack_roundtrip() {
+        let value = json!({"id": 7, "name": "alice", "score": 0.5, "tags": ["a", "b"]});
+        let packed = rmp_serde::to_vec(&value).unwrap();
+
+        let mut encoder = FluxSession::new();
+        let mut decoder = FluxSession::new();
+        let first = encoder.compress_msgpack(&packed).unwrap();
+        let second = encoder.compress_msgpack(&packed).unwrap();
+        assert!(second.len() < first.len(), "schema is cached across msgpack messages");
+
+        let unpacked = decoder.decompress_msgpack(&first).unwrap();
+        assert_eq!(rmp_serde::from_slice::<serde_json::Value>(&unpacked).unwrap(), value);
+        let unpacked = decoder.decompress_msgpack(&second).unwrap();
+        assert_eq!(rmp_serde::from_slice::<serde_json::Value>(&unpacked).unwrap(), value);
+
+        // Frames are format-agnostic
+        let json: serde_json::Value = serde_json::from_slice(&crate::decompress(&first).unwrap()).unwrap();
+        assert_eq!(json, value);
+        let from_json = crate::compress(&serde_json::to_vec(&value).unwrap()).unwrap();
+        assert_eq!(decompress_msgpack(&from_json).unwrap(), rmp_serde::to_vec(&value).unwrap());
+        assert_eq!(compress_msgpack(&packed).unwrap(), first);
+    }
+
+    #[test]
+    fn test_msgpack_rejects_non_json_values() {
+        // Integer map key
+        let packed = [0x81, 0x01, 0x02];
+        assert!(matches!(compress_msgpack(&packed), Err(Error::ParseError(_))));
+        // Truncated input
+        assert!(matches!(compress_msgpack(&[0x92, 0x01]), Err(Error::ParseError(_))));
+    }
+}
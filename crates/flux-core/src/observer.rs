@@ -0,0 +1,141 @@
+//! Per-message statistics hooks
+//!
+//! `SessionStats` only holds running totals. A `StatsObserver` installed with
+//! `FluxSession::set_observer` is told about every message as it completes,
+//! which is what metrics exporters (Prometheus histograms, tracing spans)
+//! need.
+//!
+//! ```rust,ignore
+//! struct Metrics { compressed_bytes: AtomicU64 }
+//!
+//! impl StatsObserver for Metrics {
+//!     fn observe(&self, stats: &MessageStats) {
+//!         if stats.operation == Operation::Compress {
+//!             self.compressed_bytes.fetch_add(stats.output_bytes as u64, Ordering::Relaxed);
+//!         }
+//!     }
+//! }
+//!
+//! session.set_observer(Some(Arc::new(Metrics::default())));
+//! ```
+
+use std::ops::AddAssign;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+/// Which way a message went through the session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Operation {
+    #[default]
+    Compress,
+    Decompress,
+}
+
+/// Wall time spent in each pipeline stage of one message
+///
+/// Stages a message skipped (e.g. entropy coding when disabled) stay zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    /// JSON parsing (compress) or frame parsing (decompress)
+    pub parse: Duration,
+    /// Schema inference and cache lookup, or schema loading
+    pub schema: Duration,
+    /// Schema encoding (compress) or decoding and JSON output (decompress)
+    pub encode: Duration,
+    /// LZ stage
+    pub lz: Duration,
+    /// Entropy stage
+    pub entropy: Duration,
+    /// Whole call, including framing
+    pub total: Duration,
+}
+
+impl StageTimings {
+    /// Name and time of the slowest of the encode, LZ and entropy stages
+    pub fn bottleneck(&self) -> (&'static str, Duration) {
+        [("encode", self.encode), ("lz", self.lz), ("entropy", self.entropy)]
+            .into_iter()
+            .max_by_key(|(_, time)| *time)
+            .unwrap_or(("encode", Duration::ZERO))
+    }
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.parse += other.parse;
+        self.schema += other.schema;
+        self.encode += other.encode;
+        self.lz += other.lz;
+        self.entropy += other.entropy;
+        self.total += other.total;
+    }
+}
+
+/// Statistics for a single compressed or decompressed message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageStats {
+    pub operation: Operation,
+    /// Bytes handed to the session
+    pub input_bytes: usize,
+    /// Bytes the session produced
+    pub output_bytes: usize,
+    /// Whether the schema came from the session cache rather than being
+    /// inferred anew (compress) or read from the frame (decompress)
+    pub schema_cache_hit: bool,
+    pub timings: StageTimings,
+}
+
+/// Receives `MessageStats` after every successful compress or decompress
+///
+/// Called on the thread doing the work, so implementations should be cheap;
+/// failed calls are not reported.
+pub trait StatsObserver: Send + Sync {
+    fn observe(&self, stats: &MessageStats);
+}
+
+/// Collects `MessageStats` for one call
+///
+/// The clock is only read when an observer is installed or
+/// `FluxConfig::stage_timing` is on, so other sessions pay nothing for timing.
+pub(crate) struct MessageProbe {
+    pub stats: MessageStats,
+    clock: Option<Arc<dyn Clock>>,
+    start: Duration,
+    last: Duration,
+}
+
+impl MessageProbe {
+    /// Start timing against `clock`, or with `None`, only count bytes
+    pub fn new(operation: Operation, input_bytes: usize, clock: Option<Arc<dyn Clock>>) -> Self {
+        let now = clock.as_ref().and_then(|clock| clock.now());
+        Self {
+            stats: MessageStats {
+                operation,
+                input_bytes,
+                ..MessageStats::default()
+            },
+            // A clock that cannot tell the time is as good as none
+            clock: now.and(clock),
+            start: now.unwrap_or_default(),
+            last: now.unwrap_or_default(),
+        }
+    }
+
+    /// Charge the time since the previous lap to `stage`
+    pub fn lap(&mut self, stage: fn(&mut StageTimings) -> &mut Duration) {
+        if let Some(now) = self.clock.as_ref().and_then(|clock| clock.now()) {
+            *stage(&mut self.stats.timings) += now.saturating_sub(self.last);
+            self.last = now;
+        }
+    }
+
+    /// Stop the clock and hand back the finished stats
+    pub fn finish(mut self) -> MessageStats {
+        if let Some(now) = self.clock.as_ref().and_then(|clock| clock.now()) {
+            self.stats.timings.total = now.saturating_sub(self.start);
+        }
+        self.stats
+    }
+}
@@ -0,0 +1,180 @@
+//! Parquet storage for columnar blocks
+//!
+//! FLUX on the wire, Parquet at rest: `FluxParquetWriter` appends each
+//! decompressed columnar block to a Parquet file as one row group, and
+//! `read_parquet` turns the file back into blocks. The FLUX schema is kept
+//! in the file's key-value metadata under `flux:schema`, so the at-rest
+//! data carries exactly the schema the wire format used.
+//!
+//! ```rust,ignore
+//! let mut writer = FluxParquetWriter::new(File::create("events.parquet")?, &schema)?;
+//! for block in blocks {
+//!     writer.write_block(&block)?;
+//! }
+//! writer.finish()?;
+//! ```
+//!
+//! Requires the `parquet` feature.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::errors::ParquetError;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::reader::ChunkReader;
+use ::parquet::format::KeyValue;
+use arrow_array::RecordBatch;
+use arrow_schema::Schema as ArrowSchema;
+
+use crate::columnar::ColumnarBlock;
+use crate::{Error, Result, Schema};
+
+/// Key-value metadata entry holding the hex-encoded FLUX schema
+pub const FLUX_SCHEMA_KEY: &str = "flux:schema";
+
+/// Writes columnar blocks to a Parquet file, one row group per block
+pub struct FluxParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    arrow_schema: Arc<ArrowSchema>,
+    schema: Schema,
+}
+
+impl<W: Write + Send> FluxParquetWriter<W> {
+    /// Start a Parquet file of blocks with `schema`
+    pub fn new(sink: W, schema: &Schema) -> Result<Self> {
+        Self::with_properties(sink, schema, WriterProperties::default())
+    }
+
+    /// Start a Parquet file with custom writer properties (compression, statistics, ...)
+    pub fn with_properties(sink: W, schema: &Schema, properties: WriterProperties) -> Result<Self> {
+        let arrow_schema = Arc::new(ColumnarBlock::arrow_schema(schema)?);
+        let mut writer = ArrowWriter::try_new(sink, arrow_schema.clone(), Some(properties)).map_err(encode_error)?;
+        writer.append_key_value_metadata(KeyValue::new(FLUX_SCHEMA_KEY.to_string(), hex::encode(schema.serialize())));
+        Ok(Self { writer, arrow_schema, schema: schema.clone() })
+    }
+
+    /// Schema of the blocks in the file
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Append `block` as one row group
+    ///
+    /// The block must have the file's schema; empty blocks are skipped.
+    pub fn write_block(&mut self, block: &ColumnarBlock) -> Result<()> {
+        if block.row_count == 0 {
+            return Ok(());
+        }
+        let batch = block.to_arrow_record_batch()?;
+        let batch = RecordBatch::try_new(self.arrow_schema.clone(), batch.columns().to_vec())
+            .map_err(|e| Error::EncodeError(format!("Block does not match the Parquet schema: {}", e)))?;
+        self.writer.write(&batch).map_err(encode_error)?;
+        self.writer.flush().map_err(encode_error)
+    }
+
+    /// Append `rows` (objects of the file's schema) as one row group
+    pub fn write_rows(&mut self, rows: &[serde_json::Value]) -> Result<()> {
+        let block = ColumnarBlock::from_array(rows, &self.schema)?;
+        self.write_block(&block)
+    }
+
+    /// Write the file footer and return the sink
+    pub fn finish(self) -> Result<W> {
+        self.writer.into_inner().map_err(encode_error)
+    }
+}
+
+/// Read a Parquet file written by `FluxParquetWriter`
+///
+/// Returns the FLUX schema from the file metadata and one block per row
+/// group. Files without `flux:schema` metadata are rejected.
+pub fn read_parquet<R: ChunkReader + 'static>(reader: R) -> Result<(Schema, Vec<ColumnarBlock>)> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(reader).map_err(decode_error)?;
+    let metadata = builder.metadata().clone();
+    let descriptor = metadata
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|kv| kv.iter().find(|kv| kv.key == FLUX_SCHEMA_KEY))
+        .and_then(|kv| kv.value.as_deref())
+        .ok_or_else(|| Error::DecodeError("Parquet file has no FLUX schema".into()))?;
+    let bytes = hex::decode(descriptor).map_err(|e| Error::DecodeError(format!("Bad {}: {}", FLUX_SCHEMA_KEY, e)))?;
+    let schema = Schema::deserialize(&bytes)?;
+
+    // Batches may straddle row groups, so gather the rows and split them again
+    let mut rows = Vec::new();
+    for batch in builder.build().map_err(decode_error)? {
+        let (block, batch_schema) = ColumnarBlock::from_arrow(&batch.map_err(|e| Error::DecodeError(format!("Parquet: {}", e)))?)?;
+        rows.extend(block.to_array(&batch_schema)?);
+    }
+
+    let mut blocks = Vec::with_capacity(metadata.num_row_groups());
+    let mut start = 0;
+    for row_group in metadata.row_groups() {
+        let end = start + row_group.num_rows() as usize;
+        let group = rows
+            .get(start..end)
+            .ok_or_else(|| Error::DecodeError("Parquet row groups hold more rows than were read".into()))?;
+        blocks.push(ColumnarBlock::from_array(group, &schema)?);
+        start = end;
+    }
+    Ok((schema, blocks))
+}
+
+fn encode_error(e: ParquetError) -> Error {
+    Error::EncodeError(format!("Parquet: {}", e))
+}
+
+fn decode_error(e: ParquetError) -> Error {
+    Error::DecodeError(format!("Parquet: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::SchemaInferrer;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_parquet_roundtrip() {
+        let rows: Vec<Value> = (0..30)
+            .map(|i| json!({"id": i, "temp": 18.5 + i as f64, "site": {"city": "Oslo", "floor": i % 4}, "note": if i % 3 == 0 { json!(null) } else { json!("ok") }}))
+            .collect();
+        let mut inferrer = SchemaInferrer::new();
+        inferrer.add_value(&Value::Array(rows.clone())).unwrap();
+        let schema = inferrer.infer().unwrap();
+
+        let mut writer = FluxParquetWriter::new(Vec::new(), &schema).unwrap();
+        writer.write_rows(&rows[..10]).unwrap();
+        writer.write_block(&ColumnarBlock::new()).unwrap();
+        writer.write_block(&ColumnarBlock::from_array(&rows[10..], &schema).unwrap()).unwrap();
+        let file = writer.finish().unwrap();
+
+        let (decoded_schema, blocks) = read_parquet(bytes::Bytes::from(file)).unwrap();
+        assert_eq!((decoded_schema.hash, &decoded_schema.fields), (schema.hash, &schema.fields));
+        assert_eq!(blocks.iter().map(|b| b.row_count).collect::<Vec<_>>(), [10, 20]);
+        let decoded: Vec<Value> = blocks.iter().flat_map(|b| b.to_array(&schema).unwrap()).collect();
+        let expected: Vec<Value> = rows
+            .iter()
+            .map(|row| Value::Object(row.as_object().unwrap().iter().filter(|(_, v)| !v.is_null()).map(|(k, v)| (k.clone(), v.clone())).collect()))
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_parquet_schema_mismatch() {
+        let schema = Schema::builder().field("id", crate::FieldType::String).build().unwrap();
+        let other = Schema::builder().field("id", crate::FieldType::Boolean).build().unwrap();
+        let mut writer = FluxParquetWriter::new(Vec::new(), &schema).unwrap();
+        let block = ColumnarBlock::from_array(&[json!({"id": true})], &other).unwrap();
+        assert!(matches!(writer.write_block(&block), Err(Error::EncodeError(_))));
+
+        let plain = FluxParquetWriter::new(Vec::new(), &schema).unwrap();
+        let mut file = plain.finish().unwrap();
+        // Without the metadata entry the file is not a FLUX file
+        let pos = file.windows(FLUX_SCHEMA_KEY.len()).position(|w| w == FLUX_SCHEMA_KEY.as_bytes()).unwrap();
+        file[pos] = b'X';
+        assert!(matches!(read_parquet(bytes::Bytes::from(file)), Err(Error::DecodeError(_))));
+    }
+}
@@ -1,53 +1,204 @@
 //! Schema cache for efficient schema lookup
+//!
+//! A cache may be bounded with `SchemaCache::with_capacity`; registering a
+//! schema beyond the limit evicts one according to its `EvictionPolicy`.
+//! Compressor and decompressor see the same sequence of schema uses, so
+//! two caches with the same capacity and policy evict in step.
 
 use std::collections::HashMap;
-use super::Schema;
+use std::mem::size_of;
+use super::{FieldDef, Schema};
+use crate::cursor::Cursor;
+use crate::encoding::encode_varint;
+use crate::{Error, Result};
+
+/// Which schema a full cache evicts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently used
+    #[default]
+    Lru,
+    /// Least frequently used, oldest first among equals
+    Lfu,
+}
+
+struct Entry {
+    schema: Schema,
+    last_used: u64,
+    uses: u64,
+    /// Estimated bytes held, see `SchemaCache::memory_usage`
+    bytes: usize,
+}
+
+impl Entry {
+    fn new(schema: Schema, last_used: u64, uses: u64) -> Self {
+        let bytes = size_of::<Entry>() + schema.fields.len() * size_of::<FieldDef>() + schema.serialize().len();
+        Self { schema, last_used, uses, bytes }
+    }
+}
 
 /// Schema cache with ID and hash-based lookup
 pub struct SchemaCache {
-    schemas: HashMap<u32, Schema>,
+    schemas: HashMap<u32, Entry>,
     hash_index: HashMap<u64, u32>,
     next_id: u32,
+    /// Most schemas held at once (0 = unbounded)
+    capacity: usize,
+    policy: EvictionPolicy,
+    clock: u64,
+    evictions: u64,
+    /// Sum of the entries' estimated sizes
+    bytes: usize,
 }
 
 impl SchemaCache {
     /// Create a new empty cache
     pub fn new() -> Self {
+        Self::with_capacity(0, EvictionPolicy::default())
+    }
+
+    /// Create a cache holding at most `capacity` schemas (0 = unbounded)
+    pub fn with_capacity(capacity: usize, policy: EvictionPolicy) -> Self {
         Self {
             schemas: HashMap::new(),
             hash_index: HashMap::new(),
             next_id: 1,
+            capacity,
+            policy,
+            clock: 0,
+            evictions: 0,
+            bytes: 0,
         }
     }
 
     /// Get schema by ID
     pub fn get(&self, id: u32) -> Option<&Schema> {
-        self.schemas.get(&id)
+        self.schemas.get(&id).map(|entry| &entry.schema)
     }
 
     /// Get schema by hash
     pub fn get_by_hash(&self, hash: u64) -> Option<&Schema> {
         self.hash_index
             .get(&hash)
-            .and_then(|id| self.schemas.get(id))
+            .and_then(|id| self.get(*id))
+    }
+
+    /// Record a use of schema `id`, for eviction ordering
+    pub fn touch(&mut self, id: u32) {
+        self.clock += 1;
+        if let Some(entry) = self.schemas.get_mut(&id) {
+            entry.last_used = self.clock;
+            entry.uses += 1;
+        }
     }
 
     /// Register a new schema, returns assigned ID
-    pub fn register(&mut self, mut schema: Schema) -> u32 {
+    ///
+    /// Registering counts as a use; an existing schema keeps its ID.
+    pub fn register(&mut self, schema: Schema) -> u32 {
         // Check if already exists
         if let Some(&existing_id) = self.hash_index.get(&schema.hash) {
+            self.touch(existing_id);
             return existing_id;
         }
 
         // Assign new ID
         let id = self.next_id;
-        self.next_id += 1;
+        self.insert(id, schema);
+        id
+    }
+
+    /// Cache `schema` under a given ID, replacing any schema with that ID or hash
+    ///
+    /// Decompressors use this to keep the IDs assigned by the compressor.
+    pub fn insert(&mut self, id: u32, mut schema: Schema) {
+        self.remove(id);
+        if let Some(&old_id) = self.hash_index.get(&schema.hash) {
+            self.remove(old_id);
+        }
+        self.next_id = self.next_id.max(id.saturating_add(1));
 
         schema.id = id;
         self.hash_index.insert(schema.hash, id);
-        self.schemas.insert(id, schema);
+        let entry = Entry::new(schema, 0, 0);
+        self.bytes += entry.bytes;
+        self.schemas.insert(id, entry);
+        self.touch(id);
 
-        id
+        if self.capacity > 0 && self.schemas.len() > self.capacity {
+            self.evict(Some(id));
+        }
+    }
+
+    /// Drop one schema other than `keep` according to the policy, returning
+    /// whether there was one
+    pub(crate) fn evict(&mut self, keep: Option<u32>) -> bool {
+        let victim = self
+            .schemas
+            .iter()
+            .filter(|(&id, _)| Some(id) != keep)
+            .min_by_key(|(_, entry)| match self.policy {
+                EvictionPolicy::Lru => (0, entry.last_used),
+                EvictionPolicy::Lfu => (entry.uses, entry.last_used),
+            })
+            .map(|(&id, _)| id);
+        if let Some(id) = victim {
+            self.remove(id);
+            self.evictions += 1;
+        }
+        victim.is_some()
+    }
+
+    /// Schemas evicted to stay within capacity
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Approximate bytes held by the cached schemas
+    pub fn memory_usage(&self) -> usize {
+        self.bytes
+    }
+
+    /// ID the next registered schema gets
+    pub(crate) fn next_id(&self) -> u32 {
+        self.next_id
+    }
+
+    /// Most schemas held at once (0 = unbounded)
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Iterate over cached schemas in ID order
+    pub fn iter(&self) -> impl Iterator<Item = &Schema> {
+        let mut schemas: Vec<&Schema> = self.schemas.values().map(|entry| &entry.schema).collect();
+        schemas.sort_by_key(|schema| schema.id);
+        schemas.into_iter()
+    }
+
+    /// Remove a schema by ID, returning it if it was cached
+    ///
+    /// IDs are never reused, so frames referencing a removed schema fail
+    /// with `SchemaNotFound` rather than decoding against a newer shape.
+    pub fn remove(&mut self, id: u32) -> Option<Schema> {
+        let entry = self.schemas.remove(&id)?;
+        self.hash_index.remove(&entry.schema.hash);
+        self.bytes -= entry.bytes;
+        Some(entry.schema)
+    }
+
+    /// Keep only the schemas for which `keep` returns true
+    pub fn retain<F: FnMut(&Schema) -> bool>(&mut self, mut keep: F) {
+        let hash_index = &mut self.hash_index;
+        let bytes = &mut self.bytes;
+        self.schemas.retain(|_, entry| {
+            let kept = keep(&entry.schema);
+            if !kept {
+                hash_index.remove(&entry.schema.hash);
+                *bytes -= entry.bytes;
+            }
+            kept
+        });
     }
 
     /// Number of cached schemas
@@ -65,6 +216,7 @@ impl SchemaCache {
         self.schemas.clear();
         self.hash_index.clear();
         self.next_id = 1;
+        self.bytes = 0;
     }
 
     /// Serialize entire cache
@@ -75,7 +227,7 @@ impl SchemaCache {
         buf.extend_from_slice(&(self.schemas.len() as u32).to_le_bytes());
 
         // Each schema
-        for schema in self.schemas.values() {
+        for schema in self.iter() {
             let schema_bytes = schema.serialize();
             buf.extend_from_slice(&(schema_bytes.len() as u32).to_le_bytes());
             buf.extend_from_slice(&schema_bytes);
@@ -118,6 +270,70 @@ impl SchemaCache {
     }
 }
 
+impl SchemaCache {
+    /// Append the full cache state for a session snapshot
+    ///
+    /// Unlike `serialize`, this keeps schema IDs, usage counters and the
+    /// next ID, so a restored cache references and evicts exactly like the
+    /// original.
+    pub(crate) fn write_state(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.next_id as u64, buf);
+        encode_varint(self.clock, buf);
+        encode_varint(self.evictions, buf);
+        encode_varint(self.schemas.len() as u64, buf);
+        let mut ids: Vec<u32> = self.schemas.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let entry = &self.schemas[&id];
+            encode_varint(id as u64, buf);
+            encode_varint(entry.last_used, buf);
+            encode_varint(entry.uses, buf);
+            let schema = entry.schema.serialize();
+            encode_varint(schema.len() as u64, buf);
+            buf.extend_from_slice(&schema);
+        }
+    }
+
+    /// Replace the contents with state written by `write_state`
+    ///
+    /// Capacity and policy are kept. Nothing changes on error.
+    pub(crate) fn read_state(&mut self, cursor: &mut Cursor<'_>) -> Result<()> {
+        let mut restored = Self::with_capacity(self.capacity, self.policy);
+        let next_id = read_id(cursor)?;
+        restored.clock = cursor.read_varint("cache clock")?;
+        restored.evictions = cursor.read_varint("cache evictions")?;
+        let count = cursor.read_len("cached schema count")?;
+        if self.capacity > 0 && count > self.capacity {
+            return Err(Error::InvalidFrame(format!(
+                "Snapshot holds {} schemas, cache capacity is {}",
+                count, self.capacity
+            )));
+        }
+        for _ in 0..count {
+            let id = read_id(cursor)?;
+            let last_used = cursor.read_varint("schema last use")?;
+            let uses = cursor.read_varint("schema uses")?;
+            let mut schema = Schema::deserialize(cursor.read_len_bytes("cached schema")?)?;
+            if restored.schemas.contains_key(&id) || restored.hash_index.contains_key(&schema.hash) {
+                return Err(Error::InvalidFrame(format!("Duplicate cached schema {}", id)));
+            }
+            schema.id = id;
+            restored.hash_index.insert(schema.hash, id);
+            let entry = Entry::new(schema, last_used, uses);
+            restored.bytes += entry.bytes;
+            restored.schemas.insert(id, entry);
+            restored.next_id = restored.next_id.max(id.saturating_add(1));
+        }
+        restored.next_id = restored.next_id.max(next_id);
+        *self = restored;
+        Ok(())
+    }
+}
+
+fn read_id(cursor: &mut Cursor<'_>) -> Result<u32> {
+    u32::try_from(cursor.read_varint("schema id")?).map_err(|_| Error::InvalidFrame("Schema id out of range".into()))
+}
+
 impl Default for SchemaCache {
     fn default() -> Self {
         Self::new()
@@ -171,4 +387,67 @@ mod tests {
         assert_eq!(id1, id2);
         assert_eq!(cache.len(), 1);
     }
+
+    #[test]
+    fn test_cache_iter_remove_retain() {
+        let mut cache = SchemaCache::new();
+        for name in ["a", "b", "c", "d"] {
+            cache.register(Schema::new(vec![FieldDef {
+                name: name.into(),
+                field_type: FieldType::Boolean,
+                nullable: false,
+            }]));
+        }
+
+        let ids: Vec<u32> = cache.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4]);
+
+        let removed = cache.remove(2).unwrap();
+        assert!(cache.get_by_hash(removed.hash).is_none());
+        assert!(cache.remove(2).is_none());
+
+        cache.retain(|schema| schema.fields[0].name != "c");
+        let names: Vec<&str> = cache.iter().map(|s| s.fields[0].name.as_str()).collect();
+        assert_eq!(names, ["a", "d"]);
+
+        // Removed shapes come back under a fresh ID
+        let id = cache.register(removed);
+        assert_eq!(id, 5);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let schema = |name: &str| {
+            Schema::new(vec![FieldDef {
+                name: name.into(),
+                field_type: FieldType::Boolean,
+                nullable: false,
+            }])
+        };
+
+        let mut lru = SchemaCache::with_capacity(2, EvictionPolicy::Lru);
+        let mut lfu = SchemaCache::with_capacity(2, EvictionPolicy::Lfu);
+        for cache in [&mut lru, &mut lfu] {
+            let a = cache.register(schema("a"));
+            cache.touch(a);
+            let b = cache.register(schema("b"));
+            cache.register(schema("c"));
+            assert_eq!(cache.len(), 2);
+            assert_eq!(cache.evictions(), 1);
+            // LRU drops `a` (used longest ago), LFU drops `b` (used least)
+            let evicted = if cache.policy == EvictionPolicy::Lru { a } else { b };
+            assert!(cache.get(evicted).is_none());
+        }
+
+        // Inserting keeps the given ID, and new IDs continue after it
+        let mut cache = SchemaCache::new();
+        cache.insert(7, schema("x"));
+        assert_eq!(cache.get(7).unwrap().id, 7);
+        assert_eq!(cache.register(schema("y")), 8);
+        // The same shape under a new ID replaces the old entry
+        cache.insert(9, schema("x"));
+        assert!(cache.get(7).is_none());
+        assert_eq!(cache.get_by_hash(schema("x").hash).unwrap().id, 9);
+    }
 }
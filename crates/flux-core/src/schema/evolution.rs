@@ -0,0 +1,336 @@
+//! Schema evolution
+//!
+//! When a structure gains or loses a field, its inferred schema changes
+//! and the cache misses. With `FluxConfig::schema_deltas`, the new schema
+//! is sent as the changes from the previous one instead of in full, in the
+//! `SCHEMA_DELTA_KEY` metadata entry of the frame:
+//!
+//! ```text
+//! base_id (varint) | hash (u64 LE) | count (varint) | change*
+//! change: 0x00 | name_len (u8) | name | type | flags    (set field)
+//!       | 0x01 | name_len (u8) | name                   (remove field)
+//! ```
+//!
+//! `hash` is the hash of the resulting schema, so a receiver whose base
+//! differs from the sender's fails with `Error::StateDesync` instead of
+//! decoding garbage. Each evolution bumps `Schema::version`.
+
+use super::{accepts_null, read_type, write_type, FieldDef, Schema, MAX_TYPE_DEPTH};
+use crate::cursor::Cursor;
+use crate::encoding::encode_varint;
+use crate::types::{FieldType, FloatType, IntegerType};
+use crate::{Error, Result};
+
+/// Metadata key of a frame whose schema is a delta from an earlier one
+pub const SCHEMA_DELTA_KEY: &str = "flux-schema-delta";
+
+const SET_FIELD: u8 = 0x00;
+const REMOVE_FIELD: u8 = 0x01;
+
+/// How two versions of a schema relate, in terms of the messages they accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    /// Each version accepts every message the other does
+    Full,
+    /// The newer version accepts every message the older one does
+    Backward,
+    /// The older version accepts every message the newer one does
+    Forward,
+    /// Neither accepts all messages of the other
+    None,
+}
+
+impl Compatibility {
+    /// Whether messages of the older version fit the newer one
+    pub fn is_backward(self) -> bool {
+        matches!(self, Compatibility::Full | Compatibility::Backward)
+    }
+
+    /// Whether messages of the newer version fit the older one
+    pub fn is_forward(self) -> bool {
+        matches!(self, Compatibility::Full | Compatibility::Forward)
+    }
+}
+
+/// One change between schema versions
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaChange {
+    /// Add a field, or replace the field of the same name
+    SetField(FieldDef),
+    /// Remove the named field
+    RemoveField(String),
+}
+
+/// The changes turning schema `base` into another schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaDelta {
+    /// ID of the schema the changes apply to
+    pub base: u32,
+    /// Hash of the resulting schema
+    pub hash: u64,
+    pub changes: Vec<SchemaChange>,
+}
+
+impl Schema {
+    /// How `newer` relates to this schema
+    ///
+    /// Adding a nullable field is backward compatible, removing one forward
+    /// compatible; widening a type (`Int16` to `Int32`, a type to a union
+    /// including it) is backward compatible. Schemas with different roots
+    /// are never compatible.
+    pub fn compatibility(&self, newer: &Schema) -> Compatibility {
+        if self.root != newer.root {
+            return Compatibility::None;
+        }
+        let old = members(&self.fields);
+        let new = members(&newer.fields);
+        match (members_cover(&new, &old), members_cover(&old, &new)) {
+            (true, true) => Compatibility::Full,
+            (true, false) => Compatibility::Backward,
+            (false, true) => Compatibility::Forward,
+            (false, false) => Compatibility::None,
+        }
+    }
+
+    /// Changes turning this schema into `newer`, or `None` if the roots differ
+    pub fn diff(&self, newer: &Schema) -> Option<SchemaDelta> {
+        if self.root != newer.root {
+            return None;
+        }
+        let mut changes: Vec<SchemaChange> = newer
+            .fields
+            .iter()
+            .filter(|field| !self.fields.contains(field))
+            .map(|field| SchemaChange::SetField(field.clone()))
+            .collect();
+        changes.extend(
+            self.fields
+                .iter()
+                .filter(|field| !newer.fields.iter().any(|f| f.name == field.name))
+                .map(|field| SchemaChange::RemoveField(field.name.clone())),
+        );
+        Some(SchemaDelta { base: self.id, hash: newer.hash, changes })
+    }
+
+    /// Apply `delta` to this schema, giving the next version
+    ///
+    /// Fails with `Error::InvalidFrame` if a removed field doesn't exist,
+    /// and with `Error::StateDesync` if the result doesn't match the hash
+    /// the delta was made for.
+    pub fn apply_delta(&self, delta: &SchemaDelta) -> Result<Schema> {
+        let mut fields = self.fields.clone();
+        for change in &delta.changes {
+            match change {
+                SchemaChange::SetField(field) => match fields.iter_mut().find(|f| f.name == field.name) {
+                    Some(existing) => *existing = field.clone(),
+                    None => fields.push(field.clone()),
+                },
+                SchemaChange::RemoveField(name) => {
+                    let index = fields.iter().position(|f| f.name == *name).ok_or_else(|| {
+                        Error::InvalidFrame(format!("Schema delta removes unknown field '{}'", name))
+                    })?;
+                    fields.remove(index);
+                }
+            }
+        }
+        if fields.len() > u8::MAX as usize {
+            return Err(Error::InvalidFrame("Schema delta exceeds 255 fields".into()));
+        }
+
+        let mut schema = Schema::with_root(self.root, fields);
+        if schema.hash != delta.hash {
+            return Err(Error::StateDesync { expected: delta.hash, actual: schema.hash });
+        }
+        schema.version = self.version.wrapping_add(1);
+        Ok(schema)
+    }
+}
+
+impl SchemaDelta {
+    /// Serialize the delta, appending to `buf`
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        encode_varint(self.base as u64, buf);
+        buf.extend_from_slice(&self.hash.to_le_bytes());
+        encode_varint(self.changes.len() as u64, buf);
+        for change in &self.changes {
+            let (tag, name) = match change {
+                SchemaChange::SetField(field) => (SET_FIELD, &field.name),
+                SchemaChange::RemoveField(name) => (REMOVE_FIELD, name),
+            };
+            buf.push(tag);
+            buf.push(name.len() as u8);
+            buf.extend_from_slice(name.as_bytes());
+            if let SchemaChange::SetField(field) = change {
+                write_type(&field.field_type, buf);
+                buf.push(field.nullable as u8);
+            }
+        }
+    }
+
+    /// Deserialize a delta written by `serialize_into`
+    pub fn deserialize(buf: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(buf);
+        let base = u32::try_from(cursor.read_varint("schema delta base")?)
+            .map_err(|_| Error::InvalidFrame("Schema id out of range".into()))?;
+        let hash = cursor.read_u64_le("schema delta hash")?;
+
+        // Every change takes at least two bytes
+        let count = cursor.read_len("schema delta change count")?;
+        let mut changes = Vec::with_capacity(count.min(256));
+        for _ in 0..count {
+            let tag = cursor.read_u8("schema change")?;
+            let name_len = cursor.read_u8("field name length")? as usize;
+            let name = String::from_utf8_lossy(cursor.read_bytes(name_len, "field name")?).into_owned();
+            changes.push(match tag {
+                SET_FIELD => {
+                    let field_type = read_type(&mut cursor, 0, MAX_TYPE_DEPTH)?;
+                    let nullable = cursor.read_u8("field flags")? & 0x01 != 0;
+                    SchemaChange::SetField(FieldDef { name, field_type, nullable })
+                }
+                REMOVE_FIELD => SchemaChange::RemoveField(name),
+                other => return Err(Error::InvalidFrame(format!("Unknown schema change {:#04x}", other))),
+            });
+        }
+        if !cursor.is_empty() {
+            return Err(Error::InvalidFrame("Trailing bytes after schema delta".into()));
+        }
+        Ok(Self { base, hash, changes })
+    }
+}
+
+/// Fields as object members, nullable ones accepting `null`
+fn members(fields: &[FieldDef]) -> Vec<(String, FieldType)> {
+    fields
+        .iter()
+        .map(|field| {
+            let field_type = if field.nullable && !accepts_null(&field.field_type) {
+                FieldType::Union(vec![field.field_type.clone(), FieldType::Null])
+            } else {
+                field.field_type.clone()
+            };
+            (field.name.clone(), field_type)
+        })
+        .collect()
+}
+
+/// Whether every object with `narrower` members fits `wider`
+///
+/// Members missing on one side are absent, which only a nullable member
+/// of `wider` accepts.
+fn members_cover(wider: &[(String, FieldType)], narrower: &[(String, FieldType)]) -> bool {
+    let lookup = |members: &'_ [(String, FieldType)], name: &str| {
+        members.iter().find(|(n, _)| n == name).map(|(_, t)| t.clone())
+    };
+    narrower.iter().all(|(name, t)| lookup(wider, name).is_some_and(|w| covers(&w, t)))
+        && wider.iter().all(|(name, w)| lookup(narrower, name).is_some() || accepts_null(w))
+}
+
+/// Whether every value of `narrower` is a value of `wider`
+fn covers(wider: &FieldType, narrower: &FieldType) -> bool {
+    if wider == narrower {
+        return true;
+    }
+    match (wider, narrower) {
+        (_, FieldType::Union(types)) => types.iter().all(|t| covers(wider, t)),
+        (FieldType::Union(types), t) => types.iter().any(|w| covers(w, t)),
+        (FieldType::Integer(a), FieldType::Integer(b)) => {
+            let (a_min, a_max) = integer_range(*a);
+            let (b_min, b_max) = integer_range(*b);
+            a_min <= b_min && b_max <= a_max
+        }
+        (FieldType::Float(FloatType::Float64), FieldType::Float(FloatType::Float32)) => true,
+        // All of these are strings in JSON
+        (FieldType::String, FieldType::Timestamp | FieldType::Uuid | FieldType::Binary | FieldType::Decimal { .. }) => {
+            true
+        }
+        (FieldType::Array(a), FieldType::Array(b)) => covers(a, b),
+        (FieldType::Object(a), FieldType::Object(b)) => members_cover(a, b),
+        _ => false,
+    }
+}
+
+fn integer_range(int: IntegerType) -> (i128, i128) {
+    match int {
+        IntegerType::Int8 => (i8::MIN as i128, i8::MAX as i128),
+        IntegerType::Int16 => (i16::MIN as i128, i16::MAX as i128),
+        IntegerType::Int32 => (i32::MIN as i128, i32::MAX as i128),
+        IntegerType::Int64 | IntegerType::Varint => (i64::MIN as i128, i64::MAX as i128),
+        IntegerType::UInt64 => (0, u64::MAX as i128),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::RootKind;
+
+    fn schema(builder: crate::schema::SchemaBuilder) -> Schema {
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_compatibility() {
+        let v1 = schema(Schema::builder().field("id", FieldType::Integer(IntegerType::Int16)));
+        let added = schema(
+            Schema::builder()
+                .field("id", FieldType::Integer(IntegerType::Int16))
+                .nullable_field("email", FieldType::String),
+        );
+        assert_eq!(v1.compatibility(&added), Compatibility::Backward);
+        assert_eq!(added.compatibility(&v1), Compatibility::Forward);
+        assert_eq!(v1.compatibility(&v1), Compatibility::Full);
+
+        let widened = schema(Schema::builder().field("id", FieldType::Integer(IntegerType::Int32)));
+        assert!(v1.compatibility(&widened).is_backward());
+        let retyped = schema(Schema::builder().field("id", FieldType::String));
+        assert_eq!(v1.compatibility(&retyped), Compatibility::None);
+
+        // A new required field breaks both directions
+        let required = schema(
+            Schema::builder()
+                .field("id", FieldType::Integer(IntegerType::Int16))
+                .field("email", FieldType::String),
+        );
+        assert_eq!(v1.compatibility(&required), Compatibility::None);
+
+        let records = schema(Schema::builder().root(RootKind::Records).field("id", FieldType::Integer(IntegerType::Int16)));
+        assert_eq!(v1.compatibility(&records), Compatibility::None);
+    }
+
+    #[test]
+    fn test_schema_delta() {
+        let mut v1 = schema(
+            Schema::builder()
+                .field("id", FieldType::Integer(IntegerType::Int16))
+                .field("name", FieldType::String)
+                .field("legacy", FieldType::Boolean),
+        );
+        v1.id = 7;
+        let v2 = schema(
+            Schema::builder()
+                .field("id", FieldType::Integer(IntegerType::Int32))
+                .field("name", FieldType::String)
+                .nullable_field("email", FieldType::String),
+        );
+
+        let delta = v1.diff(&v2).unwrap();
+        assert_eq!(delta.base, 7);
+        assert_eq!(delta.changes.len(), 3);
+
+        let mut bytes = Vec::new();
+        delta.serialize_into(&mut bytes);
+        assert!(bytes.len() < v2.serialize().len());
+        let parsed = SchemaDelta::deserialize(&bytes).unwrap();
+        assert_eq!(parsed, delta);
+
+        let evolved = v1.apply_delta(&parsed).unwrap();
+        assert_eq!(evolved.hash, v2.hash);
+        assert_eq!(evolved.version, v1.version + 1);
+
+        // Applied to the wrong base, the hash gives it away
+        let empty = SchemaDelta { changes: vec![], ..delta.clone() };
+        assert!(matches!(v1.apply_delta(&empty), Err(Error::StateDesync { .. })));
+        let unknown = SchemaDelta { changes: vec![SchemaChange::RemoveField("nope".into())], ..delta };
+        assert!(matches!(v1.apply_delta(&unknown), Err(Error::InvalidFrame(_))));
+    }
+}
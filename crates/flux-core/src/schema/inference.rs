@@ -1,8 +1,12 @@
 //! Schema inference from JSON values
 
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
 use crate::{Error, Result};
+use crate::encoding::{self, binary, timestamp};
 use crate::types::FieldType;
-use super::{Schema, FieldDef};
+use super::{Schema, FieldDef, RootKind};
 
 /// Schema inference engine
 pub struct SchemaInferrer {
@@ -17,6 +21,14 @@ pub struct InferenceConfig {
     pub max_samples: usize,
     pub detect_timestamps: bool,
     pub detect_uuids: bool,
+    /// Store base64 strings as raw bytes (`FieldType::Binary`)
+    pub detect_base64: bool,
+    /// Keep numbers exact when merging types (see `FieldType::merge_with`)
+    pub lossless_numbers: bool,
+    /// Use every n-th record of NDJSON input (1 = every record)
+    pub sample_every: usize,
+    /// Skip NDJSON lines that are not valid JSON instead of failing
+    pub skip_invalid: bool,
 }
 
 impl Default for InferenceConfig {
@@ -25,6 +37,10 @@ impl Default for InferenceConfig {
             max_samples: 100,
             detect_timestamps: true,
             detect_uuids: true,
+            detect_base64: true,
+            lossless_numbers: false,
+            sample_every: 1,
+            skip_invalid: false,
         }
     }
 }
@@ -56,9 +72,15 @@ impl SchemaInferrer {
             None => {
                 self.current_schema = Some(inferred);
             }
+            Some(existing) if existing.root != inferred.root => {
+                return Err(Error::ParseError(format!(
+                    "Sample root {:?} does not match earlier samples ({:?})",
+                    inferred.root, existing.root
+                )));
+            }
             Some(existing) => {
                 // Merge with existing schema
-                Self::merge_schemas(existing, &inferred);
+                Self::merge_schemas(existing, &inferred, self.config.lossless_numbers);
             }
         }
 
@@ -66,6 +88,49 @@ impl SchemaInferrer {
         Ok(())
     }
 
+    /// Add the records of an NDJSON file as samples
+    ///
+    /// See `add_reader`.
+    pub fn add_ndjson_file(&mut self, path: impl AsRef<Path>) -> Result<usize> {
+        self.add_reader(std::fs::File::open(path)?)
+    }
+
+    /// Add the records of NDJSON input (one JSON value per line) as samples
+    ///
+    /// Blank lines are ignored and only every `sample_every`-th record is
+    /// used. Reading stops once `max_samples` samples have been taken.
+    /// Returns the number of samples added.
+    pub fn add_reader(&mut self, reader: impl Read) -> Result<usize> {
+        let every = self.config.sample_every.max(1);
+        let start = self.sample_count;
+        let mut record = 0usize;
+        for (line_no, line) in BufReader::new(reader).lines().enumerate() {
+            if self.sample_count >= self.config.max_samples {
+                break;
+            }
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            record += 1;
+            if !(record - 1).is_multiple_of(every) {
+                continue;
+            }
+            let value = match serde_json::from_str(&line) {
+                Ok(value) => value,
+                Err(_) if self.config.skip_invalid => continue,
+                Err(e) => return Err(Error::ParseError(format!("Line {}: {}", line_no + 1, e))),
+            };
+            self.add_value(&value)?;
+        }
+        Ok(self.sample_count - start)
+    }
+
+    /// Number of samples taken so far
+    pub fn sample_count(&self) -> usize {
+        self.sample_count
+    }
+
     /// Get the inferred schema
     pub fn infer(&self) -> Result<Schema> {
         self.current_schema
@@ -76,36 +141,53 @@ impl SchemaInferrer {
     /// Infer schema from a single value
     fn infer_from_value(&self, value: &serde_json::Value) -> Result<Schema> {
         match value {
-            serde_json::Value::Object(obj) => {
-                let fields: Vec<FieldDef> = obj
-                    .iter()
-                    .map(|(key, val)| {
-                        let field_type = self.infer_type(val);
-                        FieldDef {
-                            name: key.clone(),
-                            field_type,
-                            nullable: false, // Will be updated during merging
-                        }
-                    })
-                    .collect();
-
-                Ok(Schema::new(fields))
-            }
-            serde_json::Value::Array(arr) if !arr.is_empty() => {
-                // For array of objects, use first element
-                if let Some(serde_json::Value::Object(_)) = arr.first() {
-                    self.infer_from_value(&arr[0])
-                } else {
-                    Err(Error::ParseError("Array of primitives not supported as root".into()))
+            serde_json::Value::Object(obj) => Ok(Schema::new(self.infer_fields(obj))),
+            serde_json::Value::Array(arr) if arr.iter().all(|v| v.is_object()) && !arr.is_empty() => {
+                // Records: members missing from some elements become nullable
+                let mut schema = Schema::with_root(RootKind::Records, Vec::new());
+                for (i, obj) in arr.iter().filter_map(|v| v.as_object()).enumerate() {
+                    let record = Schema::with_root(RootKind::Records, self.infer_fields(obj));
+                    if i == 0 {
+                        schema = record;
+                    } else {
+                        Self::merge_schemas(&mut schema, &record, self.config.lossless_numbers);
+                    }
                 }
+                Ok(schema)
+            }
+            serde_json::Value::Array(arr) => {
+                // Values: nulls are recorded as absent, everything else shares one
+                // type (a union when objects are mixed with other values)
+                let field_type = arr
+                    .iter()
+                    .filter(|v| !v.is_null())
+                    .map(|v| self.infer_type(v))
+                    .reduce(|a, b| a.merge_with(&b, self.config.lossless_numbers))
+                    .unwrap_or(FieldType::Null);
+                Ok(Schema::with_root(RootKind::Values, vec![FieldDef {
+                    name: String::new(),
+                    field_type,
+                    nullable: arr.iter().any(|v| v.is_null()),
+                }]))
             }
-            _ => Err(Error::ParseError("Root must be object or array of objects".into())),
+            _ => Err(Error::ParseError("Root must be object or array".into())),
         }
     }
 
+    /// Field definitions for the members of an object
+    fn infer_fields(&self, obj: &serde_json::Map<String, serde_json::Value>) -> Vec<FieldDef> {
+        obj.iter()
+            .map(|(key, val)| FieldDef {
+                name: key.clone(),
+                field_type: self.infer_type(val),
+                nullable: false, // Will be updated during merging
+            })
+            .collect()
+    }
+
     /// Infer type from a value
     fn infer_type(&self, value: &serde_json::Value) -> FieldType {
-        let base_type = FieldType::infer(value);
+        let base_type = FieldType::infer_with(value, self.config.lossless_numbers);
 
         // Enhanced detection
         if self.config.detect_timestamps {
@@ -124,44 +206,41 @@ impl SchemaInferrer {
             }
         }
 
+        if self.config.detect_base64 {
+            if let serde_json::Value::String(s) = value {
+                if Self::looks_like_base64(s) {
+                    return FieldType::Binary;
+                }
+            }
+        }
+
         base_type
     }
 
     /// Check if string looks like a timestamp
     fn looks_like_timestamp(s: &str) -> bool {
-        // ISO 8601 format
-        if s.len() >= 10 && s.len() <= 30 {
-            let chars: Vec<char> = s.chars().collect();
-            if chars.len() >= 10
-                && chars[4] == '-'
-                && chars[7] == '-'
-                && chars[0].is_ascii_digit()
-            {
-                return true;
-            }
-        }
-        false
+        // ISO 8601 dates and date-times the encoder can store exactly
+        timestamp::Timestamp::parse(s).is_some()
     }
 
-    /// Check if string looks like a UUID
+    /// Check if string is a UUID the encoder can store as bytes
     fn looks_like_uuid(s: &str) -> bool {
-        if s.len() == 36 {
-            let parts: Vec<&str> = s.split('-').collect();
-            if parts.len() == 5
-                && parts[0].len() == 8
-                && parts[1].len() == 4
-                && parts[2].len() == 4
-                && parts[3].len() == 4
-                && parts[4].len() == 12
-            {
-                return parts.iter().all(|p| p.chars().all(|c| c.is_ascii_hexdigit()));
-            }
-        }
-        false
+        encoding::parse_uuid(s).is_some()
+    }
+
+    /// Check if string looks like base64 that can be stored as raw bytes
+    ///
+    /// Random base64 mixes cases; requiring both keeps runs like `aaaa...`
+    /// and upper-case identifiers as strings.
+    fn looks_like_base64(s: &str) -> bool {
+        s.len() >= binary::MIN_BASE64_LEN
+            && s.bytes().any(|b| b.is_ascii_uppercase())
+            && s.bytes().any(|b| b.is_ascii_lowercase())
+            && binary::detect(s).is_some()
     }
 
     /// Merge two schemas
-    fn merge_schemas(existing: &mut Schema, new: &Schema) {
+    fn merge_schemas(existing: &mut Schema, new: &Schema, lossless: bool) {
         // Track which fields exist in new schema
         let _new_fields: std::collections::HashSet<&str> =
             new.fields.iter().map(|f| f.name.as_str()).collect();
@@ -170,7 +249,8 @@ impl SchemaInferrer {
         for field in &mut existing.fields {
             if let Some(new_field) = new.fields.iter().find(|f| f.name == field.name) {
                 // Merge types
-                field.field_type = field.field_type.merge(&new_field.field_type);
+                field.field_type = field.field_type.merge_with(&new_field.field_type, lossless);
+                field.nullable |= new_field.nullable;
             } else {
                 // Field missing in new schema - make nullable
                 field.nullable = true;
@@ -187,7 +267,8 @@ impl SchemaInferrer {
         }
 
         // Recompute hash
-        existing.hash = Schema::compute_hash(&existing.fields);
+        Schema::canonicalize(&mut existing.fields);
+        existing.hash = Schema::compute_hash(existing.root, &existing.fields);
     }
 }
 
@@ -238,11 +319,90 @@ mod tests {
         assert!(email_field.nullable);
     }
 
+    #[test]
+    fn test_infer_root_arrays() {
+        let mut inferrer = SchemaInferrer::new();
+        inferrer
+            .add_value(&serde_json::json!([{"id": 1}, {"id": 300, "name": "bob"}]))
+            .unwrap();
+        let schema = inferrer.infer().unwrap();
+        assert_eq!(schema.root, RootKind::Records);
+        assert_eq!(schema.fields[0].field_type, FieldType::Integer(crate::types::IntegerType::Int16));
+        assert!(schema.fields[1].nullable);
+
+        let mut inferrer = SchemaInferrer::new();
+        inferrer.add_value(&serde_json::json!([1, null, 2.5])).unwrap();
+        let schema = inferrer.infer().unwrap();
+        assert_eq!(schema.root, RootKind::Values);
+        assert!(schema.fields[0].nullable);
+        assert_eq!(schema.fields[0].field_type, FieldType::Float(crate::types::FloatType::Float64));
+
+        // Samples must agree on the root shape
+        assert!(inferrer.add_value(&serde_json::json!({"id": 1})).is_err());
+
+        // Objects mixed with other values share a union
+        let mut inferrer = SchemaInferrer::new();
+        inferrer.add_value(&serde_json::json!([{"id": 1}, 2])).unwrap();
+        let schema = inferrer.infer().unwrap();
+        assert_eq!(schema.root, RootKind::Values);
+        assert!(matches!(schema.fields[0].field_type, FieldType::Union(_)));
+    }
+
+    #[test]
+    fn test_add_reader() {
+        let ndjson = (0..20)
+            .map(|i| match i {
+                5 => String::new(),
+                7 => "{not json".to_string(),
+                _ if i % 4 == 0 => format!(r#"{{"id": {}, "tag": "t{}"}}"#, i, i),
+                _ => format!(r#"{{"id": {}}}"#, i),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Invalid lines fail with their line number
+        let err = SchemaInferrer::new().add_reader(ndjson.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("Line 8"), "{}", err);
+
+        let config = InferenceConfig { skip_invalid: true, ..InferenceConfig::default() };
+        let mut inferrer = SchemaInferrer::with_config(config);
+        assert_eq!(inferrer.add_reader(ndjson.as_bytes()).unwrap(), 18);
+        let schema = inferrer.infer().unwrap();
+        assert_eq!(schema.fields.len(), 2);
+        assert!(schema.fields.iter().find(|f| f.name == "tag").unwrap().nullable);
+
+        // Every third record, at most four: lines 1, 4, 11 and 14 (line 6 is blank, line 8 invalid)
+        let config = InferenceConfig { sample_every: 3, max_samples: 4, skip_invalid: true, ..InferenceConfig::default() };
+        let mut inferrer = SchemaInferrer::with_config(config);
+        assert_eq!(inferrer.add_reader(ndjson.as_bytes()).unwrap(), 4);
+        assert_eq!(inferrer.sample_count(), 4);
+
+        let path = std::env::temp_dir().join(format!("flux-infer-{}.ndjson", std::process::id()));
+        std::fs::write(&path, &ndjson).unwrap();
+        let mut inferrer = SchemaInferrer::with_config(InferenceConfig { skip_invalid: true, ..InferenceConfig::default() });
+        assert_eq!(inferrer.add_ndjson_file(&path).unwrap(), 18);
+        std::fs::remove_file(&path).unwrap();
+        let restored = Schema::deserialize(&inferrer.infer().unwrap().serialize()).unwrap();
+        assert_eq!(restored.hash, schema.hash);
+        assert!(matches!(SchemaInferrer::new().add_ndjson_file(&path), Err(Error::Io(_))));
+    }
+
     #[test]
     fn test_detect_timestamp() {
         assert!(SchemaInferrer::looks_like_timestamp("2024-01-15T10:30:00Z"));
         assert!(SchemaInferrer::looks_like_timestamp("2024-01-15"));
+        assert!(SchemaInferrer::looks_like_timestamp("2024-01-15T10:30:00.123456+02:00"));
         assert!(!SchemaInferrer::looks_like_timestamp("hello world"));
+        assert!(!SchemaInferrer::looks_like_timestamp("2024-01-15 is a Monday"));
+    }
+
+    #[test]
+    fn test_detect_base64() {
+        assert!(SchemaInferrer::looks_like_base64("iVBORw0KGgoAAAANSUhEUgAAAAEAAAAB"));
+        assert!(SchemaInferrer::looks_like_base64("c29tZSBtb3JlIGJ5dGVz"));
+        assert!(!SchemaInferrer::looks_like_base64("aGVsbG8="));
+        assert!(!SchemaInferrer::looks_like_base64(&"a".repeat(64)));
+        assert!(!SchemaInferrer::looks_like_base64("hello world, this is text"));
     }
 
     #[test]
@@ -0,0 +1,531 @@
+//! JSON Schema (draft 2020-12) import and export
+//!
+//! Lets a schema come from an existing OpenAPI or JSON Schema definition
+//! instead of inference, and hands FLUX schemas to JSON Schema validators.
+//!
+//! | FLUX type              | JSON Schema                                             |
+//! |------------------------|---------------------------------------------------------|
+//! | `Integer(Int8..Int64)` | `integer` with the type's `minimum`/`maximum`           |
+//! | `Integer(Varint)`      | `integer` without bounds                                |
+//! | `Integer(UInt64)`      | `integer`, `minimum: 0`, `maximum: u64::MAX`            |
+//! | `Float(Float32)`       | `number`, `format: float`                               |
+//! | `Float(Float64)`       | `number`                                                |
+//! | `Timestamp`            | `string`, `format: date-time` (or `date` on import)     |
+//! | `Uuid`                 | `string`, `format: uuid`                                |
+//! | `Binary`               | `string`, `contentEncoding: base64` (or `format: byte`) |
+//! | `Decimal`              | `string`, `format: decimal`, `x-precision`, `x-scale`   |
+//! | `Union`                | `anyOf`                                                 |
+//!
+//! On import, an integer gets the smallest type its bounds fit, and an
+//! optional member (not in `required`) becomes nullable. `$ref` is followed
+//! within the document; `allOf`, free-form objects and tuple arrays have no
+//! FLUX equivalent and fail with `Error::UnsupportedType`.
+
+use serde_json::{json, Map, Value};
+
+use super::{accepts_null, FieldDef, RootKind, Schema, SchemaBuilder};
+use crate::config::DEFAULT_MAX_DEPTH;
+use crate::types::{FieldType, FloatType, IntegerType};
+use crate::{Error, Result};
+
+/// Dialect written to `$schema`
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+impl Schema {
+    /// Describe this schema as a JSON Schema (draft 2020-12) document
+    ///
+    /// Nullable fields are optional and also accept `null`.
+    pub fn to_json_schema(&self) -> Value {
+        let mut doc = match self.root {
+            RootKind::Object => object_schema(&self.fields),
+            RootKind::Records => json!({ "type": "array", "items": object_schema(&self.fields) }),
+            RootKind::Values => {
+                let items = self.fields.first().map_or(json!({ "type": "null" }), field_schema);
+                json!({ "type": "array", "items": items })
+            }
+        };
+        let mut with_dialect = Map::new();
+        with_dialect.insert("$schema".into(), DIALECT.into());
+        if let Value::Object(obj) = &mut doc {
+            with_dialect.append(obj);
+        }
+        Value::Object(with_dialect)
+    }
+
+    /// Build a schema from a JSON Schema document
+    ///
+    /// The root must describe an object, an array of objects (`Records`) or
+    /// an array of other values (`Values`). Malformed JSON fails with
+    /// `Error::ParseError`, and constructs FLUX can't represent with
+    /// `Error::UnsupportedType`.
+    pub fn from_json_schema(json: &str) -> Result<Schema> {
+        let doc: Value = serde_json::from_str(json)
+            .map_err(|e| Error::ParseError(format!("Invalid JSON Schema: {}", e)))?;
+        let importer = Importer { doc: &doc };
+
+        let root = importer.resolve(&doc)?;
+        let builder = match type_names(root)?.as_slice() {
+            ["object"] => SchemaBuilder { root: RootKind::Object, fields: importer.fields(root, 0)? },
+            ["array"] => {
+                let items = importer.resolve(items(root)?)?;
+                if type_names(items)? == ["object"] {
+                    SchemaBuilder { root: RootKind::Records, fields: importer.fields(items, 0)? }
+                } else {
+                    let field_type = importer.field_type(items, 0)?;
+                    SchemaBuilder {
+                        root: RootKind::Values,
+                        fields: vec![FieldDef {
+                            name: String::new(),
+                            nullable: accepts_null(&field_type),
+                            field_type: without_null(field_type),
+                        }],
+                    }
+                }
+            }
+            _ => return Err(Error::UnsupportedType("JSON Schema root must be an object or array".into())),
+        };
+        builder.build()
+    }
+}
+
+/// Schema for an object with members `fields`
+fn object_schema(fields: &[FieldDef]) -> Value {
+    let properties: Map<String, Value> =
+        fields.iter().map(|field| (field.name.clone(), field_schema(field))).collect();
+    let required: Vec<&str> = fields
+        .iter()
+        .filter(|field| !field.nullable && !accepts_null(&field.field_type))
+        .map(|field| field.name.as_str())
+        .collect();
+    object(properties, required)
+}
+
+fn field_schema(field: &FieldDef) -> Value {
+    if field.nullable && !accepts_null(&field.field_type) {
+        type_schema(&FieldType::Union(vec![field.field_type.clone(), FieldType::Null]))
+    } else {
+        type_schema(&field.field_type)
+    }
+}
+
+fn object(properties: Map<String, Value>, required: Vec<&str>) -> Value {
+    let mut obj = Map::new();
+    obj.insert("type".into(), "object".into());
+    obj.insert("properties".into(), Value::Object(properties));
+    if !required.is_empty() {
+        obj.insert("required".into(), required.into());
+    }
+    obj.insert("additionalProperties".into(), false.into());
+    Value::Object(obj)
+}
+
+fn type_schema(field_type: &FieldType) -> Value {
+    match field_type {
+        FieldType::Null => json!({ "type": "null" }),
+        FieldType::Boolean => json!({ "type": "boolean" }),
+        FieldType::Integer(IntegerType::Int8) => integer(i8::MIN as i64, i8::MAX as u64),
+        FieldType::Integer(IntegerType::Int16) => integer(i16::MIN as i64, i16::MAX as u64),
+        FieldType::Integer(IntegerType::Int32) => integer(i32::MIN as i64, i32::MAX as u64),
+        FieldType::Integer(IntegerType::Int64) => integer(i64::MIN, i64::MAX as u64),
+        FieldType::Integer(IntegerType::UInt64) => integer(0, u64::MAX),
+        FieldType::Integer(IntegerType::Varint) => json!({ "type": "integer" }),
+        FieldType::Float(FloatType::Float32) => json!({ "type": "number", "format": "float" }),
+        FieldType::Float(FloatType::Float64) => json!({ "type": "number" }),
+        FieldType::String => json!({ "type": "string" }),
+        FieldType::Binary => json!({ "type": "string", "contentEncoding": "base64" }),
+        FieldType::Timestamp => json!({ "type": "string", "format": "date-time" }),
+        FieldType::Uuid => json!({ "type": "string", "format": "uuid" }),
+        FieldType::Decimal { precision, scale } => json!({
+            "type": "string",
+            "format": "decimal",
+            "x-precision": precision,
+            "x-scale": scale,
+        }),
+        FieldType::Array(elem) => json!({ "type": "array", "items": type_schema(elem) }),
+        FieldType::Object(members) => {
+            let properties = members.iter().map(|(name, t)| (name.clone(), type_schema(t))).collect();
+            let required = members
+                .iter()
+                .filter(|(_, t)| !accepts_null(t))
+                .map(|(name, _)| name.as_str())
+                .collect();
+            object(properties, required)
+        }
+        FieldType::Union(types) => json!({ "anyOf": types.iter().map(type_schema).collect::<Vec<_>>() }),
+    }
+}
+
+fn integer(min: i64, max: u64) -> Value {
+    json!({ "type": "integer", "minimum": min, "maximum": max })
+}
+
+/// `field_type` with any `Null` variant removed
+fn without_null(field_type: FieldType) -> FieldType {
+    match field_type {
+        FieldType::Union(types) => union(types.into_iter().filter(|t| *t != FieldType::Null).collect()),
+        t => t,
+    }
+}
+
+/// Union of `types`, flattened and deduplicated
+fn union(types: Vec<FieldType>) -> FieldType {
+    let mut variants = Vec::new();
+    for t in types {
+        let nested = match t {
+            FieldType::Union(nested) => nested,
+            t => vec![t],
+        };
+        for t in nested {
+            if !variants.contains(&t) {
+                variants.push(t);
+            }
+        }
+    }
+    match variants.len() {
+        0 => FieldType::Null,
+        1 => variants.remove(0),
+        _ => FieldType::Union(variants),
+    }
+}
+
+/// The `type` keyword as a list of names
+fn type_names(schema: &Map<String, Value>) -> Result<Vec<&str>> {
+    match schema.get("type") {
+        Some(Value::String(name)) => Ok(vec![name.as_str()]),
+        Some(Value::Array(names)) => names
+            .iter()
+            .map(|name| name.as_str().ok_or_else(|| Error::ParseError("`type` entries must be strings".into())))
+            .collect(),
+        Some(_) => Err(Error::ParseError("`type` must be a string or an array".into())),
+        // `properties` alone implies an object
+        None if schema.contains_key("properties") => Ok(vec!["object"]),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn items(schema: &Map<String, Value>) -> Result<&Value> {
+    if schema.contains_key("prefixItems") {
+        return Err(Error::UnsupportedType("tuple arrays (`prefixItems`)".into()));
+    }
+    schema
+        .get("items")
+        .ok_or_else(|| Error::UnsupportedType("array schema without `items`".into()))
+}
+
+/// Integer bound from `key` and its exclusive form, rounded inwards
+fn bound(schema: &Map<String, Value>, key: &str, exclusive_key: &str, lower: bool) -> Option<i128> {
+    let read = |key: &str, exclusive: bool| {
+        let value = schema.get(key)?;
+        let bound = match (value.as_i64(), value.as_u64()) {
+            (Some(v), _) => v as i128 + exclusive as i128 * if lower { 1 } else { -1 },
+            (_, Some(v)) => v as i128 - exclusive as i128,
+            _ => {
+                let v = value.as_f64()?;
+                match (lower, exclusive) {
+                    (true, false) => v.ceil() as i128,
+                    (true, true) => v.floor() as i128 + 1,
+                    (false, false) => v.floor() as i128,
+                    (false, true) => v.ceil() as i128 - 1,
+                }
+            }
+        };
+        Some(bound)
+    };
+    match (read(key, false), read(exclusive_key, true)) {
+        (Some(a), Some(b)) => Some(if lower { a.max(b) } else { a.min(b) }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Smallest integer type holding the schema's range
+fn integer_type(schema: &Map<String, Value>) -> FieldType {
+    let fits = |lo: i128, hi: i128, min: i128, max: i128| lo >= min && hi <= max;
+    let min = bound(schema, "minimum", "exclusiveMinimum", true);
+    let max = bound(schema, "maximum", "exclusiveMaximum", false);
+    let int = match (min, max) {
+        (Some(lo), Some(hi)) if fits(lo, hi, i8::MIN as i128, i8::MAX as i128) => IntegerType::Int8,
+        (Some(lo), Some(hi)) if fits(lo, hi, i16::MIN as i128, i16::MAX as i128) => IntegerType::Int16,
+        (Some(lo), Some(hi)) if fits(lo, hi, i32::MIN as i128, i32::MAX as i128) => IntegerType::Int32,
+        (Some(lo), Some(hi)) if fits(lo, hi, i64::MIN as i128, i64::MAX as i128) => IntegerType::Int64,
+        (Some(lo), Some(hi)) if fits(lo, hi, 0, u64::MAX as i128) => IntegerType::UInt64,
+        // Signed and above `i64::MAX`: no single type covers the range
+        (Some(_), Some(_)) => {
+            return FieldType::Union(vec![
+                FieldType::Integer(IntegerType::Int64),
+                FieldType::Integer(IntegerType::UInt64),
+            ])
+        }
+        // OpenAPI states the width as a format instead
+        _ => match schema.get("format").and_then(Value::as_str) {
+            Some("int32") => IntegerType::Int32,
+            Some("int64") => IntegerType::Int64,
+            _ => IntegerType::Varint,
+        },
+    };
+    FieldType::Integer(int)
+}
+
+fn string_type(schema: &Map<String, Value>) -> Result<FieldType> {
+    let format = schema.get("format").and_then(Value::as_str);
+    if schema.get("contentEncoding").and_then(Value::as_str) == Some("base64") || format == Some("byte") {
+        return Ok(FieldType::Binary);
+    }
+    let digits = |key: &str| match schema.get(key) {
+        None => Ok(None),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u8::try_from(v).ok())
+            .map(Some)
+            .ok_or_else(|| Error::ParseError(format!("`{}` must be an integer up to 255", key))),
+    };
+    Ok(match format {
+        Some("date-time") | Some("date") => FieldType::Timestamp,
+        Some("uuid") => FieldType::Uuid,
+        Some("decimal") => FieldType::Decimal {
+            precision: digits("x-precision")?.unwrap_or(38),
+            scale: digits("x-scale")?.unwrap_or(0),
+        },
+        _ => FieldType::String,
+    })
+}
+
+/// Walks one JSON Schema document, resolving references against it
+struct Importer<'a> {
+    doc: &'a Value,
+}
+
+impl<'a> Importer<'a> {
+    /// Follow `$ref`s to the schema object they name
+    fn resolve(&self, mut schema: &'a Value) -> Result<&'a Map<String, Value>> {
+        for _ in 0..DEFAULT_MAX_DEPTH {
+            let obj = match schema {
+                Value::Object(obj) => obj,
+                Value::Bool(_) => return Err(Error::UnsupportedType("boolean schemas".into())),
+                _ => return Err(Error::ParseError("A schema must be an object".into())),
+            };
+            let Some(reference) = obj.get("$ref") else {
+                return Ok(obj);
+            };
+            let reference = reference
+                .as_str()
+                .ok_or_else(|| Error::ParseError("`$ref` must be a string".into()))?;
+            let pointer = reference
+                .strip_prefix('#')
+                .ok_or_else(|| Error::UnsupportedType(format!("external $ref '{}'", reference)))?;
+            schema = self
+                .doc
+                .pointer(pointer)
+                .ok_or_else(|| Error::ParseError(format!("Unresolved $ref '{}'", reference)))?;
+        }
+        Err(Error::ParseError("Too many chained $refs".into()))
+    }
+
+    /// Fields of an object schema; optional members are nullable
+    fn fields(&self, schema: &'a Map<String, Value>, depth: usize) -> Result<Vec<FieldDef>> {
+        self.members(schema, depth)?
+            .into_iter()
+            .map(|(name, field_type, required)| {
+                let nullable = !required;
+                let field_type = if nullable { without_null(field_type) } else { field_type };
+                Ok(FieldDef { name, field_type, nullable })
+            })
+            .collect()
+    }
+
+    /// `(name, type, required)` for each property
+    fn members(&self, schema: &'a Map<String, Value>, depth: usize) -> Result<Vec<(String, FieldType, bool)>> {
+        let properties = match schema.get("properties") {
+            Some(Value::Object(properties)) => properties,
+            Some(_) => return Err(Error::ParseError("`properties` must be an object".into())),
+            None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => return Ok(Vec::new()),
+            None => return Err(Error::UnsupportedType("object schema without `properties`".into())),
+        };
+        let required: Vec<&str> = match schema.get("required") {
+            Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+            Some(_) => return Err(Error::ParseError("`required` must be an array".into())),
+            None => Vec::new(),
+        };
+        properties
+            .iter()
+            .map(|(name, property)| {
+                let field_type = self.field_type(self.resolve(property)?, depth + 1)?;
+                Ok((name.clone(), field_type, required.contains(&name.as_str())))
+            })
+            .collect()
+    }
+
+    fn field_type(&self, schema: &'a Map<String, Value>, depth: usize) -> Result<FieldType> {
+        if depth > DEFAULT_MAX_DEPTH {
+            return Err(Error::UnsupportedType(format!("schemas nested deeper than {}", DEFAULT_MAX_DEPTH)));
+        }
+        if schema.contains_key("allOf") {
+            return Err(Error::UnsupportedType("`allOf`".into()));
+        }
+
+        let field_type = if let Some(variants) = schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+            let variants = variants
+                .as_array()
+                .ok_or_else(|| Error::ParseError("`anyOf`/`oneOf` must be an array".into()))?;
+            let types = variants
+                .iter()
+                .map(|variant| self.field_type(self.resolve(variant)?, depth + 1))
+                .collect::<Result<Vec<_>>>()?;
+            union(types)
+        } else if let Some(value) = schema.get("const") {
+            FieldType::infer(value)
+        } else if let Some(values) = schema.get("enum") {
+            values
+                .as_array()
+                .and_then(|values| values.iter().map(FieldType::infer).reduce(|a, b| a.merge(&b)))
+                .ok_or_else(|| Error::ParseError("`enum` must be a non-empty array".into()))?
+        } else {
+            let names = type_names(schema)?;
+            if names.is_empty() {
+                return Err(Error::UnsupportedType("schema without a `type`".into()));
+            }
+            let types = names
+                .into_iter()
+                .map(|name| self.named_type(name, schema, depth))
+                .collect::<Result<Vec<_>>>()?;
+            union(types)
+        };
+
+        // OpenAPI 3.0 marks nullable types with a keyword
+        if schema.get("nullable") == Some(&Value::Bool(true)) {
+            return Ok(union(vec![field_type, FieldType::Null]));
+        }
+        Ok(field_type)
+    }
+
+    fn named_type(&self, name: &str, schema: &'a Map<String, Value>, depth: usize) -> Result<FieldType> {
+        Ok(match name {
+            "null" => FieldType::Null,
+            "boolean" => FieldType::Boolean,
+            "integer" => integer_type(schema),
+            "number" => match schema.get("format").and_then(Value::as_str) {
+                Some("float") => FieldType::Float(FloatType::Float32),
+                _ => FieldType::Float(FloatType::Float64),
+            },
+            "string" => string_type(schema)?,
+            "array" => FieldType::Array(Box::new(self.field_type(self.resolve(items(schema)?)?, depth + 1)?)),
+            "object" => {
+                let members = self
+                    .members(schema, depth)?
+                    .into_iter()
+                    .map(|(name, t, required)| {
+                        // Missing members decode as null
+                        let t = if required || accepts_null(&t) { t } else { union(vec![t, FieldType::Null]) };
+                        (name, t)
+                    })
+                    .collect();
+                FieldType::Object(members)
+            }
+            other => return Err(Error::ParseError(format!("Unknown type '{}'", other))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(schema: &Schema) -> Schema {
+        Schema::from_json_schema(&schema.to_json_schema().to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_json_schema_roundtrip() {
+        let nested = FieldType::Object(vec![
+            ("at".into(), FieldType::Timestamp),
+            ("note".into(), FieldType::Union(vec![FieldType::String, FieldType::Null])),
+        ]);
+        let schema = Schema::builder()
+            .field("blob", FieldType::Binary)
+            .field("count", FieldType::Integer(IntegerType::Varint))
+            .field("flag", FieldType::Boolean)
+            .field("id", FieldType::Uuid)
+            .field("big", FieldType::Integer(IntegerType::UInt64))
+            .field("price", FieldType::Decimal { precision: 10, scale: 2 })
+            .field("ratio", FieldType::Float(FloatType::Float32))
+            .field("score", FieldType::Union(vec![FieldType::Integer(IntegerType::Int16), FieldType::String]))
+            .field("small", FieldType::Integer(IntegerType::Int8))
+            .field("events", FieldType::Array(Box::new(nested)))
+            .nullable_field("email", FieldType::String)
+            .nullable_field("total", FieldType::Float(FloatType::Float64))
+            .build()
+            .unwrap();
+
+        let doc = schema.to_json_schema();
+        assert_eq!(doc["$schema"], DIALECT);
+        assert_eq!(doc["properties"]["small"], json!({ "type": "integer", "minimum": -128, "maximum": 127 }));
+        assert!(!doc["required"].as_array().unwrap().contains(&json!("email")));
+
+        let imported = roundtrip(&schema);
+        assert_eq!(imported.hash, schema.hash);
+
+        let records = Schema::builder().root(RootKind::Records).field("id", FieldType::Uuid).build().unwrap();
+        assert_eq!(roundtrip(&records).hash, records.hash);
+        let values = Schema::builder()
+            .root(RootKind::Values)
+            .nullable_field("", FieldType::Integer(IntegerType::Int32))
+            .build()
+            .unwrap();
+        assert_eq!(roundtrip(&values).hash, values.hash);
+    }
+
+    #[test]
+    fn test_json_schema_import() {
+        let schema = Schema::from_json_schema(
+            r##"{
+                "$defs": {
+                    "Address": {
+                        "type": "object",
+                        "properties": { "city": { "type": "string" }, "zip": { "type": "string" } },
+                        "required": ["city"]
+                    }
+                },
+                "type": "object",
+                "properties": {
+                    "address": { "$ref": "#/$defs/Address" },
+                    "age": { "type": "integer", "minimum": 0, "exclusiveMaximum": 200 },
+                    "id": { "type": "integer", "format": "int64" },
+                    "role": { "enum": ["admin", "user"] },
+                    "seen": { "type": ["string", "null"], "format": "date-time" },
+                    "tags": { "type": "array", "items": { "type": "string" }, "nullable": true }
+                },
+                "required": ["address", "age", "id", "role", "seen"]
+            }"##,
+        )
+        .unwrap();
+
+        let field = |name: &str| schema.fields.iter().find(|f| f.name == name).unwrap();
+        assert_eq!(
+            field("address").field_type,
+            FieldType::Object(vec![
+                ("city".into(), FieldType::String),
+                ("zip".into(), FieldType::Union(vec![FieldType::String, FieldType::Null])),
+            ])
+        );
+        assert_eq!(field("age").field_type, FieldType::Integer(IntegerType::Int16));
+        assert_eq!(field("id").field_type, FieldType::Integer(IntegerType::Int64));
+        assert_eq!(field("role").field_type, FieldType::String);
+        assert_eq!(field("seen").field_type, FieldType::Union(vec![FieldType::Timestamp, FieldType::Null]));
+        assert!(!field("seen").nullable);
+        assert_eq!(field("tags").field_type, FieldType::Array(Box::new(FieldType::String)));
+        assert!(field("tags").nullable);
+    }
+
+    #[test]
+    fn test_json_schema_unsupported() {
+        assert!(matches!(Schema::from_json_schema("{"), Err(Error::ParseError(_))));
+        for doc in [
+            r#"{"type": "string"}"#,
+            r#"{"type": "object", "properties": {"a": {"allOf": [{"type": "string"}]}}}"#,
+            r#"{"type": "object", "properties": {"a": {"type": "object"}}}"#,
+            r#"{"type": "object", "properties": {"a": {"$ref": "other.json#/A"}}}"#,
+            r##"{"$defs": {"N": {"type": "object", "properties": {"next": {"$ref": "#/$defs/N"}}}}, "$ref": "#/$defs/N"}"##,
+        ] {
+            assert!(matches!(Schema::from_json_schema(doc), Err(Error::UnsupportedType(_))), "{}", doc);
+        }
+    }
+}
@@ -2,12 +2,30 @@
 
 mod inference;
 mod cache;
+mod evolution;
+mod json_schema;
 
-pub use inference::SchemaInferrer;
-pub use cache::SchemaCache;
+pub use inference::{InferenceConfig, SchemaInferrer};
+pub use cache::{EvictionPolicy, SchemaCache};
+pub use evolution::{Compatibility, SchemaChange, SchemaDelta, SCHEMA_DELTA_KEY};
 
 use crate::{Error, Result};
-use crate::types::FieldType;
+use crate::cursor::Cursor;
+use crate::encoding::encode_varint;
+use crate::types::{type_id, FieldType, FloatType, IntegerType};
+
+/// Maximum nesting of type descriptors accepted by `Schema::deserialize`
+pub(crate) const MAX_TYPE_DEPTH: usize = type_depth_limit(crate::config::MAX_DEPTH);
+
+/// Type descriptor nesting that values nested `max_depth` deep can need: a
+/// union inside each object or array level, and one around the top
+pub(crate) const fn type_depth_limit(max_depth: usize) -> usize {
+    2 * max_depth + 1
+}
+
+/// Bytes of a serialized schema before its field count: ID (u32),
+/// version (u16) and hash (u64)
+pub const SCHEMA_HEADER_SIZE: usize = 14;
 
 /// Schema definition
 #[derive(Debug, Clone)]
@@ -16,10 +34,86 @@ pub struct Schema {
     pub version: u16,
     pub hash: u64,
     pub fields: Vec<FieldDef>,
+    /// Shape of the top-level value
+    pub root: RootKind,
+}
+
+/// Shape of the top-level JSON value a schema describes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RootKind {
+    /// An object whose members are `fields`
+    #[default]
+    Object,
+    /// An array of objects, each with members from `fields`
+    Records,
+    /// An array of values, typed by the single unnamed field
+    Values,
+}
+
+impl RootKind {
+    /// Trailing schema byte; object schemas omit it
+    fn tag(self) -> u8 {
+        match self {
+            RootKind::Object => 0,
+            RootKind::Records => 1,
+            RootKind::Values => 2,
+        }
+    }
+}
+
+/// Builder for hand-written schemas
+#[derive(Debug, Clone, Default)]
+pub struct SchemaBuilder {
+    root: RootKind,
+    fields: Vec<FieldDef>,
+}
+
+impl SchemaBuilder {
+    /// Shape of the top-level value (default `RootKind::Object`)
+    pub fn root(mut self, root: RootKind) -> Self {
+        self.root = root;
+        self
+    }
+
+    /// Add a required field
+    pub fn field(mut self, name: impl Into<String>, field_type: FieldType) -> Self {
+        self.fields.push(FieldDef { name: name.into(), field_type, nullable: false });
+        self
+    }
+
+    /// Add a field that may be absent or `null`
+    pub fn nullable_field(mut self, name: impl Into<String>, field_type: FieldType) -> Self {
+        self.fields.push(FieldDef { name: name.into(), field_type, nullable: true });
+        self
+    }
+
+    /// Validate and return the schema, in canonical field order
+    ///
+    /// Fails with `Error::InvalidConfig` for schemas the wire format can't
+    /// carry: more than 255 fields, longer names, duplicate names, or a
+    /// `Values` root without exactly one field.
+    pub fn build(self) -> Result<Schema> {
+        let invalid = |reason: String| Error::InvalidConfig { field: "schema", reason };
+        if self.fields.len() > u8::MAX as usize {
+            return Err(invalid(format!("{} fields, at most 255 are supported", self.fields.len())));
+        }
+        if self.root == RootKind::Values && self.fields.len() != 1 {
+            return Err(invalid("a Values root takes exactly one field".into()));
+        }
+        let mut names: Vec<&str> = self.fields.iter().map(|field| field.name.as_str()).collect();
+        names.sort_unstable();
+        if let Some(name) = names.iter().find(|name| name.len() > u8::MAX as usize) {
+            return Err(invalid(format!("field name '{}' is longer than 255 bytes", name)));
+        }
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(invalid(format!("duplicate field '{}'", pair[0])));
+        }
+        Ok(Schema::with_root(self.root, self.fields))
+    }
 }
 
 /// Field definition
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FieldDef {
     pub name: String,
     pub field_type: FieldType,
@@ -28,29 +122,88 @@ pub struct FieldDef {
 
 impl Schema {
     /// Create a new schema with auto-generated ID
+    ///
+    /// Fields (and the members of nested objects) are put in canonical,
+    /// name-sorted order, which is the order they are encoded in.
     pub fn new(fields: Vec<FieldDef>) -> Self {
-        let hash = Self::compute_hash(&fields);
+        Self::with_root(RootKind::Object, fields)
+    }
+
+    /// Create a schema for a top-level value of shape `root`
+    pub fn with_root(root: RootKind, mut fields: Vec<FieldDef>) -> Self {
+        Self::canonicalize(&mut fields);
+        let hash = Self::compute_hash(root, &fields);
         Self {
             id: 0,
             version: 1,
             hash,
             fields,
+            root,
+        }
+    }
+
+    /// Start building a schema by hand, e.g. for `FluxSession::compress_with_schema`
+    ///
+    /// ```rust,ignore
+    /// let schema = Schema::builder()
+    ///     .field("id", FieldType::Integer(IntegerType::Varint))
+    ///     .nullable_field("email", FieldType::String)
+    ///     .build()?;
+    /// ```
+    pub fn builder() -> SchemaBuilder {
+        SchemaBuilder::default()
+    }
+
+    /// This schema with every `Uuid` type widened to a `Uuid | String` union
+    ///
+    /// Canonical UUIDs still take 16 bytes; other text (uppercase, braced,
+    /// ...) is sent as a string and comes back unchanged.
+    pub(crate) fn with_uuid_text(&self) -> Schema {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| FieldDef { field_type: uuid_or_text(&field.field_type), ..field.clone() })
+            .collect();
+        Schema { version: self.version, ..Schema::with_root(self.root, fields) }
+    }
+
+    /// Sort fields by name and canonicalize their types
+    pub(crate) fn canonicalize(fields: &mut [FieldDef]) {
+        fields.sort_by(|a, b| a.name.cmp(&b.name));
+        for field in fields {
+            field.field_type.canonicalize();
         }
     }
 
     /// Compute schema hash
-    pub(crate) fn compute_hash(fields: &[FieldDef]) -> u64 {
+    ///
+    /// Independent of the order of `fields`; nested types are hashed as
+    /// given, so they should already be canonical.
+    pub(crate) fn compute_hash(root: RootKind, fields: &[FieldDef]) -> u64 {
         // FNV-1a hash
         let mut hash: u64 = 0xcbf29ce484222325;
 
-        for field in fields {
+        // Object schemas hash as they did before array roots existed
+        if root != RootKind::Object {
+            hash ^= root.tag() as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        let mut sorted: Vec<&FieldDef> = fields.iter().collect();
+        sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for field in sorted {
             for byte in field.name.bytes() {
                 hash ^= byte as u64;
                 hash = hash.wrapping_mul(0x100000001b3);
             }
 
-            hash ^= field.field_type.type_id() as u64;
-            hash = hash.wrapping_mul(0x100000001b3);
+            let mut type_bytes = Vec::new();
+            write_type(&field.field_type, &mut type_bytes);
+            for byte in type_bytes {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
 
             hash ^= field.nullable as u64;
             hash = hash.wrapping_mul(0x100000001b3);
@@ -59,10 +212,42 @@ impl Schema {
         hash
     }
 
+    /// Deepest nesting of objects and arrays in values of this schema,
+    /// counted as `FluxConfig::max_depth` counts it
+    pub(crate) fn nesting_depth(&self) -> usize {
+        let root = match self.root {
+            RootKind::Object | RootKind::Values => 1,
+            RootKind::Records => 2,
+        };
+        // Each type with the number of containers around it
+        let mut stack: Vec<(&FieldType, usize)> = self.fields.iter().map(|field| (&field.field_type, root)).collect();
+        let mut deepest = root;
+        while let Some((field_type, depth)) = stack.pop() {
+            match field_type {
+                FieldType::Array(item) => {
+                    deepest = deepest.max(depth + 1);
+                    stack.push((item, depth + 1));
+                }
+                FieldType::Object(members) => {
+                    deepest = deepest.max(depth + 1);
+                    stack.extend(members.iter().map(|(_, member)| (member, depth + 1)));
+                }
+                FieldType::Union(variants) => stack.extend(variants.iter().map(|variant| (variant, depth))),
+                _ => {}
+            }
+        }
+        deepest
+    }
+
     /// Serialize schema to bytes
     pub fn serialize(&self) -> Vec<u8> {
         let mut buf = Vec::new();
+        self.serialize_into(&mut buf);
+        buf
+    }
 
+    /// Serialize schema, appending to `buf`
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
         // ID and version
         buf.extend_from_slice(&self.id.to_le_bytes());
         buf.extend_from_slice(&self.version.to_le_bytes());
@@ -84,57 +269,46 @@ impl Schema {
             let flags = if field.nullable { 0x01 } else { 0x00 };
             buf.push(flags);
 
-            // TODO: Serialize nested types
+            // Element and member types of arrays, objects and unions
+            write_nested_types(&field.field_type, buf);
         }
 
-        buf
+        if self.root != RootKind::Object {
+            buf.push(self.root.tag());
+        }
     }
 
     /// Deserialize schema from bytes
     pub fn deserialize(buf: &[u8]) -> Result<Self> {
-        if buf.len() < 15 {
-            return Err(Error::InvalidFrame("Schema too short".into()));
-        }
+        Self::deserialize_max_depth(buf, crate::config::MAX_DEPTH)
+    }
 
-        let id = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
-        let version = u16::from_le_bytes([buf[4], buf[5]]);
-        let hash = u64::from_le_bytes([
-            buf[6], buf[7], buf[8], buf[9], buf[10], buf[11], buf[12], buf[13],
-        ]);
+    /// Deserialize a schema for values nested at most `max_depth` deep
+    ///
+    /// Deeper schemas fail with `Error::DepthLimitExceeded` before reading
+    /// recurses past what the limit allows.
+    pub(crate) fn deserialize_max_depth(buf: &[u8], max_depth: usize) -> Result<Self> {
+        let mut cursor = Cursor::new(buf);
+        let max_type_depth = type_depth_limit(max_depth);
 
-        let field_count = buf[14] as usize;
-        let mut pos = 15;
+        let id = cursor.read_u32_le("schema id")?;
+        let version = cursor.read_u16_le("schema version")?;
+        let hash = cursor.read_u64_le("schema hash")?;
+
+        let field_count = cursor.read_u8("schema field count")? as usize;
         let mut fields = Vec::with_capacity(field_count);
 
         for _ in 0..field_count {
-            if pos >= buf.len() {
-                return Err(Error::InvalidFrame("Schema truncated".into()));
-            }
+            let name_len = cursor.read_u8("field name length")? as usize;
+            let name = String::from_utf8_lossy(cursor.read_bytes(name_len, "field name")?).into_owned();
 
-            let name_len = buf[pos] as usize;
-            pos += 1;
-
-            if pos + name_len > buf.len() {
-                return Err(Error::InvalidFrame("Field name truncated".into()));
-            }
-
-            let name = String::from_utf8_lossy(&buf[pos..pos + name_len]).into_owned();
-            pos += name_len;
-
-            let type_id = buf[pos];
-            pos += 1;
-
-            let flags = buf[pos];
-            pos += 1;
-
-            let field_type = match type_id {
-                0x00 => FieldType::Null,
-                0x01 => FieldType::Boolean,
-                0x02..=0x06 => FieldType::Integer(crate::types::IntegerType::Varint),
-                0x07 | 0x08 => FieldType::Float(crate::types::FloatType::Float64),
-                0x09 => FieldType::String,
-                _ => FieldType::String, // Fallback
-            };
+            let type_id = cursor.read_u8("field type")?;
+            let flags = cursor.read_u8("field flags")?;
+            let field_type = read_nested_types(type_id, &mut cursor, 0, max_type_depth)
+                .map_err(|e| match e {
+                    Error::DepthLimitExceeded { .. } => Error::DepthLimitExceeded { max: max_depth },
+                    e => e,
+                })?;
 
             fields.push(FieldDef {
                 name,
@@ -143,19 +317,149 @@ impl Schema {
             });
         }
 
+        let root = if cursor.is_empty() {
+            RootKind::Object
+        } else {
+            match cursor.read_u8("schema root")? {
+                0 => RootKind::Object,
+                1 => RootKind::Records,
+                2 => RootKind::Values,
+                tag => return Err(Error::InvalidFrame(format!("Unknown schema root {}", tag))),
+            }
+        };
+        if root == RootKind::Values && fields.len() != 1 {
+            return Err(Error::InvalidFrame("Value array schema needs exactly one field".into()));
+        }
+
         Ok(Self {
             id,
             version,
             hash,
             fields,
+            root,
         })
     }
 }
 
+/// Whether `null` is a valid value of `field_type`
+fn accepts_null(field_type: &FieldType) -> bool {
+    *field_type == FieldType::Null || field_type.is_nullable()
+}
+
+/// Write a type descriptor: type ID followed by any nested types
+pub(crate) fn write_type(field_type: &FieldType, buf: &mut Vec<u8>) {
+    buf.push(field_type.type_id());
+    write_nested_types(field_type, buf);
+}
+
+fn write_nested_types(field_type: &FieldType, buf: &mut Vec<u8>) {
+    match field_type {
+        FieldType::Array(elem) => write_type(elem, buf),
+        FieldType::Object(fields) => {
+            encode_varint(fields.len() as u64, buf);
+            for (name, ftype) in fields {
+                encode_varint(name.len() as u64, buf);
+                buf.extend_from_slice(name.as_bytes());
+                write_type(ftype, buf);
+            }
+        }
+        FieldType::Union(types) => {
+            encode_varint(types.len() as u64, buf);
+            for ftype in types {
+                write_type(ftype, buf);
+            }
+        }
+        FieldType::Decimal { precision, scale } => {
+            buf.push(*precision);
+            buf.push(*scale);
+        }
+        _ => {}
+    }
+}
+
+/// Read a type descriptor written by `write_type`
+///
+/// Descriptors nested more than `max` deep fail with
+/// `Error::DepthLimitExceeded`.
+pub(crate) fn read_type(cursor: &mut Cursor<'_>, depth: usize, max: usize) -> Result<FieldType> {
+    let id = cursor.read_u8("nested type")?;
+    read_nested_types(id, cursor, depth, max)
+}
+
+fn read_nested_types(id: u8, cursor: &mut Cursor<'_>, depth: usize, max: usize) -> Result<FieldType> {
+    if depth > max {
+        return Err(Error::DepthLimitExceeded { max });
+    }
+
+    let field_type = match id {
+        type_id::NULL => FieldType::Null,
+        type_id::BOOLEAN => FieldType::Boolean,
+        type_id::INT8 => FieldType::Integer(IntegerType::Int8),
+        type_id::INT16 => FieldType::Integer(IntegerType::Int16),
+        type_id::INT32 => FieldType::Integer(IntegerType::Int32),
+        type_id::INT64 => FieldType::Integer(IntegerType::Int64),
+        type_id::VARINT => FieldType::Integer(IntegerType::Varint),
+        type_id::UINT64 => FieldType::Integer(IntegerType::UInt64),
+        type_id::FLOAT32 => FieldType::Float(FloatType::Float32),
+        type_id::FLOAT64 => FieldType::Float(FloatType::Float64),
+        type_id::STRING => FieldType::String,
+        type_id::BINARY => FieldType::Binary,
+        type_id::TIMESTAMP => FieldType::Timestamp,
+        type_id::UUID => FieldType::Uuid,
+        type_id::ARRAY => FieldType::Array(Box::new(read_type(cursor, depth + 1, max)?)),
+        type_id::OBJECT => {
+            // Every member takes at least one byte
+            let count = cursor.read_len("object member count")?;
+            let mut fields = Vec::with_capacity(count.min(256));
+            for _ in 0..count {
+                let name = String::from_utf8_lossy(cursor.read_len_bytes("member name")?).into_owned();
+                let ftype = read_type(cursor, depth + 1, max)?;
+                fields.push((name, ftype));
+            }
+            FieldType::Object(fields)
+        }
+        type_id::UNION => {
+            let count = cursor.read_len("union variant count")?;
+            let mut types = Vec::with_capacity(count.min(256));
+            for _ in 0..count {
+                types.push(read_type(cursor, depth + 1, max)?);
+            }
+            FieldType::Union(types)
+        }
+        type_id::DECIMAL => {
+            let [precision, scale] = cursor.read_array("decimal type")?;
+            FieldType::Decimal { precision, scale }
+        }
+        _ => FieldType::String, // Fallback
+    };
+    Ok(field_type)
+}
+
+/// `field_type` with `Uuid` widened to `Uuid | String` at every level
+fn uuid_or_text(field_type: &FieldType) -> FieldType {
+    match field_type {
+        FieldType::Uuid => FieldType::Union(vec![FieldType::Uuid, FieldType::String]),
+        FieldType::Array(elem) => FieldType::Array(Box::new(uuid_or_text(elem))),
+        FieldType::Object(members) => {
+            FieldType::Object(members.iter().map(|(name, t)| (name.clone(), uuid_or_text(t))).collect())
+        }
+        FieldType::Union(types) => {
+            let mut widened: Vec<FieldType> = types
+                .iter()
+                .map(|t| if *t == FieldType::Uuid { t.clone() } else { uuid_or_text(t) })
+                .collect();
+            if types.contains(&FieldType::Uuid) && !types.contains(&FieldType::String) {
+                widened.push(FieldType::String);
+            }
+            FieldType::Union(widened)
+        }
+        t => t.clone(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::IntegerType;
 
     #[test]
     fn test_schema_serialize_deserialize() {
@@ -181,4 +485,105 @@ mod tests {
         assert!(!parsed.fields[0].nullable);
         assert!(parsed.fields[1].nullable);
     }
+
+    #[test]
+    fn test_schema_nested_types_roundtrip() {
+        let nested = FieldType::Object(vec![
+            ("created".into(), FieldType::Timestamp),
+            ("owner".into(), FieldType::Object(vec![("id".into(), FieldType::Integer(IntegerType::Int64))])),
+            ("price".into(), FieldType::Decimal { precision: 10, scale: 2 }),
+            ("score".into(), FieldType::Union(vec![FieldType::Float(FloatType::Float64), FieldType::Null])),
+            ("tags".into(), FieldType::Array(Box::new(FieldType::String))),
+        ]);
+        let schema = Schema::new(vec![FieldDef {
+            name: "item".into(),
+            field_type: nested.clone(),
+            nullable: false,
+        }]);
+
+        let parsed = Schema::deserialize(&schema.serialize()).unwrap();
+        assert_eq!(parsed.fields[0].field_type, nested);
+        assert_eq!(parsed.hash, schema.hash);
+
+        // Nested shape is part of the hash
+        let other = Schema::new(vec![FieldDef {
+            name: "item".into(),
+            field_type: FieldType::Object(vec![("id".into(), FieldType::String)]),
+            nullable: false,
+        }]);
+        assert_ne!(other.hash, schema.hash);
+    }
+
+    #[test]
+    fn test_schema_canonical_order() {
+        let field = |name: &str, field_type: FieldType| FieldDef { name: name.into(), field_type, nullable: false };
+        let a = Schema::new(vec![
+            field("id", FieldType::Integer(IntegerType::Int32)),
+            field("meta", FieldType::Object(vec![("z".into(), FieldType::String), ("a".into(), FieldType::Boolean)])),
+        ]);
+        let b = Schema::new(vec![
+            field("meta", FieldType::Object(vec![("a".into(), FieldType::Boolean), ("z".into(), FieldType::String)])),
+            field("id", FieldType::Integer(IntegerType::Int32)),
+        ]);
+
+        assert_eq!(a.hash, b.hash);
+        assert_eq!(a.serialize(), b.serialize());
+        assert_eq!(a.fields[0].name, "id");
+        assert_eq!(Schema::compute_hash(RootKind::Object, &[b.fields[1].clone(), b.fields[0].clone()]), a.hash);
+    }
+
+    #[test]
+    fn test_schema_builder() {
+        let schema = Schema::builder()
+            .field("name", FieldType::String)
+            .nullable_field("email", FieldType::String)
+            .field("id", FieldType::Integer(IntegerType::Varint))
+            .build()
+            .unwrap();
+        let names: Vec<&str> = schema.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["email", "id", "name"]);
+        assert!(schema.fields[0].nullable);
+        assert_eq!(schema.root, RootKind::Object);
+
+        let duplicate = Schema::builder().field("a", FieldType::Boolean).field("a", FieldType::String).build();
+        assert!(matches!(duplicate, Err(Error::InvalidConfig { field: "schema", .. })));
+        assert!(Schema::builder().root(RootKind::Values).build().is_err());
+        assert!(Schema::builder().field("x".repeat(256), FieldType::Null).build().is_err());
+    }
+
+    #[test]
+    fn test_schema_root_kind_roundtrip() {
+        let fields = vec![FieldDef {
+            name: "id".into(),
+            field_type: FieldType::Integer(IntegerType::Int32),
+            nullable: false,
+        }];
+        let object = Schema::new(fields.clone());
+        let records = Schema::with_root(RootKind::Records, fields);
+
+        // Object schemas carry no root byte
+        assert_eq!(records.serialize().len(), object.serialize().len() + 1);
+        assert_ne!(records.hash, object.hash);
+
+        let parsed = Schema::deserialize(&records.serialize()).unwrap();
+        assert_eq!(parsed.root, RootKind::Records);
+        assert_eq!(Schema::deserialize(&object.serialize()).unwrap().root, RootKind::Object);
+
+        let mut bytes = object.serialize();
+        bytes.push(9);
+        assert!(matches!(Schema::deserialize(&bytes), Err(Error::InvalidFrame(_))));
+    }
+
+    #[test]
+    fn test_schema_nested_truncated() {
+        let schema = Schema::new(vec![FieldDef {
+            name: "a".into(),
+            field_type: FieldType::Array(Box::new(FieldType::Array(Box::new(FieldType::String)))),
+            nullable: false,
+        }]);
+        let bytes = schema.serialize();
+        for len in 0..bytes.len() {
+            assert!(matches!(Schema::deserialize(&bytes[..len]), Err(Error::Truncated { .. })));
+        }
+    }
 }
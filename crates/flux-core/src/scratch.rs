@@ -0,0 +1,59 @@
+//! Working buffers reused across messages
+//!
+//! Each stage of the compression pipeline writes into its own buffer here
+//! instead of a fresh `Vec`, so once the buffers have grown to the size of
+//! a typical message, `FluxSession::compress_into` no longer allocates for
+//! them. JSON parsing and schema inference still allocate.
+
+use crate::lz::LzTables;
+
+/// Buffers larger than this are released after a message, so one large
+/// message doesn't pin its memory for the rest of the session
+pub const MAX_RETAINED_SCRATCH: usize = 1 << 20;
+
+/// Per-session working buffers for the compression pipeline
+#[derive(Debug, Default)]
+pub struct ScratchBuffers {
+    /// Schema-encoded payload (input to LZ)
+    pub(crate) encoded: Vec<u8>,
+    pub(crate) lz: Vec<u8>,
+    pub(crate) lz_tables: LzTables,
+    pub(crate) nibbles: Vec<u8>,
+    pub(crate) entropy: Vec<u8>,
+    /// Frame section being assembled (metadata, schema, dictionary)
+    pub(crate) section: Vec<u8>,
+}
+
+impl ScratchBuffers {
+    /// Create empty buffers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes currently reserved by the buffers
+    pub fn capacity(&self) -> usize {
+        self.encoded.capacity()
+            + self.lz.capacity()
+            + self.lz_tables.capacity()
+            + self.nibbles.capacity()
+            + self.entropy.capacity()
+            + self.section.capacity()
+    }
+
+    /// Free all buffers
+    pub fn release(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Free the buffers that outgrew `MAX_RETAINED_SCRATCH`
+    pub(crate) fn trim(&mut self) {
+        for buf in [&mut self.encoded, &mut self.lz, &mut self.nibbles, &mut self.entropy, &mut self.section] {
+            if buf.capacity() > MAX_RETAINED_SCRATCH {
+                *buf = Vec::new();
+            }
+        }
+        if self.lz_tables.capacity() > MAX_RETAINED_SCRATCH {
+            self.lz_tables = LzTables::default();
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Sessions keyed by tenant or connection
+//!
+//! A gateway serving many peers keeps one `FluxSession` per peer, so each
+//! pair of sessions sees the same messages in the same order. Peers rarely
+//! say goodbye: without expiry the map of sessions grows until the process
+//! runs out of memory. `SessionManager` creates sessions on first use and
+//! drops them once they sit idle longer than the TTL, or when the cap on
+//! live sessions is reached (least recently used first).
+//!
+//! Sessions are handed out as `Arc<Mutex<FluxSession>>`, so requests for
+//! different keys proceed in parallel. A session dropped while in use keeps
+//! working for its holder; the next request for the key starts afresh.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::clock::{self, Clock};
+use crate::{FluxConfig, FluxSession, Result, SessionStats};
+
+/// Idle time after which a session is dropped, unless set with `ttl`
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(600);
+
+/// Live sessions allowed at once, unless set with `max_sessions`
+pub const DEFAULT_MAX_SESSIONS: usize = 10_000;
+
+/// Pool of `FluxSession`s keyed by tenant or connection ID
+///
+/// ```rust,ignore
+/// let manager = SessionManager::new(FluxConfig::default()).ttl(Some(Duration::from_secs(60)));
+/// let frame = manager.compress(&connection_id, body)?;
+/// ```
+pub struct SessionManager<K> {
+    config: FluxConfig,
+    ttl: Option<Duration>,
+    max_sessions: usize,
+    clock: Arc<dyn Clock>,
+    inner: Mutex<Inner<K>>,
+}
+
+struct Inner<K> {
+    sessions: HashMap<K, Entry>,
+    /// Incremented on each use, ordering sessions for LRU eviction
+    tick: u64,
+    created: u64,
+    expired: u64,
+    evicted: u64,
+}
+
+struct Entry {
+    session: Arc<Mutex<FluxSession>>,
+    last_used: u64,
+    /// Clock reading at last use (`None` without a clock)
+    last_seen: Option<Duration>,
+}
+
+/// Statistics of a `SessionManager`
+#[derive(Debug, Clone, Default)]
+pub struct SessionManagerStats {
+    /// Sessions currently held
+    pub active: usize,
+    /// Sessions created since the manager started
+    pub created: u64,
+    /// Sessions dropped after sitting idle longer than the TTL
+    pub expired: u64,
+    /// Sessions dropped to stay within `max_sessions`
+    pub evicted: u64,
+    /// Statistics summed over the sessions currently held
+    pub sessions: SessionStats,
+    /// Bytes held by the sessions currently held, per `FluxSession::memory_usage`
+    pub memory: usize,
+}
+
+impl<K: Eq + Hash + Clone> SessionManager<K> {
+    /// Create a manager whose sessions use `config`
+    pub fn new(config: FluxConfig) -> Self {
+        Self {
+            config,
+            ttl: Some(DEFAULT_SESSION_TTL),
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            clock: clock::default_clock(),
+            inner: Mutex::new(Inner {
+                sessions: HashMap::new(),
+                tick: 0,
+                created: 0,
+                expired: 0,
+                evicted: 0,
+            }),
+        }
+    }
+
+    /// Drop sessions idle for longer than `ttl` (`None` keeps them until evicted)
+    pub fn ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Most sessions held at once (at least 1)
+    pub fn max_sessions(mut self, max: usize) -> Self {
+        self.max_sessions = max.max(1);
+        self
+    }
+
+    /// Replace the time source idle times are measured with
+    ///
+    /// With a clock that reads `None` (`NoClock`), sessions never expire.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Session for `key`, created if there is none
+    pub fn get(&self, key: &K) -> Arc<Mutex<FluxSession>> {
+        let now = self.clock.now();
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        if let Some(entry) = inner.sessions.get_mut(key) {
+            entry.last_used = tick;
+            entry.last_seen = now;
+            return entry.session.clone();
+        }
+
+        self.expire_locked(&mut inner, now);
+        while inner.sessions.len() >= self.max_sessions {
+            let Some(oldest) = inner.sessions.iter().min_by_key(|(_, entry)| entry.last_used).map(|(k, _)| k.clone()) else {
+                break;
+            };
+            inner.sessions.remove(&oldest);
+            inner.evicted += 1;
+        }
+
+        let session = Arc::new(Mutex::new(FluxSession::with_config(self.config.clone())));
+        inner.sessions.insert(key.clone(), Entry { session: session.clone(), last_used: tick, last_seen: now });
+        inner.created += 1;
+        session
+    }
+
+    /// Run `f` on the session for `key`
+    pub fn with_session<R>(&self, key: &K, f: impl FnOnce(&mut FluxSession) -> R) -> R {
+        let session = self.get(key);
+        let mut session = session.lock().unwrap_or_else(PoisonError::into_inner);
+        f(&mut session)
+    }
+
+    /// Compress JSON data with the session for `key`
+    pub fn compress(&self, key: &K, input: &[u8]) -> Result<Vec<u8>> {
+        self.with_session(key, |session| session.compress(input))
+    }
+
+    /// Decompress a FLUX frame with the session for `key`
+    pub fn decompress(&self, key: &K, input: &[u8]) -> Result<Vec<u8>> {
+        self.with_session(key, |session| session.decompress(input))
+    }
+
+    /// Drop the session for `key` (say, when its connection closes)
+    pub fn remove(&self, key: &K) -> bool {
+        self.lock().sessions.remove(key).is_some()
+    }
+
+    /// Whether a session for `key` is held
+    pub fn contains(&self, key: &K) -> bool {
+        self.lock().sessions.contains_key(key)
+    }
+
+    /// Drop the sessions idle for longer than the TTL, returning how many
+    ///
+    /// `get` does this whenever it creates a session; call it from a timer
+    /// to release memory while no new peers arrive.
+    pub fn expire(&self) -> usize {
+        let now = self.clock.now();
+        self.expire_locked(&mut self.lock(), now)
+    }
+
+    /// Number of sessions held
+    pub fn len(&self) -> usize {
+        self.lock().sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Manager statistics, with session statistics summed over live sessions
+    ///
+    /// Locks each session in turn, so don't call it from inside `with_session`.
+    pub fn stats(&self) -> SessionManagerStats {
+        let (mut stats, sessions) = {
+            let inner = self.lock();
+            let stats = SessionManagerStats {
+                active: inner.sessions.len(),
+                created: inner.created,
+                expired: inner.expired,
+                evicted: inner.evicted,
+                sessions: SessionStats::default(),
+                memory: 0,
+            };
+            (stats, inner.sessions.values().map(|entry| entry.session.clone()).collect::<Vec<_>>())
+        };
+        for session in sessions {
+            let session = session.lock().unwrap_or_else(PoisonError::into_inner);
+            stats.sessions += session.stats();
+            stats.memory += session.memory_usage().total();
+        }
+        stats
+    }
+
+    fn expire_locked(&self, inner: &mut Inner<K>, now: Option<Duration>) -> usize {
+        let (Some(ttl), Some(now)) = (self.ttl, now) else { return 0 };
+        let before = inner.sessions.len();
+        inner.sessions.retain(|_, entry| entry.last_seen.is_none_or(|seen| now.saturating_sub(seen) <= ttl));
+        let expired = before - inner.sessions.len();
+        inner.expired += expired as u64;
+        expired
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner<K>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManualClock;
+
+    const MESSAGE: &[u8] = br#"{"id":1,"name":"alice","active":true}"#;
+
+    #[test]
+    fn test_sessions_per_key() {
+        let manager = SessionManager::new(FluxConfig::default());
+        let first = manager.compress(&"tenant-a", MESSAGE).unwrap();
+        let cached = manager.compress(&"tenant-a", MESSAGE).unwrap();
+        let other = manager.compress(&"tenant-b", MESSAGE).unwrap();
+        assert!(cached.len() < first.len());
+        assert_eq!(other, first);
+
+        let stats = manager.stats();
+        assert_eq!((stats.active, stats.created), (2, 2));
+        assert_eq!(stats.sessions.messages_processed, 3);
+        assert_eq!(stats.sessions.cache_hits, 1);
+        assert!(stats.memory > 0);
+
+        assert!(manager.remove(&"tenant-a"));
+        assert!(!manager.remove(&"tenant-a"));
+        assert_eq!(manager.len(), 1);
+    }
+
+    #[test]
+    fn test_idle_sessions_expire() {
+        let clock = Arc::new(ManualClock::new());
+        let manager = SessionManager::new(FluxConfig::default()).ttl(Some(Duration::from_secs(60))).clock(clock.clone());
+        manager.get(&1);
+        clock.advance(Duration::from_secs(40));
+        manager.get(&2);
+        clock.advance(Duration::from_secs(40));
+        manager.get(&2);
+
+        assert_eq!(manager.expire(), 1);
+        assert!(!manager.contains(&1));
+        assert!(manager.contains(&2));
+
+        // Without a TTL, sessions stay
+        let manager = SessionManager::new(FluxConfig::default()).ttl(None).clock(clock.clone());
+        manager.get(&1);
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(manager.expire(), 0);
+        assert_eq!(manager.stats().expired, 0);
+    }
+
+    #[test]
+    fn test_cap_evicts_least_recently_used() {
+        let manager = SessionManager::new(FluxConfig::default()).max_sessions(2);
+        manager.get(&1);
+        manager.get(&2);
+        manager.get(&1);
+        manager.get(&3);
+
+        assert!(manager.contains(&1));
+        assert!(!manager.contains(&2));
+        assert!(manager.contains(&3));
+        let stats = manager.stats();
+        assert_eq!((stats.active, stats.created, stats.evicted), (2, 3, 1));
+    }
+}
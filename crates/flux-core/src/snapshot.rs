@@ -0,0 +1,369 @@
+//! Session state snapshots
+//!
+//! `FluxSession::export_state` captures everything a session shares with
+//! its peer — cached schemas with their IDs, value dictionaries, LZ history
+//! and which schema was last used — so an application can persist it (say,
+//! to IndexedDB) and carry on after a restart without resending schemas.
+//! `FluxStreamSession::export_state` adds the delta baselines.
+//!
+//! ```text
+//! "FXSN" | version (u8) | kind (u8: 0 = session, 1 = stream) | body
+//! session: cache state | last_schema (varint, 0 = none, else id + 1)
+//!          | outgoing dictionary | incoming dictionary | sent history | received history
+//! stream:  default channel | [channel count (varint) | (channel (varint) | channel)*]
+//! channel: since_keyframe (varint) | sent state | received state
+//!          | patch sender (session body) | patch receiver (session body)
+//! ```
+//!
+//! The channel list is left out when only the default channel is in use.
+//!
+//! Dictionaries, histories, states (JSON, empty = none) and nested session
+//! bodies are varint-length-prefixed. Configuration, statistics and clocks
+//! are not included: restore into a session built with the configuration
+//! the snapshot was taken from.
+
+use crate::cursor::Cursor;
+use crate::encoding::{encode_varint, Encoder};
+use crate::lz::LzHistory;
+use crate::schema::SchemaCache;
+use crate::{Error, FluxSession, FluxStreamSession, Result, DEFAULT_CHANNEL};
+
+const MAGIC: &[u8; 4] = b"FXSN";
+const VERSION: u8 = 1;
+
+/// Which kind of session a snapshot was exported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    /// `FluxSession`
+    Session,
+    /// `FluxStreamSession`
+    Stream,
+}
+
+impl SnapshotKind {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Session => 0,
+            Self::Stream => 1,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Session => "session",
+            Self::Stream => "stream",
+        }
+    }
+}
+
+/// Kind of session `snapshot` restores, checking only the header
+pub fn snapshot_kind(snapshot: &[u8]) -> Result<SnapshotKind> {
+    read_header(&mut Cursor::new(snapshot))
+}
+
+fn read_header(cursor: &mut Cursor<'_>) -> Result<SnapshotKind> {
+    if cursor.read_bytes(MAGIC.len(), "snapshot magic")? != MAGIC {
+        return Err(Error::InvalidFrame("Not a FLUX session snapshot".into()));
+    }
+    let version = cursor.read_u8("snapshot version")?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    match cursor.read_u8("snapshot kind")? {
+        0 => Ok(SnapshotKind::Session),
+        1 => Ok(SnapshotKind::Stream),
+        kind => Err(Error::InvalidFrame(format!("Unknown snapshot kind {}", kind))),
+    }
+}
+
+/// Open `snapshot`, which must be of `kind`, and return a cursor at the body
+fn open(snapshot: &[u8], kind: SnapshotKind) -> Result<Cursor<'_>> {
+    let mut cursor = Cursor::new(snapshot);
+    let found = read_header(&mut cursor)?;
+    if found != kind {
+        return Err(Error::Unexpected {
+            what: "snapshot kind",
+            expected: kind.name().into(),
+            found: found.name().into(),
+        });
+    }
+    Ok(cursor)
+}
+
+fn write_header(kind: SnapshotKind) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.push(VERSION);
+    buf.push(kind.tag());
+    buf
+}
+
+fn finish(cursor: &Cursor<'_>) -> Result<()> {
+    if cursor.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidFrame("Trailing bytes after session snapshot".into()))
+    }
+}
+
+fn write_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_varint(bytes.len() as u64, buf);
+    buf.extend_from_slice(bytes);
+}
+
+/// Session state read from a snapshot, applied only once all of it parsed
+struct SessionState {
+    schema_cache: SchemaCache,
+    last_schema: Option<u32>,
+    encoder: Encoder,
+    sent_history: LzHistory,
+    received_history: LzHistory,
+}
+
+impl FluxSession {
+    /// Snapshot of the state shared with the peer
+    ///
+    /// Restore it with `import_state` on a session with the same configuration.
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut buf = write_header(SnapshotKind::Session);
+        self.write_state(&mut buf);
+        buf
+    }
+
+    /// Replace the session state with a snapshot from `export_state`
+    ///
+    /// Statistics are kept. On error the session is left unchanged.
+    pub fn import_state(&mut self, snapshot: &[u8]) -> Result<()> {
+        let mut cursor = open(snapshot, SnapshotKind::Session)?;
+        let state = self.read_state(&mut cursor)?;
+        finish(&cursor)?;
+        self.apply_state(state);
+        Ok(())
+    }
+
+    fn write_state(&self, buf: &mut Vec<u8>) {
+        self.schema_cache.write_state(buf);
+        encode_varint(self.last_schema.map_or(0, |id| id as u64 + 1), buf);
+        self.encoder.write_dictionaries(buf);
+        write_bytes(self.sent_history.as_slice(), buf);
+        write_bytes(self.received_history.as_slice(), buf);
+    }
+
+    fn read_state(&self, cursor: &mut Cursor<'_>) -> Result<SessionState> {
+        let mut schema_cache = SchemaCache::with_capacity(self.config.max_cached_schemas, self.config.schema_eviction);
+        schema_cache.read_state(cursor)?;
+        let last_schema = match cursor.read_varint("last schema")? {
+            0 => None,
+            id => Some(u32::try_from(id - 1).map_err(|_| Error::InvalidFrame("Schema id out of range".into()))?),
+        };
+        let mut encoder = Encoder::with_value_dictionary(self.config.value_dictionary, self.config.max_dict_size);
+        encoder.read_dictionaries(cursor)?;
+        let mut sent_history = LzHistory::new(self.config.lz_history);
+        sent_history.push(cursor.read_len_bytes("sent history")?);
+        let mut received_history = LzHistory::new(self.config.lz_history);
+        received_history.push(cursor.read_len_bytes("received history")?);
+        Ok(SessionState { schema_cache, last_schema, encoder, sent_history, received_history })
+    }
+
+    fn apply_state(&mut self, state: SessionState) {
+        self.schema_cache = state.schema_cache;
+        self.last_schema = state.last_schema;
+        self.encoder = state.encoder;
+        self.sent_history = state.sent_history;
+        self.received_history = state.received_history;
+    }
+}
+
+impl FluxStreamSession {
+    /// Snapshot of the sent and received states and the sessions carrying them
+    ///
+    /// Restore it with `import_state` on a session with the same configuration.
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut buf = write_header(SnapshotKind::Stream);
+        self.write_channel(DEFAULT_CHANNEL, &mut buf);
+
+        let mut channels: Vec<u32> = self.tx.channels.keys().chain(self.rx.channels.keys()).copied().collect();
+        channels.retain(|&channel| channel != DEFAULT_CHANNEL);
+        channels.sort_unstable();
+        channels.dedup();
+        if !channels.is_empty() {
+            encode_varint(channels.len() as u64, &mut buf);
+            for channel in channels {
+                encode_varint(channel as u64, &mut buf);
+                self.write_channel(channel, &mut buf);
+            }
+        }
+        buf
+    }
+
+    fn write_channel(&self, channel: u32, buf: &mut Vec<u8>) {
+        let (tx, rx) = (self.tx.channels.get(&channel), self.rx.channels.get(&channel));
+        encode_varint(tx.map_or(0, |tx| tx.since_keyframe) as u64, buf);
+        for state in [tx.and_then(|tx| tx.delta_encoder.state()), rx.and_then(|rx| rx.delta_decoder.state())] {
+            // Values always serialize
+            let json = state.map(|value| serde_json::to_vec(value).unwrap_or_default()).unwrap_or_default();
+            write_bytes(&json, buf);
+        }
+        let fresh = FluxSession::new();
+        for session in [tx.map(|tx| &tx.patch_session), rx.map(|rx| &rx.patch_session)] {
+            let mut body = Vec::new();
+            session.unwrap_or(&fresh).write_state(&mut body);
+            write_bytes(&body, buf);
+        }
+    }
+
+    /// Replace the streaming state with a snapshot from `export_state`
+    ///
+    /// The keyframe period restarts from the next update; statistics are
+    /// kept. On error the session is left unchanged.
+    pub fn import_state(&mut self, snapshot: &[u8]) -> Result<()> {
+        let mut cursor = open(snapshot, SnapshotKind::Stream)?;
+        let mut channels = vec![(DEFAULT_CHANNEL, read_channel(&mut cursor)?)];
+        if !cursor.is_empty() {
+            for _ in 0..cursor.read_len("stream channels")? {
+                let channel = u32::try_from(cursor.read_varint("stream channel")?)
+                    .map_err(|_| Error::InvalidFrame("Stream channel out of range".into()))?;
+                channels.push((channel, read_channel(&mut cursor)?));
+            }
+        }
+        finish(&cursor)?;
+
+        self.tx.channels.clear();
+        self.rx.channels.clear();
+        for (channel, state) in channels {
+            let tx = self.tx.channel(channel);
+            tx.delta_encoder.reset();
+            if let Some(sent) = state.sent {
+                tx.delta_encoder.replace_state(sent);
+            }
+            tx.since_keyframe = state.since_keyframe;
+            tx.last_keyframe = None;
+            tx.patch_session.apply_state(state.patch_sender);
+            let rx = self.rx.channel(channel);
+            *rx.delta_decoder.state_mut() = state.received;
+            rx.patch_session.apply_state(state.patch_receiver);
+        }
+        Ok(())
+    }
+}
+
+/// Stream channel state read from a snapshot
+struct ChannelState {
+    since_keyframe: u32,
+    sent: Option<serde_json::Value>,
+    received: Option<serde_json::Value>,
+    patch_sender: SessionState,
+    patch_receiver: SessionState,
+}
+
+fn read_channel(cursor: &mut Cursor<'_>) -> Result<ChannelState> {
+    let since_keyframe = u32::try_from(cursor.read_varint("updates since keyframe")?)
+        .map_err(|_| Error::InvalidFrame("Keyframe count out of range".into()))?;
+    let mut states = [None, None];
+    for state in &mut states {
+        let json = cursor.read_len_bytes("stream state")?;
+        if !json.is_empty() {
+            *state = Some(serde_json::from_slice::<serde_json::Value>(json).map_err(|e| Error::ParseError(e.to_string()))?);
+        }
+    }
+    // Patch sessions always use the default configuration
+    let fresh = FluxSession::new();
+    let mut patch_session = || -> Result<SessionState> {
+        let mut body = Cursor::new(cursor.read_len_bytes("patch session")?);
+        let state = fresh.read_state(&mut body)?;
+        finish(&body)?;
+        Ok(state)
+    };
+    let (patch_sender, patch_receiver) = (patch_session()?, patch_session()?);
+    let [sent, received] = states;
+    Ok(ChannelState { since_keyframe, sent, received, patch_sender, patch_receiver })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FluxConfig, PatchFormat, StreamConfig};
+
+    #[test]
+    fn test_session_snapshot() {
+        let config = || FluxConfig::builder().value_dictionary(true).lz_history(4096).build().unwrap();
+        let (mut sender, mut receiver) = (FluxSession::with_config(config()), FluxSession::with_config(config()));
+        let message = |i: u32| format!(r#"{{"id":{},"region":"eu-west-1","status":"active"}}"#, i);
+        for i in 0..5 {
+            receiver.decompress(&sender.compress(message(i).as_bytes()).unwrap()).unwrap();
+        }
+        sender.compress(br#"{"other":true}"#).unwrap();
+
+        let (mut sender2, mut receiver2) = (FluxSession::with_config(config()), FluxSession::with_config(config()));
+        sender2.import_state(&sender.export_state()).unwrap();
+        receiver2.import_state(&receiver.export_state()).unwrap();
+        assert_eq!(sender2.export_state(), sender.export_state());
+
+        // Restored sessions keep referencing the cached schema and dictionary
+        let frame = sender2.compress(message(5).as_bytes()).unwrap();
+        assert_eq!(frame, sender.compress(message(5).as_bytes()).unwrap());
+        assert_eq!(receiver2.decompress(&frame).unwrap(), message(5).as_bytes());
+        assert_eq!(sender2.schema_cache().iter().map(|s| s.id).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn test_stream_snapshot() {
+        for patch_format in [PatchFormat::Delta, PatchFormat::MergePatch] {
+            let config = StreamConfig { patch_format, ..StreamConfig::default() };
+            let (mut sender, mut receiver) = (FluxStreamSession::with_config(config.clone()), FluxStreamSession::with_config(config.clone()));
+            let state = |i: u32| format!(r#"{{"count":{},"name":"x"}}"#, i);
+            for i in 0..3 {
+                receiver.receive(&sender.update(state(i).as_bytes()).unwrap()).unwrap();
+            }
+
+            let mut sender2 = FluxStreamSession::with_config(config.clone());
+            let mut receiver2 = FluxStreamSession::with_config(config.clone());
+            sender2.import_state(&sender.export_state()).unwrap();
+            receiver2.import_state(&receiver.export_state()).unwrap();
+            let update = sender2.update(state(3).as_bytes()).unwrap();
+            assert_eq!(update, sender.update(state(3).as_bytes()).unwrap());
+            assert_eq!(receiver2.receive(&update).unwrap(), state(3).as_bytes());
+            assert_eq!(snapshot_kind(&sender2.export_state()).unwrap(), SnapshotKind::Stream);
+        }
+    }
+
+    #[test]
+    fn test_stream_channels_snapshot() {
+        let (mut sender, mut receiver) = (FluxStreamSession::new(), FluxStreamSession::new());
+        let state = |i: u32| format!(r#"{{"count":{},"name":"x"}}"#, i);
+        for channel in [0, 3, 9] {
+            receiver.receive(&sender.update_channel(channel, state(channel).as_bytes()).unwrap()).unwrap();
+        }
+
+        let (mut sender2, mut receiver2) = (FluxStreamSession::new(), FluxStreamSession::new());
+        sender2.import_state(&sender.export_state()).unwrap();
+        receiver2.import_state(&receiver.export_state()).unwrap();
+        assert_eq!(sender2.export_state(), sender.export_state());
+        for channel in [0, 3, 9] {
+            let update = sender2.update_channel(channel, state(channel + 1).as_bytes()).unwrap();
+            assert_eq!(update, sender.update_channel(channel, state(channel + 1).as_bytes()).unwrap());
+            assert_eq!(receiver2.receive_channel(&update).unwrap(), (channel, state(channel + 1).into_bytes()));
+        }
+        assert_eq!(sender2.stats().delta_sends, 3);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let mut session = FluxSession::new();
+        session.compress(br#"{"id":1}"#).unwrap();
+        let snapshot = session.export_state();
+        assert_eq!(snapshot_kind(&snapshot).unwrap(), SnapshotKind::Session);
+
+        let mut stream = FluxStreamSession::new();
+        assert!(matches!(stream.import_state(&snapshot), Err(Error::Unexpected { what: "snapshot kind", .. })));
+        assert!(matches!(snapshot_kind(b"nope"), Err(Error::InvalidFrame(_))));
+
+        let mut fresh = FluxSession::new();
+        for cut in 0..snapshot.len() {
+            assert!(fresh.import_state(&snapshot[..cut]).is_err(), "cut at {}", cut);
+        }
+        assert!(fresh.schema_cache().is_empty());
+        let mut long = snapshot.clone();
+        long.push(0);
+        assert!(fresh.import_state(&long).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Sequenced delta sync over message transports (WebSocket etc.)
+//!
+//! `FluxStreamSession` assumes every delta arrives exactly once and in order.
+//! `FluxSocketCodec` adds sequence numbers so a dropped or reordered delta is
+//! detected instead of silently corrupting state, plus control frames to
+//! acknowledge states and to request a full resend.
+//!
+//! # Wire format
+//!
+//! ```text
+//! STATE_FULL:  0x01 | seq (varint) | delta payload
+//! STATE_DELTA: 0x02 | seq (varint) | base_seq (varint) | delta payload
+//! ACK:         0x10 | seq (varint)
+//! RESYNC:      0x11 | last applied seq (varint, 0 if none)
+//! SUBSCRIBE:   0x12 | last applied seq (varint, 0 if none) | JSON Pointer (UTF-8)
+//! ```
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! let mut server = FluxSocketCodec::new();
+//! let mut client = FluxSocketCodec::new();
+//!
+//! let frame = server.encode(br#"{"count": 1}"#)?;
+//! match client.decode(&frame)? {
+//!     SocketEvent::State { json, ack, .. } => { apply(json); send(ack); }
+//!     SocketEvent::OutOfSync { resync } => send(resync),
+//!     _ => {}
+//! }
+//! ```
+
+use crate::encoding::{decode_varint, encode_varint};
+use crate::{Error, FluxStreamSession, Result};
+
+const MSG_STATE_FULL: u8 = 0x01;
+const MSG_STATE_DELTA: u8 = 0x02;
+const MSG_ACK: u8 = 0x10;
+const MSG_RESYNC: u8 = 0x11;
+const MSG_SUBSCRIBE: u8 = 0x12;
+
+/// Outcome of decoding a socket frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum SocketEvent {
+    /// A new state was applied; send `ack` back to the peer
+    State { seq: u64, json: Vec<u8>, ack: Vec<u8> },
+    /// A delta did not apply to our state; send `resync` back to the peer
+    OutOfSync { resync: Vec<u8> },
+    /// Stale, duplicate or unusable frame while waiting for a full state
+    Ignored,
+    /// The peer acknowledged state `seq`
+    Acked(u64),
+    /// The peer asked for full state; send `full` if there is one
+    ResyncRequested { full: Option<Vec<u8>> },
+    /// The peer subscribed to the part of the state at `pointer`; send
+    /// `full` if there is one
+    Subscribed { pointer: String, full: Option<Vec<u8>> },
+}
+
+/// Sequenced delta codec for bidirectional sockets
+///
+/// Each side owns one codec: `encode` produces state frames, `decode`
+/// consumes any frame from the peer.
+pub struct FluxSocketCodec {
+    session: FluxStreamSession,
+    /// Sequence number of the last state sent
+    send_seq: u64,
+    /// Last state sent, kept for full resends
+    last_sent: Option<Vec<u8>>,
+    /// Highest sequence number acknowledged by the peer
+    last_acked: u64,
+    /// Next `encode` must send full state
+    force_full: bool,
+    /// Sequence number of the last state applied
+    recv_seq: Option<u64>,
+    /// A resync was requested and deltas are dropped until a full state arrives
+    awaiting_full: bool,
+}
+
+impl FluxSocketCodec {
+    /// Create a new codec
+    pub fn new() -> Self {
+        Self {
+            session: FluxStreamSession::new(),
+            send_seq: 0,
+            last_sent: None,
+            last_acked: 0,
+            force_full: true,
+            recv_seq: None,
+            awaiting_full: false,
+        }
+    }
+
+    /// Encode a new state, as a delta when the peer can apply one
+    pub fn encode(&mut self, json: &[u8]) -> Result<Vec<u8>> {
+        let full = self.force_full;
+        if full {
+            self.session.force_full_next();
+        }
+        let payload = self.session.update(json)?;
+
+        let base_seq = self.send_seq;
+        self.send_seq += 1;
+        self.force_full = false;
+        self.last_sent = Some(json.to_vec());
+
+        let mut frame = Vec::with_capacity(payload.len() + 12);
+        if full {
+            frame.push(MSG_STATE_FULL);
+            encode_varint(self.send_seq, &mut frame);
+        } else {
+            frame.push(MSG_STATE_DELTA);
+            encode_varint(self.send_seq, &mut frame);
+            encode_varint(base_seq, &mut frame);
+        }
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    /// Decode a frame from the peer
+    pub fn decode(&mut self, frame: &[u8]) -> Result<SocketEvent> {
+        let (&kind, rest) = frame
+            .split_first()
+            .ok_or_else(|| Error::DecodeError("Empty socket frame".into()))?;
+        let (seq, len) = decode_varint(rest)?;
+        let rest = &rest[len..];
+
+        match kind {
+            MSG_STATE_FULL => {
+                if self.recv_seq.is_some_and(|applied| seq <= applied) {
+                    return Ok(SocketEvent::Ignored);
+                }
+                self.apply(seq, rest)
+            }
+            MSG_STATE_DELTA => {
+                let (base_seq, len) = decode_varint(rest)?;
+                let payload = &rest[len..];
+
+                if self.recv_seq.is_some_and(|applied| seq <= applied) {
+                    return Ok(SocketEvent::Ignored);
+                }
+                if self.awaiting_full {
+                    return Ok(SocketEvent::Ignored);
+                }
+                if self.recv_seq != Some(base_seq) {
+                    return Ok(SocketEvent::OutOfSync { resync: self.request_full_state() });
+                }
+                match self.apply(seq, payload) {
+                    Ok(event) => Ok(event),
+                    Err(_) => Ok(SocketEvent::OutOfSync { resync: self.request_full_state() }),
+                }
+            }
+            MSG_ACK => {
+                self.last_acked = self.last_acked.max(seq);
+                Ok(SocketEvent::Acked(seq))
+            }
+            MSG_RESYNC => {
+                self.force_full = true;
+                let full = match self.last_sent.take() {
+                    Some(json) => Some(self.encode(&json)?),
+                    None => None,
+                };
+                Ok(SocketEvent::ResyncRequested { full })
+            }
+            MSG_SUBSCRIBE => {
+                let pointer = std::str::from_utf8(rest).map_err(|e| Error::DecodeError(e.to_string()))?;
+                self.session.subscribe(pointer)?;
+                self.force_full = true;
+                let full = match self.last_sent.take() {
+                    Some(json) => Some(self.encode(&json)?),
+                    None => None,
+                };
+                Ok(SocketEvent::Subscribed { pointer: pointer.to_string(), full })
+            }
+            _ => Err(Error::DecodeError(format!("Unknown socket frame type: {:#04x}", kind))),
+        }
+    }
+
+    /// Build a control frame asking the peer for its full state
+    ///
+    /// Deltas are ignored until the full state arrives.
+    pub fn request_full_state(&mut self) -> Vec<u8> {
+        self.awaiting_full = true;
+        let mut frame = vec![MSG_RESYNC];
+        encode_varint(self.recv_seq.unwrap_or(0), &mut frame);
+        frame
+    }
+
+    /// Build a control frame asking the peer to send only the part of its
+    /// state at `pointer` (a JSON Pointer such as `/users`, `""` for all of it)
+    ///
+    /// States decoded from then on are that part alone. Deltas are ignored
+    /// until the first such state arrives.
+    pub fn subscribe(&mut self, pointer: &str) -> Vec<u8> {
+        let mut frame = self.request_full_state();
+        frame[0] = MSG_SUBSCRIBE;
+        frame.extend_from_slice(pointer.as_bytes());
+        frame
+    }
+
+    /// Sequence number of the last state sent
+    pub fn send_seq(&self) -> u64 {
+        self.send_seq
+    }
+
+    /// Highest sequence number acknowledged by the peer
+    pub fn last_acked(&self) -> u64 {
+        self.last_acked
+    }
+
+    /// Number of sent states not yet acknowledged
+    pub fn unacked(&self) -> u64 {
+        self.send_seq.saturating_sub(self.last_acked)
+    }
+
+    /// Sequence number of the last state applied, if any
+    pub fn recv_seq(&self) -> Option<u64> {
+        self.recv_seq
+    }
+
+    /// Underlying stream session (for statistics)
+    pub fn session(&self) -> &FluxStreamSession {
+        &self.session
+    }
+
+    fn apply(&mut self, seq: u64, payload: &[u8]) -> Result<SocketEvent> {
+        let json = self.session.receive(payload)?;
+        self.recv_seq = Some(seq);
+        self.awaiting_full = false;
+
+        let mut ack = vec![MSG_ACK];
+        encode_varint(seq, &mut ack);
+        Ok(SocketEvent::State { seq, json, ack })
+    }
+}
+
+impl Default for FluxSocketCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(event: SocketEvent) -> (u64, serde_json::Value, Vec<u8>) {
+        match event {
+            SocketEvent::State { seq, json, ack } => (seq, serde_json::from_slice(&json).unwrap(), ack),
+            other => panic!("expected state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_in_order_delivery_and_acks() {
+        let mut server = FluxSocketCodec::new();
+        let mut client = FluxSocketCodec::new();
+
+        for i in 0..5 {
+            let json = format!(r#"{{"count":{},"name":"room"}}"#, i);
+            let frame = server.encode(json.as_bytes()).unwrap();
+            if i > 0 {
+                assert_eq!(frame[0], MSG_STATE_DELTA);
+            }
+
+            let (seq, value, ack) = state(client.decode(&frame).unwrap());
+            assert_eq!(seq, i + 1);
+            assert_eq!(value["count"], i);
+
+            assert_eq!(server.decode(&ack).unwrap(), SocketEvent::Acked(seq));
+        }
+        assert_eq!(server.unacked(), 0);
+    }
+
+    #[test]
+    fn test_dropped_delta_triggers_resync() {
+        let mut server = FluxSocketCodec::new();
+        let mut client = FluxSocketCodec::new();
+
+        let f1 = server.encode(br#"{"count":1}"#).unwrap();
+        let _dropped = server.encode(br#"{"count":2}"#).unwrap();
+        let f3 = server.encode(br#"{"count":3}"#).unwrap();
+
+        state(client.decode(&f1).unwrap());
+
+        let resync = match client.decode(&f3).unwrap() {
+            SocketEvent::OutOfSync { resync } => resync,
+            other => panic!("expected out of sync, got {:?}", other),
+        };
+
+        // Further deltas are ignored until the full state arrives
+        let f4 = server.encode(br#"{"count":4}"#).unwrap();
+        assert_eq!(client.decode(&f4).unwrap(), SocketEvent::Ignored);
+
+        let full = match server.decode(&resync).unwrap() {
+            SocketEvent::ResyncRequested { full: Some(full) } => full,
+            other => panic!("expected full resend, got {:?}", other),
+        };
+        assert_eq!(full[0], MSG_STATE_FULL);
+
+        let (_, value, _) = state(client.decode(&full).unwrap());
+        assert_eq!(value["count"], 4);
+
+        // Back in sync: deltas apply again
+        let f6 = server.encode(br#"{"count":5}"#).unwrap();
+        let (_, value, _) = state(client.decode(&f6).unwrap());
+        assert_eq!(value["count"], 5);
+    }
+
+    #[test]
+    fn test_reordered_and_duplicate_frames() {
+        let mut server = FluxSocketCodec::new();
+        let mut client = FluxSocketCodec::new();
+
+        let f1 = server.encode(br#"{"v":1}"#).unwrap();
+        let f2 = server.encode(br#"{"v":2}"#).unwrap();
+
+        state(client.decode(&f1).unwrap());
+        state(client.decode(&f2).unwrap());
+
+        // Replays of already applied states are ignored
+        assert_eq!(client.decode(&f1).unwrap(), SocketEvent::Ignored);
+        assert_eq!(client.decode(&f2).unwrap(), SocketEvent::Ignored);
+        assert_eq!(client.recv_seq(), Some(2));
+    }
+
+    #[test]
+    fn test_explicit_full_state_request() {
+        let mut server = FluxSocketCodec::new();
+        let mut client = FluxSocketCodec::new();
+
+        // Nothing sent yet: no full state to offer
+        let request = client.request_full_state();
+        assert_eq!(server.decode(&request).unwrap(), SocketEvent::ResyncRequested { full: None });
+
+        let f1 = server.encode(br#"{"v":1}"#).unwrap();
+        assert_eq!(f1[0], MSG_STATE_FULL);
+        state(client.decode(&f1).unwrap());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut server = FluxSocketCodec::new();
+        let mut client = FluxSocketCodec::new();
+        let admin = |i: u32| format!(r#"{{"audit":["login"],"users":[{{"id":{},"name":"alice"}}]}}"#, i);
+        state(client.decode(&server.encode(admin(1).as_bytes()).unwrap()).unwrap());
+
+        // A delta in flight when the subscription is made is dropped
+        let in_flight = server.encode(admin(2).as_bytes()).unwrap();
+        let request = client.subscribe("/users");
+        assert_eq!(client.decode(&in_flight).unwrap(), SocketEvent::Ignored);
+
+        let full = match server.decode(&request).unwrap() {
+            SocketEvent::Subscribed { pointer, full: Some(full) } if pointer == "/users" => full,
+            other => panic!("expected full resend, got {:?}", other),
+        };
+        let (_, value, _) = state(client.decode(&full).unwrap());
+        assert_eq!(value, serde_json::json!([{"id": 2, "name": "alice"}]));
+
+        let delta = server.encode(admin(3).as_bytes()).unwrap();
+        assert_eq!(delta[0], MSG_STATE_DELTA);
+        let (_, value, _) = state(client.decode(&delta).unwrap());
+        assert_eq!(value[0]["id"], 3);
+
+        let mut invalid = client.subscribe("users");
+        assert!(server.decode(&invalid).is_err());
+        invalid.truncate(2);
+        invalid.push(0xFF);
+        assert!(server.decode(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_malformed_frames() {
+        let mut codec = FluxSocketCodec::new();
+        assert!(codec.decode(&[]).is_err());
+        assert!(codec.decode(&[0x7F, 0x01]).is_err());
+    }
+}
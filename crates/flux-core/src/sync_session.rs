@@ -0,0 +1,302 @@
+//! Thread-safe FLUX session
+//!
+//! `FluxSession` takes `&mut self`, so a server sharing one between request
+//! handlers has to put it behind a mutex and compress one message at a
+//! time. `SyncFluxSession` holds several sessions (shards) instead, each
+//! behind its own mutex; a call takes a free shard, preferring the one its
+//! thread used before so that shard's encoder and scratch buffers stay warm.
+//!
+//! Schemas live in a registry behind a `RwLock` that all shards share. A
+//! schema is registered, and given its ID, once; a shard meeting a schema
+//! another shard registered copies it from the registry. Lookups far
+//! outnumber registrations, so the lock is almost always taken for reading.
+//!
+//! As with a mutex around a `FluxSession`, frames from concurrent calls
+//! reach the peer in whatever order the application sends them, so a frame
+//! may reference a schema carried by one the peer hasn't decoded yet. The
+//! peer recovers with `FluxSession::schema_request`, answered here by
+//! `answer_schema_request`.
+//!
+//! State that follows the order of messages can't be split between shards:
+//! configs enabling value dictionaries, LZ history, a bounded schema cache
+//! or a memory budget are rejected.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, TryLockError};
+use std::thread;
+
+use crate::schema::{Schema, SchemaCache};
+use crate::{Error, FluxConfig, FluxSession, MessageOptions, Result, SessionStats};
+
+/// FLUX session for concurrent compression from many threads
+///
+/// ```rust,ignore
+/// let session = Arc::new(SyncFluxSession::new());
+/// // in each handler
+/// let frame = session.compress(body)?;
+/// ```
+pub struct SyncFluxSession {
+    shards: Box<[Mutex<FluxSession>]>,
+    schemas: Arc<SharedSchemas>,
+}
+
+impl SyncFluxSession {
+    /// Create a session with the default configuration and one shard per CPU
+    pub fn new() -> Self {
+        Self::build(FluxConfig::default(), default_shards())
+    }
+
+    /// Create a session with custom configuration and one shard per CPU
+    ///
+    /// Fails with `Error::InvalidConfig` for configs whose state can't be
+    /// split between shards.
+    pub fn with_config(config: FluxConfig) -> Result<Self> {
+        Self::with_shards(config, default_shards())
+    }
+
+    /// Create a session with custom configuration and `shards` shards
+    pub fn with_shards(config: FluxConfig, shards: usize) -> Result<Self> {
+        if shards == 0 {
+            return Err(invalid("shards", "must be greater than zero"));
+        }
+        if config.value_dictionary {
+            return Err(invalid("value_dictionary", "dictionary entries depend on message order"));
+        }
+        if config.lz_history > 0 {
+            return Err(invalid("lz_history", "history matches depend on message order"));
+        }
+        if config.max_cached_schemas > 0 {
+            return Err(invalid("max_cached_schemas", "evictions depend on message order"));
+        }
+        if config.memory_budget > 0 {
+            return Err(invalid("memory_budget", "evictions depend on message order"));
+        }
+        Ok(Self::build(config, shards))
+    }
+
+    fn build(config: FluxConfig, shards: usize) -> Self {
+        let schemas = Arc::new(SharedSchemas::default());
+        let shards = (0..shards)
+            .map(|_| {
+                let mut session = FluxSession::with_config(config.clone());
+                session.shared_schemas = Some(schemas.clone());
+                Mutex::new(session)
+            })
+            .collect();
+        Self { shards, schemas }
+    }
+
+    /// Compress JSON data
+    pub fn compress(&self, input: &[u8]) -> Result<Vec<u8>> {
+        self.shard().compress(input)
+    }
+
+    /// Compress JSON data with per-message options
+    pub fn compress_with(&self, input: &[u8], opts: &MessageOptions) -> Result<Vec<u8>> {
+        self.shard().compress_with(input, opts)
+    }
+
+    /// Decompress a FLUX frame
+    pub fn decompress(&self, input: &[u8]) -> Result<Vec<u8>> {
+        self.shard().decompress(input)
+    }
+
+    /// Answer a peer's `schema_request` with the schemas it asked for
+    pub fn answer_schema_request(&self, request: &[u8]) -> Result<Vec<u8>> {
+        self.shard().answer_schema_request(request)
+    }
+
+    /// Cache the schemas carried by an answer to `schema_request`
+    pub fn load_schemas(&self, reply: &[u8]) -> Result<usize> {
+        self.shard().load_schemas(reply)
+    }
+
+    /// Number of shards
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Statistics summed over all shards, counting each shared schema once
+    pub fn stats(&self) -> SessionStats {
+        let mut total = SessionStats::default();
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+            total += shard.stats();
+        }
+        total.schemas_cached = self.schemas.len();
+        total
+    }
+
+    /// Lock a free shard, or wait for this thread's own
+    fn shard(&self) -> MutexGuard<'_, FluxSession> {
+        let home = home_shard() % self.shards.len();
+        for i in 0..self.shards.len() {
+            match self.shards[(home + i) % self.shards.len()].try_lock() {
+                Ok(shard) => return shard,
+                Err(TryLockError::Poisoned(e)) => return e.into_inner(),
+                Err(TryLockError::WouldBlock) => {}
+            }
+        }
+        self.shards[home].lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for SyncFluxSession {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Schema registry the shards of a `SyncFluxSession` share
+#[derive(Default)]
+pub(crate) struct SharedSchemas {
+    cache: RwLock<SchemaCache>,
+}
+
+impl SharedSchemas {
+    pub(crate) fn get(&self, id: u32) -> Option<Schema> {
+        self.cache.read().unwrap_or_else(PoisonError::into_inner).get(id).cloned()
+    }
+
+    pub(crate) fn get_by_hash(&self, hash: u64) -> Option<(u32, Schema)> {
+        let cache = self.cache.read().unwrap_or_else(PoisonError::into_inner);
+        cache.get_by_hash(hash).map(|schema| (schema.id, schema.clone()))
+    }
+
+    /// ID of `schema`, registering it unless another shard got there first
+    pub(crate) fn register(&self, schema: &Schema) -> u32 {
+        let mut cache = self.cache.write().unwrap_or_else(PoisonError::into_inner);
+        match cache.get_by_hash(schema.hash) {
+            Some(existing) => existing.id,
+            None => cache.register(schema.clone()),
+        }
+    }
+
+    /// Cache a schema a peer sent under `id`
+    pub(crate) fn insert(&self, id: u32, schema: &Schema) {
+        if self.get(id).is_some_and(|known| known.hash == schema.hash) {
+            return;
+        }
+        self.cache.write().unwrap_or_else(PoisonError::into_inner).insert(id, schema.clone());
+    }
+
+    fn len(&self) -> usize {
+        self.cache.read().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+fn default_shards() -> usize {
+    thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+/// Shard a thread tries first, spreading threads evenly
+fn home_shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local!(static HOME: usize = NEXT.fetch_add(1, Ordering::Relaxed));
+    HOME.with(|home| *home)
+}
+
+fn invalid(field: &'static str, reason: &str) -> Error {
+    Error::InvalidConfig {
+        field,
+        reason: reason.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RawFrame;
+
+    fn message(kind: usize, n: usize) -> Vec<u8> {
+        match kind {
+            0 => format!(r#"{{"active":true,"id":{},"name":"user{}"}}"#, n, n),
+            1 => format!(r#"{{"items":["a","b"],"order":{},"total":{}.5}}"#, n, n),
+            _ => format!(r#"[{{"level":"info","ts":{}}},{{"level":"warn","ts":{}}}]"#, n, n + 1),
+        }
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_concurrent_compress() {
+        let session = SyncFluxSession::with_shards(FluxConfig::default(), 4).unwrap();
+        let frames: Vec<(Vec<u8>, Vec<u8>)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|t| {
+                    let session = &session;
+                    scope.spawn(move || {
+                        (0..50)
+                            .map(|n| {
+                                let input = message((t + n) % 3, n);
+                                (session.compress(&input).unwrap(), input)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+
+        // One ID per schema, whichever shard registered it
+        let mut ids: Vec<u32> = frames.iter().map(|(frame, _)| RawFrame::parse(frame).unwrap().header.schema_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(session.stats().schemas_cached, 3);
+        assert_eq!(session.stats().messages_processed, 400);
+
+        for (frame, input) in &frames {
+            assert_eq!(&session.decompress(frame).unwrap(), input);
+        }
+
+        // A peer missing a schema fetches it with a schema request
+        let mut receiver = FluxSession::new();
+        for (frame, input) in &frames {
+            let output = match receiver.decompress(frame) {
+                Err(Error::SchemaNotFound(id)) => {
+                    let reply = session.answer_schema_request(&receiver.schema_request(&[id])).unwrap();
+                    receiver.load_schemas(&reply).unwrap();
+                    receiver.decompress(frame).unwrap()
+                }
+                output => output.unwrap(),
+            };
+            assert_eq!(&output, input);
+        }
+    }
+
+    #[test]
+    fn test_decompress_across_shards() {
+        let mut sender = FluxSession::new();
+        let frames: Vec<_> = (0..20).map(|n| sender.compress(&message(n % 2, n)).unwrap()).collect();
+
+        // Schemas cached by whichever shard decoded the frame carrying them
+        let receiver = SyncFluxSession::with_shards(FluxConfig::default(), 3).unwrap();
+        for (n, frame) in frames.iter().enumerate() {
+            assert_eq!(receiver.decompress(frame).unwrap(), message(n % 2, n));
+        }
+        for (n, frame) in frames.iter().enumerate() {
+            receiver.shards[n % 3].lock().unwrap().decompress(frame).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rejects_unshardable_configs() {
+        let cases = [
+            (FluxConfig { value_dictionary: true, ..Default::default() }, "value_dictionary"),
+            (FluxConfig { lz_history: 4096, ..Default::default() }, "lz_history"),
+            (FluxConfig { max_cached_schemas: 16, ..Default::default() }, "max_cached_schemas"),
+            (FluxConfig { memory_budget: 1 << 20, ..Default::default() }, "memory_budget"),
+        ];
+        for (config, expected) in cases {
+            let err = SyncFluxSession::with_config(config).err().unwrap();
+            assert!(matches!(err, Error::InvalidConfig { field, .. } if field == expected));
+        }
+        assert!(SyncFluxSession::with_shards(FluxConfig::default(), 0).is_err());
+    }
+
+    #[test]
+    fn test_is_send_and_sync() {
+        fn assert_sync<T: Send + Sync>() {}
+        assert_sync::<SyncFluxSession>();
+    }
+}
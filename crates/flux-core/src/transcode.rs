@@ -0,0 +1,518 @@
+//! Transcoding between FLUX and legacy content encodings
+//!
+//! Migration proxies sit between clients that speak gzip/deflate/zstd and
+//! services that speak FLUX. Instead of wiring three libraries together,
+//! `transcode` sniffs the input encoding, decodes it and re-encodes the
+//! JSON as FLUX (or any other target via `transcode_to`).
+//!
+//! Backends are feature-gated: enable `gzip` for gzip/deflate, `zstd`
+//! for zstd and `fastpack` for FastPack and APEX frames. Identity and FLUX
+//! are always available.
+//!
+//! `decompress_any` decodes whichever frame it is given, so one endpoint
+//! can take FLUX from some clients and FastPack or APEX from others.
+//!
+//! Decoding stops once the output exceeds a limit, by default
+//! `DEFAULT_MAX_DECOMPRESSED_SIZE`, so a small hostile input can't expand
+//! into gigabytes; `decode_with_limit` and `decompress_any_with_limit`
+//! take their own.
+
+use crate::config::DEFAULT_MAX_DECOMPRESSED_SIZE;
+use crate::frame::{FrameFlags, COMPACT_HEADER, RAW_PAYLOAD};
+use crate::{Error, Result, FLUX_MAGIC, FLUX_VERSION};
+
+/// gzip magic bytes (RFC 1952)
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// zstd frame magic (little-endian 0xFD2FB528)
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// FastPack frame magic
+const FASTPACK_MAGIC: [u8; 4] = *b"FPCK";
+
+/// APEX frame magic
+const APEX_MAGIC: [u8; 4] = *b"APEX";
+
+/// Content encodings understood by the transcoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    /// Plain, uncompressed bytes
+    Identity,
+    /// FLUX frame
+    Flux,
+    /// gzip (RFC 1952)
+    Gzip,
+    /// zlib-wrapped deflate (RFC 1950), as used by HTTP `deflate`
+    Deflate,
+    /// zstd frame
+    Zstd,
+    /// FastPack (LZ4-style) frame
+    FastPack,
+    /// FastPack APEX frame
+    Apex,
+}
+
+impl ContentEncoding {
+    /// Detect the encoding of `input` from its leading bytes
+    ///
+    /// Compact FLUX frames (`frame::COMPACT_HEADER`), which have no magic,
+    /// are recognised by their version byte, and raw ones also by their
+    /// flags byte. Anything without a recognised magic is treated as
+    /// `Identity`.
+    pub fn detect(input: &[u8]) -> Self {
+        if input.starts_with(&FLUX_MAGIC) || is_compact_frame(input) {
+            ContentEncoding::Flux
+        } else if input.starts_with(&GZIP_MAGIC) {
+            ContentEncoding::Gzip
+        } else if input.starts_with(&ZSTD_MAGIC) {
+            ContentEncoding::Zstd
+        } else if input.starts_with(&FASTPACK_MAGIC) {
+            ContentEncoding::FastPack
+        } else if input.starts_with(&APEX_MAGIC) {
+            ContentEncoding::Apex
+        } else if is_zlib_header(input) {
+            ContentEncoding::Deflate
+        } else {
+            ContentEncoding::Identity
+        }
+    }
+
+    /// HTTP `Content-Encoding` token
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Flux => "flux",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::FastPack => "fastpack",
+            ContentEncoding::Apex => "apex",
+        }
+    }
+
+    /// Parse an HTTP `Content-Encoding` token
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token.trim().to_ascii_lowercase().as_str() {
+            "identity" | "" => Some(ContentEncoding::Identity),
+            "flux" => Some(ContentEncoding::Flux),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            "zstd" => Some(ContentEncoding::Zstd),
+            "fastpack" => Some(ContentEncoding::FastPack),
+            "apex" => Some(ContentEncoding::Apex),
+            _ => None,
+        }
+    }
+}
+
+/// Check for the version byte of a compact frame, and for a raw frame the
+/// only flag it may carry
+///
+/// JSON text can't start with either version byte. UTF-8 text can't start
+/// with the compact one, and text starting with the raw one continues with
+/// a byte that isn't a valid flags byte.
+fn is_compact_frame(input: &[u8]) -> bool {
+    match input {
+        [version, ..] if *version == FLUX_VERSION | COMPACT_HEADER => true,
+        [version, flags, ..] if *version == FLUX_VERSION | COMPACT_HEADER | RAW_PAYLOAD => {
+            FrameFlags::from_bits(*flags).is_some_and(|flags| FrameFlags::CHECKSUM_PRESENT.contains(flags))
+        }
+        _ => false,
+    }
+}
+
+/// Check for a zlib header: CM = 8 (deflate) and FCHECK makes the pair a multiple of 31
+fn is_zlib_header(input: &[u8]) -> bool {
+    input.len() >= 2
+        && input[0] & 0x0F == 8
+        && input[0] >> 4 <= 7
+        && (((input[0] as u16) << 8) | input[1] as u16).is_multiple_of(31)
+}
+
+/// Transcode any supported input to FLUX
+///
+/// FLUX input is returned unchanged.
+pub fn transcode(input: &[u8]) -> Result<Vec<u8>> {
+    transcode_to(input, ContentEncoding::Flux)
+}
+
+/// Transcode any supported input to the `target` encoding
+///
+/// The source encoding is detected from magic bytes. If it already matches
+/// `target` the input is returned unchanged.
+pub fn transcode_to(input: &[u8], target: ContentEncoding) -> Result<Vec<u8>> {
+    let source = ContentEncoding::detect(input);
+    if source == target {
+        return Ok(input.to_vec());
+    }
+
+    let plain = decode(input, source)?;
+    encode(&plain, target)
+}
+
+/// Decompress a FLUX, FastPack or APEX frame (or gzip, deflate or zstd
+/// data), detected from its magic bytes
+///
+/// Input with no recognised magic is refused rather than passed through.
+pub fn decompress_any(input: &[u8]) -> Result<Vec<u8>> {
+    decompress_any_with_limit(input, DEFAULT_MAX_DECOMPRESSED_SIZE)
+}
+
+/// `decompress_any`, failing once the output exceeds `limit` bytes
+pub fn decompress_any_with_limit(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+    match ContentEncoding::detect(input) {
+        ContentEncoding::Identity => Err(Error::InvalidMagic),
+        encoding => decode_with_limit(input, encoding, limit),
+    }
+}
+
+/// Decode `input` from the given encoding back to plain bytes
+pub fn decode(input: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>> {
+    decode_with_limit(input, encoding, DEFAULT_MAX_DECOMPRESSED_SIZE)
+}
+
+/// Decode untrusted `input`, failing once the output exceeds `limit` bytes
+pub fn decode_with_limit(input: &[u8], encoding: ContentEncoding, limit: usize) -> Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Identity => Ok(input.to_vec()),
+        ContentEncoding::Flux => crate::decompress_with_limit(input, limit),
+        ContentEncoding::Gzip => gzip::decode(input, limit),
+        ContentEncoding::Deflate => gzip::decode_zlib(input, limit),
+        ContentEncoding::Zstd => zstd_backend::decode(input, limit),
+        ContentEncoding::FastPack => fastpack::decode(input, limit),
+        ContentEncoding::Apex => fastpack::decode_apex(input, limit),
+    }
+}
+
+/// Read all of `reader`, failing once it yields more than `limit` bytes
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+fn read_limited(reader: impl std::io::Read, limit: usize) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut out = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut out)?;
+    if out.len() > limit {
+        return Err(Error::OutputLimitExceeded { limit });
+    }
+    Ok(out)
+}
+
+/// Encode plain bytes with the given encoding
+pub fn encode(input: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Identity => Ok(input.to_vec()),
+        ContentEncoding::Flux => crate::compress(input),
+        ContentEncoding::Gzip => gzip::encode(input),
+        ContentEncoding::Deflate => gzip::encode_zlib(input),
+        ContentEncoding::Zstd => zstd_backend::encode(input),
+        ContentEncoding::FastPack => fastpack::encode(input),
+        ContentEncoding::Apex => fastpack::encode_apex(input),
+    }
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use super::read_limited;
+    use crate::Result;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    pub fn decode(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        read_limited(GzDecoder::new(input), limit)
+    }
+
+    pub fn encode(input: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input)?;
+        Ok(encoder.finish()?)
+    }
+
+    pub fn decode_zlib(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        read_limited(ZlibDecoder::new(input), limit)
+    }
+
+    pub fn encode_zlib(input: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+#[cfg(not(feature = "gzip"))]
+mod gzip {
+    use crate::{Error, Result};
+
+    fn disabled() -> Error {
+        Error::UnsupportedCodec("gzip/deflate (enable the `gzip` feature)".into())
+    }
+
+    pub fn decode(_input: &[u8], _limit: usize) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn encode(_input: &[u8]) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn decode_zlib(_input: &[u8], _limit: usize) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn encode_zlib(_input: &[u8]) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd_backend {
+    use super::read_limited;
+    use crate::Result;
+
+    pub fn decode(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        read_limited(zstd::Decoder::new(input)?, limit)
+    }
+
+    pub fn encode(input: &[u8]) -> Result<Vec<u8>> {
+        Ok(zstd::encode_all(input, 0)?)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd_backend {
+    use crate::{Error, Result};
+
+    fn disabled() -> Error {
+        Error::UnsupportedCodec("zstd (enable the `zstd` feature)".into())
+    }
+
+    pub fn decode(_input: &[u8], _limit: usize) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn encode(_input: &[u8]) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+}
+
+#[cfg(feature = "fastpack")]
+mod fastpack {
+    use crate::{Error, Result};
+    use fastpack_core::{apex_compress, apex_decompress_with_limit, compress, decompress_with_limit, ApexOptions, Options};
+
+    fn error(e: fastpack_core::Error) -> Error {
+        match e {
+            fastpack_core::Error::OutputLimitExceeded(limit) => Error::OutputLimitExceeded { limit },
+            e => Error::DecodeError(format!("FastPack: {}", e)),
+        }
+    }
+
+    pub fn decode(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        decompress_with_limit(input, limit).map_err(error)
+    }
+
+    pub fn encode(input: &[u8]) -> Result<Vec<u8>> {
+        compress(input, &Options::default()).map_err(error)
+    }
+
+    pub fn decode_apex(input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        apex_decompress_with_limit(input, limit).map_err(error)
+    }
+
+    pub fn encode_apex(input: &[u8]) -> Result<Vec<u8>> {
+        apex_compress(input, &ApexOptions::default()).map_err(error)
+    }
+}
+
+#[cfg(not(feature = "fastpack"))]
+mod fastpack {
+    use crate::{Error, Result};
+
+    fn disabled() -> Error {
+        Error::UnsupportedCodec("FastPack/APEX (enable the `fastpack` feature)".into())
+    }
+
+    pub fn decode(_input: &[u8], _limit: usize) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn encode(_input: &[u8]) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn decode_apex(_input: &[u8], _limit: usize) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+
+    pub fn encode_apex(_input: &[u8]) -> Result<Vec<u8>> {
+        Err(disabled())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &[u8] = br#"{"id":1,"name":"alice","active":true}"#;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(ContentEncoding::detect(JSON), ContentEncoding::Identity);
+        assert_eq!(ContentEncoding::detect(b"FLUX\x20"), ContentEncoding::Flux);
+        assert_eq!(ContentEncoding::detect(&[0x1F, 0x8B, 0x08]), ContentEncoding::Gzip);
+        assert_eq!(ContentEncoding::detect(&[0x28, 0xB5, 0x2F, 0xFD]), ContentEncoding::Zstd);
+        assert_eq!(ContentEncoding::detect(&[0x78, 0x9C]), ContentEncoding::Deflate);
+        assert_eq!(ContentEncoding::detect(&[0x78, 0x00]), ContentEncoding::Identity);
+        assert_eq!(ContentEncoding::detect(b"FPCK\x02"), ContentEncoding::FastPack);
+        assert_eq!(ContentEncoding::detect(b"APEX\x05"), ContentEncoding::Apex);
+        assert_eq!(ContentEncoding::detect(&[0xA1, 0x00]), ContentEncoding::Flux);
+        assert_eq!(ContentEncoding::detect(&[0xE1, 0x10]), ContentEncoding::Flux);
+        assert_eq!(ContentEncoding::detect(&[0xE1, 0x84]), ContentEncoding::Identity);
+    }
+
+    #[test]
+    fn test_decompress_any_compact_frames() {
+        let raw = crate::FluxConfig::builder().raw_threshold(100).build().unwrap();
+        let compact = crate::FluxConfig::builder().compact_header(true).build().unwrap();
+        for config in [raw, compact] {
+            let frame = crate::FluxSession::with_config(config).compress(JSON).unwrap();
+            assert!(crate::frame::RawFrame::parse(&frame).unwrap().header.is_compact());
+            assert_eq!(ContentEncoding::detect(&frame), ContentEncoding::Flux);
+            let decoded: serde_json::Value = serde_json::from_slice(&decompress_any(&frame).unwrap()).unwrap();
+            assert_eq!(decoded, serde_json::from_slice::<serde_json::Value>(JSON).unwrap());
+        }
+
+        // Text starting with the version byte isn't taken for one
+        assert_eq!(ContentEncoding::detect("\u{1100}".as_bytes()), ContentEncoding::Identity);
+    }
+
+    #[test]
+    fn test_tokens() {
+        for enc in [
+            ContentEncoding::Identity,
+            ContentEncoding::Flux,
+            ContentEncoding::Gzip,
+            ContentEncoding::Deflate,
+            ContentEncoding::Zstd,
+            ContentEncoding::FastPack,
+            ContentEncoding::Apex,
+        ] {
+            assert_eq!(ContentEncoding::from_token(enc.as_str()), Some(enc));
+        }
+        assert_eq!(ContentEncoding::from_token("br"), None);
+    }
+
+    #[test]
+    fn test_identity_to_flux_and_back() {
+        let flux = transcode(JSON).unwrap();
+        assert_eq!(ContentEncoding::detect(&flux), ContentEncoding::Flux);
+
+        let plain = transcode_to(&flux, ContentEncoding::Identity).unwrap();
+        let original: serde_json::Value = serde_json::from_slice(JSON).unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&plain).unwrap();
+        assert_eq!(original, decoded);
+    }
+
+    #[test]
+    fn test_flux_passthrough() {
+        let flux = transcode(JSON).unwrap();
+        assert_eq!(transcode(&flux).unwrap(), flux);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_roundtrip() {
+        for enc in [ContentEncoding::Gzip, ContentEncoding::Deflate] {
+            let compressed = encode(JSON, enc).unwrap();
+            assert_eq!(ContentEncoding::detect(&compressed), enc);
+
+            let flux = transcode(&compressed).unwrap();
+            let back = transcode_to(&flux, enc).unwrap();
+            let plain = decode(&back, enc).unwrap();
+
+            let original: serde_json::Value = serde_json::from_slice(JSON).unwrap();
+            let decoded: serde_json::Value = serde_json::from_slice(&plain).unwrap();
+            assert_eq!(original, decoded);
+        }
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_limit() {
+        let zeros = vec![0u8; 1 << 20];
+        for enc in [ContentEncoding::Gzip, ContentEncoding::Deflate] {
+            let bomb = encode(&zeros, enc).unwrap();
+            let err = decode_with_limit(&bomb, enc, 1000).unwrap_err();
+            assert!(matches!(err, Error::OutputLimitExceeded { limit: 1000 }));
+            assert_eq!(decode_with_limit(&bomb, enc, zeros.len()).unwrap(), zeros);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_limit() {
+        let zeros = vec![0u8; 1 << 20];
+        let bomb = encode(&zeros, ContentEncoding::Zstd).unwrap();
+        let err = decompress_any_with_limit(&bomb, 1000).unwrap_err();
+        assert!(matches!(err, Error::OutputLimitExceeded { limit: 1000 }));
+        assert_eq!(decompress_any_with_limit(&bomb, zeros.len()).unwrap(), zeros);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_roundtrip() {
+        let compressed = encode(JSON, ContentEncoding::Zstd).unwrap();
+        assert_eq!(ContentEncoding::detect(&compressed), ContentEncoding::Zstd);
+
+        let flux = transcode(&compressed).unwrap();
+        let plain = transcode_to(&flux, ContentEncoding::Identity).unwrap();
+
+        let original: serde_json::Value = serde_json::from_slice(JSON).unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&plain).unwrap();
+        assert_eq!(original, decoded);
+    }
+
+    #[cfg(feature = "fastpack")]
+    #[test]
+    fn test_decompress_any() {
+        let flux = crate::compress(JSON).unwrap();
+        let original: serde_json::Value = serde_json::from_slice(JSON).unwrap();
+        let decoded: serde_json::Value = serde_json::from_slice(&decompress_any(&flux).unwrap()).unwrap();
+        assert_eq!(original, decoded);
+
+        for enc in [ContentEncoding::FastPack, ContentEncoding::Apex] {
+            let frame = encode(JSON, enc).unwrap();
+            assert_eq!(ContentEncoding::detect(&frame), enc);
+            assert_eq!(decompress_any(&frame).unwrap(), JSON);
+        }
+
+        assert!(matches!(decompress_any(JSON), Err(crate::Error::InvalidMagic)));
+        assert!(matches!(decompress_any(b"FPCK\x02"), Err(crate::Error::DecodeError(_))));
+
+        // Frames expanding past the limit
+        let zeros = vec![0u8; 1 << 20];
+        for enc in [ContentEncoding::FastPack, ContentEncoding::Apex] {
+            let bomb = encode(&zeros, enc).unwrap();
+            let err = decompress_any_with_limit(&bomb, 1000).unwrap_err();
+            assert!(matches!(err, Error::OutputLimitExceeded { limit: 1000 }), "{:?}: {}", enc, err);
+            assert_eq!(decompress_any_with_limit(&bomb, zeros.len()).unwrap(), zeros);
+        }
+        let flux = crate::compress(&serde_json::to_vec(&vec![0; 10_000]).unwrap()).unwrap();
+        assert!(matches!(decompress_any_with_limit(&flux, 1000).unwrap_err().root(), Error::OutputLimitExceeded { .. }));
+    }
+
+    #[cfg(not(feature = "fastpack"))]
+    #[test]
+    fn test_disabled_fastpack_errors() {
+        let err = decompress_any(b"FPCK\x02\x00\x00\x00").unwrap_err();
+        assert!(matches!(err, crate::Error::UnsupportedCodec(_)));
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_disabled_backend_errors() {
+        let err = transcode(&[0x28, 0xB5, 0x2F, 0xFD, 0x00]).unwrap_err();
+        assert!(matches!(err, crate::Error::UnsupportedCodec(_)));
+    }
+}
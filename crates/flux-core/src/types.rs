@@ -1,7 +1,5 @@
 //! FLUX core types
 
-use std::collections::HashMap;
-
 /// Type ID constants
 pub mod type_id {
     pub const NULL: u8 = 0x00;
@@ -18,11 +16,17 @@ pub mod type_id {
     pub const ARRAY: u8 = 0x0B;
     pub const OBJECT: u8 = 0x0C;
     pub const UNION: u8 = 0x0D;
+    pub const UINT64: u8 = 0x0E;
     pub const TIMESTAMP: u8 = 0x10;
     pub const UUID: u8 = 0x11;
     pub const DECIMAL: u8 = 0x12;
 }
 
+/// Array elements merged when inferring an element type
+///
+/// Elements past the cap are assumed to fit the type of the ones before.
+pub const MAX_ARRAY_SAMPLES: usize = 256;
+
 /// Field type enumeration
 #[derive(Debug, Clone, PartialEq)]
 pub enum FieldType {
@@ -48,6 +52,8 @@ pub enum IntegerType {
     Int32,
     Int64,
     Varint,
+    /// Unsigned varint, for values above `i64::MAX`
+    UInt64,
 }
 
 /// Float type variants
@@ -68,6 +74,7 @@ impl FieldType {
             FieldType::Integer(IntegerType::Int32) => type_id::INT32,
             FieldType::Integer(IntegerType::Int64) => type_id::INT64,
             FieldType::Integer(IntegerType::Varint) => type_id::VARINT,
+            FieldType::Integer(IntegerType::UInt64) => type_id::UINT64,
             FieldType::Float(FloatType::Float32) => type_id::FLOAT32,
             FieldType::Float(FloatType::Float64) => type_id::FLOAT64,
             FieldType::String => type_id::STRING,
@@ -86,8 +93,28 @@ impl FieldType {
         matches!(self, FieldType::Union(types) if types.contains(&FieldType::Null))
     }
 
+    /// Type of an object member that may be absent: a union including `Null`
+    ///
+    /// The encoder marks absent members with their own union tag, so even
+    /// an all-null member becomes a union and keeps absent apart from `null`.
+    fn optional(&self) -> FieldType {
+        match self {
+            FieldType::Union(types) if types.contains(&FieldType::Null) => self.clone(),
+            FieldType::Union(types) => FieldType::Union(types.iter().cloned().chain([FieldType::Null]).collect()),
+            FieldType::Null => FieldType::Union(vec![FieldType::Null]),
+            t => FieldType::Union(vec![t.clone(), FieldType::Null]),
+        }
+    }
+
     /// Infer type from JSON value
     pub fn infer(value: &serde_json::Value) -> Self {
+        Self::infer_with(value, false)
+    }
+
+    /// Infer type from JSON value, keeping numbers exact if `lossless`
+    ///
+    /// See `merge_with` for what `lossless` changes.
+    pub fn infer_with(value: &serde_json::Value, lossless: bool) -> Self {
         match value {
             serde_json::Value::Null => FieldType::Null,
             serde_json::Value::Bool(_) => FieldType::Boolean,
@@ -103,47 +130,88 @@ impl FieldType {
                     } else {
                         FieldType::Integer(IntegerType::Int64)
                     }
+                } else if n.is_u64() {
+                    FieldType::Integer(IntegerType::UInt64)
                 } else {
                     FieldType::Float(FloatType::Float64)
                 }
             }
             serde_json::Value::String(_) => FieldType::String,
             serde_json::Value::Array(arr) => {
-                if arr.is_empty() {
-                    FieldType::Array(Box::new(FieldType::Null))
-                } else {
-                    // Use first element's type
-                    let elem_type = FieldType::infer(&arr[0]);
-                    FieldType::Array(Box::new(elem_type))
-                }
+                let elem_type = arr
+                    .iter()
+                    .take(MAX_ARRAY_SAMPLES)
+                    .map(|v| FieldType::infer_with(v, lossless))
+                    .reduce(|a, b| a.merge_with(&b, lossless))
+                    .unwrap_or(FieldType::Null);
+                FieldType::Array(Box::new(elem_type))
             }
             serde_json::Value::Object(obj) => {
-                let fields: Vec<(String, FieldType)> = obj
+                let mut fields: Vec<(String, FieldType)> = obj
                     .iter()
-                    .map(|(k, v)| (k.clone(), FieldType::infer(v)))
+                    .map(|(k, v)| (k.clone(), FieldType::infer_with(v, lossless)))
                     .collect();
+                // Canonical member order, whatever order the keys came in
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
                 FieldType::Object(fields)
             }
         }
     }
 
+    /// Sort object members by name, at every level
+    ///
+    /// Inferred types are always canonical, so objects that differ only in
+    /// key order get the same type (and schemas the same hash).
+    pub fn canonicalize(&mut self) {
+        match self {
+            FieldType::Array(elem) => elem.canonicalize(),
+            FieldType::Object(fields) => {
+                fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+                fields.iter_mut().for_each(|(_, t)| t.canonicalize());
+            }
+            FieldType::Union(types) => types.iter_mut().for_each(FieldType::canonicalize),
+            _ => {}
+        }
+    }
+
     /// Merge two types (for schema inference across samples)
     pub fn merge(&self, other: &FieldType) -> FieldType {
+        self.merge_with(other, false)
+    }
+
+    /// Merge two types, keeping numbers exact if `lossless`
+    ///
+    /// By default integers merged with floats widen to floats, so `1` comes
+    /// back as `1.0`, and values above `i64::MAX` merged with signed
+    /// integers widen to floats too, losing precision. With `lossless` such
+    /// pairs become unions instead, and every number decodes exactly as it
+    /// was written.
+    pub fn merge_with(&self, other: &FieldType, lossless: bool) -> FieldType {
         if self == other {
             return self.clone();
         }
 
-        // If one is null, make the other nullable
         match (self, other) {
-            (FieldType::Null, t) | (t, FieldType::Null) => {
-                if let FieldType::Union(types) = t {
-                    let mut new_types = types.clone();
-                    if !new_types.contains(&FieldType::Null) {
-                        new_types.push(FieldType::Null);
-                    }
-                    FieldType::Union(new_types)
+            // Unions absorb the other side one variant at a time
+            (FieldType::Union(types), t) | (t, FieldType::Union(types)) => {
+                let mut new_types = types.clone();
+                match t {
+                    FieldType::Union(others) => others.iter().for_each(|o| add_variant(&mut new_types, o, lossless)),
+                    t => add_variant(&mut new_types, t, lossless),
+                }
+                FieldType::Union(new_types)
+            }
+
+            // If one is null, make the other nullable
+            (FieldType::Null, t) | (t, FieldType::Null) => FieldType::Union(vec![t.clone(), FieldType::Null]),
+
+            // Unsigned + signed has no exact integer type
+            (FieldType::Integer(IntegerType::UInt64), FieldType::Integer(_))
+            | (FieldType::Integer(_), FieldType::Integer(IntegerType::UInt64)) => {
+                if lossless {
+                    FieldType::Union(vec![self.clone(), other.clone()])
                 } else {
-                    FieldType::Union(vec![t.clone(), FieldType::Null])
+                    FieldType::Float(FloatType::Float64)
                 }
             }
 
@@ -151,6 +219,7 @@ impl FieldType {
             (FieldType::Integer(a), FieldType::Integer(b)) => {
                 use IntegerType::*;
                 let wider = match (a, b) {
+                    (Varint, _) | (_, Varint) => Varint,
                     (Int64, _) | (_, Int64) => Int64,
                     (Int32, _) | (_, Int32) => Int32,
                     (Int16, _) | (_, Int16) => Int16,
@@ -161,43 +230,36 @@ impl FieldType {
 
             // Integer + Float = Float
             (FieldType::Integer(_), FieldType::Float(f))
-            | (FieldType::Float(f), FieldType::Integer(_)) => FieldType::Float(*f),
+            | (FieldType::Float(f), FieldType::Integer(_)) if !lossless => FieldType::Float(*f),
+
+            // Base64 in some samples only: plain strings are the common denominator
+            (FieldType::Binary, FieldType::String) | (FieldType::String, FieldType::Binary) => FieldType::String,
 
             // Arrays: merge element types
             (FieldType::Array(a), FieldType::Array(b)) => {
-                FieldType::Array(Box::new(a.merge(b)))
+                FieldType::Array(Box::new(a.merge_with(b, lossless)))
             }
 
             // Objects: merge fields
             (FieldType::Object(a), FieldType::Object(b)) => {
-                let mut merged: HashMap<String, FieldType> = HashMap::new();
-
-                for (name, typ) in a {
-                    merged.insert(name.clone(), typ.clone());
-                }
+                let mut fields = a.clone();
 
                 for (name, typ) in b {
-                    merged
-                        .entry(name.clone())
-                        .and_modify(|existing| *existing = existing.merge(typ))
-                        .or_insert_with(|| {
-                            // New field, might be nullable
-                            FieldType::Union(vec![typ.clone(), FieldType::Null])
-                        });
+                    match fields.iter_mut().find(|(n, _)| n == name) {
+                        Some((_, existing)) => *existing = existing.merge_with(typ, lossless),
+                        // New field, might be absent
+                        None => fields.push((name.clone(), typ.optional())),
+                    }
                 }
 
-                // Check if any field from 'a' is missing in 'b'
-                for (name, _) in a {
+                // Fields from 'a' missing in 'b' might be absent too
+                for (name, t) in &mut fields {
                     if !b.iter().any(|(n, _)| n == name) {
-                        merged.entry(name.clone()).and_modify(|t| {
-                            if !t.is_nullable() {
-                                *t = FieldType::Union(vec![t.clone(), FieldType::Null]);
-                            }
-                        });
+                        *t = t.optional();
                     }
                 }
 
-                let fields: Vec<_> = merged.into_iter().collect();
+                fields.sort_by(|(x, _), (y, _)| x.cmp(y));
                 FieldType::Object(fields)
             }
 
@@ -207,6 +269,25 @@ impl FieldType {
     }
 }
 
+/// Add `t` to a union, widening a variant of the same kind rather than adding a new one
+///
+/// Integers and floats stay separate variants so integers keep their form.
+fn add_variant(types: &mut Vec<FieldType>, t: &FieldType, lossless: bool) {
+    if types.contains(t) {
+        return;
+    }
+    for variant in types.iter_mut() {
+        if std::mem::discriminant(&*variant) == std::mem::discriminant(t) {
+            let merged = variant.merge_with(t, lossless);
+            if !matches!(merged, FieldType::Union(_)) {
+                *variant = merged;
+                return;
+            }
+        }
+    }
+    types.push(t.clone());
+}
+
 /// Runtime value representation
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
@@ -319,6 +400,53 @@ mod tests {
         assert!(merged.is_nullable());
     }
 
+    #[test]
+    fn test_array_element_merge() {
+        let infer = |v: serde_json::Value| FieldType::infer(&v);
+
+        assert_eq!(
+            infer(serde_json::json!([1, 300])),
+            FieldType::Array(Box::new(FieldType::Integer(IntegerType::Int16)))
+        );
+        assert_eq!(
+            infer(serde_json::json!([1, "a", null, 2.5])),
+            FieldType::Array(Box::new(FieldType::Union(vec![
+                FieldType::Integer(IntegerType::Int8),
+                FieldType::String,
+                FieldType::Null,
+                FieldType::Float(FloatType::Float64),
+            ])))
+        );
+        assert_eq!(infer(serde_json::json!([])), FieldType::Array(Box::new(FieldType::Null)));
+
+        // Only the first MAX_ARRAY_SAMPLES elements are looked at
+        let mut arr = vec![serde_json::json!(1); MAX_ARRAY_SAMPLES];
+        arr.push(serde_json::json!("late"));
+        assert_eq!(
+            infer(serde_json::Value::Array(arr)),
+            FieldType::Array(Box::new(FieldType::Integer(IntegerType::Int8)))
+        );
+    }
+
+    #[test]
+    fn test_union_merge_flattens() {
+        let nullable = FieldType::Integer(IntegerType::Int8).merge(&FieldType::Null);
+        assert_eq!(
+            nullable.merge(&FieldType::Integer(IntegerType::Int32)),
+            FieldType::Union(vec![FieldType::Integer(IntegerType::Int32), FieldType::Null])
+        );
+        let mixed = nullable.merge(&FieldType::String).merge(&FieldType::Boolean.merge(&FieldType::Null));
+        assert_eq!(
+            mixed,
+            FieldType::Union(vec![
+                FieldType::Integer(IntegerType::Int8),
+                FieldType::Null,
+                FieldType::String,
+                FieldType::Boolean,
+            ])
+        );
+    }
+
     #[test]
     fn test_value_roundtrip() {
         let json: serde_json::Value = serde_json::json!({
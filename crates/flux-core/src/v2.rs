@@ -0,0 +1,100 @@
+//! Stable API facade
+//!
+//! Everything re-exported here follows semver: within a major version it is
+//! only ever added to, and an item that gets renamed keeps working under its
+//! old name as a `#[deprecated]` shim for at least one minor release before
+//! removal. Depend on `flux_core::v2::*` to upgrade without surprises.
+//!
+//! Modules not re-exported here (`encoding`, `columnar`, `cursor`, `lz`,
+//! `entropy`, frame internals, ...) are building blocks that may change in
+//! any minor release.
+//!
+//! # Feature flags
+//!
+//! Features only add API and never change the behaviour or wire format of
+//! what is already there, so any combination that compiles is safe to
+//! enable from any crate in a dependency graph:
+//!
+//! - `gzip`, `zstd`: backends for `transcode`
+//! - `fastpack`: FastPack and APEX frames in `transcode` and `decompress_any`
+//! - `msgpack`: `compress_msgpack` / `decompress_msgpack`
+//! - `cbor`: `compress_cbor` / `decompress_cbor`
+//! - `crypto`: `FluxSession::with_encryption` and the `Keyring` key provider
+//! - `async`: `FluxCodec`, a `tokio_util` codec for framed transports
+//! - `arrow`: Arrow record batch and IPC conversions on
+//!   `columnar::ColumnarBlock` (outside this facade)
+//! - `parquet`: `read_parquet` and `FluxParquetWriter`; implies `arrow`
+//!
+//! Combinations that cannot work are rejected at compile time with a
+//! message naming the conflict, rather than failing at link or run time.
+//!
+//! ```rust,ignore
+//! use flux_core::v2::{FluxConfig, FluxSession, Profile};
+//!
+//! let config = FluxConfig::builder().profile(Profile::Realtime).build()?;
+//! let mut session = FluxSession::with_config(config);
+//! ```
+
+#[cfg(all(feature = "zstd", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!("the `zstd` feature links libzstd, which cannot be built for wasm32-unknown-unknown; use `gzip` there");
+#[cfg(all(feature = "crypto", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!("the `crypto` feature draws nonces from the OS RNG, which wasm32-unknown-unknown does not have");
+
+pub use crate::{
+    compress, compress_to, decompress, decompress_all, decompress_projected, decompress_to, decompress_with_limit,
+};
+pub use crate::{Error, Result, FLUX_MAGIC, FLUX_VERSION};
+
+// Sessions
+pub use crate::{FluxSession, FluxStreamSession, MemoryUsage, SessionStats, StreamMemoryUsage, StreamStats, SyncFluxSession};
+pub use crate::{StreamReceiver, StreamReceiverStats, StreamSender, DEFAULT_CHANNEL, MAX_UNACKED_STATES};
+pub use crate::{SessionManager, SessionManagerStats};
+pub use crate::config::{
+    FluxConfig, FluxConfigBuilder, FluxLevel, MessageOptions, PatchFormat, Profile, StreamConfig,
+    DEFAULT_MAX_DECOMPRESSED_SIZE, DEFAULT_MAX_DEPTH, MAX_DEPTH,
+};
+pub use crate::lz::LzParams;
+pub use crate::scratch::ScratchBuffers;
+
+// Frames and schemas
+pub use crate::frame::{needs_more_bytes, read_metadata, FrameMetadata, FrameReader};
+pub use crate::schema::{
+    Compatibility, EvictionPolicy, FieldDef, RootKind, Schema, SchemaBuilder, SchemaCache, SchemaChange, SchemaDelta,
+};
+pub use crate::types::FieldType;
+
+// Observability and time
+pub use crate::clock::{Clock, ManualClock, NoClock};
+pub use crate::observer::{MessageStats, Operation, StageTimings, StatsObserver};
+
+// Streaming transports
+pub use crate::socket::{FluxSocketCodec, SocketEvent};
+pub use crate::transcode::{decompress_any, transcode, transcode_to, ContentEncoding};
+
+#[cfg(feature = "msgpack")]
+pub use crate::msgpack::{compress_msgpack, decompress_msgpack};
+#[cfg(feature = "cbor")]
+pub use crate::cbor::{compress_cbor, decompress_cbor};
+#[cfg(feature = "async")]
+pub use crate::codec::FluxCodec;
+#[cfg(feature = "crypto")]
+pub use crate::crypto::{Key, KeyProvider, Keyring};
+#[cfg(feature = "parquet")]
+pub use crate::parquet::{read_parquet, FluxParquetWriter};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facade_roundtrip() {
+        let config = FluxConfig::builder().profile(Profile::Realtime).build().unwrap();
+        let mut session = FluxSession::with_config(config);
+        let json = br#"{"id":1,"name":"alice"}"#;
+        let frame = session.compress(json).unwrap();
+        assert_eq!(decompress(&frame).unwrap(), json);
+
+        let stats: &SessionStats = session.stats();
+        assert!(stats.schema_bytes > 0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Golden frames in `testdata/golden/`
+//!
+//! Each vector pairs a plaintext input (`.json` for one message, `.ndjson`
+//! for a session sending one message per line) with the frames produced
+//! for it, one directory per format version (`2.1/<vector>.flux`, frames
+//! back to back). The frames are frozen: those of every supported version
+//! must keep decoding to their input, and the encoder must keep producing
+//! the current version's byte for byte. A change to the wire format bumps
+//! `FLUX_VERSION` and writes the new version's directory with
+//!
+//! ```text
+//! FLUX_BLESS=1 cargo test -p flux-core --test golden
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use flux_core::{negotiate_version, FluxConfig, FluxLevel, FluxSession, FrameReader, FLUX_VERSION, SUPPORTED_VERSIONS};
+use serde_json::Value;
+
+/// Vector name, input file and the configuration it was compressed with
+fn vectors() -> Vec<(&'static str, &'static str, FluxConfig)> {
+    let build = |builder: flux_core::FluxConfigBuilder| builder.build().unwrap();
+    vec![
+        ("simple", "simple.json", FluxConfig::default()),
+        ("simple_no_checksum", "simple.json", build(FluxConfig::builder().checksum(false))),
+        ("simple_plain", "simple.json", build(FluxConfig::builder().columnar(false).entropy(false))),
+        ("nested", "nested.json", FluxConfig::default()),
+        ("records", "records.json", FluxConfig::default()),
+        ("records_dictionary", "records.json", build(FluxConfig::builder().value_dictionary(true))),
+        ("metrics", "metrics.json", FluxConfig::default()),
+        ("metrics_rows", "metrics.json", build(FluxConfig::builder().columnar(false))),
+        ("metrics_max", "metrics.json", FluxConfig::with_level(FluxLevel::Max)),
+        ("numbers_lossless", "numbers.json", build(FluxConfig::builder().lossless_numbers(true))),
+        ("session", "session.ndjson", FluxConfig::default()),
+        ("small_compact", "small.ndjson", build(FluxConfig::builder().compact_header(true))),
+        ("small_compact_no_checksum", "small.ndjson", build(FluxConfig::builder().compact_header(true).checksum(false))),
+        ("small_raw", "small.ndjson", build(FluxConfig::builder().compact_header(true).raw_threshold(100))),
+    ]
+}
+
+/// Whether there are frames of `version` for a vector compressed with `config`
+///
+/// Compact and raw frames came with 2.1.
+fn has_frames(version: u8, config: &FluxConfig) -> bool {
+    version >= 0x21 || !(config.compact_header || config.raw_threshold > 0)
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../testdata/golden")
+}
+
+/// Directory of the frames of `version`, e.g. `2.1`
+fn version_dir(version: u8) -> PathBuf {
+    golden_dir().join(format!("{}.{}", version >> 4, version & 0x0F))
+}
+
+/// The messages of an input file
+fn messages(dir: &Path, input: &str) -> Vec<Vec<u8>> {
+    let data = fs::read(dir.join(input)).unwrap();
+    if input.ends_with(".ndjson") {
+        data.split(|&b| b == b'\n').filter(|line| !line.is_empty()).map(<[u8]>::to_vec).collect()
+    } else {
+        vec![data]
+    }
+}
+
+fn parse(json: &[u8]) -> Value {
+    serde_json::from_slice(json).unwrap()
+}
+
+#[test]
+fn golden_frames_decode() {
+    let dir = golden_dir();
+    for &version in SUPPORTED_VERSIONS {
+        let version_dir = version_dir(version);
+        for (name, input, config) in vectors().into_iter().filter(|(_, _, config)| has_frames(version, config)) {
+            let path = version_dir.join(format!("{}.flux", name));
+            let golden = fs::read(&path).unwrap_or_else(|e| panic!("{}: {} (regenerate with FLUX_BLESS=1)", path.display(), e));
+            let mut session = FluxSession::with_config(config);
+            let frames = FrameReader::new(&golden).collect::<Result<Vec<_>, _>>().unwrap();
+            let expected = messages(&dir, input);
+            assert_eq!(frames.len(), expected.len(), "{}: frame count", path.display());
+
+            for (i, (frame, message)) in frames.iter().zip(&expected).enumerate() {
+                assert_eq!(frame.header.format_version(), version, "{}: frame {} version", path.display(), i);
+                let output = session
+                    .decompress(frame.bytes)
+                    .unwrap_or_else(|e| panic!("{}: frame {}: {}", path.display(), i, e));
+                assert_eq!(parse(&output), parse(message), "{}: frame {}", path.display(), i);
+            }
+        }
+    }
+}
+
+#[test]
+fn golden_frames_encode() {
+    let dir = golden_dir();
+    let version_dir = version_dir(FLUX_VERSION);
+    let bless = std::env::var_os("FLUX_BLESS").is_some();
+    for (name, input, config) in vectors() {
+        let mut session = FluxSession::with_config(config);
+        let frames: Vec<u8> = messages(&dir, input).iter().flat_map(|msg| session.compress(msg).unwrap()).collect();
+        let path = version_dir.join(format!("{}.flux", name));
+        if bless {
+            fs::create_dir_all(&version_dir).unwrap();
+            fs::write(&path, &frames).unwrap();
+            continue;
+        }
+        let golden = fs::read(&path).unwrap_or_else(|e| panic!("{}: {} (regenerate with FLUX_BLESS=1)", path.display(), e));
+        assert!(
+            frames == golden,
+            "{}: encoder output changed ({} bytes, golden {}); if the format changed on purpose, bump FLUX_VERSION and regenerate with FLUX_BLESS=1",
+            path.display(),
+            frames.len(),
+            golden.len()
+        );
+    }
+}
+
+#[test]
+fn golden_version_negotiation() {
+    assert!(SUPPORTED_VERSIONS.contains(&FLUX_VERSION));
+    assert_eq!(negotiate_version(&[0x10, FLUX_VERSION, 0xFF]), Some(FLUX_VERSION));
+    assert_eq!(negotiate_version(&[0x20]), Some(0x20));
+    assert_eq!(negotiate_version(&[0xFF]), None);
+}
@@ -0,0 +1,27 @@
+//! Replays minimized fuzz crashes from `tests/regressions/<target>/`
+//!
+//! Files are added by `cargo xtask fuzz`; each must decode without panicking.
+
+use std::fs;
+use std::path::Path;
+
+use flux_core::fuzzing;
+
+#[test]
+fn fuzz_regressions() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/regressions");
+    let Ok(dirs) = fs::read_dir(&root) else {
+        return;
+    };
+
+    for dir in dirs.map(|d| d.unwrap().path()).filter(|p| p.is_dir()) {
+        let target = dir.file_name().unwrap().to_string_lossy().into_owned();
+        let harness = fuzzing::harness(&target)
+            .unwrap_or_else(|| panic!("no fuzz harness named `{}`", target));
+
+        for file in fs::read_dir(&dir).unwrap().map(|f| f.unwrap().path()) {
+            let data = fs::read(&file).unwrap();
+            harness(&data);
+        }
+    }
+}
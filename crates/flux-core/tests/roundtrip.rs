@@ -0,0 +1,224 @@
+//! Property-based roundtrips through the whole pipeline
+//!
+//! Arbitrary JSON documents — deep nesting, extreme numbers, any Unicode —
+//! must come back from every path equal to what went in. Values are
+//! compared after parsing, since FLUX may reorder object keys. Unless
+//! `lossless_numbers` is set, integers sharing a field with floats may come
+//! back as floats, so numbers are then compared by value.
+
+use flux_core::{compress, decompress, FluxConfig, FluxLevel, FluxSession, FluxStreamSession, Profile, StreamConfig};
+use proptest::prelude::*;
+use serde_json::{Map, Number, Value};
+
+fn number() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        any::<i64>().prop_map(Value::from),
+        any::<u64>().prop_map(Value::from),
+        prop_oneof![
+            any::<f64>(),
+            Just(0.0),
+            Just(-0.0),
+            Just(f64::MIN_POSITIVE),
+            Just(f64::MAX),
+            Just(f64::MIN),
+            Just(1e-300),
+            Just(0.1),
+        ]
+        .prop_filter_map("JSON numbers are finite", Number::from_f64)
+        .prop_map(Value::Number),
+        Just(Value::from(i64::MIN)),
+        Just(Value::from(u64::MAX)),
+        Just(Value::from(9007199254740993u64)),
+    ]
+}
+
+fn string() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "\\PC{0,24}",
+        any::<String>(),
+        Just("\u{0}\u{1f}\"\\/\u{7f}\u{2028}\u{fffd}".to_string()),
+        Just("😀 ñ 中文 \u{10ffff}".to_string()),
+        Just("2024-01-15T10:30:00Z".to_string()),
+        Just("550e8400-e29b-41d4-a716-446655440000".to_string()),
+    ]
+}
+
+fn leaf() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::Bool),
+        number(),
+        string().prop_map(Value::String),
+    ]
+}
+
+fn object<S: Strategy<Value = Value>>(values: S, size: usize) -> impl Strategy<Value = Value> {
+    prop::collection::vec((string(), values), 0..size).prop_map(|entries| Value::Object(entries.into_iter().collect()))
+}
+
+/// Any JSON value, up to 8 levels deep
+fn json() -> impl Strategy<Value = Value> {
+    leaf().prop_recursive(8, 128, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Value::Array),
+            object(inner, 8),
+        ]
+    })
+}
+
+/// A JSON document: FLUX frames hold an object or array root
+fn document() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        prop::collection::vec(json(), 0..8).prop_map(Value::Array),
+        object(json(), 8),
+    ]
+}
+
+/// Arrays of objects sharing a field set, the shape the columnar stage targets
+fn records() -> impl Strategy<Value = Value> {
+    (prop::collection::vec("[a-z_]{1,8}", 1..6), 0usize..40).prop_flat_map(|(keys, count)| {
+        let record = prop::collection::vec(leaf(), keys.len())
+            .prop_map(move |values| Value::Object(keys.iter().cloned().zip(values).collect::<Map<_, _>>()));
+        prop::collection::vec(record, count).prop_map(Value::Array)
+    })
+}
+
+/// A value nested `depth` levels deep in alternating arrays and objects
+fn nested(depth: usize, inner: Value) -> Value {
+    (0..depth).fold(inner, |value, i| {
+        if i % 2 == 0 {
+            Value::Array(vec![value])
+        } else {
+            Value::Object(Map::from_iter([("k".to_string(), value)]))
+        }
+    })
+}
+
+fn parse(json: &[u8]) -> Value {
+    serde_json::from_slice(json).expect("decompressed output is JSON")
+}
+
+/// Whether `actual` equals `expected`, numbers compared by value unless `exact`
+fn same(actual: &Value, expected: &Value, exact: bool) -> bool {
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(b)) => a == b || (!exact && a.as_f64() == b.as_f64()),
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b, exact)),
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && b.iter().all(|(key, b)| a.get(key).is_some_and(|a| same(a, b, exact)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+macro_rules! prop_assert_same {
+    ($actual:expr, $expected:expr, $exact:expr) => {{
+        let (actual, expected) = (&$actual, &$expected);
+        prop_assert!(same(actual, expected, $exact), "{} != {}", actual, expected);
+    }};
+}
+
+/// `value` with the members of every object in reverse order
+fn reversed(value: &Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(reversed).collect()),
+        Value::Object(obj) => Value::Object(obj.iter().rev().map(|(k, v)| (k.clone(), reversed(v))).collect()),
+        other => other.clone(),
+    }
+}
+
+fn configs() -> Vec<FluxConfig> {
+    vec![
+        FluxConfig::default(),
+        FluxConfig::builder().columnar(false).entropy(false).build().unwrap(),
+        FluxConfig::builder().delta(false).checksum(true).build().unwrap(),
+        FluxConfig::builder().value_dictionary(true).lz_history(4096).build().unwrap(),
+        FluxConfig::with_level(FluxLevel::Fast),
+        FluxConfig::with_level(FluxLevel::Max),
+        FluxConfig::builder().profile(Profile::Realtime).build().unwrap(),
+        FluxConfig::builder().lossless_numbers(true).content_hash(true).build().unwrap(),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn oneshot(value in document()) {
+        let input = serde_json::to_vec(&value).unwrap();
+        let output = decompress(&compress(&input).unwrap()).unwrap();
+        prop_assert_same!(parse(&output), value, false);
+    }
+
+    #[test]
+    fn session(values in prop::collection::vec(prop_oneof![document(), records()], 1..6)) {
+        for config in configs() {
+            let exact = config.lossless_numbers;
+            let mut sender = FluxSession::with_config(config.clone());
+            let mut receiver = FluxSession::with_config(config);
+            for value in &values {
+                let frame = sender.compress(&serde_json::to_vec(value).unwrap()).unwrap();
+                prop_assert_same!(parse(&receiver.decompress(&frame).unwrap()), *value, exact);
+            }
+        }
+    }
+
+    #[test]
+    fn columnar(value in records()) {
+        let input = serde_json::to_vec(&value).unwrap();
+        for (delta, exact) in [(false, false), (true, false), (true, true)] {
+            let config = FluxConfig::builder().columnar(true).delta(delta).lossless_numbers(exact).build().unwrap();
+            let frame = FluxSession::with_config(config.clone()).compress(&input).unwrap();
+            let output = FluxSession::with_config(config).decompress(&frame).unwrap();
+            prop_assert_same!(parse(&output), value, exact);
+        }
+    }
+
+    #[test]
+    fn stream(states in prop::collection::vec(prop_oneof![document(), records()], 1..8), interval in 0u32..3) {
+        let config = StreamConfig { keyframe_interval: interval, ..StreamConfig::default() };
+        let mut sender = FluxStreamSession::with_config(config.clone());
+        let mut receiver = FluxStreamSession::with_config(config);
+        for state in &states {
+            let update = sender.update(&serde_json::to_vec(state).unwrap()).unwrap();
+            prop_assert_same!(parse(&receiver.receive(&update).unwrap()), *state, true);
+        }
+    }
+
+    #[test]
+    fn stream_edits(base in object(json(), 12), edits in prop::collection::vec((string(), json()), 1..8)) {
+        // Successive states that each change one member, as real updates do
+        let mut sender = FluxStreamSession::new();
+        let mut receiver = FluxStreamSession::new();
+        let mut state = base;
+        for (key, value) in edits {
+            let update = sender.update(&serde_json::to_vec(&state).unwrap()).unwrap();
+            prop_assert_same!(parse(&receiver.receive(&update).unwrap()), state, true);
+            state.as_object_mut().unwrap().insert(key, value);
+        }
+    }
+
+    #[test]
+    fn deterministic(values in prop::collection::vec(prop_oneof![document(), records()], 1..6)) {
+        // Fresh sessions fed the same messages, keys in any order, write the same bytes
+        for config in configs() {
+            let mut a = FluxSession::with_config(config.clone());
+            let mut b = FluxSession::with_config(config);
+            for value in &values {
+                let frame = a.compress(&serde_json::to_vec(value).unwrap()).unwrap();
+                prop_assert_eq!(frame, b.compress(&serde_json::to_vec(&reversed(value)).unwrap()).unwrap());
+            }
+        }
+        let mut a = FluxStreamSession::new();
+        let mut b = FluxStreamSession::new();
+        for value in &values {
+            prop_assert_eq!(a.update(&serde_json::to_vec(value).unwrap()).unwrap(), b.update(&serde_json::to_vec(value).unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn deep_nesting(depth in 1usize..64, inner in leaf()) {
+        let value = nested(depth, inner);
+        let output = decompress(&compress(&serde_json::to_vec(&value).unwrap()).unwrap()).unwrap();
+        prop_assert_same!(parse(&output), value, false);
+    }
+}
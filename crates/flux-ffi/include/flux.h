@@ -0,0 +1,138 @@
+#ifndef FLUX_H
+#define FLUX_H
+
+/* Generated by cbindgen from crates/flux-ffi. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of a FLUX call
+enum FluxStatus
+#ifdef __cplusplus
+  : int32_t
+#endif // __cplusplus
+ {
+  FLUX_STATUS_OK = 0,
+  // A required pointer argument was NULL
+  FLUX_STATUS_NULL_POINTER = 1,
+  // Not a FLUX frame, or a malformed or truncated one
+  FLUX_STATUS_INVALID_FRAME = 2,
+  FLUX_STATUS_CHECKSUM_MISMATCH = 3,
+  // The frame references a schema this session has not seen
+  FLUX_STATUS_SCHEMA_NOT_FOUND = 4,
+  // The input is not valid JSON
+  FLUX_STATUS_PARSE = 5,
+  FLUX_STATUS_ENCODE = 6,
+  FLUX_STATUS_DECODE = 7,
+  // A size limit was exceeded
+  FLUX_STATUS_LIMIT_EXCEEDED = 8,
+  FLUX_STATUS_UNSUPPORTED = 9,
+  FLUX_STATUS_INVALID_CONFIG = 10,
+  FLUX_STATUS_IO = 11,
+  FLUX_STATUS_STATE_DESYNC = 12,
+  // The library panicked; the session involved should be destroyed
+  FLUX_STATUS_PANIC = 13,
+  // An encrypted frame's key is unknown or the frame fails authentication
+  FLUX_STATUS_DECRYPT = 14,
+};
+#ifndef __cplusplus
+typedef int32_t FluxStatus;
+#endif // __cplusplus
+
+// Compression session caching schemas across messages (opaque)
+typedef struct FluxSession FluxSession;
+
+// Bytes returned by the library; release with `flux_buffer_free`
+typedef struct FluxBuffer {
+  uint8_t *data;
+  size_t len;
+} FluxBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Compress `input_len` bytes of JSON at `input` into `out`
+//
+// # Safety
+//
+// `input` must point to `input_len` readable bytes (or be NULL with a
+// length of 0) and `out` must point to a writable `FluxBuffer`.
+FluxStatus flux_compress(const uint8_t *input, size_t input_len, struct FluxBuffer *out);
+
+// Decompress a FLUX frame at `input` into JSON in `out`
+//
+// # Safety
+//
+// Same requirements as `flux_compress`.
+FluxStatus flux_decompress(const uint8_t *input, size_t input_len, struct FluxBuffer *out);
+
+// Create a session with the default configuration
+//
+// Destroy it with `flux_session_destroy`.
+struct FluxSession *flux_session_create(void);
+
+// Create a session with a preset: 0 = fast, 1 = default, 2 = max
+//
+// Returns NULL for any other level.
+struct FluxSession *flux_session_create_with_level(int32_t level);
+
+// Destroy a session; NULL is ignored
+//
+// # Safety
+//
+// `session` must come from `flux_session_create*` and not be used again.
+void flux_session_destroy(struct FluxSession *session);
+
+// Compress JSON with a session, so repeated shapes skip their schema
+//
+// # Safety
+//
+// `session` must be a live session not used concurrently, plus the
+// requirements of `flux_compress`.
+FluxStatus flux_session_compress(struct FluxSession *session,
+                                 const uint8_t *input,
+                                 size_t input_len,
+                                 struct FluxBuffer *out);
+
+// Decompress a frame with a session holding the schemas sent so far
+//
+// # Safety
+//
+// Same requirements as `flux_session_compress`.
+FluxStatus flux_session_decompress(struct FluxSession *session,
+                                   const uint8_t *input,
+                                   size_t input_len,
+                                   struct FluxBuffer *out);
+
+// Forget cached schemas and history
+//
+// # Safety
+//
+// `session` must be a live session or NULL.
+FluxStatus flux_session_reset(struct FluxSession *session);
+
+// Release a buffer filled by the library and reset it to empty
+//
+// # Safety
+//
+// `buffer` must be NULL or point to a `FluxBuffer` filled by this library
+// and not freed since.
+void flux_buffer_free(struct FluxBuffer *buffer);
+
+// Message of the last failed call on this thread, or NULL
+//
+// The string stays valid until the next FLUX call on this thread.
+const char *flux_last_error(void);
+
+// Library version, e.g. "0.1.0"
+const char *flux_version(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FLUX_H */
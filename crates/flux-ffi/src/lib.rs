@@ -0,0 +1,409 @@
+//! C ABI bindings for FLUX v2
+//!
+//! Builds `libflux` (shared and static) so C, Go (cgo), Swift and Python
+//! (ctypes) clients link the same core instead of reimplementing the format.
+//! The matching header is `include/flux.h`, generated with cbindgen (see
+//! `cbindgen.toml`).
+//!
+//! ```c
+//! FluxBuffer out;
+//! if (flux_compress(json, json_len, &out) != FLUX_STATUS_OK) {
+//!     fprintf(stderr, "flux: %s\n", flux_last_error());
+//!     return -1;
+//! }
+//! send(out.data, out.len);
+//! flux_buffer_free(&out);
+//! ```
+//!
+//! Every fallible function returns a `FluxStatus`; on failure the message
+//! is available from `flux_last_error` on the same thread. Output buffers
+//! are owned by the library and released with `flux_buffer_free`. Sessions
+//! are not thread-safe: use one per thread or lock around them.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use flux_core::{Error, FluxConfig, FluxLevel, FluxSession as CoreSession};
+
+/// Result of a FLUX call
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FluxStatus {
+    Ok = 0,
+    /// A required pointer argument was NULL
+    NullPointer = 1,
+    /// Not a FLUX frame, or a malformed or truncated one
+    InvalidFrame = 2,
+    ChecksumMismatch = 3,
+    /// The frame references a schema this session has not seen
+    SchemaNotFound = 4,
+    /// The input is not valid JSON
+    Parse = 5,
+    Encode = 6,
+    Decode = 7,
+    /// A size limit was exceeded
+    LimitExceeded = 8,
+    Unsupported = 9,
+    InvalidConfig = 10,
+    Io = 11,
+    StateDesync = 12,
+    /// The library panicked; the session involved should be destroyed
+    Panic = 13,
+    /// An encrypted frame's key is unknown or the frame fails authentication
+    Decrypt = 14,
+}
+
+impl From<&Error> for FluxStatus {
+    fn from(e: &Error) -> Self {
+        match e.root() {
+            Error::InvalidMagic | Error::UnsupportedVersion(_) | Error::InvalidFrame(_) | Error::Truncated { .. } => {
+                FluxStatus::InvalidFrame
+            }
+            Error::ChecksumMismatch | Error::ContentHashMismatch { .. } => FluxStatus::ChecksumMismatch,
+            Error::KeyNotFound(_) | Error::DecryptionFailed => FluxStatus::Decrypt,
+            Error::SchemaNotFound(_) => FluxStatus::SchemaNotFound,
+            Error::ParseError(_) => FluxStatus::Parse,
+            Error::EncodeError(_) | Error::SerializeError(_) => FluxStatus::Encode,
+            Error::DecodeError(_) | Error::InvalidEncoding(_) | Error::BufferOverflow | Error::Unexpected { .. } => {
+                FluxStatus::Decode
+            }
+            Error::OutputLimitExceeded { .. }
+            | Error::LengthLimitExceeded { .. }
+            | Error::DeltaLimitExceeded { .. }
+            | Error::DepthLimitExceeded { .. } => FluxStatus::LimitExceeded,
+            Error::UnsupportedType(_) | Error::UnsupportedCodec(_) | Error::UnsupportedCapabilities(_) => {
+                FluxStatus::Unsupported
+            }
+            Error::InvalidConfig { .. } => FluxStatus::InvalidConfig,
+            Error::Io(_) => FluxStatus::Io,
+            Error::StateDesync { .. } | Error::DeltaStateMismatch { .. } => FluxStatus::StateDesync,
+            Error::Context { .. } => unreachable!("root() strips context"),
+        }
+    }
+}
+
+/// Bytes returned by the library; release with `flux_buffer_free`
+#[repr(C)]
+#[derive(Debug)]
+pub struct FluxBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl FluxBuffer {
+    fn empty() -> Self {
+        Self { data: ptr::null_mut(), len: 0 }
+    }
+
+    fn from_vec(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Self { data, len }
+    }
+}
+
+/// Compression session caching schemas across messages (opaque)
+pub struct FluxSession(CoreSession);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Interior NULs would truncate the message anyway
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// View `len` bytes at `data`; NULL is accepted for an empty input
+unsafe fn input_bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Run `f` on `input`, storing its output in `out` and mapping failures to a status
+fn run(
+    input: Option<&[u8]>,
+    out: *mut FluxBuffer,
+    f: impl FnOnce(&[u8]) -> flux_core::Result<Vec<u8>>,
+) -> FluxStatus {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    if out.is_null() {
+        set_last_error("Output buffer is NULL".into());
+        return FluxStatus::NullPointer;
+    }
+    // SAFETY: checked non-null above; the caller guarantees it is writable
+    unsafe { out.write(FluxBuffer::empty()) };
+    let Some(input) = input else {
+        set_last_error("Input pointer is NULL".into());
+        return FluxStatus::NullPointer;
+    };
+
+    match catch_unwind(AssertUnwindSafe(|| f(input))) {
+        Ok(Ok(bytes)) => {
+            unsafe { out.write(FluxBuffer::from_vec(bytes)) };
+            FluxStatus::Ok
+        }
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            FluxStatus::from(&e)
+        }
+        Err(_) => {
+            set_last_error("Internal panic".into());
+            FluxStatus::Panic
+        }
+    }
+}
+
+// ============================================================================
+// One-shot compression
+// ============================================================================
+
+/// Compress `input_len` bytes of JSON at `input` into `out`
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes (or be NULL with a
+/// length of 0) and `out` must point to a writable `FluxBuffer`.
+#[no_mangle]
+pub unsafe extern "C" fn flux_compress(input: *const u8, input_len: usize, out: *mut FluxBuffer) -> FluxStatus {
+    run(input_bytes(input, input_len), out, flux_core::compress)
+}
+
+/// Decompress a FLUX frame at `input` into JSON in `out`
+///
+/// # Safety
+///
+/// Same requirements as `flux_compress`.
+#[no_mangle]
+pub unsafe extern "C" fn flux_decompress(input: *const u8, input_len: usize, out: *mut FluxBuffer) -> FluxStatus {
+    run(input_bytes(input, input_len), out, flux_core::decompress)
+}
+
+// ============================================================================
+// Sessions (schema caching)
+// ============================================================================
+
+/// Create a session with the default configuration
+///
+/// Destroy it with `flux_session_destroy`.
+#[no_mangle]
+pub extern "C" fn flux_session_create() -> *mut FluxSession {
+    Box::into_raw(Box::new(FluxSession(CoreSession::new())))
+}
+
+/// Create a session with a preset: 0 = fast, 1 = default, 2 = max
+///
+/// Returns NULL for any other level.
+#[no_mangle]
+pub extern "C" fn flux_session_create_with_level(level: i32) -> *mut FluxSession {
+    let level = match level {
+        0 => FluxLevel::Fast,
+        1 => FluxLevel::Default,
+        2 => FluxLevel::Max,
+        _ => {
+            set_last_error(format!("Unknown level {}", level));
+            return ptr::null_mut();
+        }
+    };
+    let session = CoreSession::with_config(FluxConfig::with_level(level));
+    Box::into_raw(Box::new(FluxSession(session)))
+}
+
+/// Destroy a session; NULL is ignored
+///
+/// # Safety
+///
+/// `session` must come from `flux_session_create*` and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn flux_session_destroy(session: *mut FluxSession) {
+    if !session.is_null() {
+        drop(Box::from_raw(session));
+    }
+}
+
+/// Compress JSON with a session, so repeated shapes skip their schema
+///
+/// # Safety
+///
+/// `session` must be a live session not used concurrently, plus the
+/// requirements of `flux_compress`.
+#[no_mangle]
+pub unsafe extern "C" fn flux_session_compress(
+    session: *mut FluxSession,
+    input: *const u8,
+    input_len: usize,
+    out: *mut FluxBuffer,
+) -> FluxStatus {
+    let Some(session) = session.as_mut() else {
+        return null_session(out);
+    };
+    run(input_bytes(input, input_len), out, |json| session.0.compress(json))
+}
+
+/// Decompress a frame with a session holding the schemas sent so far
+///
+/// # Safety
+///
+/// Same requirements as `flux_session_compress`.
+#[no_mangle]
+pub unsafe extern "C" fn flux_session_decompress(
+    session: *mut FluxSession,
+    input: *const u8,
+    input_len: usize,
+    out: *mut FluxBuffer,
+) -> FluxStatus {
+    let Some(session) = session.as_mut() else {
+        return null_session(out);
+    };
+    run(input_bytes(input, input_len), out, |frame| session.0.decompress(frame))
+}
+
+/// Forget cached schemas and history
+///
+/// # Safety
+///
+/// `session` must be a live session or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn flux_session_reset(session: *mut FluxSession) -> FluxStatus {
+    match session.as_mut() {
+        Some(session) => {
+            session.0.reset();
+            FluxStatus::Ok
+        }
+        None => {
+            set_last_error("Session is NULL".into());
+            FluxStatus::NullPointer
+        }
+    }
+}
+
+fn null_session(out: *mut FluxBuffer) -> FluxStatus {
+    if !out.is_null() {
+        // SAFETY: the caller guarantees a non-null `out` is writable
+        unsafe { out.write(FluxBuffer::empty()) };
+    }
+    set_last_error("Session is NULL".into());
+    FluxStatus::NullPointer
+}
+
+// ============================================================================
+// Utilities
+// ============================================================================
+
+/// Release a buffer filled by the library and reset it to empty
+///
+/// # Safety
+///
+/// `buffer` must be NULL or point to a `FluxBuffer` filled by this library
+/// and not freed since.
+#[no_mangle]
+pub unsafe extern "C" fn flux_buffer_free(buffer: *mut FluxBuffer) {
+    let Some(buffer) = buffer.as_mut() else {
+        return;
+    };
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+    }
+    *buffer = FluxBuffer::empty();
+}
+
+/// Message of the last failed call on this thread, or NULL
+///
+/// The string stays valid until the next FLUX call on this thread.
+#[no_mangle]
+pub extern "C" fn flux_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Library version, e.g. "0.1.0"
+#[no_mangle]
+pub extern "C" fn flux_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(flux_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_oneshot_roundtrip() {
+        let json = br#"{"id":1,"name":"alice"}"#;
+        let mut frame = FluxBuffer::empty();
+        let mut decoded = FluxBuffer::empty();
+        unsafe {
+            assert_eq!(flux_compress(json.as_ptr(), json.len(), &mut frame), FluxStatus::Ok);
+            assert_eq!(flux_decompress(frame.data, frame.len, &mut decoded), FluxStatus::Ok);
+            assert_eq!(std::slice::from_raw_parts(decoded.data, decoded.len), json);
+            flux_buffer_free(&mut frame);
+            flux_buffer_free(&mut decoded);
+        }
+        assert!(frame.data.is_null() && decoded.data.is_null());
+        assert!(flux_last_error().is_null());
+    }
+
+    #[test]
+    fn test_session_roundtrip() {
+        let sender = flux_session_create();
+        let receiver = flux_session_create_with_level(2);
+        assert!(flux_session_create_with_level(7).is_null());
+        for i in 0..3 {
+            let json = format!(r#"{{"id":{},"name":"user{}"}}"#, i, i);
+            let mut frame = FluxBuffer::empty();
+            let mut decoded = FluxBuffer::empty();
+            unsafe {
+                assert_eq!(flux_session_compress(sender, json.as_ptr(), json.len(), &mut frame), FluxStatus::Ok);
+                assert_eq!(flux_session_decompress(receiver, frame.data, frame.len, &mut decoded), FluxStatus::Ok);
+                assert_eq!(std::slice::from_raw_parts(decoded.data, decoded.len), json.as_bytes());
+                flux_buffer_free(&mut frame);
+                flux_buffer_free(&mut decoded);
+            }
+        }
+        unsafe {
+            assert_eq!(flux_session_reset(sender), FluxStatus::Ok);
+            flux_session_destroy(sender);
+            flux_session_destroy(receiver);
+            flux_session_destroy(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let mut out = FluxBuffer { data: ptr::NonNull::dangling().as_ptr(), len: 3 };
+        unsafe {
+            assert_eq!(flux_decompress(b"JSON{}".as_ptr(), 6, &mut out), FluxStatus::InvalidFrame);
+            assert!(out.data.is_null());
+            assert_eq!(last_error(), "frame stage failed: Invalid magic number");
+
+            assert_eq!(flux_compress(b"{oops".as_ptr(), 5, &mut out), FluxStatus::Parse);
+            assert_eq!(flux_compress(ptr::null(), 4, &mut out), FluxStatus::NullPointer);
+            assert_eq!(flux_compress(ptr::null(), 0, ptr::null_mut()), FluxStatus::NullPointer);
+            assert_eq!(flux_session_compress(ptr::null_mut(), ptr::null(), 0, &mut out), FluxStatus::NullPointer);
+            assert_eq!(last_error(), "Session is NULL");
+
+            // A receiver that missed the schema
+            let mut frame = FluxBuffer::empty();
+            let sender = flux_session_create();
+            let receiver = flux_session_create();
+            flux_session_compress(sender, b"{\"a\":1}".as_ptr(), 7, &mut frame);
+            flux_buffer_free(&mut frame);
+            flux_session_compress(sender, b"{\"a\":2}".as_ptr(), 7, &mut frame);
+            assert_eq!(flux_session_decompress(receiver, frame.data, frame.len, &mut out), FluxStatus::SchemaNotFound);
+            flux_buffer_free(&mut frame);
+            flux_session_destroy(sender);
+            flux_session_destroy(receiver);
+        }
+        let version = unsafe { CStr::from_ptr(flux_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    }
+}
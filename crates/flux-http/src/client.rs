@@ -0,0 +1,217 @@
+//! Client-side tower `Layer`/`Service` for `Content-Encoding: flux`
+//!
+//! Wraps an HTTP client service (hyper-util's `Client`, for instance) so
+//! requests advertise FLUX and FLUX responses come back as plain JSON.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use flux_core::http::{parse_accept_encoding, FluxAccept, SchemaMode, FLUX_TOKEN};
+use flux_core::{FluxConfig, FluxSession};
+use http::header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH};
+use http::{Extensions, Request, Response};
+use http_body::Body;
+use http_body_util::{BodyExt, Either, Full};
+use tower::{Layer, Service};
+
+use crate::layer::{is_flux_encoded, BoxError, FluxResponseBody, DEFAULT_MAX_CONNECTIONS};
+use crate::session::{default_connection_key, in_order, ConnectionKeyFn, SessionStore};
+
+/// Layer that requests FLUX responses and decompresses them
+///
+/// Every request gets `flux;schema=cached` added to its `Accept-Encoding`.
+/// Responses are matched to the connection they arrived on by a key read
+/// from the *response* extensions (a `ConnectionId` by default), and each
+/// connection decodes with its own session, mirroring `FluxLayer` on the
+/// server. With hyper-util's pooled client, key on the local address from
+/// `HttpInfo`, which is unique per pooled connection:
+///
+/// ```rust,ignore
+/// let client = ServiceBuilder::new()
+///     .layer(FluxClientLayer::new().connection_key(|ext| {
+///         let info = ext.get::<HttpInfo>()?;
+///         let mut hasher = DefaultHasher::new();
+///         info.local_addr().hash(&mut hasher);
+///         Some(hasher.finish())
+///     }))
+///     .service(Client::builder(TokioExecutor::new()).build_http());
+/// ```
+///
+/// Responses without a key are decoded with a one-shot session, which
+/// works as long as the server included the schema. Sessions rely on
+/// responses arriving in order, so HTTP/2 and HTTP/3 responses, whose
+/// streams interleave, are decoded with one-shot sessions too, matching a
+/// `FluxLayer` that inlines their schemas.
+#[derive(Clone)]
+pub struct FluxClientLayer {
+    store: Arc<SessionStore>,
+    connection_key: ConnectionKeyFn,
+}
+
+impl FluxClientLayer {
+    /// Create a layer with the default FLUX configuration
+    pub fn new() -> Self {
+        Self::with_config(FluxConfig::default())
+    }
+
+    /// Create a layer whose sessions use `config`
+    pub fn with_config(config: FluxConfig) -> Self {
+        Self {
+            store: Arc::new(SessionStore::new(config, DEFAULT_MAX_CONNECTIONS)),
+            connection_key: default_connection_key(),
+        }
+    }
+
+    /// Cap the number of connections with live sessions
+    ///
+    /// Existing sessions are discarded.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        let config = self.store.config().clone();
+        self.store = Arc::new(SessionStore::new(config, max));
+        self
+    }
+
+    /// Use a custom connection key extractor, applied to response extensions
+    pub fn connection_key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<u64> + Send + Sync + 'static,
+    {
+        self.connection_key = Arc::new(f);
+        self
+    }
+
+    /// Drop the session for a closed connection
+    pub fn close_connection(&self, key: u64) {
+        self.store.remove(key);
+    }
+
+    /// Number of connections with live sessions
+    pub fn connection_count(&self) -> usize {
+        self.store.len()
+    }
+}
+
+impl Default for FluxClientLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for FluxClientLayer {
+    type Service = FluxClientService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FluxClientService {
+            inner,
+            store: self.store.clone(),
+            connection_key: self.connection_key.clone(),
+        }
+    }
+}
+
+/// Service produced by `FluxClientLayer`
+#[derive(Clone)]
+pub struct FluxClientService<S> {
+    inner: S,
+    store: Arc<SessionStore>,
+    connection_key: ConnectionKeyFn,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for FluxClientService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    ReqBody: Send + 'static,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<FluxResponseBody<ResBody>>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // Take the service that was driven to readiness, leave a clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        advertise_flux(&mut req);
+        let store = self.store.clone();
+        let connection_key = self.connection_key.clone();
+
+        Box::pin(async move {
+            let res = inner.call(req).await.map_err(Into::into)?;
+            if !is_flux_encoded(res.headers().get(CONTENT_ENCODING)) {
+                return Ok(res.map(Either::Left));
+            }
+            let key = connection_key(res.extensions()).filter(|_| in_order(res.version()));
+            decode_response(res, &store.get(key).decoder).await
+        })
+    }
+}
+
+/// Add `flux;schema=cached` to `Accept-Encoding`, ahead of other codings
+///
+/// A header that already mentions `flux` is left as the caller wrote it.
+fn advertise_flux<B>(req: &mut Request<B>) {
+    let existing = req.headers().get(ACCEPT_ENCODING).and_then(|v| v.to_str().ok());
+    if existing.is_some_and(|v| parse_accept_encoding(v).iter().any(|c| c.coding == FLUX_TOKEN)) {
+        return;
+    }
+    let flux = FluxAccept { schema: SchemaMode::Cached, ..FluxAccept::default() }.to_header_value();
+    let value = match existing {
+        Some(existing) if !existing.trim().is_empty() => format!("{}, {}", flux, existing),
+        _ => flux,
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        req.headers_mut().insert(ACCEPT_ENCODING, value);
+    }
+}
+
+/// Buffer and decompress a FLUX response body
+async fn decode_response<B>(
+    res: Response<B>,
+    session: &Mutex<FluxSession>,
+) -> Result<Response<FluxResponseBody<B>>, BoxError>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let (mut parts, body) = res.into_parts();
+    let bytes = body.collect().await.map_err(Into::into)?.to_bytes();
+
+    let json = session.lock().unwrap_or_else(|e| e.into_inner()).decompress(&bytes)?;
+
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(json.len()));
+    Ok(Response::from_parts(parts, Either::Right(Full::from(json))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advertise_flux() {
+        let accept = |value: Option<&'static str>| {
+            let mut builder = Request::get("/");
+            if let Some(value) = value {
+                builder = builder.header(ACCEPT_ENCODING, value);
+            }
+            let mut req = builder.body(()).unwrap();
+            advertise_flux(&mut req);
+            req.headers()[ACCEPT_ENCODING].to_str().unwrap().to_string()
+        };
+        assert_eq!(accept(None), "flux;schema=cached");
+        assert_eq!(accept(Some("gzip, br")), "flux;schema=cached, gzip, br");
+        assert_eq!(accept(Some("flux;q=0.5, gzip")), "flux;q=0.5, gzip");
+        assert_eq!(accept(Some("flux;q=0")), "flux;q=0");
+    }
+}
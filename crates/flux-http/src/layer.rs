@@ -0,0 +1,291 @@
+//! tower `Layer`/`Service` for `Content-Encoding: flux`
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use flux_core::http::FluxAccept;
+use flux_core::{FluxConfig, FluxSession};
+use http::header::{
+    HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY,
+};
+use http::{Extensions, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::{BodyExt, Either, Full};
+use tower::{Layer, Service};
+
+use crate::session::{default_connection_key, in_order, ConnectionKeyFn, SessionStore};
+
+/// `Content-Encoding` token for FLUX
+pub const FLUX_ENCODING: &str = flux_core::http::FLUX_TOKEN;
+
+/// Default cap on connections with live sessions
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Boxed error returned by `FluxService`
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Request body seen by the inner service
+pub type FluxRequestBody<B> = Either<B, Full<Bytes>>;
+
+/// Response body produced by `FluxService`
+pub type FluxResponseBody<B> = Either<B, Full<Bytes>>;
+
+/// Layer that adds FLUX `Content-Encoding` support
+///
+/// - Request bodies sent with `Content-Encoding: flux` are decompressed
+///   before reaching the inner service.
+/// - JSON responses are compressed when the request's `Accept-Encoding`
+///   lists `flux`.
+///
+/// HTTP/1.x requests that carry a connection key (see `ConnectionId`) share
+/// a pair of sessions with every other request on that connection, so
+/// schemas are sent once per connection. Requests without a key, and
+/// HTTP/2 or HTTP/3 requests, whose streams interleave, use one-shot
+/// sessions, which always include the schema and need no client-side state.
+#[derive(Clone)]
+pub struct FluxLayer {
+    store: Arc<SessionStore>,
+    connection_key: ConnectionKeyFn,
+}
+
+impl FluxLayer {
+    /// Create a layer with the default FLUX configuration
+    pub fn new() -> Self {
+        Self::with_config(FluxConfig::default())
+    }
+
+    /// Create a layer whose sessions use `config`
+    pub fn with_config(config: FluxConfig) -> Self {
+        Self {
+            store: Arc::new(SessionStore::new(config, DEFAULT_MAX_CONNECTIONS)),
+            connection_key: default_connection_key(),
+        }
+    }
+
+    /// Cap the number of connections with live sessions
+    ///
+    /// Existing sessions are discarded.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        let config = self.store.config().clone();
+        self.store = Arc::new(SessionStore::new(config, max));
+        self
+    }
+
+    /// Use a custom connection key extractor
+    ///
+    /// With axum, for example, key on the peer address from `ConnectInfo`.
+    pub fn connection_key<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<u64> + Send + Sync + 'static,
+    {
+        self.connection_key = Arc::new(f);
+        self
+    }
+
+    /// Drop the sessions for a closed connection
+    pub fn close_connection(&self, key: u64) {
+        self.store.remove(key);
+    }
+
+    /// Number of connections with live sessions
+    pub fn connection_count(&self) -> usize {
+        self.store.len()
+    }
+}
+
+impl Default for FluxLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for FluxLayer {
+    type Service = FluxService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FluxService {
+            inner,
+            store: self.store.clone(),
+            connection_key: self.connection_key.clone(),
+        }
+    }
+}
+
+/// Service produced by `FluxLayer`
+#[derive(Clone)]
+pub struct FluxService<S> {
+    inner: S,
+    store: Arc<SessionStore>,
+    connection_key: ConnectionKeyFn,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for FluxService<S>
+where
+    S: Service<Request<FluxRequestBody<ReqBody>>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    ReqBody: Body + Send + 'static,
+    ReqBody::Data: Send,
+    ReqBody::Error: Into<BoxError>,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<FluxResponseBody<ResBody>>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // Take the service that was driven to readiness, leave a clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let key = (self.connection_key)(req.extensions()).filter(|_| in_order(req.version()));
+        let sessions = self.store.get(key);
+        let accepts_flux = accepts_flux(req.headers().get(ACCEPT_ENCODING));
+
+        Box::pin(async move {
+            let req = if is_flux_encoded(req.headers().get(CONTENT_ENCODING)) {
+                match decode_request(req, &sessions.decoder).await? {
+                    Ok(req) => req,
+                    Err(message) => return Ok(bad_request(message)),
+                }
+            } else {
+                req.map(Either::Left)
+            };
+
+            let res = inner.call(req).await.map_err(Into::into)?;
+
+            if accepts_flux && is_compressible(&res) {
+                encode_response(res, &sessions.encoder).await
+            } else {
+                Ok(res.map(Either::Left))
+            }
+        })
+    }
+}
+
+/// Buffer and decompress a FLUX request body
+///
+/// The outer `Result` carries body errors; the inner one a decode failure
+/// that should become a 400.
+async fn decode_request<B>(
+    req: Request<B>,
+    session: &Mutex<FluxSession>,
+) -> Result<Result<Request<FluxRequestBody<B>>, String>, BoxError>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let (mut parts, body) = req.into_parts();
+    let bytes = body.collect().await.map_err(Into::into)?.to_bytes();
+
+    let decoded = session.lock().unwrap_or_else(|e| e.into_inner()).decompress(&bytes);
+    let json = match decoded {
+        Ok(json) => json,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(json.len()));
+    Ok(Ok(Request::from_parts(parts, Either::Right(Full::from(json)))))
+}
+
+/// Buffer and compress a JSON response body
+///
+/// Bodies that are not valid JSON are passed through uncompressed.
+async fn encode_response<B>(
+    res: Response<B>,
+    session: &Mutex<FluxSession>,
+) -> Result<Response<FluxResponseBody<B>>, BoxError>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    let (mut parts, body) = res.into_parts();
+    let bytes = body.collect().await.map_err(Into::into)?.to_bytes();
+
+    let encoded = session.lock().unwrap_or_else(|e| e.into_inner()).compress(&bytes);
+
+    parts.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    let body = match encoded {
+        Ok(compressed) => {
+            parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(FLUX_ENCODING));
+            compressed.into()
+        }
+        Err(_) => bytes,
+    };
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    Ok(Response::from_parts(parts, Either::Right(Full::new(body))))
+}
+
+fn bad_request<B>(message: String) -> Response<FluxResponseBody<B>> {
+    let mut res = Response::new(Either::Right(Full::from(message)));
+    *res.status_mut() = StatusCode::BAD_REQUEST;
+    res
+}
+
+pub(crate) fn is_flux_encoded(value: Option<&HeaderValue>) -> bool {
+    value
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case(FLUX_ENCODING))
+}
+
+/// Whether an `Accept-Encoding` header lists `flux` with a non-zero q-value
+pub fn accepts_flux(value: Option<&HeaderValue>) -> bool {
+    value
+        .and_then(|v| v.to_str().ok())
+        .and_then(FluxAccept::parse)
+        .is_some()
+}
+
+/// Only successful, not-yet-encoded JSON responses are compressed
+fn is_compressible<B>(res: &Response<B>) -> bool {
+    if res.headers().contains_key(CONTENT_ENCODING) || !res.status().is_success() {
+        return false;
+    }
+    res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(is_json_content_type)
+}
+
+fn is_json_content_type(value: &str) -> bool {
+    let mime = value.split(';').next().unwrap_or("").trim();
+    mime.eq_ignore_ascii_case("application/json")
+        || mime.rsplit_once('+').is_some_and(|(_, suffix)| suffix.eq_ignore_ascii_case("json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts_flux() {
+        let check = |s: &'static str| accepts_flux(Some(&HeaderValue::from_static(s)));
+        assert!(check("flux"));
+        assert!(check("gzip, FLUX;q=0.5"));
+        assert!(!check("gzip, br"));
+        assert!(!check("flux;q=0"));
+        assert!(!check("*"));
+        assert!(!accepts_flux(None));
+    }
+
+    #[test]
+    fn test_json_content_type() {
+        assert!(is_json_content_type("application/json"));
+        assert!(is_json_content_type("application/json; charset=utf-8"));
+        assert!(is_json_content_type("application/problem+json"));
+        assert!(!is_json_content_type("text/html"));
+    }
+}
@@ -0,0 +1,202 @@
+//! FLUX HTTP middleware
+//!
+//! A tower `Layer` that speaks `Content-Encoding: flux`: request bodies are
+//! decompressed transparently and JSON responses are compressed for clients
+//! that advertise `flux` in `Accept-Encoding`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use axum::{routing::get, Router};
+//! use flux_http::FluxLayer;
+//!
+//! let app = Router::new()
+//!     .route("/users", get(list_users))
+//!     .layer(FluxLayer::new());
+//! ```
+//!
+//! # Sessions
+//!
+//! To share schemas across requests, tag each connection with a
+//! `ConnectionId` extension (or pass `FluxLayer::connection_key`) and keep a
+//! matching `FluxSession` per connection on the client. Sessions are held by
+//! `flux_core::SessionManager`s and only shared on HTTP/1.x connections,
+//! whose requests arrive in order.
+//!
+//! # Client
+//!
+//! `FluxClientLayer` is the client-side counterpart: it wraps an HTTP client
+//! service, advertises `flux` in `Accept-Encoding` and decompresses FLUX
+//! responses with one session per pooled connection.
+
+mod client;
+mod layer;
+mod session;
+
+pub use client::{FluxClientLayer, FluxClientService};
+pub use layer::{
+    accepts_flux, BoxError, FluxLayer, FluxRequestBody, FluxResponseBody, FluxService,
+    DEFAULT_MAX_CONNECTIONS, FLUX_ENCODING,
+};
+pub use session::{ConnectionId, ConnectionKeyFn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+    use flux_core::FluxSession;
+    use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+    use http::{Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Full};
+    use tower::{service_fn, Layer, ServiceExt};
+
+    /// Echoes the (decoded) request body back as JSON
+    async fn echo(
+        req: Request<FluxRequestBody<Full<Bytes>>>,
+    ) -> Result<Response<Full<Bytes>>, BoxError> {
+        let body = req.into_body().collect().await?.to_bytes();
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(body))?)
+    }
+
+    fn request(body: Vec<u8>, flux_body: bool, conn: Option<u64>) -> Request<Full<Bytes>> {
+        let mut builder = Request::post("/").header(ACCEPT_ENCODING, "gzip, flux");
+        if flux_body {
+            builder = builder.header(CONTENT_ENCODING, FLUX_ENCODING);
+        }
+        if let Some(id) = conn {
+            builder = builder.extension(ConnectionId(id));
+        }
+        builder.body(Full::from(body)).unwrap()
+    }
+
+    async fn body_bytes<B>(res: Response<B>) -> Bytes
+    where
+        B: http_body::Body,
+        B::Error: std::fmt::Debug,
+    {
+        res.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_request_and_response_roundtrip() {
+        let layer = FluxLayer::new();
+        let json = br#"{"id":1,"name":"alice"}"#;
+
+        let mut client = FluxSession::new();
+        let body = client.compress(json).unwrap();
+
+        let res = layer
+            .layer(service_fn(echo))
+            .oneshot(request(body, true, None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_ENCODING], FLUX_ENCODING);
+
+        let decoded = flux_core::decompress(&body_bytes(res).await).unwrap();
+        let original: serde_json::Value = serde_json::from_slice(json).unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&decoded).unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn test_plain_client_untouched() {
+        let json = br#"{"id":1}"#.to_vec();
+        let req = Request::post("/").body(Full::from(json.clone())).unwrap();
+
+        let res = FluxLayer::new().layer(service_fn(echo)).oneshot(req).await.unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(body_bytes(res).await, json);
+    }
+
+    #[tokio::test]
+    async fn test_bad_flux_body_is_rejected() {
+        let res = FluxLayer::new()
+            .layer(service_fn(echo))
+            .oneshot(request(b"not flux".to_vec(), true, None))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_connection_shares_schema_cache() {
+        let layer = FluxLayer::new();
+        let service = layer.layer(service_fn(echo));
+        let mut client_decoder = FluxSession::new();
+
+        let mut sizes = Vec::new();
+        for i in 0..3 {
+            let json = format!(r#"{{"id":{},"name":"user{}"}}"#, i, i).into_bytes();
+            let res = service
+                .clone()
+                .oneshot(request(json.clone(), false, Some(7)))
+                .await
+                .unwrap();
+            let frame = body_bytes(res).await;
+            sizes.push(frame.len());
+
+            // Client keeps one session per connection, in lockstep
+            let decoded = client_decoder.decompress(&frame).unwrap();
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(&decoded).unwrap(),
+                serde_json::from_slice::<serde_json::Value>(&json).unwrap()
+            );
+        }
+
+        // Only the first response carries the schema
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[2] < sizes[0]);
+    }
+
+    #[tokio::test]
+    async fn test_multiplexed_requests_inline_schemas() {
+        let layer = FluxLayer::new();
+        let service = layer.layer(service_fn(echo));
+        let json = br#"{"id":1,"name":"alice"}"#;
+
+        for _ in 0..2 {
+            let mut req = request(json.to_vec(), false, Some(7));
+            *req.version_mut() = http::Version::HTTP_2;
+            let res = service.clone().oneshot(req).await.unwrap();
+            // Streams may be decoded in any order, so each frame stands alone
+            let decoded = flux_core::decompress(&body_bytes(res).await).unwrap();
+            assert_eq!(decoded, json);
+        }
+        assert_eq!(layer.connection_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_client_layer_decodes_per_connection() {
+        let server = FluxLayer::new().layer(service_fn(echo));
+        // Stand-in for the transport: tags responses with the connection id
+        let transport = service_fn(move |req: Request<Full<Bytes>>| {
+            let id = req.extensions().get::<ConnectionId>().copied();
+            let call = server.clone().oneshot(req);
+            async move {
+                let mut res = call.await?;
+                if let Some(id) = id {
+                    res.extensions_mut().insert(id);
+                }
+                Ok::<_, BoxError>(res)
+            }
+        });
+        let layer = FluxClientLayer::new();
+        let client = layer.layer(transport);
+
+        for i in 0..3 {
+            let json = format!(r#"{{"id":{},"name":"user{}"}}"#, i, i);
+            let req = Request::post("/").extension(ConnectionId(7)).body(Full::from(json.clone())).unwrap();
+            let res = client.clone().oneshot(req).await.unwrap();
+            assert!(!res.headers().contains_key(CONTENT_ENCODING));
+            assert_eq!(body_bytes(res).await, json);
+        }
+        assert_eq!(layer.connection_count(), 1);
+
+        // Without a key the server inlines the schema and a one-shot session decodes it
+        let res = client.oneshot(Request::post("/").body(Full::from(r#"{"id":9}"#)).unwrap()).await.unwrap();
+        assert_eq!(body_bytes(res).await, r#"{"id":9}"#);
+    }
+}
@@ -0,0 +1,135 @@
+//! Per-connection FLUX sessions
+//!
+//! Schema caching only pays off when both peers keep a session alive across
+//! messages. HTTP/1.1 keep-alive connections carry requests in order, so one
+//! encode session (responses) and one decode session (request bodies) per
+//! connection stay in lockstep with a client doing the same.
+//!
+//! HTTP/2 and HTTP/3 multiplex concurrent streams over a connection, so
+//! bodies may be encoded in one order and decoded in another. Each of their
+//! requests gets sessions of its own instead, as if keyed by stream, and
+//! every frame carries its schema.
+
+use std::sync::{Arc, Mutex};
+
+use flux_core::{FluxConfig, FluxSession, SessionManager};
+use http::{Extensions, Version};
+
+/// Identifies the connection a request arrived on
+///
+/// Insert this into request extensions from your accept loop, or supply a
+/// custom extractor with `FluxLayer::connection_key`. `FluxClientLayer`
+/// reads it from response extensions instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId(pub u64);
+
+/// Extracts a connection key from request extensions
+pub type ConnectionKeyFn = Arc<dyn Fn(&Extensions) -> Option<u64> + Send + Sync>;
+
+/// Default extractor: reads a `ConnectionId` extension
+pub(crate) fn default_connection_key() -> ConnectionKeyFn {
+    Arc::new(|ext: &Extensions| ext.get::<ConnectionId>().map(|id| id.0))
+}
+
+/// Whether messages of `version` can share a connection's sessions
+pub(crate) fn in_order(version: Version) -> bool {
+    version <= Version::HTTP_11
+}
+
+/// Sessions for one request
+pub(crate) struct ConnectionSessions {
+    /// Compresses responses (server side)
+    pub encoder: Arc<Mutex<FluxSession>>,
+    /// Decompresses request bodies, or responses on the client side
+    pub decoder: Arc<Mutex<FluxSession>>,
+}
+
+/// Encode and decode sessions keyed by connection
+///
+/// Connections are never observed closing at the tower level, so each
+/// `SessionManager` drops the least recently used connection's session once
+/// `max_connections` is reached.
+pub(crate) struct SessionStore {
+    config: FluxConfig,
+    encoders: SessionManager<u64>,
+    decoders: SessionManager<u64>,
+}
+
+impl SessionStore {
+    pub fn new(config: FluxConfig, max_connections: usize) -> Self {
+        let manager = || SessionManager::new(config.clone()).ttl(None).max_sessions(max_connections);
+        Self { encoders: manager(), decoders: manager(), config }
+    }
+
+    /// Configuration used for new sessions
+    pub fn config(&self) -> &FluxConfig {
+        &self.config
+    }
+
+    /// Sessions for `key`, or fresh throwaway sessions when there is no key
+    pub fn get(&self, key: Option<u64>) -> ConnectionSessions {
+        match key {
+            Some(key) => ConnectionSessions { encoder: self.encoders.get(&key), decoder: self.decoders.get(&key) },
+            None => ConnectionSessions { encoder: self.new_session(), decoder: self.new_session() },
+        }
+    }
+
+    /// Drop the sessions for a closed connection
+    pub fn remove(&self, key: u64) {
+        self.encoders.remove(&key);
+        self.decoders.remove(&key);
+    }
+
+    /// Number of tracked connections
+    pub fn len(&self) -> usize {
+        self.encoders.len()
+    }
+
+    fn new_session(&self) -> Arc<Mutex<FluxSession>> {
+        Arc::new(Mutex::new(FluxSession::with_config(self.config.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_key_shares_sessions() {
+        let store = SessionStore::new(FluxConfig::default(), 4);
+        let a = store.get(Some(1));
+        let b = store.get(Some(1));
+        assert!(Arc::ptr_eq(&a.encoder, &b.encoder));
+        assert!(Arc::ptr_eq(&a.decoder, &b.decoder));
+        assert!(!Arc::ptr_eq(&a.encoder, &a.decoder));
+        assert_eq!(store.len(), 1);
+
+        // No key means no sharing
+        let c = store.get(None);
+        let d = store.get(None);
+        assert!(!Arc::ptr_eq(&c.encoder, &d.encoder));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn test_eviction_and_remove() {
+        let store = SessionStore::new(FluxConfig::default(), 2);
+        let first = store.get(Some(1));
+        store.get(Some(2));
+        store.get(Some(3));
+        assert_eq!(store.len(), 2);
+
+        // Key 1 was evicted, so it comes back fresh
+        assert!(!Arc::ptr_eq(&first.encoder, &store.get(Some(1)).encoder));
+
+        store.remove(1);
+        store.remove(3);
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn test_multiplexed_versions() {
+        assert!(in_order(Version::HTTP_10) && in_order(Version::HTTP_11));
+        assert!(!in_order(Version::HTTP_2) && !in_order(Version::HTTP_3));
+    }
+}
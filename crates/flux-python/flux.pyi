@@ -0,0 +1,25 @@
+"""FLUX v2 - schema-aware JSON compression for API traffic"""
+
+__version__: str
+
+class FluxError(Exception): ...
+
+def compress(data: bytes) -> bytes: ...
+def decompress(data: bytes) -> bytes: ...
+
+class Session:
+    def __init__(self, columnar: bool = True, entropy: bool = True, delta: bool = True, checksum: bool = False) -> None: ...
+    def compress(self, data: bytes) -> bytes: ...
+    def decompress(self, data: bytes) -> bytes: ...
+    def decompress_all(self, data: bytes) -> list[bytes]: ...
+    def stats(self) -> dict[str, int | float]: ...
+    def list_schemas(self) -> list[dict[str, object]]: ...
+    def remove_schema(self, schema_id: int) -> bool: ...
+    def reset(self) -> None: ...
+
+class StreamSession:
+    def __init__(self, keyframe_interval: int = 0, keyframe_period_ms: float = 0.0) -> None: ...
+    def update(self, json: bytes) -> bytes: ...
+    def receive(self, data: bytes) -> bytes: ...
+    def stats(self) -> dict[str, int | float]: ...
+    def reset(self) -> None: ...
@@ -0,0 +1,268 @@
+//! Python bindings for FLUX v2
+//!
+//! Mirrors the WebAssembly surface: one-shot `compress`/`decompress`,
+//! `Session` for schema-cached messages and `StreamSession` for delta
+//! updates, with statistics as dicts. The GIL is released while data is
+//! compressed or decompressed, so other Python threads keep running.
+//!
+//! ```python
+//! import json, flux
+//! import pandas as pd
+//!
+//! session = flux.Session()
+//! records = [json.loads(msg) for msg in session.decompress_all(capture)]
+//! df = pd.DataFrame.from_records(records)
+//! ```
+//!
+//! Built with maturin (see `pyproject.toml`).
+
+use std::time::Duration;
+
+use flux_core::{FluxConfig, FluxSession, FluxStreamSession, StreamConfig};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList};
+
+create_exception!(flux, FluxError, PyException, "Raised when FLUX data can't be compressed or decompressed");
+
+fn flux_err(e: flux_core::Error) -> PyErr {
+    FluxError::new_err(e.to_string())
+}
+
+// ============================================================================
+// One-shot compression
+// ============================================================================
+
+/// Compress JSON bytes using FLUX
+#[pyfunction]
+fn compress<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let out = py.allow_threads(|| flux_core::compress(data)).map_err(flux_err)?;
+    Ok(PyBytes::new(py, &out))
+}
+
+/// Decompress a FLUX frame to JSON bytes
+#[pyfunction]
+fn decompress<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let out = py.allow_threads(|| flux_core::decompress(data)).map_err(flux_err)?;
+    Ok(PyBytes::new(py, &out))
+}
+
+// ============================================================================
+// Session-based compression (schema caching)
+// ============================================================================
+
+/// FLUX session caching schemas across messages
+#[pyclass(module = "flux")]
+struct Session {
+    inner: FluxSession,
+}
+
+#[pymethods]
+impl Session {
+    #[new]
+    #[pyo3(signature = (columnar=true, entropy=true, delta=true, checksum=false))]
+    fn new(columnar: bool, entropy: bool, delta: bool, checksum: bool) -> PyResult<Self> {
+        let config = FluxConfig::builder()
+            .columnar(columnar)
+            .entropy(entropy)
+            .delta(delta)
+            .checksum(checksum)
+            .build()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self { inner: FluxSession::with_config(config) })
+    }
+
+    /// Compress JSON bytes; schemas already sent are referenced by id
+    fn compress<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let out = py.allow_threads(|| self.inner.compress(data)).map_err(flux_err)?;
+        Ok(PyBytes::new(py, &out))
+    }
+
+    /// Decompress a frame to JSON bytes
+    fn decompress<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let out = py.allow_threads(|| self.inner.decompress(data)).map_err(flux_err)?;
+        Ok(PyBytes::new(py, &out))
+    }
+
+    /// Decompress concatenated frames, e.g. a capture file, to a list of JSON bytes
+    fn decompress_all<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyList>> {
+        let messages = py.allow_threads(|| self.inner.decompress_all(data)).map_err(flux_err)?;
+        PyList::new(py, messages.iter().map(|json| PyBytes::new(py, json)))
+    }
+
+    /// Session statistics as a dict
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = self.inner.stats();
+        let dict = PyDict::new(py);
+        dict.set_item("messages_processed", stats.messages_processed)?;
+        dict.set_item("bytes_in", stats.bytes_in)?;
+        dict.set_item("bytes_out", stats.bytes_out)?;
+        dict.set_item("schemas_cached", stats.schemas_cached)?;
+        dict.set_item("cache_hits", stats.cache_hits)?;
+        dict.set_item("cache_misses", stats.cache_misses)?;
+        dict.set_item("compression_ratio", self.inner.compression_ratio())?;
+        dict.set_item("encode_gain", stats.encode_gain())?;
+        dict.set_item("lz_gain", stats.lz_gain())?;
+        dict.set_item("entropy_gain", stats.entropy_gain())?;
+        dict.set_item("schema_overhead_bytes", stats.schema_bytes)?;
+        Ok(dict)
+    }
+
+    /// Cached schemas as a list of dicts (id, version, hash, fields)
+    fn list_schemas<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let schemas = PyList::empty(py);
+        for schema in self.inner.schema_cache().iter() {
+            let dict = PyDict::new(py);
+            dict.set_item("id", schema.id)?;
+            dict.set_item("version", schema.version)?;
+            dict.set_item("hash", schema.hash)?;
+            dict.set_item("fields", schema.fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>())?;
+            schemas.append(dict)?;
+        }
+        Ok(schemas)
+    }
+
+    /// Remove a cached schema; returns whether it was cached
+    fn remove_schema(&mut self, schema_id: u32) -> bool {
+        self.inner.remove_schema(schema_id).is_some()
+    }
+
+    /// Reset session state
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+// ============================================================================
+// Streaming delta compression (real-time state updates)
+// ============================================================================
+
+/// Session sending state updates as deltas against the previous state
+#[pyclass(module = "flux")]
+struct StreamSession {
+    inner: FluxStreamSession,
+}
+
+#[pymethods]
+impl StreamSession {
+    /// Send a full snapshot every `keyframe_interval` updates and/or every
+    /// `keyframe_period_ms` milliseconds (0 = never)
+    #[new]
+    #[pyo3(signature = (keyframe_interval=0, keyframe_period_ms=0.0))]
+    fn new(keyframe_interval: u32, keyframe_period_ms: f64) -> PyResult<Self> {
+        if !(keyframe_period_ms >= 0.0 && keyframe_period_ms.is_finite()) {
+            return Err(PyValueError::new_err("keyframe_period_ms must be a non-negative number"));
+        }
+        let config = StreamConfig {
+            keyframe_interval,
+            keyframe_period: Duration::from_secs_f64(keyframe_period_ms / 1000.0),
+            ..StreamConfig::default()
+        };
+        Ok(Self { inner: FluxStreamSession::with_config(config) })
+    }
+
+    /// Encode a state update; the first is the full state, later ones only changes
+    fn update<'py>(&mut self, py: Python<'py>, json: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let out = py.allow_threads(|| self.inner.update(json)).map_err(flux_err)?;
+        Ok(PyBytes::new(py, &out))
+    }
+
+    /// Apply an update and return the full state as JSON bytes
+    fn receive<'py>(&mut self, py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let out = py.allow_threads(|| self.inner.receive(data)).map_err(flux_err)?;
+        Ok(PyBytes::new(py, &out))
+    }
+
+    /// Streaming statistics as a dict
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let stats = self.inner.stats();
+        let dict = PyDict::new(py);
+        dict.set_item("updates_sent", stats.updates_sent)?;
+        dict.set_item("full_sends", stats.full_sends)?;
+        dict.set_item("delta_sends", stats.delta_sends)?;
+        dict.set_item("bytes_full", stats.bytes_full)?;
+        dict.set_item("bytes_delta", stats.bytes_delta)?;
+        dict.set_item("delta_efficiency", self.inner.delta_efficiency())?;
+        Ok(dict)
+    }
+
+    /// Reset streaming state
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "flux")]
+fn flux_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("FluxError", m.py().get_type::<FluxError>())?;
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_class::<Session>()?;
+    m.add_class::<StreamSession>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::ffi::c_str;
+
+    fn run(code: &std::ffi::CStr) {
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "flux").unwrap();
+            flux_module(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("flux", module).unwrap();
+            py.run(code, Some(&locals), None).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_oneshot_and_session() {
+        run(c_str!(
+            r#"
+import json
+msg = b'{"id":1,"name":"alice"}'
+assert flux.decompress(flux.compress(msg)) == msg
+
+sender, receiver = flux.Session(), flux.Session(checksum=True)
+capture = b"".join(sender.compress(json.dumps({"id": i, "v": i * 1.5}).encode()) for i in range(5))
+records = [json.loads(m) for m in receiver.decompress_all(capture)]
+assert [r["id"] for r in records] == list(range(5))
+stats = sender.stats()
+assert stats["messages_processed"] == 5 and stats["cache_hits"] == 4
+schemas = receiver.list_schemas()
+assert schemas[0]["fields"] == ["id", "v"]
+assert receiver.remove_schema(schemas[0]["id"])
+
+try:
+    flux.decompress(b"not flux")
+    raise AssertionError("expected FluxError")
+except flux.FluxError as e:
+    assert "magic" in str(e)
+"#
+        ));
+    }
+
+    #[test]
+    fn test_stream_session() {
+        run(c_str!(
+            r#"
+sender, receiver = flux.StreamSession(keyframe_interval=10), flux.StreamSession()
+for i in range(3):
+    state = ('{"count":%d,"name":"x"}' % i).encode()
+    assert receiver.receive(sender.update(state)) == state
+stats = sender.stats()
+assert stats["updates_sent"] == 3 and stats["full_sends"] == 1, stats
+try:
+    flux.StreamSession(keyframe_period_ms=-1)
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+//! FLUX codec for tonic
+//!
+//! tonic's `grpc-encoding` compressors are a closed set, so FLUX plugs in
+//! one level up, as the message codec: `FluxCodec` serializes each message
+//! to JSON with serde and sends it as a FLUX frame. This suits gateways
+//! that transcode JSON to gRPC, where messages are serde types rather than
+//! protobuf.
+//!
+//! # Example
+//!
+//! Generate services with the codec through tonic-build:
+//!
+//! ```rust,ignore
+//! tonic_build::manual::Builder::new().compile(&[Service::builder()
+//!     .name("Users")
+//!     .package("users")
+//!     .method(Method::builder()
+//!         .name("list")
+//!         .route_name("List")
+//!         .input_type("crate::ListRequest")
+//!         .output_type("crate::User")
+//!         .codec_path("flux_tonic::FluxCodec")
+//!         .server_streaming()
+//!         .build())
+//!     .build()]);
+//! ```
+//!
+//! or call a method directly:
+//!
+//! ```rust,ignore
+//! let codec = FluxCodec::<ListRequest, User>::default();
+//! let users = grpc.server_streaming(request, PathAndQuery::from_static("/users.Users/List"), codec).await?;
+//! ```
+//!
+//! # Sessions
+//!
+//! tonic builds an encoder and a decoder per call, each with its own
+//! `FluxSession`: a streaming call sends a schema once and references it
+//! in later messages, while unary calls carry their schema every time.
+//! Both peers must use the same `FluxConfig`.
+
+use std::marker::PhantomData;
+
+use bytes::{Buf, BufMut};
+use flux_core::{FluxConfig, FluxSession};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::Status;
+
+/// Name advertised for FLUX-coded messages, e.g. in gateway metadata
+pub const FLUX_ENCODING: &str = flux_core::http::FLUX_TOKEN;
+
+fn flux_status(e: flux_core::Error) -> Status {
+    Status::internal(format!("FLUX: {}", e))
+}
+
+fn json_status(e: serde_json::Error) -> Status {
+    Status::internal(format!("JSON: {}", e))
+}
+
+/// tonic `Codec` sending serde messages as FLUX-compressed JSON
+///
+/// `T` is the type sent and `U` the type received.
+#[derive(Debug, Clone)]
+pub struct FluxCodec<T, U> {
+    config: FluxConfig,
+    _marker: PhantomData<(T, U)>,
+}
+
+impl<T, U> FluxCodec<T, U> {
+    /// Codec whose sessions use `config`
+    pub fn with_config(config: FluxConfig) -> Self {
+        Self { config, _marker: PhantomData }
+    }
+
+    pub fn config(&self) -> &FluxConfig {
+        &self.config
+    }
+}
+
+impl<T, U> Default for FluxCodec<T, U> {
+    fn default() -> Self {
+        Self::with_config(FluxConfig::default())
+    }
+}
+
+impl<T, U> Codec for FluxCodec<T, U>
+where
+    T: Serialize + Send + 'static,
+    U: DeserializeOwned + Send + 'static,
+{
+    type Encode = T;
+    type Decode = U;
+    type Encoder = FluxEncoder<T>;
+    type Decoder = FluxDecoder<U>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        FluxEncoder { session: FluxSession::with_config(self.config.clone()), _marker: PhantomData }
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        FluxDecoder { session: FluxSession::with_config(self.config.clone()), _marker: PhantomData }
+    }
+}
+
+/// Encoder half of `FluxCodec`, one session per call
+pub struct FluxEncoder<T> {
+    session: FluxSession,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Serialize> FluxEncoder<T> {
+    fn encode_frame(&mut self, item: &T) -> Result<Vec<u8>, Status> {
+        let json = serde_json::to_vec(item).map_err(json_status)?;
+        self.session.compress(&json).map_err(flux_status)
+    }
+}
+
+impl<T: Serialize> Encoder for FluxEncoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn encode(&mut self, item: T, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        dst.put_slice(&self.encode_frame(&item)?);
+        Ok(())
+    }
+}
+
+/// Decoder half of `FluxCodec`, one session per call
+pub struct FluxDecoder<U> {
+    session: FluxSession,
+    _marker: PhantomData<U>,
+}
+
+impl<U: DeserializeOwned> FluxDecoder<U> {
+    fn decode_frame(&mut self, frame: &[u8]) -> Result<U, Status> {
+        let json = self.session.decompress(frame).map_err(flux_status)?;
+        serde_json::from_slice(&json).map_err(json_status)
+    }
+}
+
+impl<U: DeserializeOwned> Decoder for FluxDecoder<U> {
+    type Item = U;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<U>, Status> {
+        let frame = src.copy_to_bytes(src.remaining());
+        self.decode_frame(&frame).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use tonic::Code;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: u32,
+        name: String,
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut codec = FluxCodec::<User, User>::default();
+        let (mut encoder, mut decoder) = (codec.encoder(), codec.decoder());
+        let mut sizes = Vec::new();
+        for id in 0..3 {
+            let user = User { id, name: format!("user{}", id) };
+            let frame = encoder.encode_frame(&user).unwrap();
+            sizes.push(frame.len());
+            assert_eq!(decoder.decode_frame(&frame).unwrap(), user);
+        }
+        // The schema is sent with the first message only
+        assert!(sizes[1] < sizes[0]);
+        assert_eq!(encoder.session.stats().cache_hits, 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut codec = FluxCodec::<User, User>::default();
+        let err = codec.decoder().decode_frame(b"not flux").unwrap_err();
+        assert_eq!(err.code(), Code::Internal);
+
+        let frame = FluxCodec::<serde_json::Value, User>::default()
+            .encoder()
+            .encode_frame(&serde_json::json!({"id": "x"}))
+            .unwrap();
+        assert!(codec.decoder().decode_frame(&frame).unwrap_err().message().starts_with("JSON"));
+    }
+}
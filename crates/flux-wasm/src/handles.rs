@@ -0,0 +1,246 @@
+//! Handle-based session API (deprecated)
+//!
+//! Sessions live in a per-thread table and are addressed by integer IDs,
+//! so a destroyed or mistyped ID only fails at runtime. Kept as a shim
+//! over the same core sessions; use the `FluxSession`/`FluxStream`
+//! classes instead.
+
+// wasm-bindgen's generated glue calls the deprecated functions
+#![allow(deprecated)]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use flux_core::{snapshot_kind, FluxConfig, FluxSession, FluxStreamSession, SnapshotKind, StreamConfig};
+use wasm_bindgen::prelude::*;
+
+use crate::{js_error, new_session, new_stream, schemas_json, session_config, stream_config, to_json, SessionStats, StreamStats};
+
+// ============================================================================
+// Session-based compression (schema caching)
+// ============================================================================
+
+thread_local! {
+    static FLUX_SESSIONS: RefCell<HashMap<u32, FluxSession>> = RefCell::new(HashMap::new());
+    static STREAM_SESSIONS: RefCell<HashMap<u32, FluxStreamSession>> = RefCell::new(HashMap::new());
+    static NEXT_SESSION_ID: RefCell<u32> = const { RefCell::new(1) };
+}
+
+fn get_next_id() -> u32 {
+    NEXT_SESSION_ID.with(|next_id| {
+        let id = *next_id.borrow();
+        *next_id.borrow_mut() = id + 1;
+        id
+    })
+}
+
+fn with_session<T>(session_id: u32, f: impl FnOnce(&mut FluxSession) -> Result<T, JsValue>) -> Result<T, JsValue> {
+    FLUX_SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        let session = sessions.get_mut(&session_id)
+            .ok_or_else(|| JsValue::from_str("Invalid session ID"))?;
+        f(session)
+    })
+}
+
+fn with_stream<T>(session_id: u32, f: impl FnOnce(&mut FluxStreamSession) -> Result<T, JsValue>) -> Result<T, JsValue> {
+    STREAM_SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        let session = sessions.get_mut(&session_id)
+            .ok_or_else(|| JsValue::from_str("Invalid stream session ID"))?;
+        f(session)
+    })
+}
+
+fn insert_session(session: FluxSession) -> u32 {
+    let id = get_next_id();
+    FLUX_SESSIONS.with(|sessions| sessions.borrow_mut().insert(id, session));
+    id
+}
+
+fn insert_stream(session: FluxStreamSession) -> u32 {
+    let id = get_next_id();
+    STREAM_SESSIONS.with(|sessions| sessions.borrow_mut().insert(id, session));
+    id
+}
+
+/// Create a new FLUX session for schema-cached compression
+/// Returns session ID
+#[deprecated(note = "use `new FluxSession()`")]
+#[wasm_bindgen]
+pub fn flux_session_create() -> u32 {
+    insert_session(new_session(FluxConfig::default()))
+}
+
+/// Create a FLUX session with custom configuration
+#[deprecated(note = "use `FluxSession.withConfig()`")]
+#[wasm_bindgen]
+pub fn flux_session_create_with_config(
+    columnar: bool,
+    entropy: bool,
+    delta: bool,
+    checksum: bool,
+) -> Result<u32, JsValue> {
+    let config = session_config(columnar, entropy, delta, checksum)?;
+    Ok(insert_session(new_session(config)))
+}
+
+/// Compress using FLUX session (enables schema caching)
+#[deprecated(note = "use `FluxSession.compress()`")]
+#[wasm_bindgen]
+pub fn flux_session_compress(session_id: u32, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    with_session(session_id, |session| session.compress(data).map_err(js_error))
+}
+
+/// Decompress using FLUX session
+#[deprecated(note = "use `FluxSession.decompress()`")]
+#[wasm_bindgen]
+pub fn flux_session_decompress(session_id: u32, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    with_session(session_id, |session| session.decompress(data).map_err(js_error))
+}
+
+/// Get FLUX session statistics as JSON
+#[deprecated(note = "use `FluxSession.stats()`")]
+#[wasm_bindgen]
+pub fn flux_session_stats(session_id: u32) -> Result<String, JsValue> {
+    with_session(session_id, |session| Ok(to_json(&SessionStats::of(session))))
+}
+
+/// List the schemas cached by a FLUX session as JSON
+///
+/// Hashes are hex strings since they do not fit in a JS number.
+#[deprecated(note = "use `FluxSession.listSchemas()`")]
+#[wasm_bindgen]
+pub fn flux_session_list_schemas(session_id: u32) -> Result<String, JsValue> {
+    with_session(session_id, |session| Ok(schemas_json(session)))
+}
+
+/// Remove a cached schema from a FLUX session
+///
+/// Returns whether the schema was cached.
+#[deprecated(note = "use `FluxSession.removeSchema()`")]
+#[wasm_bindgen]
+pub fn flux_session_remove_schema(session_id: u32, schema_id: u32) -> Result<bool, JsValue> {
+    with_session(session_id, |session| Ok(session.remove_schema(schema_id).is_some()))
+}
+
+/// Reset FLUX session state
+#[deprecated(note = "use `FluxSession.reset()`")]
+#[wasm_bindgen]
+pub fn flux_session_reset(session_id: u32) -> Result<(), JsValue> {
+    with_session(session_id, |session| {
+        session.reset();
+        Ok(())
+    })
+}
+
+/// Destroy a FLUX session
+#[deprecated(note = "use `FluxSession.free()`")]
+#[wasm_bindgen]
+pub fn flux_session_destroy(session_id: u32) -> bool {
+    FLUX_SESSIONS.with(|sessions| {
+        sessions.borrow_mut().remove(&session_id).is_some()
+    })
+}
+
+// ============================================================================
+// Streaming delta compression (real-time state updates)
+// ============================================================================
+
+/// Create a new streaming session for delta compression
+/// Ideal for WebSocket-style real-time state updates
+#[deprecated(note = "use `new FluxStream()`")]
+#[wasm_bindgen]
+pub fn flux_stream_create() -> u32 {
+    insert_stream(new_stream(StreamConfig::default()))
+}
+
+/// Create a streaming session that sends a full snapshot every
+/// `keyframe_interval` updates and/or every `keyframe_period_ms` milliseconds
+/// (0 = never)
+#[deprecated(note = "use `FluxStream.withKeyframes()`")]
+#[wasm_bindgen]
+pub fn flux_stream_create_with_keyframes(keyframe_interval: u32, keyframe_period_ms: f64) -> Result<u32, JsValue> {
+    let config = stream_config(keyframe_interval, keyframe_period_ms)?;
+    Ok(insert_stream(new_stream(config)))
+}
+
+/// Send state update, returns compressed delta
+/// First call returns full state, subsequent calls return only changes
+#[deprecated(note = "use `FluxStream.update()`")]
+#[wasm_bindgen]
+pub fn flux_stream_update(session_id: u32, json: &[u8]) -> Result<Vec<u8>, JsValue> {
+    with_stream(session_id, |session| session.update(json).map_err(js_error))
+}
+
+/// Receive delta and reconstruct full state
+#[deprecated(note = "use `FluxStream.receive()`")]
+#[wasm_bindgen]
+pub fn flux_stream_receive(session_id: u32, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    with_stream(session_id, |session| session.receive(data).map_err(js_error))
+}
+
+/// Get streaming session statistics
+#[deprecated(note = "use `FluxStream.stats()`")]
+#[wasm_bindgen]
+pub fn flux_stream_stats(session_id: u32) -> Result<String, JsValue> {
+    with_stream(session_id, |session| Ok(to_json(&StreamStats::of(session))))
+}
+
+/// Reset streaming session state
+#[deprecated(note = "use `FluxStream.reset()`")]
+#[wasm_bindgen]
+pub fn flux_stream_reset(session_id: u32) -> Result<(), JsValue> {
+    with_stream(session_id, |session| {
+        session.reset();
+        Ok(())
+    })
+}
+
+/// Destroy a streaming session
+#[deprecated(note = "use `FluxStream.free()`")]
+#[wasm_bindgen]
+pub fn flux_stream_destroy(session_id: u32) -> bool {
+    STREAM_SESSIONS.with(|sessions| {
+        sessions.borrow_mut().remove(&session_id).is_some()
+    })
+}
+
+// ============================================================================
+// Session snapshots (persist across page reloads)
+// ============================================================================
+
+/// Export the state of a FLUX or streaming session
+///
+/// The snapshot holds the schema cache, value dictionaries, LZ history and,
+/// for streaming sessions, the delta baselines; store it (e.g. in
+/// IndexedDB) and pass it to `flux_session_import` after a reload.
+#[deprecated(note = "use `FluxSession.exportState()` or `FluxStream.exportState()`")]
+#[wasm_bindgen]
+pub fn flux_session_export(session_id: u32) -> Result<Vec<u8>, JsValue> {
+    let snapshot = FLUX_SESSIONS.with(|sessions| sessions.borrow().get(&session_id).map(FluxSession::export_state));
+    snapshot
+        .or_else(|| STREAM_SESSIONS.with(|sessions| sessions.borrow().get(&session_id).map(FluxStreamSession::export_state)))
+        .ok_or_else(|| JsValue::from_str("Invalid session ID"))
+}
+
+/// Restore a session from `flux_session_export`, returning its new ID
+///
+/// The snapshot decides whether a FLUX or a streaming session is created;
+/// either uses the default configuration.
+#[deprecated(note = "use `FluxSession.importState()` or `FluxStream.importState()`")]
+#[wasm_bindgen]
+pub fn flux_session_import(snapshot: &[u8]) -> Result<u32, JsValue> {
+    match snapshot_kind(snapshot).map_err(js_error)? {
+        SnapshotKind::Session => {
+            let mut session = new_session(FluxConfig::default());
+            session.import_state(snapshot).map_err(js_error)?;
+            Ok(insert_session(session))
+        }
+        SnapshotKind::Stream => {
+            let mut session = new_stream(StreamConfig::default());
+            session.import_state(snapshot).map_err(js_error)?;
+            Ok(insert_stream(session))
+        }
+    }
+}
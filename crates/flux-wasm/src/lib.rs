@@ -1,15 +1,216 @@
 //! WebAssembly bindings for FLUX v2
 //!
 //! FLUX is a schema-aware JSON compression protocol optimized for API traffic.
+//!
+//! Sessions are exported as the `FluxSession` and `FluxStream` classes;
+//! call `free()` when done (or rely on the finalizer when weak references
+//! are enabled). The older handle-based `flux_session_*`/`flux_stream_*`
+//! functions remain as a deprecated shim.
 
 use wasm_bindgen::prelude::*;
 use flux_core::{
     compress as core_compress,
     decompress as core_decompress,
-    FluxSession, FluxConfig, FluxStreamSession,
+    Clock, FluxSession, FluxConfig, FluxStreamSession, StreamConfig,
 };
-use std::cell::RefCell;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `performance.now()` as a `flux_core::Clock`
+///
+/// `std::time::Instant` is unavailable on `wasm32-unknown-unknown`; without
+/// this, sessions would run with no clock and time-based features stay off.
+struct PerformanceClock;
+
+impl Clock for PerformanceClock {
+    fn now(&self) -> Option<Duration> {
+        let performance = js_sys::Reflect::get(&js_sys::global(), &"performance".into()).ok()?;
+        let now: js_sys::Function = js_sys::Reflect::get(&performance, &"now".into()).ok()?.dyn_into().ok()?;
+        let millis = now.call0(&performance).ok()?.as_f64()?;
+        Some(Duration::from_secs_f64(millis.max(0.0) / 1000.0))
+    }
+}
+
+fn new_session(config: FluxConfig) -> FluxSession {
+    let mut session = FluxSession::with_config(config);
+    session.set_clock(Arc::new(PerformanceClock));
+    session
+}
+
+fn new_stream(config: StreamConfig) -> FluxStreamSession {
+    let mut session = FluxStreamSession::with_config(config);
+    session.set_clock(Arc::new(PerformanceClock));
+    session
+}
+
+fn js_error(e: flux_core::Error) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+fn session_config(columnar: bool, entropy: bool, delta: bool, checksum: bool) -> Result<FluxConfig, JsValue> {
+    FluxConfig::builder()
+        .columnar(columnar)
+        .entropy(entropy)
+        .delta(delta)
+        .checksum(checksum)
+        .build()
+        .map_err(js_error)
+}
+
+fn stream_config(keyframe_interval: u32, keyframe_period_ms: f64) -> Result<StreamConfig, JsValue> {
+    if !(keyframe_period_ms >= 0.0 && keyframe_period_ms.is_finite()) {
+        return Err(JsValue::from_str("keyframe_period_ms must be a non-negative number"));
+    }
+    Ok(StreamConfig {
+        keyframe_interval,
+        keyframe_period: Duration::from_secs_f64(keyframe_period_ms / 1000.0),
+        ..StreamConfig::default()
+    })
+}
+
+/// Session statistics in the shape of the `FluxSessionStats` TypeScript interface
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionStats {
+    messages_processed: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    schemas_cached: usize,
+    schema_evictions: u64,
+    cache_hits: u64,
+    cache_misses: u64,
+    compression_ratio: f64,
+    encoded_bytes: u64,
+    lz_bytes: u64,
+    entropy_bytes: u64,
+    schema_overhead_bytes: u64,
+    dictionary_bytes: u64,
+    encode_gain: i64,
+    lz_gain: i64,
+    entropy_gain: i64,
+}
+
+impl SessionStats {
+    fn of(session: &FluxSession) -> Self {
+        let stats = session.stats();
+        Self {
+            messages_processed: stats.messages_processed,
+            bytes_in: stats.bytes_in,
+            bytes_out: stats.bytes_out,
+            schemas_cached: stats.schemas_cached,
+            schema_evictions: stats.schema_evictions,
+            cache_hits: stats.cache_hits,
+            cache_misses: stats.cache_misses,
+            compression_ratio: session.compression_ratio(),
+            encoded_bytes: stats.encoded_bytes,
+            lz_bytes: stats.lz_bytes,
+            entropy_bytes: stats.entropy_bytes,
+            schema_overhead_bytes: stats.schema_bytes,
+            dictionary_bytes: stats.dictionary_bytes,
+            encode_gain: stats.encode_gain(),
+            lz_gain: stats.lz_gain(),
+            entropy_gain: stats.entropy_gain(),
+        }
+    }
+}
+
+/// Streaming statistics in the shape of the `FluxStreamStats` TypeScript interface
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamStats {
+    updates_sent: u64,
+    full_sends: u64,
+    delta_sends: u64,
+    bytes_full: u64,
+    bytes_delta: u64,
+    delta_efficiency: f64,
+}
+
+impl StreamStats {
+    fn of(session: &FluxStreamSession) -> Self {
+        let stats = session.stats();
+        Self {
+            updates_sent: stats.updates_sent,
+            full_sends: stats.full_sends,
+            delta_sends: stats.delta_sends,
+            bytes_full: stats.bytes_full,
+            bytes_delta: stats.bytes_delta,
+            delta_efficiency: session.delta_efficiency(),
+        }
+    }
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const STATS_TYPES: &'static str = r#"
+/** FLUX session statistics; sizes are in bytes */
+export interface FluxSessionStats {
+  messagesProcessed: number;
+  bytesIn: number;
+  bytesOut: number;
+  schemasCached: number;
+  schemaEvictions: number;
+  cacheHits: number;
+  cacheMisses: number;
+  compressionRatio: number;
+  /** Output of the schema encoder (input to LZ) */
+  encodedBytes: number;
+  /** Output of the LZ stage (input to entropy coding) */
+  lzBytes: number;
+  /** Output of the entropy stage (frame payloads) */
+  entropyBytes: number;
+  /** Inline schemas and schema deltas */
+  schemaOverheadBytes: number;
+  /** Value dictionary sections */
+  dictionaryBytes: number;
+  encodeGain: number;
+  lzGain: number;
+  entropyGain: number;
+}
+
+/** FLUX streaming session statistics */
+export interface FluxStreamStats {
+  updatesSent: number;
+  fullSends: number;
+  deltaSends: number;
+  bytesFull: number;
+  bytesDelta: number;
+  deltaEfficiency: number;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "FluxSessionStats")]
+    pub type FluxSessionStats;
+
+    #[wasm_bindgen(typescript_type = "FluxStreamStats")]
+    pub type FluxStreamStats;
+}
+
+fn to_js<T: Serialize, R: JsCast>(value: &T) -> Result<R, JsValue> {
+    serde_wasm_bindgen::to_value(value)
+        .map(JsCast::unchecked_into)
+        .map_err(JsValue::from)
+}
+
+/// Statistics as a JSON string, for the handle-based API
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Cached schemas as JSON; hashes are hex strings since they do not fit in a JS number
+fn schemas_json(session: &FluxSession) -> String {
+    let schemas: Vec<serde_json::Value> = session.schema_cache().iter().map(|schema| {
+        serde_json::json!({
+            "id": schema.id,
+            "version": schema.version,
+            "hash": format!("{:016x}", schema.hash),
+            "fields": schema.fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+        })
+    }).collect();
+    serde_json::Value::Array(schemas).to_string()
+}
 
 // ============================================================================
 // One-shot compression
@@ -21,225 +222,159 @@ use std::collections::HashMap;
 /// use session-based compression instead.
 #[wasm_bindgen]
 pub fn flux_compress(data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    core_compress(data)
-        .map_err(|e| JsValue::from_str(&e.to_string()))
+    core_compress(data).map_err(js_error)
 }
 
 /// Decompress FLUX data
 #[wasm_bindgen]
 pub fn flux_decompress(data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    core_decompress(data)
-        .map_err(|e| JsValue::from_str(&e.to_string()))
+    core_decompress(data).map_err(js_error)
 }
 
 // ============================================================================
 // Session-based compression (schema caching)
 // ============================================================================
 
-thread_local! {
-    static FLUX_SESSIONS: RefCell<HashMap<u32, FluxSession>> = RefCell::new(HashMap::new());
-    static STREAM_SESSIONS: RefCell<HashMap<u32, FluxStreamSession>> = RefCell::new(HashMap::new());
-    static NEXT_SESSION_ID: RefCell<u32> = RefCell::new(1);
+/// FLUX session caching schemas across messages
+#[wasm_bindgen(js_name = FluxSession)]
+pub struct WasmSession {
+    inner: FluxSession,
 }
 
-fn get_next_id() -> u32 {
-    NEXT_SESSION_ID.with(|next_id| {
-        let id = *next_id.borrow();
-        *next_id.borrow_mut() = id + 1;
-        id
-    })
-}
+#[wasm_bindgen(js_class = FluxSession)]
+impl WasmSession {
+    /// Create a session with the default configuration
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> WasmSession {
+        WasmSession { inner: new_session(FluxConfig::default()) }
+    }
 
-/// Create a new FLUX session for schema-cached compression
-/// Returns session ID
-#[wasm_bindgen]
-pub fn flux_session_create() -> u32 {
-    let id = get_next_id();
-    FLUX_SESSIONS.with(|sessions| {
-        sessions.borrow_mut().insert(id, FluxSession::new());
-    });
-    id
-}
+    /// Create a session with custom configuration
+    #[wasm_bindgen(js_name = withConfig)]
+    pub fn with_config(columnar: bool, entropy: bool, delta: bool, checksum: bool) -> Result<WasmSession, JsValue> {
+        let config = session_config(columnar, entropy, delta, checksum)?;
+        Ok(WasmSession { inner: new_session(config) })
+    }
 
-/// Create a FLUX session with custom configuration
-#[wasm_bindgen]
-pub fn flux_session_create_with_config(
-    columnar: bool,
-    entropy: bool,
-    delta: bool,
-    checksum: bool,
-) -> u32 {
-    let id = get_next_id();
-    let config = FluxConfig {
-        columnar,
-        entropy,
-        delta,
-        checksum,
-        max_dict_size: 65536,
-    };
-    FLUX_SESSIONS.with(|sessions| {
-        sessions.borrow_mut().insert(id, FluxSession::with_config(config));
-    });
-    id
-}
+    /// Restore a session from `exportState()`, using the default configuration
+    #[wasm_bindgen(js_name = importState)]
+    pub fn import_state(snapshot: &[u8]) -> Result<WasmSession, JsValue> {
+        let mut session = WasmSession::new();
+        session.inner.import_state(snapshot).map_err(js_error)?;
+        Ok(session)
+    }
 
-/// Compress using FLUX session (enables schema caching)
-#[wasm_bindgen]
-pub fn flux_session_compress(session_id: u32, data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    FLUX_SESSIONS.with(|sessions| {
-        let mut sessions = sessions.borrow_mut();
-        let session = sessions.get_mut(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid session ID"))?;
-
-        session.compress(data)
-            .map_err(|e| JsValue::from_str(&e.to_string()))
-    })
-}
+    /// Compress JSON; schemas already sent are referenced by ID
+    pub fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.inner.compress(data).map_err(js_error)
+    }
 
-/// Decompress using FLUX session
-#[wasm_bindgen]
-pub fn flux_session_decompress(session_id: u32, data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    FLUX_SESSIONS.with(|sessions| {
-        let mut sessions = sessions.borrow_mut();
-        let session = sessions.get_mut(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid session ID"))?;
-
-        session.decompress(data)
-            .map_err(|e| JsValue::from_str(&e.to_string()))
-    })
-}
+    /// Decompress a frame to JSON
+    pub fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.inner.decompress(data).map_err(js_error)
+    }
 
-/// Get FLUX session statistics as JSON
-#[wasm_bindgen]
-pub fn flux_session_stats(session_id: u32) -> Result<String, JsValue> {
-    FLUX_SESSIONS.with(|sessions| {
-        let sessions = sessions.borrow();
-        let session = sessions.get(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid session ID"))?;
+    /// Session statistics, with the output size of each stage
+    pub fn stats(&self) -> Result<FluxSessionStats, JsValue> {
+        to_js(&SessionStats::of(&self.inner))
+    }
 
-        let stats = session.stats();
-        let ratio = session.compression_ratio();
-
-        Ok(format!(
-            r#"{{"messagesProcessed":{},"bytesIn":{},"bytesOut":{},"schemasCached":{},"cacheHits":{},"cacheMisses":{},"compressionRatio":{:.3}}}"#,
-            stats.messages_processed,
-            stats.bytes_in,
-            stats.bytes_out,
-            stats.schemas_cached,
-            stats.cache_hits,
-            stats.cache_misses,
-            ratio
-        ))
-    })
-}
+    /// Cached schemas as JSON
+    #[wasm_bindgen(js_name = listSchemas)]
+    pub fn list_schemas(&self) -> String {
+        schemas_json(&self.inner)
+    }
 
-/// Reset FLUX session state
-#[wasm_bindgen]
-pub fn flux_session_reset(session_id: u32) -> Result<(), JsValue> {
-    FLUX_SESSIONS.with(|sessions| {
-        let mut sessions = sessions.borrow_mut();
-        let session = sessions.get_mut(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid session ID"))?;
-
-        session.reset();
-        Ok(())
-    })
-}
+    /// Remove a cached schema; returns whether it was cached
+    #[wasm_bindgen(js_name = removeSchema)]
+    pub fn remove_schema(&mut self, schema_id: u32) -> bool {
+        self.inner.remove_schema(schema_id).is_some()
+    }
 
-/// Destroy a FLUX session
-#[wasm_bindgen]
-pub fn flux_session_destroy(session_id: u32) -> bool {
-    FLUX_SESSIONS.with(|sessions| {
-        sessions.borrow_mut().remove(&session_id).is_some()
-    })
+    /// Reset session state
+    pub fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    /// Snapshot of the schema cache, dictionaries and LZ history,
+    /// e.g. to keep in IndexedDB across page reloads
+    #[wasm_bindgen(js_name = exportState)]
+    pub fn export_state(&self) -> Vec<u8> {
+        self.inner.export_state()
+    }
 }
 
 // ============================================================================
 // Streaming delta compression (real-time state updates)
 // ============================================================================
 
-/// Create a new streaming session for delta compression
-/// Ideal for WebSocket-style real-time state updates
-#[wasm_bindgen]
-pub fn flux_stream_create() -> u32 {
-    let id = get_next_id();
-    STREAM_SESSIONS.with(|sessions| {
-        sessions.borrow_mut().insert(id, FluxStreamSession::new());
-    });
-    id
+/// Session sending state updates as deltas against the previous state
+///
+/// Ideal for WebSocket-style real-time state updates.
+#[wasm_bindgen(js_name = FluxStream)]
+pub struct WasmStream {
+    inner: FluxStreamSession,
 }
 
-/// Send state update, returns compressed delta
-/// First call returns full state, subsequent calls return only changes
-#[wasm_bindgen]
-pub fn flux_stream_update(session_id: u32, json: &[u8]) -> Result<Vec<u8>, JsValue> {
-    STREAM_SESSIONS.with(|sessions| {
-        let mut sessions = sessions.borrow_mut();
-        let session = sessions.get_mut(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid stream session ID"))?;
-
-        session.update(json)
-            .map_err(|e| JsValue::from_str(&e.to_string()))
-    })
-}
+#[wasm_bindgen(js_class = FluxStream)]
+impl WasmStream {
+    /// Create a streaming session that never forces full snapshots
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> WasmStream {
+        WasmStream { inner: new_stream(StreamConfig::default()) }
+    }
 
-/// Receive delta and reconstruct full state
-#[wasm_bindgen]
-pub fn flux_stream_receive(session_id: u32, data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    STREAM_SESSIONS.with(|sessions| {
-        let mut sessions = sessions.borrow_mut();
-        let session = sessions.get_mut(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid stream session ID"))?;
-
-        session.receive(data)
-            .map_err(|e| JsValue::from_str(&e.to_string()))
-    })
-}
+    /// Create a streaming session that sends a full snapshot every
+    /// `keyframe_interval` updates and/or every `keyframe_period_ms`
+    /// milliseconds (0 = never)
+    #[wasm_bindgen(js_name = withKeyframes)]
+    pub fn with_keyframes(keyframe_interval: u32, keyframe_period_ms: f64) -> Result<WasmStream, JsValue> {
+        let config = stream_config(keyframe_interval, keyframe_period_ms)?;
+        Ok(WasmStream { inner: new_stream(config) })
+    }
 
-/// Get streaming session statistics
-#[wasm_bindgen]
-pub fn flux_stream_stats(session_id: u32) -> Result<String, JsValue> {
-    STREAM_SESSIONS.with(|sessions| {
-        let sessions = sessions.borrow();
-        let session = sessions.get(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid stream session ID"))?;
+    /// Restore a streaming session from `exportState()`, using the default configuration
+    #[wasm_bindgen(js_name = importState)]
+    pub fn import_state(snapshot: &[u8]) -> Result<WasmStream, JsValue> {
+        let mut session = WasmStream::new();
+        session.inner.import_state(snapshot).map_err(js_error)?;
+        Ok(session)
+    }
 
-        let stats = session.stats();
-        let efficiency = session.delta_efficiency();
-
-        Ok(format!(
-            r#"{{"updatesSent":{},"fullSends":{},"deltaSends":{},"bytesFull":{},"bytesDelta":{},"deltaEfficiency":{:.3}}}"#,
-            stats.updates_sent,
-            stats.full_sends,
-            stats.delta_sends,
-            stats.bytes_full,
-            stats.bytes_delta,
-            efficiency
-        ))
-    })
-}
+    /// Encode a state update; the first is the full state, later ones only changes
+    pub fn update(&mut self, json: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.inner.update(json).map_err(js_error)
+    }
 
-/// Reset streaming session state
-#[wasm_bindgen]
-pub fn flux_stream_reset(session_id: u32) -> Result<(), JsValue> {
-    STREAM_SESSIONS.with(|sessions| {
-        let mut sessions = sessions.borrow_mut();
-        let session = sessions.get_mut(&session_id)
-            .ok_or_else(|| JsValue::from_str("Invalid stream session ID"))?;
-
-        session.reset();
-        Ok(())
-    })
-}
+    /// Apply an update and return the full state
+    pub fn receive(&mut self, data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        self.inner.receive(data).map_err(js_error)
+    }
 
-/// Destroy a streaming session
-#[wasm_bindgen]
-pub fn flux_stream_destroy(session_id: u32) -> bool {
-    STREAM_SESSIONS.with(|sessions| {
-        sessions.borrow_mut().remove(&session_id).is_some()
-    })
+    /// Streaming statistics
+    pub fn stats(&self) -> Result<FluxStreamStats, JsValue> {
+        to_js(&StreamStats::of(&self.inner))
+    }
+
+    /// Reset streaming state
+    pub fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    /// Snapshot of the sent and received states, e.g. to keep in IndexedDB
+    /// across page reloads
+    #[wasm_bindgen(js_name = exportState)]
+    pub fn export_state(&self) -> Vec<u8> {
+        self.inner.export_state()
+    }
 }
 
+mod handles;
+#[allow(deprecated)]
+pub use handles::*;
+
 // ============================================================================
 // Utilities
 // ============================================================================
@@ -4,15 +4,66 @@
  * Uses native addon when available, falls back to WASM
  */
 
+import type { Transform, TransformOptions } from 'stream';
 import type { CompressInput, CompressOptions, CompressResult } from './types';
 import { normalizeInput } from './types';
 
+/**
+ * Keyframe options of a FLUX streaming session
+ */
+export interface FluxStreamOptions {
+  /** Send a full snapshot every this many updates (0 = never) */
+  keyframeInterval?: number;
+  /** Send a full snapshot once this many milliseconds have passed since the last one (0 = never) */
+  keyframePeriodMs?: number;
+}
+
+/**
+ * FLUX streaming session statistics
+ */
+export interface FluxStreamStats {
+  updatesSent: number;
+  fullSends: number;
+  deltaSends: number;
+  bytesFull: number;
+  bytesDelta: number;
+  deltaEfficiency: number;
+}
+
+/**
+ * Options of a FLUX compression stream
+ */
+export interface FluxCompressOptions {
+  /** 0 = fast, 1 = default, 2 = max */
+  level?: number;
+  /** Append a checksum to every frame */
+  checksum?: boolean;
+}
+
+interface NativeByteStage {
+  push(chunk: Buffer): Promise<Buffer>;
+  finish(): Promise<Buffer>;
+}
+
+interface NativeFluxStreamSession {
+  update(state: unknown): Promise<Buffer>;
+  receive(delta: Buffer): Promise<unknown>;
+  reset(): Promise<void>;
+  stats(): FluxStreamStats;
+}
+
 // Try to load native addon, fall back to WASM
 let nativeAddon: {
   compressSync: (data: Buffer) => Buffer;
   decompressSyncWithLevel: (data: Buffer, level: number) => Buffer;
   decompressSync: (data: Buffer) => Buffer;
   version: () => string;
+  autoCompressSync: (data: Buffer) => Buffer;
+  autoDecompressSync: (data: Buffer) => Buffer;
+  autoCodec: (data: Buffer) => string;
+  FluxStreamSession: new (options?: FluxStreamOptions) => NativeFluxStreamSession;
+  FluxCompressor: new (options?: FluxCompressOptions) => NativeByteStage;
+  FluxDecompressor: new () => NativeByteStage;
 } | null = null;
 
 let wasmModule: typeof import('../wasm/fastpack_wasm') | null = null;
@@ -102,6 +153,45 @@ export function decompressSync(input: Uint8Array): CompressResult {
   return new Uint8Array(nativeAddon.decompressSync(Buffer.from(input)));
 }
 
+/**
+ * Codec `autoCompressSync` chose for an envelope
+ */
+export type AutoCodec = 'lz4' | 'apex' | 'flux';
+
+/**
+ * Compress data with the codec that suits it (native addon only)
+ *
+ * JSON goes to FLUX, JSON that FLUX can't rebuild byte for byte to APEX,
+ * and small or non-JSON input to LZ4. The choice is recorded with the
+ * data, so `autoDecompressSync` takes any of them.
+ */
+export function autoCompressSync(input: CompressInput): CompressResult {
+  if (!nativeAddon) {
+    throw new Error('Auto compression requires the native addon.');
+  }
+  return new Uint8Array(nativeAddon.autoCompressSync(Buffer.from(normalizeInput(input))));
+}
+
+/**
+ * Decompress data from `autoCompressSync` (native addon only)
+ */
+export function autoDecompressSync(input: Uint8Array): CompressResult {
+  if (!nativeAddon) {
+    throw new Error('Auto compression requires the native addon.');
+  }
+  return new Uint8Array(nativeAddon.autoDecompressSync(Buffer.from(input)));
+}
+
+/**
+ * Codec data from `autoCompressSync` was compressed with (native addon only)
+ */
+export function autoCodec(input: Uint8Array): AutoCodec {
+  if (!nativeAddon) {
+    throw new Error('Auto compression requires the native addon.');
+  }
+  return nativeAddon.autoCodec(Buffer.from(input)) as AutoCodec;
+}
+
 /**
  * Create a compression Transform stream
  */
@@ -155,6 +245,125 @@ export function createDecompressStream() {
   });
 }
 
+function fluxTransform(stage: NativeByteStage, options: TransformOptions): Transform {
+  const { Transform } = require('stream');
+
+  // The callback runs once the chunk is processed, so a slow consumer
+  // pauses the source like any other Transform
+  return new Transform({
+    ...options,
+    transform(chunk: Buffer, _encoding: string, callback: (error?: Error | null, data?: Buffer) => void) {
+      stage.push(chunk).then((out) => callback(null, out.length > 0 ? out : undefined), callback);
+    },
+    flush(callback: (error?: Error | null, data?: Buffer) => void) {
+      stage.finish().then((out) => callback(null, out.length > 0 ? out : undefined), callback);
+    },
+  });
+}
+
+/**
+ * Create a Transform stream compressing newline-delimited JSON into FLUX
+ * frames, like `zlib.createGzip()` (native addon only)
+ *
+ * Each line is one message; all messages share a session, so schemas are
+ * sent once per stream.
+ *
+ * @example
+ * ```typescript
+ * res.setHeader('Content-Encoding', 'flux');
+ * ndjsonSource.pipe(createFluxCompressStream()).pipe(res);
+ * ```
+ */
+export function createFluxCompressStream(options: FluxCompressOptions & TransformOptions = {}): Transform {
+  if (!nativeAddon) {
+    throw new Error('FLUX streams require the native addon.');
+  }
+  const { level, checksum, ...transformOptions } = options;
+  return fluxTransform(new nativeAddon.FluxCompressor({ level, checksum }), transformOptions);
+}
+
+/**
+ * Create a Transform stream turning FLUX frames back into newline-delimited
+ * JSON, like `zlib.createGunzip()` (native addon only)
+ */
+export function createFluxDecompressStream(options: TransformOptions = {}): Transform {
+  if (!nativeAddon) {
+    throw new Error('FLUX streams require the native addon.');
+  }
+  return fluxTransform(new nativeAddon.FluxDecompressor(), options);
+}
+
+/**
+ * FLUX streaming session sending state objects as deltas (native addon only)
+ *
+ * Deltas are computed off the event loop; calls run in the order they
+ * were made, so updates may be issued without awaiting each one.
+ *
+ * @example
+ * ```typescript
+ * const session = new FluxStreamSession({ keyframeInterval: 100 });
+ * for await (const delta of session.deltas(liveState())) {
+ *   socket.emit('state', delta);
+ * }
+ * ```
+ */
+export class FluxStreamSession {
+  private inner: NativeFluxStreamSession;
+
+  constructor(options?: FluxStreamOptions) {
+    if (!nativeAddon) {
+      throw new Error('FluxStreamSession requires the native addon.');
+    }
+    this.inner = new nativeAddon.FluxStreamSession(options);
+  }
+
+  /**
+   * Encode a state object; the first update is the full state, later ones only changes
+   */
+  update(state: unknown): Promise<Buffer> {
+    return this.inner.update(state);
+  }
+
+  /**
+   * Apply an update and resolve to the full state
+   */
+  receive(delta: Uint8Array): Promise<unknown> {
+    return this.inner.receive(Buffer.from(delta));
+  }
+
+  /**
+   * Encode each state of `states` as it arrives
+   */
+  async *deltas(states: AsyncIterable<unknown> | Iterable<unknown>): AsyncGenerator<Buffer> {
+    for await (const state of states) {
+      yield await this.update(state);
+    }
+  }
+
+  /**
+   * Apply each delta of `deltas` as it arrives, yielding the full states
+   */
+  async *states(deltas: AsyncIterable<Uint8Array> | Iterable<Uint8Array>): AsyncGenerator<unknown> {
+    for await (const delta of deltas) {
+      yield await this.receive(delta);
+    }
+  }
+
+  /**
+   * Streaming statistics
+   */
+  stats(): FluxStreamStats {
+    return this.inner.stats();
+  }
+
+  /**
+   * Reset streaming state once pending calls have run
+   */
+  reset(): Promise<void> {
+    return this.inner.reset();
+  }
+}
+
 /**
  * Get library version
  */
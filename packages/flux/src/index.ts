@@ -31,6 +31,8 @@
 import type {
   FluxConfig,
   FluxStats,
+  FluxSchemaInfo,
+  FluxStreamOptions,
   FluxStreamStats,
   FluxAnalysis,
   FluxInput,
@@ -38,28 +40,41 @@ import type {
 } from './types';
 import { normalizeInput } from './types';
 
+// WASM classes (owned handles; `free()` releases the Rust session)
+interface WasmSession {
+  compress(data: Uint8Array): Uint8Array;
+  decompress(data: Uint8Array): Uint8Array;
+  stats(): FluxStats;
+  listSchemas(): string;
+  removeSchema(schemaId: number): boolean;
+  reset(): void;
+  exportState(): Uint8Array;
+  free(): void;
+}
+
+interface WasmStream {
+  update(data: Uint8Array): Uint8Array;
+  receive(data: Uint8Array): Uint8Array;
+  stats(): FluxStreamStats;
+  reset(): void;
+  exportState(): Uint8Array;
+  free(): void;
+}
+
 // WASM module type
 interface FluxWasm {
   flux_compress(data: Uint8Array): Uint8Array;
   flux_decompress(data: Uint8Array): Uint8Array;
-  flux_session_create(): number;
-  flux_session_create_with_config(
-    columnar: boolean,
-    entropy: boolean,
-    delta: boolean,
-    checksum: boolean
-  ): number;
-  flux_session_compress(sessionId: number, data: Uint8Array): Uint8Array;
-  flux_session_decompress(sessionId: number, data: Uint8Array): Uint8Array;
-  flux_session_stats(sessionId: number): string;
-  flux_session_reset(sessionId: number): void;
-  flux_session_destroy(sessionId: number): boolean;
-  flux_stream_create(): number;
-  flux_stream_update(sessionId: number, data: Uint8Array): Uint8Array;
-  flux_stream_receive(sessionId: number, data: Uint8Array): Uint8Array;
-  flux_stream_stats(sessionId: number): string;
-  flux_stream_reset(sessionId: number): void;
-  flux_stream_destroy(sessionId: number): boolean;
+  FluxSession: {
+    new (): WasmSession;
+    withConfig(columnar: boolean, entropy: boolean, delta: boolean, checksum: boolean): WasmSession;
+    importState(snapshot: Uint8Array): WasmSession;
+  };
+  FluxStream: {
+    new (): WasmStream;
+    withKeyframes(keyframeInterval: number, keyframePeriodMs: number): WasmStream;
+    importState(snapshot: Uint8Array): WasmStream;
+  };
   flux_version(): string;
   flux_analyze(data: Uint8Array): string;
 }
@@ -176,12 +191,10 @@ export async function version(): Promise<string> {
  * ```
  */
 export class FluxSession {
-  private wasm: FluxWasm;
-  private sessionId: number;
+  private inner: WasmSession;
 
-  private constructor(wasm: FluxWasm, sessionId: number) {
-    this.wasm = wasm;
-    this.sessionId = sessionId;
+  private constructor(inner: WasmSession) {
+    this.inner = inner;
   }
 
   /**
@@ -189,15 +202,15 @@ export class FluxSession {
    */
   static async create(config?: FluxConfig): Promise<FluxSession> {
     const wasm = await loadWasm();
-    const sessionId = config
-      ? wasm.flux_session_create_with_config(
+    const inner = config
+      ? wasm.FluxSession.withConfig(
           config.columnar ?? true,
           config.entropy ?? true,
           config.delta ?? true,
           config.checksum ?? true
         )
-      : wasm.flux_session_create();
-    return new FluxSession(wasm, sessionId);
+      : new wasm.FluxSession();
+    return new FluxSession(inner);
   }
 
   /**
@@ -205,36 +218,68 @@ export class FluxSession {
    */
   compress(input: FluxInput): FluxResult {
     const data = normalizeInput(input);
-    return this.wasm.flux_session_compress(this.sessionId, data);
+    return this.inner.compress(data);
   }
 
   /**
    * Decompress FLUX data using session schema cache
    */
   decompress(data: Uint8Array): FluxResult {
-    return this.wasm.flux_session_decompress(this.sessionId, data);
+    return this.inner.decompress(data);
   }
 
   /**
    * Get session statistics
    */
   stats(): FluxStats {
-    const json = this.wasm.flux_session_stats(this.sessionId);
+    return this.inner.stats();
+  }
+
+  /**
+   * List cached schemas
+   */
+  listSchemas(): FluxSchemaInfo[] {
+    const json = this.inner.listSchemas();
     return JSON.parse(json);
   }
 
+  /**
+   * Drop one cached schema without resetting the session
+   *
+   * Returns whether the schema was cached.
+   */
+  removeSchema(schemaId: number): boolean {
+    return this.inner.removeSchema(schemaId);
+  }
+
   /**
    * Reset session state (clears schema cache)
    */
   reset(): void {
-    this.wasm.flux_session_reset(this.sessionId);
+    this.inner.reset();
   }
 
   /**
    * Destroy session and free resources
    */
   destroy(): void {
-    this.wasm.flux_session_destroy(this.sessionId);
+    this.inner.free();
+  }
+
+  /**
+   * Snapshot of the session state (schema cache, dictionaries and LZ history),
+   * e.g. to keep in IndexedDB across page reloads
+   */
+  exportState(): Uint8Array {
+    return this.inner.exportState();
+  }
+
+  /**
+   * Restore a session from `exportState()`, using the default configuration
+   */
+  static async importState(snapshot: Uint8Array): Promise<FluxSession> {
+    const wasm = await loadWasm();
+    return new FluxSession(wasm.FluxSession.importState(snapshot));
   }
 }
 
@@ -265,21 +310,21 @@ export class FluxSession {
  * ```
  */
 export class FluxStream {
-  private wasm: FluxWasm;
-  private sessionId: number;
+  private inner: WasmStream;
 
-  private constructor(wasm: FluxWasm, sessionId: number) {
-    this.wasm = wasm;
-    this.sessionId = sessionId;
+  private constructor(inner: WasmStream) {
+    this.inner = inner;
   }
 
   /**
    * Create a new streaming session
    */
-  static async create(): Promise<FluxStream> {
+  static async create(options?: FluxStreamOptions): Promise<FluxStream> {
     const wasm = await loadWasm();
-    const sessionId = wasm.flux_stream_create();
-    return new FluxStream(wasm, sessionId);
+    const inner = options
+      ? wasm.FluxStream.withKeyframes(options.keyframeInterval ?? 0, options.keyframePeriodMs ?? 0)
+      : new wasm.FluxStream();
+    return new FluxStream(inner);
   }
 
   /**
@@ -289,36 +334,51 @@ export class FluxStream {
    */
   update(input: FluxInput): FluxResult {
     const data = normalizeInput(input);
-    return this.wasm.flux_stream_update(this.sessionId, data);
+    return this.inner.update(data);
   }
 
   /**
    * Receive delta and reconstruct full state
    */
   receive(data: Uint8Array): FluxResult {
-    return this.wasm.flux_stream_receive(this.sessionId, data);
+    return this.inner.receive(data);
   }
 
   /**
    * Get streaming session statistics
    */
   stats(): FluxStreamStats {
-    const json = this.wasm.flux_stream_stats(this.sessionId);
-    return JSON.parse(json);
+    return this.inner.stats();
   }
 
   /**
    * Reset streaming session state
    */
   reset(): void {
-    this.wasm.flux_stream_reset(this.sessionId);
+    this.inner.reset();
   }
 
   /**
    * Destroy streaming session and free resources
    */
   destroy(): void {
-    this.wasm.flux_stream_destroy(this.sessionId);
+    this.inner.free();
+  }
+
+  /**
+   * Snapshot of the session state (sent and received states),
+   * e.g. to keep in IndexedDB across page reloads
+   */
+  exportState(): Uint8Array {
+    return this.inner.exportState();
+  }
+
+  /**
+   * Restore a session from `exportState()`, using the default configuration
+   */
+  static async importState(snapshot: Uint8Array): Promise<FluxStream> {
+    const wasm = await loadWasm();
+    return new FluxStream(wasm.FluxStream.importState(snapshot));
   }
 }
 
@@ -326,6 +386,8 @@ export class FluxStream {
 export type {
   FluxConfig,
   FluxStats,
+  FluxSchemaInfo,
+  FluxStreamOptions,
   FluxStreamStats,
   FluxAnalysis,
   FluxInput,
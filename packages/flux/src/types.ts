@@ -35,9 +35,57 @@ export interface FluxStats {
   bytesIn: number;
   bytesOut: number;
   schemasCached: number;
+  /** Schemas dropped to stay within the cache capacity */
+  schemaEvictions: number;
   cacheHits: number;
   cacheMisses: number;
   compressionRatio: number;
+  /** Bytes produced by the schema encoder (input to LZ) */
+  encodedBytes: number;
+  /** Bytes after the LZ stage (input to entropy coding) */
+  lzBytes: number;
+  /** Bytes after the entropy stage (frame payloads) */
+  entropyBytes: number;
+  /** Bytes spent on inline schemas and schema deltas */
+  schemaOverheadBytes: number;
+  /** Bytes spent on value dictionary sections */
+  dictionaryBytes: number;
+  /** Bytes saved by schema encoding */
+  encodeGain: number;
+  /** Bytes saved by LZ */
+  lzGain: number;
+  /** Bytes saved by entropy coding */
+  entropyGain: number;
+}
+
+/**
+ * Schema cached by a FLUX session
+ */
+export interface FluxSchemaInfo {
+  id: number;
+  version: number;
+  /** 64-bit schema hash as 16 hex digits */
+  hash: string;
+  /** Top-level field names in encoding order */
+  fields: string[];
+}
+
+/**
+ * FLUX streaming session options
+ */
+export interface FluxStreamOptions {
+  /**
+   * Send a full snapshot every this many updates (0 = never)
+   * @default 0
+   */
+  keyframeInterval?: number;
+
+  /**
+   * Send a full snapshot once this many milliseconds have passed since the
+   * last one, measured with `performance.now()` (0 = never)
+   * @default 0
+   */
+  keyframePeriodMs?: number;
 }
 
 /**
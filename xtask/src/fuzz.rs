@@ -0,0 +1,274 @@
+//! `cargo xtask fuzz`
+//!
+//! 1. Runs each fuzz target through `cargo fuzz run`, passing the corpora of
+//!    related targets as extra read-only corpus directories. Targets in a
+//!    group decode overlapping formats (a frame embeds a schema, a delta...),
+//!    so interesting inputs found by one seed the others.
+//! 2. Minimizes every `crash-*` artifact with `cargo fuzz tmin` and moves it
+//!    into `crates/flux-core/tests/regressions/<target>/`, where the
+//!    `regressions` integration test replays it on every `cargo test`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::workspace_root;
+
+/// Targets that share corpora
+const CORPUS_GROUPS: &[&[&str]] = &[&["flux_frame", "flux_schema", "flux_delta", "flux_metadata", "flux_columnar"]];
+
+/// Where minimized crashes are kept, relative to the workspace root
+const REGRESSIONS_DIR: &str = "crates/flux-core/tests/regressions";
+
+pub struct Options {
+    targets: Vec<String>,
+    time: u64,
+    toolchain: String,
+    run: bool,
+    list: bool,
+}
+
+impl Options {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut opts = Options {
+            targets: Vec::new(),
+            time: 60,
+            toolchain: "nightly".into(),
+            run: true,
+            list: false,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--time" => {
+                    let value = args.next().ok_or("--time needs a value")?;
+                    opts.time = value.parse().map_err(|e| format!("--time: {}", e))?;
+                }
+                "--toolchain" => opts.toolchain = args.next().ok_or("--toolchain needs a value")?,
+                "--no-run" => opts.run = false,
+                "--list" => opts.list = true,
+                flag if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+                target => opts.targets.push(target.to_string()),
+            }
+        }
+        Ok(opts)
+    }
+}
+
+pub fn run(opts: &Options) -> Result<(), String> {
+    let root = workspace_root();
+    let fuzz_dir = root.join("fuzz");
+    let manifest = fs::read_to_string(fuzz_dir.join("Cargo.toml"))
+        .map_err(|e| format!("reading fuzz/Cargo.toml: {}", e))?;
+    let all = parse_targets(&manifest);
+
+    if opts.list {
+        for target in &all {
+            println!("{:<16} shares corpus with: {}", target, related_targets(target).join(", "));
+        }
+        return Ok(());
+    }
+
+    let targets = if opts.targets.is_empty() {
+        all.clone()
+    } else {
+        for target in &opts.targets {
+            if !all.contains(target) {
+                return Err(format!("unknown fuzz target `{}` (see --list)", target));
+            }
+        }
+        opts.targets.clone()
+    };
+
+    if opts.run {
+        check_cargo_fuzz(&opts.toolchain)?;
+    }
+
+    let mut found = 0;
+    for target in &targets {
+        if opts.run {
+            run_target(&root, &fuzz_dir, target, opts)?;
+        }
+        found += collect_crashes(&root, &fuzz_dir, target, &opts.toolchain)?;
+    }
+
+    if found > 0 {
+        return Err(format!(
+            "{} crash(es) minimized into {}/ - fix them and commit the inputs",
+            found, REGRESSIONS_DIR
+        ));
+    }
+    println!("no crashes");
+    Ok(())
+}
+
+fn check_cargo_fuzz(toolchain: &str) -> Result<(), String> {
+    let ok = cargo(toolchain)
+        .args(["fuzz", "--version"])
+        .output()
+        .is_ok_and(|out| out.status.success());
+    if ok {
+        Ok(())
+    } else {
+        Err(format!(
+            "`cargo +{} fuzz` not available; install with `cargo install cargo-fuzz` \
+             and `rustup toolchain install {}`",
+            toolchain, toolchain
+        ))
+    }
+}
+
+fn run_target(root: &Path, fuzz_dir: &Path, target: &str, opts: &Options) -> Result<(), String> {
+    let corpus = fuzz_dir.join("corpus");
+    let own = corpus.join(target);
+    fs::create_dir_all(&own).map_err(|e| format!("{}: {}", own.display(), e))?;
+
+    // libFuzzer writes new inputs to the first directory and reads all of them
+    let mut dirs = vec![own];
+    dirs.extend(
+        related_targets(target)
+            .into_iter()
+            .map(|other| corpus.join(other))
+            .filter(|dir| dir.is_dir()),
+    );
+
+    println!("==> fuzzing {} for {}s ({} corpus dirs)", target, opts.time, dirs.len());
+    // A crash makes cargo-fuzz exit non-zero; artifacts are handled afterwards
+    let _ = cargo(&opts.toolchain)
+        .current_dir(root)
+        .args(["fuzz", "run", target])
+        .args(&dirs)
+        .arg("--")
+        .arg(format!("-max_total_time={}", opts.time))
+        .status()
+        .map_err(|e| format!("running cargo fuzz: {}", e))?;
+    Ok(())
+}
+
+/// Minimize crash artifacts into the regressions directory, returning how many
+fn collect_crashes(root: &Path, fuzz_dir: &Path, target: &str, toolchain: &str) -> Result<usize, String> {
+    let artifacts = fuzz_dir.join("artifacts").join(target);
+    let Ok(entries) = fs::read_dir(&artifacts) else {
+        return Ok(0);
+    };
+
+    let crashes: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with("crash-")))
+        .collect();
+
+    let dest = root.join(REGRESSIONS_DIR).join(target);
+    for crash in &crashes {
+        let name = regression_name(crash);
+        let minimized = minimize(root, target, crash, toolchain);
+        let input = minimized.as_deref().unwrap_or(crash);
+
+        fs::create_dir_all(&dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+        fs::copy(input, dest.join(&name)).map_err(|e| format!("copying {}: {}", input.display(), e))?;
+        println!("    {} -> {}/{}/{}", crash.display(), REGRESSIONS_DIR, target, name);
+
+        let _ = fs::remove_file(crash);
+        if let Some(minimized) = minimized {
+            let _ = fs::remove_file(minimized);
+        }
+    }
+    Ok(crashes.len())
+}
+
+/// Run `cargo fuzz tmin`, returning the minimized file if one was produced
+fn minimize(root: &Path, target: &str, crash: &Path, toolchain: &str) -> Option<PathBuf> {
+    let status = cargo(toolchain)
+        .current_dir(root)
+        .args(["fuzz", "tmin", target])
+        .arg(crash)
+        .status()
+        .ok()?;
+    if !status.success() {
+        return None;
+    }
+
+    let name = crash.file_name()?.to_str()?;
+    let hash = name.strip_prefix("crash-")?;
+    let minimized = crash.with_file_name(format!("minimized-from-{}", hash));
+    minimized.is_file().then_some(minimized)
+}
+
+fn cargo(toolchain: &str) -> Command {
+    let mut cmd = Command::new("cargo");
+    cmd.arg(format!("+{}", toolchain));
+    cmd
+}
+
+/// `[[bin]]` names declared in the fuzz manifest
+fn parse_targets(manifest: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    let mut in_bin = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_bin = line == "[[bin]]";
+        } else if in_bin {
+            if let Some(value) = line.strip_prefix("name").map(str::trim).and_then(|l| l.strip_prefix('=')) {
+                targets.push(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    targets
+}
+
+/// Other targets in the same corpus group
+fn related_targets(target: &str) -> Vec<&'static str> {
+    CORPUS_GROUPS
+        .iter()
+        .filter(|group| group.contains(&target))
+        .flat_map(|group| group.iter().copied())
+        .filter(|&other| other != target)
+        .collect()
+}
+
+/// `crash-<sha1>` -> `<sha1>`
+fn regression_name(crash: &Path) -> String {
+    let name = crash.file_name().unwrap_or_default().to_string_lossy();
+    name.strip_prefix("crash-").unwrap_or(&name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() {
+        let manifest = r#"
+[package]
+name = "flux-fuzz"
+
+[[bin]]
+name = "flux_frame"
+path = "fuzz_targets/flux_frame.rs"
+
+[[bin]]
+name = "flux_delta"
+"#;
+        assert_eq!(parse_targets(manifest), ["flux_frame", "flux_delta"]);
+    }
+
+    #[test]
+    fn test_related_targets() {
+        let related = related_targets("flux_frame");
+        assert!(related.contains(&"flux_schema"));
+        assert!(!related.contains(&"flux_frame"));
+        assert!(related_targets("unknown").is_empty());
+    }
+
+    #[test]
+    fn test_real_manifest_targets_have_harnesses() {
+        let manifest = fs::read_to_string(workspace_root().join("fuzz/Cargo.toml")).unwrap();
+        for target in parse_targets(&manifest) {
+            assert!(!related_targets(&target).is_empty(), "{} is in no corpus group", target);
+        }
+    }
+
+    #[test]
+    fn test_regression_name() {
+        assert_eq!(regression_name(Path::new("fuzz/artifacts/x/crash-abc123")), "abc123");
+    }
+}
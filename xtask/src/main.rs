@@ -0,0 +1,49 @@
+//! Workspace automation, run as `cargo xtask <command>`
+//!
+//! Commands:
+//!
+//! - `fuzz` - run fuzz targets with shared corpora and collect minimized
+//!   crashes as regression tests (see `fuzz.rs`)
+
+mod fuzz;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: cargo xtask <command> [options]
+
+commands:
+  fuzz [options] [TARGET...]   run fuzz targets (default: all)
+      --time SECS              seconds per target (default: 60)
+      --toolchain NAME         toolchain for cargo-fuzz (default: nightly)
+      --no-run                 only minimize existing crash artifacts
+      --list                   list targets and corpus groups";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("fuzz") => fuzz::Options::parse(args).and_then(|opts| fuzz::run(&opts)),
+        Some("-h" | "--help") | None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => Err(format!("unknown command `{}`\n\n{}", other, USAGE)),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("xtask: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Workspace root (parent of the xtask crate)
+fn workspace_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask lives inside the workspace")
+        .to_path_buf()
+}